@@ -0,0 +1,274 @@
+// Screen reader support through AccessKit. The buffer is a multiline text field with a text box per
+// line and the cursor as its text selection, the mode is the field's description, so it's read out
+// when it changes, and new notifications go in a live region to be announced.
+//
+// Also the options for people who need more contrast, less movement or bigger text:
+//
+//   [accessibility]
+//   high_contrast = true
+//   cursor_blink = false
+//   reduce_motion = true      # left out to follow the system setting
+//   min_font_size = 14
+
+use std::num::NonZeroU128;
+use std::time::{Duration, Instant};
+
+use accesskit::{Live, Node, NodeBuilder, NodeClassSet, NodeId, Role, TextPosition, TextSelection, Tree, TreeUpdate};
+use serde::Deserialize;
+
+use crate::color::{contrast_ratio, luminance};
+use crate::notifications::Notifications;
+use crate::selection::line_starts;
+use crate::viewport::MIN_FONT_SIZE;
+
+// Half of a blink, visible and then hidden
+const BLINK_PHASE: Duration = Duration::from_millis(530);
+// WCAG's enhanced contrast, what high_contrast pushes colors to
+const MIN_CONTRAST: f32 = 7.;
+
+const WINDOW: NodeId = node_id(1);
+const BUFFER: NodeId = node_id(2);
+const ANNOUNCEMENT: NodeId = node_id(3);
+// Lines are numbered from here
+const FIRST_LINE: u128 = 16;
+
+const fn node_id(id: u128) -> NodeId {
+    match NonZeroU128::new(id) {
+        Some(id) => NodeId(id),
+        None => panic!("Node ids start at 1"),
+    }
+}
+
+fn line_id(line: usize) -> NodeId {
+    node_id(FIRST_LINE + line as u128)
+}
+
+// What screen readers are told about, besides the text. That's known to be unchanged by the buffer's
+// version, so it's only read when the version changes
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Snapshot {
+    pub version: u64,
+    // Bytes of the primary selection
+    pub anchor: usize,
+    pub head: usize,
+    pub mode: String,
+    pub announcement: Option<String>,
+    // Whether the window has keyboard focus
+    pub focused: bool,
+}
+
+// Characters of a line in UTF-8 bytes and words in characters, as AccessKit wants them for moving
+// by character and word. A word takes the spaces after it
+fn lengths(line: &str) -> (Vec<u8>, Vec<u8>) {
+    let characters: Vec<u8> = line.chars().map(|c| c.len_utf8() as u8).collect();
+    let mut words = Vec::new();
+    let mut previous_space = false;
+    for c in line.chars() {
+        match words.last_mut() {
+            // Lengths are bytes, so very long words are split
+            Some(word) if (c.is_whitespace() || !previous_space) && *word < u8::MAX => *word += 1,
+            _ => words.push(1),
+        }
+        previous_space = c.is_whitespace();
+    }
+    (characters, words)
+}
+
+fn position(starts: &[usize], text: &str, byte: usize) -> TextPosition {
+    let line = starts.partition_point(|&start| start <= byte) - 1;
+    TextPosition { node: line_id(line), character_index: text[starts[line]..byte].chars().count() }
+}
+
+#[derive(Default)]
+pub struct Accessibility {
+    classes: NodeClassSet,
+    last: Option<Snapshot>,
+}
+
+impl Accessibility {
+    // The whole tree. Needed by the adapter to start with
+    pub fn tree(&mut self, snapshot: &Snapshot, text: &str) -> TreeUpdate {
+        let starts = line_starts(text);
+        let mut nodes: Vec<(NodeId, Node)> = Vec::new();
+        for (line, &start) in starts.iter().enumerate() {
+            let end = starts.get(line + 1).copied().unwrap_or(text.len());
+            let line_text = &text[start..end];
+            let (characters, words) = lengths(line_text);
+            let mut builder = NodeBuilder::new(Role::InlineTextBox);
+            builder.set_value(line_text);
+            builder.set_character_lengths(characters);
+            builder.set_word_lengths(words);
+            nodes.push((line_id(line), builder.build(&mut self.classes)));
+        }
+
+        let mut buffer = NodeBuilder::new(Role::TextField);
+        buffer.set_multiline();
+        buffer.set_value(text);
+        buffer.set_description(format!("{} mode", snapshot.mode));
+        buffer.set_children((0..starts.len()).map(line_id).collect::<Vec<_>>());
+        buffer.set_text_selection(TextSelection {
+            anchor: position(&starts, text, snapshot.anchor),
+            focus: position(&starts, text, snapshot.head),
+        });
+        nodes.push((BUFFER, buffer.build(&mut self.classes)));
+
+        let mut children = vec![BUFFER];
+        if let Some(announcement) = &snapshot.announcement {
+            let mut builder = NodeBuilder::new(Role::StaticText);
+            builder.set_name(announcement.as_str());
+            builder.set_live(Live::Polite);
+            nodes.push((ANNOUNCEMENT, builder.build(&mut self.classes)));
+            children.push(ANNOUNCEMENT);
+        }
+        let mut window = NodeBuilder::new(Role::Window);
+        window.set_name("rakoune");
+        window.set_children(children);
+        nodes.push((WINDOW, window.build(&mut self.classes)));
+
+        TreeUpdate { nodes, tree: Some(Tree::new(WINDOW)), focus: snapshot.focused.then_some(BUFFER) }
+    }
+
+    // What changed since the last update, None if nothing did
+    pub fn update(&mut self, snapshot: Snapshot, text: &str) -> Option<TreeUpdate> {
+        if self.last.as_ref() == Some(&snapshot) {
+            return None;
+        }
+        let update = self.tree(&snapshot, text);
+        self.last = Some(snapshot);
+        Some(update)
+    }
+
+}
+
+// For Snapshot::announcement. The newest message stays in the live region after it expires, so it
+// isn't taken away while being read, and is only announced again when a new one replaces it
+pub fn announcement(notifications: &Notifications) -> Option<String> {
+    notifications.log.last().map(|message| message.text.clone())
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessibilityConfig {
+    pub high_contrast: bool,
+    pub cursor_blink: bool,
+    // None follows the system
+    pub reduce_motion: Option<bool>,
+    pub min_font_size: f32,
+}
+
+impl Default for AccessibilityConfig {
+    fn default() -> Self {
+        AccessibilityConfig { high_contrast: false, cursor_blink: true, reduce_motion: None, min_font_size: MIN_FONT_SIZE }
+    }
+}
+
+impl AccessibilityConfig {
+    // Whether to jump instead of animating scrolling and fading, asking the system if the config doesn't say
+    pub fn reduce_motion(&self) -> bool {
+        self.reduce_motion.unwrap_or_else(system_reduce_motion)
+    }
+
+    pub fn min_font_size(&self) -> f32 {
+        self.min_font_size.max(MIN_FONT_SIZE)
+    }
+
+    // Whether the cursor is drawn at `now`, when it last moved at `moved`. It stays on while typing
+    pub fn cursor_visible(&self, moved: Instant, now: Instant) -> bool {
+        !self.cursor_blink || (now.duration_since(moved).as_millis() / BLINK_PHASE.as_millis()).is_multiple_of(2)
+    }
+
+    // When the cursor next turns on or off, for waking up to draw it
+    pub fn next_blink(&self, moved: Instant, now: Instant) -> Option<Instant> {
+        let phases = now.duration_since(moved).as_millis() / BLINK_PHASE.as_millis() + 1;
+        self.cursor_blink.then(|| moved + BLINK_PHASE * phases as u32)
+    }
+
+    // A theme color as drawn, made to stand out from `background` in the high contrast variant
+    pub fn color(&self, color: [f32; 4], background: [f32; 4]) -> [f32; 4] {
+        if self.high_contrast { high_contrast(color, background) } else { color }
+    }
+}
+
+// GNOME's and macOS's reduced motion settings. Other systems are taken to not want it
+pub fn system_reduce_motion() -> bool {
+    let output = |program: &str, args: &[&str]| std::process::Command::new(program).args(args).output().ok().and_then(|output| String::from_utf8(output.stdout).ok());
+    if cfg!(target_os = "macos") {
+        output("defaults", &["read", "com.apple.universalaccess", "reduceMotion"]).is_some_and(|value| value.trim() == "1")
+    } else if cfg!(unix) {
+        output("gsettings", &["get", "org.gnome.desktop.interface", "enable-animations"]).is_some_and(|value| value.trim() == "false")
+    } else {
+        false
+    }
+}
+
+// `color` moved towards white or black, whichever is further from `background`, until the two
+// contrast at least MIN_CONTRAST, and made opaque. Keeps some of the hue, so syntax colors can
+// still be told apart
+pub fn high_contrast(color: [f32; 4], background: [f32; 4]) -> [f32; 4] {
+    let extreme = if luminance(background) < 0.18 { 1. } else { 0. };
+    let towards = |t: f32| [0, 1, 2, 3].map(|channel| if channel == 3 { 1. } else { color[channel] + (extreme - color[channel]) * t });
+    let mut t = 0.;
+    while t < 1. && contrast_ratio(towards(t), background) < MIN_CONTRAST {
+        t += 0.05;
+    }
+    towards(t.min(1.))
+}
+
+// Actions like focusing or scrolling aren't supported yet, so screen readers only read
+pub struct IgnoreActions;
+
+impl accesskit::ActionHandler for IgnoreActions {
+    fn do_action(&self, _request: accesskit::ActionRequest) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_lines_and_cursor() {
+        assert_eq!(lengths("let é = 1;\n"), (vec![1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1], vec![4, 2, 2, 3]));
+
+        let mut accessibility = Accessibility::default();
+        let text = "fn main() {\n    é\n}";
+        let snapshot = Snapshot { version: 1, anchor: 12, head: 18, mode: "normal".to_string(), focused: true, ..Default::default() };
+        let update = accessibility.update(snapshot.clone(), text).unwrap();
+        assert_eq!(update.focus, Some(BUFFER));
+        let (_, buffer) = update.nodes.iter().find(|(id, _)| *id == BUFFER).unwrap();
+        assert_eq!(buffer.children(), &[line_id(0), line_id(1), line_id(2)]);
+        assert_eq!(buffer.description(), Some("normal mode"));
+        let selection = buffer.text_selection().unwrap();
+        assert_eq!(selection.anchor, TextPosition { node: line_id(1), character_index: 0 });
+        assert_eq!(selection.focus, TextPosition { node: line_id(1), character_index: 5 });
+        assert!(accessibility.update(snapshot.clone(), text).is_none());
+        assert!(accessibility.update(Snapshot { version: 2, ..snapshot }, text).is_some());
+
+        let mut notifications = Notifications::default();
+        assert_eq!(announcement(&notifications), None);
+        notifications.info("Saved");
+        assert_eq!(announcement(&notifications).as_deref(), Some("Saved"));
+    }
+
+    #[test]
+    fn contrast_and_blinking() {
+        let dark = [0.1, 0.1, 0.12, 1.];
+        let gray = [0.5, 0.5, 0.5, 0.8];
+        assert!(contrast_ratio(gray, dark) < MIN_CONTRAST);
+        let config: AccessibilityConfig = toml::from_str("high_contrast = true\ncursor_blink = false\nreduce_motion = true\nmin_font_size = 2").unwrap();
+        let contrasted = config.color(gray, dark);
+        assert!(contrast_ratio(contrasted, dark) >= MIN_CONTRAST);
+        assert_eq!(contrasted[3], 1.);
+        assert!(contrast_ratio(high_contrast([0.9, 0.9, 0.2, 1.], [1.; 4]), [1.; 4]) >= MIN_CONTRAST);
+        assert_eq!(AccessibilityConfig::default().color(gray, dark), gray);
+        assert!(config.reduce_motion());
+        assert_eq!(config.min_font_size(), MIN_FONT_SIZE);
+
+        let moved = Instant::now();
+        assert!(config.cursor_visible(moved, moved + BLINK_PHASE));
+        assert_eq!(config.next_blink(moved, moved), None);
+        let blinking = AccessibilityConfig::default();
+        assert!(blinking.cursor_visible(moved, moved + BLINK_PHASE / 2));
+        assert!(!blinking.cursor_visible(moved, moved + BLINK_PHASE * 3 / 2));
+        assert_eq!(blinking.next_blink(moved, moved + BLINK_PHASE / 2), Some(moved + BLINK_PHASE));
+    }
+}
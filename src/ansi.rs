@@ -0,0 +1,189 @@
+use std::ops::Range;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    // Whatever the theme uses for text or background
+    #[default]
+    Default,
+    // 0-7 are the normal colors, 8-15 the bright ones, then the 6×6×6 cube and the grays
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+// xterm's 16 colors
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0), (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0), (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+impl Color {
+    // The color to draw with, `default` for Color::Default. Linear like every other color drawn,
+    // where programs give sRGB
+    pub fn rgba(self, default: [f32; 4]) -> [f32; 4] {
+        let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+        let (r, g, b) = match self {
+            Color::Default => return default,
+            Color::Indexed(n @ 0..=15) => PALETTE[n as usize],
+            Color::Indexed(n @ 16..=231) => (level((n - 16) / 36), level((n - 16) / 6 % 6), level((n - 16) % 6)),
+            Color::Indexed(n) => (8 + (n - 232) * 10, 8 + (n - 232) * 10, 8 + (n - 232) * 10),
+            Color::Rgb(r, g, b) => (r, g, b),
+        };
+        let linear = |c: u8| (c as f32 / 255.).powf(2.2);
+        [linear(r), linear(g), linear(b), default[3]]
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+    pub strikethrough: bool,
+}
+
+// A run of text with one style. Ranges are byte ranges in the text with the escapes removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StyledSpan {
+    pub range: Range<usize>,
+    pub style: Style,
+}
+
+// Parses 38;5;n and 38;2;r;g;b, returning the color and how many parameters it used after the 38
+fn extended_color(params: &[u16]) -> Option<(Color, usize)> {
+    match params {
+        [5, n, ..] => Some((Color::Indexed(*n as u8), 2)),
+        [2, r, g, b, ..] => Some((Color::Rgb(*r as u8, *g as u8, *b as u8), 4)),
+        _ => None,
+    }
+}
+
+impl Style {
+    // Applies the parameters of an SGR sequence, `ESC [ params m`
+    pub fn apply_sgr(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            *self = Style::default();
+            return;
+        }
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.inverse = true,
+                9 => self.strikethrough = true,
+                22 => (self.bold, self.dim) = (false, false),
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.inverse = false,
+                29 => self.strikethrough = false,
+                n @ 30..=37 => self.fg = Color::Indexed((n - 30) as u8),
+                n @ 90..=97 => self.fg = Color::Indexed((n - 90 + 8) as u8),
+                39 => self.fg = Color::Default,
+                n @ 40..=47 => self.bg = Color::Indexed((n - 40) as u8),
+                n @ 100..=107 => self.bg = Color::Indexed((n - 100 + 8) as u8),
+                49 => self.bg = Color::Default,
+                n @ (38 | 48) => {
+                    if let Some((color, used)) = extended_color(&params[i + 1..]) {
+                        if n == 38 {
+                            self.fg = color;
+                        } else {
+                            self.bg = color;
+                        }
+                        i += used;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+}
+
+// Turns output with escape sequences into plain text and spans. The style carries over between
+// lines, like in a terminal. Escape sequences other than SGR are dropped
+#[derive(Debug, Default)]
+pub struct Parser {
+    pub style: Style,
+}
+
+impl Parser {
+    pub fn parse_line(&mut self, line: &str) -> (String, Vec<StyledSpan>) {
+        let mut text = String::with_capacity(line.len());
+        let mut spans: Vec<StyledSpan> = Vec::new();
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '\u{1b}' {
+                let start = text.len();
+                text.push(c);
+                match spans.last_mut() {
+                    Some(last) if last.style == self.style && last.range.end == start => last.range.end = text.len(),
+                    _ if self.style == Style::default() => {}
+                    _ => spans.push(StyledSpan { range: start..text.len(), style: self.style }),
+                }
+                continue;
+            }
+            if chars.next_if_eq(&'[').is_none() {
+                chars.next();
+                continue;
+            }
+            // CSI: parameters, then a final byte in @..~
+            let mut params = String::new();
+            let mut final_byte = None;
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    final_byte = Some(c);
+                    break;
+                }
+                params.push(c);
+            }
+            if final_byte == Some('m') {
+                // An empty parameter means 0, so `ESC [ m` resets too
+                let params: Vec<u16> = params.split([';', ':']).map(|p| p.parse().unwrap_or(0)).collect();
+                self.style.apply_sgr(&params);
+            }
+        }
+        (text, spans)
+    }
+}
+
+// The text without any escape sequences
+pub fn strip(line: &str) -> String {
+    Parser::default().parse_line(line).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sgr() {
+        let mut parser = Parser::default();
+        let (text, spans) = parser.parse_line("\u{1b}[1;31merror\u{1b}[0m: \u{1b}[38;5;208mbad\u{1b}[38;2;1;2;3m!");
+        assert_eq!(text, "error: bad!");
+        let bold_red = Style { fg: Color::Indexed(1), bold: true, ..Default::default() };
+        assert_eq!(spans, vec![
+            StyledSpan { range: 0..5, style: bold_red },
+            StyledSpan { range: 7..10, style: Style { fg: Color::Indexed(208), ..Default::default() } },
+            StyledSpan { range: 10..11, style: Style { fg: Color::Rgb(1, 2, 3), ..Default::default() } },
+        ]);
+
+        // The color is still on for the next line, until reset
+        let (text, spans) = parser.parse_line("more\u{1b}[m done\u{1b}[2K");
+        assert_eq!(text, "more done");
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].range, 0..4);
+
+        let text = [0.5, 0.5, 0.5, 1.];
+        assert_eq!(Color::Default.rgba(text), text);
+        assert_eq!(Color::Indexed(9).rgba(text), [1., 0., 0., 1.]);
+        assert_eq!(Color::Indexed(196).rgba(text), Color::Rgb(255, 0, 0).rgba(text));
+        assert_eq!(Color::Indexed(255).rgba(text), Color::Rgb(238, 238, 238).rgba(text));
+    }
+}
@@ -0,0 +1,1489 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+use winit::event::{ElementState, ModifiersState, MouseScrollDelta, Touch, TouchPhase, VirtualKeyCode};
+use winit::window::CursorIcon;
+
+use crate::accessibility::AccessibilityConfig;
+use crate::autosave::AutoSave;
+use crate::blame::Blame;
+use crate::commands::Registry;
+use crate::completion::Menu;
+use crate::config::Config;
+use crate::editor::{self, Editor, Key};
+use crate::filetree::FileTree;
+use crate::folding::Fold;
+use crate::font::FontStack;
+use crate::hover::{self, PopupKind};
+use crate::jobs::{self, Job};
+use crate::keymap::{self, KeyboardConfig, Scancodes};
+use crate::keyrepeat::{KeyRepeat, RepeatConfig};
+use crate::images;
+use crate::layout::{self, layout, line_height, Block, LayoutSettings, Rect, RowIndex};
+use crate::links;
+use crate::markdown::{self, Element, Piece};
+use crate::notifications::{run_reporting, Notifications};
+use crate::panes::{self, PaneZoom};
+use crate::paste::{PasteDetector, Typed};
+use crate::scrollbar::{self, Scrollbar};
+use crate::search::lines_bytes;
+use crate::selection::{line_starts, word_around, Selection};
+use crate::session::Layout;
+use crate::shapes::Shape;
+use crate::splash::Splash;
+use crate::statusline::{self, StatusLine, StatusLineConfig};
+use crate::sticky;
+use crate::tabs::{Click, TabRect, TabsHost};
+use crate::terminal;
+use crate::tooltip::{self, Tip, Tooltips};
+use crate::touch::{Gesture, Handle, TouchInput};
+use crate::viewport::Viewport;
+use crate::zen::Chrome;
+
+// Length of one update step. Animations advance by exactly this much per step, no matter the frame rate
+pub const STEP: Duration = Duration::from_micros(8_333);
+// After a long stall (breakpoint, suspended laptop), skip ahead instead of running thousands of steps
+const MAX_STEPS_PER_FRAME: u32 = 10;
+// How bright text is drawn while another window has focus
+const UNFOCUSED_TEXT: f32 = 0.7;
+// How often to look for output from the program in the terminal or the debug adapter, or for git
+// status for the :tree, whose threads can't wake the event loop
+const POLL: Duration = Duration::from_millis(16);
+// Width of the sidebar with the :tree, the :outline and the debug panes, in characters
+const SIDEBAR_COLUMNS: f32 = 30.;
+// Width of the gutter for breakpoints, in characters
+const GUTTER_COLUMNS: f32 = 2.;
+
+// What the sidebar shows, from the top
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SidebarPane {
+    Tree,
+    Outline,
+    // The stack, variables and output of the program being debugged
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorStyle {
+    Block,
+    // Only the outline, while another window has focus, like terminals do
+    Hollow,
+}
+
+impl CursorStyle {
+    pub fn shape(self, rect: Rect, color: [f32; 4]) -> Shape {
+        let border = match self {
+            CursorStyle::Block => 0.,
+            CursorStyle::Hollow => 1.,
+        };
+        Shape::RoundedRect { rect, radius: 0., border, color }
+    }
+}
+
+// Turns real time into a whole number of fixed steps, keeping the remainder for the next frame
+#[derive(Debug, Default)]
+pub struct FixedStep {
+    last: Option<Instant>,
+    accumulated: Duration,
+}
+
+impl FixedStep {
+    // How many steps to run to catch up to `now`
+    pub fn advance(&mut self, now: Instant) -> u32 {
+        let Some(last) = self.last.replace(now) else { return 0 };
+        self.accumulated += now.duration_since(last);
+        let mut steps = 0;
+        while self.accumulated >= STEP {
+            self.accumulated -= STEP;
+            steps += 1;
+        }
+        if steps > MAX_STEPS_PER_FRAME {
+            steps = MAX_STEPS_PER_FRAME;
+        }
+        steps
+    }
+
+    // How far into the next step we are, from 0 to 1, for interpolating between the last two steps
+    pub fn alpha(&self) -> f32 {
+        self.accumulated.as_secs_f32() / STEP.as_secs_f32()
+    }
+
+    // Starts counting from `now`, unless already running. Called when input starts an animation,
+    // so the time until the next frame counts towards it
+    pub fn start(&mut self, now: Instant) {
+        self.last.get_or_insert(now);
+    }
+
+    // Called when there is nothing to animate, so the idle time isn't caught up on later
+    pub fn stop(&mut self) {
+        self.last = None;
+        self.accumulated = Duration::ZERO;
+    }
+}
+
+// The Key for keys that type nothing. Character keys get theirs from ReceivedCharacter
+fn named_key(key: VirtualKeyCode, shift: bool) -> Option<Key> {
+    let function = |number| Key::Function { number, shift };
+    Some(match key {
+        VirtualKeyCode::Escape => Key::Escape,
+        VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Key::Enter,
+        VirtualKeyCode::Back => Key::Backspace,
+        VirtualKeyCode::Delete => Key::Delete,
+        VirtualKeyCode::Tab => Key::Tab,
+        VirtualKeyCode::Left => Key::Left,
+        VirtualKeyCode::Right => Key::Right,
+        VirtualKeyCode::Up => Key::Up,
+        VirtualKeyCode::Down => Key::Down,
+        VirtualKeyCode::Home => Key::Home,
+        VirtualKeyCode::End => Key::End,
+        VirtualKeyCode::PageUp => Key::PageUp,
+        VirtualKeyCode::PageDown => Key::PageDown,
+        VirtualKeyCode::F1 => function(1),
+        VirtualKeyCode::F2 => function(2),
+        VirtualKeyCode::F3 => function(3),
+        VirtualKeyCode::F4 => function(4),
+        VirtualKeyCode::F5 => function(5),
+        VirtualKeyCode::F6 => function(6),
+        VirtualKeyCode::F7 => function(7),
+        VirtualKeyCode::F8 => function(8),
+        VirtualKeyCode::F9 => function(9),
+        VirtualKeyCode::F10 => function(10),
+        VirtualKeyCode::F11 => function(11),
+        VirtualKeyCode::F12 => function(12),
+        _ => return None,
+    })
+}
+
+// Keys only held with others, which shouldn't take over repeating from the key held before them
+fn is_modifier(key: VirtualKeyCode) -> bool {
+    matches!(
+        key,
+        VirtualKeyCode::LShift | VirtualKeyCode::RShift | VirtualKeyCode::LControl | VirtualKeyCode::RControl | VirtualKeyCode::LAlt | VirtualKeyCode::RAlt | VirtualKeyCode::LWin | VirtualKeyCode::RWin
+    )
+}
+
+// Everything the window shows and how input changes it. The event loop in main.rs feeds events in
+// and asks when to wake up next
+pub struct App {
+    pub fontstack: Rc<FontStack>,
+    pub editor: Editor,
+    pub registry: Registry<Editor>,
+    pub status_line: StatusLine,
+    pub settings: LayoutSettings,
+    pub viewport: Viewport,
+    // How the window is split, and the font sizes of panes zoomed on their own
+    pub layout: Layout,
+    pub zoom: PaneZoom,
+    pub modifiers: ModifiersState,
+    pub scrollbar: Scrollbar,
+    pub splash: Splash,
+    pub cursor_pos: (f32, f32),
+    // For diagnostic signs in the gutter and the branch on the status line
+    tooltips: Tooltips,
+    pub window_size: (f32, f32),
+    pub key_repeat: KeyRepeat<VirtualKeyCode>,
+    // Keys, including repeats, waiting to be handled. Each says whether it came from
+    // ReceivedCharacter, as only those can be part of a paste
+    pub keys: Vec<(Key, bool)>,
+    // The key held down, and the Key it repeats once that is known
+    held: Option<(VirtualKeyCode, Option<Key>)>,
+    // Of the last key pressed, for keymap::command_key
+    scancode: Option<u32>,
+    // Set when the platform repeats a held character key, so the character it sends is dropped too
+    platform_repeat: bool,
+    pub keyboard: KeyboardConfig,
+    pub auto_save: AutoSave,
+    // For maintenance, which waits for a pause in typing
+    pub last_edit: Instant,
+    pub touch: TouchInput,
+    // Taps, long presses and handle drags waiting to be handled. Scrolling is handled right away
+    pub gestures: Vec<Gesture>,
+    // Command lines picked from the native menu bar, waiting to be run
+    pub menu_commands: Vec<String>,
+    // From the command line, another rakoune or the OS, waiting to be opened
+    pub open_files: Vec<PathBuf>,
+    pub accessibility: AccessibilityConfig,
+    // The cursor blinks from the last key press, and is on whenever drawn with cursor_shown
+    last_key: Instant,
+    pub cursor_shown: bool,
+    // Whether the window has keyboard focus. Without it the cursor stops blinking and the text dims
+    pub focused: bool,
+    // Set while the OS has suspended us (app nap, lid closed, backgrounded on mobile). There is nothing to draw to then
+    pub suspended: bool,
+    clock: FixedStep,
+    // What the status line's clock showed last
+    time: (u8, u8),
+    // Scroll position before the last step, to interpolate from
+    previous_scroll_y: f32,
+    // Rows of the current buffer as shown, with the text and settings they were found for
+    rows: RowIndex,
+    rows_of: Option<(u64, LayoutSettings, Vec<Range<usize>>)>,
+    // The images a markdown buffer shows below the lines linking them, found with the rows
+    images: Vec<(PathBuf, Block)>,
+    // Scopes of the current buffer for the sticky header, with the version of the text they're of
+    scopes: Vec<Fold>,
+    scopes_of: Option<u64>,
+}
+
+impl App {
+    pub fn new(fontstack: FontStack, notifications: Notifications, window_size: (f32, f32)) -> App {
+        let settings = LayoutSettings::default();
+        let mut registry = Registry::default();
+        editor::register(&mut registry);
+        // Shared with the editor for :export
+        let fontstack = Rc::new(fontstack);
+        let mut editor = Editor::new(notifications);
+        editor.fontstack = Some(fontstack.clone());
+        let mut app = App {
+            viewport: Viewport::new(settings.font_size, window_size.1),
+            fontstack,
+            editor,
+            registry,
+            status_line: StatusLine::new(&StatusLineConfig::default()).expect("the default status line has only known segments"),
+            settings,
+            layout: Layout::Pane(0),
+            zoom: PaneZoom::default(),
+            modifiers: ModifiersState::empty(),
+            scrollbar: Scrollbar::default(),
+            splash: Splash::new(Instant::now()),
+            cursor_pos: (0., 0.),
+            tooltips: Tooltips::default(),
+            window_size,
+            key_repeat: KeyRepeat::new(RepeatConfig::default()),
+            keys: Vec::new(),
+            held: None,
+            scancode: None,
+            platform_repeat: false,
+            keyboard: KeyboardConfig::default(),
+            auto_save: AutoSave::default(),
+            last_edit: Instant::now(),
+            touch: TouchInput::default(),
+            gestures: Vec::new(),
+            menu_commands: Vec::new(),
+            open_files: Vec::new(),
+            accessibility: AccessibilityConfig::default(),
+            last_key: Instant::now(),
+            cursor_shown: true,
+            focused: true,
+            suspended: false,
+            clock: FixedStep::default(),
+            time: (0, 0),
+            previous_scroll_y: 0.,
+            rows: RowIndex::default(),
+            rows_of: None,
+            images: Vec::new(),
+            scopes: Vec::new(),
+            scopes_of: None,
+        };
+        app.fit_viewport();
+        app
+    }
+
+    // Applies the user config. Parts of it that are wrong are reported and left as they were
+    pub fn configure(&mut self, config: &Config) {
+        config.apply(&mut self.registry);
+        self.keyboard = config.keyboard;
+        self.auto_save = AutoSave::new(config.auto_save.clone());
+        self.editor.format_on_save = config.format_on_save;
+        self.editor.project_sessions = config.project_sessions;
+        self.editor.memory = config.memory.clone();
+        self.editor.clipboard.config = config.clipboard;
+        match StatusLine::new(&config.status_line) {
+            Ok(status_line) => self.status_line = status_line,
+            Err(e) => self.editor.notifications.error(format!("Status line: {e}")),
+        }
+        self.set_accessibility(config.accessibility.clone());
+    }
+
+    // What's drawn around the buffer, which zen mode hides
+    pub fn chrome(&self) -> Chrome {
+        self.editor.zen.chrome(Chrome::default())
+    }
+
+    // The sidebar at the right edge, in characters while any of its panes is open
+    pub fn sidebar_width(&self) -> f32 {
+        match self.sidebar_panes().is_empty() || !self.chrome().sidebars {
+            true => 0.,
+            false => (self.advance() * SIDEBAR_COLUMNS).round(),
+        }
+    }
+
+    fn sidebar_panes(&self) -> Vec<SidebarPane> {
+        let debug = self.editor.debug_panes && self.editor.debugger.is_some();
+        [(SidebarPane::Tree, self.editor.file_tree.is_some()), (SidebarPane::Outline, self.editor.outline.is_some()), (SidebarPane::Debug, debug)].into_iter().filter(|(_, open)| *open).map(|(pane, _)| pane).collect()
+    }
+
+    // The open panes of the sidebar from the top, each with its top and height. They share it
+    // evenly, in whole lines
+    pub fn sidebar(&self) -> Vec<(SidebarPane, f32, f32)> {
+        let panes = self.sidebar_panes();
+        let line_height = self.line_height();
+        let height = self.viewport.top + self.viewport.height;
+        let each = ((height / panes.len().max(1) as f32 / line_height).floor() * line_height).max(line_height);
+        let count = panes.len();
+        panes.into_iter().enumerate().map(|(i, pane)| (pane, i as f32 * each, if i + 1 == count { (height - i as f32 * each).max(0.) } else { each })).collect()
+    }
+
+    // Left of the buffer's text, inside text_width
+    pub fn gutter_width(&self) -> f32 {
+        match self.editor.gutter_shown() && self.editor.terminal.is_none() && self.chrome().gutter {
+            true => (self.advance() * GUTTER_COLUMNS).round(),
+            false => 0.,
+        }
+    }
+
+    // The width of the buffer, which shares what the sidebar leaves with the :preview pane
+    pub fn text_width(&self) -> f32 {
+        let width = self.window_size.0 - self.sidebar_width();
+        match self.editor.preview {
+            Some(_) => (width / 2.).round(),
+            None => width,
+        }
+    }
+
+    // Left edge and width of the column the text is in, which zen mode centers
+    pub fn text_column(&self) -> (f32, f32) {
+        self.editor.zen.text_column(self.text_width(), self.advance())
+    }
+
+    // Where the buffer's text starts, past the gutter
+    pub fn text_left(&self) -> f32 {
+        self.text_column().0 + self.gutter_width()
+    }
+
+    // The layout settings at the current zoom, wrapping at the edge of the window with :set wrap
+    // and at the edge of the column in zen mode
+    pub fn layout_settings(&self) -> LayoutSettings {
+        let wrap_width = match (self.editor.zen.enabled, self.editor.wrap) {
+            (true, _) => Some(self.text_column().1),
+            (false, true) => Some(self.text_width() - self.gutter_width() - scrollbar::WIDTH),
+            (false, false) => self.settings.wrap_width,
+        };
+        LayoutSettings { font_size: self.font_size(), wrap_width, ..self.settings.clone() }
+    }
+
+    // The size the buffer is drawn at: that of its pane while it's zoomed on its own, otherwise
+    // the global one
+    pub fn font_size(&self) -> f32 {
+        let pane = panes::pane_at(&self.layout, self.window_rect(), 0., self.viewport.top);
+        pane.map_or(self.viewport.font_size, |pane| self.zoom.font_size(pane, self.viewport.font_size))
+    }
+
+    fn window_rect(&self) -> Rect {
+        Rect { x: 0., y: 0., w: self.window_size.0, h: self.window_size.1 }
+    }
+
+    // The row each line of the buffer starts on. Rows only differ from lines when long lines wrap
+    pub fn rows(&self) -> &RowIndex {
+        &self.rows
+    }
+
+    // Finds the rows again when the text, the settings or the folds changed. Edits only find the
+    // rows of the lines they touched while nothing is folded
+    fn update_rows(&mut self) {
+        let settings = self.layout_settings();
+        let buffer = self.editor.buffer_mut();
+        let edit = buffer.take_edit();
+        let buffer = self.editor.buffer();
+        let folds = &buffer.folds.closed;
+        if self.rows_of.as_ref().is_some_and(|(of, with, folded)| *of == buffer.version && *with == settings && folded == folds) {
+            return;
+        }
+        let images = self.find_images(&settings);
+        let buffer = self.editor.buffer();
+        let found = match (&self.rows_of, edit) {
+            (Some((of, with, folded)), Some((from, edit))) if *of == from && *with == settings && folded.is_empty() && folds.is_empty() && self.images.is_empty() && images.is_empty() => self.rows.edit(&self.fontstack, &buffer.text, &settings, &edit),
+            _ => false,
+        };
+        if !found {
+            let blocks: Vec<Block> = images.iter().map(|(_, block)| *block).collect();
+            self.rows = RowIndex::new(&self.fontstack, &buffer.text, &settings).folded(folds).with_blocks(&blocks, line_height(&self.fontstack, &settings));
+        }
+        self.rows_of = Some((buffer.version, settings, folds.clone()));
+        self.images = images;
+    }
+
+    // Below the lines of a markdown buffer that link them, outside closed folds. Each is made a whole
+    // number of rows high, to keep to the rows
+    fn find_images(&self, settings: &LayoutSettings) -> Vec<(PathBuf, Block)> {
+        let buffer = self.editor.buffer();
+        let Some(path) = buffer.path().filter(|path| markdown::is_markdown(path)) else { return Vec::new() };
+        let preview = markdown::preview(&buffer.text, path.parent().unwrap_or(&path));
+        let width = settings.wrap_width.unwrap_or(self.text_width() - self.gutter_width() - scrollbar::WIDTH);
+        let line_height = line_height(&self.fontstack, settings);
+        let sizes: HashMap<PathBuf, (u32, u32)> = preview.images.iter().filter_map(|(_, path)| Some((path.clone(), images::png_size(path)?))).collect();
+        let blocks = preview.blocks(|path| sizes.get(path).copied(), width);
+        let paths = preview.images.into_iter().map(|(_, path)| path).filter(|path| sizes.contains_key(path));
+        paths.zip(blocks).filter(|(_, block)| !buffer.folds.is_hidden(block.after_line)).map(|(path, block)| (path, Block { height: (block.height / line_height).ceil() * line_height, ..block })).collect()
+    }
+
+    // Where find_images put images, with the files they show
+    pub fn images(&self) -> &[(PathBuf, Block)] {
+        &self.images
+    }
+
+    // The row `byte` of the buffer is shown on
+    fn row_of(&self, byte: usize) -> usize {
+        let text = &self.editor.buffer().text;
+        let line = line_starts(text).partition_point(|&start| start <= byte) - 1;
+        let bytes = lines_bytes(text, line..line + 1);
+        let shown = layout(&self.fontstack, text[bytes.clone()].trim_end_matches('\n'), &self.layout_settings());
+        self.rows.row_of_line(line) + shown.visual_row(byte - bytes.start)
+    }
+
+    pub fn line_height(&self) -> f32 {
+        layout::line_height(&self.fontstack, &self.layout_settings())
+    }
+
+    // The width of a character, which the font has one of, for the status line and terminal cells
+    pub fn advance(&self) -> f32 {
+        let settings = LayoutSettings { font_size: self.font_size(), wrap_width: None, ..self.settings.clone() };
+        layout(&self.fontstack, "M", &settings).lines[0].width.max(1.)
+    }
+
+    // The output of :make and :run, between the buffer and the status line while there's a job
+    pub fn output_rect(&self) -> Option<Rect> {
+        if self.editor.job.is_none() || self.editor.terminal.is_some() {
+            return None;
+        }
+        let height = ((jobs::PANE_ROWS + 1) as f32 * self.line_height()).min(self.window_size.1 / 2.);
+        Some(Rect { x: 0., y: self.status_top() - height, w: self.window_size.0, h: height })
+    }
+
+    // The status line is the bottom line of the window. Zen mode hides it but for the prompt
+    pub fn status_top(&self) -> f32 {
+        match self.chrome().status_line || self.editor.prompt_line().is_some() {
+            true => self.window_size.1 - self.line_height(),
+            false => self.window_size.1,
+        }
+    }
+
+    // The buffer gets the window between the tab bar and the output pane or the status line, which
+    // is one line high. A terminal gets it all but the status line
+    fn fit_viewport(&mut self) {
+        let line_height = self.line_height();
+        self.update_rows();
+        let version = self.editor.buffer().version;
+        if self.scopes_of != Some(version) {
+            self.scopes = self.editor.scopes();
+            self.scopes_of = Some(version);
+        }
+        self.editor.sync_tabs();
+        self.viewport.top = if self.editor.terminal.is_none() && self.chrome().tabs { self.editor.tab_bar.height() } else { 0. };
+        let bottom = self.output_rect().map_or(self.status_top(), |pane| pane.y);
+        self.viewport.height = (bottom - self.viewport.top).max(0.);
+        self.viewport.content_height = self.rows.rows() as f32 * line_height;
+        self.editor.visible_lines = self.rows.lines(self.viewport.visible_lines(line_height));
+        self.editor.layout_settings = self.layout_settings();
+        let (cols, rows) = ((self.window_size.0 / self.advance()) as usize, (self.viewport.height / line_height) as usize);
+        if let Some(terminal) = &mut self.editor.terminal {
+            let resized = terminal.resize(cols, rows);
+            self.editor.notifications.report(resized);
+        }
+    }
+
+    // Sends what a key typed to the program in the terminal
+    fn send_to_terminal(&mut self, bytes: &[u8]) {
+        if let Some(terminal) = &mut self.editor.terminal {
+            let written = terminal.write(bytes);
+            self.editor.notifications.report(written);
+        }
+    }
+
+    // Scrolls just enough for the row of the cursor to be on screen
+    fn scroll_to_cursor(&mut self) {
+        let line_height = self.line_height();
+        let top = self.row_of(self.editor.buffer().selections[0].head) as f32 * line_height;
+        if top < self.viewport.scroll_y {
+            self.viewport.scroll_to(top);
+        } else if top + line_height > self.viewport.scroll_y + self.viewport.height {
+            self.viewport.scroll_to(top + line_height - self.viewport.height);
+        }
+        self.previous_scroll_y = self.viewport.scroll_y;
+    }
+
+    // The line of the buffer at y in the window: its bytes without the newline, and y from the top of
+    // its first row
+    fn line_at(&self, y: f32) -> (Range<usize>, f32) {
+        let text = &self.editor.buffer().text;
+        let line_height = self.line_height();
+        let y = y - self.viewport.top + self.viewport.scroll_y;
+        let line = self.rows.line_of_row((y / line_height).max(0.) as usize);
+        let bytes = lines_bytes(text, line..line + 1);
+        let end = if text[bytes.clone()].ends_with('\n') { bytes.end - 1 } else { bytes.end };
+        (bytes.start..end, y - self.rows.row_of_line(line) as f32 * line_height)
+    }
+
+    // The byte of the buffer closest to (x, y) in the window
+    fn byte_at(&self, x: f32, y: f32) -> usize {
+        let (bytes, y) = self.line_at(y);
+        bytes.start + layout(&self.fontstack, &self.editor.buffer().text[bytes], &self.layout_settings()).hit_test(x - self.text_left(), y)
+    }
+
+    // The URL at (x, y) in the window, if there is one
+    fn link_at(&self, x: f32, y: f32) -> Option<String> {
+        if self.editor.terminal.is_some() || y < self.viewport.top || y >= self.viewport.top + self.viewport.height {
+            return None;
+        }
+        let (bytes, y) = self.line_at(y);
+        let line = &self.editor.buffer().text[bytes];
+        let url = links::url_at(&layout(&self.fontstack, line, &self.layout_settings()), line, x - self.text_left(), y)?;
+        Some(line[url].to_string())
+    }
+
+    // First lines of the scopes around the top of the view, pinned over it while scrolled off
+    pub fn sticky_lines(&self) -> Vec<usize> {
+        if self.editor.terminal.is_some() {
+            return Vec::new();
+        }
+        let top = self.rows.lines(self.viewport.visible_lines(self.line_height())).start;
+        sticky::context_lines(&self.scopes, top, sticky::MAX_ROWS)
+    }
+
+    // What has a tooltip where it's drawn now: the diagnostic signs in the gutter of the visible
+    // lines, and the branch on the status line
+    fn tips(&self, now: Instant) -> Vec<Tip> {
+        let (line_height, gutter) = (self.line_height(), self.gutter_width());
+        let buffer = self.editor.buffer();
+        let visible = self.rows.lines(self.viewport.visible_lines(line_height));
+        let sign_rect = |line: usize| {
+            let shown = gutter > 0. && visible.contains(&line) && !buffer.folds.is_hidden(line);
+            shown.then(|| Rect { x: 0., y: self.viewport.top + self.rows.row_of_line(line) as f32 * line_height - self.viewport.scroll_y, w: gutter, h: line_height })
+        };
+        let diagnostics = buffer.path().map_or(&[][..], |path| self.editor.diagnostics.get(&path));
+        let mut tips = tooltip::gutter_tips(diagnostics, &buffer.text, sign_rect);
+        tips.extend(tooltip::tab_tips(&self.editor.tab_bar.tabs, &self.tab_rects()));
+        if let (Some((branch, ahead_behind)), None) = (&self.editor.branch, self.editor.prompt_line()) {
+            let advance = self.advance();
+            let line = self.status_line.render(&self.editor.status_context(now)).line((self.window_size.0 / advance) as usize);
+            let segment = format!("⎇ {branch}");
+            if let Some(at) = line.find(&segment) {
+                let rect = Rect { x: line[..at].chars().count() as f32 * advance, y: self.status_top(), w: segment.chars().count() as f32 * advance, h: line_height };
+                tips.push(tooltip::branch_tip(rect, branch, *ahead_behind));
+            }
+        }
+        tips
+    }
+
+    // The tooltip the mouse has rested on long enough, as pieces of text one below the other, and
+    // where to draw it
+    pub fn tooltip(&self, now: Instant) -> Option<(Vec<Piece>, Rect)> {
+        let (elements, rect) = self.tooltips.popup(now, |elements| self.popup_size(elements), self.window_size)?;
+        Some((markdown::pieces(&elements), rect))
+    }
+
+    // The hover or signature help popup, drawn like a tooltip by the cursor at `cursor` on screen
+    pub fn hover_popup(&self, cursor: Rect) -> Option<(Vec<Piece>, Rect)> {
+        let popup = self.editor.popups.current.as_ref()?;
+        let rect = hover::place(popup.kind, cursor, self.popup_size(&popup.elements), (self.window_size.0, self.viewport.top + self.viewport.height));
+        Some((markdown::pieces(&popup.elements), rect))
+    }
+
+    // The completion menu, under the start of the word it completes at `start` on screen
+    pub fn completion_menu(&self, start: Rect) -> Option<(&Menu, Rect)> {
+        let menu = self.editor.completion.as_ref()?;
+        let columns = menu.items.iter().map(|item| item.chars().count()).max().unwrap_or(0);
+        let size = ((columns + 1) as f32 * self.advance(), menu.items.len() as f32 * self.line_height());
+        Some((menu, hover::place(PopupKind::Hover, start, size, (self.window_size.0, self.viewport.top + self.viewport.height))))
+    }
+
+    // Room for the lines of `elements` in a tooltip
+    fn popup_size(&self, elements: &[Element]) -> (f32, f32) {
+        let (advance, line_height) = (self.advance(), self.line_height());
+        let lines: Vec<String> = markdown::pieces(elements).iter().flat_map(|piece| piece.text.lines().map(|line| " ".repeat(piece.indent) + line).collect::<Vec<_>>()).collect();
+        let columns = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+        ((columns + 1) as f32 * advance, lines.len() as f32 * line_height + advance / 2.)
+    }
+
+    // Where each tab of the bar over the buffer goes, none while it's hidden
+    pub fn tab_rects(&self) -> Vec<TabRect> {
+        if self.viewport.top == 0. {
+            return Vec::new();
+        }
+        let settings = LayoutSettings { wrap_width: None, ..self.layout_settings() };
+        self.editor.tab_bar.layout(self.text_width(), |title| layout(&self.fontstack, title, &settings).lines.first().map_or(0., |line| line.width))
+    }
+
+    // The hand over links, which Ctrl+click opens
+    pub fn cursor_icon(&self) -> CursorIcon {
+        match self.link_at(self.cursor_pos.0, self.cursor_pos.1) {
+            Some(_) => CursorIcon::Hand,
+            None => CursorIcon::Default,
+        }
+    }
+
+    // Touch gestures on the buffer
+    fn touched(&mut self, gesture: Gesture) {
+        let selection = match gesture {
+            Gesture::Tap { x, y } => Selection::cursor(self.byte_at(x, y)),
+            Gesture::LongPress { x, y } => {
+                let at = self.byte_at(x, y);
+                let word = word_around(&self.editor.buffer().text, &(at..at));
+                Selection { anchor: word.start, head: word.end, goal: None }
+            }
+            Gesture::DragHandle { handle, x, y } => {
+                let at = self.byte_at(x, y);
+                let mut selection = self.editor.buffer().selections[0];
+                match handle {
+                    Handle::Anchor => selection.anchor = at,
+                    Handle::Head => selection.head = at,
+                }
+                selection
+            }
+            Gesture::Scroll { .. } => return,
+        };
+        self.editor.buffer_mut().selections = vec![selection];
+        if let Gesture::LongPress { .. } = gesture {
+            self.touch.handles = Some([self.handle_at(selection.anchor), self.handle_at(selection.head)]);
+        }
+    }
+
+    // Moves the handles a long press put on the selection along with its ends, and takes them away
+    // with the selection
+    fn place_handles(&mut self) {
+        let selections = &self.editor.buffer().selections;
+        match selections[..] {
+            [selection] if self.touch.handles.is_some() && selection.anchor != selection.head => self.touch.handles = Some([self.handle_at(selection.anchor), self.handle_at(selection.head)]),
+            _ => self.touch.handles = None,
+        }
+    }
+
+    // Where the handle for `byte` goes in the window: below it, at the bottom of its row
+    fn handle_at(&self, byte: usize) -> (f32, f32) {
+        let text = &self.editor.buffer().text;
+        let line = line_starts(text).partition_point(|&start| start <= byte) - 1;
+        let bytes = lines_bytes(text, line..line + 1);
+        let shown = layout(&self.fontstack, text[bytes.clone()].trim_end_matches('\n'), &self.layout_settings());
+        let x = self.text_left() + shown.cursor_rect(byte - bytes.start, 0.).x;
+        (x, self.viewport.top + (self.row_of(byte) + 1) as f32 * self.line_height() - self.viewport.scroll_y)
+    }
+
+    // Hands the keys received since the last frame to the editor. Characters that came in faster
+    // than anyone types are pasted as one edit instead
+    fn handle_keys(&mut self, now: Instant) {
+        let keys = std::mem::take(&mut self.keys);
+        let mut rest = keys.as_slice();
+        while let Some(&(key, received)) = rest.first() {
+            let run = rest.iter().take_while(|(_, received)| *received).count();
+            if !received {
+                self.editor.key(&self.registry, key, now);
+                rest = &rest[1..];
+                continue;
+            }
+            let (typed, after) = rest.split_at(run);
+            let mut detector = PasteDetector::default();
+            for (key, _) in typed {
+                if let Key::Char { typed, .. } = key {
+                    detector.character(*typed);
+                }
+            }
+            let mut typed = typed.iter();
+            for found in detector.take() {
+                match found {
+                    Typed::Char(_) => {
+                        let Some(&(key, _)) = typed.next() else { break };
+                        self.editor.key(&self.registry, key, now);
+                    }
+                    Typed::Paste(text) => {
+                        typed.by_ref().take(text.chars().count()).for_each(drop);
+                        self.editor.paste(&text);
+                    }
+                }
+            }
+            rest = after;
+        }
+    }
+
+    // Everything input asked for since the last frame: keys, touches, menu picks and files to open.
+    // Called before drawing
+    pub fn handle_input(&mut self, now: Instant) {
+        let cursor = (self.editor.current, self.editor.buffer().selections[0].head);
+        self.handle_keys(now);
+        for gesture in std::mem::take(&mut self.gestures) {
+            self.touched(gesture);
+        }
+        for command_line in std::mem::take(&mut self.menu_commands) {
+            run_reporting(&self.registry, &mut self.editor, &command_line);
+        }
+        for path in std::mem::take(&mut self.open_files) {
+            let result = self.editor.open(&path.display().to_string());
+            self.editor.notifications.report(result);
+        }
+        // Found or trusted just now, and its bindings win over the user's
+        if std::mem::take(&mut self.editor.project_changed) {
+            if let Some(project) = &self.editor.project {
+                project.apply(&mut self.registry);
+            }
+        }
+        if let Some(edited) = self.editor.last_edit.take() {
+            self.auto_save.edited(edited);
+            self.last_edit = edited;
+        }
+        // Picked or previewed with :font, in place of the primary font. The renderer's caches go
+        // with the glyphs of the old one
+        if let Some(path) = self.editor.requested_font.take() {
+            if let Some(fontstack) = self.editor.notifications.report(self.fontstack.with_primary(&path)) {
+                self.fontstack = Rc::new(fontstack);
+                self.editor.fontstack = Some(self.fontstack.clone());
+                self.rows_of = None;
+                self.editor.trim_requested = true;
+            }
+        }
+        self.editor.update_panes();
+        self.editor.update_status();
+        self.editor.update_primary();
+        self.fit_viewport();
+        if (self.editor.current, self.editor.buffer().selections[0].head) != cursor {
+            self.scroll_to_cursor();
+        }
+        self.place_handles();
+    }
+
+    // Applies the options to everything that animates or has a size
+    pub fn set_accessibility(&mut self, config: AccessibilityConfig) {
+        let reduce_motion = config.reduce_motion();
+        self.viewport.animate = !reduce_motion;
+        self.viewport.kinetic = !reduce_motion;
+        self.scrollbar.no_fade = reduce_motion;
+        self.splash.no_fade = reduce_motion;
+        self.scrollbar.high_contrast = config.high_contrast;
+        let min_font_size = config.min_font_size();
+        self.viewport.min_font_size = min_font_size;
+        self.viewport.font_size = self.viewport.font_size.max(min_font_size);
+        self.settings.font_size = self.settings.font_size.max(min_font_size);
+        self.zoom.min_font_size = min_font_size;
+        self.accessibility = config;
+    }
+
+    pub fn resized(&mut self, width: f32, height: f32) {
+        self.window_size = (width, height);
+        self.fit_viewport();
+    }
+
+    pub fn mouse_wheel(&mut self, delta: MouseScrollDelta, phase: TouchPhase, now: Instant) {
+        // Ctrl zooms the pane under the mouse instead
+        if self.modifiers.ctrl() {
+            let (x, y) = self.cursor_pos;
+            let Some(pane) = panes::pane_at(&self.layout, self.window_rect(), x, y) else { return };
+            match delta {
+                MouseScrollDelta::LineDelta(_, lines) => self.zoom.scroll(pane, self.viewport.font_size, lines),
+                MouseScrollDelta::PixelDelta(pos) => self.zoom.scroll_pixels(pane, self.viewport.font_size, pos.y as f32),
+            }
+            // The pane's rows are as high as its new size
+            self.fit_viewport();
+            self.scroll_to_cursor();
+            return;
+        }
+        match delta {
+            MouseScrollDelta::LineDelta(_, lines) => self.viewport.scroll_lines(-lines, self.line_height()),
+            MouseScrollDelta::PixelDelta(pos) => self.viewport.scroll_pixels(-pos.y as f32, phase, now),
+        }
+        self.scrollbar.activity(now);
+        self.clock.start(now);
+    }
+
+    pub fn cursor_moved(&mut self, x: f32, y: f32, now: Instant) {
+        self.cursor_pos = (x, y);
+        self.tooltips.set_tips(self.tips(now));
+        self.tooltips.mouse_moved(x, y, now);
+        let width = self.text_width();
+        self.scrollbar.mouse_moved(&mut self.viewport, width, x, y, now);
+    }
+
+    pub fn left_mouse(&mut self, state: ElementState, now: Instant) {
+        match state {
+            ElementState::Pressed => {
+                let (x, y) = self.cursor_pos;
+                let tabs = self.tab_rects();
+                match self.editor.tab_bar.click(&tabs, x, y) {
+                    Some(Click::Select(idx)) => return self.editor.select_tab(idx),
+                    Some(Click::Close(idx)) => {
+                        let closed = self.editor.close_tab(idx);
+                        self.editor.notifications.report(closed);
+                        return;
+                    }
+                    None => {}
+                }
+                // A pinned line in the sticky header goes to its scope
+                if let Some(line) = sticky::line_at(&self.sticky_lines(), y - self.viewport.top, self.line_height()).filter(|_| x < self.text_width()) {
+                    let buffer = self.editor.buffer_mut();
+                    let at = line_starts(&buffer.text)[line];
+                    buffer.selections = vec![Selection::cursor(at)];
+                    self.scroll_to_cursor();
+                    return;
+                }
+                if let Some(url) = self.link_at(x, y).filter(|_| self.modifiers.ctrl()) {
+                    let opened = links::open_url(&url).map_err(|e| format!("Couldn't open {url}: {e}"));
+                    self.editor.notifications.report(opened);
+                    return;
+                }
+                let width = self.text_width();
+                self.scrollbar.mouse_down(&mut self.viewport, width, x, y, now);
+            }
+            ElementState::Released => self.scrollbar.mouse_up(now),
+        }
+        self.clock.start(now);
+    }
+
+    // Pastes the primary selection where the mouse is, on X11 and Wayland
+    pub fn middle_mouse(&mut self, state: ElementState) {
+        let (x, y) = self.cursor_pos;
+        let in_buffer = y >= self.viewport.top && y < self.viewport.top + self.viewport.height && x < self.text_width();
+        if state != ElementState::Pressed || !in_buffer || self.editor.terminal.is_some() {
+            return;
+        }
+        let Some(text) = self.editor.clipboard.middle_click() else { return };
+        let at = self.byte_at(x, y);
+        self.editor.buffer_mut().selections = vec![Selection::cursor(at)];
+        self.editor.paste(&text);
+    }
+
+    pub fn touch(&mut self, touch: Touch, now: Instant) {
+        let gesture = self.touch.touch(touch.id, touch.phase, touch.location.x as f32, touch.location.y as f32, now);
+        self.gesture(gesture, now);
+    }
+
+    fn gesture(&mut self, gesture: Option<Gesture>, now: Instant) {
+        match gesture {
+            Some(Gesture::Scroll { dy, phase }) => {
+                self.viewport.scroll_pixels(-dy, phase, now);
+                self.scrollbar.activity(now);
+                self.clock.start(now);
+            }
+            Some(gesture) => self.gestures.push(gesture),
+            None => {}
+        }
+    }
+
+    pub fn key_input(&mut self, key: VirtualKeyCode, scancode: u32, state: ElementState, now: Instant) {
+        match state {
+            ElementState::Pressed => {
+                self.last_key = now;
+                self.cursor_shown = true;
+                // Every key goes to the terminal, repeated by the platform like in any terminal
+                if self.editor.terminal.is_some() {
+                    let ctrl = self.modifiers.ctrl().then(|| Scancodes::native().qwerty(scancode, false)).flatten();
+                    let bytes = terminal::key_bytes(key, self.modifiers).or_else(|| ctrl.map(|c| terminal::char_bytes(c, self.modifiers)));
+                    self.scancode = Some(scancode);
+                    if let Some(bytes) = bytes {
+                        self.send_to_terminal(&bytes);
+                    }
+                    return;
+                }
+                if is_modifier(key) {
+                    return;
+                }
+                if !self.key_repeat.press(key, now) {
+                    self.platform_repeat = matches!(self.held, Some((_, Some(Key::Char { .. }))));
+                    return;
+                }
+                // With Ctrl or Alt held, keys go by where they are, like normal mode commands
+                let qwerty = || Scancodes::native().qwerty(scancode, false);
+                let ctrl = self.modifiers.ctrl().then(qwerty).flatten().map(Key::Ctrl);
+                let alt = self.alt_held().then(qwerty).flatten().map(Key::Alt);
+                let named = ctrl.or(alt).or_else(|| named_key(key, self.modifiers.shift()));
+                self.scancode = Some(scancode);
+                self.held = Some((key, named));
+                self.keys.extend(named.map(|key| (key, false)));
+            }
+            ElementState::Released => {
+                self.key_repeat.release(key);
+                if self.held.is_some_and(|(held, _)| held == key) {
+                    self.held = None;
+                }
+            }
+        }
+    }
+
+    // Alt makes keys bindings, except on macOS, where Option is for typing characters like é
+    fn alt_held(&self) -> bool {
+        self.modifiers.alt() && !cfg!(target_os = "macos")
+    }
+
+    // winit's ReceivedCharacter, for the key pressed last. Control characters are left out, as they
+    // come with named keys or Ctrl, which key_input has handled already
+    pub fn received_character(&mut self, c: char) {
+        if std::mem::take(&mut self.platform_repeat) || c.is_control() || self.modifiers.ctrl() || self.alt_held() {
+            return;
+        }
+        if self.editor.terminal.is_some() {
+            return self.send_to_terminal(&terminal::char_bytes(c, self.modifiers));
+        }
+        let command = keymap::command_key(self.keyboard.mapping, Scancodes::native(), c, self.scancode, self.modifiers.shift());
+        let key = Key::Char { typed: c, command };
+        // What the held key repeats from now on
+        if let Some((_, held @ None)) = &mut self.held {
+            *held = Some(key);
+        }
+        self.keys.push((key, true));
+    }
+
+    // winit's Focused
+    pub fn focus_changed(&mut self, focused: bool, now: Instant) {
+        self.focused = focused;
+        if focused {
+            // Blinks from now, starting on
+            self.last_key = now;
+        } else {
+            self.key_repeat.clear();
+            self.held = None;
+            self.auto_save.focus_lost(&mut self.editor);
+        }
+        self.cursor_shown = true;
+    }
+
+    // Multiplies the text's alpha in the text shader
+    pub fn text_focus(&self) -> f32 {
+        if self.focused { 1. } else { UNFOCUSED_TEXT }
+    }
+
+    pub fn cursor_style(&self) -> CursorStyle {
+        if self.focused { CursorStyle::Block } else { CursorStyle::Hollow }
+    }
+
+    pub fn magnify(&mut self, delta: f32) {
+        self.viewport.magnify(delta);
+    }
+
+    // Whether anything will change without further input
+    pub fn is_animating(&self, now: Instant) -> bool {
+        !self.suspended && (self.viewport.is_animating() || self.scrollbar.is_fading(now) || self.splash.is_fading(now))
+    }
+
+    // Runs the fixed steps due by `now`. Returns true if there is something new to draw
+    pub fn update(&mut self, now: Instant) -> bool {
+        let mut changed = self.editor.notifications.expire(now);
+        changed |= self.auto_save.run_if_idle(now, &mut self.editor) > 0;
+        self.editor.track_unsaved();
+        self.editor.index_words();
+        if let Some(terminal) = &mut self.editor.terminal {
+            changed |= terminal.poll();
+            if terminal.has_exited() {
+                self.editor.terminal = None;
+                changed = true;
+            }
+        }
+        if let Some(job) = &mut self.editor.job {
+            changed |= job.poll();
+        }
+        changed |= self.editor.poll_background();
+        changed |= self.editor.poll_dirs(now);
+        if let Some(tree) = &mut self.editor.file_tree {
+            changed |= self.editor.notifications.report(tree.poll_status()).unwrap_or(false);
+        }
+        changed |= self.editor.poll_blame();
+        changed |= self.editor.poll_highlighting();
+        changed |= self.editor.poll_language_servers();
+        let cursor = (self.editor.current, self.editor.buffer().selections[0].head);
+        if self.editor.poll_debugger() {
+            // Stopping may have opened another file, and the panes and gutter take room
+            self.fit_viewport();
+            if (self.editor.current, self.editor.buffer().selections[0].head) != cursor {
+                self.scroll_to_cursor();
+            }
+            changed = true;
+        }
+        if let Some(gesture) = self.touch.update(now) {
+            self.gesture(Some(gesture), now);
+            changed = true;
+        }
+        // A tooltip is due
+        changed |= self.tooltips.current(now).is_some();
+        // The clock shows another minute
+        if self.status_line.clock {
+            let time = statusline::local_time(SystemTime::now());
+            changed |= std::mem::replace(&mut self.time, time) != time;
+        }
+        // The which-key popup is due
+        changed |= self.editor.which_key_at().is_some_and(|at| at <= now);
+        let cursor_shown = !self.focused || self.accessibility.cursor_visible(self.last_key, now);
+        if cursor_shown != self.cursor_shown {
+            self.cursor_shown = cursor_shown;
+            changed = true;
+        }
+        if let Some((_, times)) = self.key_repeat.due(now) {
+            if let Some((_, Some(key))) = self.held {
+                self.keys.extend(std::iter::repeat_n((key, false), times as usize));
+                changed = true;
+            }
+        }
+        if !self.is_animating(now) {
+            self.clock.stop();
+            return changed;
+        }
+        for _ in 0..self.clock.advance(now) {
+            self.previous_scroll_y = self.viewport.scroll_y;
+            self.viewport.tick(STEP);
+            if self.viewport.is_animating() {
+                self.scrollbar.activity(now);
+            }
+            changed = true;
+        }
+        // Keep asking for frames while fading, even though no step changes anything
+        changed || self.scrollbar.is_fading(now) || self.splash.is_fading(now)
+    }
+
+    // The scroll position to draw, between the last two steps
+    pub fn render_scroll_y(&self) -> f32 {
+        let alpha = self.clock.alpha().min(1.);
+        self.previous_scroll_y + (self.viewport.scroll_y - self.previous_scroll_y) * alpha
+    }
+
+    // When the event loop should wake up again without input, None to wait for input
+    pub fn wake_at(&self, now: Instant) -> Option<Instant> {
+        if self.is_animating(now) {
+            return Some(now + STEP);
+        }
+        let git = self.editor.file_tree.as_ref().is_some_and(FileTree::is_pending) || self.editor.blame.as_ref().is_some_and(Blame::is_pending);
+        let highlights = self.editor.highlights_due();
+        let servers = self.editor.language_servers.values().any(Option::is_some);
+        let background = self.editor.job.as_ref().is_some_and(Job::is_running) || self.editor.progress.is_busy();
+        let poll = (self.editor.terminal.is_some() || self.editor.debugger.is_some() || background || servers || git || highlights).then_some(now + POLL);
+        let which_key = self.editor.which_key_at().filter(|at| *at > now);
+        let clock = self.status_line.clock.then(|| now + statusline::until_next_minute(SystemTime::now()));
+        [self.editor.notifications.next_expiry(), poll, which_key, clock, self.editor.next_dir_poll(), self.tooltips.wake_at(now), self.auto_save.wake_at(), self.key_repeat.next_at(), self.touch.wake_at(), self.accessibility.next_blink(self.last_key, now).filter(|_| self.focused)].into_iter().flatten().min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::Buffer;
+
+    // An 800x600 window with the font from resources
+    fn app() -> App {
+        let fontstack = FontStack::new(std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/firacode-regular.ttf"))).unwrap();
+        App::new(fontstack, Notifications::default(), (800., 600.))
+    }
+
+    #[test]
+    fn fixed_steps() {
+        let mut clock = FixedStep::default();
+        let start = Instant::now();
+        assert_eq!(clock.advance(start), 0);
+        assert_eq!(clock.advance(start + STEP * 2 + STEP / 2), 2);
+        assert!((clock.alpha() - 0.5).abs() < 0.01);
+        assert_eq!(clock.advance(start + STEP * 3), 1);
+        assert_eq!(clock.advance(start + Duration::from_secs(10)), MAX_STEPS_PER_FRAME);
+    }
+
+    #[test]
+    fn scrolling_is_independent_of_frame_rate() {
+        let run = |frame: Duration| {
+            let mut app = app();
+            app.viewport.content_height = 10_000.;
+            let start = Instant::now();
+            app.mouse_wheel(MouseScrollDelta::LineDelta(0., -10.), TouchPhase::Moved, start);
+            let mut now = start;
+            while now < start + Duration::from_millis(100) {
+                now += frame;
+                app.update(now);
+            }
+            app.viewport.scroll_y
+        };
+        assert_eq!(run(Duration::from_millis(25)), run(Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn modifiers_keep_the_held_key_repeating() {
+        let mut app = app();
+        let start = Instant::now();
+        app.key_input(VirtualKeyCode::J, 0x24, ElementState::Pressed, start);
+        app.received_character('j');
+        app.key_input(VirtualKeyCode::LShift, 0x2a, ElementState::Pressed, start + Duration::from_millis(100));
+        app.update(start + Duration::from_secs(1));
+        assert!(app.keys.len() > 1);
+        assert!(app.keys[1..].iter().all(|(key, _)| *key == app.keys[0].0));
+    }
+
+    #[test]
+    fn unfocused_stops_blinking() {
+        let mut app = app();
+        let start = Instant::now();
+        app.key_input(VirtualKeyCode::A, 0x1e, ElementState::Pressed, start);
+        app.focus_changed(false, start);
+        assert_eq!((app.text_focus(), app.cursor_style()), (UNFOCUSED_TEXT, CursorStyle::Hollow));
+        app.update(start + Duration::from_millis(1600));
+        assert!(app.cursor_shown);
+        assert_eq!(app.wake_at(start + Duration::from_millis(1600)), None);
+
+        let back = start + Duration::from_secs(2);
+        app.focus_changed(true, back);
+        assert_eq!((app.text_focus(), app.cursor_style()), (1., CursorStyle::Block));
+        app.update(back + Duration::from_millis(800));
+        assert!(!app.cursor_shown);
+    }
+
+    #[test]
+    fn opening_a_file_uses_its_project_once_trusted() {
+        let dir = std::env::temp_dir().join(format!("rakoune-app-project-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join(crate::project::FILE_NAME), "format_on_save = true\n[bind]\n\"<C-b>\" = \"messages\"\n").unwrap();
+        std::fs::write(dir.join("src/main.rs"), "fn main() {}\n").unwrap();
+        let trusted = dir.join("trusted");
+
+        let mut app = app();
+        app.editor.trust = crate::project::TrustStore::load(trusted.clone());
+        app.open_files.push(dir.join("src/main.rs"));
+        app.handle_input(Instant::now());
+        // Nothing from it is used until it's trusted
+        assert!(app.editor.project.is_none() && app.registry.binding("<C-b>").is_none());
+        app.received_character('y');
+        app.handle_input(Instant::now());
+        assert_eq!(app.editor.project.as_ref().unwrap().root, dir);
+        assert_eq!(app.registry.binding("<C-b>"), Some("messages"));
+
+        // Remembered, so the next time it's used right away
+        let mut app = self::app();
+        app.editor.trust = crate::project::TrustStore::load(trusted);
+        app.open_files.push(dir.join("src"));
+        app.handle_input(Instant::now());
+        assert!(app.editor.questions.current().is_none());
+        assert_eq!(app.registry.binding("<C-b>"), Some("messages"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn runs_jobs_in_the_output_pane() {
+        let dir = std::env::temp_dir().join(format!("rakoune-run-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt");
+        std::fs::write(&file, "one\ntwo three\n").unwrap();
+        let mut app = app();
+        app.menu_commands.push(format!("run echo compiling; echo '{}:2:5: error' >&2; exit 1", file.display()));
+        app.handle_input(Instant::now());
+        let pane = app.output_rect().unwrap();
+        assert_eq!(app.viewport.top + app.viewport.height, pane.y);
+        assert_eq!(pane.y + pane.h, app.status_top());
+        let start = Instant::now();
+        while app.editor.job.as_ref().unwrap().is_running() && start.elapsed() < Duration::from_secs(5) {
+            app.update(Instant::now());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(app.editor.job.as_ref().unwrap().title(), "echo compiling; echo '".to_string() + &file.display().to_string() + ":2:5: error' >&2; exit 1: exited with 1, 1 error");
+
+        app.editor.key(&app.registry, Key::Function { number: 8, shift: false }, Instant::now());
+        assert_eq!(app.editor.buffer().name, file.display().to_string());
+        assert_eq!(app.editor.buffer().cursor(), "one\ntwo ".len());
+        app.menu_commands.push("close-output".to_string());
+        app.handle_input(Instant::now());
+        assert_eq!((app.output_rect(), app.viewport.top + app.viewport.height), (None, app.status_top()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ctrl_scrolling_zooms_the_pane_under_the_mouse() {
+        let mut app = app();
+        let now = Instant::now();
+        let (line_height, global) = (app.line_height(), app.viewport.font_size);
+        app.cursor_moved(100., 100., now);
+        app.modifiers = ModifiersState::CTRL;
+        app.mouse_wheel(MouseScrollDelta::LineDelta(0., 2.), TouchPhase::Moved, now);
+        assert!(app.font_size() > global && app.line_height() > line_height);
+        assert_eq!((app.viewport.font_size, app.layout_settings().font_size), (global, app.font_size()));
+        assert_eq!(app.editor.layout_settings.font_size, app.font_size());
+
+        app.zoom.reset(0);
+        assert_eq!(app.line_height(), line_height);
+    }
+
+    #[test]
+    fn loads_the_font_the_editor_asks_for() {
+        let mut app = app();
+        let name = app.fontstack.faces[0].name.clone();
+        app.editor.requested_font = Some(PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/linja-pona-4.1.otf")));
+        app.handle_input(Instant::now());
+        assert!(app.editor.trim_requested && app.editor.requested_font.is_none());
+        assert_ne!(app.fontstack.faces[0].name, name);
+        assert!(Rc::ptr_eq(app.editor.fontstack.as_ref().unwrap(), &app.fontstack));
+        assert_eq!(*app.rows(), RowIndex::new(&app.fontstack, &app.editor.buffer().text, &app.layout_settings()));
+
+        app.editor.requested_font = Some(PathBuf::from("/nonexistent.ttf"));
+        app.handle_input(Instant::now());
+        assert!(app.editor.notifications.latest(Instant::now()).is_some());
+    }
+
+    #[test]
+    fn zen_mode_hides_the_chrome_and_centers_the_text() {
+        let dir = std::env::temp_dir().join(format!("rakoune-zen-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut app = app();
+        // Once the splash has faded
+        let now = Instant::now() + Duration::from_secs(10);
+        app.focus_changed(false, now);
+        app.open_files.extend([dir.join("a.txt"), dir.join("b.txt")]);
+        app.handle_input(now);
+        app.editor.buffer_mut().marks.set('a', 0);
+        app.handle_input(now);
+        assert!(app.viewport.top > 0. && app.gutter_width() > 0.);
+
+        app.menu_commands.push("zen".to_string());
+        app.handle_input(now);
+        assert_eq!((app.viewport.top, app.tab_rects().len(), app.gutter_width()), (0., 0, 0.));
+        assert_eq!((app.status_top(), app.viewport.height), (600., 600.));
+        let (left, width) = app.text_column();
+        assert_eq!((left, width), ((800. - 80. * app.advance()) / 2., 80. * app.advance()));
+        assert_eq!(app.layout_settings().wrap_width, Some(width));
+        for c in "ihello".chars() {
+            app.received_character(c);
+            app.handle_input(now);
+        }
+        assert_eq!(app.byte_at(left + app.advance() * 2.2, 1.), 2);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn tab_bar_switches_and_closes_buffers() {
+        let dir = std::env::temp_dir().join(format!("rakoune-tabs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut app = app();
+        // Once the splash has faded
+        let now = Instant::now() + Duration::from_secs(10);
+        app.focus_changed(false, now);
+        app.handle_input(now);
+        assert_eq!((app.viewport.top, app.tab_rects().len()), (0., 0));
+        app.open_files.extend([dir.join("a.txt"), dir.join("b.txt")]);
+        app.handle_input(now);
+        assert_eq!(app.viewport.top, crate::tabs::HEIGHT);
+        assert_eq!(app.viewport.height, 600. - app.line_height() - crate::tabs::HEIGHT);
+        let tabs = app.tab_rects();
+        assert_eq!(tabs.iter().map(|tab| (tab.title.as_str(), tab.active)).collect::<Vec<_>>(), vec![("a.txt", false), ("b.txt", true)]);
+
+        for c in "gT".chars() {
+            app.received_character(c);
+            app.handle_input(now);
+        }
+        assert_eq!(app.editor.current, 0);
+        app.cursor_moved(tabs[1].rect.x + 1., 5., now);
+        app.left_mouse(ElementState::Pressed, now);
+        assert_eq!(app.editor.current, 1);
+
+        // Closing the first keeps b.txt shown, and with one buffer left the bar goes away
+        app.cursor_moved(tabs[0].close.x + 1., tabs[0].close.y + 1., now);
+        app.left_mouse(ElementState::Pressed, now);
+        app.handle_input(now);
+        assert_eq!(app.editor.buffers.len(), 1);
+        assert_eq!(app.editor.buffer().name, dir.join("b.txt").display().to_string());
+        assert_eq!(app.viewport.top, 0.);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn wakes_for_the_which_key_popup() {
+        let mut app = app();
+        // Once the splash has faded
+        let start = Instant::now() + Duration::from_secs(10);
+        app.focus_changed(false, start);
+        app.received_character('g');
+        app.handle_input(start);
+        let shown = start + crate::whichkey::DELAY;
+        assert_eq!(app.wake_at(start), Some(shown));
+        assert!(app.update(shown));
+        assert_eq!(app.editor.which_key_popup(&app.registry, shown), Some(vec!["T  tabprev".to_string(), "t  tabnext".to_string()]));
+        assert_eq!(app.wake_at(shown), None);
+    }
+
+    #[test]
+    fn keys_reach_the_editor() {
+        let mut app = app();
+        let start = Instant::now();
+        app.key_input(VirtualKeyCode::I, 0x17, ElementState::Pressed, start);
+        app.received_character('i');
+        app.key_input(VirtualKeyCode::I, 0x17, ElementState::Released, start);
+        // Held, so it repeats what it typed. The platform's own repeats are left out
+        app.key_input(VirtualKeyCode::A, 0x1e, ElementState::Pressed, start);
+        app.received_character('a');
+        app.update(start + Duration::from_millis(450));
+        app.key_input(VirtualKeyCode::A, 0x1e, ElementState::Pressed, start + Duration::from_millis(450));
+        app.received_character('a');
+        app.handle_input(start);
+        assert_eq!(app.editor.buffer().text, "aaa");
+
+        // Faster than anyone types
+        "pasted all at once!".chars().for_each(|c| app.received_character(c));
+        app.handle_input(start);
+        assert_eq!(app.editor.buffer().text, "aaapasted all at once!");
+        app.key_input(VirtualKeyCode::Escape, 0x01, ElementState::Pressed, start);
+        app.handle_input(start);
+        assert_eq!(app.editor.mode, crate::statusline::Mode::Normal);
+    }
+
+    #[test]
+    fn wrapped_lines_scroll_by_row() {
+        let mut app = app();
+        *app.editor.buffer_mut() = Buffer::new("*scratch*", "word ".repeat(1000) + "\nlast", false);
+        app.editor.wrap = true;
+        app.handle_input(Instant::now());
+        let line_height = app.line_height();
+        let rows = app.rows().rows();
+        assert!(rows > 20);
+        assert_eq!(app.viewport.content_height, rows as f32 * line_height);
+        // The second row is still the first line, further in
+        let (bytes, y) = app.line_at(line_height * 1.5);
+        assert_eq!((bytes.start, y), (0, line_height * 1.5));
+        assert!(app.byte_at(1., line_height * 1.5) > 0);
+        assert_eq!(app.rows().line_of_row(rows - 1), 1);
+        // Typing finds the rows of the lines it edits again, like finding them all
+        let now = Instant::now();
+        app.received_character('i');
+        for key in [VirtualKeyCode::Return, VirtualKeyCode::Return, VirtualKeyCode::Back] {
+            app.key_input(key, 0, ElementState::Pressed, now);
+            app.key_input(key, 0, ElementState::Released, now);
+        }
+        app.received_character('x');
+        app.handle_input(now);
+        assert!(app.editor.buffer().text.starts_with("\nxword "));
+        assert_eq!(*app.rows(), RowIndex::new(&app.fontstack, &app.editor.buffer().text, &app.layout_settings()));
+        // Folded away, the long line takes no rows
+        app.editor.buffer_mut().folds.closed.push(0..2);
+        app.handle_input(now);
+        assert_eq!(app.rows().rows(), 2);
+    }
+
+    #[test]
+    fn markdown_images_take_rows_below_their_line() {
+        let mut app = app();
+        let logo = std::path::absolute("resources/rakoune_logo.png").unwrap();
+        let markdown = format!("# Logo\n![logo]({})\nafter", logo.display());
+        *app.editor.buffer_mut() = Buffer::new("/docs/README.md", markdown, true);
+        app.handle_input(Instant::now());
+        let line_height = app.line_height();
+        let [(path, block)] = app.images() else { panic!("{:?}", app.images()) };
+        assert_eq!((path, block.after_line), (&logo, 1));
+        let rows = (block.height / line_height) as usize;
+        assert!(rows > 0 && block.height == rows as f32 * line_height);
+        assert_eq!(app.rows().row_of_line(2), 2 + rows);
+    }
+
+    #[test]
+    fn pins_the_scope_scrolled_off() {
+        let mut app = app();
+        let body: String = (0..100).map(|i| format!("    x = {i}\n")).collect();
+        *app.editor.buffer_mut() = Buffer::new("*scratch*", format!("def f():\n{body}"), false);
+        let line_height = app.line_height();
+        let start = Instant::now();
+        app.handle_input(start);
+        assert!(app.sticky_lines().is_empty());
+        app.viewport.scroll_to(line_height * 10.);
+        assert_eq!(app.sticky_lines(), vec![0]);
+        // Clicking the pinned line goes to it
+        app.editor.buffer_mut().selections = vec![Selection::cursor(200)];
+        app.cursor_moved(10., line_height / 2., start);
+        app.left_mouse(ElementState::Pressed, start);
+        assert_eq!(app.editor.buffer().cursor(), 0);
+        assert!(app.sticky_lines().is_empty());
+    }
+
+    #[test]
+    fn counts_the_font_files_read() {
+        use crate::memory::{Category, MemoryHost};
+        let app = app();
+        let size = std::fs::metadata("resources/firacode-regular.ttf").unwrap().len() as usize;
+        assert!(app.editor.memory_usage().category(Category::Fonts) >= size);
+    }
+
+    #[test]
+    fn long_presses_put_handles_on_the_selection() {
+        let mut app = app();
+        *app.editor.buffer_mut() = Buffer::new("*scratch*", "one two\n".to_string(), false);
+        let (left, top, line_height) = (app.text_left(), app.viewport.top, app.line_height());
+        app.gestures.push(Gesture::LongPress { x: left + 1., y: top + 1. });
+        app.handle_input(Instant::now());
+        let [anchor, head] = app.touch.handles.unwrap();
+        assert_eq!((anchor, head.1), ((left, top + line_height), top + line_height));
+        assert!(head.0 > anchor.0);
+        assert_eq!(app.touch.handle_shapes([1.; 4]).len(), 4);
+
+        // Tapping leaves a cursor, without handles
+        app.gestures.push(Gesture::Tap { x: left + 1., y: top + 1. });
+        app.handle_input(Instant::now());
+        assert_eq!(app.touch.handles, None);
+    }
+
+    #[test]
+    fn middle_click_pastes_the_selected_text() {
+        let mut app = app();
+        app.editor.clipboard.backend = crate::clipboard::Backend::Internal;
+        *app.editor.buffer_mut() = Buffer::new("*scratch*", "one two\n".to_string(), false);
+        app.editor.buffer_mut().selections = vec![Selection { anchor: 0, head: 3, goal: None }];
+        let now = Instant::now();
+        app.handle_input(now);
+        app.cursor_moved(1., 1., now);
+        app.middle_mouse(ElementState::Pressed);
+        assert_eq!(app.editor.buffer().text, "oneone two\n");
+
+        app.editor.clipboard.config.middle_click_paste = false;
+        app.middle_mouse(ElementState::Pressed);
+        assert_eq!(app.editor.buffer().text, "oneone two\n");
+    }
+
+    #[test]
+    fn tooltips_for_signs_and_the_branch() {
+        let mut app = app();
+        *app.editor.buffer_mut() = Buffer::new("a.rs", "let x = 1;\nlet y = 2;\n".to_string(), true);
+        let diagnostic = crate::diagnostics::Diagnostic { range: 15..16, severity: crate::notifications::Severity::Warning, message: "unused y".to_string(), source: None };
+        app.editor.diagnostics.set(std::path::Path::new("a.rs"), vec![diagnostic]);
+        app.editor.branch = Some(("main".to_string(), Some((2, 1))));
+        let (advance, line_height) = (app.advance(), app.line_height());
+        let start = Instant::now() + Duration::from_secs(10);
+        app.handle_input(start);
+        assert!(app.gutter_width() > 0.);
+
+        app.cursor_moved(advance / 2., line_height * 1.5, start);
+        assert!(app.tooltip(start).is_none());
+        assert_eq!(app.tooltips.wake_at(start), Some(start + tooltip::DELAY));
+        let (pieces, _) = app.tooltip(start + tooltip::DELAY).unwrap();
+        assert_eq!(pieces[0].text, "unused y");
+
+        let line = app.status_line.render(&app.editor.status_context(start)).line((app.window_size.0 / advance) as usize);
+        let column = line[..line.find("⎇ main").unwrap()].chars().count();
+        app.cursor_moved((column as f32 + 1.) * advance, app.viewport.height + line_height / 2., start);
+        let (pieces, rect) = app.tooltip(start + tooltip::DELAY).unwrap();
+        assert_eq!(pieces[0].text, "On branch main, 2 ahead and 1 behind its upstream");
+        assert!(rect.y + rect.h <= app.viewport.height);
+    }
+
+    #[test]
+    fn hand_over_links() {
+        let mut app = app();
+        *app.editor.buffer_mut() = Buffer::new("*scratch*", "first\nsee https://example.com here\n".to_string(), false);
+        let (advance, line_height) = (app.advance(), app.line_height());
+        let start = Instant::now();
+        app.handle_input(start);
+        app.cursor_moved(advance * 8.5, line_height * 1.5, start);
+        assert_eq!(app.cursor_icon(), CursorIcon::Hand);
+        assert_eq!(app.link_at(app.cursor_pos.0, app.cursor_pos.1).as_deref(), Some("https://example.com"));
+        app.cursor_moved(advance * 1.5, line_height * 1.5, start);
+        assert_eq!(app.cursor_icon(), CursorIcon::Default);
+        app.cursor_moved(advance * 8.5, line_height * 0.5, start);
+        assert_eq!(app.cursor_icon(), CursorIcon::Default);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keys_reach_the_terminal() {
+        let mut app = app();
+        app.menu_commands.push(r#"terminal sh -c 'read line; printf "got %s" "$line"; sleep 5'"#.to_string());
+        let start = Instant::now();
+        app.handle_input(start);
+        let grid = &app.editor.terminal.as_ref().unwrap().grid;
+        assert_eq!((grid.cols, grid.rows), ((800. / app.advance()) as usize, (app.viewport.height / app.line_height()) as usize));
+
+        for (key, scancode, c) in [(VirtualKeyCode::H, 0x23, 'h'), (VirtualKeyCode::I, 0x17, 'i')] {
+            app.key_input(key, scancode, ElementState::Pressed, start);
+            app.received_character(c);
+        }
+        app.key_input(VirtualKeyCode::Return, 0x1c, ElementState::Pressed, start);
+        app.received_character('\r');
+        assert_eq!(app.editor.buffer().text, "");
+        let shows = |app: &App| (0..3).any(|row| app.editor.terminal.as_ref().unwrap().grid.row_text(row) == "got hi");
+        while !shows(&app) && start.elapsed() < Duration::from_secs(5) {
+            app.update(Instant::now());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(shows(&app));
+    }
+}
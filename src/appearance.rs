@@ -0,0 +1,65 @@
+// :theme and :font. Each sets what it's given, or picks from the built in themes or the installed
+// fonts, drawing with the selected one while moving through them and going back on Escape
+
+use std::path::PathBuf;
+
+use crate::commands::Registry;
+use crate::font;
+use crate::highlighter::{Theme, THEMES};
+use crate::picker::Picker;
+
+// What :theme and :font need from the editor
+pub trait AppearanceHost {
+    fn theme(&self) -> Theme;
+    fn set_theme(&mut self, theme: Theme);
+    // The primary font's file
+    fn font(&self) -> PathBuf;
+    // Draws with the font at `path`, only until the picker closes when previewing
+    fn set_font(&mut self, path: PathBuf, preview: bool);
+    fn pick_theme(&mut self, picker: Picker<Theme>);
+    fn pick_font(&mut self, picker: Picker<PathBuf>);
+}
+
+// Starts at the item that's in use, so nothing changes before the selection moves
+fn starting_at<T: Clone + PartialEq>(items: Vec<(String, T)>, current: T) -> Picker<T> {
+    let at = items.iter().position(|(_, item)| *item == current);
+    let mut picker = Picker::new(items, current);
+    picker.selected = at.unwrap_or(0);
+    picker
+}
+
+// theme [name]
+fn theme_command<Ctx: AppearanceHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    match args {
+        [] => {
+            let items = THEMES.iter().map(|theme| (theme.name.to_string(), *theme)).collect();
+            let picker = starting_at(items, ctx.theme());
+            ctx.pick_theme(picker);
+        }
+        [name] => ctx.set_theme(Theme::named(name).ok_or_else(|| format!("No theme {name}"))?),
+        _ => return Err("Usage: theme [name]".to_string()),
+    }
+    Ok(())
+}
+
+// font [path], the rest of the line being the path
+fn font_command<Ctx: AppearanceHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    match args {
+        [] => {
+            let items: Vec<(String, PathBuf)> = font::installed_fonts().into_iter().map(|path| (path.file_name().unwrap_or_default().to_string_lossy().into_owned(), path)).collect();
+            if items.is_empty() {
+                return Err("No fonts installed".to_string());
+            }
+            let picker = starting_at(items, ctx.font());
+            ctx.pick_font(picker);
+        }
+        [path] => ctx.set_font(PathBuf::from(path), false),
+        _ => return Err("Usage: font [path]".to_string()),
+    }
+    Ok(())
+}
+
+pub fn register<Ctx: AppearanceHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("theme", theme_command::<Ctx>);
+    registry.add_raw_builtin("font", font_command::<Ctx>);
+}
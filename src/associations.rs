@@ -0,0 +1,111 @@
+// Registering rakoune with the OS as something text files can be opened with, and receiving the
+// files the OS opens with it. `rakoune --register` adds an "Open in rakoune" verb to every file in
+// the Windows shell, and a desktop entry for text files on Linux. On macOS that's the document
+// types in the bundle's Info.plist (resources/macos/Info.plist), and files arrive as an Apple event
+// rather than as arguments
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Text types to be offered for in desktop entries
+const MIME_TYPES: &[&str] = &["text/plain", "text/markdown", "text/x-rust", "text/x-python", "text/x-csrc", "text/x-c++src", "application/json", "application/toml", "application/x-shellscript"];
+
+// Registry keys under HKEY_CURRENT_USER and their default values: the shell verb on every file, and
+// the application entry the "Open with" list uses
+pub fn windows_registry_entries(exe: &Path) -> Vec<(String, String)> {
+    let command = format!("\"{}\" \"%1\"", exe.display());
+    vec![
+        (r"Software\Classes\*\shell\rakoune".to_string(), "Open in rakoune".to_string()),
+        (r"Software\Classes\*\shell\rakoune\command".to_string(), command.clone()),
+        (r"Software\Classes\Applications\rakoune.exe\shell\open\command".to_string(), command),
+    ]
+}
+
+pub fn desktop_entry(exe: &Path) -> String {
+    let mime_types: String = MIME_TYPES.iter().map(|mime_type| format!("{mime_type};")).collect();
+    format!("[Desktop Entry]\nType=Application\nName=rakoune\nComment=Edit text\nExec=\"{}\" %F\nTerminal=false\nCategories=Utility;TextEditor;\nStartupWMClass=rakoune\nMimeType={mime_types}\n", exe.display())
+}
+
+// Where the desktop entry goes, in the user's applications
+fn desktop_entry_path() -> Option<PathBuf> {
+    let data_dir = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(data_dir.join("applications").join("rakoune.desktop"))
+}
+
+// Sets up this executable as a handler for text files, for the current user. Returns what was done
+pub fn register() -> io::Result<String> {
+    let exe = std::env::current_exe()?;
+    if cfg!(windows) {
+        for (key, value) in windows_registry_entries(&exe) {
+            let status = std::process::Command::new("reg").args(["add", &format!(r"HKCU\{key}"), "/ve", "/d", &value, "/f"]).status()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("reg add {key} failed")));
+            }
+        }
+        Ok("Added \"Open in rakoune\" to the context menu of files".to_string())
+    } else if cfg!(target_os = "macos") {
+        Ok("On macOS, file types come from the Info.plist of the app bundle".to_string())
+    } else {
+        let path = desktop_entry_path().ok_or_else(|| io::Error::other("No home directory"))?;
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(&path, desktop_entry(&exe))?;
+        Ok(format!("Wrote {}", path.display()))
+    }
+}
+
+// Files opened from Finder, the Dock or `open -a rakoune` come as application:openFiles: to the app
+// delegate instead of as arguments. winit doesn't pass it on, so the method is added to winit's
+// delegate class. Has to be called after the event loop is created, which is when that class exists
+#[cfg(target_os = "macos")]
+pub fn on_open_files(opened: impl FnMut(PathBuf) + Send + 'static) {
+    use std::ffi::CStr;
+    use std::os::raw::c_char;
+    use std::sync::{Mutex, OnceLock};
+
+    use objc::runtime::{class_addMethod, Class, Imp, Object, Sel};
+    use objc::{msg_send, sel, sel_impl};
+
+    type Opened = Mutex<Box<dyn FnMut(PathBuf) + Send>>;
+    static OPENED: OnceLock<Opened> = OnceLock::new();
+
+    extern "C" fn open_files(_this: &Object, _sel: Sel, _sender: *mut Object, files: *mut Object) {
+        let Some(opened) = OPENED.get() else { return };
+        let mut opened = opened.lock().unwrap();
+        unsafe {
+            let count: usize = msg_send![files, count];
+            for idx in 0..count {
+                let file: *mut Object = msg_send![files, objectAtIndex: idx];
+                let utf8: *const c_char = msg_send![file, UTF8String];
+                opened(PathBuf::from(CStr::from_ptr(utf8).to_string_lossy().into_owned()));
+            }
+        }
+    }
+
+    if OPENED.set(Mutex::new(Box::new(opened))).is_err() {
+        return;
+    }
+    let Some(class) = Class::get("WinitApplicationDelegate") else { return };
+    unsafe {
+        let imp: Imp = std::mem::transmute(open_files as extern "C" fn(&Object, Sel, *mut Object, *mut Object));
+        class_addMethod(class as *const Class as *mut Class, sel!(application:openFiles:), imp, c"v@:@@".as_ptr());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handler_entries() {
+        let entries = windows_registry_entries(Path::new(r"C:\Programs\rakoune.exe"));
+        assert_eq!(entries[0], (r"Software\Classes\*\shell\rakoune".to_string(), "Open in rakoune".to_string()));
+        assert_eq!(entries[1].1, r#""C:\Programs\rakoune.exe" "%1""#);
+
+        let entry = desktop_entry(Path::new("/usr/bin/rakoune"));
+        assert!(entry.contains("Exec=\"/usr/bin/rakoune\" %F\n"));
+        assert!(entry.contains("MimeType=text/plain;text/markdown;"));
+    }
+}
@@ -0,0 +1,475 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::font::{self, Face};
+use crate::images::{self, Image};
+
+pub const DEFAULT_PAGE_SIZE: u32 = 1024;
+
+// Largest page size picked automatically. Bigger pages mostly waste memory on mostly empty textures
+const MAX_AUTO_PAGE_SIZE: u32 = 2048;
+const MIN_PAGE_SIZE: u32 = 256;
+// Texture memory the atlas may use when the page count isn't configured
+const AUTO_MEMORY_BUDGET: u64 = 64 * 1024 * 1024;
+
+// The [atlas] section of the config, taking precedence over what is picked from the device limits:
+//
+//   [atlas]
+//   page_size = 2048
+//   max_pages = 8
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AtlasOverrides {
+    pub page_size: Option<u32>,
+    pub max_pages: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasConfig {
+    pub page_size: u32,
+    pub max_pages: usize,
+}
+
+impl Default for AtlasConfig {
+    fn default() -> Self {
+        AtlasConfig { page_size: DEFAULT_PAGE_SIZE, max_pages: 16 }
+    }
+}
+
+impl AtlasConfig {
+    pub fn from_limits(limits: &wgpu::Limits, overrides: AtlasOverrides) -> AtlasConfig {
+        let max_dimension = limits.max_texture_dimension_2d;
+        let page_size = overrides
+            .page_size
+            .unwrap_or(MAX_AUTO_PAGE_SIZE)
+            .min(max_dimension)
+            .max(MIN_PAGE_SIZE.min(max_dimension));
+        // Any page may end up a color one, so the budget assumes they all are
+        let page_bytes = page_size as u64 * page_size as u64 * PageFormat::Color.bytes_per_pixel() as u64;
+        // Pages of one format are layers of one texture array
+        let max_pages = overrides
+            .max_pages
+            .unwrap_or((AUTO_MEMORY_BUDGET / page_bytes).max(1) as usize)
+            .min(limits.max_texture_array_layers as usize)
+            .max(1);
+        AtlasConfig { page_size, max_pages }
+    }
+
+    // Most texture memory the pages can take
+    pub fn max_bytes(&self) -> u64 {
+        self.page_size as u64 * self.page_size as u64 * PageFormat::Color.bytes_per_pixel() as u64 * self.max_pages as u64
+    }
+
+    // For :messages, to see what was picked on a given machine
+    pub fn summary(&self) -> String {
+        format!("Glyph atlas: {} pages of {}x{}, at most {:.1}MB", self.max_pages, self.page_size, self.page_size, self.max_bytes() as f64 / 1024. / 1024.)
+    }
+}
+
+// Empty space around each glyph, so linear sampling doesn't bleed in neighbouring glyphs
+const PADDING: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GlyphKey {
+    // Index of the face in the FontStack
+    pub face: usize,
+    pub glyph: u16,
+    // Size in 1/64ths of a pixel, so nearby sizes can share a rasterization
+    pub size_64ths: u32,
+}
+
+impl GlyphKey {
+    pub fn new(face: usize, glyph: u16, size_px: f32) -> GlyphKey {
+        GlyphKey { face, glyph, size_64ths: (size_px * 64.).round() as u32 }
+    }
+
+    pub fn size_px(&self) -> f32 {
+        self.size_64ths as f32 / 64.
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageFormat {
+    // One byte of coverage per pixel. The color comes from the vertex
+    Coverage,
+    // Full color, for color emoji
+    Color,
+}
+
+impl PageFormat {
+    pub fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            PageFormat::Coverage => wgpu::TextureFormat::R8Unorm,
+            PageFormat::Color => wgpu::TextureFormat::Rgba8UnormSrgb,
+        }
+    }
+
+    pub fn bytes_per_pixel(self) -> u32 {
+        match self {
+            PageFormat::Coverage => 1,
+            PageFormat::Color => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasEntry {
+    pub page: usize,
+    // Layer of the page in the texture array for its format
+    pub layer: u32,
+    // Pixel rectangle in the page
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+    // Offset from the pen position on the baseline to the top left corner of the bitmap, y pointing down
+    pub bearing: (f32, f32),
+    // Color glyphs are drawn as they are, coverage ones in the color of the text
+    pub format: PageFormat,
+}
+
+// The glyph as a color bitmap at `size_px`, from the PNGs color emoji fonts have (sbix, CBDT), with
+// its bearing. None for glyphs drawn from outlines
+pub fn color_bitmap(face: &Face, glyph: u16, size_px: f32) -> Option<(Image, (f32, f32))> {
+    let raster = face.ttf_face.glyph_raster_image(ttf_parser::GlyphId(glyph), size_px.ceil() as u16)?;
+    if raster.format != ttf_parser::RasterImageFormat::PNG || raster.pixels_per_em == 0 {
+        return None;
+    }
+    let image = images::decode_png(raster.data).ok()?;
+    // Strikes only come in a few sizes, so the closest one is scaled to the size asked for
+    let scale = size_px / raster.pixels_per_em as f32;
+    let (w, h) = (((image.width as f32 * scale).round() as u32).max(1), ((image.height as f32 * scale).round() as u32).max(1));
+    // x and y are where the bottom left corner is, y pointing up
+    let bearing = (raster.x as f32 * scale, -(raster.y as f32 * scale + h as f32));
+    Some((images::resize(&image, w, h), bearing))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    // Where the next rectangle on this shelf goes
+    x: u32,
+}
+
+// Packs rectangles into rows ("shelves") of similar height. Glyphs at one size are mostly the same
+// height, so this wastes little space and is fast
+#[derive(Debug)]
+pub struct ShelfPacker {
+    width: u32,
+    height: u32,
+    shelves: Vec<Shelf>,
+}
+
+impl ShelfPacker {
+    pub fn new(width: u32, height: u32) -> ShelfPacker {
+        ShelfPacker { width, height, shelves: Vec::new() }
+    }
+
+    // Top left corner for a w×h rectangle, or None if the page is full
+    pub fn allocate(&mut self, w: u32, h: u32) -> Option<(u32, u32)> {
+        if w > self.width || h > self.height {
+            return None;
+        }
+        // The shelf wasting the least height that still has room
+        let best = self.shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= h && shelf.height <= h + h / 2 + 2 && self.width - shelf.x >= w)
+            .min_by_key(|shelf| shelf.height - h);
+        if let Some(shelf) = best {
+            let at = (shelf.x, shelf.y);
+            shelf.x += w;
+            return Some(at);
+        }
+        let y = self.shelves.last().map_or(0, |shelf| shelf.y + shelf.height);
+        if self.height - y < h {
+            return None;
+        }
+        self.shelves.push(Shelf { y, height: h, x: w });
+        Some((0, y))
+    }
+}
+
+pub struct Page {
+    pub format: PageFormat,
+    // Layer in the texture array for its format
+    pub layer: u32,
+    packer: ShelfPacker,
+}
+
+// The pages of one format, as layers of one texture array. The whole array is bound at once, so
+// glyphs from every page can be drawn in a single draw call
+pub struct PageArray {
+    pub texture: wgpu::Texture,
+    // Views the texture as a 2D array, even while it has a single layer
+    pub view: wgpu::TextureView,
+}
+
+// Rasterized glyphs, stored in texture pages. Glyph outlines go in single channel coverage pages,
+// which use a quarter of the memory and upload bandwidth of RGBA pages. RGBA pages are only
+// created for color glyphs
+pub struct GlyphAtlas {
+    pub config: AtlasConfig,
+    pub pages: Vec<Page>,
+    coverage: Option<PageArray>,
+    color: Option<PageArray>,
+    // With when each glyph was last looked up
+    entries: HashMap<GlyphKey, (Option<AtlasEntry>, Instant)>,
+    now: Instant,
+    // Goes up with every clear. Instances kept from an earlier generation point at glyphs that are
+    // gone, so CulledDocument lays them out again when it changes
+    pub generation: u64,
+    // Faces fontdue couldn't read, by index in the FontStack, and the error for the one found last
+    // until it's taken to be reported. Each face is only reported once
+    broken_faces: HashSet<usize>,
+    font_error: Option<font::Error>,
+}
+
+impl GlyphAtlas {
+    pub fn new(config: AtlasConfig) -> GlyphAtlas {
+        GlyphAtlas { config, pages: Vec::new(), coverage: None, color: None, entries: HashMap::new(), now: Instant::now(), generation: 0, broken_faces: HashSet::new(), font_error: None }
+    }
+
+    // Why glyphs of a face were left out since the last call, if any were
+    pub fn take_font_error(&mut self) -> Option<font::Error> {
+        self.font_error.take()
+    }
+
+    // Glyphs looked up from now on count as used at `now`
+    pub fn begin_frame(&mut self, now: Instant) {
+        self.now = now;
+    }
+
+    // None until the first page of the format is created
+    pub fn array(&self, format: PageFormat) -> Option<&PageArray> {
+        match format {
+            PageFormat::Coverage => self.coverage.as_ref(),
+            PageFormat::Color => self.color.as_ref(),
+        }
+    }
+
+    fn new_page(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, format: PageFormat) -> usize {
+        let layer = self.pages.iter().filter(|page| page.format == format).count() as u32;
+        let page_size = self.config.page_size;
+        let array = match format {
+            PageFormat::Coverage => &mut self.coverage,
+            PageFormat::Color => &mut self.color,
+        };
+        let capacity = array.as_ref().map_or(0, |array| array.texture.depth_or_array_layers());
+        if layer >= capacity {
+            // Textures can't grow, so make a bigger one and copy the old layers over. Doubling
+            // keeps the number of copies down
+            let layers = (layer + 1).next_power_of_two().min(self.config.max_pages as u32).max(layer + 1);
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(match format {
+                    PageFormat::Coverage => "glyph atlas coverage pages",
+                    PageFormat::Color => "glyph atlas color pages",
+                }),
+                size: wgpu::Extent3d { width: page_size, height: page_size, depth_or_array_layers: layers },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: format.texture_format(),
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            if let Some(old) = array.as_ref() {
+                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("glyph atlas grow") });
+                encoder.copy_texture_to_texture(
+                    old.texture.as_image_copy(),
+                    texture.as_image_copy(),
+                    wgpu::Extent3d { width: page_size, height: page_size, depth_or_array_layers: capacity },
+                );
+                queue.submit([encoder.finish()]);
+            }
+            let view = texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2Array),
+                ..Default::default()
+            });
+            *array = Some(PageArray { texture, view });
+        }
+        self.pages.push(Page { format, layer, packer: ShelfPacker::new(page_size, page_size) });
+        self.pages.len() - 1
+    }
+
+    // Reserves a `w` by `h` rectangle in the first page of `format` with room, making a new page if
+    // there is none. Returns the page and the top left corner
+    pub fn allocate(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, format: PageFormat, w: u32, h: u32) -> Option<(usize, u32, u32)> {
+        let (pw, ph) = (w + 2 * PADDING, h + 2 * PADDING);
+        let existing = self.pages
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, page)| page.format == format)
+            .find_map(|(idx, page)| page.packer.allocate(pw, ph).map(|at| (idx, at)));
+        let (page, (x, y)) = match existing {
+            Some(found) => found,
+            None if self.pages.len() < self.config.max_pages => {
+                let idx = self.new_page(device, queue, format);
+                (idx, self.pages[idx].packer.allocate(pw, ph)?)
+            }
+            None => return None,
+        };
+        Some((page, x + PADDING, y + PADDING))
+    }
+
+    // Stores a bitmap with `format.bytes_per_pixel()` bytes per pixel, in the first page of that format with room
+    pub fn insert_bitmap(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, format: PageFormat, w: u32, h: u32, data: &[u8]) -> Option<(usize, u32, u32)> {
+        let (page, x, y) = self.allocate(device, queue, format, w, h)?;
+
+        if w > 0 && h > 0 {
+            let array = self.array(format).expect("pages have an array");
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &array.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x, y, z: self.pages[page].layer },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(w * format.bytes_per_pixel()),
+                    rows_per_image: Some(h),
+                },
+                wgpu::Extent3d { width: w, height: h, depth_or_array_layers: 1 },
+            );
+        }
+        Some((page, x, y))
+    }
+
+    // The entry for a glyph rasterized before. Some(None) if it didn't fit
+    pub fn cached(&mut self, key: &GlyphKey) -> Option<Option<AtlasEntry>> {
+        let (entry, used) = self.entries.get_mut(key)?;
+        *used = self.now;
+        Some(*entry)
+    }
+
+    // Records where a glyph rasterized some other way than get_or_insert ended up
+    pub fn remember(&mut self, key: GlyphKey, entry: Option<AtlasEntry>) {
+        self.entries.insert(key, (entry, self.now));
+    }
+
+    // Rasterizes the glyph the first time it's asked for, into a color page if the font has a color
+    // bitmap for it. None for glyphs that don't fit in a page, or if all pages are full
+    pub fn get_or_insert(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, face: &Face, key: GlyphKey) -> Option<AtlasEntry> {
+        if let Some(entry) = self.cached(&key) {
+            return entry;
+        }
+        let (format, w, h, bitmap, bearing) = match color_bitmap(face, key.glyph, key.size_px()) {
+            Some((image, bearing)) => (PageFormat::Color, image.width, image.height, image.rgba, bearing),
+            None => {
+                let font = match face.fontdue_font() {
+                    Ok(font) => font,
+                    Err(e) => {
+                        if self.broken_faces.insert(key.face) {
+                            self.font_error = Some(e);
+                        }
+                        self.remember(key, None);
+                        return None;
+                    }
+                };
+                let (metrics, coverage) = font.rasterize_indexed(key.glyph, key.size_px());
+                let (w, h) = (metrics.width as u32, metrics.height as u32);
+                (PageFormat::Coverage, w, h, coverage, (metrics.xmin as f32, -(metrics.ymin as f32 + h as f32)))
+            }
+        };
+        let entry = self
+            .insert_bitmap(device, queue, format, w, h, &bitmap)
+            .map(|(page, x, y)| AtlasEntry { page, x, y, w, h, layer: self.pages[page].layer, bearing, format });
+        self.remember(key, entry);
+        entry
+    }
+
+    // Drops every page and forgets every glyph, freeing the textures. Glyphs are rasterized again as
+    // they're drawn, so instances kept from before (CulledDocument) have to be invalidated too
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.coverage = None;
+        self.color = None;
+        self.entries.clear();
+        self.generation += 1;
+    }
+
+    // Glyphs not looked up for `idle`
+    pub fn idle_entries(&self, now: Instant, idle: Duration) -> usize {
+        self.entries.values().filter(|(_, used)| now.saturating_duration_since(*used) >= idle).count()
+    }
+
+    // Shelves can't give back the room of a single glyph, so idle glyphs are only dropped by clearing
+    // the whole atlas, once they're at least a quarter of it. Returns how many were idle
+    pub fn evict_idle(&mut self, now: Instant, idle: Duration) -> usize {
+        let count = self.idle_entries(now, idle);
+        if count == 0 || count * 4 < self.entries.len() {
+            return 0;
+        }
+        self.clear();
+        count
+    }
+
+    // Bytes of texture memory used by all pages
+    pub fn memory_usage(&self) -> u64 {
+        let page_bytes = self.config.page_size as u64 * self.config.page_size as u64;
+        [PageFormat::Coverage, PageFormat::Color]
+            .into_iter()
+            .filter_map(|format| self.array(format).map(|array| array.texture.depth_or_array_layers() as u64 * page_bytes * format.bytes_per_pixel() as u64))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_from_limits() {
+        let config = AtlasConfig::from_limits(&wgpu::Limits::default(), AtlasOverrides::default());
+        assert_eq!(config, AtlasConfig { page_size: 2048, max_pages: 4 });
+        assert_eq!(config.max_bytes(), 64 * 1024 * 1024);
+        assert_eq!(config.summary(), "Glyph atlas: 4 pages of 2048x2048, at most 64.0MB");
+
+        let weak = wgpu::Limits { max_texture_dimension_2d: 512, ..wgpu::Limits::downlevel_webgl2_defaults() };
+        assert_eq!(AtlasConfig::from_limits(&weak, AtlasOverrides::default()), AtlasConfig { page_size: 512, max_pages: 64 });
+
+        let overridden = AtlasConfig::from_limits(&wgpu::Limits::default(), AtlasOverrides { page_size: Some(100_000), max_pages: Some(2) });
+        assert_eq!(overridden, AtlasConfig { page_size: 8192, max_pages: 2 });
+        let too_many = AtlasConfig::from_limits(&wgpu::Limits::default(), AtlasOverrides { page_size: None, max_pages: Some(10_000) });
+        assert_eq!(too_many.max_pages, wgpu::Limits::default().max_texture_array_layers as usize);
+    }
+
+    #[test]
+    fn shelf_packing() {
+        let mut packer = ShelfPacker::new(64, 32);
+        assert_eq!(packer.allocate(30, 10), Some((0, 0)));
+        assert_eq!(packer.allocate(30, 9), Some((30, 0)));
+        // Doesn't fit on the first shelf anymore
+        assert_eq!(packer.allocate(10, 10), Some((0, 10)));
+        // Too small for the existing shelves to be worth it
+        assert_eq!(packer.allocate(10, 4), Some((0, 20)));
+        assert_eq!(packer.allocate(10, 10), Some((10, 10)));
+        assert_eq!(packer.allocate(10, 20), None);
+        assert_eq!(packer.allocate(100, 1), None);
+    }
+
+    #[test]
+    fn evicts_idle_glyphs() {
+        let start = Instant::now();
+        let mut atlas = GlyphAtlas::new(AtlasConfig::default());
+        atlas.begin_frame(start);
+        for glyph in 0..8 {
+            atlas.remember(GlyphKey::new(0, glyph, 14.), None);
+        }
+        let later = start + Duration::from_secs(600);
+        atlas.begin_frame(later);
+        for glyph in 0..7 {
+            atlas.cached(&GlyphKey::new(0, glyph, 14.));
+        }
+        // One idle glyph isn't worth rasterizing the seven others again
+        assert_eq!(atlas.evict_idle(later, Duration::from_secs(300)), 0);
+        assert_eq!(atlas.generation, 0);
+        assert_eq!(atlas.evict_idle(later + Duration::from_secs(300), Duration::from_secs(300)), 8);
+        assert_eq!(atlas.generation, 1);
+        assert_eq!(atlas.cached(&GlyphKey::new(0, 0, 14.)), None);
+    }
+}
@@ -0,0 +1,178 @@
+// Saving modified buffers without :w, when the window loses focus and/or after not typing for a
+// while. Off unless configured:
+//
+//   [auto_save]
+//   focus_lost = true
+//   idle_seconds = 30
+//
+// Saving goes through format::before_save like :w does. Buffers without a file are left alone
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::format::{before_save, FormatHost};
+use crate::notifications::MessagesHost;
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoSaveConfig {
+    pub focus_lost: bool,
+    pub idle_seconds: Option<f32>,
+}
+
+impl AutoSaveConfig {
+    pub fn idle(&self) -> Option<Duration> {
+        self.idle_seconds.filter(|seconds| *seconds > 0.).map(Duration::from_secs_f32)
+    }
+}
+
+// What auto-saving needs from the editor
+pub trait AutoSaveHost: FormatHost + MessagesHost {
+    // Every buffer with unsaved changes, with its file if it has one
+    fn modified_buffers(&self) -> Vec<Option<PathBuf>>;
+    // Writes the buffer to its file, as :w does after before_save
+    fn write_buffer(&mut self, path: &Path) -> Result<(), String>;
+}
+
+// Saves every modified buffer that has a file, showing what failed. Returns how many were saved
+pub fn save_modified(host: &mut impl AutoSaveHost) -> usize {
+    let mut saved = 0;
+    for path in host.modified_buffers().into_iter().flatten() {
+        let result = before_save(host, &path).and_then(|_| host.write_buffer(&path)).map_err(|e| format!("Couldn't auto-save {}: {e}", path.display()));
+        saved += host.notifications().report(result).is_some() as usize;
+    }
+    saved
+}
+
+#[derive(Debug, Default)]
+pub struct AutoSave {
+    pub config: AutoSaveConfig,
+    // Since the last save, so idling after saving doesn't save again
+    last_edit: Option<Instant>,
+}
+
+impl AutoSave {
+    pub fn new(config: AutoSaveConfig) -> AutoSave {
+        AutoSave { config, last_edit: None }
+    }
+
+    pub fn edited(&mut self, now: Instant) {
+        self.last_edit = Some(now);
+    }
+
+    // winit's Focused(false)
+    pub fn focus_lost(&mut self, host: &mut impl AutoSaveHost) -> usize {
+        if !self.config.focus_lost {
+            return 0;
+        }
+        self.last_edit = None;
+        save_modified(host)
+    }
+
+    // When to wake up to save after idling, if there's anything to save
+    pub fn wake_at(&self) -> Option<Instant> {
+        Some(self.last_edit? + self.config.idle()?)
+    }
+
+    pub fn run_if_idle(&mut self, now: Instant, host: &mut impl AutoSaveHost) -> usize {
+        if self.wake_at().is_none_or(|at| now < at) {
+            return 0;
+        }
+        self.last_edit = None;
+        save_modified(host)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::Notifications;
+    use std::ops::Range;
+
+    #[derive(Default)]
+    struct Editor {
+        // Path (or none) and whether it's modified
+        buffers: Vec<(Option<PathBuf>, bool)>,
+        formatted: Vec<PathBuf>,
+        notifications: Notifications,
+    }
+
+    impl crate::workspace_edit::EditHost for Editor {
+        fn buffer_text(&mut self, _path: &Path) -> Result<String, String> {
+            Ok(String::new())
+        }
+
+        fn set_buffer_text(&mut self, _path: &Path, _text: String, _edits: &[(Range<usize>, usize)]) {}
+    }
+
+    impl FormatHost for Editor {
+        fn current_path(&self) -> Option<PathBuf> {
+            None
+        }
+
+        fn formatter(&self, _path: &Path) -> Option<(String, PathBuf)> {
+            None
+        }
+
+        fn format_on_save(&self, path: &Path) -> bool {
+            path.extension().is_some_and(|extension| extension == "rs")
+        }
+
+        fn request_formatting(&mut self, path: &Path) -> Result<(), String> {
+            self.formatted.push(path.to_owned());
+            Ok(())
+        }
+    }
+
+    impl MessagesHost for Editor {
+        fn notifications(&mut self) -> &mut Notifications {
+            &mut self.notifications
+        }
+
+        fn show_report(&mut self, _title: &str, _text: String) {}
+    }
+
+    impl AutoSaveHost for Editor {
+        fn modified_buffers(&self) -> Vec<Option<PathBuf>> {
+            self.buffers.iter().filter(|(_, modified)| *modified).map(|(path, _)| path.clone()).collect()
+        }
+
+        fn write_buffer(&mut self, path: &Path) -> Result<(), String> {
+            if path.starts_with("/readonly") {
+                return Err("Permission denied".to_string());
+            }
+            self.buffers.iter_mut().filter(|(buffer_path, _)| buffer_path.as_deref() == Some(path)).for_each(|(_, modified)| *modified = false);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn saves_modified_files_only() {
+        let buffers = vec![(Some(PathBuf::from("main.rs")), true), (None, true), (Some(PathBuf::from("notes.txt")), false), (Some(PathBuf::from("/readonly/a.txt")), true)];
+        let mut editor = Editor { buffers, ..Default::default() };
+        let config: AutoSaveConfig = toml::from_str("focus_lost = true\nidle_seconds = 30").unwrap();
+        let mut auto_save = AutoSave::new(config);
+
+        assert_eq!(auto_save.focus_lost(&mut editor), 1);
+        assert_eq!(editor.formatted, vec![PathBuf::from("main.rs")]);
+        assert_eq!(editor.notifications.log[0].text, "Couldn't auto-save /readonly/a.txt: Permission denied");
+        // The scratch buffer is still modified
+        assert_eq!(editor.modified_buffers(), vec![None, Some(PathBuf::from("/readonly/a.txt"))]);
+
+        let start = Instant::now();
+        editor.buffers[0].1 = true;
+        assert_eq!(auto_save.wake_at(), None);
+        auto_save.edited(start);
+        assert_eq!(auto_save.run_if_idle(start + Duration::from_secs(29), &mut editor), 0);
+        assert_eq!(auto_save.run_if_idle(start + Duration::from_secs(30), &mut editor), 1);
+        assert_eq!(auto_save.wake_at(), None);
+
+        auto_save.config = AutoSaveConfig::default();
+        editor.buffers[0].1 = true;
+        auto_save.edited(start);
+        assert_eq!(auto_save.focus_lost(&mut editor), 0);
+        assert_eq!(auto_save.run_if_idle(start + Duration::from_secs(60), &mut editor), 0);
+    }
+}
@@ -0,0 +1,529 @@
+// Where buffers are read from and written to. Names with a registered scheme, like
+// "mem:scratch" or "tar:docs.tar!guide/intro.md", go to the backend for it, and everything else is
+// a path on the local disk
+//
+// Backends work with bytes and the whole file at once, as buffers are loaded and saved whole
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use thiserror::Error;
+
+// Separates the archive from the member in tar: names
+const ARCHIVE_SEPARATOR: char = '!';
+const TAR_BLOCK: usize = 512;
+const EMPTY_DIR_MARKER: &str = ".keep";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't access {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("{0} doesn't exist")]
+    NotFound(String),
+    #[error("{0} is read-only")]
+    ReadOnly(String),
+    #[error("{0} isn't UTF-8")]
+    NotUtf8(String),
+    #[error("{0} isn't a tar archive: {1}")]
+    BadArchive(String, String),
+    #[error("{0} failed: {1}")]
+    Command(String, String),
+    #[error("Can't move {0} to {1}, which is somewhere else")]
+    AcrossBackends(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Entry {
+    pub name: String,
+    pub is_dir: bool,
+}
+
+// `path` is the name with the scheme taken off
+pub trait BufferBackend {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error>;
+    // At most the first `max` bytes, for previews. Backends that can only read everything cut it short
+    fn read_head(&self, path: &str, max: usize) -> Result<Vec<u8>, Error> {
+        let mut bytes = self.read(path)?;
+        bytes.truncate(max);
+        Ok(bytes)
+    }
+    fn write(&mut self, path: &str, bytes: &[u8]) -> Result<(), Error>;
+    // Buffers from read-only backends refuse edits rather than failing when saved
+    fn is_read_only(&self, _path: &str) -> bool {
+        false
+    }
+    // What's directly in the directory `path`, sorted
+    fn list(&self, path: &str) -> Result<Vec<Entry>, Error>;
+    fn is_dir(&self, path: &str) -> bool;
+    // For the directory browser. Backends that can't change their files refuse
+    fn create_dir(&mut self, path: &str) -> Result<(), Error> {
+        Err(Error::ReadOnly(path.to_string()))
+    }
+    fn rename(&mut self, from: &str, _to: &str) -> Result<(), Error> {
+        Err(Error::ReadOnly(from.to_string()))
+    }
+    // Directories are removed with everything in them
+    fn remove(&mut self, path: &str) -> Result<(), Error> {
+        Err(Error::ReadOnly(path.to_string()))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LocalDisk;
+
+impl BufferBackend for LocalDisk {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        std::fs::read(path).map_err(|e| io_error(path, e))
+    }
+
+    fn read_head(&self, path: &str, max: usize) -> Result<Vec<u8>, Error> {
+        let file = std::fs::File::open(path).map_err(|e| io_error(path, e))?;
+        let mut bytes = Vec::new();
+        file.take(max as u64).read_to_end(&mut bytes).map_err(|e| io_error(path, e))?;
+        Ok(bytes)
+    }
+
+    fn write(&mut self, path: &str, bytes: &[u8]) -> Result<(), Error> {
+        std::fs::write(path, bytes).map_err(|e| io_error(path, e))
+    }
+
+    fn is_read_only(&self, path: &str) -> bool {
+        std::fs::metadata(path).is_ok_and(|metadata| metadata.permissions().readonly())
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<Entry>, Error> {
+        let entries = std::fs::read_dir(path).map_err(|e| io_error(path, e))?;
+        let mut listed: Vec<Entry> = entries.flatten().map(|entry| Entry { name: entry.file_name().to_string_lossy().into_owned(), is_dir: entry.path().is_dir() }).collect();
+        listed.sort();
+        Ok(listed)
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        Path::new(path).is_dir()
+    }
+
+    fn create_dir(&mut self, path: &str) -> Result<(), Error> {
+        std::fs::create_dir_all(path).map_err(|e| io_error(path, e))
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        std::fs::rename(from, to).map_err(|e| io_error(from, e))
+    }
+
+    fn remove(&mut self, path: &str) -> Result<(), Error> {
+        match Path::new(path).is_dir() {
+            true => std::fs::remove_dir_all(path),
+            false => std::fs::remove_file(path),
+        }
+        .map_err(|e| io_error(path, e))
+    }
+}
+
+fn io_error(path: &str, err: std::io::Error) -> Error {
+    match err.kind() {
+        std::io::ErrorKind::NotFound => Error::NotFound(path.to_string()),
+        _ => Error::Io(path.to_string(), err),
+    }
+}
+
+// Scratch buffers and tests. Directories are implied by the files in them
+#[derive(Debug, Default)]
+pub struct Memory {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl BufferBackend for Memory {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        self.files.get(path).cloned().ok_or_else(|| Error::NotFound(path.to_string()))
+    }
+
+    fn write(&mut self, path: &str, bytes: &[u8]) -> Result<(), Error> {
+        self.files.insert(path.to_string(), bytes.to_vec());
+        Ok(())
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<Entry>, Error> {
+        Ok(children(self.files.keys().map(String::as_str), path))
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        let dir = format!("{}/", path.trim_end_matches('/'));
+        path.is_empty() || self.files.keys().any(|name| name.starts_with(&dir))
+    }
+
+    // Directories only exist while something is in them, so an empty one holds an empty marker
+    // file that listings leave out
+    fn create_dir(&mut self, path: &str) -> Result<(), Error> {
+        self.files.insert(format!("{}/{EMPTY_DIR_MARKER}", path.trim_end_matches('/')), Vec::new());
+        Ok(())
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        let from_dir = format!("{from}/");
+        let moved: Vec<String> = self.files.keys().filter(|name| *name == from || name.starts_with(&from_dir)).cloned().collect();
+        if moved.is_empty() {
+            return Err(Error::NotFound(from.to_string()));
+        }
+        for name in moved {
+            let contents = self.files.remove(&name).unwrap();
+            self.files.insert(format!("{to}{}", &name[from.len()..]), contents);
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &str) -> Result<(), Error> {
+        let dir = format!("{path}/");
+        let before = self.files.len();
+        self.files.retain(|name, _| name != path && !name.starts_with(&dir));
+        match self.files.len() < before {
+            true => Ok(()),
+            false => Err(Error::NotFound(path.to_string())),
+        }
+    }
+}
+
+// The entries directly in `dir`, out of every file's full path
+fn children<'a>(paths: impl Iterator<Item = &'a str>, dir: &str) -> Vec<Entry> {
+    let dir = dir.trim_matches('/');
+    let mut listed: Vec<Entry> = paths
+        .filter_map(|path| match dir.is_empty() {
+            true => Some(path),
+            false => path.strip_prefix(dir)?.strip_prefix('/'),
+        })
+        .map(|rest| match rest.split_once('/') {
+            Some((name, _)) => Entry { name: name.to_string(), is_dir: true },
+            None => Entry { name: rest.to_string(), is_dir: false },
+        })
+        .filter(|entry| !entry.name.is_empty() && entry.name != EMPTY_DIR_MARKER)
+        .collect();
+    listed.sort();
+    listed.dedup();
+    listed
+}
+
+// Members of tar archives on disk, named "archive.tar!member/path". Read-only, since rewriting the
+// archive behind someone's back isn't what they'd expect from saving one file
+#[derive(Debug, Default)]
+pub struct TarArchive;
+
+impl TarArchive {
+    // Every file in the archive, with its contents
+    pub fn members(archive: &Path) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        let name = archive.display().to_string();
+        let bytes = std::fs::read(archive).map_err(|e| io_error(&name, e))?;
+        let bad = |reason: &str| Error::BadArchive(name.clone(), reason.to_string());
+        let mut members = Vec::new();
+        let mut at = 0;
+        while let Some(header) = bytes.get(at..at + TAR_BLOCK) {
+            if header.iter().all(|&b| b == 0) {
+                break;
+            }
+            let field = |range: std::ops::Range<usize>| {
+                let raw = &header[range];
+                String::from_utf8_lossy(&raw[..raw.iter().position(|&b| b == 0).unwrap_or(raw.len())]).into_owned()
+            };
+            let size = usize::from_str_radix(field(124..136).trim(), 8).map_err(|_| bad("bad member size"))?;
+            let mut path = field(0..100);
+            // ustar splits long names in two
+            if header[257..262] == *b"ustar" && header[345] != 0 {
+                path = format!("{}/{path}", field(345..500));
+            }
+            let data = bytes.get(at + TAR_BLOCK..at + TAR_BLOCK + size).ok_or_else(|| bad("truncated"))?;
+            // Regular files only. Directories, links and extended headers don't hold buffers
+            if matches!(header[156], 0 | b'0') {
+                members.push((path.trim_start_matches("./").to_string(), data.to_vec()));
+            }
+            at += TAR_BLOCK + size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+        }
+        Ok(members)
+    }
+}
+
+fn split_archive(path: &str) -> (&str, &str) {
+    path.split_once(ARCHIVE_SEPARATOR).unwrap_or((path, ""))
+}
+
+impl BufferBackend for TarArchive {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let (archive, member) = split_archive(path);
+        let members = TarArchive::members(Path::new(archive))?;
+        members.into_iter().find(|(name, _)| name == member).map(|(_, data)| data).ok_or_else(|| Error::NotFound(path.to_string()))
+    }
+
+    fn write(&mut self, path: &str, _bytes: &[u8]) -> Result<(), Error> {
+        Err(Error::ReadOnly(path.to_string()))
+    }
+
+    fn is_read_only(&self, _path: &str) -> bool {
+        true
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<Entry>, Error> {
+        let (archive, dir) = split_archive(path);
+        let members = TarArchive::members(Path::new(archive))?;
+        Ok(children(members.iter().map(|(name, _)| name.as_str()), dir))
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        let (archive, dir) = split_archive(path);
+        let dir = format!("{}/", dir.trim_end_matches('/'));
+        let members = TarArchive::members(Path::new(archive)).unwrap_or_default();
+        dir == "/" || members.iter().any(|(name, _)| name.starts_with(&dir))
+    }
+}
+
+// Runs shell commands to read, write and list, for remote files ("ssh host cat {}") or git objects
+// ("git show {}"). `{}` is replaced by the quoted path, and writes get the contents on stdin.
+// Listings are one entry per line, with directories ending in '/' like `ls -p` does
+#[derive(Debug, Clone)]
+pub struct ShellCommands {
+    pub read: String,
+    // Read-only without one
+    pub write: Option<String>,
+    pub list: Option<String>,
+    // The commands hand the path to a shell on another machine, like ssh does, so it's quoted
+    // for that one too
+    pub remote: bool,
+}
+
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+fn run(template: &str, path: &str, remote: bool, input: Option<&[u8]>) -> Result<Vec<u8>, Error> {
+    let quoted = if remote { shell_quote(&shell_quote(path)) } else { shell_quote(path) };
+    let command = template.replace("{}", &quoted);
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Io(command.clone(), e))?;
+    // Written from another thread, so a command writing before it has read everything can't fill
+    // its stdout pipe and wait on us forever
+    let writer = input.map(|input| {
+        let mut stdin = child.stdin.take().unwrap();
+        let input = input.to_vec();
+        std::thread::spawn(move || stdin.write_all(&input))
+    });
+    let output = child.wait_with_output().map_err(|e| Error::Io(command.clone(), e))?;
+    // A command exiting without reading everything shows up in its exit status
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(Error::Command(command, if stderr.is_empty() { output.status.to_string() } else { stderr }));
+    }
+    Ok(output.stdout)
+}
+
+impl BufferBackend for ShellCommands {
+    fn read(&self, path: &str) -> Result<Vec<u8>, Error> {
+        run(&self.read, path, self.remote, None)
+    }
+
+    fn write(&mut self, path: &str, bytes: &[u8]) -> Result<(), Error> {
+        let write = self.write.as_ref().ok_or_else(|| Error::ReadOnly(path.to_string()))?;
+        run(write, path, self.remote, Some(bytes)).map(drop)
+    }
+
+    fn is_read_only(&self, _path: &str) -> bool {
+        self.write.is_none()
+    }
+
+    fn list(&self, path: &str) -> Result<Vec<Entry>, Error> {
+        let list = self.list.as_ref().ok_or_else(|| Error::NotFound(path.to_string()))?;
+        let output = run(list, path, self.remote, None)?;
+        let mut listed: Vec<Entry> = String::from_utf8_lossy(&output)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| match line.strip_suffix('/') {
+                Some(dir) => Entry { name: dir.to_string(), is_dir: true },
+                None => Entry { name: line.to_string(), is_dir: false },
+            })
+            .collect();
+        listed.sort();
+        Ok(listed)
+    }
+
+    // Only ever asked about entries of listings, so directories are what list again
+    fn is_dir(&self, path: &str) -> bool {
+        self.list(path).is_ok_and(|listed| !listed.is_empty())
+    }
+}
+
+// Picks the backend for each buffer name
+pub struct Backends {
+    local: LocalDisk,
+    schemes: BTreeMap<String, Box<dyn BufferBackend>>,
+}
+
+impl Default for Backends {
+    fn default() -> Backends {
+        let mut backends = Backends { local: LocalDisk, schemes: BTreeMap::new() };
+        backends.add("mem", Memory::default());
+        backends.add("tar", TarArchive);
+        backends
+    }
+}
+
+impl Backends {
+    // Names starting with "<scheme>:" go to `backend` from now on
+    pub fn add(&mut self, scheme: &str, backend: impl BufferBackend + 'static) {
+        self.schemes.insert(scheme.to_string(), Box::new(backend));
+    }
+
+    // The scheme of `name`, if it has a registered one, and the rest of it. Unknown schemes are
+    // left as part of a local path, so "C:\x" and "notes:old.txt" still work
+    fn split<'a>(&self, name: &'a str) -> (Option<&'a str>, &'a str) {
+        match name.split_once(':') {
+            Some((scheme, rest)) if self.schemes.contains_key(scheme) => (Some(scheme), rest),
+            _ => (None, name),
+        }
+    }
+
+    fn backend(&self, name: &str) -> (&dyn BufferBackend, String) {
+        match self.split(name) {
+            (Some(scheme), rest) => (self.schemes[scheme].as_ref(), rest.to_string()),
+            (None, path) => (&self.local, path.to_string()),
+        }
+    }
+
+    fn backend_mut(&mut self, name: &str) -> (&mut dyn BufferBackend, String) {
+        match self.split(name) {
+            (Some(scheme), rest) => (self.schemes.get_mut(scheme).unwrap().as_mut(), rest.to_string()),
+            (None, path) => (&mut self.local, path.to_string()),
+        }
+    }
+
+    // Whether `name` is on the local disk, so it can be watched and handed to other programs
+    pub fn local_path(&self, name: &str) -> Option<PathBuf> {
+        match self.split(name) {
+            (None, path) => Some(PathBuf::from(path)),
+            _ => None,
+        }
+    }
+
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, Error> {
+        let (backend, path) = self.backend(name);
+        backend.read(&path)
+    }
+
+    pub fn read_head(&self, name: &str, max: usize) -> Result<Vec<u8>, Error> {
+        let (backend, path) = self.backend(name);
+        backend.read_head(&path, max)
+    }
+
+    pub fn read_text(&self, name: &str) -> Result<String, Error> {
+        String::from_utf8(self.read(name)?).map_err(|_| Error::NotUtf8(name.to_string()))
+    }
+
+    pub fn write(&mut self, name: &str, bytes: &[u8]) -> Result<(), Error> {
+        let (backend, path) = self.backend_mut(name);
+        backend.write(&path, bytes)
+    }
+
+    pub fn is_read_only(&self, name: &str) -> bool {
+        let (backend, path) = self.backend(name);
+        backend.is_read_only(&path)
+    }
+
+    pub fn list(&self, name: &str) -> Result<Vec<Entry>, Error> {
+        let (backend, path) = self.backend(name);
+        backend.list(&path)
+    }
+
+    pub fn is_dir(&self, name: &str) -> bool {
+        let (backend, path) = self.backend(name);
+        backend.is_dir(&path)
+    }
+
+    pub fn create_dir(&mut self, name: &str) -> Result<(), Error> {
+        let (backend, path) = self.backend_mut(name);
+        backend.create_dir(&path)
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), Error> {
+        if self.split(from).0 != self.split(to).0 {
+            return Err(Error::AcrossBackends(from.to_string(), to.to_string()));
+        }
+        let (_, to_path) = self.split(to);
+        let to_path = to_path.to_string();
+        let (backend, from_path) = self.backend_mut(from);
+        backend.rename(&from_path, &to_path)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), Error> {
+        let (backend, path) = self.backend_mut(name);
+        backend.remove(&path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, is_dir: bool) -> Entry {
+        Entry { name: name.to_string(), is_dir }
+    }
+
+    #[test]
+    fn routes_by_scheme() {
+        let dir = std::env::temp_dir().join(format!("rakoune-backend-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let local = dir.join("notes:old.txt").display().to_string();
+        let mut backends = Backends::default();
+        backends.write(&local, b"on disk").unwrap();
+        backends.write("mem:scratch/a.txt", b"in memory").unwrap();
+        assert_eq!(backends.read_text(&local).unwrap(), "on disk");
+        assert_eq!(backends.read_head(&local, 2).unwrap(), b"on");
+        assert_eq!(backends.read_head("mem:scratch/a.txt", 2).unwrap(), b"in");
+        assert_eq!(backends.read_text("mem:scratch/a.txt").unwrap(), "in memory");
+        assert_eq!(backends.local_path(&local), Some(PathBuf::from(&local)));
+        assert_eq!(backends.local_path("mem:scratch/a.txt"), None);
+        assert_eq!(backends.list("mem:").unwrap(), vec![entry("scratch", true)]);
+        assert!(matches!(backends.read("mem:missing"), Err(Error::NotFound(_))));
+
+        // Git objects through shell commands, read-only
+        backends.add("show", ShellCommands { read: "printf '%s' {}".to_string(), write: None, list: Some("printf 'src/\\nREADME.md\\n'".to_string()), remote: false });
+        assert_eq!(backends.read_text("show:it's here").unwrap(), "it's here");
+        assert!(backends.is_read_only("show:x"));
+        assert!(matches!(backends.write("show:x", b""), Err(Error::ReadOnly(_))));
+        assert_eq!(backends.list("show:").unwrap(), vec![entry("README.md", false), entry("src", true)]);
+        // eval parses the line again, like the shell ssh runs the command in
+        backends.add("remote", ShellCommands { read: "eval printf %s {}".to_string(), write: Some("cat".to_string()), list: None, remote: true });
+        assert_eq!(backends.read_text("remote:it's here").unwrap(), "it's here");
+        // More than fits in a pipe, echoed back before it's all written
+        backends.write("remote:x", &vec![b'x'; 1 << 20]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_tar_members() {
+        let dir = std::env::temp_dir().join(format!("rakoune-tar-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("docs/guide")).unwrap();
+        std::fs::write(dir.join("docs/guide/intro.md"), "# Intro\n").unwrap();
+        std::fs::write(dir.join("docs/empty.txt"), "").unwrap();
+        // Over the 100 bytes names get, so it's split into a prefix
+        let long_name = format!("docs/{}/{}.txt", "d".repeat(60), "f".repeat(50));
+        std::fs::create_dir_all(dir.join(&long_name).parent().unwrap()).unwrap();
+        std::fs::write(dir.join(&long_name), "long").unwrap();
+        let status = Command::new("tar").args(["--format=ustar", "-cf", "docs.tar", "docs"]).current_dir(&dir).status().unwrap();
+        assert!(status.success());
+
+        let backends = Backends::default();
+        let archive = format!("tar:{}!", dir.join("docs.tar").display());
+        assert_eq!(backends.read_text(&format!("{archive}docs/guide/intro.md")).unwrap(), "# Intro\n");
+        assert_eq!(backends.read_text(&format!("{archive}{long_name}")).unwrap(), "long");
+        assert!(backends.is_read_only(&format!("{archive}docs/empty.txt")));
+        let listed = backends.list(&format!("{archive}docs")).unwrap();
+        assert_eq!(listed, vec![entry(&"d".repeat(60), true), entry("empty.txt", false), entry("guide", true)]);
+        assert!(matches!(backends.read(&format!("{archive}nope")), Err(Error::NotFound(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
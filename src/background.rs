@@ -0,0 +1,119 @@
+// An image behind the text, drawn with the image renderer before the text pass. Faint by default,
+// fainter on dark themes where the same opacity stands out more. Themes can have their own image,
+// opacity and fit:
+//
+//   [background]
+//   image = "~/Pictures/paper.png"
+//   fit = "tile"
+//
+//   [background.themes.solarized-light]
+//   image = "~/Pictures/rakoune.png"
+//   fit = "watermark"
+//   opacity = 0.15
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::color::luminance;
+use crate::layout::Rect;
+
+const DARK_OPACITY: f32 = 0.06;
+const LIGHT_OPACITY: f32 = 0.1;
+// A watermark's width as a fraction of the window's, and its distance from the corner
+const WATERMARK_FRACTION: f32 = 0.2;
+const WATERMARK_MARGIN: f32 = 24.;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    // Fills the window, cropping the image
+    #[default]
+    Cover,
+    // Repeated at its own size from the top left
+    Tile,
+    // Small, in the bottom right corner
+    Watermark,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackgroundSettings {
+    pub image: Option<PathBuf>,
+    pub opacity: Option<f32>,
+    pub fit: Option<Fit>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackgroundConfig {
+    #[serde(flatten)]
+    pub base: BackgroundSettings,
+    // By theme name, overriding `base` field by field
+    pub themes: HashMap<String, BackgroundSettings>,
+}
+
+// What to draw for the current theme
+#[derive(Debug, Clone, PartialEq)]
+pub struct Background {
+    pub image: PathBuf,
+    pub opacity: f32,
+    pub fit: Fit,
+}
+
+impl BackgroundConfig {
+    // None when neither the theme nor the base has an image
+    pub fn for_theme(&self, theme: &str, theme_background: [f32; 4]) -> Option<Background> {
+        let over = self.themes.get(theme).cloned().unwrap_or_default();
+        let default_opacity = if luminance(theme_background) < 0.2 { DARK_OPACITY } else { LIGHT_OPACITY };
+        Some(Background {
+            image: over.image.or_else(|| self.base.image.clone())?,
+            opacity: over.opacity.or(self.base.opacity).unwrap_or(default_opacity).clamp(0., 1.),
+            fit: over.fit.or(self.base.fit).unwrap_or_default(),
+        })
+    }
+}
+
+// Where copies of an `image_size` image go to fit a `window_size` window
+pub fn rects(fit: Fit, window_size: (f32, f32), image_size: (u32, u32)) -> Vec<Rect> {
+    let (w, h) = (image_size.0 as f32, image_size.1 as f32);
+    if w <= 0. || h <= 0. {
+        return Vec::new();
+    }
+    match fit {
+        Fit::Cover => {
+            let scale = (window_size.0 / w).max(window_size.1 / h);
+            let (w, h) = (w * scale, h * scale);
+            vec![Rect { x: (window_size.0 - w) / 2., y: (window_size.1 - h) / 2., w, h }]
+        }
+        Fit::Tile => {
+            let (cols, rows) = ((window_size.0 / w).ceil() as usize, (window_size.1 / h).ceil() as usize);
+            (0..rows).flat_map(|row| (0..cols).map(move |col| Rect { x: col as f32 * w, y: row as f32 * h, w, h })).collect()
+        }
+        Fit::Watermark => {
+            let scale = (window_size.0 * WATERMARK_FRACTION / w).min(1.);
+            let (w, h) = (w * scale, h * scale);
+            vec![Rect { x: window_size.0 - w - WATERMARK_MARGIN, y: window_size.1 - h - WATERMARK_MARGIN, w, h }]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn per_theme_and_fits() {
+        let config: BackgroundConfig = toml::from_str("image = \"/paper.png\"\nfit = \"tile\"\n[themes.light]\nimage = \"/logo.png\"\nfit = \"watermark\"\n[themes.dim]\nopacity = 0.3\n").unwrap();
+        let (dark, white) = ([0.1, 0.1, 0.1, 1.], [1., 1., 1., 1.]);
+        assert_eq!(config.for_theme("gruvbox", dark), Some(Background { image: PathBuf::from("/paper.png"), opacity: DARK_OPACITY, fit: Fit::Tile }));
+        assert_eq!(config.for_theme("light", white), Some(Background { image: PathBuf::from("/logo.png"), opacity: LIGHT_OPACITY, fit: Fit::Watermark }));
+        assert_eq!(config.for_theme("dim", dark).unwrap().opacity, 0.3);
+        assert_eq!(BackgroundConfig::default().for_theme("gruvbox", dark), None);
+
+        assert_eq!(rects(Fit::Cover, (800., 600.), (400, 400)), vec![Rect { x: 0., y: -100., w: 800., h: 800. }]);
+        assert_eq!(rects(Fit::Tile, (800., 600.), (300, 400)).len(), 6);
+        assert_eq!(rects(Fit::Watermark, (1000., 600.), (400, 200)), vec![Rect { x: 776., y: 476., w: 200., h: 100. }]);
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::commands::Registry;
+use crate::layout::VirtualText;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlameLine {
+    pub commit: String,
+    pub author: String,
+    // Seconds since the unix epoch
+    pub author_time: u64,
+    pub summary: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlameScope {
+    CurrentLine,
+    Viewport,
+}
+
+// Parses the output of `git blame --porcelain` into one entry per line of the file
+pub fn parse_porcelain(output: &str) -> Vec<BlameLine> {
+    let mut commits: HashMap<String, BlameLine> = HashMap::new();
+    let mut lines = Vec::new();
+    let mut current: Option<BlameLine> = None;
+
+    for line in output.lines() {
+        if line.starts_with('\t') {
+            if let Some(entry) = current.take() {
+                commits.insert(entry.commit.clone(), entry.clone());
+                lines.push(entry);
+            }
+        } else if let Some(current) = current.as_mut() {
+            if let Some(author) = line.strip_prefix("author ") {
+                current.author = author.to_string();
+            } else if let Some(time) = line.strip_prefix("author-time ") {
+                current.author_time = time.parse().unwrap_or(0);
+            } else if let Some(summary) = line.strip_prefix("summary ") {
+                current.summary = summary.to_string();
+            }
+        } else if let Some(commit) = line.split(' ').next() {
+            // Header line: <commit> <original line> <final line> [<group size>]
+            // Details about a commit are only given the first time it shows up
+            current = Some(commits.get(commit).cloned().unwrap_or(BlameLine {
+                commit: commit.to_string(),
+                author: String::new(),
+                author_time: 0,
+                summary: String::new(),
+            }));
+        }
+    }
+    lines
+}
+
+fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    let (n, unit) = match secs {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        86400..=2591999 => (secs / 86400, "day"),
+        2592000..=31535999 => (secs / 2592000, "month"),
+        _ => (secs / 31536000, "year"),
+    };
+    format!("{n} {unit}{} ago", if n == 1 { "" } else { "s" })
+}
+
+impl BlameLine {
+    pub fn annotation(&self, now: SystemTime) -> String {
+        // All zeros is how git marks lines that aren't committed yet
+        if self.commit.chars().all(|c| c == '0') {
+            return "Not committed yet".to_string();
+        }
+        let age = now
+            .duration_since(UNIX_EPOCH + Duration::from_secs(self.author_time))
+            .unwrap_or_default();
+        format!("{}, {} • {}", self.author, format_age(age), self.summary)
+    }
+}
+
+// Blame for one file, computed on a background thread
+pub struct Blame {
+    pub path: PathBuf,
+    pub enabled: bool,
+    pub scope: BlameScope,
+    lines: Option<Vec<BlameLine>>,
+    pending: Option<mpsc::Receiver<Result<Vec<BlameLine>, String>>>,
+}
+
+impl Blame {
+    pub fn new(path: &Path) -> Blame {
+        Blame {
+            path: path.to_owned(),
+            enabled: false,
+            scope: BlameScope::CurrentLine,
+            lines: None,
+            pending: None,
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        if self.enabled && self.lines.is_none() && self.pending.is_none() {
+            self.refresh();
+        }
+    }
+
+    // Starts recomputing the blame, for example after the file was saved
+    pub fn refresh(&mut self) {
+        let (tx, rx) = mpsc::channel();
+        let path = self.path.clone();
+        std::thread::spawn(move || {
+            // Run git next to the file, so it finds the right repository
+            let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            let result = std::process::Command::new("git")
+                .current_dir(dir)
+                .arg("blame")
+                .arg("--porcelain")
+                .arg("--")
+                .arg(path.file_name().unwrap_or(path.as_os_str()))
+                .output()
+                .map_err(|e| e.to_string())
+                .and_then(|out| if out.status.success() {
+                    Ok(parse_porcelain(&String::from_utf8_lossy(&out.stdout)))
+                } else {
+                    Err(String::from_utf8_lossy(&out.stderr).trim().to_string())
+                });
+            let _ = tx.send(result);
+        });
+        self.pending = Some(rx);
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // Picks up the result of a finished blame. Returns true if there is something new to draw
+    pub fn poll(&mut self) -> Result<bool, String> {
+        let Some(rx) = &self.pending else { return Ok(false) };
+        match rx.try_recv() {
+            Ok(result) => {
+                self.pending = None;
+                self.lines = Some(result?);
+                Ok(true)
+            }
+            Err(mpsc::TryRecvError::Empty) => Ok(false),
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.pending = None;
+                Err("blame thread died".to_string())
+            }
+        }
+    }
+
+    // Annotations at the end of the lines to show. `line_ends` are the byte offsets of the end of each line in the text
+    pub fn virtual_text(&self, line_ends: &[usize], cursor_line: usize, viewport: Range<usize>) -> Vec<VirtualText> {
+        let Some(lines) = self.lines.as_ref().filter(|_| self.enabled) else { return Vec::new() };
+        let shown = match self.scope {
+            BlameScope::CurrentLine => cursor_line..cursor_line + 1,
+            BlameScope::Viewport => viewport,
+        };
+        let now = SystemTime::now();
+        shown
+            .filter_map(|line| Some((*line_ends.get(line)?, lines.get(line)?)))
+            .map(|(at, blame)| VirtualText { at, text: format!("    {}", blame.annotation(now)) })
+            .collect()
+    }
+}
+
+// What :blame needs from the editor
+pub trait BlameHost {
+    fn blame(&mut self) -> &mut Option<Blame>;
+    // The absolute path of the current buffer's file
+    fn blamed_path(&self) -> Option<PathBuf>;
+}
+
+// Toggles the annotations for the current file, or turns them on for just the cursor's line or
+// every line in view with `line` or `viewport`
+fn blame_command<Ctx: BlameHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let scope = match args {
+        [] => None,
+        ["line"] => Some(BlameScope::CurrentLine),
+        ["viewport"] => Some(BlameScope::Viewport),
+        _ => return Err("Usage: blame [line|viewport]".to_string()),
+    };
+    let path = ctx.blamed_path().ok_or("Only files can be blamed")?;
+    let blame = ctx.blame();
+    // Blame is kept for one file at a time
+    if blame.as_ref().is_none_or(|blame| blame.path != path) {
+        *blame = Some(Blame::new(&path));
+    }
+    let blame = blame.as_mut().expect("just set");
+    match scope {
+        Some(scope) => {
+            blame.scope = scope;
+            if !blame.enabled {
+                blame.toggle();
+            }
+        }
+        None => blame.toggle(),
+    }
+    Ok(())
+}
+
+pub fn register<Ctx: BlameHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("blame", blame_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_porcelain() {
+        let output = "\
+aaaa 1 1 2
+author Xenia
+author-time 1700000000
+summary Add things
+filename a.txt
+\tfirst
+aaaa 2 2
+\tsecond
+0000 3 3 1
+author Not Committed Yet
+author-time 1700000100
+summary Version of a.txt from a.txt
+filename a.txt
+\tthird
+";
+        let lines = parse_porcelain(output);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].author, "Xenia");
+        assert_eq!(lines[1].summary, "Add things");
+        let now = UNIX_EPOCH + Duration::from_secs(1700000000 + 3 * 86400);
+        assert_eq!(lines[0].annotation(now), "Xenia, 3 days ago • Add things");
+        assert_eq!(lines[2].annotation(now), "Not committed yet");
+
+        let mut blame = Blame::new(Path::new("a.txt"));
+        blame.lines = Some(lines);
+        assert!(blame.virtual_text(&[5, 12, 18], 1, 0..3).is_empty());
+        blame.enabled = true;
+        let ends = |shown: Vec<VirtualText>| shown.iter().map(|vt| vt.at).collect::<Vec<_>>();
+        assert_eq!(ends(blame.virtual_text(&[5, 12, 18], 1, 0..3)), vec![12]);
+        blame.scope = BlameScope::Viewport;
+        assert_eq!(ends(blame.virtual_text(&[5, 12, 18], 1, 1..3)), vec![12, 18]);
+    }
+}
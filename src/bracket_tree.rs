@@ -0,0 +1,510 @@
+// Trees of the brackets, strings and comments of buffers, kept up to date as the buffer is edited,
+// and the queries built on them: highlighting, expanding the selection to the enclosing node,
+// folding by node and text objects. This is not a real parser, only what can be told from the
+// brackets, so the nodes are bracketed groups rather than syntax nodes. Languages with a
+// tree-sitter parser get real syntax trees from syntax.rs instead, and this is for the rest.
+//
+// Grammars are loaded at runtime from TOML files describing a language's brackets, strings and
+// comments, and optionally its tree-sitter parser:
+//
+//   name = "rust"
+//   extensions = ["rs"]
+//   brackets = ["()", "[]", "{}"]
+//   strings = ["\""]
+//   line_comment = "//"
+//   block_comment = ["/*", "*/"]
+//   language_server = "rust-analyzer"
+//
+// That is enough for the structure every query here needs. The tree is only reparsed around
+// edits: top level nodes the edits didn't touch are kept as they are
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::selection::line_starts;
+use crate::syntax::SyntaxLanguage;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("{0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("{0}: brackets must be two characters, not {1:?}")]
+    BadBracket(PathBuf, String),
+    #[error("{0}: no tree-sitter parser: {1}")]
+    TreeSitter(PathBuf, String),
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Grammar {
+    pub name: String,
+    pub extensions: Vec<String>,
+    // Pairs of opening and closing characters, like "()"
+    pub brackets: Vec<String>,
+    // Characters that start and end strings. A backslash escapes the next character in them
+    pub strings: Vec<char>,
+    pub line_comment: Option<String>,
+    pub block_comment: Option<(String, String)>,
+    pub tree_sitter: Option<TreeSitterConfig>,
+    // Command starting the language server, like "rust-analyzer", run by the shell in the project
+    // root. See lsp.rs
+    pub language_server: Option<String>,
+    // Loaded from tree_sitter
+    #[serde(skip)]
+    pub syntax: Option<Arc<SyntaxLanguage>>,
+}
+
+// Where the tree-sitter parser of a language is, see syntax.rs. Paths are relative to the grammar
+// file
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TreeSitterConfig {
+    // A shared library exporting tree_sitter_<name>. Unset uses the parser built into rakoune
+    pub library: Option<PathBuf>,
+    // The query capturing nodes by the name of their theme face. Unset uses the built in parser's
+    pub highlights: Option<PathBuf>,
+}
+
+impl Grammar {
+    pub fn parse(path: &Path, source: &str) -> Result<Grammar, Error> {
+        let mut grammar: Grammar = toml::from_str(source).map_err(|e| Error::Parse(path.to_owned(), e))?;
+        if let Some(bad) = grammar.brackets.iter().find(|pair| pair.chars().count() != 2) {
+            return Err(Error::BadBracket(path.to_owned(), bad.clone()));
+        }
+        if let Some(config) = &grammar.tree_sitter {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let highlights = match &config.highlights {
+                Some(highlights) => Some(std::fs::read_to_string(dir.join(highlights)).map_err(|e| Error::Read(dir.join(highlights), e))?),
+                None => None,
+            };
+            let syntax = match (&config.library, highlights) {
+                (Some(library), Some(highlights)) => SyntaxLanguage::load(&grammar.name, &dir.join(library), &highlights),
+                (Some(_), None) => Err("a library needs highlights too".to_string()),
+                (None, highlights) => SyntaxLanguage::builtin(&grammar.name, highlights.as_deref()).unwrap_or_else(|| Err(format!("none is built in for {}", grammar.name))),
+            };
+            grammar.syntax = Some(Arc::new(syntax.map_err(|e| Error::TreeSitter(path.to_owned(), e))?));
+        }
+        Ok(grammar)
+    }
+
+    // Rust, with the parser rakoune has built in
+    pub fn rust() -> Grammar {
+        let source = "name = \"rust\"\nextensions = [\"rs\"]\nbrackets = [\"()\", \"[]\", \"{}\"]\nstrings = [\"\\\"\"]\nline_comment = \"//\"\nblock_comment = [\"/*\", \"*/\"]\n[tree_sitter]\n";
+        Grammar::parse(Path::new("rust.toml"), source).expect("the built in grammar parses")
+    }
+
+    fn closing(&self, open: char) -> Option<char> {
+        self.brackets.iter().find_map(|pair| {
+            let mut chars = pair.chars();
+            (chars.next() == Some(open)).then(|| chars.next()).flatten()
+        })
+    }
+
+    fn is_closing(&self, c: char) -> bool {
+        self.brackets.iter().any(|pair| pair.chars().nth(1) == Some(c))
+    }
+}
+
+// Grammars by name and file extension
+#[derive(Debug, Default, Clone)]
+pub struct Languages {
+    grammars: Vec<Arc<Grammar>>,
+}
+
+impl Languages {
+    // The grammars rakoune comes with
+    pub fn builtin() -> Languages {
+        let mut languages = Languages::default();
+        languages.add(Grammar::rust());
+        languages
+    }
+
+    // Loads every .toml file in `dir` over the built in grammars. A missing directory has only those
+    pub fn load_dir(dir: &Path) -> Result<Languages, Error> {
+        let mut languages = Languages::builtin();
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(languages),
+            Err(e) => return Err(Error::Read(dir.to_owned(), e)),
+        };
+        for entry in entries {
+            let path = entry.map_err(|e| Error::Read(dir.to_owned(), e))?.path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                let source = std::fs::read_to_string(&path).map_err(|e| Error::Read(path.clone(), e))?;
+                languages.add(Grammar::parse(&path, &source)?);
+            }
+        }
+        Ok(languages)
+    }
+
+    // Replaces any grammar with the same name
+    pub fn add(&mut self, grammar: Grammar) {
+        self.grammars.retain(|existing| existing.name != grammar.name);
+        self.grammars.push(Arc::new(grammar));
+    }
+
+    pub fn for_path(&self, path: &Path) -> Option<Arc<Grammar>> {
+        let ext = path.extension()?.to_str()?;
+        self.grammars.iter().find(|grammar| grammar.extensions.iter().any(|e| e == ext)).cloned()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Source,
+    // Text between a pair of brackets, including them, by opening bracket
+    Bracketed(char),
+    String,
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub range: Range<usize>,
+    pub children: Vec<Node>,
+}
+
+impl Node {
+    fn shift(&mut self, delta: isize) {
+        self.range = (self.range.start as isize + delta) as usize..(self.range.end as isize + delta) as usize;
+        self.children.iter_mut().for_each(|child| child.shift(delta));
+    }
+
+    // Innermost nodes containing `range`, outermost first
+    fn path_to(&self, range: &Range<usize>) -> Vec<&Node> {
+        let mut path = vec![self];
+        while let Some(child) = path.last().unwrap().children.iter().find(|child| child.range.start <= range.start && range.end <= child.range.end) {
+            path.push(child);
+        }
+        path
+    }
+
+    fn walk<'a>(&'a self, out: &mut Vec<&'a Node>) {
+        out.push(self);
+        self.children.iter().for_each(|child| child.walk(out));
+    }
+}
+
+// Parses `text` as a sequence of top level nodes starting at byte `offset`. None if something is
+// still open at the end, when `must_close` asks for everything to be closed
+fn parse_nodes(grammar: &Grammar, text: &str, offset: usize, must_close: bool) -> Option<Vec<Node>> {
+    // Open nodes, innermost last, with their closing bracket
+    let mut stack: Vec<(Node, Option<char>)> = vec![(Node { kind: NodeKind::Source, range: offset..offset + text.len(), children: Vec::new() }, None)];
+    let mut chars = text.char_indices().peekable();
+    while let Some((at, c)) = chars.next() {
+        let pos = offset + at;
+        let rest = &text[at..];
+        let leaf = if grammar.line_comment.as_ref().is_some_and(|start| rest.starts_with(start.as_str())) {
+            let end = rest.find('\n').map_or(text.len(), |nl| at + nl);
+            Some((NodeKind::Comment, end))
+        } else if let Some((start, end)) = grammar.block_comment.as_ref().filter(|(start, _)| rest.starts_with(start.as_str())) {
+            let close = rest[start.len()..].find(end.as_str()).map(|found| at + start.len() + found + end.len());
+            if close.is_none() && must_close {
+                return None;
+            }
+            Some((NodeKind::Comment, close.unwrap_or(text.len())))
+        } else if grammar.strings.contains(&c) {
+            let mut escaped = false;
+            let close = rest.char_indices().skip(1).find(|&(_, s)| {
+                let found = !escaped && s == c;
+                escaped = !escaped && s == '\\';
+                found
+            });
+            if close.is_none() && must_close {
+                return None;
+            }
+            Some((NodeKind::String, close.map_or(text.len(), |(end, s)| at + end + s.len_utf8())))
+        } else {
+            None
+        };
+        if let Some((kind, end)) = leaf {
+            stack.last_mut().unwrap().0.children.push(Node { kind, range: pos..offset + end, children: Vec::new() });
+            while chars.peek().is_some_and(|&(next, _)| next < end) {
+                chars.next();
+            }
+        } else if let Some(close) = grammar.closing(c) {
+            stack.push((Node { kind: NodeKind::Bracketed(c), range: pos..pos, children: Vec::new() }, Some(close)));
+        } else if grammar.is_closing(c) && stack.len() > 1 && stack.last().unwrap().1 == Some(c) {
+            let (mut node, _) = stack.pop().unwrap();
+            node.range.end = pos + c.len_utf8();
+            stack.last_mut().unwrap().0.children.push(node);
+        }
+        // Closing brackets that don't match are left as text
+    }
+    if must_close && stack.len() > 1 {
+        return None;
+    }
+    // Whatever is still open runs to the end of the text
+    while stack.len() > 1 {
+        let (mut node, _) = stack.pop().unwrap();
+        node.range.end = offset + text.len();
+        stack.last_mut().unwrap().0.children.push(node);
+    }
+    Some(stack.pop().unwrap().0.children)
+}
+
+// The bracket tree of one buffer
+#[derive(Debug, Clone)]
+pub struct BracketTree {
+    pub grammar: Arc<Grammar>,
+    pub root: Node,
+    // Part of the current text changed since the last parse, and how much longer the text got
+    dirty: Option<(Range<usize>, isize)>,
+}
+
+impl BracketTree {
+    pub fn new(grammar: Arc<Grammar>, text: &str) -> BracketTree {
+        let children = parse_nodes(&grammar, text, 0, false).unwrap_or_default();
+        BracketTree { grammar, root: Node { kind: NodeKind::Source, range: 0..text.len(), children }, dirty: None }
+    }
+
+    // Must be called for every edit to the buffer: `replaced` was replaced with `new_len` bytes
+    pub fn edit(&mut self, replaced: Range<usize>, new_len: usize) {
+        let delta = new_len as isize - replaced.len() as isize;
+        let inserted = replaced.start..replaced.start + new_len;
+        self.dirty = Some(match self.dirty.take() {
+            None => (inserted, delta),
+            Some((dirty, total)) => {
+                let end = if dirty.end >= replaced.end { (dirty.end as isize + delta) as usize } else { inserted.end };
+                (dirty.start.min(inserted.start)..end.max(inserted.end), total + delta)
+            }
+        });
+    }
+
+    // Brings the tree up to date with `text` after edits. Returns the part of the text that was
+    // parsed again, empty if nothing changed
+    pub fn update(&mut self, text: &str) -> Range<usize> {
+        let Some((dirty, delta)) = self.dirty.take() else { return 0..0 };
+        let old_dirty_end = (dirty.end as isize - delta) as usize;
+        let children = std::mem::take(&mut self.root.children);
+        // Nodes touching the edit are parsed again, as the edit may have joined or split them
+        let before = children.iter().take_while(|child| child.range.end < dirty.start).count();
+        let after = children.iter().rev().take_while(|child| child.range.start > old_dirty_end).count().min(children.len() - before);
+        let start = children[..before].last().map_or(0, |child| child.range.end);
+        let end = children[children.len() - after..].first().map_or(text.len(), |child| (child.range.start as isize + delta) as usize);
+
+        match parse_nodes(&self.grammar, &text[start..end], start, after > 0) {
+            Some(middle) => {
+                let mut rest = children;
+                let mut tail = rest.split_off(rest.len() - after);
+                tail.iter_mut().for_each(|child| child.shift(delta));
+                rest.truncate(before);
+                rest.extend(middle);
+                rest.extend(tail);
+                self.root = Node { kind: NodeKind::Source, range: 0..text.len(), children: rest };
+                start..end
+            }
+            // Something opened in the edit closes further down, so the rest has to be parsed too
+            None => {
+                *self = BracketTree::new(self.grammar.clone(), text);
+                start..text.len()
+            }
+        }
+    }
+
+    // Ranges to color, with the name of the theme face for each
+    pub fn highlights(&self, visible: Range<usize>) -> Vec<(Range<usize>, &'static str)> {
+        let mut nodes = Vec::new();
+        self.root.walk(&mut nodes);
+        let mut out = Vec::new();
+        for node in nodes.into_iter().filter(|node| node.range.start < visible.end && visible.start < node.range.end) {
+            match node.kind {
+                NodeKind::String => out.push((node.range.clone(), "string")),
+                NodeKind::Comment => out.push((node.range.clone(), "comment")),
+                NodeKind::Bracketed(open) => {
+                    out.push((node.range.start..node.range.start + open.len_utf8(), "punctuation.bracket"));
+                    if let Some(close) = self.grammar.closing(open).filter(|_| node.range.len() > open.len_utf8()) {
+                        out.push((node.range.end - close.len_utf8()..node.range.end, "punctuation.bracket"));
+                    }
+                }
+                NodeKind::Source => {}
+            }
+        }
+        out.sort_by_key(|(range, _)| range.start);
+        out
+    }
+
+    // The smallest node, or inside of a bracketed node, bigger than `range`. For expand-selection
+    pub fn expand(&self, range: Range<usize>) -> Range<usize> {
+        for node in self.root.path_to(&range).into_iter().rev() {
+            if let Some(inside) = self.inside(node).filter(|inside| inside.start <= range.start && range.end <= inside.end && inside.len() > range.len()) {
+                return inside;
+            }
+            if node.range.len() > range.len() {
+                return node.range.clone();
+            }
+        }
+        range
+    }
+
+    // Bracketed nodes without the brackets
+    fn inside(&self, node: &Node) -> Option<Range<usize>> {
+        let NodeKind::Bracketed(open) = node.kind else { return None };
+        let close = self.grammar.closing(open)?.len_utf8();
+        let end = node.range.end.saturating_sub(close).max(node.range.start + open.len_utf8());
+        Some(node.range.start + open.len_utf8()..end)
+    }
+
+    // Line ranges of nodes spanning several lines, like folding::indent_folds
+    pub fn folds(&self, text: &str) -> Vec<Range<usize>> {
+        let starts = line_starts(text);
+        let line_of = |byte: usize| starts.partition_point(|&start| start <= byte) - 1;
+        let mut nodes = Vec::new();
+        self.root.walk(&mut nodes);
+        let mut folds: Vec<Range<usize>> = nodes
+            .into_iter()
+            .filter(|node| node.kind != NodeKind::Source)
+            .map(|node| line_of(node.range.start)..line_of(node.range.end.saturating_sub(1).max(node.range.start)) + 1)
+            .filter(|lines| lines.len() > 1)
+            .collect();
+        folds.sort_by_key(|lines| (lines.start, std::cmp::Reverse(lines.end)));
+        folds.dedup();
+        folds
+    }
+
+    // The innermost node of `kind` around `pos`. With `inside`, bracketed nodes leave out their
+    // brackets, like vim's i( versus a(
+    pub fn textobject(&self, pos: usize, kind: TextObject, inside: bool) -> Option<Range<usize>> {
+        let range = pos..pos;
+        let node = self.root.path_to(&range).into_iter().rev().find(|node| {
+            matches!(
+                (kind, node.kind),
+                (TextObject::Bracketed, NodeKind::Bracketed(_)) | (TextObject::String, NodeKind::String) | (TextObject::Comment, NodeKind::Comment)
+            )
+        })?;
+        match inside {
+            true => self.inside(node).or(Some(node.range.clone())),
+            false => Some(node.range.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextObject {
+    Bracketed,
+    String,
+    Comment,
+    // Only syntax trees know functions
+    Function,
+}
+
+// Bracket trees for open buffers, by buffer id
+#[derive(Debug, Default)]
+pub struct Trees {
+    pub languages: Languages,
+    trees: HashMap<usize, BracketTree>,
+}
+
+impl Trees {
+    // Parses a newly opened buffer, if there is a grammar for it
+    pub fn open(&mut self, buffer: usize, path: &Path, text: &str) {
+        if let Some(grammar) = self.languages.for_path(path) {
+            self.trees.insert(buffer, BracketTree::new(grammar, text));
+        }
+    }
+
+    pub fn close(&mut self, buffer: usize) {
+        self.trees.remove(&buffer);
+    }
+
+    pub fn get(&self, buffer: usize) -> Option<&BracketTree> {
+        self.trees.get(&buffer)
+    }
+
+    pub fn get_mut(&mut self, buffer: usize) -> Option<&mut BracketTree> {
+        self.trees.get_mut(&buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUST: &str = "name = \"rust\"\nextensions = [\"rs\"]\nbrackets = [\"()\", \"[]\", \"{}\"]\nstrings = [\"\\\"\"]\nline_comment = \"//\"\nblock_comment = [\"/*\", \"*/\"]\n";
+
+    fn rust() -> Arc<Grammar> {
+        Arc::new(Grammar::parse(Path::new("rust.toml"), RUST).unwrap())
+    }
+
+    #[test]
+    fn parses_and_queries() {
+        let text = "fn f(a: u8) {\n    // hi (\n    g(\"x)\");\n}\n";
+        let tree = BracketTree::new(rust(), text);
+        let kinds: Vec<NodeKind> = tree.root.children.iter().map(|node| node.kind).collect();
+        assert_eq!(kinds, vec![NodeKind::Bracketed('('), NodeKind::Bracketed('{')]);
+
+        let highlights = tree.highlights(0..text.len());
+        let faces: Vec<(&str, &str)> = highlights.iter().map(|(range, face)| (&text[range.clone()], *face)).collect();
+        assert!(faces.contains(&("// hi (", "comment")));
+        assert!(faces.contains(&("\"x)\"", "string")));
+
+        let x = text.find('x').unwrap();
+        assert_eq!(&text[tree.expand(x..x + 1)], "\"x)\"");
+        // The inside of g(...) is just the string, so the next step takes the parentheses too
+        assert_eq!(&text[tree.expand(tree.expand(x..x + 1))], "(\"x)\")");
+        assert_eq!(tree.folds(text), vec![0..4]);
+        assert_eq!(tree.textobject(x, TextObject::Bracketed, true).map(|r| &text[r]), Some("\"x)\""));
+        assert_eq!(tree.textobject(x, TextObject::String, false).map(|r| &text[r]), Some("\"x)\""));
+        assert_eq!(tree.textobject(0, TextObject::Comment, false), None);
+    }
+
+    #[test]
+    fn incremental_matches_full_parse() {
+        let mut text = "a(1)\nb[2]\nc{3}\nd(4)\n".to_string();
+        let mut tree = BracketTree::new(rust(), &text);
+
+        // Inside one node, only that node is parsed again
+        let at = text.find('2').unwrap();
+        text.replace_range(at..at + 1, "[x]");
+        tree.edit(at..at + 1, 3);
+        let reparsed = tree.update(&text);
+        assert!(reparsed.len() < 12, "{reparsed:?}");
+        assert_eq!(tree.root, BracketTree::new(rust(), &text).root);
+
+        // Opening a string swallows everything after it
+        let at = text.find('c').unwrap();
+        text.insert(at, '"');
+        tree.edit(at..at, 1);
+        tree.update(&text);
+        assert_eq!(tree.root, BracketTree::new(rust(), &text).root);
+        assert_eq!(tree.root.children.last().unwrap().kind, NodeKind::String);
+
+        // Several edits before updating
+        text.remove(at);
+        tree.edit(at..at + 1, 0);
+        text.insert(0, '(');
+        tree.edit(0..0, 1);
+        text.push(')');
+        tree.edit(text.len() - 1..text.len() - 1, 1);
+        tree.update(&text);
+        assert_eq!(tree.root, BracketTree::new(rust(), &text).root);
+        assert_eq!(tree.update(&text), 0..0);
+    }
+
+    #[test]
+    fn grammars_load_from_a_directory() {
+        let dir = std::env::temp_dir().join(format!("rakoune-grammars-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("rust.toml"), RUST).unwrap();
+        let languages = Languages::load_dir(&dir).unwrap();
+        assert_eq!(languages.for_path(Path::new("src/main.rs")).unwrap().name, "rust");
+        assert!(languages.for_path(Path::new("README.md")).is_none());
+
+        std::fs::write(dir.join("bad.toml"), "brackets = [\"(\"]").unwrap();
+        assert!(matches!(Languages::load_dir(&dir), Err(Error::BadBracket(..))));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut trees = Trees { languages, ..Default::default() };
+        trees.open(1, Path::new("a.rs"), "f()");
+        trees.open(2, Path::new("a.txt"), "f()");
+        assert!(trees.get(1).is_some() && trees.get(2).is_none());
+    }
+}
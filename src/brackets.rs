@@ -0,0 +1,120 @@
+use std::ops::Range;
+
+const PAIRS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bracket {
+    // Byte offset within the line
+    pub at: usize,
+    // Nesting depth, or None for a closing bracket that doesn't match anything
+    pub depth: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LineState {
+    // Brackets still open at the start of the line
+    open_at_start: Vec<char>,
+    brackets: Vec<Bracket>,
+}
+
+// Nesting depth of every bracket in a text, for coloring matching pairs by depth
+#[derive(Debug, Default, Clone)]
+pub struct BracketDepths {
+    lines: Vec<LineState>,
+}
+
+fn analyze_line(line: &str, open: &mut Vec<char>) -> Vec<Bracket> {
+    let mut brackets = Vec::new();
+    for (at, ch) in line.char_indices() {
+        if PAIRS.iter().any(|&(o, _)| o == ch) {
+            brackets.push(Bracket { at, depth: Some(open.len()) });
+            open.push(ch);
+        } else if let Some(&(o, _)) = PAIRS.iter().find(|&&(_, c)| c == ch) {
+            if open.last() == Some(&o) {
+                open.pop();
+                brackets.push(Bracket { at, depth: Some(open.len()) });
+            } else {
+                brackets.push(Bracket { at, depth: None });
+            }
+        }
+    }
+    brackets
+}
+
+impl BracketDepths {
+    pub fn new(text: &str) -> BracketDepths {
+        let mut depths = BracketDepths::default();
+        let n_lines = text.split('\n').count();
+        depths.edit(text, 0..0, n_lines);
+        depths
+    }
+
+    // Lines the analysis is of, which edits must be within
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    // Brackets on a line, in order
+    pub fn line(&self, line: usize) -> &[Bracket] {
+        self.lines.get(line).map_or(&[], |state| &state.brackets)
+    }
+
+    // Updates after an edit that replaced the lines `old_lines` with `new_line_count` lines, `text` being
+    // the text after the edit. Only the edited lines are reanalyzed, plus the lines after them for as long
+    // as the set of open brackets carried into them changed
+    pub fn edit(&mut self, text: &str, old_lines: Range<usize>, new_line_count: usize) {
+        let placeholder = LineState { open_at_start: Vec::new(), brackets: Vec::new() };
+        self.lines.splice(old_lines.clone(), std::iter::repeat_n(placeholder, new_line_count));
+
+        let first = old_lines.start;
+        let edit_end = first + new_line_count;
+        let mut open = if first == 0 {
+            Vec::new()
+        } else {
+            let prev = &self.lines[first - 1];
+            let mut open = prev.open_at_start.clone();
+            let line = text.split('\n').nth(first - 1).unwrap_or("");
+            analyze_line(line, &mut open);
+            open
+        };
+
+        for (idx, line) in text.split('\n').enumerate().skip(first) {
+            if idx >= edit_end && self.lines[idx].open_at_start == open {
+                // Everything from here on is unaffected by the edit
+                break;
+            }
+            let open_at_start = open.clone();
+            let brackets = analyze_line(line, &mut open);
+            self.lines[idx] = LineState { open_at_start, brackets };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn depths(d: &BracketDepths, line: usize) -> Vec<Option<usize>> {
+        d.line(line).iter().map(|b| b.depth).collect()
+    }
+
+    #[test]
+    fn colors_by_depth() {
+        let d = BracketDepths::new("f(a[0], {b[c]})\n)");
+        assert_eq!(depths(&d, 0), vec![Some(0), Some(1), Some(1), Some(1), Some(2), Some(2), Some(1), Some(0)]);
+        assert_eq!(depths(&d, 1), vec![None]);
+    }
+
+    #[test]
+    fn incremental_edit_matches_full_analysis() {
+        let before = "a {\nb (\nc )\n}\nd [ ]";
+        let after = "a {\nb ( (\nx\nc )\n}\nd [ ]";
+        let mut d = BracketDepths::new(before);
+        // Line 1 was replaced by two lines
+        d.edit(after, 1..2, 2);
+        let fresh = BracketDepths::new(after);
+        for line in 0..6 {
+            assert_eq!(d.line(line), fresh.line(line), "line {line}");
+        }
+    }
+}
@@ -0,0 +1,218 @@
+// Copying and pasting through the platform's clipboard, and on X11 and Wayland the primary
+// selection. Both primary selection behaviours are on unless configured:
+//
+//   [clipboard]
+//   auto_primary = false
+//   middle_click_paste = false
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selection {
+    // The regular clipboard, used by copy and paste
+    Clipboard,
+    // On X11 and Wayland, the most recently selected text, pasted with the middle mouse button
+    Primary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClipboardConfig {
+    // Put selected text in the primary selection as soon as it's selected
+    pub auto_primary: bool,
+    pub middle_click_paste: bool,
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        ClipboardConfig { auto_primary: true, middle_click_paste: true }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Wayland,
+    X11,
+    MacOS,
+    Windows,
+    // Only within rakoune itself
+    Internal,
+}
+
+impl Backend {
+    pub fn detect() -> Backend {
+        if cfg!(target_os = "macos") {
+            Backend::MacOS
+        } else if cfg!(target_os = "windows") {
+            Backend::Windows
+        } else if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Backend::Wayland
+        } else if std::env::var_os("DISPLAY").is_some() {
+            Backend::X11
+        } else {
+            Backend::Internal
+        }
+    }
+
+    pub fn has_primary(self) -> bool {
+        matches!(self, Backend::Wayland | Backend::X11)
+    }
+
+    // With a MIME type, for offering formatted text like text/html. Only the X11 and Wayland tools
+    // take one
+    fn copy_command(self, selection: Selection, mime_type: Option<&str>) -> Option<Command> {
+        let primary = selection == Selection::Primary;
+        let mut command = match self {
+            Backend::Wayland => {
+                let mut c = Command::new("wl-copy");
+                if primary {
+                    c.arg("--primary");
+                }
+                if let Some(mime_type) = mime_type {
+                    c.args(["--type", mime_type]);
+                }
+                c
+            }
+            Backend::X11 => {
+                let mut c = Command::new("xclip");
+                c.args(["-in", "-selection", if primary { "primary" } else { "clipboard" }]);
+                if let Some(mime_type) = mime_type {
+                    c.args(["-t", mime_type]);
+                }
+                c
+            }
+            _ if mime_type.is_some() => return None,
+            Backend::MacOS if !primary => Command::new("pbcopy"),
+            Backend::Windows if !primary => Command::new("clip"),
+            _ => return None,
+        };
+        command.stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::null());
+        Some(command)
+    }
+
+    fn paste_command(self, selection: Selection) -> Option<Command> {
+        let primary = selection == Selection::Primary;
+        let command = match self {
+            Backend::Wayland => {
+                let mut c = Command::new("wl-paste");
+                c.arg("--no-newline");
+                if primary {
+                    c.arg("--primary");
+                }
+                c
+            }
+            Backend::X11 => {
+                let mut c = Command::new("xclip");
+                c.args(["-out", "-selection", if primary { "primary" } else { "clipboard" }]);
+                c
+            }
+            Backend::MacOS if !primary => Command::new("pbpaste"),
+            Backend::Windows if !primary => {
+                let mut c = Command::new("powershell");
+                c.args(["-NoProfile", "-Command", "Get-Clipboard -Raw"]);
+                c
+            }
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+// Copies and pastes through the platform's clipboard tools. If those aren't available, or the
+// platform has no primary selection, the text is kept inside rakoune instead
+pub struct Clipboard {
+    pub backend: Backend,
+    pub config: ClipboardConfig,
+    internal_clipboard: String,
+    internal_primary: String,
+}
+
+impl Clipboard {
+    pub fn new(config: ClipboardConfig) -> Clipboard {
+        Clipboard {
+            backend: Backend::detect(),
+            config,
+            internal_clipboard: String::new(),
+            internal_primary: String::new(),
+        }
+    }
+
+    fn internal(&mut self, selection: Selection) -> &mut String {
+        match selection {
+            Selection::Clipboard => &mut self.internal_clipboard,
+            Selection::Primary => &mut self.internal_primary,
+        }
+    }
+
+    pub fn copy(&mut self, selection: Selection, text: &str) -> std::io::Result<()> {
+        self.copy_typed(selection, text, None)
+    }
+
+    // Copies `text` as `mime_type`, so pasting into documents can keep its formatting. Returns
+    // false if the platform can't take formatted text, in which case nothing was copied
+    pub fn copy_formatted(&mut self, selection: Selection, text: &str, mime_type: &str) -> std::io::Result<bool> {
+        if self.backend.copy_command(selection, Some(mime_type)).is_none() {
+            return Ok(false);
+        }
+        self.copy_typed(selection, text, Some(mime_type))?;
+        Ok(true)
+    }
+
+    fn copy_typed(&mut self, selection: Selection, text: &str, mime_type: Option<&str>) -> std::io::Result<()> {
+        *self.internal(selection) = text.to_string();
+        let Some(mut command) = self.backend.copy_command(selection, mime_type) else { return Ok(()) };
+        let mut child = command.spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        // wl-copy and xclip stay around to serve the selection, so reap them in the background
+        std::thread::spawn(move || child.wait());
+        Ok(())
+    }
+
+    pub fn paste(&mut self, selection: Selection) -> String {
+        let from_platform = self.backend
+            .paste_command(selection)
+            .and_then(|mut command| command.output().ok())
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok());
+        match from_platform {
+            Some(text) => text,
+            None => self.internal(selection).clone(),
+        }
+    }
+
+    // Called whenever the selected text changes
+    pub fn selection_changed(&mut self, text: &str) -> std::io::Result<()> {
+        if self.config.auto_primary && !text.is_empty() {
+            self.copy(Selection::Primary, text)?;
+        }
+        Ok(())
+    }
+
+    // Text to insert for a middle click, if middle click pasting is on
+    pub fn middle_click(&mut self) -> Option<String> {
+        self.config.middle_click_paste.then(|| self.paste(Selection::Primary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_selections_are_separate() {
+        let mut clipboard = Clipboard::new(ClipboardConfig::default());
+        clipboard.backend = Backend::Internal;
+        clipboard.copy(Selection::Clipboard, "copied").unwrap();
+        clipboard.selection_changed("selected").unwrap();
+        assert_eq!(clipboard.paste(Selection::Clipboard), "copied");
+        assert_eq!(clipboard.middle_click(), Some("selected".to_string()));
+
+        clipboard.config.middle_click_paste = false;
+        assert_eq!(clipboard.middle_click(), None);
+    }
+}
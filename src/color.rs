@@ -0,0 +1,96 @@
+// Just enough color science for checking themes: luminance and contrast as WCAG defines them, CIELAB
+// for how different two colors look, and how colors look with the common kinds of color blindness.
+// Colors are sRGB with alpha, as everywhere else
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deficiency {
+    // No red cones
+    Protanopia,
+    // No green cones
+    Deuteranopia,
+}
+
+impl Deficiency {
+    pub const ALL: [Deficiency; 2] = [Deficiency::Protanopia, Deficiency::Deuteranopia];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Deficiency::Protanopia => "protanopia",
+            Deficiency::Deuteranopia => "deuteranopia",
+        }
+    }
+
+    // Viénot, Brettel and Mollon (1999), in linear RGB
+    fn matrix(self) -> [[f32; 3]; 3] {
+        match self {
+            Deficiency::Protanopia => [[0.11238, 0.88762, 0.], [0.11238, 0.88762, 0.], [0.00401, -0.00401, 1.]],
+            Deficiency::Deuteranopia => [[0.29275, 0.70725, 0.], [0.29275, 0.70725, 0.], [-0.02234, 0.02234, 1.]],
+        }
+    }
+}
+
+pub fn to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+pub fn from_linear(c: f32) -> f32 {
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1. / 2.4) - 0.055 }
+}
+
+pub fn luminance(color: [f32; 4]) -> f32 {
+    0.2126 * to_linear(color[0]) + 0.7152 * to_linear(color[1]) + 0.0722 * to_linear(color[2])
+}
+
+// From 1 for the same color to 21 for black on white
+pub fn contrast_ratio(a: [f32; 4], b: [f32; 4]) -> f32 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+// How `color` looks to someone with `deficiency`
+pub fn simulate(deficiency: Deficiency, color: [f32; 4]) -> [f32; 4] {
+    let linear = [0, 1, 2].map(|channel| to_linear(color[channel].clamp(0., 1.)));
+    let m = deficiency.matrix();
+    let [r, g, b] = m.map(|row| from_linear((row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2]).clamp(0., 1.)));
+    [r, g, b, color[3]]
+}
+
+// CIELAB under D65
+pub fn lab(color: [f32; 4]) -> [f32; 3] {
+    let [r, g, b] = [0, 1, 2].map(|channel| to_linear(color[channel].clamp(0., 1.)));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16. / 116. };
+    [116. * f(y) - 16., 500. * (f(x) - f(y)), 200. * (f(y) - f(z))]
+}
+
+// CIE76 ΔE. Around 2 is just noticeable, under 10 is easily confused at a glance
+pub fn difference(a: [f32; 4], b: [f32; 4]) -> f32 {
+    let (a, b) = (lab(a), lab(b));
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn red_and_green_merge() {
+        assert!((contrast_ratio([0., 0., 0., 1.], [1.; 4]) - 21.).abs() < 0.01);
+        assert!((to_linear(from_linear(0.3)) - 0.3).abs() < 1e-5);
+        assert!(lab([1.; 4])[0] > 99.9);
+
+        let seen = |deficiency, a, b| difference(simulate(deficiency, a), simulate(deficiency, b));
+        let (red, green) = ([0.95, 0.35, 0.3, 1.], [0.2, 0.5, 0.3, 1.]);
+        assert!(difference(red, green) > 50.);
+        assert!(seen(Deficiency::Protanopia, red, green) < 3.);
+        assert!(seen(Deficiency::Deuteranopia, red, green) > 20.);
+        let (red, olive) = ([0.8, 0.2, 0.2, 1.], [0.5, 0.5, 0.1, 1.]);
+        assert!(seen(Deficiency::Deuteranopia, red, olive) < 10.);
+        // Grays look the same to everyone
+        for deficiency in Deficiency::ALL {
+            assert!(difference(simulate(deficiency, [0.5, 0.5, 0.5, 1.]), [0.5, 0.5, 0.5, 1.]) < 0.5);
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+// How deep user-defined commands may call other user-defined commands, so a command calling itself fails instead of hanging
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("Unknown command: {0}")]
+    UnknownCommand(String),
+    #[error("{0}: {1}")]
+    Failed(String, String),
+    #[error("Command {0} calls itself too deeply")]
+    TooDeep(String),
+    #[error("Config line {0}: {1}")]
+    BadConfig(usize, String),
+}
+
+pub type Builtin<Ctx> = fn(&mut Ctx, &[&str]) -> Result<(), String>;
+// Shows the text of :help, which needs the registry a builtin can't see
+pub type ShowHelp<Ctx> = fn(&mut Ctx, String);
+
+// Named commands, run with :name args...
+pub struct Registry<Ctx> {
+    builtins: HashMap<String, Builtin<Ctx>>,
+    // Builtins given the rest of the line as typed, as their one argument. Patterns have spaces
+    // and backslashes that splitting would change
+    raw: HashSet<String>,
+    aliases: HashMap<String, String>,
+    // Command lines run in order, where $1, $2, ... are replaced with arguments and $@ with all of them
+    user: HashMap<String, Vec<String>>,
+    // Key (as written in the config) to command line
+    bindings: HashMap<String, String>,
+    help: Option<ShowHelp<Ctx>>,
+}
+
+impl<Ctx> Default for Registry<Ctx> {
+    fn default() -> Self {
+        Registry {
+            builtins: HashMap::new(),
+            raw: HashSet::new(),
+            aliases: HashMap::new(),
+            user: HashMap::new(),
+            bindings: HashMap::new(),
+            help: None,
+        }
+    }
+}
+
+// Splits a command line into words on whitespace. Double or single quotes keep a word with spaces in
+// it together, so `write "my notes.txt"` has one argument. Within double quotes a backslash takes
+// the next character as it is, and anywhere else it's just a backslash
+pub fn split_words(command_line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = command_line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(open), c) if c == open => quote = None,
+            (Some('"'), '\\') => word.get_or_insert_with(String::new).extend(chars.next()),
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+// The command lines of a user command, separated by ';'
+pub fn steps(line: &str) -> Vec<String> {
+    line.split(';').map(|step| step.trim().to_string()).collect()
+}
+
+// `arg` as one word of a command line, quoted if it needs to be
+pub fn quote(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '\\')) {
+        return arg.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in arg.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted + "\""
+}
+
+fn substitute(template: &str, args: &[&str]) -> String {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        match chars.peek() {
+            Some('@') => {
+                chars.next();
+                out.push_str(&args.iter().map(|arg| quote(arg)).collect::<Vec<_>>().join(" "));
+            }
+            Some(d) if d.is_ascii_digit() => {
+                let mut n = 0;
+                while let Some(d) = chars.peek().and_then(|d| d.to_digit(10)) {
+                    n = n * 10 + d as usize;
+                    chars.next();
+                }
+                if let Some(arg) = n.checked_sub(1).and_then(|i| args.get(i)) {
+                    out.push_str(&quote(arg));
+                }
+            }
+            _ => out.push('$'),
+        }
+    }
+    out
+}
+
+impl<Ctx> Registry<Ctx> {
+    pub fn add_builtin(&mut self, name: &str, run: Builtin<Ctx>) {
+        self.builtins.insert(name.to_string(), run);
+    }
+
+    // A builtin taking the rest of the command line unsplit, like `search-files s/a  b/c/`
+    pub fn add_raw_builtin(&mut self, name: &str, run: Builtin<Ctx>) {
+        self.add_builtin(name, run);
+        self.raw.insert(name.to_string());
+    }
+
+    // Makes :help show help_text with `show`
+    pub fn set_help(&mut self, show: ShowHelp<Ctx>) {
+        self.help = Some(show);
+    }
+
+    pub fn add_alias(&mut self, alias: &str, target: &str) {
+        self.aliases.insert(alias.to_string(), target.to_string());
+    }
+
+    pub fn define(&mut self, name: &str, steps: Vec<String>) {
+        self.user.insert(name.to_string(), steps);
+    }
+
+    pub fn bind(&mut self, key: &str, command_line: &str) {
+        self.bindings.insert(key.to_string(), command_line.to_string());
+    }
+
+    pub fn binding(&self, key: &str) -> Option<&str> {
+        self.bindings.get(key).map(String::as_str)
+    }
+
+    // Every binding, sorted by key
+    pub fn bindings(&self) -> Vec<(&str, &str)> {
+        let mut bindings: Vec<(&str, &str)> = self.bindings.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        bindings.sort();
+        bindings
+    }
+
+    // Bindings whose key sequence continues `prefix`, with the next key of each. Keys are single
+    // characters or written in angle brackets, so `g<C-t>` is the two keys `g` and `<C-t>`
+    pub fn continuations(&self, prefix: &str) -> Vec<(String, &str)> {
+        let mut next: Vec<(String, &str)> = self.bindings
+            .iter()
+            .filter_map(|(key, command_line)| {
+                let rest = key.strip_prefix(prefix).filter(|rest| !rest.is_empty())?;
+                let len = if rest.starts_with('<') { rest.find('>').map_or(rest.len(), |end| end + 1) } else { rest.chars().next()?.len_utf8() };
+                // Longer sequences show what they lead to instead of a command
+                let shown = if len == rest.len() { command_line.as_str() } else { "+prefix" };
+                Some((rest[..len].to_string(), shown))
+            })
+            .collect();
+        next.sort();
+        next.dedup_by(|(a, _), (b, _)| a == b);
+        next
+    }
+
+    // The :help buffer, built from what is registered right now
+    pub fn help_text(&self) -> String {
+        let mut out = String::from("Commands\n");
+        let mut builtins: Vec<&str> = self.builtins.keys().map(String::as_str).chain(self.help.map(|_| "help")).collect();
+        builtins.sort();
+        for name in builtins {
+            out.push_str(&format!("  :{name}\n"));
+        }
+        let mut user: Vec<(&String, &Vec<String>)> = self.user.iter().collect();
+        user.sort();
+        for (name, steps) in user {
+            out.push_str(&format!("  :{name}  runs {}\n", steps.join("; ")));
+        }
+        let mut aliases: Vec<(&String, &String)> = self.aliases.iter().collect();
+        aliases.sort();
+        for (alias, target) in aliases {
+            out.push_str(&format!("  :{alias}  same as :{target}\n"));
+        }
+        out.push_str("\nKeys\n");
+        for (key, command_line) in self.bindings() {
+            out.push_str(&format!("  {key}  {command_line}\n"));
+        }
+        out
+    }
+
+    // Every name that can be run, for completion
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.builtins.keys().chain(self.aliases.keys()).chain(self.user.keys()).map(String::as_str).chain(self.help.map(|_| "help"))
+    }
+
+    // Reads lines of the forms
+    //   alias W w
+    //   command name first step $1; second step $@
+    //   bind <C-s> command line
+    // Empty lines and lines starting with # are skipped
+    pub fn load_config(&mut self, config: &str) -> Result<(), Error> {
+        for (idx, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(3, char::is_whitespace);
+            let (kind, name, rest) = (parts.next(), parts.next(), parts.next().map(str::trim));
+            match (kind, name, rest) {
+                (Some("alias"), Some(alias), Some(target)) => self.add_alias(alias, target),
+                (Some("command"), Some(name), Some(line)) => self.define(name, steps(line)),
+                (Some("bind"), Some(key), Some(command_line)) => self.bind(key, command_line),
+                _ => return Err(Error::BadConfig(idx + 1, line.to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn run(&self, ctx: &mut Ctx, command_line: &str) -> Result<(), Error> {
+        self.run_at_depth(ctx, command_line, 0)
+    }
+
+    fn run_at_depth(&self, ctx: &mut Ctx, command_line: &str, depth: usize) -> Result<(), Error> {
+        let words = split_words(command_line);
+        let Some((name, args)) = words.split_first() else { return Ok(()) };
+        let name = name.as_str();
+        let rest = command_line.trim_start().split_once(char::is_whitespace).map_or("", |(_, rest)| rest.trim_start());
+        let args: Vec<&str> = match self.raw.contains(name) {
+            true if rest.is_empty() => Vec::new(),
+            true => vec![rest],
+            false => args.iter().map(String::as_str).collect(),
+        };
+
+        if depth > MAX_DEPTH {
+            return Err(Error::TooDeep(name.to_string()));
+        }
+        if let Some(target) = self.aliases.get(name) {
+            let expanded = match self.raw.contains(target) {
+                true => format!("{target} {rest}"),
+                false => std::iter::once(target.clone()).chain(args.iter().map(|arg| quote(arg))).collect::<Vec<_>>().join(" "),
+            };
+            return self.run_at_depth(ctx, &expanded, depth + 1);
+        }
+        if let Some(steps) = self.user.get(name) {
+            for step in steps {
+                self.run_at_depth(ctx, &substitute(step, &args), depth + 1)?;
+            }
+            return Ok(());
+        }
+        if let Some(show) = self.help.filter(|_| name == "help" && !self.builtins.contains_key(name)) {
+            show(ctx, self.help_text());
+            return Ok(());
+        }
+        match self.builtins.get(name) {
+            Some(run) => run(ctx, &args).map_err(|e| Error::Failed(name.to_string(), e)),
+            None => Err(Error::UnknownCommand(name.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> Registry<Vec<String>> {
+        let mut registry = Registry::default();
+        registry.add_builtin("w", |log: &mut Vec<String>, args| {
+            log.push(format!("write {}", args.join(" ")));
+            Ok(())
+        });
+        registry.add_builtin("fail", |_, _| Err("nope".to_string()));
+        registry
+    }
+
+    #[test]
+    fn aliases_and_user_commands() {
+        let mut registry = registry();
+        registry.load_config("# comment\nalias W w\ncommand both W $2; w $@\n\nbind <C-s> both a b").unwrap();
+        assert_eq!(registry.load_config("alias W"), Err(Error::BadConfig(1, "alias W".to_string())));
+
+        let mut log = Vec::new();
+        registry.run(&mut log, registry.binding("<C-s>").unwrap()).unwrap();
+        assert_eq!(log, vec!["write b", "write a b"]);
+    }
+
+    #[test]
+    fn quoted_arguments() {
+        assert_eq!(split_words(r#"w "my notes.txt" "it\"s" 'a "b"' "" a\d"#), vec!["w", "my notes.txt", "it\"s", "a \"b\"", "", r"a\d"]);
+        for arg in ["plain", "my notes.txt", r#"say "hi" \ bye"#, ""] {
+            assert_eq!(split_words(&quote(arg)), vec![arg]);
+        }
+
+        let mut registry = registry();
+        registry.load_config("alias W w\ncommand twice W $1; w $@").unwrap();
+        let mut log = Vec::new();
+        registry.run(&mut log, r#"twice "/home/me/my notes.txt" b"#).unwrap();
+        assert_eq!(log, vec!["write /home/me/my notes.txt", "write /home/me/my notes.txt b"]);
+        // Still one argument each after going through the alias and the user command
+        registry.add_builtin("count", |log: &mut Vec<String>, args| {
+            log.push(args.len().to_string());
+            Ok(())
+        });
+        registry.add_alias("c", "count");
+        registry.define("both", vec!["c $@".to_string()]);
+        registry.run(&mut log, r#"both "a b" c"#).unwrap();
+        assert_eq!(log[2], "2");
+    }
+
+    #[test]
+    fn raw_arguments() {
+        let mut registry = registry();
+        registry.add_raw_builtin("search", |log: &mut Vec<String>, args| {
+            log.push(format!("{args:?}"));
+            Ok(())
+        });
+        registry.add_alias("S", "search");
+        let mut log = Vec::new();
+        registry.run(&mut log, r"search s/\d+  \/x/'y'/").unwrap();
+        registry.run(&mut log, r"S a\b").unwrap();
+        registry.run(&mut log, "search").unwrap();
+        assert_eq!(log, vec![r#"["s/\\d+  \\/x/'y'/"]"#, r#"["a\\b"]"#, "[]"]);
+    }
+
+    #[test]
+    fn help_and_continuations() {
+        let mut registry = registry();
+        registry.load_config("alias W w\nbind gt tabnext\nbind gT tabprev\nbind g<C-x>a both\nbind <C-s> w").unwrap();
+        assert_eq!(registry.continuations("g"), vec![
+            ("<C-x>".to_string(), "+prefix"),
+            ("T".to_string(), "tabprev"),
+            ("t".to_string(), "tabnext"),
+        ]);
+        assert_eq!(registry.continuations("g<C-x>"), vec![("a".to_string(), "both")]);
+        assert!(registry.continuations("<C-s>").is_empty());
+
+        let help = registry.help_text();
+        assert!(help.starts_with("Commands\n  :fail\n  :w\n  :W  same as :w\n"));
+        assert!(help.contains("\n  gt  tabnext\n"));
+
+        registry.set_help(|log, text| log.push(text));
+        let mut log = Vec::new();
+        registry.run(&mut log, "help").unwrap();
+        assert!(log[0].starts_with("Commands\n  :fail\n  :help\n  :w\n"));
+    }
+
+    #[test]
+    fn errors_propagate() {
+        let mut registry = registry();
+        registry.define("wrapper", vec!["w".to_string(), "fail".to_string(), "w".to_string()]);
+        registry.define("loop", vec!["loop".to_string()]);
+
+        let mut log = Vec::new();
+        assert_eq!(registry.run(&mut log, "wrapper"), Err(Error::Failed("fail".to_string(), "nope".to_string())));
+        assert_eq!(log.len(), 1);
+        assert_eq!(registry.run(&mut log, "nonexistent"), Err(Error::UnknownCommand("nonexistent".to_string())));
+        assert_eq!(registry.run(&mut log, "loop"), Err(Error::TooDeep("loop".to_string())));
+    }
+}
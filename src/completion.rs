@@ -0,0 +1,206 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+
+// Words shorter than this aren't worth completing
+const MIN_WORD_LEN: usize = 3;
+// How much of a word is typed before the menu opens by itself
+const MIN_PREFIX_LEN: usize = 2;
+// The most completions the menu shows
+pub const MENU_LEN: usize = 8;
+
+// Scores how well `pattern` matches `candidate`, or None if the characters of the pattern
+// don't appear in order in the candidate. Higher is better. Lowercase characters in the
+// pattern match either case
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut candidate_chars = candidate.char_indices();
+    let mut prev_matched_at: Option<usize> = None;
+    let mut prev_char: Option<char> = None;
+
+    for p in pattern.chars() {
+        loop {
+            let (idx, c) = candidate_chars.next()?;
+            let matches = if p.is_lowercase() { c.to_lowercase().eq(p.to_lowercase()) } else { c == p };
+            let at_word_start = match prev_char {
+                None => true,
+                Some(prev) => !prev.is_alphanumeric() && c.is_alphanumeric() || prev.is_lowercase() && c.is_uppercase(),
+            };
+            prev_char = Some(c);
+            if !matches {
+                continue;
+            }
+            score += 1;
+            if idx == 0 {
+                score += 8;
+            }
+            if at_word_start {
+                score += 4;
+            }
+            if let Some(prev) = prev_matched_at {
+                if candidate[prev..idx].chars().count() == 1 {
+                    score += 6;
+                }
+            }
+            prev_matched_at = Some(idx);
+            break;
+        }
+    }
+    // Prefer shorter candidates, since less of them is left unmatched
+    Some(score * 100 - candidate.chars().count() as i64)
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+pub fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !is_word_char(c))
+        .filter(|word| word.chars().count() >= MIN_WORD_LEN)
+}
+
+// The word ending at `head` and where it starts, once enough of it is typed to complete
+pub fn word_before(text: &str, head: usize) -> Option<(usize, &str)> {
+    let start = text[..head].char_indices().rev().take_while(|&(_, c)| is_word_char(c)).last().map_or(head, |(at, _)| at);
+    let word = &text[start..head];
+    (word.chars().count() >= MIN_PREFIX_LEN).then_some((start, word))
+}
+
+// Words from every open buffer and from dictionary files
+#[derive(Debug, Default)]
+pub struct WordIndex {
+    buffers: HashMap<usize, BTreeSet<String>>,
+    dictionary: BTreeSet<String>,
+}
+
+impl WordIndex {
+    pub fn index_buffer(&mut self, buffer: usize, text: &str) {
+        self.buffers.insert(buffer, words(text).map(str::to_string).collect());
+    }
+
+    pub fn remove_buffer(&mut self, buffer: usize) {
+        self.buffers.remove(&buffer);
+    }
+
+    // One word per line
+    pub fn load_dictionary(&mut self, path: &Path) -> std::io::Result<()> {
+        let contents = std::fs::read_to_string(path)?;
+        self.dictionary.extend(contents.lines().map(str::trim).filter(|w| !w.is_empty()).map(str::to_string));
+        Ok(())
+    }
+
+    // The best `limit` completions for `prefix`, leaving out `prefix` itself
+    pub fn complete(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let candidates: BTreeSet<&String> = self.buffers.values().flatten().chain(self.dictionary.iter()).collect();
+        let mut scored: Vec<(i64, &String)> = candidates
+            .into_iter()
+            .filter(|word| word.as_str() != prefix)
+            .filter_map(|word| Some((fuzzy_score(prefix, word)?, word)))
+            .collect();
+        scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.cmp(b)));
+        scored.into_iter().take(limit).map(|(_, word)| word.clone()).collect()
+    }
+}
+
+// Keeps a WordIndex up to date on a background thread, so indexing big buffers doesn't stall typing
+pub struct Indexer {
+    pub index: Arc<Mutex<WordIndex>>,
+    tx: mpsc::Sender<(usize, Option<String>)>,
+}
+
+impl Indexer {
+    pub fn new() -> Indexer {
+        let index = Arc::new(Mutex::new(WordIndex::default()));
+        let (tx, rx) = mpsc::channel::<(usize, Option<String>)>();
+        let thread_index = index.clone();
+        std::thread::spawn(move || {
+            while let Ok((buffer, text)) = rx.recv() {
+                // Only index the newest version of each buffer if several edits queued up
+                let mut latest = HashMap::new();
+                latest.insert(buffer, text);
+                latest.extend(rx.try_iter());
+
+                for (buffer, text) in latest {
+                    let Some(text) = text else {
+                        thread_index.lock().unwrap().remove_buffer(buffer);
+                        continue;
+                    };
+                    // Split outside the lock, it's the slow part
+                    let words: BTreeSet<String> = words(&text).map(str::to_string).collect();
+                    thread_index.lock().unwrap().buffers.insert(buffer, words);
+                }
+            }
+        });
+        Indexer { index, tx }
+    }
+
+    pub fn buffer_changed(&self, buffer: usize, text: String) {
+        let _ = self.tx.send((buffer, Some(text)));
+    }
+
+    pub fn buffer_closed(&self, buffer: usize) {
+        let _ = self.tx.send((buffer, None));
+    }
+}
+
+impl Default for Indexer {
+    fn default() -> Self {
+        Indexer::new()
+    }
+}
+
+// The completions for the word before the cursor, shown under it while typing in insert mode.
+// Ctrl+N and Ctrl+P or the arrow keys pick one, Tab or Enter puts it in place of the word and
+// Escape closes the menu
+#[derive(Debug, Clone, PartialEq)]
+pub struct Menu {
+    // Byte where the word being completed starts
+    pub start: usize,
+    pub items: Vec<String>,
+    pub selected: usize,
+}
+
+impl Menu {
+    pub fn next(&mut self) {
+        self.selected = (self.selected + 1) % self.items.len();
+    }
+
+    pub fn prev(&mut self) {
+        self.selected = (self.selected + self.items.len() - 1) % self.items.len();
+    }
+
+    pub fn picked(&self) -> &str {
+        &self.items[self.selected]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_ranking() {
+        assert_eq!(fuzzy_score("xyz", "abc"), None);
+        assert!(fuzzy_score("fs", "FontStack") > fuzzy_score("fs", "offset"));
+        assert!(fuzzy_score("font", "font_size") > fuzzy_score("font", "fallback_not_found"));
+        assert_eq!(fuzzy_score("F", "font"), None);
+    }
+
+    #[test]
+    fn completes_from_buffers() {
+        let mut index = WordIndex::default();
+        index.index_buffer(0, "let font_size = fontstack.size();");
+        index.index_buffer(1, "fn frobnicate() {}");
+        assert_eq!(index.complete("fon", 10), vec!["font_size", "fontstack", "frobnicate"]);
+        index.remove_buffer(1);
+        assert_eq!(index.complete("frob", 10), Vec::<String>::new());
+    }
+
+    #[test]
+    fn words_before_the_cursor() {
+        assert_eq!(word_before("let fo", 6), Some((4, "fo")));
+        assert_eq!(word_before("x.größe", 9), Some((2, "größe")));
+        assert_eq!(word_before("let f", 5), None);
+        assert_eq!(word_before("font ", 5), None);
+    }
+}
@@ -0,0 +1,144 @@
+// The user config, ~/.config/rakoune/config.toml, with a section for each part of the editor that
+// has settings. Each section is documented where it's used. Everything has a default, so the file
+// and any section may be left out:
+//
+//   format_on_save = true
+//
+//   [bind]
+//   "<C-s>" = "w"
+//
+//   [alias]
+//   W = "w"
+//
+//   [command]
+//   both = "W $2; w $@"
+//
+//   [keyboard]
+//   mapping = "layout"
+//
+// Projects layer their own .rakoune.toml over this, see project.rs
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::accessibility::AccessibilityConfig;
+use crate::atlas::AtlasOverrides;
+use crate::autosave::AutoSaveConfig;
+use crate::background::BackgroundConfig;
+use crate::clipboard::ClipboardConfig;
+use crate::commands::{self, Registry};
+use crate::highlighter::HighlightConfig;
+use crate::keymap::KeyboardConfig;
+use crate::memory::MemoryConfig;
+use crate::render::PresentMode;
+use crate::statusline::StatusLineConfig;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("{0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub format_on_save: bool,
+    // Whether each project gets a session of its own, unless its .rakoune.toml says
+    pub project_sessions: bool,
+    pub present_mode: PresentMode,
+    // Glyphs rasterized in a compute shader, see gpu_raster.rs
+    pub gpu_raster: bool,
+    // Key to command line
+    pub bind: HashMap<String, String>,
+    // Name to the command it stands for
+    pub alias: HashMap<String, String>,
+    // Name to the command lines it runs, separated by ';', as in commands::Registry::load_config
+    pub command: HashMap<String, String>,
+    pub accessibility: AccessibilityConfig,
+    pub atlas: AtlasOverrides,
+    pub auto_save: AutoSaveConfig,
+    pub background: BackgroundConfig,
+    pub clipboard: ClipboardConfig,
+    pub highlighting: HighlightConfig,
+    pub keyboard: KeyboardConfig,
+    pub memory: MemoryConfig,
+    pub status_line: StatusLineConfig,
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("rakoune").join("config.toml"))
+    }
+
+    // Grammars for the syntax trees, a TOML file for each language, see bracket_tree.rs and syntax.rs
+    pub fn grammars_dir() -> Option<PathBuf> {
+        Some(Config::default_path()?.parent()?.join("grammars"))
+    }
+
+    // The defaults when there is no file
+    pub fn load(path: &Path) -> Result<Config, Error> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(Error::Read(path.to_path_buf(), e)),
+        };
+        toml::from_str(&text).map_err(|e| Error::Parse(path.to_path_buf(), e))
+    }
+
+    pub fn apply<Ctx>(&self, registry: &mut Registry<Ctx>) {
+        for (alias, target) in &self.alias {
+            registry.add_alias(alias, target);
+        }
+        for (name, steps) in &self.command {
+            registry.define(name, commands::steps(steps));
+        }
+        for (key, command_line) in &self.bind {
+            registry.bind(key, command_line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_and_defaults() {
+        let dir = std::env::temp_dir().join(format!("rakoune-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        assert!(!Config::load(&path).unwrap().format_on_save);
+
+        std::fs::write(&path, "format_on_save = true\npresent_mode = \"mailbox\"\ngpu_raster = true\n[bind]\n\"<C-s>\" = \"both\"\n[alias]\nW = \"w\"\n[command]\nboth = \"W; w $@\"\n[keyboard]\nmapping = \"layout\"\n[memory]\nbudget_mb = 64\n[clipboard]\nauto_primary = false\n[atlas]\nmax_pages = 2\n").unwrap();
+        let config = Config::load(&path).unwrap();
+        assert!(config.format_on_save);
+        assert_eq!((config.present_mode, config.gpu_raster), (PresentMode::Mailbox, true));
+        assert_eq!(config.memory.budget_mb, 64);
+        assert!(!config.clipboard.auto_primary && config.clipboard.middle_click_paste);
+        assert_eq!(config.atlas.max_pages, Some(2));
+        assert_eq!(config.status_line.left, StatusLineConfig::default().left);
+        let mut registry = Registry::<Vec<&str>>::default();
+        registry.add_builtin("w", |written: &mut Vec<&str>, _| {
+            written.push("w");
+            Ok(())
+        });
+        config.apply(&mut registry);
+        assert_eq!(registry.binding("<C-s>"), Some("both"));
+        let mut written = Vec::new();
+        registry.run(&mut written, "both").unwrap();
+        assert_eq!(written, vec!["w", "w"]);
+
+        std::fs::write(&path, "[keyboard]\nlayout = 1\n").unwrap();
+        assert!(matches!(Config::load(&path), Err(Error::Parse(..))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
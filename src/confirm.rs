@@ -0,0 +1,215 @@
+// Questions that need an answer before anything else happens, like quitting with unsaved changes.
+// Each choice has a key and the command line to run when it's picked, the same way :dir-delete asks
+// before running :dir-delete --confirmed <path>. While a question is open, keys answer it instead of
+// going to the buffer. Questions asked while one is open wait their turn
+use std::collections::VecDeque;
+use std::path::Path;
+
+use crate::commands::{quote, Registry};
+use crate::notifications::{run_reporting, MessagesHost};
+
+const ESCAPE: char = '\u{1b}';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Choice {
+    pub key: char,
+    // With the key in brackets where it first appears, like `[s]ave all`
+    pub label: String,
+    // None to do nothing
+    pub command_line: Option<String>,
+}
+
+impl Choice {
+    pub fn new(key: char, label: &str, command_line: Option<String>) -> Choice {
+        let label = match label.find(key) {
+            Some(at) => format!("{}[{key}]{}", &label[..at], &label[at + key.len_utf8()..]),
+            None => format!("[{key}] {label}"),
+        };
+        Choice { key, label, command_line }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub text: String,
+    // The last one is also picked by Escape
+    pub choices: Vec<Choice>,
+}
+
+impl Question {
+    pub fn yes_no(text: &str, command_line: &str) -> Question {
+        Question { text: text.to_string(), choices: vec![Choice::new('y', "yes", Some(command_line.to_string())), Choice::new('n', "no", None)] }
+    }
+
+    // Quitting with buffers that have unsaved changes
+    pub fn quit_unsaved(names: &[String]) -> Question {
+        let text = match names {
+            [name] => format!("{name} has unsaved changes. Quit anyway?"),
+            _ => format!("{} buffers have unsaved changes. Quit anyway?", names.len()),
+        };
+        Question {
+            text,
+            choices: vec![Choice::new('s', "save all and quit", Some("quit --save".to_string())), Choice::new('q', "quit without saving", Some("quit --force".to_string())), Choice::new('c', "cancel", None)],
+        }
+    }
+
+    // A file changed on disk. `modified` is whether the buffer has changes of its own, which reloading loses
+    pub fn reload_changed(path: &Path, modified: bool) -> Question {
+        let text = match modified {
+            true => format!("{} changed on disk, and has unsaved changes here. Reload it and lose them?", path.display()),
+            false => format!("{} changed on disk. Reload it?", path.display()),
+        };
+        Question { text, choices: vec![Choice::new('r', "reload", Some("reload --force".to_string())), Choice::new('k', "keep this version", None)] }
+    }
+
+    // Writing to a path where a file already is, but that isn't the buffer's own file. Yes runs
+    // `command` again with --force
+    pub fn overwrite(path: &Path, command: &str) -> Question {
+        Question::yes_no(&format!("{} already exists. Overwrite it?", path.display()), &format!("{command} --force {}", quote(&path.display().to_string())))
+    }
+
+    // A project's config was found that hasn't been trusted yet
+    pub fn trust_project(config_path: &Path) -> Question {
+        Question {
+            text: format!("{} can bind keys to commands. Trust it?", config_path.display()),
+            choices: vec![Choice::new('y', "yes", Some("trust-project".to_string())), Choice::new('n', "no", Some("ignore-project".to_string()))],
+        }
+    }
+
+    // For the status line, like `Overwrite it? [y]es [n]o`
+    pub fn line(&self) -> String {
+        std::iter::once(self.text.as_str()).chain(self.choices.iter().map(|choice| choice.label.as_str())).collect::<Vec<_>>().join(" ")
+    }
+
+    fn choice(&self, key: char) -> Option<&Choice> {
+        match key {
+            ESCAPE => self.choices.last(),
+            _ => self.choices.iter().find(|choice| choice.key == key.to_ascii_lowercase()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Answered {
+    // Run this command line
+    Run(String),
+    // Picked a choice that does nothing
+    Nothing,
+    // Not one of the keys, so the question stays open
+    Ignored,
+}
+
+#[derive(Debug, Default)]
+pub struct Questions {
+    queue: VecDeque<Question>,
+}
+
+impl Questions {
+    pub fn ask(&mut self, question: Question) {
+        // Asking the same thing twice, like saving twice over the same file, needs one answer
+        if !self.queue.contains(&question) {
+            self.queue.push_back(question);
+        }
+    }
+
+    // The question being asked, which keys go to
+    pub fn current(&self) -> Option<&Question> {
+        self.queue.front()
+    }
+
+    pub fn answer(&mut self, key: char) -> Answered {
+        let Some(choice) = self.current().and_then(|question| question.choice(key)) else {
+            return Answered::Ignored;
+        };
+        let answered = choice.command_line.clone().map_or(Answered::Nothing, Answered::Run);
+        self.queue.pop_front();
+        answered
+    }
+}
+
+// What asking needs from the editor
+pub trait ConfirmHost: MessagesHost {
+    fn questions(&mut self) -> &mut Questions;
+}
+
+// Answers the open question with `key`, running what was picked. Returns false if no question
+// took the key, so it goes to the buffer as usual
+pub fn answer_key<Ctx: ConfirmHost>(registry: &Registry<Ctx>, ctx: &mut Ctx, key: char) -> bool {
+    if ctx.questions().current().is_none() {
+        return false;
+    }
+    if let Answered::Run(command_line) = ctx.questions().answer(key) {
+        run_reporting(registry, ctx, &command_line);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::Notifications;
+
+    #[derive(Default)]
+    struct Editor {
+        questions: Questions,
+        notifications: Notifications,
+        ran: Vec<String>,
+    }
+
+    impl MessagesHost for Editor {
+        fn notifications(&mut self) -> &mut Notifications {
+            &mut self.notifications
+        }
+
+        fn show_report(&mut self, _title: &str, _text: String) {}
+    }
+
+    impl ConfirmHost for Editor {
+        fn questions(&mut self) -> &mut Questions {
+            &mut self.questions
+        }
+    }
+
+    #[test]
+    fn answers_run_their_command() {
+        let mut registry = Registry::<Editor>::default();
+        registry.add_builtin("quit", |editor, args| {
+            editor.ran.push(format!("quit {}", args.join(" ")));
+            // Asking again from the continuation queues behind what's open
+            editor.questions.ask(Question::overwrite(Path::new("notes.txt"), "write"));
+            Ok(())
+        });
+        let mut editor = Editor::default();
+        assert!(!answer_key(&registry, &mut editor, 'y'));
+
+        let quit = Question::quit_unsaved(&["main.rs".to_string(), "notes.txt".to_string()]);
+        assert_eq!(quit.line(), "2 buffers have unsaved changes. Quit anyway? [s]ave all and quit [q]uit without saving [c]ancel");
+        editor.questions.ask(quit.clone());
+        editor.questions.ask(Question::reload_changed(Path::new("main.rs"), false));
+        editor.questions.ask(quit);
+
+        assert!(answer_key(&registry, &mut editor, 'x'));
+        assert!(answer_key(&registry, &mut editor, 'S'));
+        assert_eq!(editor.ran, vec!["quit --save"]);
+        assert_eq!(editor.questions.current().unwrap().line(), "main.rs changed on disk. Reload it? [r]eload [k]eep this version");
+        // Escape picks the last choice, keeping the buffer
+        assert!(answer_key(&registry, &mut editor, ESCAPE));
+        assert_eq!(editor.questions.current().unwrap().line(), "notes.txt already exists. Overwrite it? [y]es [n]o");
+
+        // :write isn't registered here, so running it fails and says so
+        assert!(answer_key(&registry, &mut editor, 'y'));
+        assert_eq!(editor.notifications.log[0].text, "Unknown command: write");
+        assert_eq!(editor.questions.current(), None);
+
+        // Quoted, so the path stays one argument
+        let overwrite = Question::overwrite(Path::new("/home/me/my notes.txt"), "write");
+        assert_eq!(overwrite.choices[0].command_line.as_deref(), Some(r#"write --force "/home/me/my notes.txt""#));
+        registry.add_builtin("write", |editor, args| {
+            editor.ran.push(format!("write {args:?}"));
+            Ok(())
+        });
+        editor.questions.ask(overwrite);
+        assert!(answer_key(&registry, &mut editor, 'y'));
+        assert_eq!(editor.ran[1], r#"write ["--force", "/home/me/my notes.txt"]"#);
+    }
+}
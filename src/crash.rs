@@ -0,0 +1,216 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::fmt::Display;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Log lines kept for the crash report
+const LOG_LINES: usize = 100;
+// Written next to the reports after a crash, pointing at the newest one, and removed once shown
+const LAST_CRASH: &str = "last-crash";
+// Times to try for the context, a millisecond apart, before writing the report without it
+const LOCK_ATTEMPTS: u32 = 100;
+// Between a buffer's name and its recovery file in the report
+const RECOVERED: &str = " -> ";
+
+type UnsavedBuffers = Box<dyn Fn() -> Vec<(String, String)> + Send>;
+
+#[derive(Default)]
+struct Context {
+    adapter_info: Option<String>,
+    log: VecDeque<String>,
+    unsaved: Option<UnsavedBuffers>,
+}
+
+static CONTEXT: Mutex<Option<Context>> = Mutex::new(None);
+
+fn with_context(f: impl FnOnce(&mut Context)) {
+    // A panic while holding the lock shouldn't stop the crash report from being written
+    let mut guard = CONTEXT.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(guard.get_or_insert_with(Context::default));
+}
+
+pub fn set_adapter_info(info: &wgpu::AdapterInfo) {
+    let text = format!("{} ({:?}, {:?} backend, driver {} {})", info.name, info.device_type, info.backend, info.driver, info.driver_info);
+    with_context(|ctx| ctx.adapter_info = Some(text));
+}
+
+pub fn log(line: &str) {
+    with_context(|ctx| {
+        if ctx.log.len() == LOG_LINES {
+            ctx.log.pop_front();
+        }
+        ctx.log.push_back(line.to_string());
+    });
+}
+
+// `unsaved` returns the name and text of every buffer with unsaved changes. It's called from the
+// panic hook, so it must not lock anything the panicking thread might hold
+pub fn on_crash_save(unsaved: impl Fn() -> Vec<(String, String)> + Send + 'static) {
+    with_context(|ctx| ctx.unsaved = Some(Box::new(unsaved)));
+}
+
+// A copy of the buffers with unsaved changes for the panic hook, since the editor itself can't be
+// reached from it. The editor keeps it up to date with `update`
+#[derive(Clone, Default)]
+pub struct Unsaved {
+    // The version of each buffer when it was copied, and its name and text
+    shared: Arc<Mutex<Vec<(u64, String, String)>>>,
+}
+
+impl Unsaved {
+    // Makes these the buffers saved on a crash
+    pub fn install(&self) {
+        let shared = self.shared.clone();
+        on_crash_save(move || match shared.try_lock() {
+            Ok(buffers) => buffers.iter().map(|(_, name, text)| (name.clone(), text.clone())).collect(),
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner().iter().map(|(_, name, text)| (name.clone(), text.clone())).collect(),
+            // Only held by this thread while copying, which is what panicked
+            Err(TryLockError::WouldBlock) => Vec::new(),
+        });
+    }
+
+    // `buffers` are the version, name and text of each buffer with unsaved changes. Texts are only
+    // copied again when their versions changed
+    pub fn update<'a>(&self, buffers: impl Iterator<Item = (u64, &'a str, &'a str)>) {
+        let buffers: Vec<_> = buffers.collect();
+        let mut saved = self.shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if saved.iter().map(|(version, name, _)| (*version, name.as_str())).eq(buffers.iter().map(|(version, name, _)| (*version, *name))) {
+            return;
+        }
+        *saved = buffers.into_iter().map(|(version, name, text)| (version, name.to_string(), text.to_string())).collect();
+    }
+}
+
+pub fn default_dir() -> Option<PathBuf> {
+    let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_dir.join("rakoune").join("crashes"))
+}
+
+// Turns a buffer name into something that can be a file name
+fn recovery_name(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || c == '.' || c == '-' { c } else { '_' }).collect()
+}
+
+// `info` says what panicked, the PanicHookInfo from the hook. Also returns how many buffers were saved
+fn write_report(dir: &Path, info: &dyn Display) -> std::io::Result<(PathBuf, usize)> {
+    std::fs::create_dir_all(dir)?;
+    let stamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let report_path = dir.join(format!("crash-{stamp}.txt"));
+    let mut report = std::fs::File::create(&report_path)?;
+
+    writeln!(report, "rakoune {} crashed: {info}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(report, "\nBacktrace:\n{}", std::backtrace::Backtrace::force_capture())?;
+
+    // The panic may have happened while logging, with the context locked on this very thread, so
+    // waiting for it could hang instead of crashing. Another thread only holds it for a moment
+    let mut attempts = 0;
+    let mut guard = loop {
+        match CONTEXT.try_lock() {
+            Ok(guard) => break guard,
+            Err(TryLockError::Poisoned(poisoned)) => break poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) if attempts < LOCK_ATTEMPTS => {
+                attempts += 1;
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Err(TryLockError::WouldBlock) => {
+                writeln!(report, "\nThe log and unsaved buffers were in use while crashing, so they're left out")?;
+                std::fs::write(dir.join(LAST_CRASH), report_path.to_string_lossy().as_bytes())?;
+                return Ok((report_path, 0));
+            }
+        }
+    };
+    let ctx = guard.get_or_insert_with(Context::default);
+    writeln!(report, "GPU: {}", ctx.adapter_info.as_deref().unwrap_or("not initialized"))?;
+    writeln!(report, "\nRecent log:")?;
+    for line in &ctx.log {
+        writeln!(report, "  {line}")?;
+    }
+
+    let buffers = ctx.unsaved.as_ref().map(|unsaved| unsaved()).unwrap_or_default();
+    if !buffers.is_empty() {
+        writeln!(report, "\nRecovered buffers:")?;
+    }
+    let mut saved = 0;
+    for (name, text) in buffers {
+        let recovery_path = dir.join(format!("{stamp}-{}", recovery_name(&name)));
+        match std::fs::write(&recovery_path, text) {
+            Ok(()) => {
+                writeln!(report, "  {name}{RECOVERED}{}", recovery_path.display())?;
+                saved += 1;
+            }
+            Err(e) => writeln!(report, "  {name} could not be saved: {e}")?,
+        }
+    }
+
+    std::fs::write(dir.join(LAST_CRASH), report_path.to_string_lossy().as_bytes())?;
+    Ok((report_path, saved))
+}
+
+// Writes a crash report and saves unsaved buffers when anything panics, then runs the default hook
+pub fn install(dir: PathBuf) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        match write_report(&dir, info) {
+            Ok((path, 0)) => eprintln!("rakoune crashed. A report was saved, see {}", path.display()),
+            Ok((path, saved)) => eprintln!("rakoune crashed. A report and {saved} unsaved buffers were saved, see {}", path.display()),
+            Err(e) => eprintln!("rakoune crashed, and writing the crash report failed too: {e}"),
+        }
+        default_hook(info);
+    }));
+}
+
+// The report from the last crash, if it hasn't been shown yet
+pub fn take_last_report(dir: &Path) -> Option<PathBuf> {
+    let marker = dir.join(LAST_CRASH);
+    let path = std::fs::read_to_string(&marker).ok()?;
+    let _ = std::fs::remove_file(marker);
+    Some(PathBuf::from(path))
+}
+
+// The buffers saved with the crash report at `report`, by name and where their text was saved
+pub fn recovered(report: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(text) = std::fs::read_to_string(report) else { return Vec::new() };
+    let Some((_, list)) = text.split_once("\nRecovered buffers:\n") else { return Vec::new() };
+    list.lines()
+        .filter_map(|line| line.strip_prefix("  ")?.split_once(RECOVERED))
+        .map(|(name, path)| (name.to_string(), PathBuf::from(path)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_report_and_recovery_files() {
+        let dir = std::env::temp_dir().join(format!("rakoune-crash-{}", std::process::id()));
+        log("opened src/main.rs");
+        let unsaved = Unsaved::default();
+        unsaved.install();
+        unsaved.update([(1, "src/main.rs", "unsaved text")].into_iter());
+
+        assert_eq!(write_report(&dir, &"validation error").unwrap().1, 1);
+
+        let report_path = take_last_report(&dir).unwrap();
+        assert!(take_last_report(&dir).is_none());
+        let report = std::fs::read_to_string(&report_path).unwrap();
+        assert!(report.contains("validation error"));
+        assert!(report.contains("opened src/main.rs"));
+        let recovered = report.lines().find_map(|line| line.strip_prefix("  src/main.rs -> ")).unwrap();
+        assert_eq!(std::fs::read_to_string(recovered).unwrap(), "unsaved text");
+        assert!(recovered.ends_with("src_main.rs"));
+        assert_eq!(super::recovered(&report_path), vec![("src/main.rs".to_string(), PathBuf::from(recovered))]);
+
+        // Crashing while the context is locked still writes the report, without it
+        let guard = CONTEXT.lock().unwrap();
+        let (report_path, _) = write_report(&dir, &"while logging").unwrap();
+        drop(guard);
+        assert!(std::fs::read_to_string(report_path).unwrap().contains("left out"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
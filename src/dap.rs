@@ -0,0 +1,512 @@
+// Debugging through the Debug Adapter Protocol. An adapter (lldb-dap, debugpy, dlv dap, ...) runs
+// as a child process speaking JSON messages with a Content-Length header over stdin and stdout,
+// like LSP
+//
+// Session keeps what the UI shows: breakpoints for the gutter, where the program stopped, and the
+// stack and variables for their panes. It reacts to the adapter's messages with the requests that
+// should follow, so once the program stops the stack and the top frame's variables are fetched
+// without anyone asking
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::commands::Registry;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't start {0}: {1}")]
+    Spawn(String, std::io::Error),
+    #[error("Couldn't write to the debug adapter: {0}")]
+    Write(std::io::Error),
+    #[error("Bad message from the debug adapter: {0}")]
+    BadMessage(String),
+}
+
+pub fn encode(message: &Value) -> Vec<u8> {
+    let body = message.to_string();
+    let mut bytes = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+    bytes.extend_from_slice(body.as_bytes());
+    bytes
+}
+
+// Reads messages one at a time from a stream of them
+pub fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>, Error> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).map_err(|e| Error::BadMessage(e.to_string()))? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>().map_err(|_| Error::BadMessage(header.to_string()))?);
+        }
+    }
+    let length = length.ok_or_else(|| Error::BadMessage("no Content-Length".to_string()))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| Error::BadMessage(e.to_string()))?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| Error::BadMessage(e.to_string()))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub command: String,
+    pub arguments: Value,
+}
+
+fn request(command: &str, arguments: Value) -> Request {
+    Request { command: command.to_string(), arguments }
+}
+
+// The adapter process
+pub struct Adapter {
+    child: Child,
+    stdin: ChildStdin,
+    seq: u64,
+    messages: mpsc::Receiver<Result<Value, Error>>,
+}
+
+impl Adapter {
+    // `command` is run through the shell in `cwd`
+    pub fn start(command: &str, cwd: &Path) -> Result<Adapter, Error> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(cwd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| Error::Spawn(command.to_string(), e))?;
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let (tx, messages) = mpsc::channel();
+        std::thread::spawn(move || {
+            while let Some(message) = read_message(&mut stdout).transpose() {
+                let failed = message.is_err();
+                if tx.send(message).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Adapter { child, stdin, seq: 0, messages })
+    }
+
+    pub fn send(&mut self, request: &Request) -> Result<(), Error> {
+        self.seq += 1;
+        let message = json!({ "seq": self.seq, "type": "request", "command": request.command, "arguments": request.arguments });
+        self.stdin.write_all(&encode(&message)).and_then(|_| self.stdin.flush()).map_err(Error::Write)
+    }
+
+    // Messages that arrived since the last poll
+    pub fn poll(&mut self) -> Vec<Result<Value, Error>> {
+        self.messages.try_iter().collect()
+    }
+}
+
+impl Drop for Adapter {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    // Started, waiting for the adapter to be ready for breakpoints
+    Starting,
+    Running,
+    Stopped { thread: i64 },
+    Terminated,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub id: i64,
+    pub name: String,
+    pub path: Option<PathBuf>,
+    // 1-based
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    pub name: String,
+    pub value: String,
+    pub type_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Continue,
+    Over,
+    In,
+    Out,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GutterMark {
+    Breakpoint,
+    // Where the program is stopped, maybe on a breakpoint
+    Stopped { on_breakpoint: bool },
+}
+
+#[derive(Debug)]
+pub struct Session {
+    pub state: State,
+    // 1-based lines, by file
+    pub breakpoints: BTreeMap<PathBuf, BTreeSet<usize>>,
+    pub stack: Vec<Frame>,
+    pub variables: Vec<Variable>,
+    // Program output and failed requests, for the host to show
+    pub output: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+impl Session {
+    pub fn new(breakpoints: BTreeMap<PathBuf, BTreeSet<usize>>) -> Session {
+        Session { state: State::Starting, breakpoints, stack: Vec::new(), variables: Vec::new(), output: Vec::new(), errors: Vec::new() }
+    }
+
+    // The first requests to send. `launch` holds the adapter specific launch arguments, like
+    // { "program": "target/debug/app" }
+    pub fn start(&self, adapter_id: &str, launch: Value) -> Vec<Request> {
+        vec![
+            request("initialize", json!({ "adapterID": adapter_id, "clientName": "rakoune", "linesStartAt1": true, "columnsStartAt1": true, "pathFormat": "path" })),
+            request("launch", launch),
+        ]
+    }
+
+    fn set_breakpoints(&self, path: &Path) -> Request {
+        let lines = self.breakpoints.get(path).into_iter().flatten();
+        let breakpoints: Vec<Value> = lines.map(|line| json!({ "line": line })).collect();
+        request("setBreakpoints", json!({ "source": { "path": path }, "breakpoints": breakpoints }))
+    }
+
+    // Returns the request telling the adapter, if it's ready to hear about breakpoints
+    pub fn toggle_breakpoint(&mut self, path: &Path, line: usize) -> Option<Request> {
+        let lines = self.breakpoints.entry(path.to_owned()).or_default();
+        if !lines.remove(&line) {
+            lines.insert(line);
+        }
+        (self.state != State::Starting).then(|| self.set_breakpoints(path))
+    }
+
+    pub fn step(&self, step: Step) -> Option<Request> {
+        let State::Stopped { thread } = self.state else { return None };
+        let command = match step {
+            Step::Continue => "continue",
+            Step::Over => "next",
+            Step::In => "stepIn",
+            Step::Out => "stepOut",
+        };
+        Some(request(command, json!({ "threadId": thread })))
+    }
+
+    pub fn stop(&self) -> Request {
+        request("disconnect", json!({ "terminateDebuggee": true }))
+    }
+
+    // Updates the session from a message of the adapter, returning the requests to send next
+    pub fn handle(&mut self, message: &Value) -> Vec<Request> {
+        let body = &message["body"];
+        match (message["type"].as_str(), message["event"].as_str(), message["command"].as_str()) {
+            (Some("event"), Some("initialized"), _) => {
+                self.state = State::Running;
+                let mut requests: Vec<Request> = self.breakpoints.keys().map(|path| self.set_breakpoints(path)).collect();
+                requests.push(request("configurationDone", json!({})));
+                requests
+            }
+            (Some("event"), Some("stopped"), _) => {
+                let thread = body["threadId"].as_i64().unwrap_or(0);
+                self.state = State::Stopped { thread };
+                vec![request("stackTrace", json!({ "threadId": thread }))]
+            }
+            (Some("event"), Some("continued"), _) => {
+                self.state = State::Running;
+                self.stack.clear();
+                self.variables.clear();
+                Vec::new()
+            }
+            (Some("event"), Some("terminated" | "exited"), _) => {
+                self.state = State::Terminated;
+                Vec::new()
+            }
+            (Some("event"), Some("output"), _) => {
+                self.output.extend(body["output"].as_str().unwrap_or_default().lines().map(str::to_string));
+                Vec::new()
+            }
+            (Some("response"), _, command) if message["success"] == json!(false) => {
+                let reason = message["message"].as_str().or(body["error"]["format"].as_str()).unwrap_or("failed");
+                self.errors.push(format!("{}: {reason}", command.unwrap_or("request")));
+                Vec::new()
+            }
+            (Some("response"), _, Some("stackTrace")) => {
+                self.stack = body["stackFrames"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|frame| Frame {
+                        id: frame["id"].as_i64().unwrap_or(0),
+                        name: frame["name"].as_str().unwrap_or_default().to_string(),
+                        path: frame["source"]["path"].as_str().map(PathBuf::from),
+                        line: frame["line"].as_u64().unwrap_or(0) as usize,
+                    })
+                    .collect();
+                self.variables.clear();
+                self.stack.first().map(|top| request("scopes", json!({ "frameId": top.id }))).into_iter().collect()
+            }
+            (Some("response"), _, Some("scopes")) => {
+                // Locals come first, and the expensive scopes like globals aren't worth fetching every step
+                let scopes = body["scopes"].as_array().into_iter().flatten();
+                scopes
+                    .filter(|scope| scope["expensive"] != json!(true))
+                    .take(1)
+                    .map(|scope| request("variables", json!({ "variablesReference": scope["variablesReference"] })))
+                    .collect()
+            }
+            (Some("response"), _, Some("variables")) => {
+                self.variables = body["variables"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|variable| Variable {
+                        name: variable["name"].as_str().unwrap_or_default().to_string(),
+                        value: variable["value"].as_str().unwrap_or_default().to_string(),
+                        type_name: variable["type"].as_str().map(str::to_string),
+                    })
+                    .collect();
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    // Where the program is stopped, from the top of the stack
+    pub fn stopped_at(&self) -> Option<(&Path, usize)> {
+        let top = self.stack.first().filter(|_| matches!(self.state, State::Stopped { .. }))?;
+        Some((top.path.as_deref()?, top.line))
+    }
+
+    // Marks for the gutter of `path`, by 1-based line
+    pub fn gutter(&self, path: &Path) -> BTreeMap<usize, GutterMark> {
+        let breakpoints = self.breakpoints.get(path).into_iter().flatten();
+        let mut marks: BTreeMap<usize, GutterMark> = breakpoints.map(|&line| (line, GutterMark::Breakpoint)).collect();
+        if let Some((stopped, line)) = self.stopped_at().filter(|(stopped, _)| *stopped == path) {
+            let on_breakpoint = self.breakpoints.get(stopped).is_some_and(|lines| lines.contains(&line));
+            marks.insert(line, GutterMark::Stopped { on_breakpoint });
+        }
+        marks
+    }
+
+    pub fn stack_lines(&self) -> Vec<String> {
+        self.stack
+            .iter()
+            .map(|frame| match &frame.path {
+                Some(path) => format!("{}  {}:{}", frame.name, path.display(), frame.line),
+                None => frame.name.clone(),
+            })
+            .collect()
+    }
+
+    pub fn variable_lines(&self) -> Vec<String> {
+        self.variables
+            .iter()
+            .map(|variable| match &variable.type_name {
+                Some(type_name) => format!("{}: {} = {}", variable.name, type_name, variable.value),
+                None => format!("{} = {}", variable.name, variable.value),
+            })
+            .collect()
+    }
+}
+
+// A session with its adapter
+pub struct Debugger {
+    pub session: Session,
+    adapter: Adapter,
+}
+
+impl Debugger {
+    pub fn send(&mut self, requests: Vec<Request>) -> Result<(), Error> {
+        requests.iter().try_for_each(|request| self.adapter.send(request))
+    }
+
+    // Handles everything the adapter sent since the last call. Returns whether anything did
+    pub fn update(&mut self) -> Result<bool, Error> {
+        let messages = self.adapter.poll();
+        let changed = !messages.is_empty();
+        for message in messages {
+            let requests = self.session.handle(&message?);
+            self.send(requests)?;
+        }
+        Ok(changed)
+    }
+}
+
+// What the debug commands need from the editor
+pub trait DebugHost {
+    fn debugger(&mut self) -> &mut Option<Debugger>;
+    // Breakpoints set before starting, or kept from the last session
+    fn breakpoints(&mut self) -> &mut BTreeMap<PathBuf, BTreeSet<usize>>;
+    // The focused file and the 1-based line of the cursor in it
+    fn cursor_location(&self) -> Option<(PathBuf, usize)>;
+    fn cwd(&self) -> PathBuf;
+    // Opens the stack and variables panes in splits
+    fn open_debug_panes(&mut self);
+}
+
+// :debug <adapter> <program> [args...], like `:debug lldb-dap target/debug/app`
+fn debug_command<Ctx: DebugHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let [adapter_command, program, program_args @ ..] = args else { return Err("Usage: debug <adapter> <program> [args...]".to_string()) };
+    let cwd = ctx.cwd();
+    let adapter = Adapter::start(adapter_command, &cwd).map_err(|e| e.to_string())?;
+    let session = Session::new(ctx.breakpoints().clone());
+    let program = cwd.join(program);
+    let launch = json!({ "program": program, "args": program_args, "cwd": cwd });
+    let requests = session.start(adapter_command, launch);
+    let mut debugger = Debugger { session, adapter };
+    debugger.send(requests).map_err(|e| e.to_string())?;
+    *ctx.debugger() = Some(debugger);
+    ctx.open_debug_panes();
+    Ok(())
+}
+
+fn breakpoint_command<Ctx: DebugHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    let (path, line) = ctx.cursor_location().ok_or("No file to set a breakpoint in")?;
+    let lines = ctx.breakpoints().entry(path.clone()).or_default();
+    if !lines.remove(&line) {
+        lines.insert(line);
+    }
+    if let Some(debugger) = ctx.debugger() {
+        // Toggled on the session too, so it has the same breakpoints
+        if let Some(request) = debugger.session.toggle_breakpoint(&path, line) {
+            debugger.send(vec![request]).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+fn step<Ctx: DebugHost>(ctx: &mut Ctx, step: Step) -> Result<(), String> {
+    let debugger = ctx.debugger().as_mut().ok_or("Not debugging")?;
+    let request = debugger.session.step(step).ok_or("The program isn't stopped")?;
+    debugger.send(vec![request]).map_err(|e| e.to_string())
+}
+
+fn continue_command<Ctx: DebugHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    step(ctx, Step::Continue)
+}
+
+fn next_command<Ctx: DebugHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    step(ctx, Step::Over)
+}
+
+fn step_in_command<Ctx: DebugHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    step(ctx, Step::In)
+}
+
+fn step_out_command<Ctx: DebugHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    step(ctx, Step::Out)
+}
+
+fn debug_stop_command<Ctx: DebugHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    let mut debugger = ctx.debugger().take().ok_or("Not debugging")?;
+    let stop = debugger.session.stop();
+    // The adapter is killed when dropped anyway
+    let _ = debugger.send(vec![stop]);
+    Ok(())
+}
+
+pub fn register<Ctx: DebugHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("debug", debug_command::<Ctx>);
+    registry.add_builtin("breakpoint", breakpoint_command::<Ctx>);
+    registry.add_builtin("continue", continue_command::<Ctx>);
+    registry.add_builtin("next", next_command::<Ctx>);
+    registry.add_builtin("step-in", step_in_command::<Ctx>);
+    registry.add_builtin("step-out", step_out_command::<Ctx>);
+    registry.add_builtin("debug-stop", debug_stop_command::<Ctx>);
+    registry.bind("<F5>", "continue");
+    registry.bind("<F9>", "breakpoint");
+    registry.bind("<F10>", "next");
+    registry.bind("<F11>", "step-in");
+    registry.bind("<S-F11>", "step-out");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn framing_round_trips() {
+        let messages = [json!({ "seq": 1, "type": "event", "event": "stopped" }), json!({ "text": "é\r\n" })];
+        let bytes: Vec<u8> = messages.iter().flat_map(encode).collect();
+        let mut reader = std::io::Cursor::new(bytes);
+        assert_eq!(read_message(&mut reader).unwrap(), Some(messages[0].clone()));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(messages[1].clone()));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+        assert!(read_message(&mut std::io::Cursor::new(b"Content-Length: 5\r\n\r\n{nope")).is_err());
+    }
+
+    #[test]
+    fn adapter_talks_over_pipes() {
+        // cat sends every request straight back, which is enough to check both directions
+        let mut adapter = Adapter::start("cat", &std::env::temp_dir()).unwrap();
+        adapter.send(&request("threads", json!({}))).unwrap();
+        let start = std::time::Instant::now();
+        let mut received = Vec::new();
+        while received.is_empty() && start.elapsed() < std::time::Duration::from_secs(5) {
+            received = adapter.poll();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let message = received.pop().unwrap().unwrap();
+        assert_eq!((message["command"].as_str(), message["seq"].as_u64()), (Some("threads"), Some(1)));
+    }
+
+    #[test]
+    fn stopping_fetches_stack_and_variables() {
+        let main = PathBuf::from("/src/main.rs");
+        let mut session = Session::new(BTreeMap::from([(main.clone(), BTreeSet::from([3]))]));
+        assert_eq!(session.toggle_breakpoint(&main, 7), None, "not ready for breakpoints yet");
+
+        let requests = session.handle(&json!({ "type": "event", "event": "initialized" }));
+        let commands: Vec<&str> = requests.iter().map(|request| request.command.as_str()).collect();
+        assert_eq!(commands, vec!["setBreakpoints", "configurationDone"]);
+        assert_eq!(requests[0].arguments["breakpoints"], json!([{ "line": 3 }, { "line": 7 }]));
+        assert_eq!(session.step(Step::Over), None);
+
+        let requests = session.handle(&json!({ "type": "event", "event": "stopped", "body": { "threadId": 4, "reason": "breakpoint" } }));
+        assert_eq!(requests, vec![request("stackTrace", json!({ "threadId": 4 }))]);
+        let requests = session.handle(&json!({ "type": "response", "success": true, "command": "stackTrace", "body": { "stackFrames": [
+            { "id": 10, "name": "main::run", "source": { "path": "/src/main.rs" }, "line": 7 },
+            { "id": 11, "name": "main", "line": 2 },
+        ] } }));
+        assert_eq!(requests, vec![request("scopes", json!({ "frameId": 10 }))]);
+        let requests = session.handle(&json!({ "type": "response", "success": true, "command": "scopes", "body": { "scopes": [
+            { "name": "Globals", "variablesReference": 1, "expensive": true },
+            { "name": "Locals", "variablesReference": 2 },
+        ] } }));
+        assert_eq!(requests, vec![request("variables", json!({ "variablesReference": 2 }))]);
+        session.handle(&json!({ "type": "response", "success": true, "command": "variables", "body": { "variables": [
+            { "name": "x", "value": "5", "type": "i32" },
+        ] } }));
+
+        assert_eq!(session.stopped_at(), Some((main.as_path(), 7)));
+        assert_eq!(session.gutter(&main), BTreeMap::from([(3, GutterMark::Breakpoint), (7, GutterMark::Stopped { on_breakpoint: true })]));
+        assert_eq!(session.stack_lines(), vec!["main::run  /src/main.rs:7", "main"]);
+        assert_eq!(session.variable_lines(), vec!["x: i32 = 5"]);
+        assert_eq!(session.step(Step::Over), Some(request("next", json!({ "threadId": 4 }))));
+
+        session.handle(&json!({ "type": "event", "event": "continued", "body": { "threadId": 4 } }));
+        assert_eq!(session.stopped_at(), None);
+        session.handle(&json!({ "type": "response", "success": false, "command": "next", "message": "not stopped" }));
+        assert_eq!(session.errors, vec!["next: not stopped"]);
+    }
+}
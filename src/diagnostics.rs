@@ -0,0 +1,222 @@
+// Diagnostics from language servers, per file. Besides underlining their ranges, the first one on
+// each line is shown dimmed after the end of the line, and :diagnostics lists them all in a buffer
+// whose lines can be jumped to like job output
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::commands::Registry;
+use crate::layout::VirtualText;
+use crate::marks::shift_through_edit;
+use crate::notifications::Severity;
+use crate::selection::line_starts;
+
+// Space between the end of the line and its diagnostic
+const VIRTUAL_TEXT_GAP: &str = "    ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub range: Range<usize>,
+    pub severity: Severity,
+    pub message: String,
+    // Like "rustc" or "clippy"
+    pub source: Option<String>,
+}
+
+// From the numbers LSP uses. Hints are shown like information
+pub fn severity_from_lsp(severity: u32) -> Severity {
+    match severity {
+        1 => Severity::Error,
+        2 => Severity::Warning,
+        _ => Severity::Info,
+    }
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "info",
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    // Sorted by start
+    by_path: BTreeMap<PathBuf, Vec<Diagnostic>>,
+}
+
+impl Diagnostics {
+    // Replaces the diagnostics of `path`, as each publishDiagnostics does
+    pub fn set(&mut self, path: &Path, mut diagnostics: Vec<Diagnostic>) {
+        if diagnostics.is_empty() {
+            self.by_path.remove(path);
+            return;
+        }
+        diagnostics.sort_by_key(|diagnostic| diagnostic.range.start);
+        self.by_path.insert(path.to_owned(), diagnostics);
+    }
+
+    pub fn get(&self, path: &Path) -> &[Diagnostic] {
+        self.by_path.get(path).map_or(&[], Vec::as_slice)
+    }
+
+    // Must be called for every edit, so diagnostics stay on their text until the server sends new ones
+    pub fn apply_edit(&mut self, path: &Path, replaced: Range<usize>, new_len: usize) {
+        for diagnostic in self.by_path.get_mut(path).into_iter().flatten() {
+            diagnostic.range = shift_through_edit(diagnostic.range.start, &replaced, new_len)..shift_through_edit(diagnostic.range.end, &replaced, new_len);
+        }
+    }
+
+    pub fn underlines(&self, path: &Path) -> Vec<(Range<usize>, Severity)> {
+        self.get(path).iter().map(|diagnostic| (diagnostic.range.clone(), diagnostic.severity)).collect()
+    }
+
+    // The first line of the first diagnostic on each line of `text`, after the end of the line
+    pub fn virtual_text(&self, path: &Path, text: &str) -> Vec<(VirtualText, Severity)> {
+        let starts = line_starts(text);
+        let mut shown: Vec<(VirtualText, Severity)> = Vec::new();
+        let mut last_line = None;
+        for diagnostic in self.get(path) {
+            let line = starts.partition_point(|&start| start <= diagnostic.range.start.min(text.len())) - 1;
+            if last_line == Some(line) {
+                continue;
+            }
+            last_line = Some(line);
+            let line_end = starts.get(line + 1).map_or(text.len(), |&next| next - 1);
+            let message = diagnostic.message.lines().next().unwrap_or_default();
+            shown.push((VirtualText { at: line_end, text: format!("{VIRTUAL_TEXT_GAP}{message}") }, diagnostic.severity));
+        }
+        shown
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    path: PathBuf,
+    // 1-based, like job output
+    line: usize,
+    col: usize,
+    severity: Severity,
+    message: String,
+}
+
+// The contents of the :diagnostics buffer
+#[derive(Debug, Clone)]
+pub struct DiagnosticList {
+    pub min_severity: Severity,
+    entries: Vec<Entry>,
+}
+
+impl DiagnosticList {
+    // Diagnostics at least as severe as `min_severity`, worst first. `text_of` gives the text of
+    // the buffer for a path, to turn offsets into lines and columns
+    pub fn new(diagnostics: &Diagnostics, min_severity: Severity, text_of: impl Fn(&Path) -> Option<String>) -> DiagnosticList {
+        let mut entries = Vec::new();
+        for (path, in_file) in &diagnostics.by_path {
+            let text = text_of(path).unwrap_or_default();
+            let starts = line_starts(&text);
+            for diagnostic in in_file.iter().filter(|diagnostic| diagnostic.severity >= min_severity) {
+                let start = diagnostic.range.start.min(text.len());
+                let line = starts.partition_point(|&line_start| line_start <= start) - 1;
+                let col = text.get(starts[line]..start).map_or(0, |before| before.chars().count()) + 1;
+                let message = match &diagnostic.source {
+                    Some(source) => format!("{}: {} [{source}]", severity_name(diagnostic.severity), diagnostic.message),
+                    None => format!("{}: {}", severity_name(diagnostic.severity), diagnostic.message),
+                };
+                entries.push(Entry { path: path.clone(), line: line + 1, col, severity: diagnostic.severity, message: message.replace('\n', " ") });
+            }
+        }
+        // Stable, so each severity stays sorted by path and position
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.severity));
+        DiagnosticList { min_severity, entries }
+    }
+
+    // One `path:line:col: severity: message` line per diagnostic
+    pub fn text(&self) -> String {
+        self.entries.iter().map(|entry| format!("{}:{}:{}: {}\n", entry.path.display(), entry.line, entry.col, entry.message)).collect()
+    }
+
+    // Where the diagnostic on `line` of the list points, to jump to with Enter
+    pub fn location(&self, line: usize) -> Option<(PathBuf, usize, usize)> {
+        let entry = self.entries.get(line)?;
+        Some((entry.path.clone(), entry.line, entry.col))
+    }
+}
+
+// What :diagnostics needs from the editor
+pub trait DiagnosticsHost {
+    fn diagnostics(&self) -> &Diagnostics;
+    fn buffer_text(&self, path: &Path) -> Option<String>;
+    // Shows the list in a buffer of its own
+    fn open_list(&mut self, list: DiagnosticList);
+}
+
+// :diagnostics [error|warning|info], listing diagnostics at least that severe
+fn diagnostics_command<Ctx: DiagnosticsHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let min_severity = match args {
+        [] | ["info"] => Severity::Info,
+        ["warning"] => Severity::Warning,
+        ["error"] => Severity::Error,
+        _ => return Err("Usage: diagnostics [error|warning|info]".to_string()),
+    };
+    let list = DiagnosticList::new(ctx.diagnostics(), min_severity, |path| ctx.buffer_text(path));
+    ctx.open_list(list);
+    Ok(())
+}
+
+pub fn register<Ctx: DiagnosticsHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("diagnostics", diagnostics_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::parse_location;
+
+    fn diagnostic(range: Range<usize>, severity: Severity, message: &str) -> Diagnostic {
+        Diagnostic { range, severity, message: message.to_string(), source: None }
+    }
+
+    #[test]
+    fn first_diagnostic_of_each_line_after_its_end() {
+        let text = "let x = y;\nlet é = z;";
+        let path = Path::new("src/main.rs");
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.set(path, vec![
+            diagnostic(20..21, Severity::Error, "cannot find value `z`\nhelp: a local variable exists"),
+            diagnostic(4..5, Severity::Warning, "unused variable `x`"),
+            diagnostic(8..9, Severity::Error, "cannot find value `y`"),
+        ]);
+        let shown = diagnostics.virtual_text(path, text);
+        assert_eq!(shown, vec![
+            (VirtualText { at: 10, text: "    unused variable `x`".to_string() }, Severity::Warning),
+            (VirtualText { at: text.len(), text: "    cannot find value `z`".to_string() }, Severity::Error),
+        ]);
+
+        // Typing before the diagnostics moves them along
+        diagnostics.apply_edit(path, 0..0, 4);
+        assert_eq!(diagnostics.underlines(path)[0], (8..9, Severity::Warning));
+        diagnostics.set(path, Vec::new());
+        assert!(diagnostics.get(path).is_empty());
+    }
+
+    #[test]
+    fn list_filters_and_jumps() {
+        let mut diagnostics = Diagnostics::default();
+        let (a, b) = (PathBuf::from("a.rs"), PathBuf::from("b.rs"));
+        diagnostics.set(&a, vec![diagnostic(0..1, Severity::Warning, "unused"), Diagnostic { source: Some("rustc".to_string()), ..diagnostic(6..7, Severity::Error, "mismatched\ntypes") }]);
+        diagnostics.set(&b, vec![diagnostic(0..1, Severity::Info, "consider this")]);
+        let text_of = |path: &Path| Some(if path == a { "fn a\n  é x".to_string() } else { String::new() });
+
+        let list = DiagnosticList::new(&diagnostics, Severity::Info, text_of);
+        assert_eq!(list.text(), "a.rs:2:2: error: mismatched types [rustc]\na.rs:1:1: warning: unused\nb.rs:1:1: info: consider this\n");
+        assert_eq!(list.location(0), Some((a.clone(), 2, 2)));
+        assert_eq!(list.text().lines().map(parse_location).collect::<Vec<_>>(), (0..3).map(|line| list.location(line)).collect::<Vec<_>>());
+
+        let errors = DiagnosticList::new(&diagnostics, Severity::Error, text_of);
+        assert_eq!(errors.text().lines().count(), 1);
+        assert_eq!(errors.location(1), None);
+    }
+}
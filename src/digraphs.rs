@@ -0,0 +1,201 @@
+// Typing characters the keyboard doesn't have, in insert mode. Ctrl+K then two characters types a
+// digraph, like `a:` for ä, the same ones as vim's (RFC 1345). Ctrl+V then `u{XXXX}` types the code
+// point in hex, and Ctrl+V then anything else types it as is. The picker finds characters by name.
+// What comes out goes through insert::insert like any typed text
+
+use crate::picker::Picker;
+
+const ESCAPE: char = '\u{1b}';
+
+// Digraph, character and its Unicode name
+pub const DIGRAPHS: &[(&str, char, &str)] = &[
+    ("a:", 'ä', "latin small letter a with diaeresis"),
+    ("o:", 'ö', "latin small letter o with diaeresis"),
+    ("u:", 'ü', "latin small letter u with diaeresis"),
+    ("A:", 'Ä', "latin capital letter a with diaeresis"),
+    ("O:", 'Ö', "latin capital letter o with diaeresis"),
+    ("U:", 'Ü', "latin capital letter u with diaeresis"),
+    ("ss", 'ß', "latin small letter sharp s"),
+    ("a'", 'á', "latin small letter a with acute"),
+    ("e'", 'é', "latin small letter e with acute"),
+    ("i'", 'í', "latin small letter i with acute"),
+    ("o'", 'ó', "latin small letter o with acute"),
+    ("e!", 'è', "latin small letter e with grave"),
+    ("a!", 'à', "latin small letter a with grave"),
+    ("e>", 'ê', "latin small letter e with circumflex"),
+    ("n?", 'ñ', "latin small letter n with tilde"),
+    ("c,", 'ç', "latin small letter c with cedilla"),
+    ("aa", 'å', "latin small letter a with ring above"),
+    ("o/", 'ø', "latin small letter o with stroke"),
+    ("ae", 'æ', "latin small letter ae"),
+    ("l/", 'ł', "latin small letter l with stroke"),
+    ("z.", 'ż', "latin small letter z with dot above"),
+    ("a*", 'α', "greek small letter alpha"),
+    ("b*", 'β', "greek small letter beta"),
+    ("g*", 'γ', "greek small letter gamma"),
+    ("d*", 'δ', "greek small letter delta"),
+    ("l*", 'λ', "greek small letter lamda"),
+    ("m*", 'μ', "greek small letter mu"),
+    ("p*", 'π', "greek small letter pi"),
+    ("s*", 'σ', "greek small letter sigma"),
+    ("S*", 'Σ', "greek capital letter sigma"),
+    ("W*", 'Ω', "greek capital letter omega"),
+    ("->", '→', "rightwards arrow"),
+    ("<-", '←', "leftwards arrow"),
+    ("=>", '⇒', "rightwards double arrow"),
+    ("!=", '≠', "not equal to"),
+    ("=<", '≤', "less-than or equal to"),
+    (">=", '≥', "greater-than or equal to"),
+    ("+-", '±', "plus-minus sign"),
+    ("*X", '×', "multiplication sign"),
+    ("-:", '÷', "division sign"),
+    ("00", '∞', "infinity"),
+    ("FA", '∀', "for all"),
+    ("TE", '∃', "there exists"),
+    ("(-", '∈', "element of"),
+    ("DG", '°', "degree sign"),
+    ("Eu", '€', "euro sign"),
+    ("Pd", '£', "pound sign"),
+    ("Co", '©', "copyright sign"),
+    ("-N", '–', "en dash"),
+    ("-M", '—', "em dash"),
+    ("..", '‥', "two dot leader"),
+    (",.", '…', "horizontal ellipsis"),
+    ("OK", '✓', "check mark"),
+    ("XX", '✗', "ballot x"),
+    ("NS", '\u{a0}', "no-break space"),
+];
+
+// Either order works, like in vim, so `:a` is ä too
+pub fn digraph(first: char, second: char) -> Option<char> {
+    let find = |pair: [char; 2]| DIGRAPHS.iter().find(|(digraph, _, _)| digraph.chars().eq(pair)).map(|&(_, ch, _)| ch);
+    find([first, second]).or_else(|| find([second, first]))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pending {
+    // After Ctrl+K, with the first character once typed
+    Digraph(Option<char>),
+    // After Ctrl+V, with what's typed of `u{XXXX}`
+    Literal(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entered {
+    // Waiting for more keys
+    Pending,
+    Insert(char),
+    // Not a digraph or code point, or cancelled with Escape. Nothing is typed
+    Nothing,
+}
+
+// Keys typed after Ctrl+K or Ctrl+V, which go here instead of into the buffer until a character comes out
+#[derive(Debug, Default)]
+pub struct Entry {
+    pending: Option<Pending>,
+}
+
+impl Entry {
+    // Ctrl+K
+    pub fn start_digraph(&mut self) {
+        self.pending = Some(Pending::Digraph(None));
+    }
+
+    // Ctrl+V
+    pub fn start_literal(&mut self) {
+        self.pending = Some(Pending::Literal(String::new()));
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // What's typed so far, for the status line, like `^K a` or `^V u{1F4`
+    pub fn shown(&self) -> Option<String> {
+        Some(match self.pending.as_ref()? {
+            Pending::Digraph(first) => format!("^K {}", first.map(String::from).unwrap_or_default()),
+            Pending::Literal(typed) => format!("^V {typed}"),
+        })
+    }
+
+    pub fn key(&mut self, key: char) -> Entered {
+        let Some(pending) = self.pending.take() else {
+            return Entered::Nothing;
+        };
+        if key == ESCAPE {
+            return Entered::Nothing;
+        }
+        match pending {
+            Pending::Digraph(None) => {
+                self.pending = Some(Pending::Digraph(Some(key)));
+                Entered::Pending
+            }
+            Pending::Digraph(Some(first)) => digraph(first, key).map_or(Entered::Nothing, Entered::Insert),
+            // Anything but `u` is typed as is, like a Tab where Tab would indent
+            Pending::Literal(typed) if typed.is_empty() && key != 'u' => Entered::Insert(key),
+            Pending::Literal(mut typed) => {
+                typed.push(key);
+                let Some(hex) = typed.strip_prefix("u{").and_then(|rest| rest.strip_suffix('}')) else {
+                    let valid = typed == "u" || typed == "u{" || (typed.len() <= 8 && typed.strip_prefix("u{").is_some_and(|hex| hex.chars().all(|c| c.is_ascii_hexdigit())));
+                    if valid {
+                        self.pending = Some(Pending::Literal(typed));
+                    }
+                    return if valid { Entered::Pending } else { Entered::Nothing };
+                };
+                u32::from_str_radix(hex, 16).ok().and_then(char::from_u32).map_or(Entered::Nothing, Entered::Insert)
+            }
+        }
+    }
+}
+
+// Picks a character by its name or digraph, like `arrow` or `->`. Committing gives the character to type
+pub fn picker() -> Picker<Option<char>> {
+    let items = DIGRAPHS.iter().map(|&(digraph, ch, name)| (format!("{ch}  {name}  {digraph}"), Some(ch))).collect();
+    Picker::new(items, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::insert::insert;
+    use crate::picker::PickerEvent;
+    use crate::selection::Selection;
+
+    fn type_keys(entry: &mut Entry, keys: &str) -> Vec<Entered> {
+        keys.chars().map(|key| entry.key(key)).collect()
+    }
+
+    #[test]
+    fn digraphs_and_code_points() {
+        let mut entry = Entry::default();
+        entry.start_digraph();
+        assert_eq!(type_keys(&mut entry, "a"), vec![Entered::Pending]);
+        assert_eq!(entry.shown().as_deref(), Some("^K a"));
+        assert_eq!(entry.key(':'), Entered::Insert('ä'));
+        assert!(!entry.is_pending());
+        entry.start_digraph();
+        assert_eq!(type_keys(&mut entry, ">-"), vec![Entered::Pending, Entered::Insert('→')]);
+        entry.start_digraph();
+        assert_eq!(type_keys(&mut entry, "qq"), vec![Entered::Pending, Entered::Nothing]);
+
+        entry.start_literal();
+        assert_eq!(type_keys(&mut entry, "u{1F414}").last(), Some(&Entered::Insert('🐔')));
+        entry.start_literal();
+        assert_eq!(type_keys(&mut entry, "u{d800}").last(), Some(&Entered::Nothing));
+        entry.start_literal();
+        assert_eq!(type_keys(&mut entry, "ux"), vec![Entered::Pending, Entered::Nothing]);
+        entry.start_literal();
+        assert_eq!(entry.key('\t'), Entered::Insert('\t'));
+        entry.start_literal();
+        assert_eq!(type_keys(&mut entry, "u{"), vec![Entered::Pending, Entered::Pending]);
+        assert_eq!(entry.key(ESCAPE), Entered::Nothing);
+        assert!(!entry.is_pending());
+
+        let mut picker = picker();
+        picker.set_filter("right arrow");
+        let PickerEvent::Commit(Some(ch)) = picker.enter() else { panic!() };
+        let (mut text, mut selections) = ("a  b".to_string(), vec![Selection::cursor(2)]);
+        insert(&mut text, &mut selections, &ch.to_string());
+        assert_eq!(text, "a → b");
+    }
+}
@@ -0,0 +1,421 @@
+// Opening a directory shows a buffer listing it, one entry per line with directories first and
+// ending in '/'. Enter on a line opens the file or descends into the directory, and "../" on the
+// first line goes up. Entries are created, renamed and deleted with commands, and deleting asks
+// first
+//
+// Listings go through backend::Backends, so directories inside archives or in memory are browsed
+// the same way, and those that can't be changed refuse to be. Local directories are listed again
+// when their modification time changes, which is checked every POLL
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::backend::{Backends, Entry, Error};
+use crate::commands::{quote, Registry};
+
+const PARENT: &str = "../";
+pub const POLL: Duration = Duration::from_secs(1);
+
+// Keys in directory buffers, with the command lines they run
+pub const KEYS: &[(&str, &str)] = &[("<Enter>", "dir-open"), ("-", "dir-up"), ("%", "dir-create"), ("R", "dir-rename"), ("D", "dir-delete"), ("g", "dir-refresh")];
+
+// Joins `name` onto the directory `dir`, which may end in a scheme or archive separator
+pub fn join(dir: &str, name: &str) -> String {
+    match dir.is_empty() || dir.ends_with(['/', ':', '!']) {
+        true => format!("{dir}{name}"),
+        false => format!("{dir}/{name}"),
+    }
+}
+
+// The directory `dir` is in and the name of `dir` in it, or None at the top. Colons only count
+// when there's no '/' or '!', as they may be in file names
+fn split_last(dir: &str) -> Option<(String, &str)> {
+    let dir = if dir.len() > 1 { dir.strip_suffix('/').unwrap_or(dir) } else { dir };
+    let cut = dir.rfind(['/', '!']).or_else(|| dir.find(':'))?;
+    let name = &dir[cut + 1..];
+    if name.is_empty() {
+        return None;
+    }
+    let parent = match &dir[cut..=cut] {
+        "/" if cut == 0 => "/",
+        "/" => &dir[..cut],
+        _ => &dir[..=cut],
+    };
+    Some((parent.to_string(), name))
+}
+
+pub fn parent(dir: &str) -> Option<String> {
+    split_last(dir).map(|(parent, _)| parent)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Opened {
+    // Descended into it, so the buffer changed
+    Dir,
+    File(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct DirBuffer {
+    pub dir: String,
+    entries: Vec<Entry>,
+    // Of watched_path, when the entries were listed
+    modified: Option<SystemTime>,
+}
+
+impl DirBuffer {
+    pub fn new(backends: &Backends, dir: &str) -> Result<DirBuffer, Error> {
+        // Local directories by their full path, so going up works from "."
+        let dir = match backends.local_path(dir).and_then(|path| path.canonicalize().ok()) {
+            Some(path) => path.display().to_string(),
+            None => dir.to_string(),
+        };
+        let mut buffer = DirBuffer { dir, entries: Vec::new(), modified: None };
+        buffer.refresh(backends)?;
+        Ok(buffer)
+    }
+
+    // Lists the directory again, returning whether anything changed. Called when changed_on_disk
+    // says so, or on g
+    pub fn refresh(&mut self, backends: &Backends) -> Result<bool, Error> {
+        self.modified = self.modified_on_disk(backends);
+        let mut entries = backends.list(&self.dir)?;
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+        let changed = entries != self.entries;
+        self.entries = entries;
+        Ok(changed)
+    }
+
+    // What to check for changes, for directories on the local disk
+    pub fn watched_path(&self, backends: &Backends) -> Option<PathBuf> {
+        backends.local_path(&self.dir)
+    }
+
+    fn modified_on_disk(&self, backends: &Backends) -> Option<SystemTime> {
+        std::fs::metadata(self.watched_path(backends)?).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    // Whether entries were added, removed or renamed since the last refresh
+    pub fn changed_on_disk(&self, backends: &Backends) -> bool {
+        self.watched_path(backends).is_some() && self.modified_on_disk(backends) != self.modified
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = format!("{PARENT}\n");
+        for entry in &self.entries {
+            text.push_str(&entry.name);
+            text.push_str(if entry.is_dir { "/\n" } else { "\n" });
+        }
+        text
+    }
+
+    // The entry on `line` of the text, None for the parent line
+    pub fn entry(&self, line: usize) -> Option<&Entry> {
+        self.entries.get(line.checked_sub(1)?)
+    }
+
+    // The line of the entry called `name`, to put the cursor on after going up or renaming
+    pub fn line_of(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name == name).map(|idx| idx + 1)
+    }
+
+    pub fn open(&mut self, backends: &Backends, line: usize) -> Result<Opened, Error> {
+        match self.entry(line) {
+            None => self.up(backends).map(|_| Opened::Dir),
+            Some(entry) if entry.is_dir => {
+                let dir = join(&self.dir, &entry.name);
+                self.change_dir(backends, dir)?;
+                Ok(Opened::Dir)
+            }
+            Some(entry) => Ok(Opened::File(join(&self.dir, &entry.name))),
+        }
+    }
+
+    // Goes to the parent directory, returning the line of the one we were in
+    pub fn up(&mut self, backends: &Backends) -> Result<Option<usize>, Error> {
+        let Some((parent, left)) = split_last(&self.dir) else { return Ok(None) };
+        let left = left.to_string();
+        self.change_dir(backends, parent)?;
+        Ok(self.line_of(&left))
+    }
+
+    fn change_dir(&mut self, backends: &Backends, dir: String) -> Result<(), Error> {
+        let old = std::mem::replace(&mut self.dir, dir);
+        // Left where it was if the new one can't be listed
+        if let Err(err) = self.refresh(backends) {
+            self.dir = old;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    // Names ending in '/' are created as directories, others as empty files. Returns the new line
+    pub fn create(&mut self, backends: &mut Backends, name: &str) -> Result<Option<usize>, Error> {
+        let path = join(&self.dir, name.trim_end_matches('/'));
+        match name.ends_with('/') {
+            true => backends.create_dir(&path)?,
+            false => backends.write(&path, b"")?,
+        }
+        self.refresh(backends)?;
+        Ok(self.line_of(name.trim_end_matches('/').split('/').next().unwrap_or_default()))
+    }
+
+    pub fn rename(&mut self, backends: &mut Backends, line: usize, new_name: &str) -> Result<Option<usize>, Error> {
+        let entry = self.entry(line).ok_or_else(|| Error::ReadOnly(PARENT.to_string()))?;
+        backends.rename(&join(&self.dir, &entry.name), &join(&self.dir, new_name))?;
+        self.refresh(backends)?;
+        Ok(self.line_of(new_name))
+    }
+
+    // The entry on `line` as a path, ending in '/' for directories, to say what to delete
+    pub fn target(&self, line: usize) -> Option<String> {
+        let entry = self.entry(line)?;
+        Some(join(&self.dir, &entry.name) + if entry.is_dir { "/" } else { "" })
+    }
+
+    // Deletes `target` from Self::target, after checking it's still listed here as the same kind
+    // of entry, so a listing that changed since asking doesn't delete something else
+    pub fn delete(&mut self, backends: &mut Backends, target: &str) -> Result<(), String> {
+        self.refresh(backends).map_err(|e| e.to_string())?;
+        let line = (1..=self.entries.len()).find(|&line| self.target(line).as_deref() == Some(target)).ok_or_else(|| format!("{target} isn't in this listing anymore"))?;
+        let entry = self.entry(line).unwrap();
+        backends.remove(&join(&self.dir, &entry.name)).map_err(|e| e.to_string())?;
+        self.refresh(backends).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+// What the directory commands need from the editor
+pub trait DirHost {
+    fn backends(&mut self) -> &mut Backends;
+    // The directory buffer that's focused, if it is one, and the line of the cursor in it
+    fn dir_buffer(&mut self) -> Option<(&mut DirBuffer, usize)>;
+    // Shows `buffer` in place of the focused buffer, with the cursor on `line`
+    fn show_dir(&mut self, buffer: DirBuffer, line: usize);
+    fn open_file(&mut self, name: &str) -> Result<(), String>;
+    // Opens the ':' prompt with `line` already typed
+    fn open_prompt(&mut self, line: &str);
+    // Asks `question`, running `command_line` if the answer is yes
+    fn confirm(&mut self, question: &str, command_line: &str);
+}
+
+// Takes the focused directory buffer out so the backends can be borrowed next to it, and shows
+// it again with the cursor where `run` says
+fn with_dir<Ctx: DirHost>(ctx: &mut Ctx, run: impl FnOnce(&mut DirBuffer, &mut Backends, usize) -> Result<Option<usize>, Error>) -> Result<(), String> {
+    let (buffer, line) = ctx.dir_buffer().ok_or("Not in a directory listing")?;
+    let mut buffer = buffer.clone();
+    let result = run(&mut buffer, ctx.backends(), line);
+    let new_line = result.as_ref().ok().copied().flatten().unwrap_or(line);
+    ctx.show_dir(buffer, new_line);
+    result.map(drop).map_err(|e| e.to_string())
+}
+
+// :browse [dir], listing the current directory without one
+fn browse_command<Ctx: DirHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let dir = args.first().copied().unwrap_or(".");
+    let buffer = DirBuffer::new(ctx.backends(), dir).map_err(|e| e.to_string())?;
+    ctx.show_dir(buffer, 0);
+    Ok(())
+}
+
+fn open_command<Ctx: DirHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    let (buffer, line) = ctx.dir_buffer().ok_or("Not in a directory listing")?;
+    if let Some(entry) = buffer.entry(line).filter(|entry| !entry.is_dir) {
+        let name = join(&buffer.dir, &entry.name);
+        return ctx.open_file(&name);
+    }
+    with_dir(ctx, |buffer, backends, line| match line {
+        0 => buffer.up(backends),
+        _ => buffer.open(backends, line).map(|_| Some(0)),
+    })
+}
+
+fn up_command<Ctx: DirHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    with_dir(ctx, |buffer, backends, _| buffer.up(backends))
+}
+
+fn refresh_command<Ctx: DirHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    with_dir(ctx, |buffer, backends, _| buffer.refresh(backends).map(|_| None))
+}
+
+// :dir-create <name>, a directory if it ends in '/'. Without a name it's asked for. Names with
+// spaces can be quoted, or typed as they are
+fn create_command<Ctx: DirHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    if args.is_empty() {
+        ctx.open_prompt("dir-create ");
+        return Ok(());
+    }
+    let name = args.join(" ");
+    with_dir(ctx, |buffer, backends, _| buffer.create(backends, &name))
+}
+
+// :dir-rename <new name>, for the entry under the cursor
+fn rename_command<Ctx: DirHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    if args.is_empty() {
+        let (buffer, line) = ctx.dir_buffer().ok_or("Not in a directory listing")?;
+        let name = buffer.entry(line).ok_or("Nothing to rename under the cursor")?.name.clone();
+        ctx.open_prompt(&format!("dir-rename {}", quote(&name)));
+        return Ok(());
+    }
+    let new_name = args.join(" ");
+    with_dir(ctx, |buffer, backends, line| buffer.rename(backends, line, &new_name))
+}
+
+// :dir-delete asks before deleting the entry under the cursor. Answering yes runs
+// :dir-delete --confirmed <path>, which deletes that path only if it's still listed
+fn delete_command<Ctx: DirHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    match args {
+        [] => {
+            let (buffer, line) = ctx.dir_buffer().ok_or("Not in a directory listing")?;
+            let entry = buffer.entry(line).ok_or("Nothing to delete under the cursor")?;
+            let question = match entry.is_dir {
+                true => format!("Delete {}/ and everything in it?", entry.name),
+                false => format!("Delete {}?", entry.name),
+            };
+            let target = buffer.target(line).unwrap();
+            ctx.confirm(&question, &format!("dir-delete --confirmed {}", quote(&target)));
+            Ok(())
+        }
+        ["--confirmed", target] => {
+            let (buffer, line) = ctx.dir_buffer().ok_or("Not in a directory listing")?;
+            let mut buffer = buffer.clone();
+            let result = buffer.delete(ctx.backends(), target);
+            ctx.show_dir(buffer, line);
+            result
+        }
+        _ => Err("Usage: dir-delete".to_string()),
+    }
+}
+
+pub fn register<Ctx: DirHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("browse", browse_command::<Ctx>);
+    registry.add_builtin("dir-open", open_command::<Ctx>);
+    registry.add_builtin("dir-up", up_command::<Ctx>);
+    registry.add_builtin("dir-refresh", refresh_command::<Ctx>);
+    registry.add_builtin("dir-create", create_command::<Ctx>);
+    registry.add_builtin("dir-rename", rename_command::<Ctx>);
+    registry.add_builtin("dir-delete", delete_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Editor {
+        backends: Backends,
+        shown: Option<(DirBuffer, usize)>,
+        opened: Vec<String>,
+        question: Option<(String, String)>,
+    }
+
+    impl DirHost for Editor {
+        fn backends(&mut self) -> &mut Backends {
+            &mut self.backends
+        }
+
+        fn dir_buffer(&mut self) -> Option<(&mut DirBuffer, usize)> {
+            self.shown.as_mut().map(|(buffer, line)| (buffer, *line))
+        }
+
+        fn show_dir(&mut self, buffer: DirBuffer, line: usize) {
+            self.shown = Some((buffer, line));
+        }
+
+        fn open_file(&mut self, name: &str) -> Result<(), String> {
+            self.opened.push(name.to_string());
+            Ok(())
+        }
+
+        fn open_prompt(&mut self, _line: &str) {}
+
+        fn confirm(&mut self, question: &str, command_line: &str) {
+            self.question = Some((question.to_string(), command_line.to_string()));
+        }
+    }
+
+    #[test]
+    fn joins_and_parents() {
+        assert_eq!(join("/home/me", "src"), "/home/me/src");
+        assert_eq!(join("tar:docs.tar!", "guide"), "tar:docs.tar!guide");
+        assert_eq!(parent("/home/me/"), Some("/home".to_string()));
+        assert_eq!(parent("/home"), Some("/".to_string()));
+        assert_eq!(parent("/"), None);
+        assert_eq!(parent("tar:docs.tar!guide/intro"), Some("tar:docs.tar!guide".to_string()));
+        assert_eq!(parent("tar:docs.tar!guide"), Some("tar:docs.tar!".to_string()));
+        assert_eq!(parent("tar:docs.tar!"), None);
+        assert_eq!(parent("mem:"), None);
+        assert_eq!(parent("mem:project"), Some("mem:".to_string()));
+        assert_eq!(parent("/tmp/notes:old"), Some("/tmp".to_string()));
+    }
+
+    #[test]
+    fn browses_and_changes_entries() {
+        let mut registry = Registry::<Editor>::default();
+        register(&mut registry);
+        let mut editor = Editor::default();
+        editor.backends.write("mem:project/src/main.rs", b"fn main() {}").unwrap();
+        editor.backends.write("mem:project/README.md", b"").unwrap();
+        registry.run(&mut editor, "browse mem:project").unwrap();
+        let text = |editor: &Editor| editor.shown.as_ref().unwrap().0.text();
+        assert_eq!(text(&editor), "../\nsrc/\nREADME.md\n");
+
+        // Into src and open main.rs
+        editor.shown.as_mut().unwrap().1 = 1;
+        registry.run(&mut editor, "dir-open").unwrap();
+        assert_eq!(text(&editor), "../\nmain.rs\n");
+        editor.shown.as_mut().unwrap().1 = 1;
+        registry.run(&mut editor, "dir-open").unwrap();
+        assert_eq!(editor.opened, vec!["mem:project/src/main.rs"]);
+
+        // Back up lands on src
+        registry.run(&mut editor, "dir-up").unwrap();
+        assert_eq!(editor.shown.as_ref().unwrap().1, 1);
+
+        registry.run(&mut editor, "dir-create docs/").unwrap();
+        registry.run(&mut editor, "dir-create notes.txt").unwrap();
+        assert_eq!(text(&editor), "../\ndocs/\nsrc/\nREADME.md\nnotes.txt\n");
+        assert_eq!(editor.shown.as_ref().unwrap().1, 4);
+        registry.run(&mut editor, "dir-rename my todo.txt").unwrap();
+        assert_eq!(text(&editor), "../\ndocs/\nsrc/\nREADME.md\nmy todo.txt\n");
+
+        // Deleting asks first
+        editor.shown.as_mut().unwrap().1 = 4;
+        registry.run(&mut editor, "dir-delete").unwrap();
+        let (question, command_line) = editor.question.clone().unwrap();
+        assert_eq!((question.as_str(), command_line.as_str()), ("Delete my todo.txt?", r#"dir-delete --confirmed "mem:project/my todo.txt""#));
+        // The cursor moving before the answer doesn't change what's deleted
+        editor.shown.as_mut().unwrap().1 = 2;
+        registry.run(&mut editor, &command_line).unwrap();
+        assert_eq!(text(&editor), "../\ndocs/\nsrc/\nREADME.md\n");
+        assert!(registry.run(&mut editor, &command_line).is_err());
+        assert!(registry.run(&mut editor, "dir-delete --confirmed").is_err());
+
+        registry.run(&mut editor, "dir-delete").unwrap();
+        let (question, command_line) = editor.question.clone().unwrap();
+        assert_eq!(question, "Delete src/ and everything in it?");
+        // Replaced by a file of the same name, which isn't what was asked about
+        editor.backends.remove("mem:project/src").unwrap();
+        editor.backends.write("mem:project/src", b"").unwrap();
+        assert!(registry.run(&mut editor, &command_line).is_err());
+        assert!(editor.backends.read("mem:project/src").is_ok());
+    }
+
+    #[test]
+    fn refreshes_local_directories() {
+        let dir = std::env::temp_dir().join(format!("rakoune-dired-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let backends = Backends::default();
+        let mut buffer = DirBuffer::new(&backends, &dir.join("sub/..").display().to_string()).unwrap();
+        assert_eq!(buffer.watched_path(&backends), Some(dir.canonicalize().unwrap()));
+        assert_eq!(buffer.text(), "../\nsub/\n");
+        assert!(!buffer.refresh(&backends).unwrap());
+        assert!(!buffer.changed_on_disk(&backends));
+        std::fs::write(dir.join("new.txt"), "").unwrap();
+        assert!(buffer.changed_on_disk(&backends));
+        assert!(buffer.refresh(&backends).unwrap());
+        assert!(!buffer.changed_on_disk(&backends));
+        assert_eq!(buffer.text(), "../\nsub/\nnew.txt\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
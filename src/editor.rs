@@ -0,0 +1,3902 @@
+// Everything being edited, apart from the window: the open buffers, the mode, the ':' and '/'
+// prompts and the questions waiting for an answer. App turns key events into Keys and hands them
+// over, and commands run through a Registry<Editor>, where the commands of every module end up.
+// The Host traits of those modules are implemented here, so their commands work on the buffers
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::time::{Instant, SystemTime};
+
+use serde_json::Value;
+
+use crate::appearance::{self, AppearanceHost};
+use crate::autosave::{self, AutoSaveHost};
+use crate::backend::{self, Backends};
+use crate::blame::{self, Blame, BlameHost};
+use crate::bracket_tree::{BracketTree, Languages};
+use crate::brackets::BracketDepths;
+use crate::clipboard::{self, Clipboard, ClipboardConfig};
+use crate::commands::Registry;
+use crate::completion::{self, Indexer, Menu};
+use crate::confirm::{self, ConfirmHost, Question, Questions};
+use crate::crash;
+use crate::dap::{self, DebugHost, Debugger, GutterMark, State};
+use crate::diagnostics::{self, DiagnosticList, Diagnostics, DiagnosticsHost};
+use crate::digraphs::{self, Entered, Entry};
+use crate::dired::{self, DirBuffer, DirHost};
+use crate::export::{self, Colors, ExportHost};
+use crate::file_preview::Previews;
+use crate::filetree::{self, FileTree, FileTreeHost, Icons};
+use crate::font::{self, FontStack};
+use crate::folding::{self, Fold, FoldHost, FoldState};
+use crate::format::{self, FormatHost};
+use crate::grammar::{self, normalize, Action, Step};
+use crate::highlighter::{self, Highlighting, Theme};
+use crate::hover::{self, HoverHost, Popups};
+use crate::insert;
+use crate::jobs::{self, Job, JobSpec, JobsHost, Location};
+use crate::keymap::{self, KeyEventLog, KeyEventsHost};
+use crate::layout::{LayoutSettings, LineEdit, VirtualText};
+use crate::lsp::{self, Event, Request, Server, WorkDone};
+use crate::markdown::{self, PreviewHost, PreviewPane};
+use crate::marks::{shift_through_edit, GlobalMarks, Marks};
+use crate::memory::{self, Category, MemoryConfig, MemoryHost, Usage};
+use crate::menubar::{self, OptionsHost};
+use crate::normal::{Command, Input, NormalMode};
+use crate::notifications::{self, run_reporting, MessagesHost, Notifications};
+use crate::palette::{self, PaletteHost};
+use crate::paste;
+use crate::picker::{Picker, PickerEvent};
+use crate::progress::{self, Progress, ProgressHost, ProgressTracker};
+use crate::project::{self, Project, Trust, TrustStore};
+use crate::prompt::{Prompt, Sources};
+use crate::refactor::{self, CodeAction, CodeActionMenu, RefactorHost};
+use crate::render;
+use crate::richtext::{self, RichCopyHost, Style};
+use crate::search::{self, Search, SearchHistory};
+use crate::session::{self, OpenFile, Session, SessionHost};
+use crate::selection::{line_starts, visual_column, word_around, ExpansionHistory, Selection};
+use crate::statusline::{self, Context, Mode};
+use crate::sticky;
+use crate::substitute::{self, Answer, Confirm, FileSearch, FileUndo, Match, ReplaceHost, SearchResults, Substitution};
+use crate::symbols::{self, OutlinePane, Symbol, SymbolHost};
+use crate::syntax::{self, SyntaxHost, SyntaxTree};
+use crate::tabs::{self, Tab, TabBar, TabsHost};
+use crate::terminal::Terminal;
+use crate::tooltip;
+use crate::undo::{Delta, History};
+use crate::welcome::{self, RecentFiles, RecentHost, StartKey, StartScreen};
+use crate::whichkey::{KeyResult, WhichKey};
+use crate::workspace_edit::{self, EditHost, WorkspaceEdit};
+use crate::zen::{self, ZenHost, ZenMode};
+
+const ESCAPE: char = '\u{1b}';
+const SCRATCH: &str = "*scratch*";
+// Files this big are read on another thread, with how far along it is in the status line
+const LARGE_FILE: u64 = 4 << 20;
+
+// A key once App has made sense of it: what a character key typed, with the key it is for normal
+// mode commands (see keymap::command_key), or one of the keys that type nothing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char { typed: char, command: char },
+    // A key pressed with Ctrl, by where it is on a US QWERTY keyboard. Only ever runs bindings
+    Ctrl(char),
+    // The same with Alt, except on macOS, where Option types characters
+    Alt(char),
+    Escape,
+    Enter,
+    Backspace,
+    Delete,
+    Tab,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    // F1 to F12. Only ever run bindings, like <F5> or <S-F11>
+    Function { number: u8, shift: bool },
+}
+
+impl Key {
+    // A character key that is the same command as what it types
+    pub fn char(c: char) -> Key {
+        Key::Char { typed: c, command: c }
+    }
+}
+
+// Byte ranges an edit replaced and the lengths of what replaced them, in the order they were made,
+// as returned by insert::insert and workspace_edit::apply
+type Edits = Vec<(Range<usize>, usize)>;
+
+// Every version of every buffer gets its own number
+static VERSIONS: AtomicU64 = AtomicU64::new(0);
+
+fn next_version() -> u64 {
+    VERSIONS.fetch_add(1, Ordering::Relaxed)
+}
+
+// A picker taking the keys until Enter or Escape, by what it picks
+#[derive(Debug)]
+pub enum Picking {
+    // Lines of files, like symbols, jumped to as they're moved over
+    Location(Picker<(PathBuf, usize)>),
+    // Files, like :recent and :find, only opened once picked and previewed read-only until then
+    File(Picker<PathBuf>),
+    // Characters by name, typed once picked
+    Char(Picker<Option<char>>),
+    // Themes and fonts, drawn with while selected and kept once picked
+    Theme(Picker<Theme>),
+    Font(Picker<PathBuf>),
+    // The language server's code actions at the cursor, applied once picked
+    CodeAction(CodeActionMenu),
+}
+
+impl Picking {
+    // What's been typed, the labels matching it and which one is selected, for drawing
+    pub fn view(&self) -> (&str, Vec<&str>, usize) {
+        match self {
+            Picking::Location(picker) => (&picker.filter, picker.shown_labels(), picker.selected),
+            Picking::File(picker) => (&picker.filter, picker.shown_labels(), picker.selected),
+            Picking::Char(picker) => (&picker.filter, picker.shown_labels(), picker.selected),
+            Picking::Theme(picker) => (&picker.filter, picker.shown_labels(), picker.selected),
+            Picking::Font(picker) => (&picker.filter, picker.shown_labels(), picker.selected),
+            Picking::CodeAction(menu) => (&menu.picker.filter, menu.picker.shown_labels(), menu.picker.selected),
+        }
+    }
+}
+
+// Types into the filter or moves the selection, giving what to preview
+fn picker_key<T: Clone>(picker: &mut Picker<T>, key: Key) -> Option<PickerEvent<T>> {
+    match key {
+        Key::Up | Key::Ctrl('p') => picker.move_selection(-1),
+        Key::Down | Key::Ctrl('n') | Key::Tab => picker.move_selection(1),
+        Key::Backspace => {
+            let mut filter = picker.filter.clone();
+            filter.pop();
+            picker.set_filter(&filter)
+        }
+        Key::Char { typed, .. } => {
+            let filter = format!("{}{typed}", picker.filter);
+            picker.set_filter(&filter)
+        }
+        _ => None,
+    }
+}
+
+// Enter and Escape close the picker, other keys leave it open
+fn picked<T: Clone>(mut picker: Picker<T>, key: Key) -> (Option<Picker<T>>, Option<PickerEvent<T>>) {
+    match key {
+        Key::Enter => (None, Some(picker.enter())),
+        Key::Escape => (None, Some(picker.escape())),
+        _ => {
+            let event = picker_key(&mut picker, key);
+            (Some(picker), event)
+        }
+    }
+}
+
+// Line of the text `byte` is on
+fn line_of(text: &str, byte: usize) -> usize {
+    text.as_bytes()[..byte.min(text.len())].iter().filter(|&&b| b == b'\n').count()
+}
+
+#[derive(Debug, Clone)]
+pub struct Buffer {
+    // What the backends know the file by, or a title in asterisks for scratch buffers
+    pub name: String,
+    pub text: String,
+    pub selections: Vec<Selection>,
+    pub history: History,
+    pub modified: bool,
+    // Written back to `name` by :w
+    pub is_file: bool,
+    // Set for directory listings, whose text is the listing
+    pub dir: Option<DirBuffer>,
+    // Set for the :diagnostics list, whose lines Enter jumps to
+    pub diagnostic_list: Option<DiagnosticList>,
+    // Set for :search-files results, whose lines Enter jumps to too
+    pub search_results: Option<SearchResults>,
+    // The closed folds, by line
+    pub folds: FoldState,
+    // Set with m{a-z}
+    pub marks: Marks,
+    // How deep each bracket is nested, for coloring them
+    pub brackets: BracketDepths,
+    // For files whose grammar has a tree-sitter parser, see syntax.rs
+    pub syntax: Option<SyntaxTree>,
+    // Changes with every edit, and no two buffers have the same one, so what's shown of the text
+    // can be kept until it's different
+    pub version: u64,
+    // The lines edited since they were last taken, with the version they're lines of. None after
+    // edits too big to say
+    edited: Option<(u64, LineEdit)>,
+}
+
+impl Buffer {
+    pub fn new(name: &str, text: String, is_file: bool) -> Buffer {
+        Buffer { name: name.to_string(), brackets: BracketDepths::new(&text), text, selections: vec![Selection::cursor(0)], history: History::default(), modified: false, is_file, dir: None, diagnostic_list: None, search_results: None, folds: FoldState::default(), marks: Marks::default(), syntax: None, version: next_version(), edited: None }
+    }
+
+    fn edited(&mut self, edit: Option<LineEdit>) {
+        self.folds.edited(edit.as_ref());
+        match &edit {
+            Some(edit) if edit.lines.end <= self.brackets.line_count() => self.brackets.edit(&self.text, edit.lines.clone(), edit.new_lines),
+            _ => self.brackets = BracketDepths::new(&self.text),
+        }
+        if let Some(syntax) = &mut self.syntax {
+            syntax.edit(edit.as_ref(), &self.text);
+        }
+        self.edited = match (self.edited.take(), edit) {
+            (Some((version, edited)), Some(edit)) => Some((version, edited.then(&edit))),
+            (None, Some(edit)) => Some((self.version, edit)),
+            (_, None) => None,
+        };
+        self.version = next_version();
+    }
+
+    // Records that text with `removed` newlines in it at `at` was replaced with text with `inserted`
+    // newlines, which the text already has
+    fn replaced(&mut self, at: usize, removed: usize, inserted: usize) {
+        let line = line_of(&self.text, at);
+        self.edited(Some(LineEdit { lines: line..line + removed + 1, new_lines: inserted + 1 }));
+    }
+
+    // Runs `edit`, which may only change the text at and just before the heads of the selections,
+    // like typing, and returns what it replaced like insert::insert. Only the lines from the first
+    // head to the last are recorded as edited
+    fn edit_at_heads(&mut self, edit: impl FnOnce(&mut String, &mut Vec<Selection>) -> Edits) -> Edits {
+        let first = self.selections.iter().map(|selection| selection.head).min().unwrap_or(0);
+        let last = self.selections.iter().map(|selection| selection.head).max().unwrap_or(0);
+        let (len, first_line, last_line) = (self.text.len(), line_of(&self.text, first.saturating_sub(1)), line_of(&self.text, last));
+        let result = edit(&mut self.text, &mut self.selections);
+        let last = (last + self.text.len()).saturating_sub(len);
+        self.edited(Some(LineEdit { lines: first_line..last_line + 1, new_lines: line_of(&self.text, last) + 1 - first_line }));
+        result
+    }
+
+    // Records the lines the last step of the history changed, once it's made, or once it's reverted
+    // if `undone`, and returns what it replaced. Only steps of one delta say which lines, as the
+    // deltas of the rest are each in the text as it was after the one before
+    fn replaced_by_step(&mut self, undone: bool) -> Edits {
+        let step = if undone { self.history.undone() } else { self.history.done() };
+        let deltas = step.map_or(&[][..], |step| step.deltas.as_slice());
+        let edits = match undone {
+            true => deltas.iter().rev().map(|delta| (delta.at..delta.at + delta.inserted.len(), delta.removed.len())).collect(),
+            false => deltas.iter().map(|delta| (delta.at..delta.at + delta.removed.len(), delta.inserted.len())).collect(),
+        };
+        let newlines = |text: &str| text.matches('\n').count();
+        let delta = match step.map(|step| step.deltas.as_slice()) {
+            Some([delta]) => Some((delta.at, newlines(&delta.removed), newlines(&delta.inserted))),
+            _ => None,
+        };
+        match delta {
+            Some((at, removed, inserted)) if undone => self.replaced(at, inserted, removed),
+            Some((at, removed, inserted)) => self.replaced(at, removed, inserted),
+            None => self.edited(None),
+        }
+        edits
+    }
+
+    // The lines edited since the last call, and the version they were lines of then, for App to
+    // find their rows again. None without edits, or after ones too big to say which lines they were
+    // in, which the version tells apart
+    pub fn take_edit(&mut self) -> Option<(u64, LineEdit)> {
+        self.edited.take()
+    }
+
+    pub fn path(&self) -> Option<PathBuf> {
+        self.is_file.then(|| PathBuf::from(&self.name))
+    }
+
+    // The head of the first selection, which '*', n and the status line go by
+    pub fn cursor(&self) -> usize {
+        self.selections.first().map_or(0, |selection| selection.head)
+    }
+
+    // 0-based
+    pub fn cursor_line(&self) -> usize {
+        line_starts(&self.text).partition_point(|&start| start <= self.cursor()).saturating_sub(1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PromptKind {
+    Command,
+    Search { backwards: bool },
+}
+
+// The one replacement turning `old` into `new`: everything between what they start and end with
+fn delta_between(old: &str, new: &str) -> Option<Delta> {
+    if old == new {
+        return None;
+    }
+    let mut prefix = old.bytes().zip(new.bytes()).take_while(|(a, b)| a == b).count();
+    while !old.is_char_boundary(prefix) {
+        prefix -= 1;
+    }
+    let most = (old.len() - prefix).min(new.len() - prefix);
+    let mut suffix = old.bytes().rev().zip(new.bytes().rev()).take(most).take_while(|(a, b)| a == b).count();
+    while !old.is_char_boundary(old.len() - suffix) || !new.is_char_boundary(new.len() - suffix) {
+        suffix -= 1;
+    }
+    Some(Delta::replace(old, prefix..old.len() - suffix, &new[prefix..new.len() - suffix]))
+}
+
+// A big file being read in the background. Its buffer stays empty and can't be edited until then
+struct Loading {
+    name: String,
+    text: mpsc::Receiver<Result<String, String>>,
+}
+
+// Reads a file a piece at a time, so it can be cancelled
+fn read_in_background(path: PathBuf, progress: Progress) -> mpsc::Receiver<Result<String, String>> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let read = || {
+            let mut file = std::fs::File::open(&path).map_err(|e| e.to_string())?;
+            progress.set_total(file.metadata().map_or(0, |metadata| metadata.len()));
+            let (mut bytes, mut piece) = (Vec::new(), vec![0; 1 << 20]);
+            loop {
+                if progress.is_cancelled() {
+                    return Err("cancelled".to_string());
+                }
+                let read = file.read(&mut piece).map_err(|e| e.to_string())?;
+                if read == 0 {
+                    break;
+                }
+                bytes.extend_from_slice(&piece[..read]);
+                progress.advance(read as u64);
+            }
+            String::from_utf8(bytes).map_err(|_| "not UTF-8".to_string())
+        };
+        let _ = tx.send(read());
+        progress.finish();
+    });
+    rx
+}
+
+pub struct Editor {
+    pub buffers: Vec<Buffer>,
+    pub current: usize,
+    pub mode: Mode,
+    normal: NormalMode,
+    which_key: WhichKey,
+    pub prompt: Option<(PromptKind, Prompt)>,
+    // Selections from before the '/' prompt opened, searched from and gone back to on Escape
+    search_from: Vec<Selection>,
+    // While in insert mode: the command that started it, and the text and selections from before
+    // it, so leaving makes one undo step and a change for '.'. Then what was typed since
+    inserting: Option<(Command, String, Vec<Selection>)>,
+    typed: String,
+    pub notifications: Notifications,
+    pub questions: Questions,
+    // Matches of :s with the c flag, answered y/n/a/q one at a time
+    substituting: Option<Confirm>,
+    // What the last replace-all changed, for undo-replace
+    replace_undo: Vec<FileUndo>,
+    pub backends: Backends,
+    // When directory listings were last checked for changes on disk
+    dirs_polled: Option<Instant>,
+    pub clipboard: Clipboard,
+    // The selected text last given to the primary selection
+    primary: String,
+    // A digraph after Ctrl+K or a code point after Ctrl+V, being typed in insert mode
+    entry: Entry,
+    // What the last d, y or c took, one per selection, for p
+    yanked: Vec<String>,
+    pub search: Option<Search>,
+    // Matches of the search and whether the text has CRLF line endings, for the status line, with
+    // the buffer version and search they were found for
+    status_of: Option<(u64, Option<Search>)>,
+    status_found: (Vec<Range<usize>>, bool),
+    search_backwards: bool,
+    // Searches with '/' only match whole words. Alt+W in the prompt or toggle whole-word
+    pub whole_word: bool,
+    pub search_history: SearchHistory,
+    pub recent: RecentFiles,
+    // Shown instead of the buffer when started without files, until one is opened
+    pub start_screen: Option<StartScreen>,
+    pub project: Option<Project>,
+    // Found by opening a file in it, waiting for the user to trust its config. Left here when they
+    // don't, so they're only asked once
+    pending_project: Option<Project>,
+    // Set when `project` changes, for its bindings to be added
+    pub project_changed: bool,
+    pub trust: TrustStore,
+    // From language servers, by path
+    pub diagnostics: Diagnostics,
+    // The branch checked out in cwd and how far it is from its upstream, for the status line. Found
+    // by refresh_branch, as asking git takes a while
+    pub branch: Option<(String, Option<(usize, usize)>)>,
+    pub format_on_save: bool,
+    // The user's setting for sessions per project, see session.rs
+    pub project_sessions: bool,
+    pub sessions_dir: Option<PathBuf>,
+    // Set when starting in a project that isn't trusted yet, as whether it's used decides which
+    // session to restore
+    restore_when_trusted: bool,
+    pub zen: ZenMode,
+    // A tab for each buffer, kept in step with them by sync_tabs
+    pub tab_bar: TabBar,
+    // :blame, for one file at a time
+    pub blame: Option<Blame>,
+    // The :preview pane right of the buffers, and the version of its source it shows
+    pub preview: Option<(PreviewPane, u64)>,
+    // The :outline sidebar at the right edge, the same way
+    pub outline: Option<(OutlinePane, u64)>,
+    // The file selected in a Picking::File, without a buffer of its own
+    pub file_previews: Previews,
+    // Selections before each expand-selection, for shrink-selection
+    expansions: ExpansionHistory,
+    pub picking: Option<Picking>,
+    pub debugger: Option<Debugger>,
+    // By grammar name. None once one couldn't start or exited, so it isn't started again for
+    // every file of its language
+    pub language_servers: HashMap<String, Option<Server>>,
+    // Hover and signature help from them
+    pub popups: Popups,
+    // Words from every buffer, indexed in the background, and the menu of them while typing one
+    pub indexer: Indexer,
+    // The version of each buffer the indexer last saw
+    indexed: Vec<u64>,
+    pub completion: Option<Menu>,
+    // Copies of the buffers with unsaved changes, saved if rakoune crashes
+    pub unsaved: crash::Unsaved,
+    // Set with m{A-Z}, by absolute path
+    pub global_marks: GlobalMarks,
+    // 1-based lines, by absolute path, kept between debugging sessions
+    pub breakpoints: BTreeMap<PathBuf, BTreeSet<usize>>,
+    // Whether the stack and variables share the sidebar while debugging
+    pub debug_panes: bool,
+    // The :tree sidebar, which takes the keys while it's focused
+    pub file_tree: Option<FileTree>,
+    pub tree_focused: bool,
+    pub icons: Icons,
+    // Grammars by file extension, which the scopes of the sticky header go by
+    pub languages: Languages,
+    // Set up by main from the config, or nothing is highlighted
+    pub highlighting: Option<Highlighting>,
+    // App's, kept up to date by it, for :export
+    pub fontstack: Option<Rc<FontStack>>,
+    // The primary font's file, and one to draw with instead, for App to load
+    pub font: PathBuf,
+    pub requested_font: Option<PathBuf>,
+    // The syntax colors
+    pub theme: Theme,
+    pub layout_settings: LayoutSettings,
+    pub wrap: bool,
+    pub line_numbers: bool,
+    pub key_event_log: KeyEventLog,
+    pub memory: MemoryConfig,
+    // What the renderer holds, updated by App every frame for :memory
+    pub render_usage: Usage,
+    // Set by :memory trim, for App to drop the renderer's caches
+    pub trim_requested: bool,
+    // Lines in the viewport, kept up to date by App, for paging and selecting matches in view
+    pub visible_lines: Range<usize>,
+    pub last_edit: Option<Instant>,
+    // Shown in place of the buffers until the program in it exits. App sends it the keys and
+    // sizes it to the window
+    pub terminal: Option<Terminal>,
+    // The latest :make or :run, whose output is shown under the buffer until it's closed
+    pub job: Option<Job>,
+    // Work going on in the background, for the status line, which :cancel stops
+    pub progress: ProgressTracker,
+    // When the editor started, which the progress spinner turns from
+    started: Instant,
+    // The files of the project as last scanned, and the scan that's running
+    project_files: Option<Vec<PathBuf>>,
+    scanning: Option<mpsc::Receiver<Vec<PathBuf>>>,
+    searching: Option<FileSearch>,
+    loading: Vec<Loading>,
+    // Work language servers report progress on, by grammar and token
+    server_progress: HashMap<(String, String), (Value, Progress)>,
+    pub quit: bool,
+}
+
+impl Editor {
+    pub fn new(notifications: Notifications) -> Editor {
+        Editor {
+            buffers: vec![Buffer::new(SCRATCH, String::new(), false)],
+            current: 0,
+            mode: Mode::Normal,
+            normal: NormalMode::default(),
+            which_key: WhichKey::default(),
+            prompt: None,
+            search_from: Vec::new(),
+            inserting: None,
+            typed: String::new(),
+            notifications,
+            questions: Questions::default(),
+            substituting: None,
+            replace_undo: Vec::new(),
+            backends: Backends::default(),
+            dirs_polled: None,
+            clipboard: Clipboard::new(ClipboardConfig::default()),
+            primary: String::new(),
+            entry: Entry::default(),
+            yanked: Vec::new(),
+            search: None,
+            status_of: None,
+            status_found: (Vec::new(), false),
+            search_backwards: false,
+            whole_word: false,
+            search_history: SearchHistory::default(),
+            recent: RecentFiles::default(),
+            start_screen: None,
+            project: None,
+            pending_project: None,
+            project_changed: false,
+            trust: TrustStore::default(),
+            diagnostics: Diagnostics::default(),
+            branch: None,
+            format_on_save: false,
+            project_sessions: false,
+            sessions_dir: session::default_dir(),
+            restore_when_trusted: false,
+            zen: ZenMode::default(),
+            tab_bar: TabBar::default(),
+            blame: None,
+            preview: None,
+            outline: None,
+            file_previews: Previews::default(),
+            expansions: ExpansionHistory::default(),
+            picking: None,
+            debugger: None,
+            language_servers: HashMap::new(),
+            popups: Popups::default(),
+            indexer: Indexer::new(),
+            indexed: Vec::new(),
+            completion: None,
+            unsaved: crash::Unsaved::default(),
+            global_marks: GlobalMarks::default(),
+            breakpoints: BTreeMap::new(),
+            debug_panes: false,
+            file_tree: None,
+            tree_focused: false,
+            icons: Icons::Plain,
+            languages: Languages::default(),
+            highlighting: None,
+            fontstack: None,
+            font: PathBuf::new(),
+            requested_font: None,
+            theme: Theme::default(),
+            layout_settings: LayoutSettings::default(),
+            wrap: false,
+            line_numbers: false,
+            key_event_log: KeyEventLog::default(),
+            memory: MemoryConfig::default(),
+            render_usage: Usage::default(),
+            trim_requested: false,
+            visible_lines: 0..0,
+            last_edit: None,
+            terminal: None,
+            job: None,
+            progress: ProgressTracker::default(),
+            started: Instant::now(),
+            project_files: None,
+            scanning: None,
+            searching: None,
+            loading: Vec::new(),
+            server_progress: HashMap::new(),
+            quit: false,
+        }
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffers[self.current]
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut Buffer {
+        &mut self.buffers[self.current]
+    }
+
+    // Lists the directories shown again when they changed on disk, checking every dired::POLL.
+    // Returns whether any did
+    pub fn poll_dirs(&mut self, now: Instant) -> bool {
+        if self.next_dir_poll().is_none_or(|at| now < at) {
+            return false;
+        }
+        self.dirs_polled = Some(now);
+        let mut changed = false;
+        for buffer in &mut self.buffers {
+            let Some(dir) = buffer.dir.as_ref().filter(|dir| dir.changed_on_disk(&self.backends)) else { continue };
+            let mut dir = dir.clone();
+            if dir.refresh(&self.backends).unwrap_or(false) {
+                *buffer = listing(dir, buffer.cursor_line());
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    // When poll_dirs next checks, while there are directory listings
+    pub fn next_dir_poll(&self) -> Option<Instant> {
+        self.buffers.iter().any(|buffer| buffer.dir.is_some()).then(|| self.dirs_polled.map_or(self.started, |at| at + dired::POLL))
+    }
+
+    // Searches the text again for the status line, when it or the search changed since
+    pub fn update_status(&mut self) {
+        let of = (self.buffer().version, self.search.clone());
+        if self.status_of.as_ref() == Some(&of) {
+            return;
+        }
+        let text = &self.buffer().text;
+        let matches = self.search.as_ref().and_then(|search| search.matches(text, 0..text.len()).ok()).unwrap_or_default();
+        self.status_found = (matches, text.contains("\r\n"));
+        self.status_of = Some(of);
+    }
+
+    // Brings the preview and outline panes up to date with their buffers
+    pub fn update_panes(&mut self) {
+        let Editor { buffers, preview, outline, .. } = self;
+        let changed = |path: &Path, version: u64| buffers.iter().find(|buffer| buffer.path().as_deref() == Some(path) && buffer.version != version);
+        if let Some((pane, version)) = preview {
+            if let Some(source) = changed(&pane.source, *version) {
+                pane.update(&source.text);
+                *version = source.version;
+            }
+        }
+        if let Some((pane, version)) = outline {
+            if let Some(source) = changed(&pane.path, *version) {
+                pane.update(&source.text);
+                *version = source.version;
+            }
+        }
+    }
+
+    // Where rakoune started, which relative buffer names are from
+    pub fn cwd(&self) -> PathBuf {
+        std::env::current_dir().unwrap_or_default()
+    }
+
+    // The project root, like :terminal, or where rakoune started. Tools working on the whole
+    // project run from here
+    pub fn root(&self) -> PathBuf {
+        self.project.as_ref().map_or_else(|| self.cwd(), |project| project.root.clone())
+    }
+
+    // Handles what the debug adapter sent since the last call: failed requests become
+    // notifications, and stopping somewhere new shows where. Returns whether anything changed
+    pub fn poll_debugger(&mut self) -> bool {
+        let Some(debugger) = &mut self.debugger else { return false };
+        let (state, stopped) = (debugger.session.state, debugger.session.stopped_at().map(|(path, line)| (path.to_path_buf(), line)));
+        let changed = match debugger.update() {
+            Ok(changed) => changed,
+            Err(e) => {
+                // Without its adapter there's nothing left to debug
+                self.notifications.error(e.to_string());
+                self.debugger = None;
+                return true;
+            }
+        };
+        for error in debugger.session.errors.drain(..) {
+            self.notifications.error(error);
+        }
+        if debugger.session.state == State::Terminated && state != State::Terminated {
+            self.notifications.info("The program exited, debug-stop closes the panes");
+        }
+        let Some((path, line)) = debugger.session.stopped_at().map(|(path, line)| (path.to_path_buf(), line)).filter(|now| Some(now) != stopped.as_ref()) else { return changed };
+        let result = self.jump_to(Path::new(&self.buffer_name(&path)), line.saturating_sub(1), 0);
+        self.notifications.report(result);
+        true
+    }
+
+    // One tab for each buffer, the bar shown while there's more than one
+    pub fn sync_tabs(&mut self) {
+        self.tab_bar.tabs = self.buffers.iter().map(|buffer| Tab { path: (buffer.name != SCRATCH).then(|| PathBuf::from(&buffer.name)), modified: buffer.modified }).collect();
+        self.tab_bar.active = self.current;
+        self.tab_bar.visible = self.buffers.len() > 1;
+    }
+
+    // Hands the buffers edited since they were last indexed to the indexer, and drops closed ones.
+    // Buffers are indexed by position, so closing one reindexes the ones after it
+    pub fn index_words(&mut self) {
+        for (at, buffer) in self.buffers.iter().enumerate() {
+            if self.indexed.get(at) != Some(&buffer.version) {
+                self.indexer.buffer_changed(at, buffer.text.clone());
+            }
+        }
+        for at in self.buffers.len()..self.indexed.len() {
+            self.indexer.buffer_closed(at);
+        }
+        self.indexed = self.buffers.iter().map(|buffer| buffer.version).collect();
+    }
+
+    // Copies the buffers with unsaved changes for the crash handler, the ones that changed since
+    pub fn track_unsaved(&self) {
+        self.unsaved.update(self.buffers.iter().filter(|buffer| buffer.modified).map(|buffer| (buffer.version, buffer.name.as_str(), buffer.text.as_str())));
+    }
+
+    // Picks up blame computed in the background. Returns whether there's something new to draw
+    pub fn poll_blame(&mut self) -> bool {
+        let Some(blame) = &mut self.blame else { return false };
+        let result = blame.poll();
+        self.notifications.report(result).unwrap_or(true)
+    }
+
+    // Tells the language servers about opened, edited and closed buffers, starting the server of a
+    // language with its first file
+    fn sync_language_servers(&mut self) {
+        let (cwd, root) = (self.cwd(), self.root());
+        let mut open: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for buffer in &self.buffers {
+            let Some(grammar) = buffer.path().and_then(|path| self.languages.for_path(&path)) else { continue };
+            let Some(command) = &grammar.language_server else { continue };
+            let server = self.language_servers.entry(grammar.name.clone()).or_insert_with(|| self.notifications.report(Server::start(command, &root)));
+            let Some(server) = server else { continue };
+            let path = cwd.join(&buffer.name);
+            let result = match server.documents.get(&path) {
+                None => server.open(&path, &grammar.name, buffer.version, &buffer.text),
+                Some(version) if *version != buffer.version => server.change(&path, buffer.version, &buffer.text),
+                Some(_) => Ok(()),
+            };
+            self.notifications.report(result);
+            open.entry(grammar.name.clone()).or_default().push(path);
+        }
+        for (name, server) in &mut self.language_servers {
+            let Some(server) = server else { continue };
+            let closed: Vec<PathBuf> = server.documents.keys().filter(|path| !open.get(name).is_some_and(|open| open.contains(path))).cloned().collect();
+            for path in closed {
+                let result = server.close(&path);
+                self.notifications.report(result);
+            }
+        }
+    }
+
+    // Whether the buffer for `path` has a running language server with `capability`
+    fn has_language_server(&mut self, path: &Path, capability: &str) -> bool {
+        self.sync_language_servers();
+        let Some(grammar) = self.languages.for_path(path) else { return false };
+        self.language_servers.get(&grammar.name).and_then(Option::as_ref).is_some_and(|server| server.supports(capability))
+    }
+
+    // Asks the language server of the open buffer for `path` for `what`, which needs the server
+    // to have `capability`. `ask` gets the absolute path the server knows the file by
+    fn ask_language_server(&mut self, path: &Path, capability: &str, what: &str, ask: impl FnOnce(&mut Server, &Path, &Buffer) -> Result<(), lsp::Error>) -> Result<(), String> {
+        let absolute = self.cwd().join(path);
+        let at = self.buffers.iter().position(|buffer| buffer.path().as_deref() == Some(path)).ok_or_else(|| format!("{} isn't open", path.display()))?;
+        self.sync_language_servers();
+        let grammar = self.languages.for_path(path);
+        let server = grammar.and_then(|grammar| self.language_servers.get_mut(&grammar.name)?.as_mut()).ok_or_else(|| format!("No language server for {}", path.display()))?;
+        if !server.supports(capability) {
+            return Err(format!("{} doesn't do {what}", server.command));
+        }
+        ask(server, &absolute, &self.buffers[at]).map_err(|e| e.to_string())
+    }
+
+    // Sends edits to the language servers and handles what they sent since the last call. Returns
+    // whether anything changed
+    pub fn poll_language_servers(&mut self) -> bool {
+        self.sync_language_servers();
+        self.cancel_server_progress();
+        let (mut events, mut progress) = (Vec::new(), Vec::new());
+        let mut exited = false;
+        for (grammar, server) in &mut self.language_servers {
+            let Some(running) = server else { continue };
+            for polled in running.poll() {
+                match polled {
+                    Ok(Event::Progress(token, work)) => progress.push((grammar.clone(), token, work)),
+                    Ok(event) => events.push(event),
+                    // Without its process there's nothing more to come
+                    Err(e @ lsp::Error::Exited(_)) => {
+                        self.notifications.error(e.to_string());
+                        *server = None;
+                        exited = true;
+                        break;
+                    }
+                    Err(e) => self.notifications.error(e.to_string()),
+                }
+            }
+        }
+        let changed = exited || !events.is_empty() || !progress.is_empty();
+        for (grammar, token, work) in progress {
+            self.server_progress(&grammar, token, work);
+        }
+        for event in events {
+            let result = self.language_event(event);
+            self.notifications.report(result);
+        }
+        let head = self.buffer().cursor();
+        self.popups.cursor_moved(head);
+        changed
+    }
+
+    // Work a language server reported it's doing, in the status line until it's done
+    fn server_progress(&mut self, grammar: &str, token: Value, work: WorkDone) {
+        let key = (grammar.to_string(), token.to_string());
+        match work {
+            WorkDone::Begin(title) => {
+                let progress = self.progress.start(format!("{grammar}: {title}"));
+                self.server_progress.insert(key, (token, progress));
+            }
+            WorkDone::Report(percentage) => {
+                if let (Some((_, progress)), Some(percentage)) = (self.server_progress.get(&key), percentage) {
+                    progress.set_total(100);
+                    progress.set_done(percentage);
+                }
+            }
+            WorkDone::End => {
+                if let Some((_, progress)) = self.server_progress.remove(&key) {
+                    progress.finish();
+                }
+            }
+        }
+    }
+
+    // Tells the servers about the work :cancel stopped
+    fn cancel_server_progress(&mut self) {
+        let cancelled: Vec<(String, String)> = self.server_progress.iter().filter(|(_, (_, progress))| progress.is_cancelled()).map(|(key, _)| key.clone()).collect();
+        for key in cancelled {
+            let Some((token, progress)) = self.server_progress.remove(&key) else { continue };
+            progress.finish();
+            if let Some(Some(server)) = self.language_servers.get_mut(&key.0) {
+                let result = server.cancel_progress(&token);
+                self.notifications.report(result);
+            }
+        }
+    }
+
+    fn language_event(&mut self, event: Event) -> Result<(), String> {
+        match event {
+            Event::Response(request, result) => self.language_response(request, &result),
+            // Tokens are asked for again with the next edit
+            Event::Failed(Request::SemanticTokens(_), _) => Ok(()),
+            Event::Failed(_, message) => Err(message),
+            // Only open files have the text to place them in
+            Event::Diagnostics(path, diagnostics) => {
+                let name = self.buffer_name(&path);
+                if let Some(buffer) = self.buffers.iter().find(|buffer| buffer.is_file && buffer.name == name) {
+                    self.diagnostics.set(Path::new(&name), lsp::diagnostics(&diagnostics, &buffer.text));
+                }
+                Ok(())
+            }
+            Event::SemanticTokens(path, legend, tokens) => {
+                let name = PathBuf::from(self.buffer_name(&path));
+                let shown = self.buffer().path().as_ref() == Some(&name);
+                if let Some(highlighting) = &mut self.highlighting {
+                    highlighting.semantic.set(&name, legend, tokens);
+                    if shown {
+                        highlighting.invalidate();
+                    }
+                }
+                Ok(())
+            }
+            Event::Message(severity, message) => {
+                self.notifications.notify(severity, message);
+                Ok(())
+            }
+            Event::ApplyEdit(edit) => self.apply_server_edit(edit),
+            Event::Progress(..) => Ok(()),
+        }
+    }
+
+    fn language_response(&mut self, request: Request, result: &Value) -> Result<(), String> {
+        match request {
+            // Shown if the cursor is still where they were asked for
+            Request::Hover(path, anchor) => {
+                if self.is_cursor_at(&path, anchor) {
+                    self.popups.show_hover(anchor, &lsp::hover_markdown(result));
+                }
+            }
+            Request::SignatureHelp(path, anchor) => {
+                if self.is_cursor_at(&path, anchor) {
+                    self.popups.show_signature_help(anchor, &lsp::signature_help(result));
+                }
+            }
+            Request::Formatting(path) => {
+                let name = PathBuf::from(self.buffer_name(&path));
+                format::apply_formatting_edits(self, &name, &lsp::text_edits(result))?;
+            }
+            Request::Rename => self.apply_server_edit(lsp::workspace_edit(result))?,
+            Request::CodeActions => {
+                let actions = lsp::code_actions(result).into_iter().map(|action| CodeAction { edit: action.edit.map(|edit| self.local_edit(edit)), ..action }).collect();
+                let menu = CodeActionMenu::new(actions).ok_or("No code actions here")?;
+                self.picking = Some(Picking::CodeAction(menu));
+            }
+            Request::DocumentSymbols(path) => {
+                let symbols = self.local_symbols(lsp::symbols(result, &path));
+                let original = (PathBuf::from(self.buffer_name(&path)), self.buffer().cursor_line());
+                self.picking = Some(Picking::Location(symbols::picker(&symbols, false, original)));
+            }
+            Request::WorkspaceSymbols => {
+                let symbols = self.local_symbols(lsp::symbols(result, Path::new("")));
+                let original = (self.buffer().path().unwrap_or_default(), self.buffer().cursor_line());
+                self.picking = Some(Picking::Location(symbols::picker(&symbols, true, original)));
+            }
+            Request::Initialize | Request::ExecuteCommand | Request::SemanticTokens(_) => {}
+        }
+        Ok(())
+    }
+
+    // Whether the cursor of the focused buffer is at byte `at` of the file at absolute `path`
+    fn is_cursor_at(&self, path: &Path, at: usize) -> bool {
+        let buffer = self.buffer();
+        buffer.path().is_some_and(|name| self.cwd().join(name) == path) && buffer.cursor() == at
+    }
+
+    // Server edits are by absolute path, buffers by the name they were opened with
+    fn local_edit(&self, edit: WorkspaceEdit) -> WorkspaceEdit {
+        WorkspaceEdit { changes: edit.changes.into_iter().map(|(path, edits)| (PathBuf::from(self.buffer_name(&path)), edits)).collect() }
+    }
+
+    fn local_symbols(&self, symbols: Vec<Symbol>) -> Vec<Symbol> {
+        symbols.into_iter().map(|symbol| Symbol { path: PathBuf::from(self.buffer_name(&symbol.path)), ..symbol }).collect()
+    }
+
+    fn apply_server_edit(&mut self, edit: WorkspaceEdit) -> Result<(), String> {
+        let edit = self.local_edit(edit);
+        workspace_edit::apply_workspace_edit(self, &edit).map(|_| ()).map_err(|e| e.to_string())
+    }
+
+    // Signature help in insert mode, from a server that has it
+    fn request_signature_help(&mut self) {
+        let Some(path) = self.buffer().path() else { return };
+        if self.has_language_server(&path, "signatureHelpProvider") {
+            let result = self.ask_language_server(&path, "signatureHelpProvider", "signature help", |server, path, buffer| server.signature_help(path, &buffer.text, buffer.cursor()));
+            self.notifications.report(result);
+        }
+    }
+
+    // The :blame annotations for the current buffer, at the end of its lines. None while it has
+    // unsaved changes, as git only knows the lines that were saved
+    pub fn blame_annotations(&self, viewport: Range<usize>) -> Vec<VirtualText> {
+        let buffer = self.buffer();
+        let blamed = buffer.path().map(|path| self.cwd().join(path));
+        let Some(blame) = self.blame.as_ref().filter(|blame| Some(&blame.path) == blamed.as_ref() && !buffer.modified) else { return Vec::new() };
+        let starts = line_starts(&buffer.text);
+        let ends: Vec<usize> = starts.iter().skip(1).map(|next| next - 1).chain([buffer.text.len()]).collect();
+        blame.virtual_text(&ends, buffer.cursor_line(), viewport)
+    }
+
+    // Scopes of the current buffer for the sticky header, by its brackets if its grammar has them
+    pub fn scopes(&self) -> Vec<Fold> {
+        let buffer = self.buffer();
+        let tree = buffer.path().and_then(|path| self.languages.for_path(&path)).map(|grammar| BracketTree::new(grammar, &buffer.text));
+        sticky::scopes(&buffer.text, tree.as_ref())
+    }
+
+    // Whether the highlights of the current buffer are out of date and should be worked out again
+    pub fn highlights_due(&self) -> bool {
+        let buffer = self.buffer();
+        self.highlighting.as_ref().is_some_and(|highlighting| highlighting.version != Some(buffer.version)) && buffer.path().is_some()
+    }
+
+    // Starts working out the highlights of the current buffer once it changed, and takes them when
+    // they're done. Returns whether there are new ones to draw
+    pub fn poll_highlighting(&mut self) -> bool {
+        let buffer = &self.buffers[self.current];
+        let (Some(highlighting), Some(path)) = (&mut self.highlighting, buffer.path()) else { return false };
+        if highlighting.version != Some(buffer.version) && !highlighting.is_requested(buffer.version) {
+            highlighting.request(&path, buffer.text.clone(), buffer.syntax.clone(), buffer.version);
+        }
+        highlighting.poll(buffer.version)
+    }
+
+    // Which highlights text_colors draws, to tell when they change
+    pub fn highlights_version(&self) -> Option<u64> {
+        self.highlighting.as_ref().and_then(|highlighting| highlighting.version)
+    }
+
+    // The colors of the bytes `bytes` of the current buffer: the last highlights found for its file,
+    // which may be from before the latest edits, and its brackets by depth
+    pub fn text_colors(&self, bytes: Range<usize>) -> Colors {
+        let buffer = self.buffer();
+        let highlighting = self.highlighting.as_ref().filter(|highlighting| highlighting.path.is_some() && highlighting.path == buffer.path());
+        let spans = highlighting.map_or(&[][..], |highlighting| &highlighting.spans);
+        highlighter::colors(&self.theme, spans, &buffer.brackets, &line_starts(&buffer.text), bytes)
+    }
+
+    // The highlights of the current buffer in the colors of the theme, worked out now rather than
+    // on the highlighting thread. Nothing for buffers without a path to tell the filetype by
+    fn highlight_colors(&self) -> Colors {
+        let (Some(highlighting), Some(path)) = (&self.highlighting, self.buffer().path()) else { return Colors::new() };
+        let spans = highlighting.highlight(&path, &self.buffer().text, self.buffer().syntax.as_ref());
+        spans.into_iter().filter_map(|(range, face)| Some((range, self.theme.face_color(&face)?))).collect()
+    }
+
+    // What the buffer of the file at `path` is called, which may be relative to cwd if it's open
+    fn buffer_name(&self, path: &Path) -> String {
+        let cwd = self.cwd();
+        let open = self.buffers.iter().filter_map(Buffer::path).find(|open| cwd.join(open) == path);
+        open.as_deref().unwrap_or(path).display().to_string()
+    }
+
+    // Breakpoints, and the line the debugged program is stopped on, in the current buffer by
+    // 0-based line
+    pub fn gutter(&self) -> BTreeMap<usize, GutterMark> {
+        let Some(path) = self.buffer().path() else { return BTreeMap::new() };
+        let path = self.cwd().join(path);
+        let marks = match &self.debugger {
+            Some(debugger) => debugger.session.gutter(&path),
+            None => self.breakpoints.get(&path).into_iter().flatten().map(|&line| (line, GutterMark::Breakpoint)).collect(),
+        };
+        marks.into_iter().map(|(line, mark)| (line.saturating_sub(1), mark)).collect()
+    }
+
+    // When the which-key popup for the keys typed so far should appear
+    pub fn which_key_at(&self) -> Option<Instant> {
+        self.which_key.show_at()
+    }
+
+    pub fn which_key_popup(&self, registry: &Registry<Editor>, now: Instant) -> Option<Vec<String>> {
+        self.which_key.popup(registry, now)
+    }
+
+    pub fn refresh_branch(&mut self) {
+        let cwd = self.root();
+        let output = std::process::Command::new("git").args(["rev-parse", "--abbrev-ref", "HEAD"]).current_dir(&cwd).output();
+        let branch = output.ok().filter(|output| output.status.success()).map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+        self.branch = branch.map(|branch| (branch, tooltip::ahead_behind(&cwd)));
+    }
+
+    // The marks set in the current buffer, lowercase and uppercase, by line
+    pub fn marks_by_line(&self) -> BTreeMap<usize, Vec<char>> {
+        let buffer = self.buffer();
+        let mut marks = buffer.marks.clone();
+        if let Some(path) = buffer.path() {
+            for (name, at) in self.global_marks.in_file(&self.cwd().join(path)).iter() {
+                marks.set(name, at);
+            }
+        }
+        marks.by_line(&line_starts(&buffer.text))
+    }
+
+    // The gutter only takes room while there's a breakpoint or mark somewhere, a program being
+    // debugged or a diagnostic for the current buffer to sign
+    pub fn gutter_shown(&self) -> bool {
+        let diagnosed = self.buffer().path().is_some_and(|path| !self.diagnostics.get(&path).is_empty());
+        self.debugger.is_some() || self.breakpoints.values().any(|lines| !lines.is_empty()) || !self.buffer().marks.is_empty() || !self.global_marks.is_empty() || diagnosed
+    }
+
+    // Opens `path` with the cursor `column` characters into `line`, both 0-based. An empty path
+    // stays in the current buffer
+    fn jump_to(&mut self, path: &Path, line: usize, column: usize) -> Result<(), String> {
+        if !path.as_os_str().is_empty() {
+            self.open(&path.display().to_string())?;
+        }
+        let buffer = self.buffer_mut();
+        let start = line_starts(&buffer.text).get(line).copied().unwrap_or(buffer.text.len());
+        let line_text = buffer.text[start..].split('\n').next().unwrap_or_default();
+        let at = start + line_text.char_indices().nth(column).map_or(line_text.len(), |(at, _)| at);
+        buffer.selections = vec![Selection::cursor(at)];
+        Ok(())
+    }
+
+    fn picking_key(&mut self, key: Key) {
+        let Some(picking) = self.picking.take() else { return };
+        match picking {
+            Picking::Location(picker) => {
+                let (open, event) = picked(picker, key);
+                self.picking = open.map(Picking::Location);
+                if let Some(PickerEvent::Preview((path, line)) | PickerEvent::Commit((path, line)) | PickerEvent::Revert((path, line))) = event {
+                    let result = self.jump_to(&path, line, 0);
+                    self.notifications.report(result);
+                }
+            }
+            Picking::File(picker) => {
+                let (open, event) = picked(picker, key);
+                self.picking = open.map(Picking::File);
+                match event {
+                    Some(PickerEvent::Preview(path)) => {
+                        self.file_previews.show(&self.backends, &self.languages, &path);
+                    }
+                    Some(PickerEvent::Commit(path)) => {
+                        let result = self.open(&self.buffer_name(&path));
+                        self.notifications.report(result);
+                    }
+                    _ => {}
+                }
+                if self.picking.is_none() {
+                    self.file_previews.clear();
+                }
+            }
+            Picking::Char(picker) => {
+                let (open, event) = picked(picker, key);
+                self.picking = open.map(Picking::Char);
+                if let Some(PickerEvent::Commit(Some(c))) = event {
+                    self.type_char(c);
+                }
+            }
+            Picking::Theme(picker) => {
+                let (open, event) = picked(picker, key);
+                self.picking = open.map(Picking::Theme);
+                if let Some(PickerEvent::Preview(theme) | PickerEvent::Commit(theme) | PickerEvent::Revert(theme)) = event {
+                    self.theme = theme;
+                }
+            }
+            Picking::Font(picker) => {
+                let (open, event) = picked(picker, key);
+                self.picking = open.map(Picking::Font);
+                match event {
+                    Some(PickerEvent::Preview(path)) => self.set_font(path, true),
+                    Some(PickerEvent::Commit(path) | PickerEvent::Revert(path)) => self.set_font(path, false),
+                    None => {}
+                }
+            }
+            Picking::CodeAction(mut menu) => match key {
+                Key::Enter => {
+                    if let Some(action) = menu.enter() {
+                        let result = refactor::apply_code_action(self, &action);
+                        self.notifications.report(result);
+                    }
+                }
+                Key::Escape => {}
+                _ => {
+                    picker_key(&mut menu.picker, key);
+                    self.picking = Some(Picking::CodeAction(menu));
+                }
+            },
+        }
+    }
+
+    // The buffer for `name`, reading it if it isn't open yet. A file that doesn't exist yet is an
+    // empty buffer, created when written
+    fn load(&mut self, name: &str) -> Result<usize, String> {
+        if let Some(at) = self.buffers.iter().position(|buffer| buffer.name == name) {
+            return Ok(at);
+        }
+        let large = self.backends.local_path(name).filter(|path| std::fs::metadata(path).is_ok_and(|metadata| metadata.len() > LARGE_FILE));
+        let text = match large {
+            Some(path) => {
+                let title = format!("Loading {}", path.file_name().unwrap_or_default().to_string_lossy());
+                self.loading.push(Loading { name: name.to_string(), text: read_in_background(path, self.progress.start(title)) });
+                String::new()
+            }
+            None => match self.backends.read_text(name) {
+                Ok(text) => text,
+                Err(backend::Error::NotFound(_)) => String::new(),
+                Err(e) => return Err(e.to_string()),
+            },
+        };
+        if let Some(path) = self.backends.local_path(name) {
+            self.notifications.report(self.recent.opened(&path));
+        }
+        // The empty buffer rakoune starts with makes way for the first file
+        if let [only] = self.buffers.as_slice() {
+            if only.name == SCRATCH && !only.modified && only.text.is_empty() {
+                self.buffers.clear();
+            }
+        }
+        self.buffers.push(Buffer::new(name, text, true));
+        self.parse_syntax(self.buffers.len() - 1);
+        Ok(self.buffers.len() - 1)
+    }
+
+    // Gives the buffer at `at` the syntax tree of its file's language, if its grammar has a parser
+    fn parse_syntax(&mut self, at: usize) {
+        let buffer = &self.buffers[at];
+        let language = buffer.path().and_then(|path| self.languages.for_path(&path)).and_then(|grammar| grammar.syntax.clone());
+        self.buffers[at].syntax = language.and_then(|language| SyntaxTree::new(language, &buffer.text));
+    }
+
+    // Shows the file or directory called `name`
+    pub fn open(&mut self, name: &str) -> Result<(), String> {
+        if let Some(path) = self.backends.local_path(name) {
+            self.discover_project(&path);
+        }
+        if self.backends.is_dir(name) {
+            let buffer = DirBuffer::new(&self.backends, name).map_err(|e| e.to_string())?;
+            self.show_dir(buffer, 0);
+        } else {
+            self.current = self.load(name)?;
+        }
+        self.start_screen = None;
+        Ok(())
+    }
+
+    // Uses the .rakoune.toml above `path`, asking first unless its config was trusted before. The
+    // first project found stays, as the finder, jobs and the session are for one project
+    pub fn discover_project(&mut self, path: &Path) {
+        if self.project.is_some() || self.pending_project.is_some() {
+            return;
+        }
+        let Some(config_path) = std::path::absolute(path).ok().and_then(|path| project::discover(&path)) else { return };
+        let Some(project) = self.notifications.report(Project::load(&config_path)) else { return };
+        match self.trust.check(&project) {
+            Trust::Trusted => self.use_project(project),
+            Trust::Unknown => {
+                self.questions.ask(Question::trust_project(&config_path));
+                self.pending_project = Some(project);
+            }
+        }
+    }
+
+    // Restores the session rakoune starts with, the project's own if it has sessions. While the
+    // project waits to be trusted, that's once the user answers
+    pub fn restore_session_at_start(&mut self) {
+        match self.project.is_none() && self.pending_project.is_some() {
+            true => self.restore_when_trusted = true,
+            false => {
+                let restored = session::restore(self);
+                self.notifications.report(restored);
+            }
+        }
+    }
+
+    fn use_project(&mut self, project: Project) {
+        self.project = Some(project);
+        self.project_changed = true;
+        self.project_files = None;
+        self.scan_project();
+    }
+
+    // Lists the project's files in the background, for the finder and :search-files
+    fn scan_project(&mut self) {
+        let Some(project) = self.project.as_ref().filter(|_| self.scanning.is_none()) else { return };
+        self.scanning = Some(project.scan(self.progress.start("Scanning project")));
+    }
+
+    // The project's files as last scanned, scanning again so the next time finds new files too
+    fn scanned_files(&mut self) -> Option<Vec<PathBuf>> {
+        let project = self.project.as_ref()?;
+        let files = self.project_files.clone().unwrap_or_else(|| project.files());
+        self.scan_project();
+        Some(files)
+    }
+
+    // Picks up the project scan, :search-files and the files read in the background once they're
+    // done. Returns whether there's something new to draw, as there is while the spinner turns
+    pub fn poll_background(&mut self) -> bool {
+        let mut changed = self.progress.is_busy();
+        if let Some(scanning) = &self.scanning {
+            match scanning.try_recv() {
+                Ok(files) => {
+                    self.project_files = Some(files);
+                    self.scanning = None;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => self.scanning = None,
+                Err(mpsc::TryRecvError::Empty) => {}
+            }
+        }
+        if let Some(files) = self.searching.as_ref().and_then(FileSearch::poll) {
+            self.searching = None;
+            changed = true;
+            match files.is_empty() {
+                true => self.notifications.error("Pattern not found"),
+                false => self.show_results(SearchResults { files }),
+            }
+        }
+        let mut at = 0;
+        while at < self.loading.len() {
+            let result = match self.loading[at].text.try_recv() {
+                Ok(result) => result,
+                Err(mpsc::TryRecvError::Empty) => {
+                    at += 1;
+                    continue;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => Err("stopped reading".to_string()),
+            };
+            let name = self.loading.remove(at).name;
+            changed = true;
+            let Some(buffer) = self.buffers.iter().position(|buffer| buffer.name == name) else { continue };
+            match result {
+                Ok(text) => {
+                    self.buffers[buffer] = Buffer::new(&name, text, true);
+                    self.parse_syntax(buffer);
+                }
+                Err(e) => {
+                    self.notifications.error(format!("Couldn't read {name}: {e}"));
+                    let closed = self.close_tab(buffer);
+                    self.notifications.report(closed);
+                }
+            }
+        }
+        changed
+    }
+
+    fn is_loading(&self, name: &str) -> bool {
+        self.loading.iter().any(|loading| loading.name == name)
+    }
+
+    // Why the current buffer can't be edited, if it can't
+    fn read_only(&self) -> Option<String> {
+        let buffer = self.buffer();
+        if buffer.dir.is_some() {
+            return Some("Directory listings can't be edited, use dir-create, dir-rename and dir-delete".to_string());
+        }
+        if self.is_loading(&buffer.name) {
+            return Some(format!("{} is still loading", buffer.name));
+        }
+        (buffer.is_file && self.backends.is_read_only(&buffer.name)).then(|| format!("{} is read-only", buffer.name))
+    }
+
+    // Runs `edit` on the current buffer, making what it changed one undo step
+    fn edit<R>(&mut self, edit: impl FnOnce(&mut String, &mut Vec<Selection>) -> R) -> R {
+        let buffer = &mut self.buffers[self.current];
+        let (text, selections) = (buffer.text.clone(), buffer.selections.clone());
+        let result = edit(&mut buffer.text, &mut buffer.selections);
+        self.changed(&text, selections);
+        result
+    }
+
+    // Records which lines of the current buffer changed from `before`, returning the change
+    fn lines_changed(&mut self, before: &str) -> Option<Delta> {
+        let buffer = &mut self.buffers[self.current];
+        let delta = delta_between(before, &buffer.text)?;
+        buffer.replaced(delta.at, delta.removed.matches('\n').count(), delta.inserted.matches('\n').count());
+        self.moved_through(&[(delta.at..delta.at + delta.removed.len(), delta.inserted.len())]);
+        Some(delta)
+    }
+
+    // Keeps what's tied to places in the current buffer on them through `edits`
+    fn moved_through(&mut self, edits: &[(Range<usize>, usize)]) {
+        for (replaced, new_len) in edits {
+            self.buffers[self.current].marks.apply_edit(replaced.clone(), *new_len);
+        }
+        let Some(path) = self.buffer().path() else { return };
+        let absolute = self.cwd().join(&path);
+        for (replaced, new_len) in edits {
+            self.diagnostics.apply_edit(&path, replaced.clone(), *new_len);
+            self.global_marks.apply_edit(&absolute, replaced.clone(), *new_len);
+        }
+    }
+
+    // Records the change from `before` to the text of the current buffer as one undo step
+    fn changed(&mut self, before: &str, selections: Vec<Selection>) {
+        if let Some(delta) = self.lines_changed(before) {
+            self.record_step(delta, selections);
+        }
+    }
+
+    // Makes `delta`, which the current buffer has already, one undo step
+    fn record_step(&mut self, delta: Delta, selections: Vec<Selection>) {
+        let buffer = &mut self.buffers[self.current];
+        let after = buffer.selections.clone();
+        buffer.history.record(vec![delta], selections, after);
+        buffer.modified = true;
+        self.last_edit = Some(Instant::now());
+    }
+
+    pub fn key(&mut self, registry: &Registry<Editor>, key: Key, now: Instant) {
+        // An open question takes every key until it's answered
+        if self.questions.current().is_some() {
+            let answer = match key {
+                Key::Char { typed, .. } => typed,
+                Key::Escape => ESCAPE,
+                _ => return,
+            };
+            confirm::answer_key(registry, self, answer);
+            return;
+        }
+        if self.substituting.is_some() {
+            return self.substitute_key(key);
+        }
+        if self.prompt.is_some() {
+            return self.prompt_key(registry, key);
+        }
+        if self.picking.is_some() {
+            return self.picking_key(key);
+        }
+        if self.tree_focused && self.file_tree.is_some() {
+            return self.tree_key(key);
+        }
+        if self.mode == Mode::Normal && self.start_screen_key(registry, key) {
+            return;
+        }
+        if self.mode == Mode::Insert && self.completion_key(key) {
+            return;
+        }
+        if matches!(key, Key::Escape) && self.popups.escape() {
+            return;
+        }
+        if let Key::Function { number, shift } = key {
+            let shift = if shift { "S-" } else { "" };
+            return self.binding_key(registry, &format!("<{shift}F{number}>"), now);
+        }
+        match (self.mode, key) {
+            (Mode::Insert, Key::Char { typed, .. }) => {
+                self.insert_key(key);
+                // Signature help follows what's typed, and is asked for by '(' and ','
+                let head = self.buffer().cursor();
+                if self.mode == Mode::Insert && self.popups.typed(typed, head) {
+                    self.request_signature_help();
+                }
+                match completion::is_word_char(typed) {
+                    true => self.update_completion(),
+                    false => self.completion = None,
+                }
+            }
+            (Mode::Insert, _) => {
+                self.insert_key(key);
+                match key == Key::Backspace && self.completion.is_some() {
+                    true => self.update_completion(),
+                    false => self.completion = None,
+                }
+            }
+            _ => self.normal_key(registry, key, now),
+        }
+        if self.mode != Mode::Insert {
+            self.completion = None;
+        }
+        let head = self.buffer().cursor();
+        self.popups.cursor_moved(head);
+    }
+
+    // The completion menu's keys in insert mode. Ctrl+N opens it when it's closed. Returns whether
+    // the key was the menu's
+    fn completion_key(&mut self, key: Key) -> bool {
+        let Some(menu) = &mut self.completion else {
+            if key == Key::Ctrl('n') {
+                self.update_completion();
+            }
+            return key == Key::Ctrl('n');
+        };
+        match key {
+            Key::Ctrl('n') | Key::Down => menu.next(),
+            Key::Ctrl('p') | Key::Up => menu.prev(),
+            Key::Tab | Key::Enter => self.accept_completion(),
+            Key::Escape => self.completion = None,
+            _ => return false,
+        }
+        true
+    }
+
+    // Opens the completion menu for the word before the cursor, or closes it with nothing to offer
+    fn update_completion(&mut self) {
+        let buffer = self.buffer();
+        self.completion = completion::word_before(&buffer.text, buffer.cursor()).and_then(|(start, word)| {
+            let items = self.indexer.index.lock().unwrap().complete(word, completion::MENU_LEN);
+            (!items.is_empty()).then_some(Menu { start, items, selected: 0 })
+        });
+    }
+
+    // Types the picked completion in place of the word it completes
+    fn accept_completion(&mut self) {
+        let Some(menu) = self.completion.take() else { return };
+        let Some(word) = self.buffer().text.get(menu.start..self.buffer().cursor()) else { return };
+        for _ in 0..word.chars().count() {
+            self.insert_key(Key::Backspace);
+        }
+        for c in menu.picked().chars() {
+            self.insert_key(Key::char(c));
+        }
+    }
+
+    // Where the completion menu, or else the hover or signature help popup, is drawn from
+    pub fn popup_anchor(&self) -> Option<usize> {
+        match &self.completion {
+            Some(menu) => Some(menu.start),
+            None => Some(self.popups.current.as_ref()?.anchor),
+        }
+    }
+
+    // Moves through the recent files of the start screen, and opens one or a new buffer with the
+    // command line it gives. Returns whether the key was the start screen's
+    fn start_screen_key(&mut self, registry: &Registry<Editor>, key: Key) -> bool {
+        let Some(screen) = &mut self.start_screen else { return false };
+        let name = match key {
+            Key::Char { typed, .. } => typed.to_string(),
+            Key::Enter => "<Enter>".to_string(),
+            Key::Up => "<Up>".to_string(),
+            Key::Down => "<Down>".to_string(),
+            _ => return false,
+        };
+        match screen.key(&name) {
+            StartKey::Moved => {}
+            StartKey::Run(command_line) => run_reporting(registry, self, &command_line),
+            StartKey::Other => return false,
+        }
+        true
+    }
+
+    // Moves around the :tree and opens files from it. Escape or opening a file goes back to the buffer
+    fn tree_key(&mut self, key: Key) {
+        let Some(tree) = &mut self.file_tree else { return };
+        let name = match key {
+            Key::Escape => {
+                self.tree_focused = false;
+                return;
+            }
+            Key::Char { typed, .. } => typed.to_string(),
+            Key::Enter => "<Enter>".to_string(),
+            Key::Left => "<Left>".to_string(),
+            Key::Right => "<Right>".to_string(),
+            Key::Up => "<Up>".to_string(),
+            Key::Down => "<Down>".to_string(),
+            _ => return,
+        };
+        // Buffers have no splits to open in, so files always open in place
+        let Some((path, _)) = tree.key(&name) else { return };
+        let result = self.open(&path.display().to_string());
+        self.notifications.report(result);
+        self.tree_focused = false;
+    }
+
+    // Text that came in faster than anyone types, or in bracketed paste markers. One undo step in
+    // normal mode, and part of what's typed in insert mode
+    pub fn paste(&mut self, pasted: &str) {
+        if let Some((_, prompt)) = &mut self.prompt {
+            prompt.line.push_str(&pasted.replace('\n', " "));
+            return;
+        }
+        if let Some(reason) = self.read_only() {
+            return self.notifications.error(reason);
+        }
+        let buffer = &mut self.buffers[self.current];
+        match self.mode {
+            Mode::Insert => {
+                let edits = buffer.edit_at_heads(|text, selections| insert::insert(text, selections, pasted));
+                self.moved_through(&edits);
+                self.typed.push_str(pasted);
+            }
+            _ => {
+                paste::paste(&mut buffer.text, &mut buffer.selections, &mut buffer.history, &[pasted.to_string()]);
+                let edits = buffer.replaced_by_step(false);
+                buffer.modified = true;
+                self.moved_through(&edits);
+                self.last_edit = Some(Instant::now());
+            }
+        }
+    }
+
+    fn normal_key(&mut self, registry: &Registry<Editor>, key: Key, now: Instant) {
+        let c = match key {
+            Key::Char { command, .. } => command,
+            Key::Escape => {
+                self.normal.cancel();
+                self.which_key.cancel();
+                self.buffer_mut().selections.iter_mut().for_each(|selection| *selection = Selection::cursor(selection.head));
+                return;
+            }
+            Key::Enter if self.buffer().dir.is_some() => return run_reporting(registry, self, "dir-open"),
+            Key::Enter if self.buffer().diagnostic_list.is_some() || self.buffer().search_results.is_some() => {
+                let (buffer, line) = (self.buffer(), self.buffer().cursor_line());
+                let location = buffer.diagnostic_list.as_ref().and_then(|list| list.location(line)).or_else(|| buffer.search_results.as_ref()?.location(line));
+                let Some((path, line, col)) = location else { return };
+                let path = PathBuf::from(self.buffer_name(&path));
+                let result = self.jump_to(&path, line - 1, col - 1);
+                self.notifications.report(result);
+                return;
+            }
+            Key::PageUp | Key::PageDown => {
+                let count = self.visible_lines.len().max(1);
+                let key = if key == Key::PageUp { 'k' } else { 'j' };
+                self.run_normal(Command { count, prefix: None, key, inserted: String::new() }, false);
+                return self.keep_out_of_folds(key);
+            }
+            Key::Left | Key::Backspace => 'h',
+            Key::Right => 'l',
+            Key::Up => 'k',
+            Key::Down | Key::Enter => 'j',
+            Key::Home => '0',
+            Key::End => '$',
+            Key::Delete => 'd',
+            Key::Tab | Key::Function { .. } => return,
+            Key::Ctrl(c) => return self.binding_key(registry, &format!("<C-{c}>"), now),
+            Key::Alt(c) => return self.binding_key(registry, &format!("<A-{c}>"), now),
+        };
+        if !matches!(key, Key::Char { .. }) {
+            return self.normal_char(c);
+        }
+        if self.buffer().dir.is_some() {
+            if let Some((_, command_line)) = dired::KEYS.iter().find(|(key, _)| key.chars().eq([c])) {
+                return run_reporting(registry, self, command_line);
+            }
+        }
+        match self.which_key.key(registry, &c.to_string(), now) {
+            KeyResult::Pending => {}
+            KeyResult::Run(command_line) => run_reporting(registry, self, &command_line),
+            KeyResult::Unbound(keys) => keys.chars().for_each(|c| self.normal_char(c)),
+        }
+    }
+
+    // A key with Ctrl or Alt, which only runs what it's bound to
+    fn binding_key(&mut self, registry: &Registry<Editor>, key: &str, now: Instant) {
+        if let KeyResult::Run(command_line) = self.which_key.key(registry, key, now) {
+            run_reporting(registry, self, &command_line);
+        }
+    }
+
+    fn normal_char(&mut self, c: char) {
+        let Input::Run(command) = self.normal.key(c) else { return };
+        let key = command.key;
+        self.run_normal(command, c == '.');
+        self.keep_out_of_folds(key);
+    }
+
+    // Moving up or down onto lines hidden in a fold goes on past it, to the line after it or its
+    // first line. Anything else landing in a fold, like a search match, opens it
+    fn keep_out_of_folds(&mut self, key: char) {
+        let buffer = &mut self.buffers[self.current];
+        let starts = line_starts(&buffer.text);
+        for at in 0..buffer.selections.len() {
+            let head = buffer.selections[at].head;
+            let line = starts.partition_point(|&start| start <= head) - 1;
+            if !buffer.folds.is_hidden(line) {
+                continue;
+            }
+            let past = buffer.folds.closed.iter().filter(|fold| fold.start < line && line < fold.end).map(|fold| fold.end).max().filter(|&end| end < starts.len());
+            let target = match (key, past) {
+                ('j' | 'J', Some(past)) => past,
+                ('j' | 'J' | 'k' | 'K', _) => buffer.folds.visible_line(line),
+                _ => {
+                    buffer.folds.reveal(line);
+                    continue;
+                }
+            };
+            let line_text = buffer.text[starts[target]..].split('\n').next().unwrap_or_default();
+            let column = buffer.text[starts[line]..head].chars().count();
+            let selection = &mut buffer.selections[at];
+            selection.head = starts[target] + line_text.char_indices().nth(column).map_or(line_text.len(), |(at, _)| at);
+            if key.is_ascii_lowercase() {
+                selection.anchor = selection.head;
+            }
+        }
+    }
+
+    // `repeat` for '.', which types what was typed the last time instead of entering insert mode
+    fn run_normal(&mut self, command: Command, repeat: bool) {
+        if let Some(step) = Step::from_command(&command) {
+            if step.is_change() {
+                if let Some(reason) = self.read_only() {
+                    return self.notifications.error(reason);
+                }
+            }
+            let buffer = &mut self.buffers[self.current];
+            // Only edits need the text from before, for their undo step
+            let before = step.is_change().then(|| (buffer.text.clone(), buffer.selections.clone()));
+            let outcome = grammar::apply(step, &mut buffer.text, &mut buffer.selections);
+            let Some((text, selections)) = before else {
+                if !outcome.yanked.is_empty() {
+                    self.yank(outcome.yanked);
+                }
+                return;
+            };
+            if step == Step::Act(Action::Change) && !repeat {
+                self.lines_changed(&text);
+                self.yank(outcome.yanked);
+                return self.start_insert(command, text, selections);
+            }
+            if repeat && outcome.insert {
+                insert::insert(&mut buffer.text, &mut buffer.selections, &command.inserted);
+            }
+            self.changed(&text, selections);
+            if !outcome.yanked.is_empty() {
+                self.yank(outcome.yanked);
+            }
+            self.normal.record_change(command);
+            return;
+        }
+        let result = match (command.prefix, command.key) {
+            (Some('m'), name) => self.set_mark(name),
+            (Some('\''), name) => self.jump_to_mark(name),
+            (None, 'i' | 'a') => {
+                if let Some(reason) = self.read_only() {
+                    return self.notifications.error(reason);
+                }
+                let buffer = &mut self.buffers[self.current];
+                for selection in &mut buffer.selections {
+                    let range = selection.range();
+                    *selection = Selection::cursor(if command.key == 'i' { range.start } else { range.end });
+                }
+                if repeat {
+                    self.edit(|text, selections| insert::insert(text, selections, &command.inserted));
+                } else {
+                    let buffer = &self.buffers[self.current];
+                    let before = (buffer.text.clone(), buffer.selections.clone());
+                    self.start_insert(command, before.0, before.1);
+                }
+                Ok(())
+            }
+            (None, 'u') => self.undo(false),
+            (None, 'U') => self.undo(true),
+            (None, 'p') => {
+                if let Some(reason) = self.read_only() {
+                    return self.notifications.error(reason);
+                }
+                let yanked = self.yanked.clone();
+                self.edit(|text, selections| insert::paste(text, selections, &yanked));
+                Ok(())
+            }
+            (None, 'P') => {
+                let pasted = self.clipboard.paste(clipboard::Selection::Clipboard);
+                self.paste(&pasted);
+                Ok(())
+            }
+            (None, ':') => {
+                self.open_prompt_as(PromptKind::Command, "");
+                Ok(())
+            }
+            (None, '/' | '?') => {
+                self.open_prompt_as(PromptKind::Search { backwards: command.key == '?' }, "");
+                Ok(())
+            }
+            (None, 'n') => self.next_match(false),
+            (None, 'N') => self.next_match(true),
+            (None, '*') => {
+                let buffer = self.buffer();
+                self.search = Search::from_selection(&buffer.text, &buffer.selections[0]);
+                Ok(())
+            }
+            (None, '%') => {
+                let len = self.buffer().text.len();
+                self.buffer_mut().selections = vec![Selection { anchor: 0, head: len, goal: None }];
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        self.notifications.report(result);
+    }
+
+    // m{a-z} marks the cursor in this buffer, m{A-Z} in this file for jumping to from anywhere
+    fn set_mark(&mut self, name: char) -> Result<(), String> {
+        let at = self.buffer().selections[0].head;
+        if name.is_ascii_lowercase() {
+            self.buffer_mut().marks.set(name, at);
+        } else if name.is_ascii_uppercase() {
+            let path = self.buffer().path().ok_or("Only buffers with a file can have uppercase marks")?;
+            self.global_marks.set(name, &self.cwd().join(path), at);
+        } else {
+            return Err(format!("{name} isn't a mark, they're letters"));
+        }
+        Ok(())
+    }
+
+    fn jump_to_mark(&mut self, name: char) -> Result<(), String> {
+        let at = match self.global_marks.get(name) {
+            Some((path, at)) if name.is_ascii_uppercase() => {
+                let name = self.buffer_name(path);
+                self.open(&name)?;
+                at
+            }
+            _ => self.buffer().marks.get(name).ok_or_else(|| format!("Mark {name} isn't set"))?,
+        };
+        let buffer = self.buffer_mut();
+        buffer.selections = vec![Selection::cursor(at.min(buffer.text.len()))];
+        Ok(())
+    }
+
+    fn start_insert(&mut self, command: Command, text: String, selections: Vec<Selection>) {
+        self.inserting = Some((command, text, selections));
+        self.typed.clear();
+        self.mode = Mode::Insert;
+    }
+
+    fn insert_key(&mut self, key: Key) {
+        if self.entry.is_pending() {
+            let c = match key {
+                // Twice picks the character by name instead
+                Key::Ctrl('k') => {
+                    self.entry.key(ESCAPE);
+                    self.picking = Some(Picking::Char(digraphs::picker()));
+                    return;
+                }
+                Key::Char { typed, .. } => typed,
+                Key::Tab => '\t',
+                Key::Enter => '\n',
+                Key::Escape => ESCAPE,
+                _ => return,
+            };
+            if let Entered::Insert(c) = self.entry.key(c) {
+                self.type_char(c);
+            }
+            return;
+        }
+        let buffer = &mut self.buffers[self.current];
+        let edits = match key {
+            Key::Char { typed, .. } => {
+                self.typed.push(typed);
+                buffer.edit_at_heads(|text, selections| insert::insert(text, selections, typed.encode_utf8(&mut [0; 4])))
+            }
+            Key::Enter => {
+                self.typed.push('\n');
+                buffer.edit_at_heads(|text, selections| insert::insert(text, selections, "\n"))
+            }
+            Key::Tab => {
+                self.typed.push('\t');
+                buffer.edit_at_heads(|text, selections| insert::insert(text, selections, "\t"))
+            }
+            Key::Backspace => {
+                self.typed.pop();
+                buffer.edit_at_heads(insert::backspace)
+            }
+            Key::Escape => return self.leave_insert(),
+            Key::Left | Key::Right | Key::Up | Key::Down | Key::Home | Key::End => {
+                let key = match key {
+                    Key::Left => 'h',
+                    Key::Right => 'l',
+                    Key::Up => 'k',
+                    Key::Down => 'j',
+                    Key::Home => '0',
+                    _ => '$',
+                };
+                if let Some(step) = Step::from_command(&Command { count: 1, prefix: None, key, inserted: String::new() }) {
+                    grammar::apply(step, &mut buffer.text, &mut buffer.selections);
+                }
+                return;
+            }
+            Key::Ctrl('k') => return self.entry.start_digraph(),
+            Key::Ctrl('v') => return self.entry.start_literal(),
+            Key::Delete | Key::PageUp | Key::PageDown | Key::Ctrl(_) | Key::Alt(_) | Key::Function { .. } => return,
+        };
+        self.moved_through(&edits);
+    }
+
+    // A character entered some other way than typing it, typed in insert mode or inserted as its
+    // own undo step otherwise
+    fn type_char(&mut self, c: char) {
+        if self.mode != Mode::Insert {
+            if let Some(reason) = self.read_only() {
+                return self.notifications.error(reason);
+            }
+            self.edit(|text, selections| insert::insert(text, selections, c.encode_utf8(&mut [0; 4])));
+            return;
+        }
+        self.typed.push(c);
+        let edits = self.buffers[self.current].edit_at_heads(|text, selections| insert::insert(text, selections, c.encode_utf8(&mut [0; 4])));
+        self.moved_through(&edits);
+    }
+
+    fn leave_insert(&mut self) {
+        self.mode = Mode::Normal;
+        let Some((mut command, text, selections)) = self.inserting.take() else { return };
+        // The lines were recorded as each key edited them
+        if let Some(delta) = delta_between(&text, &self.buffer().text) {
+            self.record_step(delta, selections);
+        }
+        command.inserted = std::mem::take(&mut self.typed);
+        self.normal.record_change(command);
+    }
+
+    // Gives the primary selection the first selection's text when that changed
+    pub fn update_primary(&mut self) {
+        let buffer = self.buffer();
+        let text = &buffer.text[buffer.selections[0].range()];
+        if text == self.primary {
+            return;
+        }
+        self.primary = text.to_string();
+        let result = self.clipboard.selection_changed(&self.primary);
+        self.notifications.report(result);
+    }
+
+    fn yank(&mut self, yanked: Vec<String>) {
+        let result = self.clipboard.copy(clipboard::Selection::Clipboard, &yanked.join("\n"));
+        self.notifications.report(result);
+        self.yanked = yanked;
+    }
+
+    fn undo(&mut self, redo: bool) -> Result<(), String> {
+        let buffer = &mut self.buffers[self.current];
+        let selections = match redo {
+            false => buffer.history.undo(&mut buffer.text).ok_or("Nothing to undo")?,
+            true => buffer.history.redo(&mut buffer.text).ok_or("Nothing to redo")?,
+        };
+        let edits = buffer.replaced_by_step(!redo);
+        buffer.selections = selections;
+        buffer.modified = !buffer.history.is_saved();
+        self.moved_through(&edits);
+        Ok(())
+    }
+
+    fn open_prompt_as(&mut self, kind: PromptKind, line: &str) {
+        self.search_from = self.buffer().selections.clone();
+        self.prompt = Some((kind, Prompt::new(line)));
+        self.mode = Mode::Command;
+    }
+
+    fn close_prompt(&mut self, cancelled: bool) -> Option<(PromptKind, String)> {
+        let (kind, prompt) = self.prompt.take()?;
+        self.mode = Mode::Normal;
+        if cancelled && kind != PromptKind::Command {
+            self.buffer_mut().selections = std::mem::take(&mut self.search_from);
+        }
+        Some((kind, prompt.line))
+    }
+
+    fn prompt_key(&mut self, registry: &Registry<Editor>, key: Key) {
+        let Some((kind, prompt)) = &mut self.prompt else { return };
+        let kind = *kind;
+        match key {
+            Key::Char { typed, .. } => prompt.type_char(typed),
+            Key::Backspace if prompt.line.is_empty() => {
+                self.close_prompt(true);
+                return;
+            }
+            Key::Backspace => prompt.backspace(),
+            Key::Escape => {
+                self.close_prompt(true);
+                return;
+            }
+            Key::Enter => {
+                let Some((kind, line)) = self.close_prompt(false) else { return };
+                match kind {
+                    // Patterns have spaces in them, which would split into arguments
+                    PromptKind::Command if line.starts_with("s/") => {
+                        let result = self.substitute(&line);
+                        self.notifications.report(result);
+                    }
+                    PromptKind::Command => run_reporting(registry, self, &line),
+                    PromptKind::Search { backwards } => {
+                        self.search_entered(line, backwards);
+                        self.keep_out_of_folds('/');
+                    }
+                }
+                return;
+            }
+            Key::Alt('w') if kind != PromptKind::Command => self.whole_word = !self.whole_word,
+            Key::Tab if kind == PromptKind::Command => {
+                let commands: Vec<&str> = registry.names().collect();
+                let buffers: Vec<String> = self.buffers.iter().map(|buffer| buffer.name.clone()).collect();
+                prompt.tab(Sources { commands: &commands, buffers: &buffers, themes: &highlighter::theme_names() }, false);
+            }
+            Key::Up if kind != PromptKind::Command => {
+                if let Some(entry) = self.search_history.older(&prompt.line) {
+                    prompt.line = entry.to_string();
+                }
+            }
+            Key::Down if kind != PromptKind::Command => {
+                if let Some(entry) = self.search_history.newer() {
+                    prompt.line = entry;
+                }
+            }
+            _ => return,
+        }
+        if let PromptKind::Search { backwards } = kind {
+            self.preview_search(backwards);
+        }
+    }
+
+    // Replaces the matches of `:s/pattern/replacement/flags` in the selections, or the whole buffer
+    // when nothing is selected. With the c flag, each is asked about first
+    fn substitute(&mut self, command: &str) -> Result<(), String> {
+        let substitution = Substitution::parse(command).map_err(|e| e.to_string())?;
+        let matches = self.substitution_matches(&substitution);
+        if matches.is_empty() {
+            return Err("Pattern not found".to_string());
+        }
+        if let Some(reason) = self.read_only() {
+            return Err(reason);
+        }
+        if substitution.confirm {
+            self.substituting = Some(Confirm::new(matches));
+            self.select_substituted();
+            return Ok(());
+        }
+        self.replace_matches(&matches);
+        Ok(())
+    }
+
+    fn substitution_matches(&self, substitution: &Substitution) -> Vec<Match> {
+        let buffer = self.buffer();
+        let mut ranges: Vec<Range<usize>> = buffer.selections.iter().map(Selection::range).filter(|range| !range.is_empty()).collect();
+        if ranges.is_empty() {
+            ranges.push(0..buffer.text.len());
+        }
+        ranges.sort_by_key(|range| range.start);
+        substitution.find(&buffer.text, &ranges)
+    }
+
+    fn replace_matches(&mut self, matches: &[Match]) {
+        let count = matches.len();
+        self.edit(|text, selections| {
+            *text = substitute::apply(text, matches);
+            let at = matches.first().map_or(0, |m| m.range.start).min(text.len());
+            *selections = vec![Selection::cursor(at)];
+        });
+        self.notifications.info(format!("Replaced {count} matches"));
+    }
+
+    // Selects the match being asked about
+    fn select_substituted(&mut self) {
+        let Some(range) = self.substituting.as_ref().and_then(Confirm::current).map(|m| m.range.clone()) else { return };
+        self.buffer_mut().selections = vec![Selection { anchor: range.start, head: range.end, goal: None }];
+    }
+
+    fn substitute_key(&mut self, key: Key) {
+        let Some(confirm) = &mut self.substituting else { return };
+        let answer = match key {
+            Key::Char { typed, .. } => Answer::from_key(typed),
+            Key::Escape => Some(Answer::Quit),
+            _ => None,
+        };
+        let Some(answer) = answer else { return };
+        confirm.answer(answer);
+        if !confirm.is_done() {
+            return self.select_substituted();
+        }
+        let accepted = self.substituting.take().map(Confirm::finish).unwrap_or_default();
+        if accepted.is_empty() {
+            let head = self.buffer().selections[0].head;
+            self.buffer_mut().selections = vec![Selection::cursor(head)];
+            return;
+        }
+        self.replace_matches(&accepted);
+    }
+
+    // Replacements to show in place of what they replace: of every match while :s is typed, or of
+    // the one being asked about
+    pub fn substitute_preview(&self) -> Vec<Match> {
+        if let Some(confirm) = &self.substituting {
+            return confirm.current().cloned().into_iter().collect();
+        }
+        let Some((PromptKind::Command, prompt)) = &self.prompt else { return Vec::new() };
+        // Patterns are often not valid regexes halfway through typing them
+        match Substitution::parse(&prompt.line) {
+            Ok(substitution) => self.substitution_matches(&substitution),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // Selects the match the pattern typed so far finds, from where the search started
+    fn preview_search(&mut self, backwards: bool) {
+        let Some((_, prompt)) = &self.prompt else { return };
+        let search = Search { pattern: prompt.line.clone(), whole_word: self.whole_word };
+        let buffer = &mut self.buffers[self.current];
+        buffer.selections = self.search_from.clone();
+        // Patterns are often not valid regexes halfway through typing them
+        let Ok(matches) = search.matches(&buffer.text, 0..buffer.text.len()) else { return };
+        let from = self.search_from.first().map_or(0, |selection| selection.range().start);
+        if let Some(found) = search::next_match(&matches, from, backwards).filter(|_| !search.pattern.is_empty()) {
+            buffer.selections = vec![Selection { anchor: found.start, head: found.end, goal: None }];
+        }
+    }
+
+    fn search_entered(&mut self, pattern: String, backwards: bool) {
+        if pattern.is_empty() {
+            return;
+        }
+        let result = self.search_history.add(&pattern);
+        self.notifications.report(result);
+        let search = Search { pattern, whole_word: self.whole_word };
+        match search.matches(&self.buffer().text, 0..self.buffer().text.len()) {
+            Err(e) => self.notifications.error(e.to_string()),
+            Ok(matches) if matches.is_empty() => self.notifications.warn(format!("No matches for {}", search.pattern)),
+            Ok(_) => {}
+        }
+        self.search = Some(search);
+        self.search_backwards = backwards;
+    }
+
+    // n, and N with `reverse`, which goes the other way from the search
+    fn next_match(&mut self, reverse: bool) -> Result<(), String> {
+        let search = self.search.clone().ok_or("Nothing searched for yet")?;
+        let backwards = self.search_backwards != reverse;
+        let buffer = &mut self.buffers[self.current];
+        let matches = search.matches(&buffer.text, 0..buffer.text.len()).map_err(|e| e.to_string())?;
+        let from = buffer.selections[0].range().start;
+        let found = search::next_match(&matches, from, backwards).ok_or_else(|| format!("No matches for {}", search.pattern))?;
+        buffer.selections = vec![Selection { anchor: found.start, head: found.end, goal: None }];
+        Ok(())
+    }
+
+    // Writes the current buffer to `name`. With `rename`, `name` becomes its file once written, like
+    // :save-as. Otherwise it's a copy, unless it's the buffer's own file
+    fn write_as(&mut self, name: &str, rename: bool) -> Result<(), String> {
+        if self.is_loading(&self.buffer().name) {
+            return Err(format!("{} is still loading", self.buffer().name));
+        }
+        // Formatting goes by the buffer's own file, as the buffer isn't called `name` yet
+        if let Some(path) = self.buffer().path() {
+            format::before_save(self, &path)?;
+        }
+        let buffer = &mut self.buffers[self.current];
+        self.backends.write(name, buffer.text.as_bytes()).map_err(|e| e.to_string())?;
+        if rename {
+            buffer.name = name.to_string();
+            buffer.is_file = true;
+        }
+        if buffer.is_file && buffer.name == name {
+            buffer.modified = false;
+            buffer.history.mark_saved();
+        }
+        if rename {
+            self.parse_syntax(self.current);
+        }
+        // What's committed didn't change, but the lines it's shown on did
+        let written = self.cwd().join(name);
+        if let Some(blame) = self.blame.as_mut().filter(|blame| blame.enabled && blame.path == written) {
+            blame.refresh();
+        }
+        Ok(())
+    }
+
+    // Asks before `command` writes over a file that isn't the buffer's own. True if it asked
+    fn asks_to_overwrite(&mut self, name: &str, force: bool, command: &str) -> bool {
+        let buffer = self.buffer();
+        let elsewhere = !buffer.is_file || buffer.name != name;
+        if elsewhere && !force && self.backends.local_path(name).is_some_and(|path| path.exists()) {
+            self.questions.ask(Question::overwrite(Path::new(name), command));
+            return true;
+        }
+        false
+    }
+
+    // What the status line shows
+    pub fn status_context(&self, now: Instant) -> Context {
+        let buffer = self.buffer();
+        let starts = line_starts(&buffer.text);
+        let line = buffer.cursor_line();
+        let (matches, crlf) = &self.status_found;
+        Context {
+            mode: self.mode,
+            path: buffer.path(),
+            modified: buffer.modified,
+            line,
+            column: visual_column(&buffer.text[starts[line]..buffer.cursor()]),
+            encoding: "utf-8".to_string(),
+            crlf: *crlf,
+            search: search::match_count(matches, buffer.cursor()),
+            branch: self.branch.as_ref().map(|(branch, _)| branch.clone()),
+            lsp: self.language_server_name(),
+            progress: self.progress.status(self.started, now),
+            time: Some(statusline::local_time(SystemTime::now())),
+            ..Default::default()
+        }
+        .with_message(self.notifications.latest(now))
+    }
+
+    // The command of the running language server of the focused buffer
+    fn language_server_name(&self) -> Option<String> {
+        let grammar = self.languages.for_path(&self.buffer().path()?)?;
+        Some(self.language_servers.get(&grammar.name)?.as_ref()?.command.clone())
+    }
+
+    // What's drawn in place of the status line while a prompt is open or a question asked
+    pub fn prompt_line(&self) -> Option<String> {
+        if let Some(question) = self.questions.current() {
+            return Some(question.line());
+        }
+        if let Some(shown) = self.entry.shown() {
+            return Some(shown);
+        }
+        if let Some(m) = self.substituting.as_ref().and_then(Confirm::current) {
+            return Some(format!("Replace with {}? (y/n/a/q)", m.replacement));
+        }
+        let (kind, prompt) = self.prompt.as_ref()?;
+        let start = match kind {
+            PromptKind::Command => ':',
+            PromptKind::Search { backwards: false } => '/',
+            PromptKind::Search { backwards: true } => '?',
+        };
+        let whole_word = if *kind != PromptKind::Command && self.whole_word { "  (whole words)" } else { "" };
+        Some(format!("{start}{}{whole_word}", prompt.line))
+    }
+}
+
+// write [--force] [path]. Writing somewhere another file already is asks first. Writing to a path
+// other than the buffer's file writes a copy, except for buffers without a file, which it names
+fn write_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    let (force, args) = match args {
+        ["--force", rest @ ..] => (true, rest),
+        _ => (false, args),
+    };
+    let buffer = ctx.buffer();
+    let name = match args {
+        [] if buffer.is_file => buffer.name.clone(),
+        [] => return Err("No file name, use write <path>".to_string()),
+        [name] => name.to_string(),
+        _ => return Err("Usage: write [--force] [path]".to_string()),
+    };
+    let rename = !buffer.is_file;
+    if ctx.asks_to_overwrite(&name, force, "write") {
+        return Ok(());
+    }
+    ctx.write_as(&name, rename)
+}
+
+// save-as [--force] <path>, writing the buffer to `path`, which is its file from then on
+fn save_as_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    let (force, name) = match args {
+        ["--force", name] => (true, name),
+        [name] => (false, name),
+        _ => return Err("Usage: save-as [--force] <path>".to_string()),
+    };
+    if ctx.asks_to_overwrite(name, force, "save-as") {
+        return Ok(());
+    }
+    ctx.write_as(name, true)
+}
+
+// quit [--force|--save]. With unsaved changes, asks whether to save them first
+fn quit_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    let unsaved = |ctx: &Editor| ctx.buffers.iter().filter(|buffer| buffer.modified && buffer.is_file).map(|buffer| buffer.name.clone()).collect::<Vec<_>>();
+    match args {
+        ["--force"] => {}
+        ["--save"] => {
+            autosave::save_modified(ctx);
+            if !unsaved(ctx).is_empty() {
+                return Err("Not every buffer could be saved".to_string());
+            }
+        }
+        [] => {
+            let names = unsaved(ctx);
+            if !names.is_empty() {
+                ctx.questions.ask(Question::quit_unsaved(&names));
+                return Ok(());
+            }
+        }
+        _ => return Err("Usage: quit [--force|--save]".to_string()),
+    }
+    ctx.quit = true;
+    Ok(())
+}
+
+// edit <path>
+fn edit_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    let [name] = args else { return Err("Usage: edit <path>".to_string()) };
+    ctx.open(name)
+}
+
+// new, an empty buffer that save-as names. The scratch buffer if it's still empty
+fn new_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: new".to_string());
+    }
+    ctx.start_screen = None;
+    ctx.current = match ctx.buffers.iter().position(|buffer| buffer.name == SCRATCH && buffer.text.is_empty()) {
+        Some(at) => at,
+        None => {
+            ctx.buffers.push(Buffer::new(SCRATCH, String::new(), false));
+            ctx.buffers.len() - 1
+        }
+    };
+    Ok(())
+}
+
+// buffer <name>
+fn buffer_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    let [name] = args else { return Err("Usage: buffer <name>".to_string()) };
+    ctx.current = ctx.buffers.iter().position(|buffer| buffer.name == *name).ok_or_else(|| format!("No buffer {name}"))?;
+    Ok(())
+}
+
+// reload [--force], reading the file again. Unsaved changes are only thrown away when forced
+fn reload_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    let buffer = ctx.buffer();
+    let path = buffer.path().ok_or("Not a file")?;
+    match args {
+        [] if buffer.modified => {
+            ctx.questions.ask(Question::reload_changed(&path, true));
+            return Ok(());
+        }
+        [] | ["--force"] => {}
+        _ => return Err("Usage: reload [--force]".to_string()),
+    }
+    let text = ctx.backends.read_text(&buffer.name).map_err(|e| e.to_string())?;
+    ctx.edit(|old, selections| {
+        *old = text;
+        normalize(old, selections);
+    });
+    let buffer = ctx.buffer_mut();
+    buffer.modified = false;
+    buffer.history.mark_saved();
+    Ok(())
+}
+
+// recover <report>, opening the buffers saved when rakoune crashed with the text they had
+fn recover_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    let [report] = args else { return Err("Usage: recover <crash report>".to_string()) };
+    let recovered = crash::recovered(Path::new(report));
+    if recovered.is_empty() {
+        return Err(format!("No buffers were saved with {report}"));
+    }
+    for (name, path) in recovered {
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+        ctx.current = match ctx.buffers.iter().position(|buffer| buffer.name == name) {
+            Some(at) => at,
+            // Scratch buffers, which have no file to start from
+            None if name.starts_with('*') => {
+                ctx.buffers.push(Buffer::new(&name, String::new(), false));
+                ctx.buffers.len() - 1
+            }
+            None => ctx.load(&name)?,
+        };
+        ctx.edit(|old, selections| {
+            *old = text;
+            normalize(old, selections);
+        });
+    }
+    Ok(())
+}
+
+// trust-project, using the config of the project found when opening a file from now on
+fn trust_project_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: trust-project".to_string());
+    }
+    let project = ctx.pending_project.take().ok_or("No project is waiting to be trusted")?;
+    let saved = ctx.trust.trust(&project);
+    ctx.use_project(project);
+    if std::mem::take(&mut ctx.restore_when_trusted) {
+        ctx.restore_session_at_start();
+    }
+    saved.map_err(|e| format!("Using it, but couldn't remember that: {e}"))
+}
+
+// ignore-project, not using the config of the project found. It isn't asked about again
+fn ignore_project_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: ignore-project".to_string());
+    }
+    // Staying pending is what keeps it from being asked about again, so the global session is
+    // restored directly
+    if std::mem::take(&mut ctx.restore_when_trusted) {
+        session::restore(ctx)?;
+    }
+    Ok(())
+}
+
+// insert-char, picking a character to insert by its name or digraph. Ctrl+K twice in insert mode does the same
+fn insert_char_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: insert-char".to_string());
+    }
+    ctx.picking = Some(Picking::Char(digraphs::picker()));
+    Ok(())
+}
+
+// select-matches [view]: every match of the search in the buffer, or only in the viewport
+fn select_matches_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    let search = ctx.search.clone().ok_or("Nothing searched for yet")?;
+    let buffer = &ctx.buffers[ctx.current];
+    let range = match args {
+        [] => 0..buffer.text.len(),
+        ["view"] => search::lines_bytes(&buffer.text, ctx.visible_lines.clone()),
+        _ => return Err("Usage: select-matches [view]".to_string()),
+    };
+    let selections = search::select_matches(&buffer.text, &search, range).map_err(|e| e.to_string())?;
+    ctx.buffer_mut().selections = selections.ok_or_else(|| format!("No matches for {}", search.pattern))?;
+    Ok(())
+}
+
+// terminal [program args...], the shell from $SHELL without a program
+fn terminal_command(ctx: &mut Editor, args: &[&str]) -> Result<(), String> {
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string());
+    let (program, args) = args.split_first().map_or((shell.as_str(), &[][..]), |(program, args)| (program, args));
+    let mut command = std::process::Command::new(program);
+    command.args(args);
+    if let Some(project) = &ctx.project {
+        command.current_dir(&project.root);
+    }
+    // The size is only a guess until App fits it to the window
+    ctx.terminal = Some(Terminal::spawn(&mut command, 80, 24).map_err(|e| format!("Couldn't start {program}: {e}"))?);
+    Ok(())
+}
+
+pub fn register(registry: &mut Registry<Editor>) {
+    registry.add_builtin("write", write_command);
+    registry.add_alias("w", "write");
+    registry.add_builtin("save-as", save_as_command);
+    registry.add_builtin("quit", quit_command);
+    registry.add_alias("q", "quit");
+    registry.add_builtin("edit", edit_command);
+    registry.add_alias("e", "edit");
+    registry.add_builtin("new", new_command);
+    registry.add_builtin("buffer", buffer_command);
+    registry.add_alias("b", "buffer");
+    registry.add_builtin("reload", reload_command);
+    registry.add_builtin("recover", recover_command);
+    registry.add_builtin("trust-project", trust_project_command);
+    registry.add_builtin("ignore-project", ignore_project_command);
+    registry.add_builtin("select-matches", select_matches_command);
+    registry.add_builtin("terminal", terminal_command);
+    registry.add_builtin("insert-char", insert_char_command);
+    notifications::register(registry);
+    appearance::register(registry);
+    blame::register(registry);
+    dap::register(registry);
+    diagnostics::register(registry);
+    dired::register(registry);
+    export::register(registry);
+    filetree::register(registry);
+    folding::register(registry);
+    format::register(registry);
+    hover::register(registry);
+    jobs::register(registry);
+    keymap::register(registry);
+    markdown::register(registry);
+    memory::register(registry);
+    menubar::register(registry);
+    palette::register(registry);
+    progress::register(registry);
+    refactor::register(registry);
+    richtext::register(registry);
+    session::register(registry);
+    substitute::register(registry);
+    symbols::register(registry);
+    syntax::register(registry);
+    tabs::register(registry);
+    welcome::register(registry);
+    zen::register(registry);
+}
+
+impl MessagesHost for Editor {
+    fn notifications(&mut self) -> &mut Notifications {
+        &mut self.notifications
+    }
+
+    fn show_report(&mut self, title: &str, text: String) {
+        let at = match self.buffers.iter().position(|buffer| buffer.name == title) {
+            Some(at) => at,
+            None => {
+                self.buffers.push(Buffer::new(title, String::new(), false));
+                self.buffers.len() - 1
+            }
+        };
+        self.buffers[at] = Buffer::new(title, text, false);
+        self.current = at;
+    }
+}
+
+impl ConfirmHost for Editor {
+    fn questions(&mut self) -> &mut Questions {
+        &mut self.questions
+    }
+}
+
+impl EditHost for Editor {
+    fn buffer_text(&mut self, path: &Path) -> Result<String, String> {
+        let at = self.load(&path.display().to_string())?;
+        Ok(self.buffers[at].text.clone())
+    }
+
+    fn set_buffer_text(&mut self, path: &Path, text: String, edits: &[(Range<usize>, usize)]) {
+        let Some(at) = self.buffers.iter().position(|buffer| buffer.name == path.display().to_string()) else { return };
+        let current = std::mem::replace(&mut self.current, at);
+        self.edit(|old, selections| {
+            *old = text;
+            for selection in selections.iter_mut() {
+                for (range, new_len) in edits {
+                    selection.anchor = shift_through_edit(selection.anchor, range, *new_len);
+                    selection.head = shift_through_edit(selection.head, range, *new_len);
+                }
+            }
+            normalize(old, selections);
+        });
+        self.current = current;
+    }
+}
+
+impl ExportHost for Editor {
+    fn fontstack(&self) -> Option<&FontStack> {
+        self.fontstack.as_deref()
+    }
+
+    fn layout_settings(&self) -> &LayoutSettings {
+        &self.layout_settings
+    }
+
+    fn export_source(&self) -> Option<(String, Colors)> {
+        Some((self.buffer().text.clone(), self.highlight_colors()))
+    }
+}
+
+impl FileTreeHost for Editor {
+    fn project(&self) -> Option<Project> {
+        self.project.clone()
+    }
+
+    // Only :tree asks for it, and the tree it opens takes the keys
+    fn file_tree(&mut self) -> &mut Option<FileTree> {
+        self.tree_focused = true;
+        &mut self.file_tree
+    }
+
+    fn icons(&self) -> Icons {
+        self.icons
+    }
+
+    fn current_path(&self) -> Option<PathBuf> {
+        Some(self.cwd().join(self.buffer().path()?))
+    }
+}
+
+impl FormatHost for Editor {
+    fn current_path(&self) -> Option<PathBuf> {
+        self.buffer().path()
+    }
+
+    fn formatter(&self, path: &Path) -> Option<(String, PathBuf)> {
+        let project = self.project.as_ref()?;
+        Some((project.formatter(path)?.to_string(), project.root.clone()))
+    }
+
+    fn format_on_save(&self, path: &Path) -> bool {
+        self.project.as_ref().map_or(self.format_on_save, |project| project.format_file_on_save(path, self.format_on_save))
+    }
+
+    fn request_formatting(&mut self, path: &Path) -> Result<(), String> {
+        self.ask_language_server(path, "documentFormattingProvider", "formatting", |server, path, _| server.formatting(path))
+    }
+}
+
+impl AutoSaveHost for Editor {
+    fn modified_buffers(&self) -> Vec<Option<PathBuf>> {
+        self.buffers.iter().filter(|buffer| buffer.modified).map(Buffer::path).collect()
+    }
+
+    fn write_buffer(&mut self, path: &Path) -> Result<(), String> {
+        let name = path.display().to_string();
+        let at = self.buffers.iter().position(|buffer| buffer.name == name).ok_or_else(|| format!("{name} isn't open"))?;
+        self.backends.write(&name, self.buffers[at].text.as_bytes()).map_err(|e| e.to_string())?;
+        self.buffers[at].modified = false;
+        self.buffers[at].history.mark_saved();
+        Ok(())
+    }
+}
+
+impl DebugHost for Editor {
+    fn debugger(&mut self) -> &mut Option<Debugger> {
+        &mut self.debugger
+    }
+
+    fn breakpoints(&mut self) -> &mut BTreeMap<PathBuf, BTreeSet<usize>> {
+        &mut self.breakpoints
+    }
+
+    fn cursor_location(&self) -> Option<(PathBuf, usize)> {
+        Some((self.cwd().join(self.buffer().path()?), self.buffer().cursor_line() + 1))
+    }
+
+    fn cwd(&self) -> PathBuf {
+        self.root()
+    }
+
+    fn open_debug_panes(&mut self) {
+        self.debug_panes = true;
+    }
+}
+
+impl DiagnosticsHost for Editor {
+    fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    fn buffer_text(&self, path: &Path) -> Option<String> {
+        let buffer = self.buffers.iter().find(|buffer| buffer.path().as_deref() == Some(path))?;
+        Some(buffer.text.clone())
+    }
+
+    fn open_list(&mut self, list: DiagnosticList) {
+        MessagesHost::show_report(self, "*diagnostics*", list.text());
+        self.buffer_mut().diagnostic_list = Some(list);
+    }
+}
+
+// A buffer showing `dir`, with the cursor at the start of `line`
+fn listing(dir: DirBuffer, line: usize) -> Buffer {
+    let mut buffer = Buffer::new(&dir.dir, dir.text(), false);
+    let start = line_starts(&buffer.text).get(line).copied().unwrap_or(0);
+    buffer.selections = vec![Selection::cursor(start)];
+    buffer.dir = Some(dir);
+    buffer
+}
+
+impl DirHost for Editor {
+    fn backends(&mut self) -> &mut Backends {
+        &mut self.backends
+    }
+
+    fn dir_buffer(&mut self) -> Option<(&mut DirBuffer, usize)> {
+        let line = self.buffer().cursor_line();
+        Some((self.buffer_mut().dir.as_mut()?, line))
+    }
+
+    fn show_dir(&mut self, dir: DirBuffer, line: usize) {
+        let buffer = listing(dir, line);
+        match self.buffer().dir.is_some() {
+            true => *self.buffer_mut() = buffer,
+            false => {
+                self.buffers.push(buffer);
+                self.current = self.buffers.len() - 1;
+            }
+        }
+    }
+
+    fn open_file(&mut self, name: &str) -> Result<(), String> {
+        self.open(name)
+    }
+
+    fn open_prompt(&mut self, line: &str) {
+        self.open_prompt_as(PromptKind::Command, line);
+    }
+
+    fn confirm(&mut self, question: &str, command_line: &str) {
+        self.questions.ask(Question::yes_no(question, command_line));
+    }
+}
+
+impl HoverHost for Editor {
+    fn request_hover(&mut self) -> Result<(), String> {
+        let path = self.buffer().path().ok_or_else(|| format!("No language server for {}", self.buffer().name))?;
+        self.ask_language_server(&path, "hoverProvider", "hover", |server, path, buffer| server.hover(path, &buffer.text, buffer.cursor()))
+    }
+}
+
+impl KeyEventsHost for Editor {
+    fn key_event_log(&mut self) -> &mut KeyEventLog {
+        &mut self.key_event_log
+    }
+
+    fn show_key_events(&mut self) {
+        let text = self.key_event_log.text();
+        MessagesHost::show_report(self, "*key-events*", text);
+    }
+}
+
+impl MemoryHost for Editor {
+    fn memory_usage(&self) -> Usage {
+        let mut usage = self.render_usage.clone();
+        for buffer in &self.buffers {
+            usage.add(Category::Text, &buffer.name, buffer.text.capacity());
+            usage.add(Category::Undo, &buffer.name, buffer.history.memory_usage());
+        }
+        for (path, bytes) in font::leaked_files() {
+            usage.add(Category::Fonts, path.file_name().unwrap_or_default().to_string_lossy(), bytes);
+        }
+        usage
+    }
+
+    fn trim_caches(&mut self) {
+        self.trim_requested = true;
+    }
+
+    fn memory_config(&self) -> &MemoryConfig {
+        &self.memory
+    }
+
+    fn show_report(&mut self, title: &str, text: String) {
+        MessagesHost::show_report(self, title, text);
+    }
+}
+
+impl OptionsHost for Editor {
+    fn option(&self, name: &str) -> Option<bool> {
+        match name {
+            "wrap" => Some(self.wrap),
+            "line-numbers" => Some(self.line_numbers),
+            "zen" => Some(self.zen.enabled),
+            "zen-dim" => Some(self.zen.config.dim_others),
+            "whole-word" => Some(self.whole_word),
+            _ => None,
+        }
+    }
+
+    fn set_option(&mut self, name: &str, on: bool) {
+        match name {
+            "wrap" => self.wrap = on,
+            "line-numbers" => self.line_numbers = on,
+            "zen" => self.zen.enabled = on,
+            "zen-dim" => self.zen.config.dim_others = on,
+            "whole-word" => self.whole_word = on,
+            _ => {}
+        }
+    }
+}
+
+impl FoldHost for Editor {
+    fn available_folds(&self) -> Vec<Fold> {
+        let buffer = self.buffer();
+        buffer.syntax.as_ref().map_or_else(|| folding::indent_folds(&buffer.text), SyntaxTree::folds)
+    }
+
+    fn fold_state(&mut self) -> &mut FoldState {
+        &mut self.buffer_mut().folds
+    }
+
+    fn cursor_line(&self) -> usize {
+        self.buffer().cursor_line()
+    }
+
+    fn cursor_out_of_folds(&mut self) {
+        self.keep_out_of_folds('k');
+    }
+}
+
+impl SyntaxHost for Editor {
+    fn text(&self) -> &str {
+        &self.buffer().text
+    }
+
+    fn syntax(&self) -> Option<&SyntaxTree> {
+        self.buffer().syntax.as_ref()
+    }
+
+    fn bracket_tree(&self) -> Option<BracketTree> {
+        let buffer = self.buffer();
+        buffer.path().and_then(|path| self.languages.for_path(&path)).map(|grammar| BracketTree::new(grammar, &buffer.text))
+    }
+
+    fn selection_ranges(&self) -> Vec<Range<usize>> {
+        self.buffer().selections.iter().map(Selection::range).collect()
+    }
+
+    fn select_ranges(&mut self, ranges: Vec<Range<usize>>) {
+        self.buffer_mut().selections = ranges.into_iter().map(|range| Selection { anchor: range.start, head: range.end, goal: None }).collect();
+    }
+
+    fn expansions(&mut self) -> &mut ExpansionHistory {
+        &mut self.expansions
+    }
+}
+
+impl ReplaceHost for Editor {
+    fn files_to_search(&mut self) -> Vec<PathBuf> {
+        match self.scanned_files() {
+            Some(files) => files,
+            None => self.buffers.iter().filter_map(Buffer::path).map(|path| self.cwd().join(path)).collect(),
+        }
+    }
+
+    fn start_search(&mut self, search: FileSearch) {
+        self.searching = Some(search);
+    }
+
+    fn show_results(&mut self, results: SearchResults) {
+        MessagesHost::show_report(self, "*search results*", results.text());
+        self.buffer_mut().search_results = Some(results);
+    }
+
+    fn search_results(&self) -> Option<&SearchResults> {
+        self.buffer().search_results.as_ref()
+    }
+
+    fn unsaved(&self, path: &Path) -> Option<String> {
+        let cwd = self.cwd();
+        let buffer = self.buffers.iter().find(|buffer| buffer.modified && buffer.path().is_some_and(|open| cwd.join(open) == path))?;
+        Some(format!("{} has unsaved changes", buffer.name))
+    }
+
+    // Each buffer reads its file again as one undo step, so the replacement can be undone in it too
+    fn files_changed(&mut self, paths: &[PathBuf]) {
+        let (current, cwd) = (self.current, self.cwd());
+        for at in 0..self.buffers.len() {
+            if !self.buffers[at].path().is_some_and(|open| paths.contains(&cwd.join(open))) {
+                continue;
+            }
+            let text = match self.backends.read_text(&self.buffers[at].name) {
+                Ok(text) => text,
+                Err(e) => {
+                    self.notifications.error(e.to_string());
+                    continue;
+                }
+            };
+            self.current = at;
+            self.edit(|old, selections| {
+                *old = text;
+                normalize(old, selections);
+            });
+            self.buffer_mut().modified = false;
+            self.buffer_mut().history.mark_saved();
+        }
+        self.current = current;
+    }
+
+    fn replace_undo(&mut self) -> &mut Vec<FileUndo> {
+        &mut self.replace_undo
+    }
+
+    fn show_report(&mut self, title: &str, text: String) {
+        MessagesHost::show_report(self, title, text);
+    }
+}
+
+impl AppearanceHost for Editor {
+    fn theme(&self) -> Theme {
+        self.theme
+    }
+
+    fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    fn font(&self) -> PathBuf {
+        self.font.clone()
+    }
+
+    fn set_font(&mut self, path: PathBuf, preview: bool) {
+        if !preview {
+            self.font = path.clone();
+        }
+        self.requested_font = Some(path);
+    }
+
+    fn pick_theme(&mut self, picker: Picker<Theme>) {
+        self.picking = Some(Picking::Theme(picker));
+    }
+
+    fn pick_font(&mut self, picker: Picker<PathBuf>) {
+        self.picking = Some(Picking::Font(picker));
+    }
+}
+
+impl PaletteHost for Editor {
+    fn syntax_palette(&self) -> Vec<(String, [f32; 4])> {
+        self.theme.faces()
+    }
+
+    fn theme_background(&self) -> [f32; 4] {
+        render::BACKGROUND
+    }
+
+    fn show_report(&mut self, title: &str, text: String) {
+        MessagesHost::show_report(self, title, text);
+    }
+}
+
+impl PreviewHost for Editor {
+    fn current_buffer(&self) -> Option<(PathBuf, String)> {
+        Some((self.buffer().path()?, self.buffer().text.clone()))
+    }
+
+    fn open_split(&mut self, pane: PreviewPane) {
+        self.preview = Some((pane, self.buffer().version));
+    }
+
+    fn close_split(&mut self) -> bool {
+        self.preview.take().is_some()
+    }
+}
+
+impl RecentHost for Editor {
+    fn recent_files(&self) -> &RecentFiles {
+        &self.recent
+    }
+
+    fn current_path(&self) -> Option<PathBuf> {
+        Some(self.cwd().join(self.buffer().path()?))
+    }
+
+    fn project_files(&mut self) -> Option<(Vec<PathBuf>, PathBuf)> {
+        let files = self.scanned_files()?;
+        Some((files, self.project.as_ref()?.root.clone()))
+    }
+
+    fn open_picker(&mut self, picker: Picker<PathBuf>) {
+        if let Some(path) = picker.current() {
+            self.file_previews.show(&self.backends, &self.languages, path);
+        }
+        self.picking = Some(Picking::File(picker));
+    }
+}
+
+impl RefactorHost for Editor {
+    fn word_at_cursor(&self) -> Option<String> {
+        let buffer = self.buffer();
+        let word = &buffer.text[word_around(&buffer.text, &(buffer.cursor()..buffer.cursor()))];
+        (!word.is_empty()).then(|| word.to_string())
+    }
+
+    fn open_prompt(&mut self, line: &str) {
+        self.open_prompt_as(PromptKind::Command, line);
+    }
+
+    fn request_rename(&mut self, new_name: &str) -> Result<(), String> {
+        let path = self.buffer().path().ok_or_else(|| format!("No language server to rename in {}", self.buffer().name))?;
+        self.ask_language_server(&path, "renameProvider", "rename", |server, path, buffer| server.rename(path, &buffer.text, buffer.cursor(), new_name))
+    }
+
+    // For the first selection, with the diagnostics it touches
+    fn request_code_actions(&mut self) -> Result<(), String> {
+        let path = self.buffer().path().ok_or_else(|| format!("No language server for code actions in {}", self.buffer().name))?;
+        let range = self.buffer().selections[0].range();
+        let diagnostics: Vec<_> = self.diagnostics.get(&path).iter().filter(|diagnostic| diagnostic.range.start <= range.end && range.start <= diagnostic.range.end).cloned().collect();
+        self.ask_language_server(&path, "codeActionProvider", "code actions", |server, path, buffer| server.code_actions(path, &buffer.text, range, &diagnostics))
+    }
+
+    fn execute_command(&mut self, command: &refactor::Command) -> Result<(), String> {
+        let path = self.buffer().path().ok_or_else(|| format!("No language server to run {}", command.name))?;
+        self.ask_language_server(&path, "executeCommandProvider", "commands", |server, _, _| server.execute_command(command))
+    }
+}
+
+impl RichCopyHost for Editor {
+    // Selections are joined by newlines, like yanking them all would paste them
+    fn selected_highlights(&self) -> Option<(String, Colors)> {
+        let buffer = self.buffer();
+        let colors = self.highlight_colors();
+        let (mut text, mut selected) = (String::new(), Colors::new());
+        for range in buffer.selections.iter().map(Selection::range).filter(|range| !range.is_empty()) {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            // From bytes of the buffer to bytes of the copied text
+            let moved = |at: usize| at.clamp(range.start, range.end) - range.start + text.len();
+            let inside = colors.iter().filter(|(colored, _)| colored.start < range.end && range.start < colored.end);
+            selected.extend(inside.map(|(colored, color)| (moved(colored.start)..moved(colored.end), *color)));
+            text.push_str(&buffer.text[range]);
+        }
+        (!text.is_empty()).then_some((text, selected))
+    }
+
+    fn copy_style(&self) -> Style {
+        let font_family = self.fontstack.as_ref().and_then(|fontstack| fontstack.faces.first()).map_or("monospace".to_string(), |face| face.name.clone());
+        Style { font_family, foreground: render::TEXT, background: render::BACKGROUND }
+    }
+
+    fn clipboard(&mut self) -> &mut Clipboard {
+        &mut self.clipboard
+    }
+}
+
+impl ProgressHost for Editor {
+    fn progress(&mut self) -> &mut ProgressTracker {
+        &mut self.progress
+    }
+}
+
+impl JobsHost for Editor {
+    fn project_job(&self, name: &str) -> Option<JobSpec> {
+        self.project.as_ref()?.job(name)
+    }
+
+    fn job_dir(&self) -> PathBuf {
+        self.root()
+    }
+
+    fn start_job(&mut self, spec: JobSpec) -> Result<(), String> {
+        self.close_job();
+        let name = spec.name.clone();
+        self.job = Some(Job::start(spec).map_err(|e| format!("Couldn't start {name}: {e}"))?);
+        Ok(())
+    }
+
+    fn job(&mut self) -> Option<&mut Job> {
+        self.job.as_mut()
+    }
+
+    fn jump_to_error(&mut self, location: Location) -> Result<(), String> {
+        let path = PathBuf::from(self.buffer_name(&location.path));
+        self.jump_to(&path, location.line.saturating_sub(1), location.col.saturating_sub(1))
+    }
+
+    fn close_job(&mut self) {
+        if let Some(job) = self.job.take() {
+            job.kill();
+        }
+    }
+}
+
+impl TabsHost for Editor {
+    fn tab_bar(&mut self) -> &mut TabBar {
+        self.sync_tabs();
+        &mut self.tab_bar
+    }
+
+    fn select_tab(&mut self, idx: usize) {
+        self.current = idx.min(self.buffers.len() - 1);
+    }
+
+    fn close_tab(&mut self, idx: usize) -> Result<(), String> {
+        let buffer = self.buffers.get(idx).ok_or("No such tab")?;
+        if buffer.modified {
+            return Err(format!("{} has unsaved changes", buffer.name));
+        }
+        self.sync_tabs();
+        self.tab_bar.close(idx);
+        self.buffers.remove(idx);
+        if self.buffers.is_empty() {
+            self.buffers.push(Buffer::new(SCRATCH, String::new(), false));
+        }
+        self.current = self.tab_bar.active.min(self.buffers.len() - 1);
+        Ok(())
+    }
+}
+
+impl SessionHost for Editor {
+    fn project(&self) -> Option<&Project> {
+        self.project.as_ref()
+    }
+
+    fn project_sessions(&self) -> bool {
+        self.project_sessions
+    }
+
+    fn sessions_dir(&self) -> Option<PathBuf> {
+        self.sessions_dir.clone()
+    }
+
+    // Every open file with its cursor. Buffers aren't split, so there's no layout to keep
+    fn current_session(&self) -> Session {
+        let cwd = self.cwd();
+        let files: Vec<(usize, OpenFile)> = self
+            .buffers
+            .iter()
+            .enumerate()
+            .filter_map(|(at, buffer)| {
+                let cursor_line = buffer.cursor_line();
+                let start = line_starts(&buffer.text)[cursor_line];
+                let cursor_column = buffer.text[start..buffer.cursor()].chars().count();
+                let top_line = if at == self.current { self.visible_lines.start } else { cursor_line };
+                Some((at, OpenFile { path: cwd.join(buffer.path()?), cursor_line, cursor_column, top_line }))
+            })
+            .collect();
+        let focused = files.iter().position(|(at, _)| *at == self.current).unwrap_or(0);
+        Session { root: None, files: files.into_iter().map(|(_, file)| file).collect(), layout: None, focused }
+    }
+
+    // App scrolls to the cursor, which keeps the top line close enough
+    fn restore_session(&mut self, session: Session) {
+        for file in &session.files {
+            let result = self.jump_to(Path::new(&self.buffer_name(&file.path)), file.cursor_line, file.cursor_column);
+            self.notifications.report(result);
+        }
+        if let Some(file) = session.files.get(session.focused) {
+            let result = self.open(&self.buffer_name(&file.path));
+            self.notifications.report(result);
+        }
+    }
+}
+
+impl SymbolHost for Editor {
+    fn current_buffer(&self) -> Option<(PathBuf, String)> {
+        Some((self.buffer().path()?, self.buffer().text.clone()))
+    }
+
+    fn cursor_line(&self) -> usize {
+        self.buffer().cursor_line()
+    }
+
+    fn open_buffers(&self) -> Vec<(PathBuf, String)> {
+        self.buffers.iter().filter_map(|buffer| Some((buffer.path()?, buffer.text.clone()))).collect()
+    }
+
+    fn request_document_symbols(&mut self, path: &Path) -> Result<bool, String> {
+        if !self.has_language_server(path, "documentSymbolProvider") {
+            return Ok(false);
+        }
+        self.ask_language_server(path, "documentSymbolProvider", "document symbols", |server, path, _| server.document_symbols(path)).map(|_| true)
+    }
+
+    // From the server of the focused buffer
+    fn request_workspace_symbols(&mut self, query: &str) -> Result<bool, String> {
+        let Some(path) = self.buffer().path() else { return Ok(false) };
+        if !self.has_language_server(&path, "workspaceSymbolProvider") {
+            return Ok(false);
+        }
+        self.ask_language_server(&path, "workspaceSymbolProvider", "workspace symbols", |server, _, _| server.workspace_symbols(query)).map(|_| true)
+    }
+
+    fn open_picker(&mut self, picker: Picker<(PathBuf, usize)>) {
+        self.picking = Some(Picking::Location(picker));
+    }
+
+    fn toggle_outline(&mut self, pane: OutlinePane) {
+        self.outline = match self.outline.take() {
+            Some(_) => None,
+            None => Some((pane, self.buffer().version)),
+        };
+    }
+}
+
+impl BlameHost for Editor {
+    fn blame(&mut self) -> &mut Option<Blame> {
+        &mut self.blame
+    }
+
+    fn blamed_path(&self) -> Option<PathBuf> {
+        Some(self.cwd().join(self.buffer().path()?))
+    }
+}
+
+impl ZenHost for Editor {
+    fn zen(&mut self) -> &mut ZenMode {
+        &mut self.zen
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn typed(editor: &mut Editor, registry: &Registry<Editor>, keys: &str) {
+        let now = Instant::now();
+        for c in keys.chars() {
+            let key = match c {
+                '\u{1b}' => Key::Escape,
+                '\n' => Key::Enter,
+                c => Key::char(c),
+            };
+            editor.key(registry, key, now);
+        }
+    }
+
+    #[test]
+    fn typing_undo_and_writing() {
+        let dir = std::env::temp_dir().join(format!("rakoune-editor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt").display().to_string();
+        std::fs::write(&path, "one two\n").unwrap();
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+
+        editor.open(&path).unwrap();
+        assert_eq!(editor.buffers.len(), 1);
+        typed(&mut editor, &registry, "wiold \u{1b}");
+        assert_eq!(editor.buffer().text, "one old two\n");
+        // One undo step for everything typed, and '.' types it again
+        typed(&mut editor, &registry, "u");
+        assert_eq!(editor.buffer().text, "one two\n");
+        typed(&mut editor, &registry, "U0.");
+        assert_eq!(editor.buffer().text, "old one old two\n");
+        assert_eq!(editor.status_context(Instant::now()).column, 4);
+
+        typed(&mut editor, &registry, "/T\n");
+        assert!(editor.notifications.log.last().unwrap().text.contains("No matches"));
+        typed(&mut editor, &registry, "/o\nn");
+        assert_eq!(editor.buffer().selections, vec![Selection { anchor: 14, head: 15, goal: None }]);
+        editor.update_status();
+        assert_eq!(editor.status_context(Instant::now()).search, Some((4, 4)));
+        // Found again once the search changes
+        typed(&mut editor, &registry, "/w\n");
+        editor.update_status();
+        assert_eq!(editor.status_context(Instant::now()).search, Some((1, 1)));
+
+        // Quitting with unsaved changes asks, and saving writes the file
+        typed(&mut editor, &registry, ":q\n");
+        assert!(!editor.quit);
+        typed(&mut editor, &registry, "s");
+        assert!(editor.quit);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old one old two\n");
+
+        // Writing over another file asks too
+        let other = dir.join("other.txt").display().to_string();
+        std::fs::write(&other, "").unwrap();
+        typed(&mut editor, &registry, &format!(":w {other}\n"));
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "");
+        typed(&mut editor, &registry, "y");
+        assert_eq!(std::fs::read_to_string(&other).unwrap(), "old one old two\n");
+        // Only a copy, the buffer is still the file it was
+        assert_eq!(editor.buffer().name, path);
+
+        // Undoing back to what was written isn't a change
+        typed(&mut editor, &registry, "d");
+        assert!(editor.buffer().modified);
+        typed(&mut editor, &registry, "u");
+        assert!(!editor.buffer().modified);
+
+        // A write that fails renames nothing
+        typed(&mut editor, &registry, &format!(":save-as {}\n", dir.join("missing/dir.txt").display()));
+        assert_eq!(editor.buffer().name, path);
+        let renamed = dir.join("renamed.txt").display().to_string();
+        typed(&mut editor, &registry, &format!(":save-as {renamed}\n"));
+        assert_eq!((editor.buffer().name.as_str(), editor.buffer().modified), (renamed.as_str(), false));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn searching_whole_words() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        editor.buffer_mut().text = "cat concat cat".to_string();
+        typed(&mut editor, &registry, "/cat");
+        editor.key(&registry, Key::Alt('w'), Instant::now());
+        assert_eq!(editor.prompt_line().as_deref(), Some("/cat  (whole words)"));
+        typed(&mut editor, &registry, "\nn");
+        assert_eq!(editor.buffer().selections, vec![Selection { anchor: 0, head: 3, goal: None }]);
+        typed(&mut editor, &registry, ":toggle whole-word\n/cat\n");
+        assert_eq!(editor.buffer().selections[0].range(), 7..10);
+    }
+
+    #[test]
+    fn preview_follows_edits() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let path = std::env::temp_dir().join(format!("rakoune-preview-{}.md", std::process::id()));
+        editor.open(&path.display().to_string()).unwrap();
+        typed(&mut editor, &registry, "i# Hi\u{1b}:preview\n");
+        assert_eq!(editor.preview.as_ref().unwrap().0.elements().count(), 1);
+        typed(&mut editor, &registry, "u");
+        editor.update_panes();
+        assert_eq!(editor.preview.as_ref().unwrap().0.elements().count(), 0);
+        typed(&mut editor, &registry, ":preview\n");
+        assert!(editor.preview.is_none());
+    }
+
+    #[test]
+    fn rename_without_a_server() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        typed(&mut editor, &registry, "icount + 1\u{1b}0:rename\n");
+        assert_eq!(editor.prompt_line().as_deref(), Some(":rename count"));
+        typed(&mut editor, &registry, "\u{1b}");
+        assert!(registry.run(&mut editor, "rename total").unwrap_err().to_string().contains("No language server"));
+    }
+
+    #[test]
+    fn language_server_responses_reach_the_buffer() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let grammar = crate::bracket_tree::Grammar::parse(Path::new("rust.toml"), "name = \"rust\"\nextensions = [\"rs\"]\nlanguage_server = \"cat\"\n").unwrap();
+        editor.languages.add(grammar);
+        let dir = std::env::temp_dir().join(format!("rakoune-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.rs");
+        std::fs::write(&path, "fn main() {}\n").unwrap();
+        editor.open(&path.display().to_string()).unwrap();
+        editor.poll_language_servers();
+        assert!(editor.language_servers["rust"].as_ref().unwrap().documents.contains_key(&path));
+        assert_eq!(editor.status_context(Instant::now()).lsp.as_deref(), Some("cat"));
+
+        editor.language_event(Event::Response(Request::Hover(path.clone(), 0), serde_json::json!({ "contents": "`fn main()`" }))).unwrap();
+        assert!(editor.popups.current.is_some());
+        typed(&mut editor, &registry, "l");
+        assert!(editor.popups.current.is_none());
+
+        let range = |start: u32, end: u32| serde_json::json!({ "start": { "line": 0, "character": start }, "end": { "line": 0, "character": end } });
+        editor.language_event(Event::Diagnostics(path.clone(), serde_json::json!([{ "range": range(3, 7), "message": "unused" }]))).unwrap();
+        assert_eq!(editor.diagnostics.get(&path)[0].range, 3..7);
+
+        let edit = serde_json::json!({ "changes": { lsp::uri(&path): [{ "range": range(3, 7), "newText": "run" }] } });
+        editor.language_event(Event::Response(Request::CodeActions, serde_json::json!([{ "title": "Rename to run", "kind": "quickfix", "edit": edit }]))).unwrap();
+        assert!(matches!(editor.picking, Some(Picking::CodeAction(_))));
+        typed(&mut editor, &registry, "\n");
+        assert_eq!(editor.buffer().text, "fn run() {}\n");
+
+        editor.language_event(Event::Response(Request::Formatting(path.clone()), serde_json::json!([{ "range": range(0, 0), "newText": "// main\n" }]))).unwrap();
+        assert_eq!(editor.buffer().text, "// main\nfn run() {}\n");
+        assert!(editor.language_event(Event::Response(Request::CodeActions, serde_json::json!([]))).is_err());
+    }
+
+    // Waits for the search and the files being read in the background
+    fn finish_background(editor: &mut Editor) {
+        let start = Instant::now();
+        while (editor.searching.is_some() || !editor.loading.is_empty()) && start.elapsed() < Duration::from_secs(5) {
+            editor.poll_background();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn lists_directories_again_when_they_change() {
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-dirpoll-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.txt"), "").unwrap();
+        editor.open(&dir.display().to_string()).unwrap();
+        assert_eq!(editor.buffer().text, "../\na.txt\n");
+        let now = editor.started;
+        assert!(!editor.poll_dirs(now));
+        assert_eq!(editor.next_dir_poll(), Some(now + dired::POLL));
+
+        std::fs::write(dir.join("b.txt"), "").unwrap();
+        assert!(!editor.poll_dirs(now + dired::POLL / 2));
+        assert!(editor.poll_dirs(now + dired::POLL));
+        assert_eq!(editor.buffer().text, "../\na.txt\nb.txt\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_large_files_in_the_background() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-large-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (large, binary) = (dir.join("large.txt"), dir.join("large.bin"));
+        let text = "line\n".repeat(LARGE_FILE as usize / 5 + 1);
+        std::fs::write(&large, &text).unwrap();
+        editor.open(&large.display().to_string()).unwrap();
+        // Empty, and left alone, until it's read
+        assert_eq!(editor.loading.len(), 1);
+        typed(&mut editor, &registry, "ix\u{1b}");
+        assert_eq!(editor.buffer().text, "");
+        assert!(registry.run(&mut editor, "w").is_err());
+        finish_background(&mut editor);
+        assert_eq!((editor.buffer().text == text, editor.buffer().modified), (true, false));
+        assert_eq!(editor.status_context(editor.started).progress, None);
+
+        // One that can't be read goes away again
+        std::fs::write(&binary, vec![0xff; LARGE_FILE as usize + 1]).unwrap();
+        editor.open(&binary.display().to_string()).unwrap();
+        finish_background(&mut editor);
+        assert_eq!(editor.buffers.len(), 1);
+        assert!(editor.notifications.log.last().unwrap().text.ends_with("large.bin: not UTF-8"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shows_and_cancels_background_work() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let token = serde_json::json!(1);
+        editor.server_progress("rust", token.clone(), WorkDone::Begin("Indexing".to_string()));
+        editor.server_progress("rust", token.clone(), WorkDone::Report(Some(40)));
+        let search = editor.progress.start("Searching");
+        assert_eq!(editor.status_context(editor.started).progress.as_deref(), Some("⣾ rust: Indexing 40% (+1)"));
+
+        registry.run(&mut editor, "cancel Search").unwrap();
+        assert!(search.is_cancelled());
+        drop(search);
+        editor.server_progress("rust", token, WorkDone::End);
+        assert_eq!(editor.status_context(editor.started).progress, None);
+        assert!(registry.run(&mut editor, "cancel").is_err());
+    }
+
+    #[test]
+    fn completes_words_from_buffers() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        typed(&mut editor, &registry, "ilet font_size = fontstack.size();\n");
+        editor.index_words();
+        // The indexer catches up in the background
+        let start = Instant::now();
+        while editor.indexer.index.lock().unwrap().complete("fon", 1).is_empty() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        typed(&mut editor, &registry, "f");
+        assert_eq!(editor.completion, None);
+        typed(&mut editor, &registry, "s");
+        assert_eq!(editor.completion.as_ref().map(|menu| menu.items.clone()), Some(vec!["font_size".to_string(), "fontstack".to_string()]));
+        editor.key(&registry, Key::Ctrl('n'), Instant::now());
+        editor.key(&registry, Key::Tab, Instant::now());
+        assert_eq!(editor.buffer().text, "let font_size = fontstack.size();\nfontstack");
+        assert_eq!(editor.completion, None);
+
+        // Escape closes the menu before leaving insert mode
+        typed(&mut editor, &registry, " fs\u{1b}");
+        assert_eq!((editor.completion.as_ref(), editor.mode), (None, Mode::Insert));
+        typed(&mut editor, &registry, "\u{1b}");
+        assert_eq!(editor.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn closes_tabs_without_unsaved_changes() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-tabclose-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        typed(&mut editor, &registry, "idraft\u{1b}");
+        editor.open(&dir.join("a.txt").display().to_string()).unwrap();
+        editor.open(&dir.join("b.txt").display().to_string()).unwrap();
+        typed(&mut editor, &registry, "gt");
+        assert_eq!(editor.current, 0);
+        assert_eq!(registry.run(&mut editor, "tabclose").unwrap_err().to_string(), format!("tabclose: {SCRATCH} has unsaved changes"));
+        registry.run(&mut editor, "tabnext 3").unwrap();
+        registry.run(&mut editor, "tabclose").unwrap();
+        assert_eq!(editor.buffers.iter().map(|buffer| buffer.name.as_str()).collect::<Vec<_>>(), vec![SCRATCH, dir.join("a.txt").to_str().unwrap()]);
+        assert_eq!(editor.current, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recovers_buffers_saved_in_a_crash() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-recover-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("notes.txt");
+        std::fs::write(&file, "saved\n").unwrap();
+        typed(&mut editor, &registry, "ifirst\u{1b}");
+        editor.open(&file.display().to_string()).unwrap();
+        typed(&mut editor, &registry, "iunsaved \u{1b}");
+
+        // A report like the panic hook writes
+        let report = dir.join("crash.txt");
+        let mut text = "rakoune crashed\n\nRecovered buffers:\n".to_string();
+        for (at, buffer) in editor.buffers.iter().enumerate() {
+            let recovery = dir.join(format!("recovery-{at}"));
+            std::fs::write(&recovery, &buffer.text).unwrap();
+            text += &format!("  {} -> {}\n", buffer.name, recovery.display());
+        }
+        std::fs::write(&report, text).unwrap();
+
+        let mut editor = Editor::new(Notifications::default());
+        registry.run(&mut editor, &format!("recover {}", crate::commands::quote(&report.display().to_string()))).unwrap();
+        assert_eq!(editor.buffers.len(), 2);
+        assert_eq!((editor.buffers[0].name.as_str(), editor.buffers[0].text.as_str()), (SCRATCH, "first"));
+        assert_eq!(editor.buffer().text, "unsaved saved\n");
+        assert!(editor.buffers.iter().all(|buffer| buffer.modified));
+        assert!(registry.run(&mut editor, "recover /nonexistent").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn symbol_picker_jumps_and_reverts() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let path = std::env::temp_dir().join(format!("rakoune-symbols-{}.rs", std::process::id()));
+        editor.open(&path.display().to_string()).unwrap();
+        typed(&mut editor, &registry, "ifn one() {}\nfn two() {}\nfn three() {}\u{1b}");
+        typed(&mut editor, &registry, ":symbols\nt");
+        assert_eq!(editor.picking.as_ref().unwrap().view(), ("t", vec!["fn two", "fn three"], 0));
+        assert_eq!(editor.buffer().cursor_line(), 1);
+        editor.key(&registry, Key::Down, Instant::now());
+        assert_eq!(editor.buffer().cursor_line(), 2);
+        editor.key(&registry, Key::Up, Instant::now());
+        typed(&mut editor, &registry, "\u{1b}");
+        assert!(editor.picking.is_none());
+        assert_eq!(editor.buffer().cursor_line(), 2);
+
+        typed(&mut editor, &registry, ":outline\n");
+        assert_eq!(editor.outline.as_ref().unwrap().0.symbols.len(), 3);
+        typed(&mut editor, &registry, "Ld");
+        editor.update_panes();
+        assert_eq!(editor.outline.as_ref().unwrap().0.symbols.len(), 2);
+    }
+
+    #[test]
+    fn debugging_marks_the_gutter_and_jumps_to_stops() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-debug-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.rs");
+        std::fs::write(&path, "fn main() {\n    run();\n}\n").unwrap();
+        editor.open(&path.display().to_string()).unwrap();
+        editor.key(&registry, Key::Function { number: 9, shift: false }, Instant::now());
+        assert_eq!(editor.gutter(), BTreeMap::from([(0, GutterMark::Breakpoint)]));
+
+        // An adapter that only says the program stopped on the second line
+        let frames = serde_json::json!([{ "id": 1, "name": "main", "source": { "path": path }, "line": 2 }]);
+        let messages = [serde_json::json!({ "type": "event", "event": "stopped", "body": { "threadId": 1 } }), serde_json::json!({ "type": "response", "command": "stackTrace", "success": true, "body": { "stackFrames": frames } })];
+        std::fs::write(dir.join("messages"), messages.iter().flat_map(dap::encode).collect::<Vec<u8>>()).unwrap();
+        let adapter = dir.join("adapter");
+        std::fs::write(&adapter, format!("#!/bin/sh\ncat {}\nsleep 5\n", dir.join("messages").display())).unwrap();
+        std::fs::set_permissions(&adapter, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        registry.run(&mut editor, &format!("debug {} app", adapter.display())).unwrap();
+        assert!(editor.debug_panes);
+        let start = Instant::now();
+        while editor.buffer().cursor_line() != 1 && start.elapsed().as_secs() < 5 {
+            editor.poll_debugger();
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(editor.buffer().cursor_line(), 1);
+        assert_eq!(editor.gutter(), BTreeMap::from([(0, GutterMark::Breakpoint), (1, GutterMark::Stopped { on_breakpoint: false })]));
+        registry.run(&mut editor, "debug-stop").unwrap();
+        assert!(editor.debugger.is_none() && editor.gutter_shown());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tree_takes_keys_and_opens_files() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-tree-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/main.rs"), "").unwrap();
+        std::fs::write(dir.join("README.md"), "hi\n").unwrap();
+        editor.project = Some(Project { root: dir.clone(), config: Default::default(), hash: 0 });
+        typed(&mut editor, &registry, ":tree\n");
+        assert!(editor.tree_focused);
+        typed(&mut editor, &registry, "j\n");
+        assert_eq!(editor.buffer().name, dir.join("README.md").display().to_string());
+        assert!(!editor.tree_focused && editor.file_tree.is_some());
+        // Keys are the buffer's again
+        typed(&mut editor, &registry, "j:tree\n");
+        assert!(editor.file_tree.is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recent_files_open_from_a_picker() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-recent-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (older, newer) = (dir.join("older.txt"), dir.join("newer.txt"));
+        std::fs::write(&older, "old\n").unwrap();
+        std::fs::write(&newer, "new\n").unwrap();
+        editor.recent.opened(&older).unwrap();
+        editor.recent.opened(&newer).unwrap();
+        typed(&mut editor, &registry, ":recent\n");
+        assert_eq!(editor.picking.as_ref().unwrap().view().1, vec![newer.display().to_string(), older.display().to_string()]);
+        assert_eq!(editor.file_previews.current().unwrap().text, "new\n");
+        editor.key(&registry, Key::Down, Instant::now());
+        // Previewed without opening a buffer
+        assert_eq!(editor.file_previews.current().unwrap().text, "old\n");
+        assert_eq!((editor.buffers.len(), editor.buffer().name.as_str()), (1, SCRATCH));
+        typed(&mut editor, &registry, "\n");
+        assert!(editor.picking.is_none() && editor.file_previews.current().is_none());
+        assert_eq!(editor.buffer().name, older.display().to_string());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn start_screen_opens_recent_files_and_new_buffers() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-start-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (older, newer) = (dir.join("older.txt"), dir.join("newer.txt"));
+        std::fs::write(&older, "old\n").unwrap();
+        std::fs::write(&newer, "new\n").unwrap();
+        editor.recent.opened(&older).unwrap();
+        editor.recent.opened(&newer).unwrap();
+        editor.start_screen = Some(StartScreen::new(&editor.recent, &registry));
+        typed(&mut editor, &registry, "j\n");
+        assert!(editor.start_screen.is_none());
+        assert_eq!(editor.buffer().name, older.display().to_string());
+
+        editor.start_screen = Some(StartScreen::new(&editor.recent, &registry));
+        typed(&mut editor, &registry, "x");
+        assert!(editor.start_screen.is_some());
+        typed(&mut editor, &registry, "n");
+        assert!(editor.start_screen.is_none());
+        assert_eq!((editor.buffers.len(), editor.buffer().name.as_str()), (2, SCRATCH));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replaces_across_search_results() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-replace-all-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        std::fs::write(&a, "old\nnot here\n  old old\n").unwrap();
+        std::fs::write(&b, "an old one").unwrap();
+        editor.open(&a.display().to_string()).unwrap();
+        editor.open(&b.display().to_string()).unwrap();
+
+        // Backslashes and spaces in the pattern reach it as typed
+        typed(&mut editor, &registry, ":search-files s/\\w+ o\\w+/new/\n");
+        finish_background(&mut editor);
+        assert_eq!(editor.buffer().text, format!("{}:3:3: old old\n{}:1:1: an old one\n", a.display(), b.display()));
+        typed(&mut editor, &registry, ":search-files s/old/new/\n");
+        finish_background(&mut editor);
+        assert_eq!(editor.buffer().text, format!("{0}:1:1: old\n{0}:3:3: old old\n{1}:1:4: an old one\n", a.display(), b.display()));
+        typed(&mut editor, &registry, "j\n");
+        assert_eq!((editor.buffer().name.as_str(), editor.buffer().cursor()), (a.display().to_string().as_str(), 15));
+
+        // Not while a file has unsaved changes
+        typed(&mut editor, &registry, "ix\u{1b}:b \"*search results*\"\n:replace-all\n");
+        assert!(editor.notifications.log.last().unwrap().text.ends_with("a.txt has unsaved changes"));
+        typed(&mut editor, &registry, &format!(":b {}\nu:w\n:b \"*search results*\"\n:replace-all\n", a.display()));
+        assert_eq!(editor.buffer().text, "Replaced 3 matches in 2 files\n");
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "new\nnot here\n  new old\n");
+        typed(&mut editor, &registry, &format!(":b {}\n", b.display()));
+        assert_eq!((editor.buffer().text.as_str(), editor.buffer().modified), ("an new one", false));
+        // Undone in the buffer alone, then written, so undo-replace leaves that file be
+        typed(&mut editor, &registry, "u");
+        assert_eq!((editor.buffer().text.as_str(), editor.buffer().modified), ("an old one", true));
+        typed(&mut editor, &registry, "ix\u{1b}:undo-replace\n");
+        assert!(editor.notifications.log.last().unwrap().text.ends_with("b.txt has unsaved changes"));
+        typed(&mut editor, &registry, "u:w\n:undo-replace\n");
+        assert_eq!(editor.buffer().text, format!("Restored 1 files, 1 files failed:\n  {}: changed since\n", b.display()));
+        assert_eq!(editor.buffers.iter().find(|buffer| buffer.name == a.display().to_string()).unwrap().text, "old\nnot here\n  old old\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sessions_keep_files_and_cursors() {
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-session-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (first, second) = (dir.join("first.txt"), dir.join("second.txt"));
+        std::fs::write(&first, "one\ntwo words\n").unwrap();
+        std::fs::write(&second, "").unwrap();
+        editor.open(&first.display().to_string()).unwrap();
+        editor.buffer_mut().selections = vec![Selection::cursor(8)];
+        editor.open(&second.display().to_string()).unwrap();
+        editor.open(&first.display().to_string()).unwrap();
+        let session = editor.current_session();
+        assert_eq!(session.files.iter().map(|file| (file.path.clone(), file.cursor_line, file.cursor_column)).collect::<Vec<_>>(), vec![(first.clone(), 1, 4), (second.clone(), 0, 0)]);
+        assert_eq!(session.focused, 0);
+
+        let mut restored = Editor::new(Notifications::default());
+        restored.restore_session(session);
+        assert_eq!(restored.buffers.len(), 2);
+        assert_eq!((restored.buffer().name.as_str(), restored.buffer().cursor()), (first.display().to_string().as_str(), 8));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn projects_restore_their_own_session() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let dir = std::env::temp_dir().join(format!("rakoune-project-session-{}", std::process::id()));
+        let root = dir.join("project");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join(project::FILE_NAME), "session = true\n").unwrap();
+        let file = root.join("notes.txt");
+        std::fs::write(&file, "one\ntwo\n").unwrap();
+        let editor = |trusted: &str| {
+            let mut editor = Editor::new(Notifications::default());
+            editor.sessions_dir = Some(dir.join("sessions"));
+            editor.trust = TrustStore::load(dir.join(trusted));
+            editor
+        };
+
+        let mut first = editor("trusted");
+        first.open(&file.display().to_string()).unwrap();
+        typed(&mut first, &registry, "yj");
+        session::store(&first).unwrap();
+        // Other files in the global session
+        let global_file = dir.join("global.txt");
+        std::fs::write(&global_file, "").unwrap();
+        let mut global = editor("trusted");
+        global.open(&global_file.display().to_string()).unwrap();
+        session::store(&global).unwrap();
+
+        // Started in the project, it restores its session once trusted
+        let mut started = editor("later");
+        started.discover_project(&root);
+        started.restore_session_at_start();
+        assert!(started.buffers.is_empty() || started.buffer().name == SCRATCH);
+        typed(&mut started, &registry, "y");
+        assert_eq!(started.project.as_ref().unwrap().root, root);
+        assert_eq!((started.buffer().name.as_str(), started.buffer().cursor_line()), (file.display().to_string().as_str(), 1));
+
+        // Right away the next time, and not trusting it falls back to the global session
+        let mut again = editor("later");
+        again.discover_project(&root);
+        again.restore_session_at_start();
+        assert_eq!(again.buffer().name, file.display().to_string());
+        let mut distrusted = editor("other");
+        distrusted.discover_project(&root);
+        distrusted.restore_session_at_start();
+        typed(&mut distrusted, &registry, "n");
+        assert!(distrusted.project.is_none());
+        assert_eq!(distrusted.buffer().name, global_file.display().to_string());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn exports_and_copies_with_highlight_colors() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        editor.open(&dir.join("main.txt").display().to_string()).unwrap();
+        typed(&mut editor, &registry, "ifn main\nfn x\u{1b}");
+        let config: highlighter::HighlightConfig = toml::from_str("default = [\"regex\"]\n[regex.txt]\nkeyword = \"fn\"\n").unwrap();
+        editor.highlighting = Some(Highlighting::new(config.clone(), highlighter::builtin(&config, Default::default()).unwrap()).unwrap());
+        let keyword = editor.theme.face_color("keyword").unwrap();
+        assert_eq!(editor.highlight_colors(), vec![(0..2, keyword), (8..10, keyword)]);
+        editor.buffer_mut().selections = vec![Selection { anchor: 3, head: 10, goal: None }];
+        assert_eq!(editor.selected_highlights(), Some(("main\nfn".to_string(), vec![(5..7, keyword)])));
+
+        assert!(registry.run(&mut editor, &format!("export {}", dir.join("main.pdf").display())).unwrap_err().to_string().contains("No fonts"));
+        editor.fontstack = Some(Rc::new(FontStack::new(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/firacode-regular.ttf"))).unwrap()));
+        registry.run(&mut editor, &format!("export {}", dir.join("main.pdf").display())).unwrap();
+        assert!(std::fs::read(dir.join("main.pdf")).unwrap().starts_with(b"%PDF"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn digraphs_code_points_and_picked_characters() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let now = Instant::now();
+        typed(&mut editor, &registry, "i");
+        editor.key(&registry, Key::Ctrl('k'), now);
+        typed(&mut editor, &registry, "a");
+        assert_eq!(editor.prompt_line().as_deref(), Some("^K a"));
+        typed(&mut editor, &registry, ":");
+        editor.key(&registry, Key::Ctrl('v'), now);
+        typed(&mut editor, &registry, "u{1F414}");
+        assert_eq!((editor.buffer().text.as_str(), editor.prompt_line()), ("ä🐔", None));
+        editor.key(&registry, Key::Ctrl('k'), now);
+        editor.key(&registry, Key::Ctrl('k'), now);
+        typed(&mut editor, &registry, "right arrow\n\u{1b}");
+        assert_eq!(editor.buffer().text, "ä🐔→");
+        // One undo step for all that was typed, and one for a character picked outside insert mode
+        typed(&mut editor, &registry, ":insert-char\nellipsis\n");
+        assert_eq!(editor.buffer().text, "ä🐔→…");
+        typed(&mut editor, &registry, "u");
+        assert_eq!(editor.buffer().text, "ä🐔→");
+        typed(&mut editor, &registry, "u");
+        assert_eq!(editor.buffer().text, "");
+    }
+
+    #[test]
+    fn folds_skip_moves_and_open_for_search() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        *editor.buffer_mut() = Buffer::new("*scratch*", "fn main() {\n    if x {\n        a();\n\n        b();\n    }\n}\n".to_string(), false);
+        typed(&mut editor, &registry, "j:fold\n");
+        assert_eq!(editor.buffer().folds.closed, vec![1..5]);
+        typed(&mut editor, &registry, "j");
+        assert_eq!(editor.buffer().cursor_line(), 5);
+        typed(&mut editor, &registry, "k");
+        assert_eq!(editor.buffer().cursor_line(), 1);
+
+        typed(&mut editor, &registry, "/b\n");
+        assert_eq!(editor.buffer().cursor_line(), 4);
+        assert!(editor.buffer().folds.closed.is_empty());
+
+        typed(&mut editor, &registry, ":fold-level 0\n");
+        assert_eq!((editor.buffer().folds.closed.clone(), editor.buffer().cursor_line()), (vec![0..6, 1..5], 0));
+        typed(&mut editor, &registry, ":unfold all\nj:toggle-fold\n");
+        assert_eq!((editor.buffer().folds.closed.clone(), editor.buffer().cursor_line()), (vec![1..5], 1));
+        // Lines added above move it down
+        typed(&mut editor, &registry, "gki\n\u{1b}");
+        assert_eq!(editor.buffer().folds.closed, vec![2..6]);
+    }
+
+    #[test]
+    fn marks_follow_edits_and_jump_across_files() {
+        let registry = Registry::default();
+        let mut editor = Editor::new(Notifications::default());
+        let dir = std::env::temp_dir().join(format!("rakoune-marks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        std::fs::write(&a, "one\ntwo\nthree\n").unwrap();
+        std::fs::write(&b, "other").unwrap();
+        editor.open(&a.display().to_string()).unwrap();
+        assert!(!editor.gutter_shown());
+
+        typed(&mut editor, &registry, "jmajmB");
+        assert!(editor.gutter_shown());
+        assert_eq!(editor.marks_by_line(), BTreeMap::from([(1, vec!['a']), (2, vec!['B'])]));
+        // Lines added above move them down
+        typed(&mut editor, &registry, "kkizero\n\u{1b}'a");
+        assert_eq!(editor.buffer().cursor_line(), 2);
+        assert_eq!(editor.marks_by_line(), BTreeMap::from([(2, vec!['a']), (3, vec!['B'])]));
+
+        // Uppercase marks open their file, lowercase ones are per buffer
+        editor.open(&b.display().to_string()).unwrap();
+        typed(&mut editor, &registry, "'a");
+        assert_eq!(editor.notifications.log.last().unwrap().text, "Mark a isn't set");
+        typed(&mut editor, &registry, "'B");
+        assert_eq!((editor.buffer().name.clone(), editor.buffer().cursor_line()), (a.display().to_string(), 3));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn brackets_follow_edits() {
+        let registry = Registry::default();
+        let mut editor = Editor::new(Notifications::default());
+        *editor.buffer_mut() = Buffer::new("*scratch*", "f(a) {\n}\n".to_string(), false);
+        typed(&mut editor, &registry, "ji[\n\u{1b}");
+        typed(&mut editor, &registry, "u");
+        typed(&mut editor, &registry, "i(x\n\u{1b}");
+        let buffer = editor.buffer();
+        let fresh = BracketDepths::new(&buffer.text);
+        for line in 0..buffer.text.split('\n').count() {
+            assert_eq!(buffer.brackets.line(line), fresh.line(line), "line {line}");
+        }
+        assert_eq!(editor.text_colors(0..2), vec![(1..2, highlighter::bracket_color(Some(0)))]);
+    }
+
+    #[test]
+    fn syntax_tree_follows_edits_and_selects_nodes() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        editor.languages = Languages::builtin();
+        let dir = std::env::temp_dir().join(format!("rakoune-syntax-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("main.rs");
+        std::fs::write(&path, "fn main() {\n    run(1);\n}\n").unwrap();
+        editor.open(&path.display().to_string()).unwrap();
+        typed(&mut editor, &registry, "jiif ok {\n    go();\n}\n\u{1b}");
+        typed(&mut editor, &registry, "u");
+        typed(&mut editor, &registry, "iwait(2);\n\u{1b}");
+        assert_eq!(editor.buffer().text, "fn main() {\nwait(2);\n    run(1);\n}\n");
+
+        typed(&mut editor, &registry, ":select-object function inside\n");
+        let selected = |editor: &Editor| editor.buffer().text[editor.buffer().selections[0].range()].to_string();
+        assert_eq!(selected(&editor), "\nwait(2);\n    run(1);\n");
+        typed(&mut editor, &registry, ":expand-selection\n");
+        assert_eq!(selected(&editor), "{\nwait(2);\n    run(1);\n}");
+        typed(&mut editor, &registry, ":shrink-selection\n");
+        assert_eq!(selected(&editor), "\nwait(2);\n    run(1);\n");
+        assert_eq!(editor.available_folds(), vec![Fold { lines: 0..4, depth: 0 }]);
+        typed(&mut editor, &registry, ":select-object comment\n");
+        assert_eq!(editor.notifications.log.last().unwrap().text, "select-object: No comment around the cursor");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn substitutes_with_preview_and_confirm() {
+        let registry = Registry::default();
+        let mut editor = Editor::new(Notifications::default());
+        *editor.buffer_mut() = Buffer::new("*scratch*", "let a = 1;\nlet b = 2;\nlet c = 3;".to_string(), false);
+        typed(&mut editor, &registry, ":s/let (\\w)/const \\1");
+        assert_eq!(substitute::preview(&editor.substitute_preview()).iter().map(|vt| vt.text.as_str()).collect::<Vec<_>>(), vec!["const a", "const b", "const c"]);
+        typed(&mut editor, &registry, "/c\n");
+        assert_eq!(editor.prompt_line().unwrap(), "Replace with const a? (y/n/a/q)");
+        typed(&mut editor, &registry, "yn");
+        assert_eq!(editor.substitute_preview()[0].range, 22..27);
+        typed(&mut editor, &registry, "y");
+        assert_eq!(editor.buffer().text, "const a = 1;\nlet b = 2;\nconst c = 3;");
+        assert!(editor.substitute_preview().is_empty());
+
+        // Undone with the last match still selected, which would limit :s to it
+        typed(&mut editor, &registry, "u\u{1b}:s/ = \\d/ = 0/\n");
+        assert_eq!(editor.buffer().text, "let a = 0;\nlet b = 0;\nlet c = 0;");
+        typed(&mut editor, &registry, ":s/x/y/\n");
+        assert_eq!(editor.notifications.log.last().unwrap().text, "Pattern not found");
+    }
+
+    #[test]
+    fn help_lists_commands_and_keys() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        typed(&mut editor, &registry, ":help\n");
+        assert_eq!(editor.buffer().name, "*help*");
+        assert!(editor.buffer().text.contains("\n  :help\n") && editor.buffer().text.contains("\n  gt  tabnext\n"));
+    }
+
+    #[test]
+    fn checks_the_theme() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        for theme in highlighter::THEMES {
+            editor.theme = *theme;
+            typed(&mut editor, &registry, ":check-theme\n");
+            assert_eq!(editor.buffer().name, "*theme check*");
+            assert!(!editor.buffer().text.contains("against the background\n"), "{}", theme.name);
+        }
+    }
+
+    #[test]
+    fn picks_themes_and_fonts_with_a_preview() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        typed(&mut editor, &registry, ":theme\n");
+        assert_eq!(editor.picking.as_ref().unwrap().view().2, 0);
+        editor.key(&registry, Key::Down, Instant::now());
+        assert_eq!(editor.theme.name, "gruvbox");
+        typed(&mut editor, &registry, "\u{1b}");
+        assert!(editor.picking.is_none());
+        assert_eq!(editor.theme.name, "default");
+        typed(&mut editor, &registry, ":theme\nnord\n");
+        assert_eq!(editor.theme.name, "nord");
+        typed(&mut editor, &registry, ":theme gr");
+        editor.key(&registry, Key::Tab, Instant::now());
+        typed(&mut editor, &registry, "\n");
+        assert_eq!(editor.theme.name, "gruvbox");
+        assert!(registry.run(&mut editor, "theme solarized").is_err());
+
+        typed(&mut editor, &registry, ":font /fonts/Fira Code.ttf\n");
+        assert_eq!((editor.font.as_path(), editor.requested_font.as_deref()), (Path::new("/fonts/Fira Code.ttf"), Some(Path::new("/fonts/Fira Code.ttf"))));
+        editor.requested_font = None;
+        editor.picking = Some(Picking::Font(Picker::new(vec![("a.ttf".to_string(), PathBuf::from("/a.ttf")), ("b.ttf".to_string(), PathBuf::from("/b.ttf"))], editor.font.clone())));
+        editor.key(&registry, Key::Down, Instant::now());
+        assert_eq!((editor.font.as_path(), editor.requested_font.take()), (Path::new("/fonts/Fira Code.ttf"), Some(PathBuf::from("/b.ttf"))));
+        typed(&mut editor, &registry, "\u{1b}");
+        assert_eq!(editor.requested_font, Some(PathBuf::from("/fonts/Fira Code.ttf")));
+    }
+
+    #[test]
+    fn diagnostics_move_with_edits_and_list() {
+        use crate::diagnostics::Diagnostic;
+        use crate::notifications::Severity;
+
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut editor = Editor::new(Notifications::default());
+        let path = std::env::temp_dir().join(format!("rakoune-diagnostics-{}.rs", std::process::id()));
+        editor.open(&path.display().to_string()).unwrap();
+        typed(&mut editor, &registry, "ilet x = y;\nlet z = x;\u{1b}");
+        let diagnostic = Diagnostic { range: 8..9, severity: Severity::Error, message: "no y".to_string(), source: None };
+        editor.diagnostics.set(&path, vec![diagnostic]);
+        // Typing in front moves it along, and undoing moves it back
+        typed(&mut editor, &registry, "k0i  \u{1b}");
+        assert_eq!(editor.diagnostics.get(&path)[0].range, 10..11);
+        typed(&mut editor, &registry, "u");
+        assert_eq!(editor.diagnostics.get(&path)[0].range, 8..9);
+
+        typed(&mut editor, &registry, ":diagnostics\n");
+        assert_eq!(editor.buffer().text, format!("{}:1:9: error: no y\n", path.display()));
+        typed(&mut editor, &registry, "\n");
+        assert_eq!((editor.buffer().name.as_str(), editor.buffer().cursor()), (path.to_str().unwrap(), 8));
+    }
+}
@@ -0,0 +1,88 @@
+use thiserror::Error;
+
+use crate::font;
+
+// Errors from drawing, split up by what can be done about them
+#[derive(Debug, Error)]
+pub enum RenderError {
+    // The surface needs to be configured again, like after a resize or the window moving to another GPU
+    #[error("Surface lost or outdated")]
+    SurfaceLost,
+    // Acquiring a frame took too long. Usually goes away by itself
+    #[error("Timed out getting the next frame")]
+    Timeout,
+    #[error("Out of GPU memory")]
+    OutOfMemory,
+    // Neither a GPU, a software adapter nor OpenGL could draw to the window. Says what was tried
+    #[error("No adapter available (tried {0})")]
+    NoAdapter(String),
+    // Resuming couldn't get the window's surface back
+    #[error("Couldn't draw to the window: {0}")]
+    Surface(#[from] wgpu::CreateSurfaceError),
+    // The adapter was there, but wouldn't give a device to draw with
+    #[error("Couldn't open the GPU: {0}")]
+    Device(#[from] wgpu::RequestDeviceError),
+    #[error("Shader {0} failed to compile: {1}")]
+    Shader(String, String),
+    #[error("Font error: {0}")]
+    Font(#[from] font::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<wgpu::SurfaceError> for RenderError {
+    fn from(e: wgpu::SurfaceError) -> Self {
+        match e {
+            wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => RenderError::SurfaceLost,
+            wgpu::SurfaceError::Timeout => RenderError::Timeout,
+            wgpu::SurfaceError::OutOfMemory => RenderError::OutOfMemory,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    RebuildSwapchain,
+    // Try drawing the next frame as usual
+    SkipFrame,
+    // Drop the broken font and continue with the rest of the stack
+    FallbackFont,
+    // Nothing to do but report it and exit
+    Fatal,
+}
+
+impl RenderError {
+    pub fn recovery(&self) -> Recovery {
+        match self {
+            RenderError::SurfaceLost => Recovery::RebuildSwapchain,
+            RenderError::Timeout => Recovery::SkipFrame,
+            RenderError::Font(_) => Recovery::FallbackFont,
+            RenderError::OutOfMemory | RenderError::NoAdapter(_) | RenderError::Surface(_) | RenderError::Device(_) | RenderError::Shader(..) | RenderError::Io(_) => Recovery::Fatal,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum EditorError {
+    #[error(transparent)]
+    Render(#[from] RenderError),
+    #[error("Font error: {0}")]
+    Font(#[from] font::Error),
+    #[error("Windowing error: {0}")]
+    Windowing(#[from] winit::error::OsError),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_by_kind() {
+        assert_eq!(RenderError::from(wgpu::SurfaceError::Outdated).recovery(), Recovery::RebuildSwapchain);
+        assert_eq!(RenderError::from(wgpu::SurfaceError::Timeout).recovery(), Recovery::SkipFrame);
+        assert_eq!(RenderError::from(font::Error::FontIndexOutOfRange(3)).recovery(), Recovery::FallbackFont);
+        assert_eq!(RenderError::Shader("glyph.wgsl".to_string(), "oops".to_string()).recovery(), Recovery::Fatal);
+    }
+}
@@ -0,0 +1,428 @@
+// Exporting a buffer as it's laid out, for sharing highlighted code: a PDF, or a PNG per page.
+// Lines wrap at the page width. PDFs keep the text as text, in Type3 fonts holding only the
+// outlines of the glyphs used, so no whole font files end up in them. PNGs are rasterized on the
+// CPU with fontdue, so exporting works without a window or GPU
+//
+//   :export snippet.pdf
+//   :export snippet.png letter
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::commands::Registry;
+use crate::font::{Face, FontStack};
+use crate::images::Image;
+use crate::layout::{layout, Layout, LayoutSettings};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't write {0}: {1}")]
+    Write(PathBuf, std::io::Error),
+    #[error("Couldn't encode {0}: {1}")]
+    Png(PathBuf, png::EncodingError),
+    #[error("Unknown page size {0}, expected a4, letter or a size in mm like 100x150")]
+    PageSize(String),
+    #[error("Can only export to .pdf or .png, not {0}")]
+    Format(PathBuf),
+}
+
+// Text size on the page, in points
+pub const FONT_SIZE: f32 = 10.;
+// Space left around the text, in points
+pub const MARGIN: f32 = 36.;
+// PNG pixels per point, for 144 dpi
+pub const PNG_SCALE: f32 = 2.;
+// Text without a color of its own, on the white page
+const INK: [f32; 4] = [0., 0., 0., 1.];
+const POINTS_PER_MM: f32 = 72. / 25.4;
+
+// Byte ranges of the text with their color, like for the text renderer
+pub type Colors = Vec<(Range<usize>, [f32; 4])>;
+
+// In points, 1/72 of an inch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PageSize {
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PageSize {
+    pub const A4: PageSize = PageSize { width: 595., height: 842. };
+    pub const LETTER: PageSize = PageSize { width: 612., height: 792. };
+
+    // a4, letter, or width x height in millimeters
+    pub fn parse(name: &str) -> Result<PageSize, Error> {
+        match name.to_ascii_lowercase().as_str() {
+            "a4" => Ok(PageSize::A4),
+            "letter" => Ok(PageSize::LETTER),
+            mm => {
+                let size = mm.split_once('x').and_then(|(w, h)| Some((w.parse::<f32>().ok()?, h.parse::<f32>().ok()?)));
+                match size {
+                    Some((w, h)) if w > 0. && h > 0. => Ok(PageSize { width: w * POINTS_PER_MM, height: h * POINTS_PER_MM }),
+                    _ => Err(Error::PageSize(name.to_string())),
+                }
+            }
+        }
+    }
+}
+
+// A buffer laid out for pages of `page`
+pub struct Document<'a> {
+    pub layout: Layout<'a>,
+    pub page: PageSize,
+    // Rows of the layout on each page
+    pub pages: Vec<Range<usize>>,
+    text: String,
+    colors: Colors,
+}
+
+impl<'a> Document<'a> {
+    pub fn new(fontstack: &'a FontStack, text: &str, colors: Colors, settings: &LayoutSettings, page: PageSize) -> Document<'a> {
+        let settings = LayoutSettings { font_size: FONT_SIZE, wrap_width: Some(page.width - 2. * MARGIN), ..settings.clone() };
+        let layout = layout(fontstack, text, &settings);
+        let rows_per_page = (((page.height - 2. * MARGIN) / layout.line_height) as usize).max(1);
+        let pages = (0..layout.lines.len()).step_by(rows_per_page).map(|start| start..(start + rows_per_page).min(layout.lines.len())).collect();
+        Document { layout, page, pages, text: text.to_string(), colors }
+    }
+
+    fn color(&self, byte: usize) -> [f32; 4] {
+        self.colors.iter().find(|(range, _)| range.contains(&byte)).map_or(INK, |(_, color)| *color)
+    }
+
+    // The glyphs on page `idx` with their face, position of their origin from the top left of the
+    // page in points, and color
+    fn glyphs(&self, idx: usize) -> impl Iterator<Item = (&'a Face, u16, (f32, f32), [f32; 4])> + '_ {
+        let rows = &self.pages[idx];
+        let top = self.layout.lines[rows.start].top;
+        let glyph_range = self.layout.lines[rows.start].glyph_range.start..self.layout.lines[rows.end - 1].glyph_range.end;
+        self.layout.glyphs[glyph_range].iter().filter_map(move |glyph| {
+            let shaped = glyph.shaped.as_ref()?;
+            let position = (MARGIN + glyph.x + glyph.offset.0, MARGIN + glyph.y - top + glyph.offset.1);
+            Some((shaped.face, shaped.glyph, position, self.color(glyph.byte_range.start)))
+        })
+    }
+
+    // Page `idx` on white, PNG_SCALE pixels per point
+    pub fn render_page(&self, idx: usize) -> Image {
+        let (width, height) = ((self.page.width * PNG_SCALE) as u32, (self.page.height * PNG_SCALE) as u32);
+        let mut rgba = vec![255; (width * height * 4) as usize];
+        for (face, glyph, (x, y), color) in self.glyphs(idx) {
+            let Ok(font) = face.fontdue_font() else { continue };
+            let (metrics, coverage) = font.rasterize_indexed(glyph, FONT_SIZE * face.size_scale * PNG_SCALE);
+            let left = (x * PNG_SCALE).round() as i64 + metrics.xmin as i64;
+            let top = (y * PNG_SCALE).round() as i64 - (metrics.ymin as i64 + metrics.height as i64);
+            for (row, line) in coverage.chunks(metrics.width.max(1)).enumerate() {
+                for (column, &covered) in line.iter().enumerate() {
+                    let (px, py) = (left + column as i64, top + row as i64);
+                    if px < 0 || py < 0 || px >= width as i64 || py >= height as i64 {
+                        continue;
+                    }
+                    let alpha = covered as f32 / 255. * color[3];
+                    let at = ((py as u32 * width + px as u32) * 4) as usize;
+                    for channel in 0..3 {
+                        let under = rgba[at + channel] as f32;
+                        rgba[at + channel] = (under + (color[channel] * 255. - under) * alpha).round() as u8;
+                    }
+                }
+            }
+        }
+        Image { width, height, rgba }
+    }
+
+    // Writes a PNG per page next to `path`, numbered from 1 unless there's only one
+    pub fn write_pngs(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut written = Vec::new();
+        for idx in 0..self.pages.len() {
+            let page_path = match self.pages.len() {
+                1 => path.to_owned(),
+                _ => path.with_file_name(format!("{}-{}.png", path.file_stem().unwrap_or_default().to_string_lossy(), idx + 1)),
+            };
+            let image = self.render_page(idx);
+            let mut encoded = Vec::new();
+            let mut encoder = png::Encoder::new(&mut encoded, image.width, image.height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.write_header().and_then(|mut writer| writer.write_image_data(&image.rgba)).map_err(|err| Error::Png(page_path.clone(), err))?;
+            std::fs::write(&page_path, encoded).map_err(|err| Error::Write(page_path.clone(), err))?;
+            written.push(page_path);
+        }
+        Ok(written)
+    }
+
+    pub fn write_pdf(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, self.pdf()).map_err(|err| Error::Write(path.to_owned(), err))
+    }
+
+    pub fn pdf(&self) -> Vec<u8> {
+        let mut pdf = Pdf::default();
+        let catalog = pdf.reserve();
+        let pages_id = pdf.reserve();
+        let mut fonts = Subsets::default();
+        let mut page_ids = Vec::new();
+        for idx in 0..self.pages.len() {
+            let mut content = String::from("BT\n");
+            for (face, glyph, (x, y), color) in self.glyphs(idx) {
+                let (font, code) = fonts.code(face, glyph);
+                let _ = writeln!(content, "/F{font} {} Tf {} {} {} rg 1 0 0 1 {} {} Tm <{code:02x}> Tj", FONT_SIZE * face.size_scale, color[0], color[1], color[2], x, self.page.height - y);
+            }
+            content.push_str("ET\n");
+            let content_id = pdf.add(stream("", content.as_bytes()));
+            let page_id = pdf.reserve();
+            page_ids.push((page_id, content_id));
+        }
+        let font_ids: Vec<usize> = fonts.fonts.iter().map(|font| font.write(&mut pdf, &self.text)).collect();
+        let font_refs: String = font_ids.iter().enumerate().map(|(idx, id)| format!("/F{idx} {id} 0 R ")).collect();
+        for &(page_id, content_id) in &page_ids {
+            let page = format!("<< /Type /Page /Parent {pages_id} 0 R /MediaBox [0 0 {} {}] /Contents {content_id} 0 R /Resources << /Font << {font_refs}>> >> >>", self.page.width, self.page.height);
+            pdf.set(page_id, page.into_bytes());
+        }
+        let kids: String = page_ids.iter().map(|(id, _)| format!("{id} 0 R ")).collect();
+        pdf.set(pages_id, format!("<< /Type /Pages /Kids [{kids}] /Count {} >>", page_ids.len()).into_bytes());
+        pdf.set(catalog, format!("<< /Type /Catalog /Pages {pages_id} 0 R >>").into_bytes());
+        pdf.finish(catalog)
+    }
+}
+
+fn stream(dict: &str, data: &[u8]) -> Vec<u8> {
+    let mut object = format!("<< {dict} /Length {} >>\nstream\n", data.len()).into_bytes();
+    object.extend_from_slice(data);
+    object.extend_from_slice(b"\nendstream");
+    object
+}
+
+// Objects of a PDF, numbered from 1
+#[derive(Default)]
+struct Pdf {
+    objects: Vec<Vec<u8>>,
+}
+
+impl Pdf {
+    // An object to be set later, for objects that are referred to before they're known
+    fn reserve(&mut self) -> usize {
+        self.add(Vec::new())
+    }
+
+    fn add(&mut self, object: Vec<u8>) -> usize {
+        self.objects.push(object);
+        self.objects.len()
+    }
+
+    fn set(&mut self, id: usize, object: Vec<u8>) {
+        self.objects[id - 1] = object;
+    }
+
+    fn finish(self, root: usize) -> Vec<u8> {
+        let mut out = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (idx, object) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", idx + 1).as_bytes());
+            out.extend_from_slice(object);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", self.objects.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(table, "{offset:010} 00000 n ");
+        }
+        let _ = write!(table, "trailer\n<< /Size {} /Root {root} 0 R >>\nstartxref\n{xref}\n%%EOF\n", self.objects.len() + 1);
+        out.extend_from_slice(table.as_bytes());
+        out
+    }
+}
+
+// The glyphs used of one face, at most 256 as Type3 fonts are addressed by single bytes
+struct Subset<'a> {
+    face: &'a Face,
+    glyphs: Vec<u16>,
+}
+
+#[derive(Default)]
+struct Subsets<'a> {
+    fonts: Vec<Subset<'a>>,
+    // By face address and glyph, the font and code of glyphs already used
+    codes: HashMap<(usize, u16), (usize, u8)>,
+}
+
+impl<'a> Subsets<'a> {
+    fn code(&mut self, face: &'a Face, glyph: u16) -> (usize, u8) {
+        let key = (face as *const Face as usize, glyph);
+        if let Some(&code) = self.codes.get(&key) {
+            return code;
+        }
+        let font = match self.fonts.iter().rposition(|font| std::ptr::eq(font.face, face)).filter(|&idx| self.fonts[idx].glyphs.len() < 256) {
+            Some(idx) => idx,
+            None => {
+                self.fonts.push(Subset { face, glyphs: Vec::new() });
+                self.fonts.len() - 1
+            }
+        };
+        self.fonts[font].glyphs.push(glyph);
+        let code = (font, (self.fonts[font].glyphs.len() - 1) as u8);
+        self.codes.insert(key, code);
+        code
+    }
+}
+
+// Glyph outlines as PDF path operators, in font units
+struct PathWriter(String);
+
+impl ttf_parser::OutlineBuilder for PathWriter {
+    fn move_to(&mut self, x: f32, y: f32) {
+        let _ = writeln!(self.0, "{x} {y} m");
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let _ = writeln!(self.0, "{x} {y} l");
+    }
+
+    // PDF only has cubic curves, so quadratic ones are raised to them
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (x0, y0) = self.current();
+        let (c1x, c1y) = (x0 + 2. / 3. * (x1 - x0), y0 + 2. / 3. * (y1 - y0));
+        let (c2x, c2y) = (x + 2. / 3. * (x1 - x), y + 2. / 3. * (y1 - y));
+        let _ = writeln!(self.0, "{c1x} {c1y} {c2x} {c2y} {x} {y} c");
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let _ = writeln!(self.0, "{x1} {y1} {x2} {y2} {x} {y} c");
+    }
+
+    fn close(&mut self) {
+        self.0.push_str("h\n");
+    }
+}
+
+impl PathWriter {
+    // The end point of the last operator
+    fn current(&self) -> (f32, f32) {
+        let last = self.0.lines().last().unwrap_or_default();
+        let numbers: Vec<f32> = last.split(' ').filter_map(|n| n.parse().ok()).collect();
+        match numbers.len() {
+            n if n >= 2 => (numbers[n - 2], numbers[n - 1]),
+            _ => (0., 0.),
+        }
+    }
+}
+
+impl Subset<'_> {
+    // Adds the font with its glyph procedures and returns its object
+    fn write(&self, pdf: &mut Pdf, text: &str) -> usize {
+        let ttf = &self.face.ttf_face;
+        let scale = 1. / ttf.units_per_em() as f32;
+        let bbox = ttf.global_bounding_box();
+        let mut procs = String::new();
+        let mut widths = String::new();
+        for (code, &glyph) in self.glyphs.iter().enumerate() {
+            let id = ttf_parser::GlyphId(glyph);
+            let advance = ttf.glyph_hor_advance(id).unwrap_or(0);
+            let mut path = PathWriter(String::new());
+            let glyph_box = ttf.outline_glyph(id, &mut path);
+            let body = match glyph_box {
+                Some(b) => format!("{advance} 0 {} {} {} {} d1\n{}f\n", b.x_min, b.y_min, b.x_max, b.y_max, path.0),
+                None => format!("{advance} 0 0 0 0 0 d1\n"),
+            };
+            let proc_id = pdf.add(stream("", body.as_bytes()));
+            let _ = write!(procs, "/g{code} {proc_id} 0 R ");
+            let _ = write!(widths, "{advance} ");
+        }
+        let names: String = (0..self.glyphs.len()).map(|code| format!("/g{code}")).collect();
+        let to_unicode = pdf.add(stream("", self.to_unicode(text).as_bytes()));
+        let font = format!(
+            "<< /Type /Font /Subtype /Type3 /FontBBox [{} {} {} {}] /FontMatrix [{scale} 0 0 {scale} 0 0] /CharProcs << {procs}>> /Encoding << /Type /Encoding /Differences [0 {names}] >> /FirstChar 0 /LastChar {} /Widths [{widths}] /Resources << >> /ToUnicode {to_unicode} 0 R >>",
+            bbox.x_min,
+            bbox.y_min,
+            bbox.x_max,
+            bbox.y_max,
+            self.glyphs.len() - 1,
+        );
+        pdf.add(font.into_bytes())
+    }
+
+    // Maps codes back to the characters their glyphs were shaped from, so text copied out of the
+    // PDF is the text of the buffer
+    fn to_unicode(&self, text: &str) -> String {
+        let mut chars = HashMap::new();
+        for (c, glyph) in text.chars().filter_map(|c| Some((c, self.face.ttf_face.glyph_index(c)?.0))) {
+            chars.entry(glyph).or_insert(c);
+        }
+        let mut cmap = String::from("/CIDInit /ProcSet findresource begin\n12 dict begin\nbegincmap\n/CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n/CMapName /Adobe-Identity-UCS def\n/CMapType 2 def\n1 begincodespacerange\n<00> <FF>\nendcodespacerange\n");
+        let mapped: Vec<(usize, char)> = self.glyphs.iter().enumerate().filter_map(|(code, glyph)| Some((code, *chars.get(glyph)?))).collect();
+        // At most 100 mappings per block
+        for block in mapped.chunks(100) {
+            let _ = writeln!(cmap, "{} beginbfchar", block.len());
+            for &(code, c) in block {
+                let utf16: String = c.encode_utf16(&mut [0; 2]).iter().map(|unit| format!("{unit:04X}")).collect();
+                let _ = writeln!(cmap, "<{code:02X}> <{utf16}>");
+            }
+            cmap.push_str("endbfchar\n");
+        }
+        cmap.push_str("endcmap\nCMapName currentdict /CMap defineresource pop\nend\nend\n");
+        cmap
+    }
+}
+
+// What :export needs from the editor
+pub trait ExportHost {
+    // None until the fonts are loaded
+    fn fontstack(&self) -> Option<&FontStack>;
+    fn layout_settings(&self) -> &LayoutSettings;
+    // Text of the current buffer with its highlight colors
+    fn export_source(&self) -> Option<(String, Colors)>;
+}
+
+// export <path> [page size]. The extension picks PDF or PNG
+fn export_command<Ctx: ExportHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let (path, page) = match args {
+        [path] => (Path::new(path), PageSize::A4),
+        [path, page] => (Path::new(path), PageSize::parse(page).map_err(|e| e.to_string())?),
+        _ => return Err("Usage: export <file.pdf|file.png> [a4|letter|WxH]".to_string()),
+    };
+    let (text, colors) = ctx.export_source().ok_or("No buffer to export")?;
+    let fontstack = ctx.fontstack().ok_or("No fonts to export with")?;
+    let document = Document::new(fontstack, &text, colors, ctx.layout_settings(), page);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("pdf") => document.write_pdf(path),
+        Some("png") => document.write_pngs(path).map(|_| ()),
+        _ => Err(Error::Format(path.to_owned())),
+    }
+    .map_err(|e| e.to_string())
+}
+
+pub fn register<Ctx: ExportHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("export", export_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_pages() {
+        let fontstack = FontStack::new(&Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/firacode-regular.ttf")).unwrap();
+        assert_eq!(PageSize::parse("Letter").unwrap(), PageSize::LETTER);
+        assert!((PageSize::parse("254x127").unwrap().width - 720.).abs() < 1e-3);
+        assert!(PageSize::parse("a5").is_err());
+
+        let text = "fn main() {}\n".repeat(100);
+        let document = Document::new(&fontstack, &text, vec![(0..2, [1., 0., 0., 1.])], &LayoutSettings::default(), PageSize::parse("100x60").unwrap());
+        assert!(document.pages.len() > 1);
+        assert_eq!(document.pages.last().unwrap().end, document.layout.lines.len());
+
+        let pdf = String::from_utf8_lossy(&document.pdf()).into_owned();
+        assert!(pdf.starts_with("%PDF-1.4") && pdf.ends_with("%%EOF\n"));
+        assert_eq!(pdf.matches("/Type /Page ").count(), document.pages.len());
+        // Only the glyphs used, each once: f n m a i ( ) { } and the space
+        assert_eq!(pdf.matches(" d1\n").count(), 10);
+        assert!(pdf.contains("<00> <0066>"));
+
+        let image = document.render_page(0);
+        assert_eq!(image.width, (100. * POINTS_PER_MM * PNG_SCALE) as u32);
+        // Some of the red `fn` is drawn, and the corners stay white
+        assert!(image.rgba.chunks(4).any(|pixel| pixel[0] > 200 && pixel[1] < 100));
+        assert_eq!(&image.rgba[..4], &[255; 4]);
+    }
+}
@@ -0,0 +1,118 @@
+// The file under the selection in the finder, shown next to it while moving through the results.
+// Only the start of the file is read, and it's never opened as a buffer: there is no undo, no
+// language server and nothing to save, and it's gone when the finder closes. A few recent ones
+// are kept, so moving back and forth doesn't read them again
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::backend::Backends;
+use crate::bracket_tree::Languages;
+use crate::highlighter;
+
+// Read from the start of the file, which is more than fits in the pane anyway
+pub const PREVIEW_BYTES: usize = 32 * 1024;
+const KEPT: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilePreview {
+    pub path: PathBuf,
+    pub text: String,
+    // Ranges of `text` to color, with the name of the theme face for each
+    pub highlights: Vec<(Range<usize>, String)>,
+    // The file goes on after `text`
+    pub truncated: bool,
+}
+
+impl FilePreview {
+    // Binary files and ones that can't be read are described instead of shown
+    pub fn load(backends: &Backends, languages: &Languages, path: &Path) -> FilePreview {
+        let name = path.display().to_string();
+        let bytes = match backends.read_head(&name, PREVIEW_BYTES + 1) {
+            Ok(bytes) => bytes,
+            Err(e) => return FilePreview::message(path, e.to_string()),
+        };
+        let truncated = bytes.len() > PREVIEW_BYTES;
+        let bytes = &bytes[..bytes.len().min(PREVIEW_BYTES)];
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) => text,
+            // Cut in the middle of a character
+            Err(e) if truncated && e.error_len().is_none() => std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+            Err(_) => return FilePreview::message(path, "Binary file".to_string()),
+        };
+        if text.contains('\0') {
+            return FilePreview::message(path, "Binary file".to_string());
+        }
+        let highlights = highlighter::text_highlights(languages, path, text).unwrap_or_default();
+        FilePreview { path: path.to_owned(), text: text.to_string(), highlights, truncated }
+    }
+
+    fn message(path: &Path, text: String) -> FilePreview {
+        FilePreview { path: path.to_owned(), text, highlights: Vec::new(), truncated: false }
+    }
+}
+
+// Previews for the finder, most recent last
+#[derive(Debug, Default)]
+pub struct Previews {
+    kept: Vec<FilePreview>,
+}
+
+impl Previews {
+    // For PickerEvent::Preview of the finder
+    pub fn show(&mut self, backends: &Backends, languages: &Languages, path: &Path) -> &FilePreview {
+        let preview = match self.kept.iter().position(|preview| preview.path == path) {
+            Some(at) => self.kept.remove(at),
+            None => FilePreview::load(backends, languages, path),
+        };
+        if self.kept.len() >= KEPT {
+            self.kept.remove(0);
+        }
+        self.kept.push(preview);
+        self.kept.last().unwrap()
+    }
+
+    pub fn current(&self) -> Option<&FilePreview> {
+        self.kept.last()
+    }
+
+    // When the finder closes
+    pub fn clear(&mut self) {
+        self.kept.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bracket_tree::Grammar;
+
+    #[test]
+    fn previews_the_start_highlighted() {
+        let mut backends = Backends::default();
+        let mut languages = Languages::default();
+        languages.add(Grammar::parse(Path::new("rust.toml"), "name = \"rust\"\nextensions = [\"rs\"]\nbrackets = [\"{}\"]\nline_comment = \"//\"\n").unwrap());
+        backends.write("mem:src/main.rs", b"// entry\nfn main() {}\n").unwrap();
+        let mut long = "é".repeat(PREVIEW_BYTES / 2 - 1).into_bytes();
+        long.extend("xé".as_bytes());
+        backends.write("mem:long.txt", &long).unwrap();
+        backends.write("mem:logo.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+
+        let mut previews = Previews::default();
+        let main = previews.show(&backends, &languages, Path::new("mem:src/main.rs")).clone();
+        assert_eq!(main.text, "// entry\nfn main() {}\n");
+        assert!(main.highlights.contains(&(0..8, "comment".to_string())));
+
+        // Cut short in the middle of the last é
+        let long = previews.show(&backends, &languages, Path::new("mem:long.txt"));
+        assert!(long.truncated && long.text.ends_with("éx"));
+        assert_eq!(previews.show(&backends, &languages, Path::new("mem:logo.png")).text, "Binary file");
+        assert!(previews.show(&backends, &languages, Path::new("mem:missing.rs")).text.contains("missing.rs"));
+
+        // Kept, so changes since aren't seen until it's shown again after the finder closes
+        backends.write("mem:src/main.rs", b"").unwrap();
+        assert_eq!(previews.show(&backends, &languages, Path::new("mem:src/main.rs")), &main);
+        previews.clear();
+        assert_eq!(previews.show(&backends, &languages, Path::new("mem:src/main.rs")).text, "");
+    }
+}
@@ -0,0 +1,348 @@
+// The project tree in a sidebar. Directories expand and collapse in place, files open in the
+// focused pane or a new split, and each entry shows its git status. Directories are listed with
+// Project::list_dir, like the finder does, so both skip the same excluded paths
+//
+// With a Nerd Font installed, it's added to the FontStack as a fallback and entries get icons for
+// their filetype. Otherwise directories just get an arrow
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use crate::commands::Registry;
+use crate::project::Project;
+
+// Worst first when a directory has several inside it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Badge {
+    Conflict,
+    Deleted,
+    Modified,
+    Renamed,
+    Added,
+    Untracked,
+}
+
+impl Badge {
+    pub fn letter(&self) -> char {
+        match self {
+            Badge::Conflict => '!',
+            Badge::Deleted => 'D',
+            Badge::Modified => 'M',
+            Badge::Renamed => 'R',
+            Badge::Added => 'A',
+            Badge::Untracked => '?',
+        }
+    }
+}
+
+// Parses `git status --porcelain -z`, with paths relative to `root`, the top of the repository
+pub fn parse_status(output: &str, root: &Path) -> BTreeMap<PathBuf, Badge> {
+    let mut status = BTreeMap::new();
+    let mut entries = output.split('\0');
+    while let Some(entry) = entries.next() {
+        let (Some(code), Some(path)) = (entry.get(..2), entry.get(3..)) else { continue };
+        let badge = match code.as_bytes() {
+            [b'?', b'?'] => Badge::Untracked,
+            [b'U', _] | [_, b'U'] | [b'A', b'A'] | [b'D', b'D'] => Badge::Conflict,
+            [b'R', _] => {
+                // The path it was renamed from comes next
+                entries.next();
+                Badge::Renamed
+            }
+            [b'D', _] | [_, b'D'] => Badge::Deleted,
+            [b'A', _] => Badge::Added,
+            [b'!', b'!'] => continue,
+            _ => Badge::Modified,
+        };
+        status.insert(root.join(path), badge);
+    }
+    status
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Icons {
+    Plain,
+    NerdFont,
+}
+
+// A Nerd Font among the installed fonts, to add to the FontStack for icons
+pub fn find_icon_font(installed: &[PathBuf]) -> Option<&Path> {
+    let is_nerd_font = |path: &&PathBuf| path.file_name().and_then(|name| name.to_str()).is_some_and(|name| name.contains("NerdFont") || name.contains("Nerd Font"));
+    installed.iter().find(is_nerd_font).map(PathBuf::as_path)
+}
+
+pub fn icon(icons: Icons, path: &Path, is_dir: bool, expanded: bool) -> &'static str {
+    match (icons, is_dir) {
+        (Icons::Plain, true) if expanded => "▾ ",
+        (Icons::Plain, true) => "▸ ",
+        (Icons::Plain, false) => "  ",
+        (Icons::NerdFont, true) if expanded => "\u{f07c} ",
+        (Icons::NerdFont, true) => "\u{f07b} ",
+        (Icons::NerdFont, false) => match path.extension().and_then(|ext| ext.to_str()).unwrap_or_default() {
+            "rs" => "\u{e7a8} ",
+            "md" => "\u{f48a} ",
+            "toml" => "\u{e615} ",
+            "py" => "\u{e606} ",
+            "js" | "ts" => "\u{e74e} ",
+            "c" | "h" => "\u{e61e} ",
+            "png" | "jpg" | "svg" => "\u{f1c5} ",
+            _ => "\u{f15b} ",
+        },
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenIn {
+    Current,
+    HorizontalSplit,
+    VerticalSplit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Row {
+    path: PathBuf,
+    is_dir: bool,
+    depth: usize,
+}
+
+#[derive(Debug)]
+pub struct FileTree {
+    pub project: Project,
+    pub icons: Icons,
+    pub selected: usize,
+    rows: Vec<Row>,
+    expanded: BTreeSet<PathBuf>,
+    status: BTreeMap<PathBuf, Badge>,
+    pending: Option<mpsc::Receiver<Result<BTreeMap<PathBuf, Badge>, String>>>,
+}
+
+impl FileTree {
+    pub fn new(project: Project, icons: Icons) -> FileTree {
+        let mut tree = FileTree { project, icons, selected: 0, rows: Vec::new(), expanded: BTreeSet::new(), status: BTreeMap::new(), pending: None };
+        tree.refresh();
+        tree
+    }
+
+    // Lists the expanded directories again, keeping the same entry selected if it's still there
+    pub fn refresh(&mut self) {
+        let selected = self.rows.get(self.selected).map(|row| row.path.clone());
+        self.rows.clear();
+        let mut stack: Vec<(PathBuf, bool, usize)> = self.project.list_dir(&self.project.root).into_iter().rev().map(|(path, is_dir)| (path, is_dir, 0)).collect();
+        while let Some((path, is_dir, depth)) = stack.pop() {
+            if is_dir && self.expanded.contains(&path) {
+                stack.extend(self.project.list_dir(&path).into_iter().rev().map(|(path, is_dir)| (path, is_dir, depth + 1)));
+            }
+            self.rows.push(Row { path, is_dir, depth });
+        }
+        self.selected = selected.and_then(|path| self.rows.iter().position(|row| row.path == path)).unwrap_or(self.selected.min(self.rows.len().saturating_sub(1)));
+    }
+
+    // Runs git status in the background. poll_status picks up the result
+    pub fn refresh_status(&mut self) {
+        let (tx, rx) = mpsc::channel();
+        let root = self.project.root.clone();
+        std::thread::spawn(move || {
+            let result = git_status(&root);
+            let _ = tx.send(result);
+        });
+        self.pending = Some(rx);
+    }
+
+    // Whether git status is still running, for the host to keep polling
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // Returns true if there are new badges to draw
+    pub fn poll_status(&mut self) -> Result<bool, String> {
+        let Some(rx) = &self.pending else { return Ok(false) };
+        match rx.try_recv() {
+            Ok(result) => {
+                self.pending = None;
+                self.status = result?;
+                Ok(true)
+            }
+            Err(mpsc::TryRecvError::Empty) => Ok(false),
+            Err(mpsc::TryRecvError::Disconnected) => {
+                self.pending = None;
+                Err("git status thread died".to_string())
+            }
+        }
+    }
+
+    // A file's own status, or for a directory the worst of what's in it
+    pub fn badge(&self, path: &Path) -> Option<Badge> {
+        self.status.range(path.to_owned()..).take_while(|(changed, _)| changed.starts_with(path)).map(|(_, &badge)| badge).min()
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.rows
+            .iter()
+            .map(|row| {
+                let name = row.path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+                let icon = icon(self.icons, &row.path, row.is_dir, self.expanded.contains(&row.path));
+                let badge = self.badge(&row.path).map(|badge| format!(" {}", badge.letter())).unwrap_or_default();
+                format!("{}{icon}{name}{}{badge}", "  ".repeat(row.depth), if row.is_dir { "/" } else { "" })
+            })
+            .collect()
+    }
+
+    pub fn selected_path(&self) -> Option<&Path> {
+        self.rows.get(self.selected).map(|row| row.path.as_path())
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        self.selected = self.selected.saturating_add_signed(delta).min(self.rows.len().saturating_sub(1));
+    }
+
+    // Expands the selected directory, or collapses it if it was expanded
+    pub fn toggle(&mut self) {
+        let Some(row) = self.rows.get(self.selected).filter(|row| row.is_dir) else { return };
+        let path = row.path.clone();
+        if !self.expanded.remove(&path) {
+            self.expanded.insert(path);
+        }
+        self.refresh();
+    }
+
+    // Collapses the selected directory, or else selects the directory it's in
+    pub fn collapse(&mut self) {
+        let Some(row) = self.rows.get(self.selected) else { return };
+        if self.expanded.remove(&row.path) {
+            self.refresh();
+        } else if let Some(parent) = self.rows[..self.selected].iter().rposition(|above| above.depth + 1 == row.depth) {
+            self.selected = parent;
+        }
+    }
+
+    // Expands the directories down to `path` and selects it, to follow the focused buffer
+    pub fn reveal(&mut self, path: &Path) {
+        let Ok(relative) = path.strip_prefix(&self.project.root) else { return };
+        let mut dir = self.project.root.clone();
+        for component in relative.parent().into_iter().flat_map(Path::components) {
+            dir.push(component);
+            self.expanded.insert(dir.clone());
+        }
+        self.refresh();
+        if let Some(idx) = self.rows.iter().position(|row| row.path == path) {
+            self.selected = idx;
+        }
+    }
+
+    // Handles a key while the sidebar is focused. Returns a file to open
+    pub fn key(&mut self, key: &str) -> Option<(PathBuf, OpenIn)> {
+        let open_in = match key {
+            "l" | "<Right>" | "<Enter>" => OpenIn::Current,
+            "s" => OpenIn::HorizontalSplit,
+            "v" => OpenIn::VerticalSplit,
+            _ => {
+                match key {
+                    "j" | "<Down>" => self.move_selection(1),
+                    "k" | "<Up>" => self.move_selection(-1),
+                    "h" | "<Left>" => self.collapse(),
+                    "R" => {
+                        self.refresh();
+                        self.refresh_status();
+                    }
+                    _ => {}
+                }
+                return None;
+            }
+        };
+        let row = self.rows.get(self.selected)?;
+        if row.is_dir {
+            self.toggle();
+            return None;
+        }
+        Some((row.path.clone(), open_in))
+    }
+}
+
+fn git_status(root: &Path) -> Result<BTreeMap<PathBuf, Badge>, String> {
+    let output = std::process::Command::new("git").current_dir(root).args(["status", "--porcelain", "-z"]).output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    // Paths are relative to the repository, which may be above the project
+    let top = std::process::Command::new("git").current_dir(root).args(["rev-parse", "--show-toplevel"]).output().map_err(|e| e.to_string())?;
+    let top = PathBuf::from(String::from_utf8_lossy(&top.stdout).trim());
+    Ok(parse_status(&String::from_utf8_lossy(&output.stdout), &top))
+}
+
+// What the sidebar needs from the editor
+pub trait FileTreeHost {
+    fn project(&self) -> Option<Project>;
+    fn file_tree(&mut self) -> &mut Option<FileTree>;
+    fn icons(&self) -> Icons;
+    fn current_path(&self) -> Option<PathBuf>;
+}
+
+// :tree shows or hides the sidebar, opened on the focused file
+fn tree_command<Ctx: FileTreeHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    if ctx.file_tree().take().is_some() {
+        return Ok(());
+    }
+    let project = ctx.project().ok_or("No project to show the tree of")?;
+    let mut tree = FileTree::new(project, ctx.icons());
+    if let Some(path) = ctx.current_path() {
+        tree.reveal(&path);
+    }
+    tree.refresh_status();
+    *ctx.file_tree() = Some(tree);
+    Ok(())
+}
+
+pub fn register<Ctx: FileTreeHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("tree", tree_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::ProjectConfig;
+
+    #[test]
+    fn parses_status_badges() {
+        let root = Path::new("/repo");
+        let output = " M src/main.rs\0?? notes.txt\0R  src/new.rs\0src/old.rs\0UU src/lib.rs\0A  docs/a.md\0";
+        let status = parse_status(output, root);
+        assert_eq!(status.get(Path::new("/repo/src/main.rs")), Some(&Badge::Modified));
+        assert_eq!(status.get(Path::new("/repo/src/new.rs")), Some(&Badge::Renamed));
+        assert_eq!(status.get(Path::new("/repo/src/old.rs")), None);
+        assert_eq!(status.get(Path::new("/repo/src/lib.rs")), Some(&Badge::Conflict));
+        assert_eq!(status.get(Path::new("/repo/notes.txt")), Some(&Badge::Untracked));
+        assert_eq!(find_icon_font(&[PathBuf::from("/f/DejaVuSans.ttf"), PathBuf::from("/f/FiraCodeNerdFont-Regular.ttf")]), Some(Path::new("/f/FiraCodeNerdFont-Regular.ttf")));
+    }
+
+    #[test]
+    fn expands_and_opens() {
+        let dir = std::env::temp_dir().join(format!("rakoune-filetree-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src/bin")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join("src/main.rs"), "").unwrap();
+        std::fs::write(dir.join("src/bin/tool.rs"), "").unwrap();
+        std::fs::write(dir.join("README.md"), "").unwrap();
+        let project = Project { root: dir.clone(), config: ProjectConfig { exclude: vec!["target".to_string()], ..Default::default() }, hash: 0 };
+        let mut tree = FileTree::new(project, Icons::Plain);
+        assert_eq!(tree.lines(), vec!["▸ src/", "  README.md"]);
+
+        tree.key("<Enter>");
+        assert_eq!(tree.lines(), vec!["▾ src/", "  ▸ bin/", "    main.rs", "  README.md"]);
+        tree.key("j");
+        tree.key("j");
+        assert_eq!(tree.key("v"), Some((dir.join("src/main.rs"), OpenIn::VerticalSplit)));
+        // h from a file goes to its directory, and again collapses it
+        tree.key("h");
+        assert_eq!(tree.selected_path(), Some(dir.join("src").as_path()));
+        tree.key("h");
+        assert_eq!(tree.lines().len(), 2);
+
+        tree.reveal(&dir.join("src/bin/tool.rs"));
+        assert_eq!(tree.selected_path(), Some(dir.join("src/bin/tool.rs").as_path()));
+        tree.status = parse_status(" M src/bin/tool.rs\0?? src/main.rs\0", &dir);
+        assert_eq!(tree.badge(&dir.join("src")), Some(Badge::Modified));
+        assert_eq!(tree.lines(), vec!["▾ src/ M", "  ▾ bin/ M", "      tool.rs M", "    main.rs ?", "  README.md"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,285 @@
+use std::ops::Range;
+
+use crate::commands::Registry;
+use crate::layout::LineEdit;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fold {
+    // Line numbers covered by the fold, including the header line which stays visible when folded
+    pub lines: Range<usize>,
+    // Number of folds enclosing this one
+    pub depth: usize,
+}
+
+fn indent_of(line: &str) -> Option<usize> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    Some(line.chars().take_while(|c| c.is_whitespace()).map(|c| if c == '\t' { 4 } else { 1 }).sum())
+}
+
+// Every line followed by lines indented further than it starts a fold over those lines.
+// Blank lines belong to the block they are inside of, but not to the end of a block
+pub fn indent_folds(text: &str) -> Vec<Fold> {
+    let indents: Vec<Option<usize>> = text.split('\n').map(indent_of).collect();
+    let mut folds = Vec::new();
+    // Headers of folds still open, with their indent
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    let mut last_nonblank = 0;
+
+    for (idx, indent) in indents.iter().enumerate() {
+        let Some(indent) = *indent else { continue };
+        while let Some(&(header, header_indent)) = open.last() {
+            if indent > header_indent {
+                break;
+            }
+            open.pop();
+            if last_nonblank > header {
+                folds.push(Fold { lines: header..last_nonblank + 1, depth: open.len() });
+            }
+        }
+        open.push((idx, indent));
+        last_nonblank = idx;
+    }
+    while let Some((header, _)) = open.pop() {
+        if last_nonblank > header {
+            folds.push(Fold { lines: header..last_nonblank + 1, depth: open.len() });
+        }
+    }
+    folds.sort_by_key(|fold| fold.lines.start);
+    folds
+}
+
+// Which folds of a buffer are currently closed
+#[derive(Debug, Default, Clone)]
+pub struct FoldState {
+    pub closed: Vec<Range<usize>>,
+}
+
+impl FoldState {
+    // Closes the innermost fold containing `line` that isn't closed already
+    pub fn fold(&mut self, available: &[Fold], line: usize) -> bool {
+        let candidate = available
+            .iter()
+            .filter(|fold| fold.lines.contains(&line) && !self.closed.contains(&fold.lines))
+            .max_by_key(|fold| fold.depth);
+        match candidate {
+            Some(fold) => {
+                self.closed.push(fold.lines.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Opens the innermost closed fold containing `line`
+    pub fn unfold(&mut self, line: usize) -> bool {
+        let innermost = self.closed
+            .iter()
+            .enumerate()
+            .filter(|(_, fold)| fold.contains(&line))
+            .min_by_key(|(_, fold)| fold.len())
+            .map(|(idx, _)| idx);
+        match innermost {
+            Some(idx) => {
+                self.closed.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn toggle(&mut self, available: &[Fold], line: usize) {
+        if !self.unfold(line) {
+            self.fold(available, line);
+        }
+    }
+
+    // Closes exactly the folds nested at least `level` deep, like vim's foldlevel
+    pub fn set_level(&mut self, available: &[Fold], level: usize) {
+        self.closed = available
+            .iter()
+            .filter(|fold| fold.depth >= level)
+            .map(|fold| fold.lines.clone())
+            .collect();
+    }
+
+    pub fn is_hidden(&self, line: usize) -> bool {
+        self.closed.iter().any(|fold| fold.start < line && line < fold.end)
+    }
+
+    // Opens every fold hiding `line`, for example when a search match or the cursor lands inside it
+    pub fn reveal(&mut self, line: usize) {
+        self.closed.retain(|fold| !(fold.start < line && line < fold.end));
+    }
+
+    // The visible line that the cursor should be on when it is placed at `line`
+    pub fn visible_line(&self, line: usize) -> usize {
+        self.closed
+            .iter()
+            .filter(|fold| fold.start < line && line < fold.end)
+            .map(|fold| fold.start)
+            .min()
+            .unwrap_or(line)
+    }
+
+    // Keeps the folds on their lines through an edit. Folds the edit reaches into from outside are
+    // opened, as are all of them after edits too big to say which lines they were in
+    pub fn edited(&mut self, edit: Option<&LineEdit>) {
+        let Some(edit) = edit else { return self.closed.clear() };
+        let (added, removed) = (edit.new_lines, edit.lines.len());
+        self.closed.retain_mut(|fold| {
+            if edit.lines.start >= fold.end {
+                return true;
+            }
+            // Up to and including the header, which stays where it was relative to the rest
+            if edit.lines.end <= fold.start + 1 {
+                *fold = fold.start + added - removed..fold.end + added - removed;
+                return true;
+            }
+            if edit.lines.start > fold.start && edit.lines.end <= fold.end {
+                fold.end = fold.end + added - removed;
+                return fold.len() > 1;
+            }
+            false
+        });
+    }
+
+    // Moves `delta` visible lines from `line`, skipping over folded lines
+    pub fn move_lines(&self, line: usize, delta: isize, n_lines: usize) -> usize {
+        let mut line = self.visible_line(line);
+        for _ in 0..delta.unsigned_abs() {
+            let mut next = line;
+            loop {
+                next = if delta < 0 {
+                    match next.checked_sub(1) {
+                        Some(n) => n,
+                        None => return line,
+                    }
+                } else if next + 1 < n_lines {
+                    next + 1
+                } else {
+                    return line;
+                };
+                if !self.is_hidden(next) {
+                    break;
+                }
+            }
+            line = next;
+        }
+        line
+    }
+}
+
+// What the fold commands need from the editor
+pub trait FoldHost {
+    // Of the current buffer, which the folds are of
+    fn available_folds(&self) -> Vec<Fold>;
+    fn fold_state(&mut self) -> &mut FoldState;
+    fn cursor_line(&self) -> usize;
+    // Moves cursors hidden by folds just closed to their first lines
+    fn cursor_out_of_folds(&mut self);
+}
+
+// fold, closing the innermost open fold around the cursor
+fn fold_command<Ctx: FoldHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: fold".to_string());
+    }
+    let (available, line) = (ctx.available_folds(), ctx.cursor_line());
+    if !ctx.fold_state().fold(&available, line) {
+        return Err("Nothing to fold here".to_string());
+    }
+    ctx.cursor_out_of_folds();
+    Ok(())
+}
+
+// unfold [all]
+fn unfold_command<Ctx: FoldHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let line = ctx.cursor_line();
+    match args {
+        [] if !ctx.fold_state().unfold(line) => Err("No fold here".to_string()),
+        [] => Ok(()),
+        ["all"] => {
+            ctx.fold_state().closed.clear();
+            Ok(())
+        }
+        _ => Err("Usage: unfold [all]".to_string()),
+    }
+}
+
+fn toggle_fold_command<Ctx: FoldHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: toggle-fold".to_string());
+    }
+    let (available, line) = (ctx.available_folds(), ctx.cursor_line());
+    ctx.fold_state().toggle(&available, line);
+    ctx.cursor_out_of_folds();
+    Ok(())
+}
+
+// fold-level <level>, where 0 folds everything and 1 leaves the outermost folds open
+fn fold_level_command<Ctx: FoldHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let level = match args {
+        [level] => level.parse().map_err(|_| format!("Not a level: {level}"))?,
+        _ => return Err("Usage: fold-level <level>".to_string()),
+    };
+    let available = ctx.available_folds();
+    ctx.fold_state().set_level(&available, level);
+    ctx.cursor_out_of_folds();
+    Ok(())
+}
+
+pub fn register<Ctx: FoldHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("fold", fold_command::<Ctx>);
+    registry.add_builtin("unfold", unfold_command::<Ctx>);
+    registry.add_builtin("toggle-fold", toggle_fold_command::<Ctx>);
+    registry.add_builtin("fold-level", fold_level_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "fn main() {\n    if x {\n        a();\n\n        b();\n    }\n}\n";
+
+    #[test]
+    fn folds_by_indentation() {
+        let folds = indent_folds(TEXT);
+        assert_eq!(folds, vec![
+            Fold { lines: 0..6, depth: 0 },
+            Fold { lines: 1..5, depth: 1 },
+        ]);
+    }
+
+    #[test]
+    fn movement_skips_folds() {
+        let folds = indent_folds(TEXT);
+        let mut state = FoldState::default();
+        state.fold(&folds, 2);
+        assert_eq!(state.closed.len(), 1);
+        assert_eq!(state.closed[0], 1..5);
+        assert!(state.is_hidden(3));
+        assert_eq!(state.move_lines(0, 2, 8), 5);
+        assert_eq!(state.move_lines(5, -1, 8), 1);
+        assert_eq!(state.visible_line(4), 1);
+
+        state.reveal(3);
+        assert!(state.closed.is_empty());
+    }
+
+    #[test]
+    fn folds_move_with_edits() {
+        let mut state = FoldState { closed: vec![1..5, 8..10] };
+        // A line added above both, then one removed inside the first
+        state.edited(Some(&LineEdit { lines: 0..1, new_lines: 2 }));
+        assert_eq!(state.closed, vec![2..6, 9..11]);
+        state.edited(Some(&LineEdit { lines: 3..5, new_lines: 1 }));
+        assert_eq!(state.closed, vec![2..5, 8..10]);
+        // Reaching into the second from before it
+        state.edited(Some(&LineEdit { lines: 6..10, new_lines: 4 }));
+        assert_eq!(state.closed, vec![2..5]);
+        state.edited(None);
+        assert!(state.closed.is_empty());
+    }
+}
@@ -1,6 +1,17 @@
+use std::cell::OnceCell;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use thiserror::Error;
 
+// Font files are leaked so faces can borrow them for 'static, and never freed. Each file is only
+// leaked once, however often its faces are loaded, like when the font picker previews it again
+static LEAKED_FILES: Mutex<Vec<(PathBuf, &'static [u8])>> = Mutex::new(Vec::new());
+
+// The font files read so far and how big each is, for :memory
+pub fn leaked_files() -> Vec<(PathBuf, usize)> {
+    LEAKED_FILES.lock().unwrap().iter().map(|(path, data)| (path.clone(), data.len())).collect()
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Parsing ttf failed: {0:?}")]
@@ -32,23 +43,75 @@ impl FontStack {
     }
 
     pub fn add_fallback(&mut self, at: &Path) -> Result<(), Error> {
-        self.faces.extend(Face::load_all_indices(at)?);
+        for face in Face::load_all_indices(at)? {
+            self.add_face(face);
+        }
         Ok(())
     }
 
-    pub fn add_face(&mut self, face: Face) {
+    // The primary font and its fallbacks, read and parsed in parallel. Fallbacks that fail are skipped and returned
+    pub fn load(primary: &Path, fallbacks: &[&Path]) -> Result<(FontStack, Vec<(PathBuf, Error)>), Error> {
+        let paths: Vec<&Path> = std::iter::once(primary).chain(fallbacks.iter().copied()).collect();
+        let mut parsed = std::thread::scope(|scope| {
+            let threads: Vec<_> = paths.iter().map(|path| scope.spawn(move || read_faces(path))).collect();
+            threads.into_iter().map(|thread| thread.join().expect("Font parsing panicked")).collect::<Vec<_>>()
+        })
+        .into_iter();
+        let (data, faces) = parsed.next().unwrap()?;
+        let mut stack = FontStack { faces: faces.into_iter().enumerate().map(|(index, ttf_face)| Face::new(data, index as u32, ttf_face)).collect() };
+        let mut failed = Vec::new();
+        for (path, parsed) in fallbacks.iter().zip(parsed) {
+            match parsed {
+                Ok((data, faces)) => faces.into_iter().enumerate().for_each(|(index, ttf_face)| stack.add_face(Face::new(data, index as u32, ttf_face))),
+                Err(e) => failed.push((path.to_path_buf(), e)),
+            }
+        }
+        Ok((stack, failed))
+    }
+
+    // The same fallbacks behind the faces of the file at `primary`, in place of those of the
+    // current primary face's file
+    pub fn with_primary(&self, primary: &Path) -> Result<FontStack, Error> {
+        let mut stack = FontStack::new(primary)?;
+        let old = self.faces.first().map(|face| face.data.as_ptr());
+        for face in self.faces.iter().filter(|face| Some(face.data.as_ptr()) != old) {
+            stack.add_face(Face::new(face.data, face.index, face.ttf_face.clone()));
+        }
+        Ok(stack)
+    }
+
+    pub fn add_face(&mut self, mut face: Face) {
+        if let Some(primary) = self.faces.first() {
+            face.size_scale = face.harmonizing_scale(primary);
+        }
         self.faces.push(face)
     }
 
+    // Metrics covering every face in the stack, so that lines have the same height no matter which faces they use
+    pub fn vertical_metrics(&self) -> VerticalMetrics {
+        let mut metrics = self.faces.first().map(Face::vertical_metrics).unwrap_or_default();
+        for face in self.faces.iter().skip(1) {
+            let m = face.vertical_metrics();
+            metrics.ascent = metrics.ascent.max(m.ascent);
+            metrics.descent = metrics.descent.max(m.descent);
+        }
+        metrics
+    }
+
     pub fn shape<'a>(&'a self, text: &str) -> Vec<(Option<ShapedCodepoint<'a>>, std::ops::Range<usize>)> {
         self.shape_with_index(text, 0, 0)
     }
 
+    #[allow(clippy::into_iter_on_ref, clippy::useless_conversion, clippy::redundant_field_names, clippy::clone_on_copy)]
     fn shape_with_index<'a>(&'a self, text: &str, text_offset: usize, font_index: usize) -> Vec<(Option<ShapedCodepoint<'a>>, std::ops::Range<usize>)> {
+        // harfbuzz gives a null glyph array for an empty buffer, which can't be made into a slice
+        if text.is_empty() {
+            return Vec::new();
+        }
         let face = &self.faces[font_index];
 
         let buffer = harfbuzz_rs::UnicodeBuffer::new().add_str(text);
-        let glyphbuf = harfbuzz_rs::shape(&face.hb_font, buffer, &[]);
+        let glyphbuf = harfbuzz_rs::shape(face.hb_font(), buffer, &[]);
 
         let mut shaped: Vec<_> = glyphbuf
             .get_glyph_infos()
@@ -98,14 +161,55 @@ impl FontStack {
     }
 }
 
+// Vertical metrics relative to the em size, with descent pointing downwards (positive)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VerticalMetrics {
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_gap: f32,
+}
+
+// Reads a font file and parses the header of every face in it. Only needs ttf-parser, so it's
+// cheap and can run on any thread
+fn read_faces(at: &Path) -> Result<(&'static [u8], Vec<ttf_parser::Face<'static>>), Error> {
+    let leaked = LEAKED_FILES.lock().unwrap().iter().find(|(path, _)| path == at).map(|(_, data)| *data);
+    let static_data: &'static [u8] = match leaked {
+        Some(data) => data,
+        None => {
+            let data = std::fs::read(at).map_err(|e| Error::CouldNotRead(at.to_owned(), e))?;
+            let data = data.leak(); // :3
+            LEAKED_FILES.lock().unwrap().push((at.to_owned(), data));
+            data
+        }
+    };
+
+    let mut faces = Vec::new();
+    for index in 0.. {
+        match ttf_parser::Face::parse(static_data, index) {
+            Ok(face) => faces.push(face),
+            Err(ttf_parser::FaceParsingError::FaceIndexOutOfBounds) => break,
+            Err(e) => Err(e)?,
+        }
+    }
+    Ok((static_data, faces))
+}
+
+// Shaping and rasterizing need harfbuzz and fontdue, which parse the whole font, fontdue every
+// glyph outline. Most fallback faces are never used, so those are only made on first use
 pub struct Face {
     pub name: String,
-    pub hb_font: harfbuzz_rs::Owned<harfbuzz_rs::Font<'static>>, // TODO: Proper memory management :3
-    pub fontdue_font: fontdue::Font,
+    data: &'static [u8],
+    index: u32,
+    hb_font: OnceCell<harfbuzz_rs::Owned<harfbuzz_rs::Font<'static>>>, // TODO: Proper memory management :3
+    // Or why fontdue couldn't read it
+    fontdue_font: OnceCell<Result<fontdue::Font, String>>,
     pub ttf_face: ttf_parser::Face<'static>,
     pub n_glyphs: u16,
     pub italic: bool,
     pub bold: bool,
+    // Multiplier applied to the font size when rendering this face, so that fallback faces
+    // appear about as large as the primary face. Always 1 for the primary face
+    pub size_scale: f32,
 }
 
 impl std::fmt::Debug for Face {
@@ -115,27 +219,15 @@ impl std::fmt::Debug for Face {
             .field("n_glyphs", &self.n_glyphs)
             .field("italic", &self.italic)
             .field("bold", &self.bold)
+            .field("size_scale", &self.size_scale)
             .finish_non_exhaustive()
     }
 }
 
 impl Face {
     pub fn load_all_indices(at: &Path) -> Result<Vec<Face>, Error> {
-        use std::io::Read;
-        let mut f = std::fs::File::open(at).map_err(|e| Error::CouldNotRead(at.to_owned(), e))?;
-        let mut data = Vec::new();
-        f.read_to_end(&mut data).map_err(|e| Error::CouldNotRead(at.to_owned(), e))?;
-        let static_data: &'static [u8] = data.leak(); // :3
-
-        let mut faces = Vec::new();
-        for i in 0.. {
-            match Face::from_data_index(static_data, i) {
-                Ok(f) => faces.push(f),
-                Err(Error::FontIndexOutOfRange(_)) => break,
-                Err(e) => return Err(e),
-            }
-        }
-        Ok(faces)
+        let (data, faces) = read_faces(at)?;
+        Ok(faces.into_iter().enumerate().map(|(index, ttf_face)| Face::new(data, index as u32, ttf_face)).collect())
     }
 
     pub fn from_data_index(data: &'static [u8], index: u32) -> Result<Face, Error> {
@@ -144,27 +236,104 @@ impl Face {
             Err(ttf_parser::FaceParsingError::FaceIndexOutOfBounds) => return Err(Error::FontIndexOutOfRange(index)),
             Err(e) => Err(e)?,
         };
-        let italic = ttf_face.is_italic();
-        let bold = ttf_face.is_bold();
-        let n_glyphs = ttf_face.number_of_glyphs();
-
-        let name = get_name_by_id(&ttf_face, NAME_ID_FULL_NAME).unwrap_or("(unknown name)".to_string());
+        Ok(Face::new(data, index, ttf_face))
+    }
 
-        let hb_font = harfbuzz_rs::Font::new(harfbuzz_rs::Face::from_bytes(data, index));
-        let fontdue_font = fontdue::Font::from_bytes(
+    fn new(data: &'static [u8], index: u32, ttf_face: ttf_parser::Face<'static>) -> Face {
+        Face {
+            name: get_name_by_id(&ttf_face, NAME_ID_FULL_NAME).unwrap_or("(unknown name)".to_string()),
             data,
-            fontdue::FontSettings {
-                collection_index: index,
-                ..Default::default()
-            }
-        ).map_err(|e| Error::ParseFailFontdue(e.to_string()))?;
+            index,
+            hb_font: OnceCell::new(),
+            fontdue_font: OnceCell::new(),
+            italic: ttf_face.is_italic(),
+            bold: ttf_face.is_bold(),
+            n_glyphs: ttf_face.number_of_glyphs(),
+            ttf_face,
+            size_scale: 1.,
+        }
+    }
 
-        Ok(Face {
-            name,
-            hb_font, fontdue_font, ttf_face,
-            italic, bold, n_glyphs,
-        })
+    pub fn hb_font(&self) -> &harfbuzz_rs::Owned<harfbuzz_rs::Font<'static>> {
+        self.hb_font.get_or_init(|| harfbuzz_rs::Font::new(harfbuzz_rs::Face::from_bytes(self.data, self.index)))
+    }
+
+    // Fails if fontdue can't read the face, even though ttf-parser could. Only tried once
+    pub fn fontdue_font(&self) -> Result<&fontdue::Font, Error> {
+        self.fontdue_font
+            .get_or_init(|| {
+                let settings = fontdue::FontSettings { collection_index: self.index, ..Default::default() };
+                fontdue::Font::from_bytes(self.data, settings).map_err(|e| format!("{}: {e}", self.name))
+            })
+            .as_ref()
+            .map_err(|e| Error::ParseFailFontdue(e.clone()))
     }
+
+    // ttf-parser picks between hhea and OS/2 metrics as the font requests
+    pub fn vertical_metrics(&self) -> VerticalMetrics {
+        let scale = self.size_scale / self.ttf_face.units_per_em() as f32;
+        VerticalMetrics {
+            ascent: self.ttf_face.ascender() as f32 * scale,
+            descent: -self.ttf_face.descender() as f32 * scale,
+            line_gap: self.ttf_face.line_gap() as f32 * scale,
+        }
+    }
+
+    // Height of lowercase letters (or failing that, uppercase letters, or the ascender) relative to the em size
+    pub fn relative_glyph_height(&self) -> f32 {
+        let height = self.ttf_face.x_height()
+            .or(self.ttf_face.capital_height())
+            .filter(|&h| h > 0)
+            .unwrap_or(self.ttf_face.ascender());
+        height as f32 / self.ttf_face.units_per_em() as f32
+    }
+
+    // Scale that makes glyphs in this face about as tall as in `primary`
+    pub fn harmonizing_scale(&self, primary: &Face) -> f32 {
+        // Only compare like with like: an x-height against a cap height would be off by a lot
+        let (ours, theirs) = match (self.ttf_face.x_height(), primary.ttf_face.x_height()) {
+            (Some(a), Some(b)) if a > 0 && b > 0 => (a as f32 / self.ttf_face.units_per_em() as f32, b as f32 / primary.ttf_face.units_per_em() as f32),
+            _ => (self.relative_glyph_height(), primary.relative_glyph_height()),
+        };
+        if ours <= 0. {
+            return 1.;
+        }
+        (theirs / ours).clamp(0.5, 2.)
+    }
+}
+
+// Font files in the usual system and user font directories, sorted by path
+pub fn installed_fonts() -> Vec<PathBuf> {
+    let mut dirs = vec![
+        PathBuf::from("/usr/share/fonts"),
+        PathBuf::from("/usr/local/share/fonts"),
+        PathBuf::from("/System/Library/Fonts"),
+        PathBuf::from("/Library/Fonts"),
+        PathBuf::from("C:\\Windows\\Fonts"),
+    ];
+    if let Some(home) = std::env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        dirs.push(home.join(".fonts"));
+        dirs.push(home.join(".local/share/fonts"));
+        dirs.push(home.join("Library/Fonts"));
+    }
+
+    let mut fonts = Vec::new();
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+                if ["ttf", "otf", "ttc", "otc"].contains(&ext.to_lowercase().as_str()) {
+                    fonts.push(path);
+                }
+            }
+        }
+    }
+    fonts.sort();
+    fonts
 }
 
 #[allow(unused)]
@@ -178,6 +347,7 @@ pub const NAME_ID_FULL_NAME: u16 = 4;
 
 // Get the field, preferring English
 pub fn get_name_by_id(ttf_face: &ttf_parser::Face, id: u16) -> Option<String> {
+    #[allow(clippy::manual_ok_err)]
     fn get_name(name: ttf_parser::name::Name) -> Option<String> {
         if let Some(x) = name.to_string() {
             Some(x)
@@ -204,3 +374,25 @@ pub fn get_name_by_id(ttf_face: &ttf_parser::Face, id: u16) -> Option<String> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faces_load_lazily() {
+        let primary = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/firacode-regular.ttf"));
+        let missing = Path::new("/nonexistent/font.ttf");
+        let (stack, failed) = FontStack::load(primary, &[missing, primary]).unwrap();
+        assert_eq!(stack.faces.len(), 2);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, missing);
+
+        assert!(stack.faces[1].hb_font.get().is_none() && stack.faces[1].fontdue_font.get().is_none());
+        stack.shape("fn");
+        assert!(stack.faces[0].hb_font.get().is_some());
+        assert!(stack.faces[1].hb_font.get().is_none());
+        assert!(stack.faces[0].fontdue_font().is_ok());
+        assert!(FontStack::load(missing, &[]).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+// :format, with the external formatter configured for the filetype or else the language server.
+// Formatters return the whole formatted text, and servers often a single edit replacing
+// everything, so the result is diffed against the buffer and only what changed is replaced.
+// Cursors and marks on untouched text stay where they were
+
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use thiserror::Error;
+
+use crate::commands::Registry;
+use crate::workspace_edit::{self, EditHost, TextEdit};
+
+// Bigger changed regions are replaced in one piece instead of diffed line by line, which takes
+// memory for each pair of lines
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't run {0}: {1}")]
+    Spawn(String, std::io::Error),
+    #[error("{0} failed: {1}")]
+    Failed(String, String),
+    #[error("{0} wrote output that isn't UTF-8")]
+    NotUtf8(String),
+}
+
+// Pipes `text` through `command`, run by the shell in `cwd`
+pub fn run_formatter(command: &str, text: &str, cwd: &Path) -> Result<String, Error> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(cwd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::Spawn(command.to_string(), e))?;
+    // Written from another thread, so a formatter writing before it has read everything can't
+    // fill its stdout pipe and wait on us forever
+    let mut stdin = child.stdin.take().unwrap();
+    let input = text.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().map_err(|e| Error::Spawn(command.to_string(), e))?;
+    // A formatter exiting without reading everything shows up in its exit status
+    let _ = writer.join();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(Error::Failed(command.to_string(), if stderr.is_empty() { output.status.to_string() } else { stderr }));
+    }
+    String::from_utf8(output.stdout).map_err(|_| Error::NotUtf8(command.to_string()))
+}
+
+// Edits turning `old` into `new`, as byte ranges of `old` and their replacements, sorted. Lines are
+// diffed, then each changed part shrunk to what actually differs in it
+pub fn minimal_edits(old: &str, new: &str) -> Vec<(Range<usize>, String)> {
+    let a: Vec<&str> = old.split_inclusive('\n').collect();
+    let b: Vec<&str> = new.split_inclusive('\n').collect();
+    let prefix = a.iter().zip(&b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+    let matches = match a_mid.len().saturating_mul(b_mid.len()) <= MAX_DIFF_CELLS {
+        true => common_lines(a_mid, b_mid),
+        false => Vec::new(),
+    };
+
+    let mut edits = Vec::new();
+    let mut at: usize = a[..prefix].iter().map(|line| line.len()).sum();
+    let (mut i, mut j) = (0, 0);
+    // The end works like one more matching line, to finish the last changed part
+    for (next_i, next_j) in matches.into_iter().chain([(a_mid.len(), b_mid.len())]) {
+        let old_len: usize = a_mid[i..next_i].iter().map(|line| line.len()).sum();
+        if next_i - i == next_j - j {
+            // As many lines changed as there are new ones, like when reindenting, so each line
+            // only gets edited within itself
+            let mut line_at = at;
+            for (old_line, new_line) in a_mid[i..next_i].iter().zip(&b_mid[j..next_j]) {
+                push_shrunk(&mut edits, old, line_at..line_at + old_line.len(), new_line);
+                line_at += old_line.len();
+            }
+        } else {
+            push_shrunk(&mut edits, old, at..at + old_len, &b_mid[j..next_j].concat());
+        }
+        at += old_len + a_mid.get(next_i).map_or(0, |line| line.len());
+        (i, j) = (next_i + 1, next_j + 1);
+    }
+    edits
+}
+
+// Indices of the lines in a longest common subsequence of `a` and `b`
+fn common_lines(a: &[&str], b: &[&str]) -> Vec<(usize, usize)> {
+    let (n, m) = (a.len(), b.len());
+    let idx = |i: usize, j: usize| i * (m + 1) + j;
+    // Length of the longest common subsequence of a[i..] and b[j..]
+    let mut lengths = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[idx(i, j)] = match a[i] == b[j] {
+                true => lengths[idx(i + 1, j + 1)] + 1,
+                false => lengths[idx(i + 1, j)].max(lengths[idx(i, j + 1)]),
+            };
+        }
+    }
+    let mut matches = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if a[i] == b[j] {
+            matches.push((i, j));
+            (i, j) = (i + 1, j + 1);
+        } else if lengths[idx(i + 1, j)] >= lengths[idx(i, j + 1)] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    matches
+}
+
+// Leaves out what `old[range]` and `replacement` start and end with in common
+fn push_shrunk(edits: &mut Vec<(Range<usize>, String)>, old: &str, range: Range<usize>, replacement: &str) {
+    let replaced = &old[range.clone()];
+    let prefix: usize = replaced.chars().zip(replacement.chars()).take_while(|(x, y)| x == y).map(|(c, _)| c.len_utf8()).sum();
+    let (replaced_rest, replacement_rest) = (&replaced[prefix..], &replacement[prefix..]);
+    let suffix: usize = replaced_rest.chars().rev().zip(replacement_rest.chars().rev()).take_while(|(x, y)| x == y).map(|(c, _)| c.len_utf8()).sum();
+    if replaced_rest.len() == suffix && replacement_rest.len() == suffix {
+        return;
+    }
+    let start = range.start + prefix;
+    edits.push((start..range.end - suffix, replacement_rest[..replacement_rest.len() - suffix].to_string()));
+}
+
+// Replaces the text of the buffer for `path` with `formatted`, changing only what differs
+pub fn apply_formatted(host: &mut impl EditHost, path: &Path, formatted: &str) -> Result<(), String> {
+    let mut text = host.buffer_text(path)?;
+    let edits = minimal_edits(&text, formatted);
+    if edits.is_empty() {
+        return Ok(());
+    }
+    let applied = workspace_edit::apply(&mut text, &edits);
+    host.set_buffer_text(path, text, &applied);
+    Ok(())
+}
+
+// For the edits of a textDocument/formatting response
+pub fn apply_formatting_edits(host: &mut impl EditHost, path: &Path, edits: &[TextEdit]) -> Result<(), String> {
+    let mut formatted = host.buffer_text(path)?;
+    let resolved = workspace_edit::resolve(path, &formatted, edits).map_err(|e| e.to_string())?;
+    workspace_edit::apply(&mut formatted, &resolved);
+    apply_formatted(host, path, &formatted)
+}
+
+// What :format needs from the editor
+pub trait FormatHost: EditHost {
+    fn current_path(&self) -> Option<PathBuf>;
+    // The external formatter configured for `path`, and the directory to run it in
+    fn formatter(&self, path: &Path) -> Option<(String, PathBuf)>;
+    // Whether `path` should be formatted before it's written
+    fn format_on_save(&self, path: &Path) -> bool;
+    // Asks the language server for textDocument/formatting. The response goes to
+    // apply_formatting_edits
+    fn request_formatting(&mut self, path: &Path) -> Result<(), String>;
+}
+
+pub fn format_buffer(host: &mut impl FormatHost, path: &Path) -> Result<(), String> {
+    match host.formatter(path) {
+        Some((command, cwd)) => {
+            let text = host.buffer_text(path)?;
+            let formatted = run_formatter(&command, &text, &cwd).map_err(|e| e.to_string())?;
+            apply_formatted(host, path, &formatted)
+        }
+        None => host.request_formatting(path),
+    }
+}
+
+// To be called by :w before writing `path`
+pub fn before_save(host: &mut impl FormatHost, path: &Path) -> Result<(), String> {
+    match host.format_on_save(path) {
+        true => format_buffer(host, path),
+        false => Ok(()),
+    }
+}
+
+fn format_command<Ctx: FormatHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    let path = ctx.current_path().ok_or("No buffer to format")?;
+    format_buffer(ctx, &path)
+}
+
+pub fn register<Ctx: FormatHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("format", format_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::marks::Marks;
+
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    fn apply_all(old: &str, new: &str) -> String {
+        let mut text = old.to_string();
+        workspace_edit::apply(&mut text, &minimal_edits(old, new));
+        text
+    }
+
+    #[test]
+    fn edits_only_what_changed() {
+        let old = "fn main(){\n  let x=1;\n    println!(\"{x}\");\n}\n";
+        let new = "fn main() {\n    let x = 1;\n    println!(\"{x}\");\n}\n";
+        let edits = minimal_edits(old, new);
+        assert_eq!(apply_all(old, new), new);
+        assert!(edits.iter().all(|(range, _)| range.end <= old.find("println").unwrap()), "{edits:?}");
+        assert_eq!(edits[0], (9..9, " ".to_string()));
+        assert!(minimal_edits(new, new).is_empty());
+
+        // A mark on the println line moves with it, as it's on text that wasn't touched
+        let mut marks = Marks::default();
+        let println = old.find("println").unwrap();
+        marks.set('a', println);
+        let mut text = old.to_string();
+        for (replaced, new_len) in workspace_edit::apply(&mut text, &edits) {
+            marks.apply_edit(replaced, new_len);
+        }
+        assert_eq!(marks.get('a'), new.find("println"));
+    }
+
+    #[test]
+    fn random_changes_round_trip() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let words = ["a", "bé", "\n", "  ", "{", "}\n", "x = 1;\n"];
+        for _ in 0..300 {
+            let old: String = (0..rng.below(30)).map(|_| words[rng.below(words.len())]).collect();
+            let mut new = old.clone();
+            for _ in 0..rng.below(4) {
+                let boundaries: Vec<usize> = (0..=new.len()).filter(|&at| new.is_char_boundary(at)).collect();
+                let at = boundaries[rng.below(boundaries.len())];
+                match rng.below(2) {
+                    0 => new.insert_str(at, words[rng.below(words.len())]),
+                    _ => {
+                        let end = boundaries[rng.below(boundaries.len())].max(at);
+                        new.replace_range(at..end, "");
+                    }
+                }
+            }
+            assert_eq!(apply_all(&old, &new), new, "{old:?} -> {new:?}");
+        }
+    }
+
+    #[test]
+    fn external_formatter() {
+        let cwd = std::env::temp_dir();
+        assert_eq!(run_formatter("tr a-z A-Z", "fn main\n", &cwd).unwrap(), "FN MAIN\n");
+        let failed = run_formatter("echo 'bad input' >&2; exit 1", "", &cwd).unwrap_err();
+        assert_eq!(failed.to_string(), "echo 'bad input' >&2; exit 1 failed: bad input");
+    }
+}
@@ -0,0 +1,111 @@
+// Finding something to draw with. A real GPU first, then the software adapter of the platform
+// (llvmpipe, lavapipe, WARP), then OpenGL on its own, which VMs and remote desktops often have even
+// when Vulkan or DX12 isn't there. Only when all of those fail does rakoune give up
+
+use crate::error::RenderError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attempt {
+    pub backends: wgpu::Backends,
+    // Asks for the software adapter
+    pub force_fallback_adapter: bool,
+}
+
+pub const ATTEMPTS: [Attempt; 3] = [
+    Attempt { backends: wgpu::Backends::all(), force_fallback_adapter: false },
+    Attempt { backends: wgpu::Backends::all(), force_fallback_adapter: true },
+    Attempt { backends: wgpu::Backends::GL, force_fallback_adapter: false },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdapterKind {
+    Hardware,
+    // Rasterizing on the CPU, behind a GPU API
+    Software,
+    Gl,
+}
+
+impl AdapterKind {
+    pub fn of(info: &wgpu::AdapterInfo) -> AdapterKind {
+        if info.device_type == wgpu::DeviceType::Cpu {
+            AdapterKind::Software
+        } else if info.backend == wgpu::Backend::Gl {
+            AdapterKind::Gl
+        } else {
+            AdapterKind::Hardware
+        }
+    }
+}
+
+pub struct Gpu {
+    // The surface belongs to the instance it was made with, so both are kept
+    pub instance: wgpu::Instance,
+    // None while suspended, when the window has nothing to draw to
+    pub surface: Option<wgpu::Surface>,
+    pub adapter: wgpu::Adapter,
+    pub kind: AdapterKind,
+}
+
+impl Gpu {
+    // Goes through ATTEMPTS until one gives an adapter that can draw to the window
+    pub fn new(window: &winit::window::Window) -> Result<Gpu, RenderError> {
+        let mut tried = Vec::new();
+        for attempt in ATTEMPTS {
+            let instance = wgpu::Instance::new(wgpu::InstanceDescriptor { backends: attempt.backends, ..Default::default() });
+            // Safety: the window outlives the surface, as both live until the event loop exits
+            let surface = match unsafe { instance.create_surface(window) } {
+                Ok(surface) => surface,
+                Err(e) => {
+                    tried.push(format!("{:?}: {e}", attempt.backends));
+                    continue;
+                }
+            };
+            let options = wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: attempt.force_fallback_adapter,
+                compatible_surface: Some(&surface),
+            };
+            match futures::executor::block_on(instance.request_adapter(&options)) {
+                Some(adapter) => {
+                    let kind = AdapterKind::of(&adapter.get_info());
+                    return Ok(Gpu { instance, surface: Some(surface), adapter, kind });
+                }
+                None => tried.push(format!("{:?}{}: no adapter", attempt.backends, if attempt.force_fallback_adapter { " (software)" } else { "" })),
+            }
+        }
+        Err(RenderError::NoAdapter(tried.join(", ")))
+    }
+
+    // Again for the same window after resuming
+    pub fn create_surface(&mut self, window: &winit::window::Window) -> Result<&wgpu::Surface, RenderError> {
+        // Safety: as in new
+        let surface = unsafe { self.instance.create_surface(window) }?;
+        Ok(self.surface.insert(surface))
+    }
+
+    // For the notifications, when drawing will be slower than usual
+    pub fn warning(&self) -> Option<String> {
+        let name = self.adapter.get_info().name;
+        match self.kind {
+            AdapterKind::Hardware => None,
+            AdapterKind::Software => Some(format!("No GPU found, drawing in software with {name}. Scrolling may be slow")),
+            AdapterKind::Gl => Some(format!("Drawing with OpenGL ({name}), as no Vulkan, Metal or DirectX adapter was found")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kinds_of_adapters() {
+        let info = |backend, device_type| wgpu::AdapterInfo { name: "llvmpipe".to_string(), vendor: 0, device: 0, device_type, driver: String::new(), driver_info: String::new(), backend };
+        assert_eq!(AdapterKind::of(&info(wgpu::Backend::Vulkan, wgpu::DeviceType::DiscreteGpu)), AdapterKind::Hardware);
+        assert_eq!(AdapterKind::of(&info(wgpu::Backend::Vulkan, wgpu::DeviceType::Cpu)), AdapterKind::Software);
+        assert_eq!(AdapterKind::of(&info(wgpu::Backend::Gl, wgpu::DeviceType::Other)), AdapterKind::Gl);
+        // Hardware first, GL only as a last resort
+        assert!(!ATTEMPTS[0].force_fallback_adapter && ATTEMPTS[1].force_fallback_adapter);
+        assert_eq!(ATTEMPTS[2].backends, wgpu::Backends::GL);
+    }
+}
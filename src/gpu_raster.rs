@@ -0,0 +1,283 @@
+// Experimental glyph rasterization on the GPU. Outlines are flattened into line segments on the
+// CPU, and a compute shader fills in coverage straight into the atlas, so neither fontdue nor the
+// upload of the bitmap is needed. Off unless the config turns it on:
+//
+//   gpu_raster = true
+
+use crate::atlas::{AtlasEntry, GlyphAtlas, GlyphKey, PageFormat};
+use crate::font::Face;
+
+pub const SHADER: &str = include_str!("raster.wgsl");
+
+// Segments each curve is split into when flattening
+const QUAD_STEPS: usize = 8;
+const CUBIC_STEPS: usize = 12;
+// Rows copied from a buffer into a texture must start at multiples of this many bytes
+const ROW_ALIGNMENT: u32 = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    size: [u32; 2],
+    row_words: u32,
+    segment_count: u32,
+}
+
+// A glyph outline as line segments in bitmap pixels, y pointing down
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outline {
+    pub segments: Vec<[f32; 4]>,
+    pub w: u32,
+    pub h: u32,
+    // Same as AtlasEntry::bearing
+    pub bearing: (f32, f32),
+}
+
+struct Flattener {
+    segments: Vec<[f32; 4]>,
+    start: (f32, f32),
+    pen: (f32, f32),
+    // Font units to bitmap pixels
+    scale: f32,
+    origin: (f32, f32),
+}
+
+impl Flattener {
+    fn to_pixels(&self, x: f32, y: f32) -> (f32, f32) {
+        (x * self.scale - self.origin.0, self.origin.1 - y * self.scale)
+    }
+
+    fn line(&mut self, to: (f32, f32)) {
+        if to != self.pen {
+            self.segments.push([self.pen.0, self.pen.1, to.0, to.1]);
+        }
+        self.pen = to;
+    }
+}
+
+impl ttf_parser::OutlineBuilder for Flattener {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.pen = self.to_pixels(x, y);
+        self.start = self.pen;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let to = self.to_pixels(x, y);
+        self.line(to);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p2) = (self.pen, self.to_pixels(x1, y1), self.to_pixels(x, y));
+        for step in 1..=QUAD_STEPS {
+            let t = step as f32 / QUAD_STEPS as f32;
+            let u = 1. - t;
+            self.line((u * u * p0.0 + 2. * u * t * p1.0 + t * t * p2.0, u * u * p0.1 + 2. * u * t * p1.1 + t * t * p2.1));
+        }
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p0, p1, p2, p3) = (self.pen, self.to_pixels(x1, y1), self.to_pixels(x2, y2), self.to_pixels(x, y));
+        for step in 1..=CUBIC_STEPS {
+            let t = step as f32 / CUBIC_STEPS as f32;
+            let u = 1. - t;
+            let (a, b, c, d) = (u * u * u, 3. * u * u * t, 3. * u * t * t, t * t * t);
+            self.line((a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0, a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1));
+        }
+    }
+
+    fn close(&mut self) {
+        let start = self.start;
+        self.line(start);
+    }
+}
+
+// The outline of `glyph` at `size_px` pixels per em. None for glyphs without an outline, like
+// spaces and bitmap-only glyphs
+pub fn outline(face: &Face, glyph: u16, size_px: f32) -> Option<Outline> {
+    let scale = size_px / face.ttf_face.units_per_em() as f32;
+    let bbox = face.ttf_face.glyph_bounding_box(ttf_parser::GlyphId(glyph))?;
+    let x_min = (bbox.x_min as f32 * scale).floor();
+    let y_max = (bbox.y_max as f32 * scale).ceil();
+    let w = (bbox.x_max as f32 * scale).ceil() - x_min;
+    let h = y_max - (bbox.y_min as f32 * scale).floor();
+    let mut flattener = Flattener { segments: Vec::new(), start: (0., 0.), pen: (0., 0.), scale, origin: (x_min, y_max) };
+    face.ttf_face.outline_glyph(ttf_parser::GlyphId(glyph), &mut flattener)?;
+    Some(Outline { segments: flattener.segments, w: w as u32, h: h as u32, bearing: (x_min, -y_max) })
+}
+
+pub struct GpuRasterizer {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl GpuRasterizer {
+    pub fn new(device: &wgpu::Device) -> GpuRasterizer {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("glyph raster shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only }, has_dynamic_offset: false, min_binding_size: None },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("glyph raster bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("glyph raster pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("glyph raster pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+        GpuRasterizer { pipeline, bind_group_layout }
+    }
+
+    // Like GlyphAtlas::get_or_insert, but rasterizes on the GPU. Glyphs without an outline fall
+    // back to get_or_insert
+    pub fn get_or_insert(&self, device: &wgpu::Device, queue: &wgpu::Queue, atlas: &mut GlyphAtlas, face: &Face, key: GlyphKey) -> Option<AtlasEntry> {
+        if let Some(entry) = atlas.cached(&key) {
+            return entry;
+        }
+        // Glyphs with bitmaps, like color emoji, are left to get_or_insert
+        let bitmap = face.ttf_face.glyph_raster_image(ttf_parser::GlyphId(key.glyph), key.size_px().ceil() as u16);
+        let outline = outline(face, key.glyph, key.size_px()).filter(|_| bitmap.is_none());
+        let Some(outline) = outline.filter(|outline| outline.w > 0 && outline.h > 0 && !outline.segments.is_empty()) else {
+            return atlas.get_or_insert(device, queue, face, key);
+        };
+        let entry = atlas
+            .allocate(device, queue, PageFormat::Coverage, outline.w, outline.h)
+            .map(|(page, x, y)| {
+                let layer = atlas.pages[page].layer;
+                self.rasterize(device, queue, atlas, &outline, (x, y, layer));
+                AtlasEntry { page, layer, x, y, w: outline.w, h: outline.h, bearing: outline.bearing, format: PageFormat::Coverage }
+            });
+        atlas.remember(key, entry);
+        entry
+    }
+
+    fn rasterize(&self, device: &wgpu::Device, queue: &wgpu::Queue, atlas: &GlyphAtlas, outline: &Outline, (x, y, layer): (u32, u32, u32)) {
+        use wgpu::util::DeviceExt;
+
+        let row_bytes = outline.w.div_ceil(ROW_ALIGNMENT) * ROW_ALIGNMENT;
+        let params = Params { size: [outline.w, outline.h], row_words: row_bytes / 4, segment_count: outline.segments.len() as u32 };
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("glyph raster params"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let segments = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("glyph outline segments"),
+            contents: bytemuck::cast_slice(&outline.segments),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let coverage = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("glyph coverage"),
+            size: row_bytes as u64 * outline.h as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("glyph raster bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: segments.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: coverage.as_entire_binding() },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("glyph raster") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("glyph raster pass") });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(params.row_words.div_ceil(8), outline.h.div_ceil(8), 1);
+        }
+        let pages = atlas.array(PageFormat::Coverage).expect("allocating made the array");
+        encoder.copy_buffer_to_texture(
+            wgpu::ImageCopyBuffer {
+                buffer: &coverage,
+                layout: wgpu::ImageDataLayout { offset: 0, bytes_per_row: Some(row_bytes), rows_per_image: Some(outline.h) },
+            },
+            wgpu::ImageCopyTexture {
+                texture: &pages.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: layer },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::Extent3d { width: outline.w, height: outline.h, depth_or_array_layers: 1 },
+        );
+        queue.submit([encoder.finish()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::FontStack;
+    use crate::test_helpers::validate_shader;
+
+    // What the shader computes, to compare against fontdue
+    fn rasterize_cpu(outline: &Outline) -> Vec<u8> {
+        let winding = |px: f32, py: f32| {
+            outline.segments.iter().fold(0, |winding, &[ax, ay, bx, by]| {
+                if (ay <= py) != (by <= py) && ax + (py - ay) * (bx - ax) / (by - ay) > px {
+                    winding + if by > ay { 1 } else { -1 }
+                } else {
+                    winding
+                }
+            })
+        };
+        let samples = 4;
+        let mut out = Vec::new();
+        for y in 0..outline.h {
+            for x in 0..outline.w {
+                let inside = (0..samples * samples)
+                    .filter(|i| winding(x as f32 + ((i % samples) as f32 + 0.5) / samples as f32, y as f32 + ((i / samples) as f32 + 0.5) / samples as f32) != 0)
+                    .count();
+                out.push((inside as f32 / (samples * samples) as f32 * 255.).round() as u8);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn shader_is_valid() {
+        validate_shader(SHADER);
+    }
+
+    #[test]
+    fn coverage_matches_fontdue() {
+        let fontstack = FontStack::new(std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/firacode-regular.ttf"))).unwrap();
+        let face = &fontstack.faces[0];
+        for c in ['a', 'g', '@', 'W'] {
+            let glyph = face.ttf_face.glyph_index(c).unwrap().0;
+            let outline = outline(face, glyph, 32.).unwrap();
+            let (metrics, reference) = face.fontdue_font().unwrap().rasterize_indexed(glyph, 32.);
+            assert!(outline.w.abs_diff(metrics.width as u32) <= 1 && outline.h.abs_diff(metrics.height as u32) <= 1, "size of {c}");
+            assert_eq!(outline.bearing.0, metrics.xmin as f32, "bearing of {c}");
+
+            // Same amount of ink, give or take the difference between sampling and exact area
+            let ink = |coverage: &[u8]| coverage.iter().map(|&c| c as f32).sum::<f32>();
+            let ours = ink(&rasterize_cpu(&outline));
+            assert!((ours - ink(&reference)).abs() / ink(&reference) < 0.03, "coverage of {c}");
+        }
+    }
+}
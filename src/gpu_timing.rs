@@ -0,0 +1,182 @@
+// How long each render pass takes on the GPU, from timestamp queries written around the passes.
+// Tells apart a slow frame spent shaping on the CPU from one spent filling pixels. Only where the
+// adapter supports TIMESTAMP_QUERY; elsewhere there's no timer and frames are timed on the CPU only.
+// Results are read back a few frames late, without ever waiting on the GPU
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    Clear,
+    Text,
+    // Shapes: selections, cursors, underlines
+    Decorations,
+    Ui,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 4] = [Pass::Clear, Pass::Text, Pass::Decorations, Pass::Ui];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Clear => "clear",
+            Pass::Text => "text",
+            Pass::Decorations => "decorations",
+            Pass::Ui => "ui",
+        }
+    }
+
+    fn index(self) -> usize {
+        Pass::ALL.iter().position(|pass| *pass == self).unwrap()
+    }
+}
+
+// Durations of the passes of one frame. None for passes that didn't run
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PassTimes(pub [Option<Duration>; Pass::ALL.len()]);
+
+impl PassTimes {
+    pub fn get(&self, pass: Pass) -> Option<Duration> {
+        self.0[pass.index()]
+    }
+
+    pub fn total(&self) -> Duration {
+        self.0.iter().flatten().sum()
+    }
+
+    // From the start and end ticks of each pass, `period` nanoseconds apart
+    pub fn from_ticks(ticks: &[[u64; 2]], written: &[bool], period: f32) -> PassTimes {
+        let mut times = PassTimes::default();
+        for (idx, ([start, end], written)) in ticks.iter().zip(written).enumerate() {
+            if *written {
+                times.0[idx] = Some(Duration::from_nanos((end.saturating_sub(*start) as f64 * period as f64) as u64));
+            }
+        }
+        times
+    }
+}
+
+impl fmt::Display for PassTimes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "GPU {:.2}ms", self.total().as_secs_f64() * 1000.)?;
+        for pass in Pass::ALL {
+            if let Some(took) = self.get(pass) {
+                write!(f, ", {} {:.2}ms", pass.name(), took.as_secs_f64() * 1000.)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Each pass resolves to its own slot, as resolve destinations have to be aligned
+const SLOT: u64 = wgpu::QUERY_RESOLVE_BUFFER_ALIGNMENT;
+
+enum State {
+    Idle,
+    // Copied to the readback buffer by commands not submitted yet
+    Copied([bool; Pass::ALL.len()]),
+    Mapping([bool; Pass::ALL.len()], Arc<AtomicBool>),
+}
+
+pub struct GpuTimer {
+    queries: wgpu::QuerySet,
+    resolve: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    // Nanoseconds per tick
+    period: f32,
+    written: [bool; Pass::ALL.len()],
+    state: State,
+}
+
+impl GpuTimer {
+    // Features to request the device with, so the timer can be made
+    pub fn features(adapter: &wgpu::Adapter) -> wgpu::Features {
+        adapter.features() & wgpu::Features::TIMESTAMP_QUERY
+    }
+
+    // None if the device was made without TIMESTAMP_QUERY
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<GpuTimer> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let size = SLOT * Pass::ALL.len() as u64;
+        let buffer = |label, usage| device.create_buffer(&wgpu::BufferDescriptor { label: Some(label), size, usage, mapped_at_creation: false });
+        Some(GpuTimer {
+            queries: device.create_query_set(&wgpu::QuerySetDescriptor { label: Some("pass timestamps"), ty: wgpu::QueryType::Timestamp, count: 2 * Pass::ALL.len() as u32 }),
+            resolve: buffer("pass timestamps resolve", wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC),
+            readback: buffer("pass timestamps readback", wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ),
+            period: queue.get_timestamp_period(),
+            written: [false; Pass::ALL.len()],
+            state: State::Idle,
+        })
+    }
+
+    // Called before beginning the pass's render pass on `encoder`
+    pub fn begin(&mut self, encoder: &mut wgpu::CommandEncoder, pass: Pass) {
+        encoder.write_timestamp(&self.queries, 2 * pass.index() as u32);
+    }
+
+    // Called after the render pass is dropped
+    pub fn end(&mut self, encoder: &mut wgpu::CommandEncoder, pass: Pass) {
+        encoder.write_timestamp(&self.queries, 2 * pass.index() as u32 + 1);
+        self.written[pass.index()] = true;
+    }
+
+    // Called at the end of the frame's commands. Copies the timestamps out, unless the last ones
+    // are still being read back, in which case this frame goes unmeasured
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let written = std::mem::take(&mut self.written);
+        if !matches!(self.state, State::Idle) {
+            return;
+        }
+        for (idx, _) in written.iter().enumerate().filter(|(_, written)| **written) {
+            encoder.resolve_query_set(&self.queries, 2 * idx as u32..2 * idx as u32 + 2, &self.resolve, SLOT * idx as u64);
+        }
+        encoder.copy_buffer_to_buffer(&self.resolve, 0, &self.readback, 0, self.resolve.size());
+        self.state = State::Copied(written);
+    }
+
+    // Called after submitting the commands resolve was called with
+    pub fn submitted(&mut self) {
+        if let State::Copied(written) = self.state {
+            let mapped = Arc::new(AtomicBool::new(false));
+            let done = mapped.clone();
+            self.readback.slice(..).map_async(wgpu::MapMode::Read, move |result| done.store(result.is_ok(), Ordering::Release));
+            self.state = State::Mapping(written, mapped);
+        }
+    }
+
+    // The times of the last frame that has finished on the GPU, once per frame measured. The
+    // device has to be polled (presenting does it) for the readback to finish
+    pub fn poll(&mut self) -> Option<PassTimes> {
+        let State::Mapping(written, mapped) = &self.state else { return None };
+        if !mapped.load(Ordering::Acquire) {
+            return None;
+        }
+        let ticks: Vec<[u64; 2]> = {
+            let data = self.readback.slice(..).get_mapped_range();
+            data.chunks(SLOT as usize).map(|slot| [0, 1].map(|at| u64::from_le_bytes(slot[8 * at..8 * at + 8].try_into().unwrap()))).collect()
+        };
+        self.readback.unmap();
+        let times = PassTimes::from_ticks(&ticks, written, self.period);
+        self.state = State::Idle;
+        Some(times)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pass_durations() {
+        let ticks = [[1000, 1500], [1500, 40_000], [0, 0], [40_000, 42_000]];
+        let times = PassTimes::from_ticks(&ticks, &[true, true, false, true], 2.);
+        assert_eq!(times.get(Pass::Text), Some(Duration::from_nanos(77_000)));
+        assert_eq!(times.get(Pass::Decorations), None);
+        assert_eq!(times.to_string(), "GPU 0.08ms, clear 0.00ms, text 0.08ms, ui 0.00ms");
+    }
+}
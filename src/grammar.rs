@@ -0,0 +1,417 @@
+// The normal mode grammar, selections first like kakoune: motions move or extend every selection,
+// and actions work on whatever is selected. Keys are turned into Steps, which are plain data, so
+// macros and '.' can replay them without going through the keys again
+
+use std::ops::Range;
+
+use crate::layout::Layout;
+use crate::marks::shift_through_edit;
+use crate::normal::Command;
+use crate::selection::{byte_at_column, line_starts, visual_column, Goal, Selection};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    NextWord,
+    PrevWord,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    LastLine,
+    // Up and down by rows as shown, which differ from lines when long lines wrap
+    RowUp,
+    RowDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Delete,
+    Yank,
+    // Delete, then insert in place of what was deleted
+    Change,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    // With `extend`, the selections grow to the new position instead of moving there
+    Move { motion: Motion, count: usize, extend: bool },
+    Act(Action),
+    // Swap the anchor and head of every selection
+    Flip,
+}
+
+impl Step {
+    // Holding shift extends instead of moving. None for keys that aren't part of the grammar
+    pub fn from_command(command: &Command) -> Option<Step> {
+        let extend = command.key.is_uppercase();
+        if let Some(prefix) = command.prefix {
+            let motion = match (prefix, command.key.to_ascii_lowercase()) {
+                ('g', 'k') => Motion::RowUp,
+                ('g', 'j') => Motion::RowDown,
+                _ => return None,
+            };
+            return Some(Step::Move { motion, count: command.count, extend });
+        }
+        let motion = match command.key.to_ascii_lowercase() {
+            'h' => Motion::Left,
+            'l' => Motion::Right,
+            'k' => Motion::Up,
+            'j' => Motion::Down,
+            'w' => Motion::NextWord,
+            'b' => Motion::PrevWord,
+            'e' => Motion::WordEnd,
+            '0' => Motion::LineStart,
+            '$' => Motion::LineEnd,
+            // G goes to the last line, as there is no lowercase version to shift
+            'g' if extend => return Some(Step::Move { motion: Motion::LastLine, count: 1, extend: false }),
+            'd' if !extend => return Some(Step::Act(Action::Delete)),
+            'y' if !extend => return Some(Step::Act(Action::Yank)),
+            'c' if !extend => return Some(Step::Act(Action::Change)),
+            // kakoune has this on alt-;, but normal mode keys can't have modifiers
+            ';' => return Some(Step::Flip),
+            _ => return None,
+        };
+        Some(Step::Move { motion, count: command.count, extend })
+    }
+
+    // Whether the step changes the text, so it should be what '.' repeats
+    pub fn is_change(&self) -> bool {
+        matches!(self, Step::Act(Action::Delete | Action::Change))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Space,
+    Word,
+    Punctuation,
+}
+
+fn class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Space
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+fn next_char(text: &str, pos: usize) -> Option<(usize, char)> {
+    text[pos..].chars().next().map(|c| (pos + c.len_utf8(), c))
+}
+
+fn prev_char(text: &str, pos: usize) -> Option<(usize, char)> {
+    text[..pos].chars().next_back().map(|c| (pos - c.len_utf8(), c))
+}
+
+fn next_word(text: &str, mut pos: usize) -> usize {
+    let Some((_, first)) = next_char(text, pos) else { return pos };
+    while let Some((next, _)) = next_char(text, pos).filter(|&(_, c)| class(c) == class(first)) {
+        pos = next;
+    }
+    while let Some((next, _)) = next_char(text, pos).filter(|&(_, c)| class(c) == CharClass::Space) {
+        pos = next;
+    }
+    pos
+}
+
+fn prev_word(text: &str, mut pos: usize) -> usize {
+    while let Some((prev, _)) = prev_char(text, pos).filter(|&(_, c)| class(c) == CharClass::Space) {
+        pos = prev;
+    }
+    let Some((_, last)) = prev_char(text, pos) else { return pos };
+    while let Some((prev, _)) = prev_char(text, pos).filter(|&(_, c)| class(c) == class(last)) {
+        pos = prev;
+    }
+    pos
+}
+
+// End of the word at or after `pos`, exclusive
+fn word_end(text: &str, mut pos: usize) -> usize {
+    while let Some((next, _)) = next_char(text, pos).filter(|&(_, c)| class(c) == CharClass::Space) {
+        pos = next;
+    }
+    let Some((_, first)) = next_char(text, pos) else { return pos };
+    while let Some((next, _)) = next_char(text, pos).filter(|&(_, c)| class(c) == class(first)) {
+        pos = next;
+    }
+    pos
+}
+
+// Where `motion` takes a cursor at byte `pos`. Without a layout, rows are lines
+pub fn target(text: &str, pos: usize, motion: Motion, count: usize) -> usize {
+    target_from(text, pos, motion, count, None).0
+}
+
+// Like target, for a head that has been moving up and down towards `goal`. Returns the goal to
+// keep, None unless the motion was vertical
+pub fn target_from(text: &str, pos: usize, motion: Motion, count: usize, goal: Option<Goal>) -> (usize, Option<Goal>) {
+    let starts = line_starts(text);
+    let line = starts.partition_point(|&start| start <= pos) - 1;
+    let line_end = |line: usize| starts.get(line + 1).map_or(text.len(), |&next| next - 1);
+    // Moving up and down keeps the column on screen, clamped to the length of the line
+    let column = match goal {
+        Some(Goal::Column(column)) => column,
+        _ => visual_column(&text[starts[line]..pos]),
+    };
+    let at_column = |line: usize| (starts[line] + byte_at_column(&text[starts[line]..line_end(line)], column), Some(Goal::Column(column)));
+    let repeat = |f: &dyn Fn(usize) -> usize| (0..count).fold(pos, |pos, _| f(pos));
+    let to = match motion {
+        Motion::Up | Motion::RowUp => return at_column(line.saturating_sub(count)),
+        Motion::Down | Motion::RowDown => return at_column((line + count).min(starts.len() - 1)),
+        Motion::Left => repeat(&|pos| prev_char(text, pos).filter(|&(_, c)| c != '\n').map_or(pos, |(prev, _)| prev)),
+        Motion::Right => repeat(&|pos| next_char(text, pos).filter(|&(_, c)| c != '\n').map_or(pos, |(next, _)| next)),
+        Motion::NextWord => repeat(&|pos| next_word(text, pos)),
+        Motion::PrevWord => repeat(&|pos| prev_word(text, pos)),
+        Motion::WordEnd => repeat(&|pos| word_end(text, pos)),
+        Motion::LineStart => starts[line],
+        Motion::LineEnd => line_end(line),
+        Motion::LastLine => starts[starts.len() - 1],
+    };
+    (to, None)
+}
+
+// What running a step left for the caller to do
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    // Text of each selection, for the yank register, when the action was d, y or c
+    pub yanked: Vec<String>,
+    // Whether to enter insert mode at the selections
+    pub insert: bool,
+}
+
+// Runs `step` on every selection. Motions move the head, and extending leaves the anchor behind
+pub fn apply(step: Step, text: &mut String, selections: &mut Vec<Selection>) -> Outcome {
+    // Selections made by anything else, like selecting regex matches, may overlap or be past the end
+    normalize(text, selections);
+    match step {
+        Step::Move { motion, count, extend } => {
+            for selection in selections.iter_mut() {
+                let (to, goal) = target_from(text, selection.head, motion, count, selection.goal);
+                let anchor = if extend { selection.anchor } else { to };
+                *selection = Selection { anchor, head: to, goal };
+            }
+            normalize(text, selections);
+            Outcome::default()
+        }
+        Step::Flip => {
+            selections.iter_mut().for_each(Selection::flip);
+            Outcome::default()
+        }
+        Step::Act(action) => {
+            let yanked = selections.iter().map(|selection| text[selection.range()].to_string()).collect();
+            if action == Action::Yank {
+                return Outcome { yanked, insert: false };
+            }
+            let deleted: Vec<Range<usize>> = selections.iter().map(Selection::range).collect();
+            // Back to front, so earlier selections aren't moved by the deletions after them
+            for range in deleted.iter().rev() {
+                text.replace_range(range.clone(), "");
+            }
+            for (selection, range) in selections.iter_mut().zip(&deleted) {
+                let at = deleted.iter().rev().fold(range.start, |pos, removed| shift_through_edit(pos, removed, 0));
+                *selection = Selection::cursor(at);
+            }
+            normalize(text, selections);
+            Outcome { yanked, insert: action == Action::Change }
+        }
+    }
+}
+
+// Like apply, moving by rows of `layout` for gj and gk. The layout has to be of the current text
+pub fn apply_shown(step: Step, text: &mut String, selections: &mut Vec<Selection>, layout: &Layout) -> Outcome {
+    let rows = match step {
+        Step::Move { motion: Motion::RowUp, count, .. } => -(count as isize),
+        Step::Move { motion: Motion::RowDown, count, .. } => count as isize,
+        _ => return apply(step, text, selections),
+    };
+    let Step::Move { extend, .. } = step else { unreachable!() };
+    normalize(text, selections);
+    for selection in selections.iter_mut() {
+        let x = match selection.goal {
+            Some(Goal::X(x)) => x,
+            _ => layout.caret_x_of(selection.head),
+        };
+        let to = layout.move_rows_to(selection.head, rows, x);
+        let anchor = if extend { selection.anchor } else { to };
+        *selection = Selection { anchor, head: to, goal: Some(Goal::X(x)) };
+    }
+    normalize(text, selections);
+    Outcome::default()
+}
+
+// Sorts the selections and joins the ones that overlap, so edits never touch the same text twice.
+// A joined selection keeps the orientation of the first one
+pub fn merge(selections: &mut Vec<Selection>) {
+    selections.sort_by_key(|selection| (selection.range().start, selection.range().end));
+    selections.dedup_by(|next, prev| {
+        let (next, prev_range) = (next.range(), prev.range());
+        let overlaps = next.start < prev_range.end || next == prev_range;
+        if overlaps {
+            let end = prev_range.end.max(next.end);
+            *prev = if prev.is_backward() { Selection { anchor: end, head: prev_range.start, goal: prev.goal } } else { Selection { anchor: prev_range.start, head: end, goal: prev.goal } };
+        }
+        overlaps
+    });
+}
+
+// The form every command leaves selections in: inside the text and on character boundaries, sorted,
+// with overlapping ones merged, and at least one. Normalizing twice changes nothing. A selection
+// ending inside a character grows to cover all of it, so no selected text is lost
+pub fn normalize(text: &str, selections: &mut Vec<Selection>) {
+    let floor = |at: usize| (0..=at.min(text.len())).rev().find(|&at| text.is_char_boundary(at)).unwrap_or(0);
+    let ceil = |at: usize| (at.min(text.len())..=text.len()).find(|&at| text.is_char_boundary(at)).unwrap_or(text.len());
+    for selection in selections.iter_mut() {
+        let range = selection.range();
+        let start = floor(range.start);
+        let end = if range.is_empty() { start } else { ceil(range.end) };
+        (selection.anchor, selection.head) = if selection.is_backward() { (end, start) } else { (start, end) };
+    }
+    if selections.is_empty() {
+        selections.push(Selection::cursor(0));
+    }
+    merge(selections);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normal::{Input, NormalMode};
+    use crate::test_helpers::Rng;
+
+    fn ranges(selections: &[Selection]) -> Vec<Range<usize>> {
+        selections.iter().map(Selection::range).collect()
+    }
+
+    fn steps(mode: &mut NormalMode, keys: &str) -> Vec<Step> {
+        keys.chars()
+            .filter_map(|key| match mode.key(key) {
+                Input::Run(command) => Step::from_command(&command),
+                Input::Pending => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn motions_then_actions() {
+        let mut text = "let foo = bar;\nfoo(1, 2);\n".to_string();
+        let mut selections = vec![Selection::cursor(0), Selection::cursor(15)];
+        let mut mode = NormalMode::default();
+
+        for step in steps(&mut mode, "wE") {
+            apply(step, &mut text, &mut selections);
+        }
+        assert_eq!(ranges(&selections), vec![4..7, 18..19]);
+
+        let delete = steps(&mut mode, "d")[0];
+        let outcome = apply(delete, &mut text, &mut selections);
+        assert_eq!(outcome, Outcome { yanked: vec!["foo".to_string(), "(".to_string()], insert: false });
+        assert_eq!(text, "let  = bar;\nfoo1, 2);\n");
+        assert_eq!(selections, vec![Selection::cursor(4), Selection::cursor(15)]);
+
+        // Steps are data, so a recorded one replays the same way
+        assert!(delete.is_change());
+        apply(Step::Move { motion: Motion::WordEnd, count: 1, extend: true }, &mut text, &mut selections);
+        assert_eq!(apply(delete, &mut text, &mut selections).yanked, vec![" =", "1"]);
+        assert_eq!(text, "let  bar;\nfoo, 2);\n");
+    }
+
+    #[test]
+    fn vertical_motions_keep_column() {
+        let text = "abcdef\nab\nabcdef\n";
+        assert_eq!(target(text, 4, Motion::Down, 1), 9);
+        assert_eq!(target(text, 4, Motion::Down, 2), 14);
+        assert_eq!(target(text, 14, Motion::Up, 5), 4);
+        assert_eq!(target(text, 14, Motion::LastLine, 1), text.len());
+        assert_eq!(target(text, 2, Motion::Left, 5), 0);
+        assert_eq!(target(text, 2, Motion::LineEnd, 1), 6);
+
+        // Through a short line and back to the column, counting tabs as the columns they take up
+        let mut text = "abcdef\nab\n\tcd\nabcdef".to_string();
+        let mut selections = vec![Selection::cursor(5)];
+        let down = Step::Move { motion: Motion::Down, count: 1, extend: false };
+        let heads = (0..3).map(|_| {
+            apply(down, &mut text, &mut selections);
+            selections[0].head
+        }).collect::<Vec<_>>();
+        assert_eq!(heads, vec![9, 12, 19]);
+        apply(Step::Move { motion: Motion::Left, count: 1, extend: false }, &mut text, &mut selections);
+        assert_eq!(selections[0].goal, None);
+    }
+
+    #[test]
+    fn moving_by_rows() {
+        let fontstack = crate::font::FontStack::new(&std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("resources/firacode-regular.ttf")).unwrap();
+        let mut text = "aaaa bbbb cccc\ndd".to_string();
+        let mut mode = NormalMode::default();
+        let [down, up] = steps(&mut mode, "gjgK")[..] else { panic!() };
+        assert_eq!(up, Step::Move { motion: Motion::RowUp, count: 1, extend: true });
+        let advance = crate::layout::layout(&fontstack, "a", &Default::default()).lines[0].width;
+        let layout = crate::layout::layout(&fontstack, &text, &crate::layout::LayoutSettings { wrap_width: Some(advance * 7.), ..Default::default() });
+        let mut selections = vec![Selection::cursor(0)];
+        apply_shown(down, &mut text, &mut selections, &layout);
+        assert_eq!(ranges(&selections), vec![5..5]);
+        // Back up to where gj started, though the continuation row starts after the wrap marker
+        apply_shown(up, &mut text, &mut selections, &layout);
+        assert_eq!((selections[0].anchor, selections[0].head), (5, 0));
+        // Starting on the continuation row, straight up is further right
+        let mut selections = vec![Selection::cursor(5)];
+        apply_shown(up, &mut text, &mut selections, &layout);
+        assert_eq!((selections[0].anchor, selections[0].head), (5, 2));
+        // Without wrapping they're j and k
+        apply(down, &mut text, &mut selections);
+        assert_eq!(selections, vec![Selection { anchor: 17, head: 17, goal: Some(Goal::Column(2)) }]);
+    }
+
+    #[test]
+    fn extending_back_past_the_anchor() {
+        let mut text = "one two three".to_string();
+        let mut selections = vec![Selection::cursor(4)];
+        apply(Step::Move { motion: Motion::WordEnd, count: 1, extend: true }, &mut text, &mut selections);
+        assert_eq!(selections, vec![Selection { anchor: 4, head: 7, goal: None }]);
+        // Going back over the anchor selects what's before it, instead of losing where it started
+        apply(Step::Move { motion: Motion::PrevWord, count: 2, extend: true }, &mut text, &mut selections);
+        assert_eq!(selections, vec![Selection { anchor: 4, head: 0, goal: None }]);
+        apply(Step::Flip, &mut text, &mut selections);
+        apply(Step::Move { motion: Motion::NextWord, count: 1, extend: true }, &mut text, &mut selections);
+        assert_eq!(selections, vec![Selection { anchor: 0, head: 8, goal: None }]);
+    }
+
+    #[test]
+    fn normalizing_is_canonical() {
+        // Random selections, some past the end or inside a character, from a fixed seed
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let text = "fn ö() {}\nlet x = ö;\n";
+        for _ in 0..500 {
+            let count = rng.below(6);
+            let mut selections: Vec<Selection> = (0..count).map(|_| Selection { anchor: rng.below(text.len() + 4), head: rng.below(text.len() + 4), goal: None }).collect();
+            let covered: Vec<usize> = (0..text.len()).filter(|&at| selections.iter().any(|selection| selection.range().contains(&at))).collect();
+            normalize(text, &mut selections);
+
+            assert!(!selections.is_empty());
+            assert!(selections.iter().all(|selection| text.is_char_boundary(selection.anchor) && text.is_char_boundary(selection.head)));
+            assert!(selections.windows(2).all(|pair| pair[0].range().end <= pair[1].range().start && pair[0].range() != pair[1].range()));
+            // Merging never drops text that was selected
+            assert!(covered.iter().all(|at| selections.iter().any(|selection| selection.range().start <= *at && *at < selection.range().end)));
+            let again = selections.clone();
+            normalize(text, &mut selections);
+            assert_eq!(selections, again);
+        }
+    }
+
+    #[test]
+    fn overlapping_selections_merge() {
+        let mut selections = vec![
+            Selection { anchor: 5, head: 9, goal: None },
+            Selection { anchor: 2, head: 0, goal: None },
+            Selection { anchor: 1, head: 4, goal: None },
+            Selection::cursor(9),
+        ];
+        merge(&mut selections);
+        assert_eq!(selections, vec![Selection { anchor: 4, head: 0, goal: None }, Selection { anchor: 5, head: 9, goal: None }, Selection::cursor(9)]);
+    }
+}
@@ -0,0 +1,419 @@
+// Where highlights come from, chosen per filetype. Each highlighter is one way of finding them:
+// patterns from the config, the syntax tree of the buffer or else the bracket tree from the
+// grammar, the language server's semantic tokens, or anything else added with its own name. A
+// filetype lists the ones to use, and where they overlap the one listed first wins:
+//
+//   [highlighting]
+//   default = ["semantic", "syntax"]
+//
+//   [highlighting.filetypes]
+//   md = ["regex"]
+//
+//   [highlighting.regex.md]
+//   "markup.heading" = "(?m)^#+ .*$"
+//
+// Faster ones listed alone keep big files responsive, more of them get more detail. Highlighting
+// runs on another thread, and what it finds for text that has been edited since is thrown away
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+
+use regex::Regex;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::bracket_tree::{BracketTree, Languages};
+use crate::brackets::BracketDepths;
+use crate::semantic::{self, Highlight, Legend, SemanticTokens};
+use crate::syntax::SyntaxTree;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Unknown highlighter {0}")]
+    UnknownHighlighter(String),
+    #[error("Bad pattern for {0} in highlighting.regex: {1}")]
+    BadPattern(String, regex::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HighlightConfig {
+    // Names of highlighters, first wins where they overlap
+    pub default: Vec<String>,
+    // The same, by file extension
+    pub filetypes: HashMap<String, Vec<String>>,
+    // Patterns of the regex highlighter by file extension, then face
+    pub regex: HashMap<String, HashMap<String, String>>,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        HighlightConfig { default: vec!["semantic".to_string(), "syntax".to_string()], filetypes: HashMap::new(), regex: HashMap::new() }
+    }
+}
+
+impl HighlightConfig {
+    pub fn for_path(&self, path: &Path) -> &[String] {
+        let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+        self.filetypes.get(extension).unwrap_or(&self.default)
+    }
+}
+
+// Sorted and not overlapping, with the name of the theme face for each
+pub type Spans = Vec<(Range<usize>, String)>;
+
+const DEFAULT: &[(&str, [f32; 4])] = &[
+    ("keyword", [0.8, 0.55, 0.9, 1.]),
+    ("string", [0.6, 0.8, 0.5, 1.]),
+    ("comment", [0.5, 0.55, 0.6, 1.]),
+    ("function", [0.5, 0.7, 0.95, 1.]),
+    ("method", [0.5, 0.7, 0.95, 1.]),
+    ("macro", [0.45, 0.8, 0.8, 1.]),
+    ("type", [0.9, 0.75, 0.45, 1.]),
+    ("struct", [0.9, 0.75, 0.45, 1.]),
+    ("enum", [0.9, 0.75, 0.45, 1.]),
+    ("number", [0.9, 0.6, 0.4, 1.]),
+    ("enumMember", [0.9, 0.6, 0.4, 1.]),
+    ("punctuation", [0.6, 0.6, 0.65, 1.]),
+    ("operator", [0.75, 0.75, 0.8, 1.]),
+    ("markup.heading", [0.95, 0.7, 0.4, 1.]),
+];
+
+const GRUVBOX: &[(&str, [f32; 4])] = &[
+    ("keyword", [0.98, 0.29, 0.2, 1.]),
+    ("string", [0.72, 0.73, 0.15, 1.]),
+    ("comment", [0.57, 0.51, 0.45, 1.]),
+    ("function", [0.56, 0.75, 0.49, 1.]),
+    ("method", [0.56, 0.75, 0.49, 1.]),
+    ("macro", [1., 0.5, 0.1, 1.]),
+    ("type", [0.98, 0.74, 0.18, 1.]),
+    ("struct", [0.98, 0.74, 0.18, 1.]),
+    ("enum", [0.98, 0.74, 0.18, 1.]),
+    ("number", [0.83, 0.53, 0.61, 1.]),
+    ("enumMember", [0.83, 0.53, 0.61, 1.]),
+    ("punctuation", [0.66, 0.6, 0.52, 1.]),
+    ("operator", [0.92, 0.86, 0.7, 1.]),
+    ("markup.heading", [1., 0.5, 0.1, 1.]),
+];
+
+const NORD: &[(&str, [f32; 4])] = &[
+    ("keyword", [0.51, 0.63, 0.76, 1.]),
+    ("string", [0.64, 0.75, 0.55, 1.]),
+    ("comment", [0.47, 0.53, 0.63, 1.]),
+    ("function", [0.53, 0.75, 0.82, 1.]),
+    ("method", [0.53, 0.75, 0.82, 1.]),
+    ("macro", [0.37, 0.51, 0.67, 1.]),
+    ("type", [0.56, 0.74, 0.73, 1.]),
+    ("struct", [0.56, 0.74, 0.73, 1.]),
+    ("enum", [0.56, 0.74, 0.73, 1.]),
+    ("number", [0.71, 0.56, 0.68, 1.]),
+    ("enumMember", [0.71, 0.56, 0.68, 1.]),
+    ("punctuation", [0.7, 0.73, 0.78, 1.]),
+    ("operator", [0.51, 0.63, 0.76, 1.]),
+    ("markup.heading", [0.53, 0.75, 0.82, 1.]),
+];
+
+// Syntax colors by face. Faces without their own take the color of the face they extend, so
+// "keyword.control" is drawn like "keyword", and ones that extend nothing here are drawn like
+// plain text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub name: &'static str,
+    faces: &'static [(&'static str, [f32; 4])],
+}
+
+// The built in themes, the first one being the default
+pub const THEMES: &[Theme] = &[Theme { name: "default", faces: DEFAULT }, Theme { name: "gruvbox", faces: GRUVBOX }, Theme { name: "nord", faces: NORD }];
+
+impl Default for Theme {
+    fn default() -> Self {
+        THEMES[0]
+    }
+}
+
+impl Theme {
+    pub fn named(name: &str) -> Option<Theme> {
+        THEMES.iter().find(|theme| theme.name == name).copied()
+    }
+
+    pub fn face_color(&self, face: &str) -> Option<[f32; 4]> {
+        semantic::fallbacks(face).find_map(|face| self.faces.iter().find(|(name, _)| *name == face).map(|(_, color)| *color))
+    }
+
+    // Every face of the theme with its color
+    pub fn faces(&self) -> Vec<(String, [f32; 4])> {
+        self.faces.iter().map(|(face, color)| (face.to_string(), *color)).collect()
+    }
+}
+
+pub fn theme_names() -> Vec<String> {
+    THEMES.iter().map(|theme| theme.name.to_string()).collect()
+}
+
+// Brackets by how deep they're nested, cycling through these, and ones that close nothing
+const BRACKETS: &[[f32; 4]] = &[[0.95, 0.8, 0.35, 1.], [0.8, 0.5, 0.85, 1.], [0.4, 0.7, 0.95, 1.]];
+const UNMATCHED_BRACKET: [f32; 4] = [0.9, 0.3, 0.3, 1.];
+
+pub fn bracket_color(depth: Option<usize>) -> [f32; 4] {
+    depth.map_or(UNMATCHED_BRACKET, |depth| BRACKETS[depth % BRACKETS.len()])
+}
+
+// The colors of the bytes `bytes` of a text with lines starting at `starts`: its highlights in the
+// colors of `theme`, with the brackets outside strings and comments colored by their depth over
+// them. Sorted and not overlapping, like the highlights
+pub fn colors(theme: &Theme, spans: &[(Range<usize>, String)], brackets: &BracketDepths, starts: &[usize], bytes: Range<usize>) -> Vec<(Range<usize>, [f32; 4])> {
+    let first = spans.partition_point(|(range, _)| range.end <= bytes.start);
+    let shown = spans[first..].iter().take_while(|(range, _)| range.start < bytes.end);
+    let colored = shown.filter_map(|(range, face)| Some((range.start.max(bytes.start)..range.end.min(bytes.end), theme.face_color(face)?)));
+    let quoted = |at: usize| spans.get(spans.partition_point(|(range, _)| range.end <= at)).is_some_and(|(range, face)| range.contains(&at) && (face.starts_with("string") || face.starts_with("comment")));
+    let lines = starts.partition_point(|&start| start <= bytes.start).saturating_sub(1)..starts.partition_point(|&start| start < bytes.end);
+    let mut brackets = lines.flat_map(|line| brackets.line(line).iter().map(move |bracket| (starts[line] + bracket.at, bracket_color(bracket.depth)))).filter(|(at, _)| bytes.contains(at) && !quoted(*at)).peekable();
+    // Brackets are one byte each, and split the highlights they're in
+    let mut merged = Vec::new();
+    for (range, color) in colored {
+        let mut start = range.start;
+        while let Some((at, bracket)) = brackets.next_if(|(at, _)| *at < range.end) {
+            if start < at {
+                merged.push((start..at, color));
+            }
+            merged.push((at..at + 1, bracket));
+            start = start.max(at + 1);
+        }
+        if start < range.end {
+            merged.push((start..range.end, color));
+        }
+    }
+    merged.extend(brackets.map(|(at, color)| (at..at + 1, color)));
+    merged
+}
+pub type Named = (String, Arc<dyn Highlighter>);
+
+// Called on the highlighting thread, so it gets the text and the buffer's syntax tree, if it has
+// one, instead of the buffer
+pub trait Highlighter: Send + Sync {
+    // None when it has nothing for this file, like the bracket tree without a grammar
+    fn highlight(&self, path: &Path, text: &str, syntax: Option<&SyntaxTree>) -> Option<Vec<Highlight>>;
+}
+
+pub struct SyntaxHighlighter {
+    pub languages: Languages,
+}
+
+impl Highlighter for SyntaxHighlighter {
+    fn highlight(&self, path: &Path, text: &str, syntax: Option<&SyntaxTree>) -> Option<Vec<Highlight>> {
+        let highlights = match syntax {
+            Some(syntax) => syntax.highlights(text, 0..text.len()).into_iter().map(|(range, face)| (range, face.to_string())).collect(),
+            None => text_highlights(&self.languages, path, text)?,
+        };
+        Some(highlights.into_iter().map(|(range, face)| Highlight { range, face, priority: semantic::SYNTAX }).collect())
+    }
+}
+
+// The highlights of text without a syntax tree kept for it, like a preview, from a tree made for it
+// now: a syntax tree if its grammar has a parser, or else the bracket tree
+pub fn text_highlights(languages: &Languages, path: &Path, text: &str) -> Option<Vec<(Range<usize>, String)>> {
+    let grammar = languages.for_path(path)?;
+    let highlights = match grammar.syntax.clone().and_then(|language| SyntaxTree::new(language, text)) {
+        Some(syntax) => syntax.highlights(text, 0..text.len()).into_iter().map(|(range, face)| (range, face.to_string())).collect(),
+        None => BracketTree::new(grammar, text).highlights(0..text.len()).into_iter().map(|(range, face)| (range, face.to_string())).collect(),
+    };
+    Some(highlights)
+}
+
+pub struct RegexHighlighter {
+    // By file extension
+    patterns: HashMap<String, Vec<(Regex, String)>>,
+}
+
+impl RegexHighlighter {
+    pub fn new(config: &HighlightConfig) -> Result<RegexHighlighter, Error> {
+        let mut patterns = HashMap::new();
+        for (extension, faces) in &config.regex {
+            let mut compiled = Vec::new();
+            for (face, pattern) in faces {
+                compiled.push((Regex::new(pattern).map_err(|e| Error::BadPattern(face.clone(), e))?, face.clone()));
+            }
+            patterns.insert(extension.clone(), compiled);
+        }
+        Ok(RegexHighlighter { patterns })
+    }
+}
+
+impl Highlighter for RegexHighlighter {
+    fn highlight(&self, path: &Path, text: &str, _syntax: Option<&SyntaxTree>) -> Option<Vec<Highlight>> {
+        let patterns = self.patterns.get(path.extension()?.to_str()?)?;
+        Some(patterns.iter().flat_map(|(regex, face)| regex.find_iter(text).map(|found| Highlight { range: found.range(), face: face.clone(), priority: semantic::SYNTAX })).collect())
+    }
+}
+
+// The last tokens each language server sent, by file
+#[derive(Default)]
+pub struct SemanticHighlighter {
+    tokens: Mutex<HashMap<PathBuf, (Legend, SemanticTokens)>>,
+}
+
+impl SemanticHighlighter {
+    pub fn set(&self, path: &Path, legend: Legend, tokens: SemanticTokens) {
+        self.tokens.lock().unwrap().insert(path.to_owned(), (legend, tokens));
+    }
+}
+
+impl Highlighter for SemanticHighlighter {
+    fn highlight(&self, path: &Path, text: &str, _syntax: Option<&SyntaxTree>) -> Option<Vec<Highlight>> {
+        let tokens = self.tokens.lock().unwrap();
+        let (legend, tokens) = tokens.get(path)?;
+        Some(tokens.highlights(legend, text))
+    }
+}
+
+// The highlighters every rakoune has, by the names the config uses. "semantic" comes with Highlighting
+pub fn builtin(config: &HighlightConfig, languages: Languages) -> Result<Vec<Named>, Error> {
+    Ok(vec![
+        ("syntax".to_string(), Arc::new(SyntaxHighlighter { languages }) as Arc<dyn Highlighter>),
+        ("regex".to_string(), Arc::new(RegexHighlighter::new(config)?)),
+    ])
+}
+
+// Runs the highlighters for `path` in order, and merges what they found
+pub fn highlight_with(highlighters: &[Arc<dyn Highlighter>], path: &Path, text: &str, syntax: Option<&SyntaxTree>) -> Spans {
+    let mut all = Vec::new();
+    for (idx, highlighter) in highlighters.iter().enumerate() {
+        let priority = (highlighters.len() - idx).min(u8::MAX as usize) as u8;
+        all.extend(highlighter.highlight(path, text, syntax).unwrap_or_default().into_iter().map(|highlight| Highlight { priority, ..highlight }));
+    }
+    semantic::merge(all)
+}
+
+// The highlights of one buffer, and the ones being worked out for it
+pub struct Highlighting {
+    config: HighlightConfig,
+    highlighters: HashMap<String, Arc<dyn Highlighter>>,
+    // Given what language servers send, and listed as "semantic"
+    pub semantic: Arc<SemanticHighlighter>,
+    // Version of the text they're for, as counted by the caller, and its file
+    pending: Option<(u64, PathBuf, mpsc::Receiver<Spans>)>,
+    pub spans: Spans,
+    pub version: Option<u64>,
+    pub path: Option<PathBuf>,
+}
+
+impl Highlighting {
+    pub fn new(config: HighlightConfig, highlighters: Vec<Named>) -> Result<Highlighting, Error> {
+        let semantic = Arc::new(SemanticHighlighter::default());
+        let mut highlighters: HashMap<String, Arc<dyn Highlighter>> = highlighters.into_iter().collect();
+        highlighters.insert("semantic".to_string(), semantic.clone());
+        let listed = config.default.iter().chain(config.filetypes.values().flatten());
+        if let Some(unknown) = listed.into_iter().find(|name| !highlighters.contains_key(*name)) {
+            return Err(Error::UnknownHighlighter(unknown.clone()));
+        }
+        Ok(Highlighting { config, highlighters, semantic, pending: None, spans: Vec::new(), version: None, path: None })
+    }
+
+    pub fn highlighter(&self, name: &str) -> Option<&Arc<dyn Highlighter>> {
+        self.highlighters.get(name)
+    }
+
+    fn for_path(&self, path: &Path) -> Vec<Arc<dyn Highlighter>> {
+        self.config.for_path(path).iter().map(|name| self.highlighters[name].clone()).collect()
+    }
+
+    // Highlights `text` on this thread, for one-off uses like :export
+    pub fn highlight(&self, path: &Path, text: &str, syntax: Option<&SyntaxTree>) -> Spans {
+        highlight_with(&self.for_path(path), path, text, syntax)
+    }
+
+    // Starts highlighting `text` on another thread. Anything still running for an older version
+    // is forgotten
+    pub fn request(&mut self, path: &Path, text: String, syntax: Option<SyntaxTree>, version: u64) {
+        let highlighters = self.for_path(path);
+        let (tx, rx) = mpsc::channel();
+        let for_path = path.to_owned();
+        std::thread::spawn(move || {
+            let _ = tx.send(highlight_with(&highlighters, &for_path, &text, syntax.as_ref()));
+        });
+        self.pending = Some((version, path.to_owned(), rx));
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    // For when a highlighter has something new, like semantic tokens, so the text is highlighted again
+    pub fn invalidate(&mut self) {
+        self.version = None;
+        self.pending = None;
+    }
+
+    // Whether the highlights for the text at `version` are being worked out
+    pub fn is_requested(&self, version: u64) -> bool {
+        self.pending.as_ref().is_some_and(|(for_version, ..)| *for_version == version)
+    }
+
+    // Takes the new highlights if they're done and still for the text as it is at `version`.
+    // Returns true if they changed, so there is something to redraw
+    pub fn poll(&mut self, version: u64) -> bool {
+        let Some((_, _, rx)) = &self.pending else { return false };
+        let Ok(spans) = rx.try_recv() else { return false };
+        let Some((for_version, path, _)) = self.pending.take().filter(|(for_version, ..)| *for_version == version) else { return false };
+        self.spans = spans;
+        self.version = Some(for_version);
+        self.path = Some(path);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bracket_tree::Grammar;
+
+    // Until the highlights are taken or thrown away
+    fn wait(highlighting: &mut Highlighting, version: u64) -> bool {
+        while highlighting.pending.is_some() {
+            if highlighting.poll(version) {
+                return true;
+            }
+            std::thread::yield_now();
+        }
+        false
+    }
+
+    #[test]
+    fn merges_in_listed_order() {
+        let config: HighlightConfig = toml::from_str("default = [\"syntax\", \"regex\"]\n[filetypes]\nmd = [\"regex\"]\n[regex.rs]\nkeyword = \"\\\\bfn\\\\b\"\ntodo = \"TODO\"\n[regex.md]\n\"markup.heading\" = \"(?m)^#+ .*$\"\n").unwrap();
+        let mut languages = Languages::default();
+        languages.add(Grammar::parse(Path::new("rust.toml"), "name = \"rust\"\nextensions = [\"rs\"]\nline_comment = \"//\"\n").unwrap());
+        let mut highlighting = Highlighting::new(config.clone(), builtin(&config, languages).unwrap()).unwrap();
+
+        let text = "fn main() {} // TODO\n";
+        let spans = highlight_with(&[highlighting.highlighter("syntax").unwrap().clone(), highlighting.highlighter("regex").unwrap().clone()], Path::new("main.rs"), text, None);
+        // The comment wins over the TODO inside it, as syntax is listed first
+        assert_eq!(spans, vec![(0..2, "keyword".to_string()), (13..20, "comment".to_string())]);
+        // The buffer's syntax tree knows more than the brackets
+        let syntax = SyntaxTree::new(Grammar::rust().syntax.unwrap(), text).unwrap();
+        let spans = highlight_with(&[highlighting.highlighter("syntax").unwrap().clone()], Path::new("main.rs"), text, Some(&syntax));
+        assert!(spans.contains(&(3..7, "function".to_string())) && spans.contains(&(13..20, "comment".to_string())));
+
+        highlighting.request(Path::new("README.md"), "# rakoune\ntext\n".to_string(), None, 1);
+        assert!(wait(&mut highlighting, 1));
+        assert_eq!(highlighting.spans, vec![(0..9, "markup.heading".to_string())]);
+        // Edited while highlighting, so the result is out of date
+        highlighting.request(Path::new("README.md"), "## kakoune\n".to_string(), None, 2);
+        assert!(!wait(&mut highlighting, 3));
+        assert_eq!(highlighting.version, Some(1));
+
+        // Brackets by depth over the highlights, but not in comments
+        let text = "f(a[0]) // (\n]";
+        let spans = vec![(0..1, "function".to_string()), (3..6, "keyword".to_string()), (8..12, "comment".to_string())];
+        let theme = Theme::default();
+        let colors = colors(&theme, &spans, &BracketDepths::new(text), &[0, 13], 0..text.len());
+        let (function, keyword, comment) = (theme.face_color("function").unwrap(), theme.face_color("keyword").unwrap(), theme.face_color("comment").unwrap());
+        assert_ne!(Theme::named("gruvbox").unwrap().face_color("keyword"), Some(keyword));
+        assert_eq!(colors, vec![(0..1, function), (1..2, bracket_color(Some(0))), (3..4, bracket_color(Some(1))), (4..5, keyword), (5..6, bracket_color(Some(1))), (6..7, bracket_color(Some(0))), (8..12, comment), (13..14, bracket_color(None))]);
+
+        let unknown: HighlightConfig = toml::from_str("default = [\"tree-sitter\"]").unwrap();
+        assert!(matches!(Highlighting::new(unknown, Vec::new()), Err(Error::UnknownHighlighter(name)) if name == "tree-sitter"));
+    }
+}
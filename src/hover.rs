@@ -0,0 +1,223 @@
+// Hover and signature help popups. Language servers send both as markdown, which is formatted like
+// the markdown preview and shown in a popup anchored at the cursor. Hover is asked for with <C-k>,
+// as K extends up, and signature help when typing '(' or ','. Either goes away when the cursor
+// moves or on Escape
+
+use std::ops::Range;
+use std::path::Path;
+
+use crate::commands::Registry;
+use crate::layout::Rect;
+use crate::markdown::{self, Element, FormattedSpan, SpanStyle};
+
+// Typing these asks the server for signature help
+pub const SIGNATURE_TRIGGERS: [char; 2] = ['(', ','];
+
+// Space between the cursor and the popup
+const GAP: f32 = 4.;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub label: String,
+    pub documentation: Option<String>,
+    // Byte ranges of the parameters in `label`
+    pub parameters: Vec<Range<usize>>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SignatureHelp {
+    pub signatures: Vec<Signature>,
+    pub active_signature: usize,
+    pub active_parameter: usize,
+}
+
+impl SignatureHelp {
+    // The active signature with its active parameter in bold, then its documentation
+    pub fn elements(&self) -> Vec<Element> {
+        let Some(signature) = self.signatures.get(self.active_signature) else { return Vec::new() };
+        let code = SpanStyle { code: true, ..Default::default() };
+        let active = signature.parameters.get(self.active_parameter).filter(|range| signature.label.get((*range).clone()).is_some());
+        let mut spans = match active {
+            Some(range) => vec![
+                FormattedSpan { text: signature.label[..range.start].to_string(), style: code },
+                FormattedSpan { text: signature.label[range.clone()].to_string(), style: SpanStyle { bold: true, ..code } },
+                FormattedSpan { text: signature.label[range.end..].to_string(), style: code },
+            ],
+            None => vec![FormattedSpan { text: signature.label.clone(), style: code }],
+        };
+        spans.retain(|span| !span.text.is_empty());
+        if self.signatures.len() > 1 {
+            spans.push(FormattedSpan { text: format!("  ({}/{})", self.active_signature + 1, self.signatures.len()), style: SpanStyle::default() });
+        }
+        let mut elements = vec![Element::Paragraph(spans)];
+        if let Some(documentation) = &signature.documentation {
+            elements.extend(markdown::format(documentation, Path::new("")));
+        }
+        elements
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopupKind {
+    Hover,
+    SignatureHelp,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Popup {
+    pub kind: PopupKind,
+    // Byte of the cursor it was shown for
+    pub anchor: usize,
+    pub elements: Vec<Element>,
+}
+
+// The popup that is showing, if any
+#[derive(Debug, Default)]
+pub struct Popups {
+    pub current: Option<Popup>,
+}
+
+impl Popups {
+    // Empty hovers, which servers send for positions with nothing to say, close the popup
+    pub fn show_hover(&mut self, anchor: usize, markdown: &str) {
+        let elements = markdown::format(markdown, Path::new(""));
+        self.current = (!elements.is_empty()).then_some(Popup { kind: PopupKind::Hover, anchor, elements });
+    }
+
+    pub fn show_signature_help(&mut self, anchor: usize, help: &SignatureHelp) {
+        let elements = help.elements();
+        self.current = (!elements.is_empty()).then_some(Popup { kind: PopupKind::SignatureHelp, anchor, elements });
+    }
+
+    // For motions. Typing goes through `typed` instead
+    pub fn cursor_moved(&mut self, head: usize) {
+        if self.current.as_ref().is_some_and(|popup| popup.anchor != head) {
+            self.current = None;
+        }
+    }
+
+    // `c` was typed in insert mode, leaving the cursor at `head`. Signature help follows the cursor
+    // while the arguments are typed, until the call is closed. Returns whether to ask the server
+    // for signature help
+    pub fn typed(&mut self, c: char, head: usize) -> bool {
+        match &mut self.current {
+            Some(popup) if popup.kind == PopupKind::SignatureHelp && c != ')' => popup.anchor = head,
+            _ => self.current = None,
+        }
+        SIGNATURE_TRIGGERS.contains(&c)
+    }
+
+    // Returns whether there was a popup to close, so Escape does nothing else
+    pub fn escape(&mut self) -> bool {
+        self.current.take().is_some()
+    }
+}
+
+// Where to draw a popup of `size` for the cursor at `cursor`, on a screen of `screen` pixels.
+// Signature help goes above the cursor, to keep the line being typed and the lines after it
+// visible, and hover below. Either flips if it doesn't fit, and moves left to stay on screen
+pub fn place(kind: PopupKind, cursor: Rect, size: (f32, f32), screen: (f32, f32)) -> Rect {
+    let above = cursor.y - GAP - size.1;
+    let below = cursor.y + cursor.h + GAP;
+    let y = match kind {
+        PopupKind::SignatureHelp if above >= 0. => above,
+        PopupKind::Hover if below + size.1 > screen.1 && above >= 0. => above,
+        _ => below,
+    };
+    let x = cursor.x.min(screen.0 - size.0).max(0.);
+    Rect { x, y, w: size.0, h: size.1 }
+}
+
+// What :hover needs from the editor
+pub trait HoverHost {
+    // Asks the language server of the focused buffer about the symbol under the cursor. The
+    // response is shown with Popups::show_hover
+    fn request_hover(&mut self) -> Result<(), String>;
+}
+
+fn hover_command<Ctx: HoverHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    ctx.request_hover()
+}
+
+pub fn register<Ctx: HoverHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("hover", hover_command::<Ctx>);
+    registry.bind("<C-k>", "hover");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Host(usize);
+
+    impl HoverHost for Host {
+        fn request_hover(&mut self) -> Result<(), String> {
+            self.0 += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bound_without_shadowing_the_grammar() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        assert_eq!(registry.binding("K"), None);
+        let mut host = Host(0);
+        registry.run(&mut host, registry.binding("<C-k>").unwrap()).unwrap();
+        assert_eq!(host.0, 1);
+    }
+
+    #[test]
+    fn signature_highlights_active_parameter() {
+        let label = "fn add(a: i32, b: i32) -> i32";
+        let signature = Signature { label: label.to_string(), documentation: Some("Adds **both**".to_string()), parameters: vec![7..13, 15..21] };
+        let help = SignatureHelp { signatures: vec![signature.clone(), signature], active_signature: 0, active_parameter: 1 };
+        let elements = help.elements();
+        let Element::Paragraph(spans) = &elements[0] else { panic!("{elements:?}") };
+        let bold: Vec<&str> = spans.iter().filter(|span| span.style.bold).map(|span| span.text.as_str()).collect();
+        assert_eq!(bold, vec!["b: i32"]);
+        assert_eq!(spans.last().unwrap().text, "  (1/2)");
+        assert_eq!(elements.len(), 2);
+
+        let out_of_range = SignatureHelp { active_parameter: 5, ..help };
+        let Element::Paragraph(spans) = &out_of_range.elements()[0] else { panic!() };
+        assert_eq!(spans[0].text, label);
+    }
+
+    #[test]
+    fn dismissal() {
+        let mut popups = Popups::default();
+        popups.show_hover(10, "");
+        assert!(popups.current.is_none());
+        popups.show_hover(10, "`x: i32`");
+        popups.cursor_moved(10);
+        assert!(popups.current.is_some());
+        popups.cursor_moved(11);
+        assert!(popups.current.is_none());
+
+        let help = SignatureHelp { signatures: vec![Signature { label: "f(a, b)".to_string(), documentation: None, parameters: vec![2..3, 5..6] }], ..Default::default() };
+        assert!(popups.typed('(', 3));
+        popups.show_signature_help(3, &help);
+        assert!(!popups.typed('x', 4));
+        assert_eq!(popups.current.as_ref().unwrap().anchor, 4);
+        assert!(popups.typed(',', 5));
+        popups.typed(')', 6);
+        assert!(popups.current.is_none());
+
+        popups.show_signature_help(3, &help);
+        assert!(popups.escape());
+        assert!(!popups.escape());
+    }
+
+    #[test]
+    fn placement() {
+        let cursor = Rect { x: 700., y: 100., w: 2., h: 20. };
+        assert_eq!(place(PopupKind::SignatureHelp, cursor, (200., 50.), (800., 600.)), Rect { x: 600., y: 46., w: 200., h: 50. });
+        assert_eq!(place(PopupKind::Hover, cursor, (200., 50.), (800., 600.)).y, 124.);
+        // No room above
+        assert_eq!(place(PopupKind::SignatureHelp, cursor, (200., 150.), (800., 600.)).y, 124.);
+        // No room below
+        assert_eq!(place(PopupKind::Hover, cursor, (200., 500.), (800., 600.)).y, 124.);
+        assert_eq!(place(PopupKind::Hover, Rect { y: 560., ..cursor }, (200., 100.), (800., 600.)).y, 456.);
+    }
+}
@@ -0,0 +1,33 @@
+struct Globals {
+    // Size of the render target in pixels
+    target_size: vec2<f32>,
+}
+
+@group(0) @binding(0) var<uniform> globals: Globals;
+@group(0) @binding(1) var image: texture_2d<f32>;
+@group(0) @binding(2) var image_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) opacity: f32,
+}
+
+// `rect` is where the image goes on the target, as x, y, w, h in pixels
+@vertex
+fn vs_main(@builtin(vertex_index) vertex: u32, @location(0) rect: vec4<f32>, @location(1) opacity: f32) -> VertexOutput {
+    // Triangle strip over the corners (0, 0), (1, 0), (0, 1), (1, 1)
+    let corner = vec2<f32>(f32(vertex & 1u), f32(vertex >> 1u));
+    let pixel = rect.xy + corner * rect.zw;
+    var out: VertexOutput;
+    out.position = vec4<f32>(pixel / globals.target_size * vec2<f32>(2., -2.) + vec2<f32>(-1., 1.), 0., 1.);
+    out.uv = corner;
+    out.opacity = opacity;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(image, image_sampler, in.uv);
+    return vec4<f32>(color.rgb, color.a * in.opacity);
+}
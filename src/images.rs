@@ -0,0 +1,372 @@
+// Images shown inside buffers. The ImageStore decodes and uploads each file once, the layout
+// reserves room for them with layout::Block, and the ImageRenderer draws them where the blocks went
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::layout::Rect;
+
+pub const SHADER: &str = include_str!("image.wgsl");
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Could not read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Could not decode {0}: {1}")]
+    Decode(PathBuf, png::DecodingError),
+}
+
+// Decoded pixels, 8 bit sRGB with alpha
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+pub fn decode_png(bytes: &[u8]) -> Result<Image, png::DecodingError> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buf.chunks(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        // Indexed images are expanded to RGB(A) by normalize_to_color8
+        png::ColorType::Grayscale | png::ColorType::Indexed => buf.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+    };
+    Ok(Image { width: info.width, height: info.height, rgba })
+}
+
+// Width and height from the header, without decoding the rest
+pub fn png_size(path: &Path) -> Option<(u32, u32)> {
+    let file = std::io::BufReader::new(std::fs::File::open(path).ok()?);
+    let info = png::Decoder::new(file).read_info().ok()?.info().size();
+    Some(info)
+}
+
+// Averages blocks of pixels until neither side is over `max_side`. For icons, which the OS only
+// shows small
+pub fn shrink(image: &Image, max_side: u32) -> Image {
+    let factor = image.width.max(image.height).div_ceil(max_side).max(1);
+    if factor == 1 {
+        return image.clone();
+    }
+    let (width, height) = (image.width / factor, image.height / factor);
+    let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0u32; 4];
+            for (dx, dy) in (0..factor).flat_map(|dy| (0..factor).map(move |dx| (dx, dy))) {
+                let at = (((y * factor + dy) * image.width + x * factor + dx) * 4) as usize;
+                for (total, value) in sum.iter_mut().zip(&image.rgba[at..at + 4]) {
+                    *total += *value as u32;
+                }
+            }
+            rgba.extend(sum.map(|total| (total / (factor * factor)) as u8));
+        }
+    }
+    Image { width, height, rgba }
+}
+
+// Scales to exactly `width` by `height`, averaging the pixels each new one covers. For color
+// glyphs, whose bitmaps come in a few sizes only
+pub fn resize(image: &Image, width: u32, height: u32) -> Image {
+    // The source pixels from `at` to `at + 1` cover, out of `to`, at least one
+    let span = |at: u32, to: u32, from: u32| (at * from / to)..((at + 1) * from / to).max(at * from / to + 1);
+    let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let (xs, ys) = (span(x, width, image.width), span(y, height, image.height));
+            let count = (xs.len() * ys.len()) as u64;
+            let mut sum = [0u64; 4];
+            for (sx, sy) in ys.flat_map(|sy| xs.clone().map(move |sx| (sx, sy))) {
+                let at = ((sy * image.width + sx) * 4) as usize;
+                for (total, value) in sum.iter_mut().zip(&image.rgba[at..at + 4]) {
+                    *total += *value as u64;
+                }
+            }
+            rgba.extend(sum.map(|total| (total / count) as u8));
+        }
+    }
+    Image { width, height, rgba }
+}
+
+// Size to show an image at: its own size, scaled down to fit in `max_width`
+pub fn fit_width(width: u32, height: u32, max_width: f32) -> (f32, f32) {
+    let scale = (max_width / width as f32).min(1.);
+    (width as f32 * scale, height as f32 * scale)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageId(usize);
+
+pub struct StoredImage {
+    pub width: u32,
+    pub height: u32,
+    pub view: wgpu::TextureView,
+}
+
+// Images uploaded to textures, each file only once
+#[derive(Default)]
+pub struct ImageStore {
+    images: Vec<StoredImage>,
+    by_path: HashMap<PathBuf, ImageId>,
+    // Files that couldn't be read or decoded
+    failed: HashSet<PathBuf>,
+}
+
+impl ImageStore {
+    pub fn load(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, path: &Path) -> Result<ImageId, Error> {
+        if let Some(id) = self.by_path.get(path) {
+            return Ok(*id);
+        }
+        let bytes = std::fs::read(path).map_err(|e| Error::Read(path.to_owned(), e))?;
+        let image = decode_png(&bytes).map_err(|e| Error::Decode(path.to_owned(), e))?;
+        let id = self.upload(device, queue, &image);
+        self.by_path.insert(path.to_owned(), id);
+        Ok(id)
+    }
+
+    // Like load, for drawing every frame: a file that failed isn't read again
+    pub fn load_once(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, path: &Path) -> Option<ImageId> {
+        if self.failed.contains(path) {
+            return None;
+        }
+        let id = self.load(device, queue, path).ok();
+        if id.is_none() {
+            self.failed.insert(path.to_owned());
+        }
+        id
+    }
+
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, image: &Image) -> ImageId {
+        let size = wgpu::Extent3d { width: image.width, height: image.height, depth_or_array_layers: 1 };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("image"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            texture.as_image_copy(),
+            &image.rgba,
+            wgpu::ImageDataLayout { offset: 0, bytes_per_row: Some(4 * image.width), rows_per_image: Some(image.height) },
+            size,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.images.push(StoredImage { width: image.width, height: image.height, view });
+        ImageId(self.images.len() - 1)
+    }
+
+    pub fn get(&self, id: ImageId) -> &StoredImage {
+        &self.images[id.0]
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct Globals {
+    target_size: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct ImageInstance {
+    rect: [f32; 4],
+    opacity: f32,
+}
+
+pub struct ImageRenderer {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    globals: wgpu::Buffer,
+    queued: Vec<(ImageId, Rect, f32)>,
+    instance_buffer: Option<wgpu::Buffer>,
+}
+
+impl ImageRenderer {
+    pub fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat) -> ImageRenderer {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("image shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("image bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("image pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("image pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<ImageInstance>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32],
+                }],
+            },
+            primitive: wgpu::PrimitiveState { topology: wgpu::PrimitiveTopology::TriangleStrip, ..Default::default() },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("image sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let globals = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("image globals"),
+            size: std::mem::size_of::<Globals>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        ImageRenderer { pipeline, bind_group_layout, sampler, globals, queued: Vec::new(), instance_buffer: None }
+    }
+
+    pub fn queue(&mut self, id: ImageId, rect: Rect) {
+        self.queue_faded(id, rect, 1.);
+    }
+
+    // Drawn with its alpha multiplied by `opacity`
+    pub fn queue_faded(&mut self, id: ImageId, rect: Rect, opacity: f32) {
+        self.queued.push((id, rect, opacity));
+    }
+
+    // Draws everything queued onto `view`, which is `target_size` pixels large, and clears the queue
+    pub fn render(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, target_size: (u32, u32), store: &ImageStore) {
+        if self.queued.is_empty() {
+            return;
+        }
+        let instances: Vec<ImageInstance> = self.queued.iter().map(|&(_, rect, opacity)| ImageInstance { rect: [rect.x, rect.y, rect.w, rect.h], opacity }).collect();
+        let bytes: &[u8] = bytemuck::cast_slice(&instances);
+        let fits = self.instance_buffer.as_ref().is_some_and(|buffer| buffer.size() >= bytes.len() as u64);
+        if !fits {
+            self.instance_buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("image instances"),
+                size: (bytes.len() as u64).next_power_of_two(),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        let instance_buffer = self.instance_buffer.as_ref().unwrap();
+        queue.write_buffer(instance_buffer, 0, bytes);
+        queue.write_buffer(&self.globals, 0, bytemuck::bytes_of(&Globals { target_size: [target_size.0 as f32, target_size.1 as f32] }));
+
+        let bind_groups: Vec<wgpu::BindGroup> = self.queued
+            .iter()
+            .map(|(id, _, _)| device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("image bind group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: self.globals.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(&store.get(*id).view) },
+                    wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::Sampler(&self.sampler) },
+                ],
+            }))
+            .collect();
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("image pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        // Every image is its own texture, so one draw each
+        for (idx, bind_group) in bind_groups.iter().enumerate() {
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.draw(0..4, idx as u32..idx as u32 + 1);
+        }
+        drop(pass);
+        self.queued.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::validate_shader;
+
+    #[test]
+    fn shader_is_valid() {
+        validate_shader(SHADER);
+    }
+
+    #[test]
+    fn decodes_to_rgba() {
+        let mut encoded = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut encoded, 2, 1);
+            encoder.set_color(png::ColorType::GrayscaleAlpha);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[10, 255, 200, 128]).unwrap();
+        }
+        let image = decode_png(&encoded).unwrap();
+        assert_eq!(image, Image { width: 2, height: 1, rgba: vec![10, 10, 10, 255, 200, 200, 200, 128] });
+        assert!(decode_png(b"not a png").is_err());
+
+        assert_eq!(fit_width(400, 200, 100.), (100., 50.));
+        assert_eq!(fit_width(40, 20, 100.), (40., 20.));
+
+        let image = Image { width: 4, height: 2, rgba: [[0, 0, 0, 255], [100, 0, 0, 255], [10, 10, 10, 0], [10, 10, 10, 0]].repeat(2).concat() };
+        assert_eq!(shrink(&image, 2), Image { width: 2, height: 1, rgba: vec![50, 0, 0, 255, 10, 10, 10, 0] });
+        assert_eq!(shrink(&image, 4), image);
+        assert_eq!(resize(&image, 2, 1), shrink(&image, 2));
+        assert_eq!(resize(&image, 1, 4).rgba[..4], [30, 5, 5, 127]);
+    }
+}
@@ -0,0 +1,143 @@
+// Insert mode with several selections: every edit happens at the head of each selection at once,
+// and afterwards each selection is an empty cursor after what was typed
+
+use std::ops::Range;
+
+use crate::grammar::merge;
+use crate::selection::Selection;
+
+// Makes one edit per selection, front to back. `edit` gets the head of a selection in the
+// original text and returns the range to replace and what to replace it with. Ranges are clamped
+// so they never reach back into an earlier edit, which happens when cursors are next to each other.
+// Returns the ranges of the original text replaced and the lengths of what replaced them, back to
+// front, so they can be applied one after the other like workspace_edit::apply's
+fn edit_each(text: &mut String, selections: &mut Vec<Selection>, mut edit: impl FnMut(usize, usize) -> (Range<usize>, String)) -> Vec<(Range<usize>, usize)> {
+    merge(selections);
+    let original = text.clone();
+    let mut out = String::with_capacity(text.len());
+    let mut edits = Vec::with_capacity(selections.len());
+    // End of the last replaced range in the original text, up to which `out` is written
+    let mut copied = 0;
+    for (idx, selection) in selections.iter_mut().enumerate() {
+        let (range, new) = edit(idx, selection.head);
+        let start = range.start.max(copied);
+        let end = range.end.max(start);
+        out.push_str(&original[copied..start]);
+        out.push_str(&new);
+        edits.push((start..end, new.len()));
+        copied = end;
+        *selection = Selection::cursor(out.len());
+    }
+    out.push_str(&original[copied..]);
+    *text = out;
+    merge(selections);
+    edits.reverse();
+    edits
+}
+
+pub fn insert(text: &mut String, selections: &mut Vec<Selection>, typed: &str) -> Vec<(Range<usize>, usize)> {
+    edit_each(text, selections, |_, cursor| (cursor..cursor, typed.to_string()))
+}
+
+// Deletes the character before each cursor
+pub fn backspace(text: &mut String, selections: &mut Vec<Selection>) -> Vec<(Range<usize>, usize)> {
+    let original = text.clone();
+    edit_each(text, selections, |_, cursor| {
+        let start = original[..cursor].chars().next_back().map_or(cursor, |c| cursor - c.len_utf8());
+        (start..cursor, String::new())
+    })
+}
+
+// With one clip per selection, like after yanking with the same selections, each selection gets
+// its own. Otherwise every selection gets all of them, one per line
+pub fn paste(text: &mut String, selections: &mut Vec<Selection>, clips: &[String]) -> Vec<(Range<usize>, usize)> {
+    merge(selections);
+    let joined = clips.join("\n");
+    let one_each = clips.len() == selections.len();
+    edit_each(text, selections, |idx, cursor| (cursor..cursor, if one_each { clips[idx].clone() } else { joined.clone() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::Rng;
+
+    fn cursors(at: &[usize]) -> Vec<Selection> {
+        at.iter().map(|&at| Selection::cursor(at)).collect()
+    }
+
+    #[test]
+    fn types_at_every_cursor() {
+        let mut text = "ab\ncd\n".to_string();
+        let mut selections = vec![Selection { anchor: 3, head: 4, goal: None }, Selection { anchor: 0, head: 1, goal: None }];
+        insert(&mut text, &mut selections, "xy");
+        assert_eq!(text, "axyb\ncxyd\n");
+        assert_eq!(selections, cursors(&[3, 8]));
+
+        assert_eq!(backspace(&mut text, &mut selections), vec![(7..8, 0), (2..3, 0)]);
+        assert_eq!(text, "axb\ncxd\n");
+        assert_eq!(selections, cursors(&[2, 6]));
+
+        paste(&mut text, &mut selections, &["1".to_string(), "2".to_string()]);
+        assert_eq!(text, "ax1b\ncx2d\n");
+        paste(&mut text, &mut selections, &["!".to_string()]);
+        assert_eq!(text, "ax1!b\ncx2!d\n");
+    }
+
+    #[test]
+    fn backspace_into_neighbour() {
+        let mut text = "aéc".to_string();
+        let mut selections = vec![Selection { anchor: 1, head: 3, goal: None }, Selection::cursor(3), Selection::cursor(4)];
+        backspace(&mut text, &mut selections);
+        assert_eq!(text, "a");
+        assert_eq!(selections, cursors(&[1]));
+        backspace(&mut text, &mut selections);
+        backspace(&mut text, &mut selections);
+        assert_eq!(text, "");
+        assert_eq!(selections, cursors(&[0]));
+    }
+
+    #[test]
+    fn selections_stay_valid_through_edit_storms() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..200 {
+            let mut text = "fn main() {\n    println!(\"hé\");\n}\n".to_string();
+            let mut selections: Vec<Selection> = (0..1 + rng.below(6))
+                .map(|_| {
+                    let boundaries: Vec<usize> = text.char_indices().map(|(at, _)| at).chain([text.len()]).collect();
+                    let a = boundaries[rng.below(boundaries.len())];
+                    let b = boundaries[rng.below(boundaries.len())];
+                    Selection { anchor: a, head: b, goal: None }
+                })
+                .collect();
+            for _ in 0..50 {
+                let before = text.len();
+                let cursors = {
+                    let mut merged = selections.clone();
+                    merge(&mut merged);
+                    merged.len()
+                };
+                match rng.below(4) {
+                    0 => {
+                        insert(&mut text, &mut selections, ["a", "ü", "\n", "xyz"][rng.below(4)]);
+                        assert!(text.len() >= before + cursors);
+                    }
+                    1 | 2 => {
+                        backspace(&mut text, &mut selections);
+                        assert!(text.len() <= before);
+                    }
+                    _ => {
+                        let clips: Vec<String> = (0..rng.below(4)).map(|i| "p".repeat(i)).collect();
+                        paste(&mut text, &mut selections, &clips);
+                    }
+                }
+                assert!(!selections.is_empty());
+                assert!(selections.windows(2).all(|pair| pair[0].head < pair[1].head));
+                for selection in &selections {
+                    assert_eq!(selection.anchor, selection.head);
+                    assert!(selection.head <= text.len() && text.is_char_boundary(selection.head));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use crate::ansi::{self, StyledSpan};
+use crate::commands::Registry;
+
+// How often the job is checked for having exited. It can't be waited on, as kill needs it too
+const EXIT_POLL: Duration = Duration::from_millis(20);
+// Lines of output the pane under the buffer shows, under a line saying what's running
+pub const PANE_ROWS: usize = 10;
+
+// A build or test command from the config, run through the shell: sh, or cmd on Windows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobSpec {
+    pub name: String,
+    pub command: String,
+    pub cwd: PathBuf,
+}
+
+// Where an error or warning in the output points
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    // 1-based, like compilers print them
+    pub line: usize,
+    pub col: usize,
+    // Line of the output it was found on
+    pub output_line: usize,
+}
+
+// Finds `path:line:col` or `path:line` in a line of output, as printed by rustc (after `-->`),
+// gcc, clang, tsc, eslint, pytest and most others
+pub fn parse_location(line: &str) -> Option<(PathBuf, usize, usize)> {
+    let line = ansi::strip(line);
+    let line = line.trim_start();
+    let line = line.strip_prefix("--> ").unwrap_or(line);
+    for (start, _) in line.char_indices().filter(|&(at, _)| at == 0 || line[..at].ends_with(char::is_whitespace)) {
+        let mut parts = line[start..].splitn(4, ':');
+        let (Some(path), Some(line_no)) = (parts.next(), parts.next()) else { continue };
+        let Ok(line_no) = line_no.trim_end().parse::<usize>() else { continue };
+        let col = parts.next().and_then(|col| col.split_whitespace().next()?.parse().ok()).unwrap_or(1);
+        // All digits is more likely a time of day than a file
+        if path.chars().all(|c| c.is_ascii_digit()) || path.contains(char::is_whitespace) || line_no == 0 {
+            continue;
+        }
+        return Some((PathBuf::from(path), line_no, col));
+    }
+    None
+}
+
+enum JobEvent {
+    Line(String),
+    Exited(Option<i32>),
+}
+
+// A running (or finished) job, with its output and the error locations found in it
+pub struct Job {
+    pub spec: JobSpec,
+    // Lines of output with the escape sequences taken out, and their colors
+    pub output: Vec<String>,
+    pub styles: Vec<Vec<StyledSpan>>,
+    pub errors: Vec<Location>,
+    // Exit code once finished. None for a job that's running, or was killed by a signal
+    pub status: Option<Option<i32>>,
+    current_error: Option<usize>,
+    // Shared with the thread waiting for it to exit
+    child: Arc<Mutex<Child>>,
+    ansi: ansi::Parser,
+    events: mpsc::Receiver<JobEvent>,
+}
+
+fn stream(reader: impl Read + Send + 'static, tx: mpsc::Sender<JobEvent>) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(Result::ok) {
+            if tx.send(JobEvent::Line(line)).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+fn shell(command: &str) -> Command {
+    let mut shell = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+    shell.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(command);
+    // In a process group of its own, so kill gets what it started too
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+    shell
+}
+
+impl Job {
+    pub fn start(spec: JobSpec) -> std::io::Result<Job> {
+        let mut child: Child = shell(&spec.command)
+            .current_dir(&spec.cwd)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let (tx, rx) = mpsc::channel();
+        let readers = [
+            child.stdout.take().map(|out| stream(out, tx.clone())),
+            child.stderr.take().map(|err| stream(err, tx.clone())),
+        ];
+        let child = Arc::new(Mutex::new(child));
+        let waited = child.clone();
+        std::thread::spawn(move || {
+            let code = loop {
+                match waited.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).try_wait() {
+                    Ok(Some(status)) => break status.code(),
+                    Ok(None) => {}
+                    Err(_) => break None,
+                }
+                std::thread::sleep(EXIT_POLL);
+            };
+            // Report the exit once all output is read, so it's always the last event
+            for reader in readers.into_iter().flatten() {
+                let _ = reader.join();
+            }
+            let _ = tx.send(JobEvent::Exited(code));
+        });
+        Ok(Job {
+            spec,
+            output: Vec::new(),
+            styles: Vec::new(),
+            errors: Vec::new(),
+            status: None,
+            current_error: None,
+            child,
+            ansi: ansi::Parser::default(),
+            events: rx,
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status.is_none()
+    }
+
+    // Sends SIGTERM to the job and everything it started, or ends it on Windows
+    pub fn kill(&self) {
+        let mut child = self.child.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Only while it hasn't been reaped, as until then its pid, which names its process group,
+        // can't be given to another process
+        if !matches!(child.try_wait(), Ok(None)) {
+            return;
+        }
+        #[cfg(unix)]
+        // SAFETY: kill only sends a signal
+        unsafe {
+            libc::kill(-(child.id() as libc::pid_t), libc::SIGTERM);
+        }
+        #[cfg(not(unix))]
+        let _ = child.kill();
+    }
+
+    // Takes in output produced since the last call. Returns true if there is something new to draw
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok(event) = self.events.try_recv() {
+            changed = true;
+            match event {
+                JobEvent::Line(line) => {
+                    let (line, styles) = self.ansi.parse_line(&line);
+                    if let Some((path, line_no, col)) = parse_location(&line) {
+                        let path = if path.is_relative() { self.spec.cwd.join(path) } else { path };
+                        self.errors.push(Location { path, line: line_no, col, output_line: self.output.len() });
+                    }
+                    self.output.push(line);
+                    self.styles.push(styles);
+                }
+                JobEvent::Exited(code) => self.status = Some(code),
+            }
+        }
+        changed
+    }
+
+    pub fn current_error(&self) -> Option<&Location> {
+        self.errors.get(self.current_error?)
+    }
+
+    // Steps to the next error, wrapping around, and returns it to jump to
+    pub fn next_error(&mut self) -> Option<&Location> {
+        if self.errors.is_empty() {
+            return None;
+        }
+        self.current_error = Some(self.current_error.map_or(0, |i| (i + 1) % self.errors.len()));
+        self.current_error()
+    }
+
+    pub fn prev_error(&mut self) -> Option<&Location> {
+        if self.errors.is_empty() {
+            return None;
+        }
+        let n = self.errors.len();
+        self.current_error = Some(self.current_error.map_or(n - 1, |i| (i + n - 1) % n));
+        self.current_error()
+    }
+
+    // The pane's first line: what runs, and how it ended once it has
+    pub fn title(&self) -> String {
+        let errors = match self.errors.len() {
+            0 => String::new(),
+            1 => ", 1 error".to_string(),
+            n => format!(", {n} errors"),
+        };
+        match self.status {
+            None => format!("{}: running{errors}", self.spec.name),
+            Some(Some(0)) => format!("{}: done{errors}", self.spec.name),
+            Some(Some(code)) => format!("{}: exited with {code}{errors}", self.spec.name),
+            Some(None) => format!("{}: killed{errors}", self.spec.name),
+        }
+    }
+
+    // The last `rows` lines of output, or the ones around the current error once stepped to
+    pub fn shown_lines(&self, rows: usize) -> std::ops::Range<usize> {
+        let end = match self.current_error() {
+            Some(error) => (error.output_line + rows.div_ceil(2)).min(self.output.len()),
+            None => self.output.len(),
+        };
+        let start = end.saturating_sub(rows);
+        start..(start + rows).min(self.output.len())
+    }
+}
+
+// What :make and :run need from the editor
+pub trait JobsHost {
+    // A job of the project config, by name
+    fn project_job(&self, name: &str) -> Option<JobSpec>;
+    // Where :run runs commands
+    fn job_dir(&self) -> PathBuf;
+    // Kills the job that's running, if any, and starts `spec` with its output in the pane
+    fn start_job(&mut self, spec: JobSpec) -> Result<(), String>;
+    fn job(&mut self) -> Option<&mut Job>;
+    // Opens the file an error is in at its line and column
+    fn jump_to_error(&mut self, location: Location) -> Result<(), String>;
+    // Hides the output pane, killing the job if it's still running
+    fn close_job(&mut self);
+}
+
+// make [name], the project's job of that name, or its build job
+fn make_command<Ctx: JobsHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let name = match args {
+        [] => "build",
+        [name] => name,
+        _ => return Err("Usage: make [job]".to_string()),
+    };
+    let spec = ctx.project_job(name).ok_or_else(|| format!("No job {name} in the project config"))?;
+    ctx.start_job(spec)
+}
+
+// run <command>, through the shell from the project root
+fn run_command<Ctx: JobsHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let command = args.first().map(|command| command.trim()).filter(|command| !command.is_empty()).ok_or("Usage: run <command>")?;
+    let spec = JobSpec { name: command.to_string(), command: command.to_string(), cwd: ctx.job_dir() };
+    ctx.start_job(spec)
+}
+
+fn step_error<Ctx: JobsHost>(ctx: &mut Ctx, next: bool) -> Result<(), String> {
+    let job = ctx.job().ok_or("Nothing has run")?;
+    let location = if next { job.next_error() } else { job.prev_error() };
+    let location = location.cloned().ok_or("No errors in the output")?;
+    ctx.jump_to_error(location)
+}
+
+// next-error and prev-error, to the locations found in the output, wrapping around
+fn next_error_command<Ctx: JobsHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    step_error(ctx, true)
+}
+
+fn prev_error_command<Ctx: JobsHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    step_error(ctx, false)
+}
+
+fn kill_job_command<Ctx: JobsHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    ctx.job().filter(|job| job.is_running()).ok_or("Nothing is running")?.kill();
+    Ok(())
+}
+
+fn close_output_command<Ctx: JobsHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    ctx.close_job();
+    Ok(())
+}
+
+pub fn register<Ctx: JobsHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("make", make_command::<Ctx>);
+    registry.add_raw_builtin("run", run_command::<Ctx>);
+    registry.add_builtin("next-error", next_error_command::<Ctx>);
+    registry.add_builtin("prev-error", prev_error_command::<Ctx>);
+    registry.add_builtin("kill-job", kill_job_command::<Ctx>);
+    registry.add_builtin("close-output", close_output_command::<Ctx>);
+    registry.bind("<F8>", "next-error");
+    registry.bind("<S-F8>", "prev-error");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_locations() {
+        assert_eq!(parse_location("  --> src/main.rs:12:5"), Some((PathBuf::from("src/main.rs"), 12, 5)));
+        assert_eq!(parse_location("foo.c:3:14: error: expected ';'"), Some((PathBuf::from("foo.c"), 3, 14)));
+        assert_eq!(parse_location("\u{1b}[1mtests/a.py:7\u{1b}[0m: AssertionError"), Some((PathBuf::from("tests/a.py"), 7, 1)));
+        assert_eq!(parse_location("FAILED at 12:30: timeout"), None);
+        assert_eq!(parse_location("Compiling rakoune v0.1.0"), None);
+    }
+
+    #[test]
+    fn runs_and_collects_errors() {
+        let spec = JobSpec {
+            name: "build".to_string(),
+            command: "printf '\\033[32mbuilding\\033[0m\\n'; echo 'a.rs:1:2: error' >&2; echo 'b.rs:3:4: warning'; exit 3".to_string(),
+            cwd: PathBuf::from("/"),
+        };
+        let mut job = Job::start(spec).unwrap();
+        while job.is_running() {
+            job.poll();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(job.status, Some(Some(3)));
+        assert_eq!(job.output.len(), 3);
+        assert!(job.output.contains(&"building".to_string()));
+        assert_eq!(job.errors.len(), 2);
+        let (first, last) = (job.errors[0].clone(), job.errors[1].clone());
+        assert_eq!(job.next_error(), Some(&first));
+        assert_eq!(job.prev_error(), Some(&last));
+        assert_eq!(job.next_error(), Some(&first));
+        assert!(first.path.is_absolute());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn kill_ends_what_the_job_started() {
+        let spec = JobSpec { name: "watch".to_string(), command: "sleep 30 & echo $!; wait".to_string(), cwd: PathBuf::from("/") };
+        let mut job = Job::start(spec).unwrap();
+        while job.output.is_empty() {
+            job.poll();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let sleep: u32 = job.output[0].parse().unwrap();
+        job.kill();
+        while job.is_running() {
+            job.poll();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(job.status, Some(None));
+        // Gone, or a zombie waiting for init to reap it
+        let start = std::time::Instant::now();
+        let alive = || std::fs::read_to_string(format!("/proc/{sleep}/stat")).is_ok_and(|stat| !stat.contains(") Z "));
+        while alive() && start.elapsed() < std::time::Duration::from_secs(5) {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert!(!alive());
+        // Reaped, so killing again does nothing
+        job.kill();
+    }
+}
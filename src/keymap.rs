@@ -0,0 +1,172 @@
+// Which key a normal mode command is. Shortcuts are meant for where the keys are, so by default they
+// go by the physical key as if the layout was US QWERTY, and hjkl stay under the right hand on
+// Dvorak, AZERTY or a Cyrillic layout. With the layout mapping the typed character is used instead,
+// except that characters outside of Latin layouts still go by the physical key. Insert mode always
+// types what the layout says
+//
+//   [keyboard]
+//   mapping = "layout"
+
+use std::collections::VecDeque;
+
+use serde::Deserialize;
+
+use crate::commands::Registry;
+
+// Raw events kept for :key-events
+const MAX_LOGGED: usize = 50;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mapping {
+    #[default]
+    Physical,
+    Layout,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    pub mapping: Mapping,
+}
+
+// What winit's scancodes number: PC set 1 on Linux and Windows, Apple's virtual key codes on macOS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scancodes {
+    Pc,
+    Mac,
+}
+
+impl Scancodes {
+    pub fn native() -> Scancodes {
+        if cfg!(target_os = "macos") { Scancodes::Mac } else { Scancodes::Pc }
+    }
+
+    // The character of the key at `scancode` on a US QWERTY keyboard
+    pub fn qwerty(self, scancode: u32, shift: bool) -> Option<char> {
+        let (plain, shifted) = match self {
+            Scancodes::Pc => match scancode {
+                0x02..=0x0d => (b"1234567890-="[scancode as usize - 0x02], b"!@#$%^&*()_+"[scancode as usize - 0x02]),
+                0x10..=0x1b => (b"qwertyuiop[]"[scancode as usize - 0x10], b"QWERTYUIOP{}"[scancode as usize - 0x10]),
+                0x1e..=0x29 => (b"asdfghjkl;'`"[scancode as usize - 0x1e], b"ASDFGHJKL:\"~"[scancode as usize - 0x1e]),
+                0x2b..=0x35 => (b"\\zxcvbnm,./"[scancode as usize - 0x2b], b"|ZXCVBNM<>?"[scancode as usize - 0x2b]),
+                0x39 => (b' ', b' '),
+                _ => return None,
+            },
+            // Apple's numbering follows the ANSI layout in no order in particular
+            Scancodes::Mac => {
+                const PLAIN: &[u8; 0x33] = b"asdfhgzxcv\0bqweryt123465=97-80]ou[ip\rlj'k;\\,/nm.\t `";
+                const SHIFTED: &[u8; 0x33] = b"ASDFHGZXCV\0BQWERYT!@#$^%+(&_*)}OU{IP\rLJ\"K:|<?NM>\t ~";
+                match PLAIN.get(scancode as usize) {
+                    Some(c) if c.is_ascii_graphic() || *c == b' ' => (*c, SHIFTED[scancode as usize]),
+                    _ => return None,
+                }
+            }
+        };
+        Some(if shift { shifted } else { plain } as char)
+    }
+}
+
+// The key for normal mode commands, from the character the layout typed and the physical key
+pub fn command_key(mapping: Mapping, scancodes: Scancodes, typed: char, scancode: Option<u32>, shift: bool) -> char {
+    let physical = scancode.and_then(|scancode| scancodes.qwerty(scancode, shift));
+    match mapping {
+        Mapping::Physical => physical.unwrap_or(typed),
+        Mapping::Layout if typed.is_ascii() || !typed.is_alphabetic() => typed,
+        Mapping::Layout => physical.unwrap_or(typed),
+    }
+}
+
+// Raw key events, for finding out why a shortcut doesn't do what it should
+#[derive(Debug, Default)]
+pub struct KeyEventLog {
+    pub enabled: bool,
+    events: VecDeque<String>,
+}
+
+impl KeyEventLog {
+    // winit's KeyboardInput, with the virtual key code as its name
+    pub fn key(&mut self, scancode: u32, virtual_keycode: Option<&str>, pressed: bool, modifiers: &str) {
+        let state = if pressed { "pressed" } else { "released" };
+        self.push(format!("key {scancode:#04x} {} {state} {modifiers}", virtual_keycode.unwrap_or("-")).trim_end().to_string());
+    }
+
+    // winit's ReceivedCharacter
+    pub fn character(&mut self, c: char) {
+        self.push(format!("char {c:?} U+{:04X}", c as u32));
+    }
+
+    fn push(&mut self, event: String) {
+        if !self.enabled {
+            return;
+        }
+        if self.events.len() == MAX_LOGGED {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    pub fn text(&self) -> String {
+        self.events.iter().map(|event| format!("{event}\n")).collect()
+    }
+}
+
+// What :key-events needs from the editor
+pub trait KeyEventsHost {
+    fn key_event_log(&mut self) -> &mut KeyEventLog;
+    // Shows the logged events in a scratch buffer of their own, kept up to date while logging
+    fn show_key_events(&mut self);
+}
+
+// Starts logging and shows the log, or stops with `off`
+fn key_events_command<Ctx: KeyEventsHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    match args {
+        [] => {
+            ctx.key_event_log().enabled = true;
+            ctx.show_key_events();
+        }
+        ["off"] => ctx.key_event_log().enabled = false,
+        _ => return Err("Usage: key-events [off]".to_string()),
+    }
+    Ok(())
+}
+
+pub fn register<Ctx: KeyEventsHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("key-events", key_events_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_by_position() {
+        assert_eq!(Scancodes::Pc.qwerty(0x24, false), Some('j'));
+        assert_eq!(Scancodes::Pc.qwerty(0x22, true), Some('G'));
+        assert_eq!(Scancodes::Pc.qwerty(0x0b, false), Some('0'));
+        assert_eq!(Scancodes::Pc.qwerty(0x01, false), None);
+        assert_eq!(Scancodes::Mac.qwerty(0x26, false), Some('j'));
+        assert_eq!(Scancodes::Mac.qwerty(0x05, true), Some('G'));
+        assert_eq!(Scancodes::Mac.qwerty(0x1d, false), Some('0'));
+        assert_eq!(Scancodes::Mac.qwerty(0x24, false), None);
+
+        // Cyrillic о is where j is on QWERTY
+        assert_eq!(command_key(Mapping::Physical, Scancodes::Pc, 'о', Some(0x24), false), 'j');
+        assert_eq!(command_key(Mapping::Layout, Scancodes::Pc, 'о', Some(0x24), false), 'j');
+        // Dvorak h is where j is on QWERTY, and with the layout mapping it stays h
+        assert_eq!(command_key(Mapping::Physical, Scancodes::Pc, 'h', Some(0x24), false), 'j');
+        assert_eq!(command_key(Mapping::Layout, Scancodes::Pc, 'h', Some(0x24), false), 'h');
+        assert_eq!(command_key(Mapping::Physical, Scancodes::Pc, '€', None, false), '€');
+
+        let config: KeyboardConfig = toml::from_str("mapping = \"layout\"").unwrap();
+        assert_eq!(config.mapping, Mapping::Layout);
+
+        let mut log = KeyEventLog::default();
+        log.character('x');
+        assert_eq!(log.text(), "");
+        log.enabled = true;
+        log.key(0x24, Some("J"), true, "");
+        log.character('о');
+        assert_eq!(log.text(), "key 0x24 J pressed\nchar 'о' U+043E\n");
+    }
+}
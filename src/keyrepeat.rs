@@ -0,0 +1,104 @@
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepeatConfig {
+    // How long a key is held before it starts repeating
+    pub delay: Duration,
+    // Time between repeats after that
+    pub interval: Duration,
+}
+
+impl Default for RepeatConfig {
+    fn default() -> Self {
+        RepeatConfig { delay: Duration::from_millis(400), interval: Duration::from_millis(33) }
+    }
+}
+
+// Repeats held keys ourselves instead of trusting the platform, whose repeat rate differs between
+// systems and whose repeat events pile up or get dropped when frames are slow
+#[derive(Debug)]
+pub struct KeyRepeat<K> {
+    pub config: RepeatConfig,
+    held: Option<(K, Instant)>,
+    // Repeats already sent for the held key
+    sent: u32,
+}
+
+impl<K: Copy + Eq> KeyRepeat<K> {
+    pub fn new(config: RepeatConfig) -> KeyRepeat<K> {
+        KeyRepeat { config, held: None, sent: 0 }
+    }
+
+    // Returns false for the platform's own repeats of the held key, which should be ignored
+    pub fn press(&mut self, key: K, now: Instant) -> bool {
+        if self.held.is_some_and(|(held, _)| held == key) {
+            return false;
+        }
+        // Only the last key pressed repeats, like on every platform
+        self.held = Some((key, now));
+        self.sent = 0;
+        true
+    }
+
+    pub fn release(&mut self, key: K) {
+        if self.held.is_some_and(|(held, _)| held == key) {
+            self.held = None;
+        }
+    }
+
+    // Forget the held key, like when the window loses focus and won't get the release
+    pub fn clear(&mut self) {
+        self.held = None;
+    }
+
+    fn repeats_by(&self, since_press: Duration) -> u32 {
+        match since_press.checked_sub(self.config.delay) {
+            None => 0,
+            Some(after_delay) => 1 + (after_delay.as_nanos() / self.config.interval.as_nanos().max(1)) as u32,
+        }
+    }
+
+    // The held key and how many times it should repeat now. After a slow frame that's several
+    // times, so held keys move the same distance no matter the frame rate
+    pub fn due(&mut self, now: Instant) -> Option<(K, u32)> {
+        let (key, pressed_at) = self.held?;
+        let total = self.repeats_by(now.duration_since(pressed_at));
+        let new = total.saturating_sub(self.sent);
+        self.sent = total.max(self.sent);
+        (new > 0).then_some((key, new))
+    }
+
+    // When the next repeat is due, to wake up for
+    pub fn next_at(&self) -> Option<Instant> {
+        let (_, pressed_at) = self.held?;
+        Some(pressed_at + self.config.delay + self.config.interval * self.sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_after_delay() {
+        let config = RepeatConfig { delay: Duration::from_millis(300), interval: Duration::from_millis(50) };
+        let mut repeat = KeyRepeat::new(config);
+        let start = Instant::now();
+        assert!(repeat.press('j', start));
+        assert_eq!(repeat.due(start + Duration::from_millis(299)), None);
+        assert_eq!(repeat.next_at(), Some(start + Duration::from_millis(300)));
+        assert_eq!(repeat.due(start + Duration::from_millis(300)), Some(('j', 1)));
+        assert_eq!(repeat.next_at(), Some(start + Duration::from_millis(350)));
+
+        // The platform's own repeat doesn't restart the delay
+        assert!(!repeat.press('j', start + Duration::from_millis(310)));
+        // A slow frame catches up
+        assert_eq!(repeat.due(start + Duration::from_millis(460)), Some(('j', 3)));
+
+        assert!(repeat.press('k', start + Duration::from_millis(470)));
+        repeat.release('j');
+        assert_eq!(repeat.due(start + Duration::from_millis(770)), Some(('k', 1)));
+        repeat.release('k');
+        assert_eq!(repeat.due(start + Duration::from_secs(5)), None);
+    }
+}
@@ -0,0 +1,720 @@
+use std::ops::Range;
+
+use crate::font::{FontStack, ShapedCodepoint};
+use crate::selection::line_starts;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutSettings {
+    pub font_size: f32,
+    // Multiplier applied to the natural line height given by the font metrics
+    pub line_height: f32,
+    // Extra space in pixels added after every glyph
+    pub letter_spacing: f32,
+    // Lines longer than this many pixels continue on the next row. None to never wrap
+    pub wrap_width: Option<f32>,
+}
+
+// Drawn dimmed at the start of rows that continue a wrapped line
+pub const WRAP_MARKER: &str = "↪ ";
+
+impl Default for LayoutSettings {
+    fn default() -> Self {
+        LayoutSettings {
+            font_size: 16.,
+            line_height: 1.,
+            letter_spacing: 0.,
+            wrap_width: None,
+        }
+    }
+}
+
+// Things that change how text is laid out without being part of the text itself
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Decorations<'d> {
+    // Ranges of line numbers. The first line of a fold stays visible, the rest are hidden
+    pub folds: &'d [Range<usize>],
+    // Must be sorted by position
+    pub virtual_text: &'d [VirtualText],
+    // Must be sorted by line
+    pub blocks: &'d [Block],
+}
+
+// Space reserved below a line for something that isn't text, like an image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Block {
+    // Line number in the text the block goes below
+    pub after_line: usize,
+    pub width: f32,
+    pub height: f32,
+}
+
+// Text shown inline at a position in the buffer without being part of it, like inlay hints
+#[derive(Debug, Clone, PartialEq)]
+pub struct VirtualText {
+    // Byte offset the text is shown before
+    pub at: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+#[derive(Debug)]
+pub struct PositionedGlyph<'a> {
+    // None if no face in the stack could shape this part of the text
+    pub shaped: Option<ShapedCodepoint<'a>>,
+    // Empty for virtual text, placed where the virtual text is shown
+    pub byte_range: Range<usize>,
+    // Part of a VirtualText, to be drawn dimmed
+    pub is_virtual: bool,
+    // Index into Layout::lines
+    pub line: usize,
+    // Pen position on the baseline, in pixels
+    pub x: f32,
+    pub y: f32,
+    // Where to draw the glyph relative to the pen position, y pointing down
+    pub offset: (f32, f32),
+    pub advance: f32,
+}
+
+// One row of the layout. With soft wrap a line of the text can take several
+#[derive(Debug)]
+pub struct Line {
+    // Line number in the text, which differs from the index in Layout::lines when lines above are
+    // folded or wrapped
+    pub logical_line: usize,
+    // Continues the line of the row above after it wrapped, so WRAP_MARKER goes at its start
+    pub continuation: bool,
+    pub byte_range: Range<usize>,
+    // The text hidden after this line by a fold, drawn as a fold marker at the end of the line
+    pub folded: Option<Range<usize>>,
+    // Index range into Layout::glyphs
+    pub glyph_range: Range<usize>,
+    pub top: f32,
+    pub baseline: f32,
+    pub width: f32,
+}
+
+#[derive(Debug)]
+pub struct Layout<'a> {
+    pub glyphs: Vec<PositionedGlyph<'a>>,
+    pub lines: Vec<Line>,
+    pub line_height: f32,
+    // Where each of Decorations::blocks went, by index. Blocks below folded lines are left out
+    pub blocks: Vec<(usize, Rect)>,
+}
+
+// Scale from font units to pixels for a face at the given font size, including the face's size harmonization
+fn px_per_unit(shaped: &ShapedCodepoint, font_size: f32) -> f32 {
+    font_size * shaped.face.size_scale / shaped.face.ttf_face.units_per_em() as f32
+}
+
+// Height of one line in pixels, the same for every line of a layout
+pub fn line_height(fontstack: &FontStack, settings: &LayoutSettings) -> f32 {
+    let metrics = fontstack.vertical_metrics();
+    (metrics.ascent + metrics.descent + metrics.line_gap) * settings.font_size * settings.line_height
+}
+
+pub fn layout<'a>(fontstack: &'a FontStack, text: &str, settings: &LayoutSettings) -> Layout<'a> {
+    layout_decorated(fontstack, text, settings, Decorations::default())
+}
+
+// Lines of a text that an edit replaced, and how many lines replaced them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineEdit {
+    pub lines: Range<usize>,
+    pub new_lines: usize,
+}
+
+impl LineEdit {
+    // One edit doing what this one and then `next` did, which covers the lines either touched
+    pub fn then(&self, next: &LineEdit) -> LineEdit {
+        let start = self.lines.start.min(next.lines.start);
+        // In the lines as they were between the two edits
+        let end = (self.lines.start + self.new_lines).max(next.lines.end);
+        LineEdit { lines: start..end - self.new_lines + self.lines.len(), new_lines: end - start - next.lines.len() + next.new_lines }
+    }
+}
+
+// The first row each line of a text is shown on, for scrolling and culling by rows when long lines
+// wrap. Without a wrap width rows are lines, and nothing is laid out to find them
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowIndex {
+    // One per line, then the total number of rows
+    starts: Vec<usize>,
+}
+
+impl Default for RowIndex {
+    fn default() -> Self {
+        RowIndex { starts: vec![0, 1] }
+    }
+}
+
+impl RowIndex {
+    pub fn new(fontstack: &FontStack, text: &str, settings: &LayoutSettings) -> RowIndex {
+        let lines = line_starts(text);
+        if settings.wrap_width.is_none() {
+            return RowIndex { starts: (0..=lines.len()).collect() };
+        }
+        let mut starts = vec![0];
+        for (idx, line) in text.split('\n').enumerate() {
+            starts.push(starts[idx] + line_rows(fontstack, line, settings));
+        }
+        RowIndex { starts }
+    }
+
+    // Without rows for the lines hidden in `folds`, as with Decorations::folds
+    pub fn folded(mut self, folds: &[Range<usize>]) -> RowIndex {
+        let mut row = 0;
+        for line in 0..self.starts.len() - 1 {
+            let rows = self.starts[line + 1] - self.starts[line];
+            self.starts[line] = row;
+            if !folds.iter().any(|fold| fold.start < line && line < fold.end) {
+                row += rows;
+            }
+        }
+        *self.starts.last_mut().unwrap() = row;
+        self
+    }
+
+    // With the rows taken by the blocks below lines, as with Decorations::blocks. Blocks take whole
+    // rows, so a block part of a row high should be made a whole number of rows high for the layout
+    // to agree
+    pub fn with_blocks(mut self, blocks: &[Block], line_height: f32) -> RowIndex {
+        let mut added = 0;
+        for line in 0..self.starts.len() - 1 {
+            self.starts[line] += added;
+            added += blocks.iter().filter(|block| block.after_line == line).map(|block| (block.height / line_height).ceil() as usize).sum::<usize>();
+        }
+        *self.starts.last_mut().unwrap() += added;
+        self
+    }
+
+    // Finds the rows of the lines `edit` left in `text` again, keeping those of the rest. False if
+    // the edit doesn't fit the lines this was made for, which then have to be found again with new
+    pub fn edit(&mut self, fontstack: &FontStack, text: &str, settings: &LayoutSettings, edit: &LineEdit) -> bool {
+        if edit.lines.start >= edit.lines.end || edit.lines.end >= self.starts.len() {
+            return false;
+        }
+        let mut row = self.starts[edit.lines.start];
+        let mut starts = Vec::with_capacity(edit.new_lines);
+        for line in text.split('\n').skip(edit.lines.start).take(edit.new_lines) {
+            row += if settings.wrap_width.is_some() { line_rows(fontstack, line, settings) } else { 1 };
+            starts.push(row);
+        }
+        if starts.len() != edit.new_lines {
+            return false;
+        }
+        let old_end = self.starts[edit.lines.end];
+        self.starts.splice(edit.lines.start + 1..edit.lines.end + 1, starts);
+        for start in &mut self.starts[edit.lines.start + edit.new_lines + 1..] {
+            *start = *start + row - old_end;
+        }
+        true
+    }
+
+    pub fn rows(&self) -> usize {
+        self.starts[self.starts.len() - 1]
+    }
+
+    pub fn row_of_line(&self, line: usize) -> usize {
+        self.starts[line.min(self.starts.len() - 1)]
+    }
+
+    // The line shown on `row`, the last one for rows past the end
+    pub fn line_of_row(&self, row: usize) -> usize {
+        self.starts.partition_point(|&start| start <= row).saturating_sub(1).min(self.starts.len() - 2)
+    }
+
+    // The lines with any of their rows in `rows`
+    pub fn lines(&self, rows: Range<usize>) -> Range<usize> {
+        if rows.is_empty() {
+            let line = self.line_of_row(rows.start);
+            return line..line;
+        }
+        self.line_of_row(rows.start)..self.line_of_row(rows.end - 1) + 1
+    }
+}
+
+// Rows `line`, without its newline, takes when laid out on its own
+fn line_rows(fontstack: &FontStack, line: &str, settings: &LayoutSettings) -> usize {
+    layout(fontstack, line, settings).lines.len().max(1)
+}
+
+pub fn layout_decorated<'a>(fontstack: &'a FontStack, text: &str, settings: &LayoutSettings, decorations: Decorations) -> Layout<'a> {
+    let metrics = fontstack.vertical_metrics();
+    let ascent = metrics.ascent * settings.font_size;
+    let descent = metrics.descent * settings.font_size;
+    let line_height = line_height(fontstack, settings);
+    // Split the leading evenly above and below the glyphs
+    let baseline_offset = (line_height - ascent - descent) / 2. + ascent;
+    // Glyphs that no face could shape still take up some room, so the cursor can move over them
+    let tofu_advance = settings.font_size / 2.;
+    let marker_width: f32 = match settings.wrap_width {
+        Some(_) => fontstack.shape(WRAP_MARKER).iter().map(|(shaped, _)| shaped.as_ref().map_or(tofu_advance, |shaped| shaped.at.x_advance as f32 * px_per_unit(shaped, settings.font_size))).sum(),
+        None => 0.,
+    };
+
+    let mut glyphs = Vec::new();
+    let mut lines: Vec<Line> = Vec::new();
+    let mut blocks = Vec::new();
+    let mut y = 0.;
+
+    let mut line_start = 0;
+    for (logical_line, line_text) in text.split('\n').enumerate() {
+        let line_range = line_start..line_start + line_text.len();
+        line_start += line_text.len() + 1;
+
+        if decorations.folds.iter().any(|fold| fold.start < logical_line && logical_line < fold.end) {
+            if let Some(header) = lines.last_mut() {
+                let hidden_start = header.byte_range.end;
+                header.folded = Some(hidden_start..line_range.end);
+                continue;
+            }
+        }
+
+        let first_glyph = glyphs.len();
+        let mut x = 0.;
+        let mut push_glyphs = |piece: &str, piece_offset: usize, is_virtual: bool| {
+            for (shaped, range) in fontstack.shape(piece) {
+                let (advance, x_offset, y_offset) = match &shaped {
+                    Some(shaped) => {
+                        let scale = px_per_unit(shaped, settings.font_size);
+                        (shaped.at.x_advance as f32 * scale, shaped.at.x_offset as f32 * scale, shaped.at.y_offset as f32 * scale)
+                    }
+                    None => (tofu_advance, 0., 0.),
+                };
+                let advance = advance + settings.letter_spacing;
+                glyphs.push(PositionedGlyph {
+                    shaped,
+                    byte_range: if is_virtual {
+                        piece_offset..piece_offset
+                    } else {
+                        piece_offset + range.start..piece_offset + range.end
+                    },
+                    is_virtual,
+                    // Both set below, once it's known which row the glyph goes on
+                    line: 0,
+                    x,
+                    y: 0.,
+                    offset: (x_offset, -y_offset),
+                    advance,
+                });
+                x += advance;
+            }
+        };
+
+        // Shape the text between virtual texts separately, so the virtual text can go in between
+        let mut piece_start = line_range.start;
+        for virtual_text in decorations.virtual_text.iter().filter(|vt| line_range.contains(&vt.at) || vt.at == line_range.end) {
+            push_glyphs(&text[piece_start..virtual_text.at], piece_start, false);
+            push_glyphs(&virtual_text.text, virtual_text.at, true);
+            piece_start = virtual_text.at;
+        }
+        push_glyphs(&text[piece_start..line_range.end], piece_start, false);
+
+        let breaks = match settings.wrap_width {
+            Some(wrap_width) => wrap_points(&glyphs[first_glyph..], text, wrap_width, marker_width),
+            None => Vec::new(),
+        };
+        let row_bounds: Vec<usize> = std::iter::once(0).chain(breaks).chain(std::iter::once(glyphs.len() - first_glyph)).map(|idx| first_glyph + idx).collect();
+        for (row, bounds) in row_bounds.windows(2).enumerate() {
+            let (start, end) = (bounds[0], bounds[1]);
+            let indent = if row > 0 { marker_width } else { 0. };
+            let shift = if row > 0 { glyphs[start].x - indent } else { 0. };
+            let top = y;
+            let baseline = top + baseline_offset;
+            for glyph in &mut glyphs[start..end] {
+                glyph.x -= shift;
+                glyph.y = baseline;
+                glyph.line = lines.len();
+            }
+            let last_row = end == row_bounds[row_bounds.len() - 1];
+            lines.push(Line {
+                logical_line,
+                continuation: row > 0,
+                byte_range: if row > 0 { glyphs[start].byte_range.start } else { line_range.start }..if last_row { line_range.end } else { glyphs[end].byte_range.start },
+                folded: None,
+                glyph_range: start..end,
+                top,
+                baseline,
+                width: glyphs[start..end].last().map_or(indent, |glyph| glyph.x + glyph.advance),
+            });
+            y += line_height;
+        }
+        for (idx, block) in decorations.blocks.iter().enumerate().filter(|(_, block)| block.after_line == logical_line) {
+            blocks.push((idx, Rect { x: 0., y, w: block.width, h: block.height }));
+            y += block.height;
+        }
+    }
+
+    Layout { glyphs, lines, line_height, blocks }
+}
+
+// Where the rows of a line after the first start, as indices into its glyphs. Rows break after the
+// last space that fits, or mid-word when a word is wider than a row. Spaces may hang past the edge
+fn wrap_points(glyphs: &[PositionedGlyph], text: &str, wrap_width: f32, marker_width: f32) -> Vec<usize> {
+    let is_space = |glyph: &PositionedGlyph| !glyph.is_virtual && !glyph.byte_range.is_empty() && text[glyph.byte_range.clone()].chars().all(char::is_whitespace);
+    let mut breaks = Vec::new();
+    let mut row_start = 0;
+    let mut after_space = None;
+    for (idx, glyph) in glyphs.iter().enumerate() {
+        let origin = glyphs[row_start].x - if breaks.is_empty() { 0. } else { marker_width };
+        if idx > row_start && !is_space(glyph) && glyph.x + glyph.advance - origin > wrap_width {
+            row_start = after_space.filter(|&at| at > row_start).unwrap_or(idx);
+            breaks.push(row_start);
+            after_space = None;
+        }
+        if is_space(glyph) {
+            after_space = Some(idx + 1);
+        }
+    }
+    breaks
+}
+
+impl<'a> Layout<'a> {
+    // Whether the row continues on the next one, which then starts where this one ends
+    fn wraps(&self, idx: usize) -> bool {
+        self.lines.get(idx + 1).is_some_and(|next| next.continuation)
+    }
+
+    fn line_of_byte(&self, byte: usize) -> usize {
+        (0..self.lines.len())
+            .position(|idx| {
+                let line = &self.lines[idx];
+                let end = line.folded.as_ref().map_or(line.byte_range.end, |hidden| hidden.end);
+                byte < end || byte == end && !self.wraps(idx)
+            })
+            .unwrap_or(self.lines.len() - 1)
+    }
+
+    // The row `byte` is shown on, which with soft wrap isn't its line in the text
+    pub fn visual_row(&self, byte: usize) -> usize {
+        self.line_of_byte(byte)
+    }
+
+    // Moves `byte` up or down by rows as shown, keeping it at the same x like j and k keep the
+    // column, for gj and gk
+    pub fn move_rows(&self, byte: usize, rows: isize) -> usize {
+        self.move_rows_to(byte, rows, self.caret_x_of(byte))
+    }
+
+    // Like move_rows, to the byte nearest `x` instead of the x of `byte`. Moving several times
+    // with the x of the first keeps the column through rows too short for it
+    pub fn move_rows_to(&self, byte: usize, rows: isize, x: f32) -> usize {
+        let row = self.line_of_byte(byte);
+        self.hit_row(row.saturating_add_signed(rows).min(self.lines.len() - 1), x)
+    }
+
+    pub fn caret_x_of(&self, byte: usize) -> f32 {
+        self.caret_x(&self.lines[self.line_of_byte(byte)], byte)
+    }
+
+    // x coordinate of the caret placed before `byte`
+    fn caret_x(&self, line: &Line, byte: usize) -> f32 {
+        for glyph in &self.glyphs[line.glyph_range.clone()] {
+            // The caret goes before virtual text shown at its position
+            if glyph.byte_range.end > byte || glyph.is_virtual && glyph.byte_range.start == byte {
+                return glyph.x;
+            }
+        }
+        line.width
+    }
+
+    pub fn cursor_rect(&self, byte: usize, width: f32) -> Rect {
+        let line = &self.lines[self.line_of_byte(byte)];
+        Rect {
+            x: self.caret_x(line, byte),
+            y: line.top,
+            w: width,
+            h: self.line_height,
+        }
+    }
+
+    // One rectangle per line touched by the selection
+    pub fn selection_rects(&self, range: Range<usize>) -> Vec<Rect> {
+        let first = self.line_of_byte(range.start);
+        let last = self.line_of_byte(range.end);
+        (first..=last)
+            .map(|idx| {
+                let line = &self.lines[idx];
+                let start = range.start.max(line.byte_range.start);
+                let x0 = self.caret_x(line, start);
+                let x1 = if range.end > line.byte_range.end {
+                    line.width
+                } else {
+                    self.caret_x(line, range.end)
+                };
+                Rect { x: x0, y: line.top, w: x1 - x0, h: self.line_height }
+            })
+            .collect()
+    }
+
+    // Where to draw the head of a selection, given its cell from Selection::head_cell. An empty cell
+    // at the end of the text is drawn `width` wide
+    pub fn head_rect(&self, cell: Range<usize>, width: f32) -> Rect {
+        if cell.is_empty() {
+            return self.cursor_rect(cell.start, width);
+        }
+        self.selection_rects(cell)[0]
+    }
+
+    // The row at `y`, or above it if `y` is in a block
+    fn row_at(&self, y: f32) -> usize {
+        self.lines.partition_point(|line| line.top <= y).saturating_sub(1)
+    }
+
+    // The glyph covering (x, y), if any
+    pub fn glyph_at(&self, x: f32, y: f32) -> Option<&PositionedGlyph<'a>> {
+        let line = &self.lines[self.row_at(y)];
+        if y < 0. || y >= line.top + self.line_height {
+            return None;
+        }
+        self.glyphs[line.glyph_range.clone()]
+            .iter()
+            .find(|glyph| glyph.x <= x && x < glyph.x + glyph.advance)
+    }
+
+    // Byte offset of the caret position closest to (x, y)
+    pub fn hit_test(&self, x: f32, y: f32) -> usize {
+        self.hit_row(self.row_at(y), x)
+    }
+
+    fn hit_row(&self, idx: usize, x: f32) -> usize {
+        let line = &self.lines[idx];
+        let glyphs = &self.glyphs[line.glyph_range.clone()];
+        for glyph in glyphs {
+            if x < glyph.x + glyph.advance / 2. {
+                return glyph.byte_range.start;
+            }
+        }
+        // The end of a wrapped row is the start of the next, so stay before its last glyph instead
+        match glyphs.last().filter(|_| self.wraps(idx)) {
+            Some(last) => last.byte_range.start,
+            None => line.byte_range.end,
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const FONT_SIZE: f32 = 32.;
+
+    const CORPUS: &[(&str, &[&str])] = &[
+        ("resources/firacode-regular.ttf", &[
+            "hello world",
+            "fn main() -> Result<(), Error> { x => y != z }",
+            "<!-- www === ::: |> <| -->",
+            "e\u{301} a\u{308} n\u{303}\u{323}",
+            "AVAWAYToTaLT",
+        ]),
+        ("resources/linja-pona-4.1.otf", &[
+            "toki pona",
+            "mi olin e sina",
+            "jan [_sitelen_olin_nasin_ante] li pona",
+        ]),
+    ];
+
+    fn load(path: &str) -> FontStack {
+        FontStack::new(&Path::new(env!("CARGO_MANIFEST_DIR")).join(path)).unwrap()
+    }
+
+    // Advances in pixels as harfbuzz computes them when shaping with the face directly
+    fn reference_advances(fontstack: &FontStack, text: &str) -> Vec<(u32, f32)> {
+        let face = &fontstack.faces[0];
+        let scale = FONT_SIZE / face.ttf_face.units_per_em() as f32;
+        let buffer = harfbuzz_rs::UnicodeBuffer::new().add_str(text);
+        let glyphbuf = harfbuzz_rs::shape(face.hb_font(), buffer, &[]);
+        glyphbuf
+            .get_glyph_infos()
+            .iter()
+            .zip(glyphbuf.get_glyph_positions())
+            .map(|(info, pos)| (info.codepoint, pos.x_advance as f32 * scale))
+            .collect()
+    }
+
+    fn settings() -> LayoutSettings {
+        LayoutSettings { font_size: FONT_SIZE, ..Default::default() }
+    }
+
+    #[test]
+    fn advances_match_harfbuzz() {
+        for (path, texts) in CORPUS {
+            let fontstack = load(path);
+            for text in *texts {
+                let reference = reference_advances(&fontstack, text);
+                let layout = layout(&fontstack, text, &settings());
+
+                assert_eq!(layout.glyphs.len(), reference.len(), "glyph count for {text:?} in {path}");
+                let mut x = 0.;
+                for (glyph, (codepoint, advance)) in layout.glyphs.iter().zip(&reference) {
+                    if *codepoint != 0 {
+                        let shaped = glyph.shaped.as_ref().expect("harfbuzz found a glyph");
+                        assert_eq!(shaped.glyph as u32, *codepoint, "glyph id for {text:?} in {path}");
+                        assert!((glyph.advance - advance).abs() < 1e-3, "advance for {text:?} in {path}");
+                    }
+                    assert!((glyph.x - x).abs() < 1e-3, "pen position for {text:?} in {path}");
+                    x += glyph.advance;
+                }
+                assert!((layout.lines[0].width - x).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn ligatures_become_single_glyphs() {
+        let fontstack = load("resources/linja-pona-4.1.otf");
+        let layout = layout(&fontstack, "toki", &settings());
+        assert_eq!(layout.glyphs.len(), 1);
+        assert_eq!(layout.glyphs[0].byte_range, 0..4);
+    }
+
+    #[test]
+    fn marks_stay_in_their_cluster() {
+        let fontstack = load("resources/firacode-regular.ttf");
+        let text = "e\u{301}x";
+        let layout = layout(&fontstack, text, &settings());
+        // Whether the mark is composed with its base or positioned on its own, it must not
+        // split the cluster, so the cursor can never land between the base and the mark
+        let base_cluster = "e\u{301}".len();
+        assert!(layout.glyphs.iter().all(|g| g.byte_range.start == 0 || g.byte_range.start >= base_cluster));
+        assert_eq!(layout.hit_test(layout.lines[0].width, 0.), text.len());
+    }
+
+    #[test]
+    fn folded_lines_are_hidden() {
+        let fontstack = load("resources/firacode-regular.ttf");
+        let text = "a {\n  b\n  c\n}";
+        let fold = 0..3;
+        let decorations = Decorations { folds: std::slice::from_ref(&fold), ..Default::default() };
+        let layout = layout_decorated(&fontstack, text, &settings(), decorations);
+        assert_eq!(layout.lines.len(), 2);
+        assert_eq!(layout.lines[0].folded, Some(3..text.find('}').unwrap() - 1));
+        assert_eq!(layout.lines[1].logical_line, 3);
+        assert_eq!(layout.lines[1].top, layout.line_height);
+        // Positions inside the fold put the cursor at the end of the header line
+        assert_eq!(layout.cursor_rect(6, 1.).y, 0.);
+    }
+
+    #[test]
+    fn virtual_text_does_not_take_bytes() {
+        let fontstack = load("resources/firacode-regular.ttf");
+        let text = "let x = 1;";
+        let hint = [VirtualText { at: 5, text: ": i32".to_string() }];
+        let decorations = Decorations { virtual_text: &hint, ..Default::default() };
+        let plain = layout(&fontstack, text, &settings());
+        let hinted = layout_decorated(&fontstack, text, &settings(), decorations);
+
+        assert_eq!(hinted.glyphs.iter().filter(|g| g.is_virtual).count(), 5);
+        assert!(hinted.glyphs.iter().filter(|g| g.is_virtual).all(|g| g.byte_range == (5..5)));
+        // The caret at the hint's position stays before it, but everything after moves right
+        assert_eq!(hinted.cursor_rect(5, 1.).x, plain.cursor_rect(5, 1.).x);
+        assert!(hinted.cursor_rect(6, 1.).x > plain.cursor_rect(6, 1.).x);
+        assert_eq!(hinted.hit_test(hinted.lines[0].width, 0.), text.len());
+    }
+
+    #[test]
+    fn blocks_push_lines_down() {
+        let fontstack = load("resources/firacode-regular.ttf");
+        let text = "a\nb\nc";
+        let blocks = [Block { after_line: 0, width: 50., height: 100. }];
+        let decorations = Decorations { blocks: &blocks, ..Default::default() };
+        let layout = layout_decorated(&fontstack, text, &settings(), decorations);
+        let line_height = layout.line_height;
+        assert_eq!(layout.blocks, vec![(0, Rect { x: 0., y: line_height, w: 50., h: 100. })]);
+        assert_eq!(layout.lines[1].top, line_height + 100.);
+        assert!((layout.lines[2].top - (2. * line_height + 100.)).abs() < 1e-3);
+
+        assert!(layout.glyph_at(1., line_height + 50.).is_none());
+        assert_eq!(layout.glyph_at(1., line_height + 101.).unwrap().byte_range, 2..3);
+        assert_eq!(layout.hit_test(0., 2. * line_height + 101.), 4);
+    }
+
+    #[test]
+    fn long_lines_wrap_at_spaces() {
+        let fontstack = load("resources/firacode-regular.ttf");
+        let text = "aaaa bbbb cccccccccccc\nd";
+        let advance = layout(&fontstack, "a", &settings()).lines[0].width;
+        let wrapped = layout(&fontstack, text, &LayoutSettings { wrap_width: Some(advance * 10.5), ..settings() });
+        let rows: Vec<(usize, &str, bool)> = wrapped.lines.iter().map(|line| (line.logical_line, &text[line.byte_range.clone()], line.continuation)).collect();
+        // The marker takes about two cells of the continuation rows, so the long word is cut after 8
+        assert_eq!(rows, vec![(0, "aaaa bbbb ", false), (0, "cccccccc", true), (0, "cccc", true), (1, "d", false)]);
+        assert!((wrapped.lines[1].top - wrapped.line_height).abs() < 1e-3);
+        assert!((wrapped.glyphs[wrapped.lines[1].glyph_range.start].x - layout(&fontstack, WRAP_MARKER, &settings()).lines[0].width).abs() < 1e-3);
+
+        // The start of a continuation row is shown on it, not after the end of the row above
+        assert_eq!(wrapped.visual_row(10), 1);
+        assert_eq!(wrapped.cursor_rect(10, 1.).y, wrapped.lines[1].top);
+        assert_eq!(wrapped.visual_row(text.find('\n').unwrap()), 2);
+        assert_eq!(wrapped.hit_test(1000., wrapped.lines[1].top), 17);
+
+        // gj from the second a goes to the row below, where j would go to the next line of the text
+        assert_eq!(wrapped.move_rows(1, 1), 10);
+        assert_eq!(wrapped.move_rows(13, 1), 21);
+        assert_eq!(wrapped.move_rows(13, -1), 5);
+        assert_eq!(wrapped.move_rows(0, 10), text.len() - 1);
+        assert_eq!(layout(&fontstack, text, &settings()).lines.len(), 2);
+    }
+
+    #[test]
+    fn rows_of_wrapped_lines() {
+        let fontstack = load("resources/firacode-regular.ttf");
+        let text = "aaaa bbbb cccccccccccc\nd\n";
+        let unwrapped = RowIndex::new(&fontstack, text, &settings());
+        assert_eq!((unwrapped.rows(), unwrapped.row_of_line(1), unwrapped.lines(1..2)), (3, 1, 1..2));
+
+        let advance = layout(&fontstack, "a", &settings()).lines[0].width;
+        let rows = RowIndex::new(&fontstack, text, &LayoutSettings { wrap_width: Some(advance * 10.5), ..settings() });
+        // The first line takes three rows, as in long_lines_wrap_at_spaces
+        assert_eq!(rows.rows(), 5);
+        assert_eq!((rows.row_of_line(1), rows.row_of_line(2)), (3, 4));
+        assert_eq!((rows.line_of_row(2), rows.line_of_row(3), rows.line_of_row(100)), (0, 1, 2));
+        assert_eq!(rows.lines(1..4), 0..2);
+        assert_eq!(rows.lines(3..3), 1..1);
+
+        // Lines after the first of a fold take no rows
+        let folded = RowIndex::new(&fontstack, "a\nb\nc\nd", &settings()).folded(&[0..3]);
+        assert_eq!((folded.rows(), folded.row_of_line(3), folded.line_of_row(1)), (2, 1, 3));
+
+        // A block a row and a half high takes two rows, which belong to the line above it
+        let blocks = [Block { after_line: 0, width: 10., height: 15. }];
+        let with_blocks = RowIndex::new(&fontstack, "a\nb\nc", &settings()).with_blocks(&blocks, 10.);
+        assert_eq!((with_blocks.rows(), with_blocks.row_of_line(1), with_blocks.line_of_row(2)), (5, 3, 0));
+    }
+
+    #[test]
+    fn rows_of_edited_lines() {
+        let fontstack = load("resources/firacode-regular.ttf");
+        let advance = layout(&fontstack, "a", &settings()).lines[0].width;
+        let settings = LayoutSettings { wrap_width: Some(advance * 10.5), ..settings() };
+        let text = "a\nb\nc\nd";
+        let mut rows = RowIndex::new(&fontstack, text, &settings);
+
+        // The second line grows to wrap and splits in two, then the last two lines are joined
+        let first = LineEdit { lines: 1..2, new_lines: 2 };
+        let second = LineEdit { lines: 3..5, new_lines: 1 };
+        let edited = "a\nbbbb bbbb bbbb\nb\ncd";
+        assert_eq!(first.then(&second), LineEdit { lines: 1..4, new_lines: 3 });
+        assert!(rows.edit(&fontstack, edited, &settings, &first.then(&second)));
+        assert_eq!(rows, RowIndex::new(&fontstack, edited, &settings));
+        assert_eq!(rows.rows(), 5);
+        assert!(!rows.edit(&fontstack, edited, &settings, &LineEdit { lines: 3..9, new_lines: 1 }));
+    }
+
+    #[test]
+    fn letter_spacing_is_added_per_glyph() {
+        let fontstack = load("resources/firacode-regular.ttf");
+        let plain = layout(&fontstack, "abcd", &settings());
+        let spaced = layout(&fontstack, "abcd", &LayoutSettings { letter_spacing: 2., ..settings() });
+        assert!((spaced.lines[0].width - plain.lines[0].width - 8.).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,91 @@
+// The editor core and text rendering stack, usable on their own from other winit/wgpu applications.
+// The rakoune binary in main.rs is built on top of this
+
+pub mod accessibility;
+pub mod ansi;
+pub mod app;
+pub mod appearance;
+pub mod associations;
+pub mod atlas;
+pub mod autosave;
+pub mod backend;
+pub mod background;
+pub mod blame;
+pub mod bracket_tree;
+pub mod brackets;
+pub mod clipboard;
+pub mod color;
+pub mod commands;
+pub mod completion;
+pub mod config;
+pub mod confirm;
+pub mod crash;
+pub mod dap;
+pub mod diagnostics;
+pub mod digraphs;
+pub mod dired;
+pub mod editor;
+pub mod error;
+pub mod export;
+pub mod file_preview;
+pub mod filetree;
+pub mod folding;
+pub mod font;
+pub mod format;
+pub mod gpu;
+pub mod gpu_raster;
+pub mod gpu_timing;
+pub mod grammar;
+pub mod highlighter;
+pub mod hover;
+pub mod images;
+pub mod insert;
+pub mod jobs;
+pub mod keymap;
+pub mod keyrepeat;
+pub mod layout;
+pub mod links;
+pub mod lsp;
+pub mod maintenance;
+pub mod markdown;
+pub mod marks;
+pub mod memory;
+pub mod menubar;
+pub mod normal;
+pub mod notifications;
+pub mod palette;
+pub mod panes;
+pub mod paste;
+pub mod picker;
+pub mod progress;
+pub mod project;
+pub mod prompt;
+pub mod refactor;
+pub mod render;
+pub mod richtext;
+pub mod scrollbar;
+pub mod search;
+pub mod selection;
+pub mod semantic;
+pub mod server;
+pub mod session;
+pub mod shapes;
+pub mod splash;
+pub mod statusline;
+pub mod sticky;
+pub mod substitute;
+pub mod symbols;
+pub mod syntax;
+pub mod tabs;
+pub mod terminal;
+#[cfg(test)]
+mod test_helpers;
+pub mod text_renderer;
+pub mod tooltip;
+pub mod touch;
+pub mod undo;
+pub mod viewport;
+pub mod welcome;
+pub mod whichkey;
+pub mod workspace_edit;
+pub mod zen;
@@ -0,0 +1,105 @@
+use std::ops::Range;
+
+use crate::layout::{Layout, Rect};
+
+const SCHEMES: &[&str] = &["https://", "http://", "file://", "mailto:"];
+
+// Characters that commonly end a sentence rather than a URL
+const TRAILING_PUNCTUATION: &[char] = &['.', ',', ':', ';', '!', '?', '\'', '"', ')', ']', '}', '>'];
+
+// Byte ranges of everything in `text` that looks like a URL
+pub fn find_urls(text: &str) -> Vec<Range<usize>> {
+    let mut urls = Vec::new();
+    let mut search_from = 0;
+    while search_from < text.len() {
+        let next = SCHEMES
+            .iter()
+            .filter_map(|scheme| text[search_from..].find(scheme).map(|at| (search_from + at, scheme.len())))
+            .min();
+        let Some((start, scheme_len)) = next else { break };
+
+        let rest = &text[start..];
+        let mut end = start + rest.find(|c: char| c.is_whitespace() || c.is_control()).unwrap_or(rest.len());
+        while let Some(c) = text[start..end].chars().last() {
+            // Keep closing parens that have a matching open paren inside the URL, like in wikipedia links
+            let balanced = c == ')' && text[start..end].matches('(').count() >= text[start..end].matches(')').count();
+            if TRAILING_PUNCTUATION.contains(&c) && !balanced {
+                end -= c.len_utf8();
+            } else {
+                break;
+            }
+        }
+
+        if end > start + scheme_len {
+            urls.push(start..end);
+        }
+        search_from = end.max(start + scheme_len);
+    }
+    urls
+}
+
+// The URL under the point (x, y) in a layout of `text`
+pub fn url_at(layout: &Layout, text: &str, x: f32, y: f32) -> Option<Range<usize>> {
+    let glyph = layout.glyph_at(x, y)?;
+    find_urls(text).into_iter().find(|url| url.contains(&glyph.byte_range.start))
+}
+
+// Rectangles along the baseline to draw as the underline for a URL
+pub fn underline_rects(layout: &Layout, url: Range<usize>, thickness: f32) -> Vec<Rect> {
+    layout
+        .selection_rects(url)
+        .into_iter()
+        .map(|rect| {
+            let line = layout.lines.iter().find(|line| line.top == rect.y).expect("selection rects are aligned to lines");
+            Rect { x: rect.x, y: line.baseline + thickness, w: rect.w, h: thickness }
+        })
+        .collect()
+}
+
+// Opens the URL using the platform's default handler
+pub fn open_url(url: &str) -> std::io::Result<()> {
+    opener(url).spawn()?;
+    Ok(())
+}
+
+// The URL goes to the handler as one argument, never through a shell. URLs keep characters like
+// '&' and '|', which cmd would take as the start of another command
+fn opener(url: &str) -> std::process::Command {
+    let program = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(target_os = "windows") {
+        "explorer.exe"
+    } else {
+        "xdg-open"
+    };
+    let mut command = std::process::Command::new(program);
+    command.arg(url);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(text: &str) -> Vec<&str> {
+        find_urls(text).into_iter().map(|r| &text[r]).collect()
+    }
+
+    #[test]
+    fn finds_urls() {
+        assert_eq!(urls("see https://example.com/a?b=c, or http://x.org."), vec!["https://example.com/a?b=c", "http://x.org"]);
+        assert_eq!(urls("(https://en.wikipedia.org/wiki/Rust_(programming_language))"), vec!["https://en.wikipedia.org/wiki/Rust_(programming_language)"]);
+        assert_eq!(urls("mailto:me@example.com"), vec!["mailto:me@example.com"]);
+        assert!(urls("https:// nothing here").is_empty());
+    }
+
+    #[test]
+    fn urls_never_reach_a_shell() {
+        let url = urls("http://x.org/?a=1&calc|x^y")[0];
+        assert_eq!(url, "http://x.org/?a=1&calc|x^y");
+        let command = opener(url);
+        assert!(!["cmd", "sh"].contains(&command.get_program().to_str().unwrap()));
+        let args: Vec<&std::ffi::OsStr> = command.get_args().collect();
+        assert_eq!(args, vec![url]);
+    }
+}
@@ -0,0 +1,729 @@
+// Language servers, through the Language Server Protocol. The command a grammar gives as its
+// language_server is started for the first file of that language, in the project root, and told
+// about every open file of it, with the whole text each time it changes. Messages are framed like
+// the debug adapter's, see dap.rs
+//
+// Server remembers what each request was for until its response comes back, answers the few
+// requests servers send on their own, and keeps the semantic tokens of each file up to date,
+// asking for deltas where the server can send them. The functions after it turn results into the
+// types the rest of rakoune uses
+
+use std::collections::{HashMap, HashSet};
+use std::io::{BufReader, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::dap;
+use crate::diagnostics::{severity_from_lsp, Diagnostic};
+use crate::hover::{Signature, SignatureHelp};
+use crate::notifications::Severity;
+use crate::refactor::{self, CodeAction};
+use crate::selection::line_starts;
+use crate::semantic::{utf16_to_byte, Legend, SemanticTokens, TokensEdit};
+use crate::symbols::{Symbol, SymbolKind};
+use crate::workspace_edit::{byte_offset, Position, TextEdit, WorkspaceEdit};
+
+// The token types and modifiers of the specification, which servers may limit their legend to
+const TOKEN_TYPES: [&str; 23] = [
+    "namespace", "type", "class", "enum", "interface", "struct", "typeParameter", "parameter", "variable", "property", "enumMember", "event", "function", "method", "macro", "keyword",
+    "modifier", "comment", "string", "number", "regexp", "operator", "decorator",
+];
+const TOKEN_MODIFIERS: [&str; 10] = ["declaration", "definition", "readonly", "static", "deprecated", "abstract", "async", "modification", "documentation", "defaultLibrary"];
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't start {0}: {1}")]
+    Spawn(String, std::io::Error),
+    #[error("Couldn't write to {0}: {1}")]
+    Write(String, std::io::Error),
+    #[error("Bad message from {0}: {1}")]
+    BadMessage(String, String),
+    #[error("The language server {0} exited")]
+    Exited(String),
+}
+
+// What a request was for, handed back with its response. Paths are absolute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Initialize,
+    // With the byte of the cursor it was asked at
+    Hover(PathBuf, usize),
+    SignatureHelp(PathBuf, usize),
+    Formatting(PathBuf),
+    Rename,
+    CodeActions,
+    ExecuteCommand,
+    DocumentSymbols(PathBuf),
+    WorkspaceSymbols,
+    SemanticTokens(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    // A result as it came, for the functions below
+    Response(Request, Value),
+    // An error response, with its message
+    Failed(Request, String),
+    // The diagnostics array of publishDiagnostics
+    Diagnostics(PathBuf, Value),
+    SemanticTokens(PathBuf, Legend, SemanticTokens),
+    // window/showMessage
+    Message(Severity, String),
+    // workspace/applyEdit, which has already been answered as applied
+    ApplyEdit(WorkspaceEdit),
+    // $/progress, with the token the server named the work with
+    Progress(Value, WorkDone),
+}
+
+// How far along the server is with some work, like indexing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WorkDone {
+    Begin(String),
+    // Percent done, if the server says
+    Report(Option<u64>),
+    End,
+}
+
+// The server process
+pub struct Server {
+    // What it was started with, for messages and the status line
+    pub command: String,
+    child: Child,
+    stdin: ChildStdin,
+    next_id: u64,
+    messages: mpsc::Receiver<Result<Value, dap::Error>>,
+    // What each request waiting for a response is for, by id
+    pending: HashMap<u64, Request>,
+    // Held back until initialize is answered, as servers want nothing else before
+    queued: Vec<Value>,
+    // From the initialize response
+    capabilities: Option<Value>,
+    // Open documents, with the version of the text the server has
+    pub documents: HashMap<PathBuf, u64>,
+    // The last tokens of each document, which deltas are against
+    tokens: HashMap<PathBuf, SemanticTokens>,
+    // Documents that changed while their tokens were being asked for, to ask again once they come
+    outdated_tokens: HashSet<PathBuf>,
+}
+
+impl Server {
+    // `command` is run through the shell in `root`, the folder it's told is the workspace
+    pub fn start(command: &str, root: &Path) -> Result<Server, Error> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| Error::Spawn(command.to_string(), e))?;
+        let stdin = child.stdin.take().unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let (tx, messages) = mpsc::channel();
+        std::thread::spawn(move || {
+            while let Some(message) = dap::read_message(&mut stdout).transpose() {
+                let failed = message.is_err();
+                if tx.send(message).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let mut server = Server {
+            command: command.to_string(),
+            child,
+            stdin,
+            next_id: 0,
+            messages,
+            pending: HashMap::new(),
+            queued: Vec::new(),
+            capabilities: None,
+            documents: HashMap::new(),
+            tokens: HashMap::new(),
+            outdated_tokens: HashSet::new(),
+        };
+        let name = root.file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+        let params = json!({
+            "processId": std::process::id(),
+            "rootUri": uri(root),
+            "workspaceFolders": [{ "uri": uri(root), "name": name }],
+            "capabilities": client_capabilities(),
+        });
+        server.next_id += 1;
+        server.pending.insert(server.next_id, Request::Initialize);
+        server.write_now(&json!({ "jsonrpc": "2.0", "id": server.next_id, "method": "initialize", "params": params }))?;
+        Ok(server)
+    }
+
+    fn write_now(&mut self, message: &Value) -> Result<(), Error> {
+        self.stdin.write_all(&dap::encode(message)).and_then(|_| self.stdin.flush()).map_err(|e| Error::Write(self.command.clone(), e))
+    }
+
+    fn write(&mut self, message: Value) -> Result<(), Error> {
+        match self.capabilities {
+            Some(_) => self.write_now(&message),
+            None => {
+                self.queued.push(message);
+                Ok(())
+            }
+        }
+    }
+
+    fn request(&mut self, method: &str, params: Value, request: Request) -> Result<(), Error> {
+        self.next_id += 1;
+        self.pending.insert(self.next_id, request);
+        self.write(json!({ "jsonrpc": "2.0", "id": self.next_id, "method": method, "params": params }))
+    }
+
+    // Asks the server to stop work it reported progress on, which the user cancelled
+    pub fn cancel_progress(&mut self, token: &Value) -> Result<(), Error> {
+        self.notify("window/workDoneProgress/cancel", json!({ "token": token }))
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> Result<(), Error> {
+        self.write(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    // Whether the server said it has `capability`, like "hoverProvider". Assumed until it has said
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.as_ref().is_none_or(|capabilities| !matches!(capabilities[capability], Value::Null | Value::Bool(false)))
+    }
+
+    pub fn open(&mut self, path: &Path, language_id: &str, version: u64, text: &str) -> Result<(), Error> {
+        self.documents.insert(path.to_owned(), version);
+        self.notify("textDocument/didOpen", json!({ "textDocument": { "uri": uri(path), "languageId": language_id, "version": version, "text": text } }))?;
+        self.request_tokens(path)
+    }
+
+    pub fn change(&mut self, path: &Path, version: u64, text: &str) -> Result<(), Error> {
+        self.documents.insert(path.to_owned(), version);
+        self.notify("textDocument/didChange", json!({ "textDocument": { "uri": uri(path), "version": version }, "contentChanges": [{ "text": text }] }))?;
+        self.request_tokens(path)
+    }
+
+    pub fn close(&mut self, path: &Path) -> Result<(), Error> {
+        self.documents.remove(path);
+        self.tokens.remove(path);
+        self.notify("textDocument/didClose", json!({ "textDocument": { "uri": uri(path) } }))
+    }
+
+    pub fn hover(&mut self, path: &Path, text: &str, at: usize) -> Result<(), Error> {
+        self.request("textDocument/hover", at_position(path, text, at), Request::Hover(path.to_owned(), at))
+    }
+
+    pub fn signature_help(&mut self, path: &Path, text: &str, at: usize) -> Result<(), Error> {
+        self.request("textDocument/signatureHelp", at_position(path, text, at), Request::SignatureHelp(path.to_owned(), at))
+    }
+
+    // Indenting with four spaces, as there's no setting for it
+    pub fn formatting(&mut self, path: &Path) -> Result<(), Error> {
+        let params = json!({ "textDocument": { "uri": uri(path) }, "options": { "tabSize": 4, "insertSpaces": true } });
+        self.request("textDocument/formatting", params, Request::Formatting(path.to_owned()))
+    }
+
+    pub fn rename(&mut self, path: &Path, text: &str, at: usize, new_name: &str) -> Result<(), Error> {
+        let mut params = at_position(path, text, at);
+        params["newName"] = json!(new_name);
+        self.request("textDocument/rename", params, Request::Rename)
+    }
+
+    // For the selection `range`, with the diagnostics on it so the server can offer fixes for them
+    pub fn code_actions(&mut self, path: &Path, text: &str, range: Range<usize>, diagnostics: &[Diagnostic]) -> Result<(), Error> {
+        let diagnostics: Vec<Value> = diagnostics.iter().map(|diagnostic| diagnostic_json(text, diagnostic)).collect();
+        let params = json!({ "textDocument": { "uri": uri(path) }, "range": range_json(text, range), "context": { "diagnostics": diagnostics } });
+        self.request("textDocument/codeAction", params, Request::CodeActions)
+    }
+
+    pub fn execute_command(&mut self, command: &refactor::Command) -> Result<(), Error> {
+        self.request("workspace/executeCommand", json!({ "command": command.name, "arguments": command.arguments }), Request::ExecuteCommand)
+    }
+
+    pub fn document_symbols(&mut self, path: &Path) -> Result<(), Error> {
+        self.request("textDocument/documentSymbol", json!({ "textDocument": { "uri": uri(path) } }), Request::DocumentSymbols(path.to_owned()))
+    }
+
+    pub fn workspace_symbols(&mut self, query: &str) -> Result<(), Error> {
+        self.request("workspace/symbol", json!({ "query": query }), Request::WorkspaceSymbols)
+    }
+
+    // A delta against the last tokens when the server sends them, or else all of them. One request
+    // for a document at a time, as a delta is against the tokens before it
+    fn request_tokens(&mut self, path: &Path) -> Result<(), Error> {
+        let Some(capabilities) = &self.capabilities else { return Ok(()) };
+        let provider = &capabilities["semanticTokensProvider"];
+        if matches!(provider["full"], Value::Null | Value::Bool(false)) {
+            return Ok(());
+        }
+        if self.pending.values().any(|request| *request == Request::SemanticTokens(path.to_owned())) {
+            self.outdated_tokens.insert(path.to_owned());
+            return Ok(());
+        }
+        let previous = self.tokens.get(path).and_then(|tokens| tokens.result_id.clone()).filter(|_| provider["full"]["delta"] == json!(true));
+        let (method, params) = match previous {
+            Some(previous) => ("textDocument/semanticTokens/full/delta", json!({ "textDocument": { "uri": uri(path) }, "previousResultId": previous })),
+            None => ("textDocument/semanticTokens/full", json!({ "textDocument": { "uri": uri(path) } })),
+        };
+        self.request(method, params, Request::SemanticTokens(path.to_owned()))
+    }
+
+    // Messages that arrived since the last poll
+    pub fn poll(&mut self) -> Vec<Result<Event, Error>> {
+        let mut events = Vec::new();
+        loop {
+            match self.messages.try_recv() {
+                Ok(Ok(message)) => {
+                    if let Err(e) = self.handle(&message, &mut events) {
+                        events.push(Err(e));
+                    }
+                }
+                Ok(Err(dap::Error::BadMessage(e))) => events.push(Err(Error::BadMessage(self.command.clone(), e))),
+                Ok(Err(e)) => events.push(Err(Error::BadMessage(self.command.clone(), e.to_string()))),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    events.push(Err(Error::Exited(self.command.clone())));
+                    break;
+                }
+            }
+        }
+        events
+    }
+
+    fn handle(&mut self, message: &Value, events: &mut Vec<Result<Event, Error>>) -> Result<(), Error> {
+        match (message.get("id"), message["method"].as_str()) {
+            (Some(id), Some(method)) => self.answer(id, method, &message["params"], events),
+            (None, Some(method)) => {
+                events.extend(notification(method, &message["params"]).map(Ok));
+                Ok(())
+            }
+            (Some(id), None) => {
+                let Some(request) = id.as_u64().and_then(|id| self.pending.remove(&id)) else { return Ok(()) };
+                if let Some(error) = message.get("error") {
+                    events.push(Ok(Event::Failed(request, error["message"].as_str().unwrap_or("failed").to_string())));
+                    return Ok(());
+                }
+                let result = &message["result"];
+                match request {
+                    Request::Initialize => self.initialized(result),
+                    Request::SemanticTokens(path) => {
+                        events.extend(self.update_tokens(&path, result).transpose());
+                        match self.outdated_tokens.remove(&path) {
+                            true => self.request_tokens(&path),
+                            false => Ok(()),
+                        }
+                    }
+                    request => {
+                        events.push(Ok(Event::Response(request, result.clone())));
+                        Ok(())
+                    }
+                }
+            }
+            (None, None) => Ok(()),
+        }
+    }
+
+    // Sends what waited for initialize, and asks for the tokens of what was opened meanwhile
+    fn initialized(&mut self, result: &Value) -> Result<(), Error> {
+        self.capabilities = Some(result["capabilities"].clone());
+        self.write_now(&json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }))?;
+        for message in std::mem::take(&mut self.queued) {
+            self.write_now(&message)?;
+        }
+        let documents: Vec<PathBuf> = self.documents.keys().cloned().collect();
+        documents.iter().try_for_each(|path| self.request_tokens(path))
+    }
+
+    // Requests from the server. Edits are taken as applied once they're handed to the editor
+    fn answer(&mut self, id: &Value, method: &str, params: &Value, events: &mut Vec<Result<Event, Error>>) -> Result<(), Error> {
+        let result = match method {
+            "workspace/applyEdit" => {
+                events.push(Ok(Event::ApplyEdit(workspace_edit(&params["edit"]))));
+                json!({ "applied": true })
+            }
+            // No settings for anything
+            "workspace/configuration" => Value::Array(vec![Value::Null; params["items"].as_array().map_or(0, Vec::len)]),
+            _ => Value::Null,
+        };
+        self.write_now(&json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+    }
+
+    fn update_tokens(&mut self, path: &Path, result: &Value) -> Result<Option<Event>, Error> {
+        let Some(legend) = self.capabilities.as_ref().and_then(legend) else { return Ok(None) };
+        let result_id = result["resultId"].as_str().map(str::to_string);
+        let bad = |e: crate::semantic::Error| Error::BadMessage(self.command.clone(), e.to_string());
+        let tokens = match (result["edits"].as_array(), result["data"].as_array()) {
+            (Some(edits), _) => {
+                let edits: Vec<TokensEdit> = edits
+                    .iter()
+                    .map(|edit| TokensEdit { start: edit["start"].as_u64().unwrap_or(0) as usize, delete_count: edit["deleteCount"].as_u64().unwrap_or(0) as usize, data: numbers(&edit["data"]) })
+                    .collect();
+                let Some(tokens) = self.tokens.get_mut(path) else { return Ok(None) };
+                tokens.apply_delta(result_id, &edits).map_err(bad)?;
+                tokens.clone()
+            }
+            (None, Some(_)) => {
+                let tokens = SemanticTokens::full(result_id, numbers(&result["data"])).map_err(bad)?;
+                self.tokens.insert(path.to_owned(), tokens.clone());
+                tokens
+            }
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(Event::SemanticTokens(path.to_owned(), legend, tokens)))
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn notification(method: &str, params: &Value) -> Option<Event> {
+    match method {
+        "textDocument/publishDiagnostics" => Some(Event::Diagnostics(path_from_uri(params["uri"].as_str()?)?, params["diagnostics"].clone())),
+        // Log messages, type 4, are left out
+        "window/showMessage" => {
+            let kind = params["type"].as_u64().filter(|kind| *kind <= 3)?;
+            Some(Event::Message(severity_from_lsp(kind as u32), params["message"].as_str()?.to_string()))
+        }
+        "$/progress" => {
+            let value = &params["value"];
+            let work = match value["kind"].as_str()? {
+                "begin" => WorkDone::Begin(value["title"].as_str()?.to_string()),
+                "report" => WorkDone::Report(value["percentage"].as_u64()),
+                "end" => WorkDone::End,
+                _ => return None,
+            };
+            Some(Event::Progress(params["token"].clone(), work))
+        }
+        _ => None,
+    }
+}
+
+fn client_capabilities() -> Value {
+    json!({
+        "general": { "positionEncodings": ["utf-16"] },
+        "window": { "workDoneProgress": true },
+        "workspace": { "applyEdit": true, "workspaceEdit": { "documentChanges": true }, "symbol": {}, "executeCommand": {}, "configuration": true },
+        "textDocument": {
+            "synchronization": { "didSave": false },
+            "hover": { "contentFormat": ["markdown", "plaintext"] },
+            "signatureHelp": { "signatureInformation": { "documentationFormat": ["markdown", "plaintext"], "parameterInformation": { "labelOffsetSupport": true } } },
+            "formatting": {},
+            "rename": {},
+            "codeAction": { "codeActionLiteralSupport": { "codeActionKind": { "valueSet": ["", "quickfix", "refactor", "refactor.extract", "refactor.inline", "refactor.rewrite", "source", "source.organizeImports"] } } },
+            "documentSymbol": { "hierarchicalDocumentSymbolSupport": true },
+            "publishDiagnostics": {},
+            "semanticTokens": { "requests": { "full": { "delta": true } }, "tokenTypes": TOKEN_TYPES, "tokenModifiers": TOKEN_MODIFIERS, "formats": ["relative"] },
+        },
+    })
+}
+
+// Percent-encoded, so spaces and the like survive
+pub fn uri(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    uri
+}
+
+pub fn path_from_uri(uri: &str) -> Option<PathBuf> {
+    let mut rest = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    while let [first, tail @ ..] = rest {
+        match (first, tail) {
+            (b'%', [high, low, tail @ ..]) => {
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&[*high, *low]).ok()?, 16).ok()?);
+                rest = tail;
+            }
+            _ => {
+                bytes.push(*first);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+// The line and UTF-16 column of byte `at` of `text`
+pub fn position_of(text: &str, at: usize) -> Position {
+    let before = &text[..at];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    Position { line: before.matches('\n').count() as u32, character: before[line_start..].encode_utf16().count() as u32 }
+}
+
+fn position_json(position: Position) -> Value {
+    json!({ "line": position.line, "character": position.character })
+}
+
+fn range_json(text: &str, range: Range<usize>) -> Value {
+    json!({ "start": position_json(position_of(text, range.start)), "end": position_json(position_of(text, range.end)) })
+}
+
+fn at_position(path: &Path, text: &str, at: usize) -> Value {
+    json!({ "textDocument": { "uri": uri(path) }, "position": position_json(position_of(text, at)) })
+}
+
+fn position(value: &Value) -> Option<Position> {
+    Some(Position { line: value["line"].as_u64()? as u32, character: value["character"].as_u64()? as u32 })
+}
+
+fn numbers(value: &Value) -> Vec<u32> {
+    value.as_array().into_iter().flatten().filter_map(|number| number.as_u64()).map(|number| number as u32).collect()
+}
+
+fn diagnostic_json(text: &str, diagnostic: &Diagnostic) -> Value {
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+        Severity::Info => 3,
+    };
+    json!({ "range": range_json(text, diagnostic.range.clone()), "severity": severity, "message": diagnostic.message, "source": diagnostic.source })
+}
+
+pub fn legend(capabilities: &Value) -> Option<Legend> {
+    let legend = &capabilities["semanticTokensProvider"]["legend"];
+    let strings = |value: &Value| value.as_array().map(|values| values.iter().filter_map(|value| value.as_str().map(str::to_string)).collect::<Vec<_>>());
+    Some(Legend { token_types: strings(&legend["tokenTypes"])?, token_modifiers: strings(&legend["tokenModifiers"]).unwrap_or_default() })
+}
+
+// Hover contents come as markup, a marked string, or a list of marked strings, which are code in
+// a language when they aren't plain strings
+fn markdown(contents: &Value) -> String {
+    match contents {
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(markdown).filter(|text| !text.is_empty()).collect::<Vec<_>>().join("\n\n"),
+        Value::Object(object) => match (object.get("language").and_then(Value::as_str), object.get("value").and_then(Value::as_str)) {
+            (Some(language), Some(value)) => format!("```{language}\n{value}\n```"),
+            (None, Some(value)) => value.to_string(),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+// Empty for nothing to say, which Popups::show_hover takes as closing
+pub fn hover_markdown(result: &Value) -> String {
+    markdown(&result["contents"])
+}
+
+pub fn signature_help(result: &Value) -> SignatureHelp {
+    let signatures = result["signatures"].as_array().into_iter().flatten();
+    let signatures: Vec<Signature> = signatures
+        .filter_map(|signature| {
+            let label = signature["label"].as_str()?.to_string();
+            // Parameters are named by their text in the label, or by UTF-16 offsets into it. Ones
+            // that can't be found are left empty, to keep the indices of the rest
+            let parameters = signature["parameters"].as_array().into_iter().flatten().map(|parameter| match &parameter["label"] {
+                Value::String(name) => label.find(name.as_str()).map_or(0..0, |start| start..start + name.len()),
+                Value::Array(offsets) => {
+                    let offset = |idx: usize| offsets.get(idx).and_then(Value::as_u64).and_then(|offset| utf16_to_byte(&label, offset as usize));
+                    offset(0).zip(offset(1)).map_or(0..0, |(start, end)| start..end)
+                }
+                _ => 0..0,
+            });
+            let parameters = parameters.collect();
+            let documentation = Some(markdown(&signature["documentation"])).filter(|documentation| !documentation.is_empty());
+            Some(Signature { label, documentation, parameters })
+        })
+        .collect();
+    let active_signature = result["activeSignature"].as_u64().unwrap_or(0) as usize;
+    let active_parameter = signatures.get(active_signature).and(result["signatures"][active_signature]["activeParameter"].as_u64()).or(result["activeParameter"].as_u64()).unwrap_or(0) as usize;
+    SignatureHelp { signatures, active_signature, active_parameter }
+}
+
+fn text_edit(edit: &Value) -> Option<TextEdit> {
+    Some(TextEdit { start: position(&edit["range"]["start"])?, end: position(&edit["range"]["end"])?, new_text: edit["newText"].as_str()?.to_string() })
+}
+
+pub fn text_edits(result: &Value) -> Vec<TextEdit> {
+    result.as_array().into_iter().flatten().filter_map(text_edit).collect()
+}
+
+// Creating, renaming and deleting files aren't supported, so only the edits of documentChanges
+// are taken
+pub fn workspace_edit(edit: &Value) -> WorkspaceEdit {
+    let mut changes = Vec::new();
+    for (uri, edits) in edit["changes"].as_object().into_iter().flatten() {
+        changes.extend(path_from_uri(uri).map(|path| (path, text_edits(edits))));
+    }
+    for change in edit["documentChanges"].as_array().into_iter().flatten() {
+        changes.extend(change["textDocument"]["uri"].as_str().and_then(path_from_uri).map(|path| (path, text_edits(&change["edits"]))));
+    }
+    WorkspaceEdit { changes }
+}
+
+fn command(value: &Value) -> Option<refactor::Command> {
+    Some(refactor::Command { name: value["command"].as_str()?.to_string(), arguments: value["arguments"].as_array().cloned().unwrap_or_default() })
+}
+
+pub fn code_actions(result: &Value) -> Vec<CodeAction> {
+    let actions = result.as_array().into_iter().flatten().filter_map(|item| {
+        let title = item["title"].as_str()?.to_string();
+        // A bare command has its name where a code action has its command
+        if item["command"].is_string() {
+            return Some(CodeAction { title, kind: None, is_preferred: false, edit: None, command: command(item) });
+        }
+        let edit = item.get("edit").map(workspace_edit);
+        Some(CodeAction { title, kind: item["kind"].as_str().map(str::to_string), is_preferred: item["isPreferred"] == json!(true), edit, command: command(&item["command"]) })
+    });
+    actions.collect()
+}
+
+// Document symbols nest, and are all in `path`. Workspace symbols, and flat document ones, have
+// their location instead
+pub fn symbols(result: &Value, path: &Path) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for item in result.as_array().into_iter().flatten() {
+        push_symbol(&mut symbols, item, path, 0);
+    }
+    symbols
+}
+
+fn push_symbol(symbols: &mut Vec<Symbol>, item: &Value, path: &Path, depth: usize) {
+    let (Some(name), Some(kind)) = (item["name"].as_str(), item["kind"].as_u64()) else { return };
+    let (path, line) = match item.get("location") {
+        Some(location) => (location["uri"].as_str().and_then(path_from_uri), location["range"]["start"]["line"].as_u64()),
+        None => (Some(path.to_owned()), item["selectionRange"]["start"]["line"].as_u64()),
+    };
+    let Some(path) = path else { return };
+    symbols.push(Symbol { name: name.to_string(), kind: SymbolKind::from_lsp(kind as u32), path: path.clone(), line: line.unwrap_or(0) as usize, depth });
+    for child in item["children"].as_array().into_iter().flatten() {
+        push_symbol(symbols, child, &path, depth + 1);
+    }
+}
+
+// Positions past the end of `text`, from a server that hasn't caught up with an edit, go to its end
+pub fn diagnostics(diagnostics: &Value, text: &str) -> Vec<Diagnostic> {
+    let starts = line_starts(text);
+    let offset = |value: &Value| position(value).and_then(|position| byte_offset(text, &starts, position)).unwrap_or(text.len());
+    let diagnostics = diagnostics.as_array().into_iter().flatten().filter_map(|diagnostic| {
+        let start = offset(&diagnostic["range"]["start"]);
+        let range = start..offset(&diagnostic["range"]["end"]).max(start);
+        // Errors unless the server says otherwise
+        let severity = severity_from_lsp(diagnostic["severity"].as_u64().unwrap_or(1) as u32);
+        Some(Diagnostic { range, severity, message: diagnostic["message"].as_str()?.to_string(), source: diagnostic["source"].as_str().map(str::to_string) })
+    });
+    diagnostics.collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Value {
+        json!({ "start": { "line": start.0, "character": start.1 }, "end": { "line": end.0, "character": end.1 } })
+    }
+
+    #[test]
+    fn uris_and_positions() {
+        let path = Path::new("/src/my crate/naïve.rs");
+        assert_eq!(uri(path), "file:///src/my%20crate/na%C3%AFve.rs");
+        assert_eq!(path_from_uri(&uri(path)).as_deref(), Some(path));
+        assert_eq!(path_from_uri("https://example.com"), None);
+        let text = "fn a() {}\nlet 😀 = 1;\n";
+        assert_eq!(position_of(text, text.find('=').unwrap()), Position { line: 1, character: 7 });
+    }
+
+    #[test]
+    fn results_become_rakoune_types() {
+        let hover = json!({ "contents": [{ "language": "rust", "value": "fn f()" }, "Does *nothing*"] });
+        assert_eq!(hover_markdown(&hover), "```rust\nfn f()\n```\n\nDoes *nothing*");
+        assert_eq!(hover_markdown(&json!({ "contents": { "kind": "markdown", "value": "`x: i32`" } })), "`x: i32`");
+        assert_eq!(hover_markdown(&Value::Null), "");
+
+        let help = signature_help(&json!({ "signatures": [{ "label": "fn add(a: i32, b: i32)", "parameters": [{ "label": "a: i32" }, { "label": [15, 21] }] }], "activeParameter": 1 }));
+        assert_eq!((help.signatures[0].parameters.clone(), help.active_parameter), (vec![7..13, 15..21], 1));
+
+        let edit = workspace_edit(&json!({
+            "changes": { "file:///a.rs": [{ "range": range((0, 0), (0, 1)), "newText": "b" }] },
+            "documentChanges": [{ "textDocument": { "uri": "file:///c.rs", "version": 1 }, "edits": [{ "range": range((1, 0), (1, 0)), "newText": "x" }] }, { "kind": "create", "uri": "file:///d.rs" }],
+        }));
+        let paths: Vec<&Path> = edit.changes.iter().map(|(path, _)| path.as_path()).collect();
+        assert_eq!(paths, vec![Path::new("/a.rs"), Path::new("/c.rs")]);
+        assert_eq!(edit.changes[1].1[0].new_text, "x");
+
+        let actions = code_actions(&json!([
+            { "title": "Run", "command": "run", "arguments": [1] },
+            { "title": "Import", "kind": "quickfix", "isPreferred": true, "edit": { "changes": {} }, "command": { "title": "", "command": "after" } },
+        ]));
+        assert_eq!(actions[0].command, Some(refactor::Command { name: "run".to_string(), arguments: vec![json!(1)] }));
+        assert_eq!((actions[1].kind.as_deref(), actions[1].is_preferred, actions[1].edit.is_some()), (Some("quickfix"), true, true));
+        assert_eq!(actions[1].command.as_ref().map(|command| command.name.as_str()), Some("after"));
+
+        let nested = json!([{ "name": "Editor", "kind": 23, "range": range((0, 0), (3, 1)), "selectionRange": range((0, 11), (0, 17)), "children": [{ "name": "new", "kind": 6, "range": range((1, 4), (1, 20)), "selectionRange": range((1, 7), (1, 10)) }] }]);
+        let found: Vec<(String, SymbolKind, usize, usize)> = symbols(&nested, Path::new("/a.rs")).into_iter().map(|symbol| (symbol.name, symbol.kind, symbol.line, symbol.depth)).collect();
+        assert_eq!(found, vec![("Editor".to_string(), SymbolKind::Struct, 0, 0), ("new".to_string(), SymbolKind::Method, 1, 1)]);
+        let flat = json!([{ "name": "main", "kind": 12, "location": { "uri": "file:///b.rs", "range": range((4, 0), (4, 9)) } }]);
+        assert_eq!(symbols(&flat, Path::new("/a.rs"))[0].path, Path::new("/b.rs"));
+
+        let text = "let 😀 = x;\n";
+        let found = diagnostics(&json!([{ "range": range((0, 9), (0, 10)), "severity": 2, "message": "unknown x", "source": "rustc" }, { "range": range((5, 0), (5, 1)), "message": "stale" }]), text);
+        assert_eq!((found[0].range.clone(), found[0].severity), (11..12, Severity::Warning));
+        assert_eq!((found[1].range.clone(), found[1].severity), (text.len()..text.len(), Severity::Error));
+
+        let progress = notification("$/progress", &json!({ "token": 3, "value": { "kind": "report", "percentage": 40 } }));
+        assert!(matches!(progress, Some(Event::Progress(token, WorkDone::Report(Some(40)))) if token == json!(3)));
+        assert!(notification("$/progress", &json!({ "token": "t", "value": { "kind": "begin" } })).is_none());
+    }
+
+    fn wait_for(server: &mut Server, found: impl Fn(&Event) -> bool) -> Event {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if let Some(event) = server.poll().into_iter().filter_map(Result::ok).find(|event| found(event)) {
+                return event;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("nothing came from the server");
+    }
+
+    #[test]
+    fn talks_over_pipes() {
+        // cat sends every message straight back, so each request comes in as a request from the
+        // server, whose empty answer comes back as the response
+        let mut server = Server::start("cat", &std::env::temp_dir()).unwrap();
+        let path = Path::new("/src/a.rs");
+        server.open(path, "rust", 1, "fn main() {}\n").unwrap();
+        server.hover(path, "fn main() {}\n", 3).unwrap();
+        assert_eq!(server.queued.len(), 2, "held back until initialized");
+        let event = wait_for(&mut server, |event| matches!(event, Event::Response(..)));
+        assert!(matches!(event, Event::Response(Request::Hover(hovered, 3), Value::Null) if hovered == path));
+        assert!(server.queued.is_empty());
+    }
+
+    #[test]
+    fn tokens_follow_deltas() {
+        let mut server = Server::start("cat", &std::env::temp_dir()).unwrap();
+        server.capabilities = Some(json!({ "semanticTokensProvider": { "legend": { "tokenTypes": ["function", "variable"], "tokenModifiers": [] }, "full": { "delta": true } } }));
+        let path = Path::new("/src/a.rs");
+        let id = |server: &Server| *server.pending.iter().find(|(_, request)| matches!(request, Request::SemanticTokens(_))).unwrap().0;
+        server.open(path, "rust", 1, "fn main() {}\n").unwrap();
+        let full = id(&server);
+        // Changed before the tokens came, so they're asked for again once they do
+        server.change(path, 2, "fn main() { x }\n").unwrap();
+        let mut events = Vec::new();
+        server.handle(&json!({ "id": full, "result": { "resultId": "1", "data": [0, 3, 4, 0, 0] } }), &mut events).unwrap();
+        let Some(Ok(Event::SemanticTokens(_, legend, tokens))) = events.pop() else { panic!("{events:?}") };
+        assert_eq!(tokens.highlights(&legend, "fn main() {}\n")[0].face, "function");
+
+        let delta = id(&server);
+        assert_ne!(delta, full);
+        server.handle(&json!({ "id": delta, "result": { "resultId": "2", "edits": [{ "start": 5, "deleteCount": 0, "data": [0, 9, 1, 1, 0] }] } }), &mut events).unwrap();
+        let Some(Ok(Event::SemanticTokens(_, legend, tokens))) = events.pop() else { panic!("{events:?}") };
+        let faces: Vec<String> = tokens.highlights(&legend, "fn main() { x }\n").into_iter().map(|highlight| highlight.face).collect();
+        assert_eq!(faces, vec!["function", "variable"]);
+        assert_eq!(tokens.result_id.as_deref(), Some("2"));
+    }
+}
@@ -1,16 +1,38 @@
 use std::time::{Duration, Instant};
 use std::sync::mpsc;
-use thiserror::Error;
 
-pub mod font;
+use rakoune::error::{EditorError, Recovery};
+use rakoune::app::App;
+use rakoune::commands;
+use rakoune::config::Config;
+use rakoune::confirm::Question;
+use rakoune::editor::Editor;
+use rakoune::maintenance::{Maintenance, MaintenanceHost};
+use rakoune::memory::{MemoryConfig, MemoryHost, Usage};
+use rakoune::gpu_timing::PassTimes;
+use rakoune::project::TrustStore;
+use rakoune::render::{self, Renderer};
+use rakoune::search::SearchHistory;
+use rakoune::server::{self, Request};
+use rakoune::session;
+use rakoune::welcome::{RecentFiles, StartScreen};
+use rakoune::{accessibility, associations, bracket_tree, crash, filetree, font, highlighter, images, notifications};
 
+enum PerfEvent {
+    Frame(Duration),
+    // Time from a key event being received until the frame containing its effect was presented
+    KeyLatency(Duration),
+    // How long the passes of the latest frame the GPU finished took
+    Gpu(PassTimes),
+    // The app was suspended or resumed. Time spent suspended shouldn't count towards any statistics
+    Suspended,
+    Resumed,
+}
 
-#[derive(Debug, Error)]
-enum Error {
-    #[error("Font error: {0}")]
-    FontError(#[from] font::Error),
-    #[error("Windowing error: {0}")]
-    WindowingError(#[from] winit::error::OsError),
+// Expects `sorted` to be sorted and non-empty
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let idx = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[idx]
 }
 
 fn main() {
@@ -19,67 +41,502 @@ fn main() {
     }
 }
 
-fn run() -> Result<(), Error> {
+// --profile-startup: how long each part of starting up took, printed once the first frame is drawn
+struct StartupProfile {
+    start: Instant,
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl StartupProfile {
+    fn new() -> StartupProfile {
+        StartupProfile { start: Instant::now(), last: Instant::now(), phases: Vec::new() }
+    }
+
+    // Ends the phase called `name`, started when the last one ended
+    fn phase(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.phases.push((name, now - self.last));
+        self.last = now;
+    }
+
+    fn print(&self) {
+        eprintln!("Startup took {:.1}ms:", self.start.elapsed().as_secs_f64() * 1000.);
+        for (name, took) in &self.phases {
+            eprintln!("  {name:<16} {:>8.1}ms", took.as_secs_f64() * 1000.);
+        }
+    }
+}
+
+// The logo, for the title bar, taskbar and window switcher
+fn window_icon() -> Option<winit::window::Icon> {
+    let logo = images::decode_png(include_bytes!("../resources/rakoune_logo.png")).ok()?;
+    // Windows wants at most 256x256, and nothing shows it bigger anyway
+    let logo = images::shrink(&logo, 256);
+    winit::window::Icon::from_rgba(logo.rgba, logo.width, logo.height).ok()
+}
+
+// The editor along with the renderer, which holds the caches maintenance trims
+struct Housekeeping<'a> {
+    editor: &'a mut Editor,
+    renderer: &'a mut Renderer,
+}
+
+impl MemoryHost for Housekeeping<'_> {
+    fn memory_usage(&self) -> Usage {
+        self.editor.memory_usage()
+    }
+
+    fn trim_caches(&mut self) {
+        self.renderer.trim();
+        self.editor.render_usage = self.renderer.memory_usage();
+    }
+
+    fn memory_config(&self) -> &MemoryConfig {
+        self.editor.memory_config()
+    }
+
+    fn show_report(&mut self, title: &str, text: String) {
+        MemoryHost::show_report(self.editor, title, text);
+    }
+}
+
+impl MaintenanceHost for Housekeeping<'_> {
+    fn evict_idle_glyphs(&mut self, now: Instant, idle: Duration) -> usize {
+        let evicted = self.renderer.evict_idle(now, idle);
+        self.editor.render_usage = self.renderer.memory_usage();
+        evicted
+    }
+
+    fn compact_undo(&mut self, limit: usize) -> usize {
+        self.editor.buffers.iter_mut().map(|buffer| buffer.history.compact(limit)).sum()
+    }
+}
+
+fn is_font(path: &str) -> bool {
+    [".ttf", ".otf", ".ttc"].iter().any(|extension| path.to_lowercase().ends_with(extension))
+}
+
+fn run() -> Result<(), EditorError> {
+    let mut profile = StartupProfile::new();
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--register") {
+        eprintln!("{}", associations::register()?);
+        return Ok(());
+    }
+    let profiling = args.iter().any(|arg| arg == "--profile-startup");
+    args.retain(|arg| arg != "--profile-startup");
+    // rakoune [--profile-startup] [font] [files...]
+    let (font_args, file_args): (Vec<&String>, Vec<&String>) = args.iter().partition(|arg| is_font(arg));
+    let files: Vec<std::path::PathBuf> = file_args.iter().map(std::path::absolute).collect::<Result<_, _>>()?;
+    let socket = server::default_path();
+    // When the running one can't be reached, this one opens them instead
+    let sent = match files.is_empty() {
+        true => Ok(false),
+        false => server::send(&socket, &files.iter().cloned().map(Request::Open).collect::<Vec<_>>()),
+    };
+    if let Ok(true) = sent {
+        eprintln!("Opened in the rakoune already running");
+        return Ok(());
+    }
+
     eprintln!("Loading fonts...");
-    let path_arg = std::env::args().skip(1).next().unwrap_or("./resources/linja-pona-4.1.otf".to_string());
+    let path_arg = font_args.first().map_or("./resources/linja-pona-4.1.otf".to_string(), |font| font.to_string());
     let path = std::path::Path::new(&path_arg);
-    let mut fontstack = font::FontStack::new(path)?;
-    fontstack.add_fallback(std::path::Path::new("/System/Library/Fonts/Helvetica.ttc"))?;
-    fontstack.add_fallback(std::path::Path::new("/System/Library/Fonts/Apple Color Emoji.ttc"))?;
+    let mut notifications = notifications::Notifications::default();
+    let config = Config::default_path().map_or(Ok(Config::default()), |path| Config::load(&path));
+    let config = notifications.report(config).unwrap_or_default();
+    if let Err(e) = sent {
+        notifications.warn(format!("Couldn't send the files to the rakoune already running: {e}"));
+    }
+    let mut last_crash = None;
+    if let Some(crash_dir) = crash::default_dir() {
+        if let Some(report) = crash::take_last_report(&crash_dir) {
+            notifications.warn(format!("rakoune crashed last time, see {}", report.display()));
+            last_crash = Some(report);
+        }
+        crash::install(crash_dir);
+    }
+    profile.phase("arguments");
+    let fallbacks = ["/System/Library/Fonts/Helvetica.ttc", "/System/Library/Fonts/Apple Color Emoji.ttc"].map(std::path::Path::new);
+    let (mut fontstack, failed) = font::FontStack::load(path, &fallbacks)?;
+    for (fallback, e) in failed {
+        notifications.warn(format!("Fallback font {} not loaded, skipping it: {e}", fallback.display()));
+    }
+    // Filetype icons for the :tree, if there's a Nerd Font to draw them with
+    let mut icons = filetree::Icons::Plain;
+    if let Some(icon_font) = filetree::find_icon_font(&font::installed_fonts()) {
+        match font::Face::load_all_indices(icon_font) {
+            Ok(faces) => {
+                faces.into_iter().for_each(|face| fontstack.add_face(face));
+                icons = filetree::Icons::NerdFont;
+            }
+            Err(e) => notifications.warn(format!("Icon font {} not loaded: {e}", icon_font.display())),
+        }
+    }
     eprintln!("Loaded fonts");
+    profile.phase("fonts");
 
     // let text = "pona mute tawa sina Σ 🇵🇱 mjau 🐔🐔 👉👈 ☝🏾 ☝🏽<=> mjau";
     // debug_font_text(&fontstack, text.to_string());
 
-    let event_loop = winit::event_loop::EventLoop::new();
-    let window = winit::window::WindowBuilder::new()
+    let event_loop = winit::event_loop::EventLoopBuilder::<Request>::with_user_event().build();
+    // Files opened while running, by starting rakoune again or from the OS
+    let proxy = event_loop.create_proxy();
+    if let Err(e) = server::listen(&socket, move |request| {
+        let _ = proxy.send_event(request);
+    }) {
+        notifications.warn(format!("Can't listen on {}, files opened elsewhere will start another rakoune: {e}", socket.display()));
+    }
+    profile.phase("event loop");
+    #[cfg(target_os = "macos")]
+    {
+        let proxy = event_loop.create_proxy();
+        associations::on_open_files(move |path| {
+            let _ = proxy.send_event(Request::Open(path));
+        });
+    }
+    let icon = window_icon();
+    let builder = winit::window::WindowBuilder::new()
         .with_inner_size(winit::dpi::LogicalSize::new(600, 400))
         .with_resizable(true)
         .with_title("rakoune :3")
-        .build(&event_loop)?;
+        .with_window_icon(icon.clone())
+        // AccessKit has to be set up before the window is first shown
+        .with_visible(false);
+    #[cfg(target_os = "windows")]
+    let builder = winit::platform::windows::WindowBuilderExtWindows::with_taskbar_icon(builder, icon);
+    // WM_CLASS on X11 and the app id on Wayland, which both set. Window managers match it against
+    // the desktop entry (rakoune.desktop) to group windows and find the icon
+    #[cfg(all(unix, not(target_os = "macos")))]
+    let builder = winit::platform::x11::WindowBuilderExtX11::with_name(builder, "rakoune", "rakoune");
+    let window = builder.build(&event_loop)?;
+    profile.phase("window");
+    let mut renderer = Renderer::new(&window, config.atlas, config.present_mode, config.gpu_raster)?;
+    if let Some(warning) = renderer.warning() {
+        notifications.warn(warning);
+    }
+    notifications.info(renderer.atlas_summary());
+    let background = renderer.set_background(config.background.for_theme("default", render::BACKGROUND));
+    notifications.report(background);
+    profile.phase("renderer");
+
+    // Screen readers get the buffer and new notifications
+    let mut accessibility = accessibility::Accessibility::default();
+    let initial = accessibility.tree(&accessibility::Snapshot { mode: "normal".to_string(), ..Default::default() }, "\n");
+    let access_adapter = accesskit_winit::Adapter::with_action_handler(&window, move || initial, Box::new(accessibility::IgnoreActions));
+    window.set_visible(true);
+    profile.phase("accessibility");
+
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
+    let mut menu_bar = match rakoune::menubar::native::MenuBar::new(&window) {
+        Ok(menu_bar) => Some(menu_bar),
+        Err(e) => {
+            notifications.error(format!("Could not create the menu bar: {e}"));
+            None
+        }
+    };
 
-    // FPS monitoring
-    let (frame_tx, frame_rx) = mpsc::channel::<Duration>();
+    // FPS and key-to-photon latency monitoring
+    let (perf_tx, perf_rx) = mpsc::channel::<PerfEvent>();
     std::thread::spawn(move || {
         let mut last_print = Instant::now();
         let mut frame_times: Vec<Duration> = Vec::new();
+        let mut key_latencies: Vec<Duration> = Vec::new();
+        let mut gpu_times: Option<PassTimes> = None;
+        let mut suspended = false;
         loop {
-            let frame_time = match frame_rx.recv() {
-                Ok(t) => t,
+            match perf_rx.recv() {
+                Ok(PerfEvent::Frame(t)) => frame_times.push(t),
+                Ok(PerfEvent::KeyLatency(t)) => key_latencies.push(t),
+                Ok(PerfEvent::Gpu(times)) => gpu_times = Some(times),
+                Ok(PerfEvent::Suspended) => {
+                    suspended = true;
+                    frame_times.drain(..);
+                    key_latencies.drain(..);
+                }
+                Ok(PerfEvent::Resumed) => {
+                    suspended = false;
+                    last_print = Instant::now();
+                }
                 Err(mpsc::RecvError) => { // Sender disconnected
                     eprintln!("FPS monitor: channel died");
                     return;
                 }
             };
-            frame_times.push(frame_time);
-            if last_print.elapsed() > Duration::from_secs(1) {
+            if !suspended && last_print.elapsed() > Duration::from_secs(1) {
                 last_print = Instant::now();
                 let average_frame_time = frame_times.iter().map(|x| x.as_secs_f64()).sum::<f64>() / frame_times.len() as f64;
 
                 eprintln!("Rendering at {} FPS. Average frame took {:.4}ms to render.", frame_times.len(), average_frame_time * 1000.);
                 frame_times.drain(..);
+                if let Some(times) = gpu_times.take() {
+                    eprintln!("{times}");
+                }
+
+                if !key_latencies.is_empty() {
+                    key_latencies.sort();
+                    eprintln!(
+                        "Key-to-photon latency over {} keys: p50 {:.2}ms, p99 {:.2}ms",
+                        key_latencies.len(),
+                        percentile(&key_latencies, 0.50).as_secs_f64() * 1000.,
+                        percentile(&key_latencies, 0.99).as_secs_f64() * 1000.,
+                    );
+                    key_latencies.drain(..);
+                }
             }
         }
     });
 
+    // Receipt times of key events whose effect has not yet been presented
+    let mut pending_keys: Vec<Instant> = Vec::new();
     let mut has_warned_about_channel_died = false;
+    let mut send_perf_event = move |event: PerfEvent| {
+        if let Err(mpsc::SendError(_)) = perf_tx.send(event) {
+            if !has_warned_about_channel_died {
+                eprintln!("Could not send frame time to FPS monitoring thread");
+                has_warned_about_channel_died = true;
+            }
+        }
+    };
+    let size = window.inner_size();
+    let mut app = App::new(fontstack, notifications, (size.width as f32, size.height as f32));
+    app.configure(&config);
+    app.editor.search_history = SearchHistory::default_path().map(SearchHistory::load).unwrap_or_default();
+    app.editor.recent = RecentFiles::default_path().map(RecentFiles::load).unwrap_or_default();
+    app.editor.icons = icons;
+    app.editor.font = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    app.editor.refresh_branch();
+    let languages = Config::grammars_dir().map_or_else(|| Ok(bracket_tree::Languages::builtin()), |dir| bracket_tree::Languages::load_dir(&dir));
+    let languages = app.editor.notifications.report(languages).unwrap_or_else(bracket_tree::Languages::builtin);
+    app.editor.languages = languages.clone();
+    let highlighting = highlighter::builtin(&config.highlighting, languages).and_then(|highlighters| highlighter::Highlighting::new(config.highlighting.clone(), highlighters));
+    app.editor.highlighting = app.editor.notifications.report(highlighting);
+    app.editor.trust = TrustStore::default_path().map(TrustStore::load).unwrap_or_default();
+    // The files from last time, unless some were asked for
+    if files.is_empty() {
+        if let Ok(cwd) = std::env::current_dir() {
+            app.editor.discover_project(&cwd);
+        }
+        app.editor.restore_session_at_start();
+        // Nothing to edit, so the start screen shows until a file is opened
+        if app.editor.buffer().path().is_none() {
+            app.editor.start_screen = Some(StartScreen::new(&app.editor.recent, &app.registry));
+        }
+    }
+    app.open_files = files;
+    app.editor.unsaved.install();
+    // Offering back what was unsaved when it crashed
+    if let Some(report) = last_crash {
+        let saved = crash::recovered(&report).len();
+        if saved > 0 {
+            let question = format!("{saved} buffers with unsaved changes were saved when rakoune crashed. Open them?");
+            app.editor.questions.ask(Question::yes_no(&question, &format!("recover {}", commands::quote(&report.to_string_lossy()))));
+        }
+    }
+    profile.phase("app");
+    let mut profile = profiling.then_some(profile);
+    let mut maintenance = Maintenance::default();
+
     event_loop.run(move |evt, _target, ctrl| {
-        use winit::event::{Event, WindowEvent};
+        use winit::event::{Event, WindowEvent, StartCause, MouseButton, KeyboardInput};
+
+        // Only wake up when there is input to respond to, or for the next animation frame
+        ctrl.set_wait();
+
+        // The adapter may keep events for itself
+        if let Event::WindowEvent { event, .. } = &evt {
+            if !access_adapter.on_event(&window, event) {
+                return;
+            }
+        }
+
+        // Raw, for :key-events
+        match &evt {
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { input, .. }, .. } => {
+                let pressed = input.state == winit::event::ElementState::Pressed;
+                app.editor.key_event_log.key(input.scancode, input.virtual_keycode.map(|key| format!("{key:?}")).as_deref(), pressed, &format!("{:?}", app.modifiers));
+            }
+            Event::WindowEvent { event: WindowEvent::ReceivedCharacter(c), .. } => app.editor.key_event_log.character(*c),
+            _ => {}
+        }
+
         match evt {
+            // Like :quit, which asks about unsaved changes first
             Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
-                eprintln!("bye");
-                *ctrl = winit::event_loop::ControlFlow::ExitWithCode(0);
+                app.menu_commands.push("quit".to_string());
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { input: KeyboardInput { virtual_keycode: Some(key), scancode, state, .. }, .. }, .. } => {
+                let now = Instant::now();
+                app.key_input(key, scancode, state, now);
+                // One sample per press. Releases and the character a press sends show nothing new
+                if state == winit::event::ElementState::Pressed {
+                    pending_keys.push(now);
+                }
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::ReceivedCharacter(c), .. } => {
+                app.received_character(c);
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::Focused(now_focused), .. } => {
+                app.focus_changed(now_focused, Instant::now());
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { input, .. }, .. } => {
+                if input.state == winit::event::ElementState::Pressed {
+                    pending_keys.push(Instant::now());
+                }
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::Resized(size), .. } => {
+                renderer.resize(size.width, size.height);
+                app.resized(size.width as f32, size.height as f32);
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::ScaleFactorChanged { .. }, .. } => {
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::MouseWheel { delta, phase, .. }, .. } => {
+                app.mouse_wheel(delta, phase, Instant::now());
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                app.cursor_moved(position.x as f32, position.y as f32, Instant::now());
+                window.set_cursor_icon(app.cursor_icon());
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::MouseInput { state, button: MouseButton::Left, .. }, .. } => {
+                app.left_mouse(state, Instant::now());
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::MouseInput { state, button: MouseButton::Middle, .. }, .. } => {
+                app.middle_mouse(state);
+                window.request_redraw();
             }
+            Event::UserEvent(Request::Open(path)) => {
+                app.open_files.push(path);
+                window.focus_window();
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::Touch(touch), .. } => {
+                app.touch(touch, Instant::now());
+                window.request_redraw();
+            }
+            Event::WindowEvent { event: WindowEvent::ModifiersChanged(modifiers), .. } => app.modifiers = modifiers,
+            Event::WindowEvent { event: WindowEvent::TouchpadMagnify { delta, .. }, .. } => {
+                app.magnify(delta as f32);
+                window.request_redraw();
+            }
+            Event::NewEvents(StartCause::ResumeTimeReached { .. }) if !app.suspended && app.update(Instant::now()) => {
+                window.request_redraw();
+            }
+            // Timers, autosave and maintenance wait until resuming
+            Event::MainEventsCleared if app.suspended => ctrl.set_wait(),
+            Event::MainEventsCleared => {
+                #[cfg(any(target_os = "macos", target_os = "windows"))]
+                if let Some(menu_bar) = &menu_bar {
+                    app.menu_commands.extend(menu_bar.picked());
+                }
+                let now = Instant::now();
+                let mut housekeeping = Housekeeping { editor: &mut app.editor, renderer: &mut renderer };
+                if maintenance.run_if_due(&mut housekeeping, now, app.last_edit).is_some_and(|outcome| outcome.glyphs_evicted > 0 || outcome.trimmed) {
+                    window.request_redraw();
+                }
+                if !app.menu_commands.is_empty() {
+                    window.request_redraw();
+                }
+                let wake_at = app.wake_at(now).into_iter().chain([maintenance.wake_at(app.last_edit)]).min();
+                if let Some(at) = wake_at {
+                    ctrl.set_wait_until(at);
+                }
+            }
+            Event::Suspended => {
+                eprintln!("Suspended");
+                app.suspended = true;
+                // These keys will only be shown after resuming, which says nothing about our latency
+                pending_keys.clear();
+                send_perf_event(PerfEvent::Suspended);
+                renderer.suspend();
+            }
+            Event::Resumed => {
+                if app.suspended {
+                    eprintln!("Resumed");
+                    app.suspended = false;
+                    send_perf_event(PerfEvent::Resumed);
+                }
+                if let Err(e) = renderer.resume(&window) {
+                    eprintln!("Can't draw: {e}");
+                    *ctrl = winit::event_loop::ControlFlow::ExitWithCode(1);
+                    return;
+                }
+                window.request_redraw();
+            }
+            Event::RedrawRequested(_) if app.suspended => {}
             Event::RedrawRequested(_) => {
                 let start = Instant::now();
-                if let Err(mpsc::SendError(_)) = frame_tx.send(start.elapsed()) {
-                    if !has_warned_about_channel_died {
-                        eprintln!("Could not send frame time to FPS monitoring thread");
-                        has_warned_about_channel_died = true;
+                app.handle_input(start);
+                // Commands run above may have toggled options the menu has checkmarks for
+                #[cfg(any(target_os = "macos", target_os = "windows"))]
+                if let Some(menu_bar) = &mut menu_bar {
+                    menu_bar.refresh(&app.editor);
+                }
+                if app.editor.quit {
+                    if let Err(e) = session::store(&app.editor) {
+                        eprintln!("Couldn't save the session: {e}");
                     }
+                    eprintln!("bye");
+                    *ctrl = winit::event_loop::ControlFlow::ExitWithCode(0);
+                    return;
                 }
+                if std::mem::take(&mut app.editor.trim_requested) {
+                    renderer.trim();
+                }
+                if let Err(e) = renderer.draw(&app, start) {
+                    match e.recovery() {
+                        Recovery::RebuildSwapchain => {
+                            renderer.reconfigure();
+                            window.request_redraw();
+                        }
+                        Recovery::SkipFrame => window.request_redraw(),
+                        Recovery::FallbackFont => app.editor.notifications.error(e.to_string()),
+                        Recovery::Fatal => {
+                            eprintln!("Can't draw: {e}");
+                            *ctrl = winit::event_loop::ControlFlow::ExitWithCode(1);
+                            return;
+                        }
+                    }
+                }
+                app.editor.render_usage = renderer.memory_usage();
+                let mut events = vec![PerfEvent::Frame(start.elapsed())];
+                events.extend(renderer.gpu_times().map(PerfEvent::Gpu));
 
-                window.request_redraw();
+                // The frame has been presented, so every key received before it is now visible
+                let presented = Instant::now();
+                events.extend(pending_keys.drain(..).map(|t| PerfEvent::KeyLatency(presented - t)));
+
+                let buffer = app.editor.buffer();
+                let selection = &buffer.selections[0];
+                let snapshot = accessibility::Snapshot {
+                    version: buffer.version,
+                    anchor: selection.anchor,
+                    head: selection.head,
+                    mode: app.editor.mode.name().to_string(),
+                    announcement: accessibility::announcement(&app.editor.notifications),
+                    focused: app.focused,
+                };
+                if let Some(update) = accessibility.update(snapshot, &buffer.text) {
+                    access_adapter.update_if_active(|| update);
+                }
+
+                for event in events {
+                    send_perf_event(event);
+                }
+                if let Some(mut profile) = profile.take() {
+                    profile.phase("first frame");
+                    profile.print();
+                }
             }
             _ => {}
         }
@@ -0,0 +1,166 @@
+// Housekeeping run from the event loop while the editor is idle: glyphs not drawn in a while leave
+// the atlas, the oldest steps of long undo histories are dropped, and under memory pressure every cache is trimmed.
+// The caches belong to the main thread, so this runs there, between frames rather than on a thread
+
+use std::time::{Duration, Instant};
+
+use crate::memory::MemoryHost;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaintenanceConfig {
+    // Time between runs
+    pub interval: Duration,
+    // Nothing is trimmed until this long after the last edit, so typing never waits on glyphs being rasterized again
+    pub quiet: Duration,
+    // Glyphs not drawn for this long are dropped from the atlas
+    pub glyph_idle: Duration,
+    // Undo steps kept per buffer. Older ones are dropped
+    pub undo_limit: usize,
+    // Once over the memory budget, caches are trimmed on every run until usage is below this fraction of it
+    pub low_water: f32,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        MaintenanceConfig {
+            interval: Duration::from_secs(30),
+            quiet: Duration::from_secs(2),
+            glyph_idle: Duration::from_secs(10 * 60),
+            undo_limit: 1000,
+            low_water: 0.75,
+        }
+    }
+}
+
+// What maintenance needs from the editor, on top of accounting for memory
+pub trait MaintenanceHost: MemoryHost {
+    // Returns how many glyphs were dropped
+    fn evict_idle_glyphs(&mut self, now: Instant, idle: Duration) -> usize;
+    // Drops undo steps beyond the `limit` newest of each buffer. Returns how many were dropped
+    fn compact_undo(&mut self, limit: usize) -> usize;
+}
+
+// What a run did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Outcome {
+    pub glyphs_evicted: usize,
+    pub undo_compacted: usize,
+    pub trimmed: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Maintenance {
+    pub config: MaintenanceConfig,
+    last_run: Option<Instant>,
+    // Went over the budget and hasn't come down below the low water mark since
+    under_pressure: bool,
+}
+
+impl Maintenance {
+    pub fn new(config: MaintenanceConfig) -> Maintenance {
+        Maintenance { config, last_run: None, under_pressure: false }
+    }
+
+    pub fn wake_at(&self, last_edit: Instant) -> Instant {
+        let due = self.last_run.map_or(last_edit, |last_run| last_run + self.config.interval);
+        due.max(last_edit + self.config.quiet)
+    }
+
+    // Runs if the interval has passed and nothing was edited recently
+    pub fn run_if_due(&mut self, host: &mut impl MaintenanceHost, now: Instant, last_edit: Instant) -> Option<Outcome> {
+        if now < self.wake_at(last_edit) {
+            return None;
+        }
+        self.last_run = Some(now);
+        let mut outcome = Outcome {
+            glyphs_evicted: host.evict_idle_glyphs(now, self.config.glyph_idle),
+            undo_compacted: host.compact_undo(self.config.undo_limit),
+            trimmed: false,
+        };
+
+        let budget = host.memory_config().budget();
+        let total = host.memory_usage().total();
+        if total > budget {
+            self.under_pressure = true;
+        } else if (total as f32) < budget as f32 * self.config.low_water {
+            self.under_pressure = false;
+        }
+        if self.under_pressure {
+            host.trim_caches();
+            outcome.trimmed = true;
+        }
+        Some(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{Category, MemoryConfig, Usage};
+
+    struct Editor {
+        text: usize,
+        atlas: usize,
+        undo_steps: usize,
+        config: MemoryConfig,
+    }
+
+    impl MemoryHost for Editor {
+        fn memory_usage(&self) -> Usage {
+            let mut usage = Usage::default();
+            usage.add(Category::Text, "main.rs", self.text);
+            usage.add(Category::Atlas, "coverage", self.atlas);
+            usage
+        }
+
+        fn trim_caches(&mut self) {
+            self.atlas = 0;
+        }
+
+        fn memory_config(&self) -> &MemoryConfig {
+            &self.config
+        }
+
+        fn show_report(&mut self, _title: &str, _text: String) {}
+    }
+
+    impl MaintenanceHost for Editor {
+        fn evict_idle_glyphs(&mut self, _now: Instant, _idle: Duration) -> usize {
+            0
+        }
+
+        fn compact_undo(&mut self, limit: usize) -> usize {
+            let dropped = self.undo_steps.saturating_sub(limit);
+            self.undo_steps -= dropped;
+            dropped
+        }
+    }
+
+    #[test]
+    fn trims_with_hysteresis() {
+        const MB: usize = 1024 * 1024;
+        let start = Instant::now();
+        let mut editor = Editor { text: 5 * MB, atlas: 0, undo_steps: 1200, config: MemoryConfig { budget_mb: 10 } };
+        let mut maintenance = Maintenance::default();
+
+        // Still typing
+        assert_eq!(maintenance.run_if_due(&mut editor, start + Duration::from_secs(1), start), None);
+        let outcome = maintenance.run_if_due(&mut editor, start + Duration::from_secs(2), start).unwrap();
+        assert_eq!(outcome, Outcome { glyphs_evicted: 0, undo_compacted: 200, trimmed: false });
+        assert_eq!(maintenance.wake_at(start), start + Duration::from_secs(32));
+
+        // Over budget: trimmed until usage is back under three quarters of it, not just under it
+        let mut now = start + Duration::from_secs(32);
+        editor.atlas = 6 * MB;
+        assert!(maintenance.run_if_due(&mut editor, now, start).unwrap().trimmed);
+        for atlas in [3 * MB, 4 * MB] {
+            now += Duration::from_secs(30);
+            editor.atlas = atlas;
+            assert!(maintenance.run_if_due(&mut editor, now, start).unwrap().trimmed);
+        }
+        now += Duration::from_secs(30);
+        editor.atlas = MB;
+        assert!(!maintenance.run_if_due(&mut editor, now, start).unwrap().trimmed);
+        assert_eq!(editor.atlas, MB);
+    }
+}
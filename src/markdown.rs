@@ -0,0 +1,453 @@
+// Markdown preview: the buffer as text with the markup taken out, and its images shown below the
+// lines that reference them. PreviewPane goes further and formats headings, emphasis, lists and
+// code blocks, for :preview to show next to the source
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::commands::Registry;
+use crate::images::fit_width;
+use crate::layout::Block;
+
+fn image_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r#"!\[([^\]]*)\]\(\s*<?([^)\s>]+)>?(?:\s+"[^"]*")?\s*\)"#).unwrap())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preview {
+    pub text: String,
+    // Images to show below each line, by line number in `text`
+    pub images: Vec<(usize, PathBuf)>,
+}
+
+// Headings lose their #s, and images are replaced by their alt text. Relative image paths are
+// resolved against `base_dir`. Remote images aren't fetched
+pub fn preview(markdown: &str, base_dir: &Path) -> Preview {
+    let mut lines = Vec::new();
+    let mut images = Vec::new();
+    for (idx, line) in markdown.lines().enumerate() {
+        let trimmed = line.trim_start_matches('#');
+        let line = if trimmed.len() < line.len() && line.len() - trimmed.len() <= 6 && (trimmed.is_empty() || trimmed.starts_with(' ')) {
+            trimmed.trim_start()
+        } else {
+            line
+        };
+        for captures in image_pattern().captures_iter(line) {
+            let src = &captures[2];
+            if !src.contains("://") {
+                images.push((idx, base_dir.join(src)));
+            }
+        }
+        lines.push(image_pattern().replace_all(line, "$1").into_owned());
+    }
+    Preview { text: lines.join("\n"), images }
+}
+
+impl Preview {
+    // Room to reserve for each image, for the images `size_of` knows the size of, scaled down to
+    // `max_width`
+    pub fn blocks(&self, size_of: impl Fn(&Path) -> Option<(u32, u32)>, max_width: f32) -> Vec<Block> {
+        self.images
+            .iter()
+            .filter_map(|(line, path)| {
+                let (width, height) = size_of(path)?;
+                let (width, height) = fit_width(width, height, max_width);
+                Some(Block { after_line: *line, width, height })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpanStyle {
+    pub bold: bool,
+    pub italic: bool,
+    // Inline `code`, drawn in the monospace face
+    pub code: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormattedSpan {
+    pub text: String,
+    pub style: SpanStyle,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Heading { level: usize, spans: Vec<FormattedSpan> },
+    Paragraph(Vec<FormattedSpan>),
+    // `marker` is the bullet or number to draw, `depth` how far the item is nested
+    ListItem { depth: usize, marker: String, spans: Vec<FormattedSpan> },
+    CodeBlock { language: String, text: String },
+    Image { path: PathBuf, alt: String },
+}
+
+// Font size of a heading relative to body text
+pub fn heading_scale(level: usize) -> f32 {
+    match level {
+        1 => 2.,
+        2 => 1.5,
+        3 => 1.25,
+        4 => 1.1,
+        _ => 1.,
+    }
+}
+
+fn list_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"^(\s*)([-*+]|\d+[.)])\s+(.*)$").unwrap())
+}
+
+// Splits inline markup into styled spans: **bold**, *italic*, _italic_ and `code`
+pub fn inline(text: &str) -> Vec<FormattedSpan> {
+    let mut spans: Vec<FormattedSpan> = Vec::new();
+    let mut style = SpanStyle::default();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+    let mut previous = None;
+    while let Some(c) = chars.next() {
+        let toggled = match c {
+            '`' => Some(SpanStyle { code: !style.code, ..style }),
+            '*' | '_' if !style.code && chars.peek() == Some(&c) => {
+                chars.next();
+                Some(SpanStyle { bold: !style.bold, ..style })
+            }
+            // snake_case words aren't italic
+            '_' if previous.is_some_and(char::is_alphanumeric) && chars.peek().is_some_and(|next: &char| next.is_alphanumeric()) => None,
+            '*' | '_' if !style.code => Some(SpanStyle { italic: !style.italic, ..style }),
+            _ => None,
+        };
+        match toggled {
+            Some(new_style) => {
+                if !current.is_empty() {
+                    spans.push(FormattedSpan { text: std::mem::take(&mut current), style });
+                }
+                style = new_style;
+            }
+            None => current.push(c),
+        }
+        previous = Some(c);
+    }
+    if !current.is_empty() {
+        spans.push(FormattedSpan { text: current, style });
+    }
+    spans
+}
+
+// Pieces of the source between blank lines, which are formatted on their own. Blank lines in
+// code blocks don't split them
+fn chunks(markdown: &str) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut in_code = false;
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+        if line.trim().is_empty() && !in_code {
+            if !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        current.push_str(line);
+        current.push('\n');
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+// Formats a whole markdown text at once, for short texts like hover documentation
+pub fn format(markdown: &str, base_dir: &Path) -> Vec<Element> {
+    chunks(markdown).iter().flat_map(|chunk| format_chunk(chunk, base_dir)).collect()
+}
+
+fn format_chunk(chunk: &str, base_dir: &Path) -> Vec<Element> {
+    let mut elements = Vec::new();
+    let mut paragraph: Vec<String> = Vec::new();
+    let mut code: Option<(String, String)> = None;
+    let flush = |paragraph: &mut Vec<String>, elements: &mut Vec<Element>| {
+        if !paragraph.is_empty() {
+            elements.push(Element::Paragraph(inline(&paragraph.join(" "))));
+            paragraph.clear();
+        }
+    };
+    for line in chunk.lines() {
+        if let Some(fence) = line.trim_start().strip_prefix("```") {
+            match code.take() {
+                Some((language, text)) => elements.push(Element::CodeBlock { language, text }),
+                None => {
+                    flush(&mut paragraph, &mut elements);
+                    code = Some((fence.trim().to_string(), String::new()));
+                }
+            }
+            continue;
+        }
+        if let Some((_, text)) = &mut code {
+            text.push_str(line);
+            text.push('\n');
+            continue;
+        }
+
+        let images: Vec<Element> = image_pattern()
+            .captures_iter(line)
+            .filter(|captures| !captures[2].contains("://"))
+            .map(|captures| Element::Image { path: base_dir.join(&captures[2]), alt: captures[1].to_string() })
+            .collect();
+        let hashes = line.len() - line.trim_start_matches('#').len();
+        if (1..=6).contains(&hashes) && line[hashes..].starts_with(' ') {
+            flush(&mut paragraph, &mut elements);
+            let text = image_pattern().replace_all(line[hashes..].trim(), "$1");
+            elements.push(Element::Heading { level: hashes, spans: inline(&text) });
+        } else if let Some(captures) = list_pattern().captures(line) {
+            flush(&mut paragraph, &mut elements);
+            let marker = match &captures[2] {
+                "-" | "*" | "+" => "•".to_string(),
+                number => number.to_string(),
+            };
+            let text = image_pattern().replace_all(&captures[3], "$1");
+            elements.push(Element::ListItem { depth: captures[1].len() / 2, marker, spans: inline(&text) });
+        } else if images.len() == 1 && image_pattern().replace(line.trim(), "").is_empty() {
+            // A line with nothing but an image shows just the image
+            flush(&mut paragraph, &mut elements);
+        } else {
+            // Images in the middle of text are shown after the paragraph, with the alt text in their place
+            paragraph.push(image_pattern().replace_all(line.trim(), "$1").into_owned());
+            if !images.is_empty() {
+                flush(&mut paragraph, &mut elements);
+            }
+        }
+        elements.extend(images);
+    }
+    flush(&mut paragraph, &mut elements);
+    // An unclosed code block runs to the end of the chunk
+    if let Some((language, text)) = code {
+        elements.push(Element::CodeBlock { language, text });
+    }
+    elements
+}
+
+// The formatted preview of one markdown buffer. Only the chunks of the source that changed since
+// the last update are formatted again
+#[derive(Debug, Clone)]
+pub struct PreviewPane {
+    pub source: PathBuf,
+    base_dir: PathBuf,
+    chunks: Vec<(String, Vec<Element>)>,
+}
+
+impl PreviewPane {
+    pub fn new(source: &Path) -> PreviewPane {
+        let base_dir = source.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+        PreviewPane { source: source.to_owned(), base_dir, chunks: Vec::new() }
+    }
+
+    // Should be called whenever the source buffer changes. Returns how many chunks were formatted
+    pub fn update(&mut self, markdown: &str) -> usize {
+        let mut old: HashMap<String, Vec<Element>> = std::mem::take(&mut self.chunks).into_iter().collect();
+        let mut formatted = 0;
+        self.chunks = chunks(markdown)
+            .into_iter()
+            .map(|chunk| {
+                let elements = old.remove(&chunk).unwrap_or_else(|| {
+                    formatted += 1;
+                    format_chunk(&chunk, &self.base_dir)
+                });
+                (chunk, elements)
+            })
+            .collect();
+        formatted
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.chunks.iter().flat_map(|(_, elements)| elements)
+    }
+
+    // The elements as pieces of text for the renderer to lay out one below the other
+    pub fn pieces(&self) -> Vec<Piece> {
+        pieces(self.elements())
+    }
+}
+
+// Elements as pieces of text to lay out one below the other. Images keep their alt text for when
+// they can't be loaded
+pub fn pieces<'e>(elements: impl IntoIterator<Item = &'e Element>) -> Vec<Piece> {
+    elements
+        .into_iter()
+        .map(|element| match element {
+            Element::Heading { level, spans } => Piece::of_spans("", spans, heading_scale(*level), 0),
+            Element::Paragraph(spans) => Piece::of_spans("", spans, 1., 0),
+            Element::ListItem { depth, marker, spans } => Piece::of_spans(&format!("{marker} "), spans, 1., depth * 2),
+            Element::CodeBlock { text, .. } => {
+                let text = text.trim_end_matches('\n').to_string();
+                let styles = vec![(0..text.len(), SpanStyle { code: true, ..SpanStyle::default() })];
+                Piece { text, styles, scale: 1., indent: 0, code_block: true, image: None }
+            }
+            Element::Image { path, alt } => {
+                let text = format!("[{alt}]");
+                let styles = vec![(0..text.len(), SpanStyle { italic: true, ..SpanStyle::default() })];
+                Piece { text, styles, scale: 1., indent: 0, code_block: false, image: Some(path.clone()) }
+            }
+        })
+        .collect()
+}
+
+// Text of one element of the pane, in one size
+#[derive(Debug, Clone, PartialEq)]
+pub struct Piece {
+    pub text: String,
+    // Byte ranges of `text` and how they're styled, in order
+    pub styles: Vec<(Range<usize>, SpanStyle)>,
+    // Of the font size
+    pub scale: f32,
+    // In characters
+    pub indent: usize,
+    // Drawn on a background of its own
+    pub code_block: bool,
+    // Drawn in place of the text, when it loads
+    pub image: Option<PathBuf>,
+}
+
+impl Piece {
+    fn of_spans(prefix: &str, spans: &[FormattedSpan], scale: f32, indent: usize) -> Piece {
+        let mut text = prefix.to_string();
+        let mut styles = vec![(0..text.len(), SpanStyle::default())];
+        for span in spans {
+            styles.push((text.len()..text.len() + span.text.len(), span.style));
+            text.push_str(&span.text);
+        }
+        Piece { text, styles, scale, indent, code_block: false, image: None }
+    }
+}
+
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "md" || ext == "markdown")
+}
+
+// What :preview needs from the editor
+pub trait PreviewHost {
+    // Path and text of the focused buffer
+    fn current_buffer(&self) -> Option<(PathBuf, String)>;
+    // Shows the pane in a split next to the focused one
+    fn open_split(&mut self, pane: PreviewPane);
+    // Closes the pane if one is open, and says whether one was
+    fn close_split(&mut self) -> bool;
+}
+
+// Opens the preview of the focused buffer, or closes the one that's open
+fn preview_command<Ctx: PreviewHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    if ctx.close_split() {
+        return Ok(());
+    }
+    let (path, text) = ctx.current_buffer().ok_or("No buffer to preview")?;
+    if !is_markdown(&path) {
+        return Err(format!("{} is not a markdown file", path.display()));
+    }
+    let mut pane = PreviewPane::new(&path);
+    pane.update(&text);
+    ctx.open_split(pane);
+    Ok(())
+}
+
+pub fn register<Ctx: PreviewHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("preview", preview_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headings_and_images() {
+        let markdown = "# Title\n\nSee ![the logo](img/logo.png \"Logo\") and ![remote](https://x.org/a.png)\n####### not a heading\n#hashtag";
+        let preview = preview(markdown, Path::new("/docs"));
+        assert_eq!(preview.text, "Title\n\nSee the logo and remote\n####### not a heading\n#hashtag");
+        assert_eq!(preview.images, vec![(2, PathBuf::from("/docs/img/logo.png"))]);
+
+        let blocks = preview.blocks(|_| Some((800, 400)), 400.);
+        assert_eq!(blocks, vec![Block { after_line: 2, width: 400., height: 200. }]);
+        assert!(preview.blocks(|_| None, 400.).is_empty());
+    }
+
+    fn span(text: &str, bold: bool, italic: bool, code: bool) -> FormattedSpan {
+        FormattedSpan { text: text.to_string(), style: SpanStyle { bold, italic, code } }
+    }
+
+    #[test]
+    fn inline_styles() {
+        assert_eq!(inline("a **b *c*** `d*e` snake_case _f_"), vec![
+            span("a ", false, false, false),
+            span("b ", true, false, false),
+            span("c", true, true, false),
+            span(" ", false, false, false),
+            span("d*e", false, false, true),
+            span(" snake_case ", false, false, false),
+            span("f", false, true, false),
+        ]);
+    }
+
+    #[test]
+    fn formats_blocks() {
+        let markdown = "## Intro\nSome *text*\nwrapped.\n\n- one\n  2. two\n\n```rust\nfn main() {}\n\nlet x;\n```\n\n![logo](logo.png)";
+        let mut pane = PreviewPane::new(Path::new("/docs/README.md"));
+        assert_eq!(pane.update(markdown), 4);
+        assert_eq!(pane.elements().cloned().collect::<Vec<_>>(), vec![
+            Element::Heading { level: 2, spans: vec![span("Intro", false, false, false)] },
+            Element::Paragraph(vec![span("Some ", false, false, false), span("text", false, true, false), span(" wrapped.", false, false, false)]),
+            Element::ListItem { depth: 0, marker: "•".to_string(), spans: vec![span("one", false, false, false)] },
+            Element::ListItem { depth: 1, marker: "2.".to_string(), spans: vec![span("two", false, false, false)] },
+            Element::CodeBlock { language: "rust".to_string(), text: "fn main() {}\n\nlet x;\n".to_string() },
+            Element::Image { path: PathBuf::from("/docs/logo.png"), alt: "logo".to_string() },
+        ]);
+
+        let pieces = pane.pieces();
+        assert_eq!((pieces[0].text.as_str(), pieces[0].scale), ("Intro", 1.5));
+        assert_eq!((pieces[3].text.as_str(), pieces[3].indent), ("2. two", 2));
+        assert_eq!(pieces[3].styles, vec![(0..3, SpanStyle::default()), (3..6, SpanStyle::default())]);
+        assert!(pieces[4].code_block && pieces[4].text.ends_with("let x;"));
+        assert_eq!((pieces[5].text.as_str(), pieces[5].image.as_deref()), ("[logo]", Some(Path::new("/docs/logo.png"))));
+
+        // Editing one paragraph only formats that one again
+        assert_eq!(pane.update(&markdown.replace("wrapped", "changed")), 1);
+        assert_eq!(pane.update(&markdown.replace("wrapped", "changed")), 0);
+    }
+
+    struct Host {
+        buffer: Option<(PathBuf, String)>,
+        splits: Vec<PreviewPane>,
+    }
+
+    impl PreviewHost for Host {
+        fn current_buffer(&self) -> Option<(PathBuf, String)> {
+            self.buffer.clone()
+        }
+
+        fn open_split(&mut self, pane: PreviewPane) {
+            self.splits.push(pane);
+        }
+
+        fn close_split(&mut self) -> bool {
+            self.splits.pop().is_some()
+        }
+    }
+
+    #[test]
+    fn preview_command_opens_split() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut host = Host { buffer: Some((PathBuf::from("notes.txt"), String::new())), splits: Vec::new() };
+        assert!(registry.run(&mut host, "preview").is_err());
+        host.buffer = Some((PathBuf::from("notes.md"), "# Hi".to_string()));
+        registry.run(&mut host, "preview").unwrap();
+        assert_eq!(host.splits[0].elements().count(), 1);
+        registry.run(&mut host, "preview").unwrap();
+        assert!(host.splits.is_empty());
+    }
+}
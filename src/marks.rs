@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// Adjusts a byte offset for an edit that replaced `replaced` with `new_len` bytes.
+// Positions inside the replaced text move to its start
+pub fn shift_through_edit(pos: usize, replaced: &Range<usize>, new_len: usize) -> usize {
+    if pos < replaced.start {
+        pos
+    } else if pos >= replaced.end {
+        pos - replaced.len() + new_len
+    } else {
+        replaced.start
+    }
+}
+
+// Marks set with m{a-z}, local to a buffer
+#[derive(Debug, Default, Clone)]
+pub struct Marks {
+    marks: BTreeMap<char, usize>,
+}
+
+impl Marks {
+    pub fn set(&mut self, name: char, at: usize) {
+        self.marks.insert(name, at);
+    }
+
+    pub fn get(&self, name: char) -> Option<usize> {
+        self.marks.get(&name).copied()
+    }
+
+    pub fn remove(&mut self, name: char) {
+        self.marks.remove(&name);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.marks.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (char, usize)> + '_ {
+        self.marks.iter().map(|(&name, &at)| (name, at))
+    }
+
+    // Must be called for every edit to the buffer, so marks stay on the text they were set on
+    pub fn apply_edit(&mut self, replaced: Range<usize>, new_len: usize) {
+        for pos in self.marks.values_mut() {
+            *pos = shift_through_edit(*pos, &replaced, new_len);
+        }
+    }
+
+    // Marks on each line, for the gutter. `line_starts` are the byte offsets where each line starts
+    pub fn by_line(&self, line_starts: &[usize]) -> BTreeMap<usize, Vec<char>> {
+        let mut lines: BTreeMap<usize, Vec<char>> = BTreeMap::new();
+        for (&name, &pos) in &self.marks {
+            let line = line_starts.partition_point(|&start| start <= pos).saturating_sub(1);
+            lines.entry(line).or_default().push(name);
+        }
+        lines
+    }
+}
+
+// Uppercase marks, which remember the file they were set in so they can be jumped to from any buffer
+#[derive(Debug, Default, Clone)]
+pub struct GlobalMarks {
+    marks: BTreeMap<char, (PathBuf, usize)>,
+}
+
+impl GlobalMarks {
+    pub fn set(&mut self, name: char, path: &Path, at: usize) {
+        self.marks.insert(name, (path.to_owned(), at));
+    }
+
+    pub fn get(&self, name: char) -> Option<(&Path, usize)> {
+        self.marks.get(&name).map(|(path, at)| (path.as_path(), *at))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.marks.is_empty()
+    }
+
+    pub fn apply_edit(&mut self, path: &Path, replaced: Range<usize>, new_len: usize) {
+        for (mark_path, pos) in self.marks.values_mut() {
+            if mark_path == path {
+                *pos = shift_through_edit(*pos, &replaced, new_len);
+            }
+        }
+    }
+
+    pub fn in_file(&self, path: &Path) -> Marks {
+        let mut marks = Marks::default();
+        for (&name, (mark_path, pos)) in &self.marks {
+            if mark_path == path {
+                marks.set(name, *pos);
+            }
+        }
+        marks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_follow_edits() {
+        let mut marks = Marks::default();
+        marks.set('a', 10);
+        marks.set('b', 20);
+        marks.set('c', 2);
+        // Replace 5..12 with 3 bytes
+        marks.apply_edit(5..12, 3);
+        assert_eq!(marks.get('a'), Some(5));
+        assert_eq!(marks.get('b'), Some(16));
+        assert_eq!(marks.get('c'), Some(2));
+
+        let lines = marks.by_line(&[0, 4, 15]);
+        assert_eq!(lines[&0], vec!['c']);
+        assert_eq!(lines[&1], vec!['a']);
+        assert_eq!(lines[&2], vec!['b']);
+    }
+}
@@ -0,0 +1,189 @@
+// Where the memory goes. The editor adds up what it holds by category, `:memory` shows it, and
+// when the total goes over the budget the caches, which can always be rebuilt, are dropped
+
+use serde::Deserialize;
+
+use crate::commands::Registry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    // Buffer contents
+    Text,
+    Undo,
+    // Laid out glyph instances kept around for scrolling
+    Layout,
+    // Glyph atlas textures
+    Atlas,
+    // Font files, which are never freed
+    Fonts,
+}
+
+impl Category {
+    pub const ALL: [Category; 5] = [Category::Text, Category::Undo, Category::Layout, Category::Atlas, Category::Fonts];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Text => "text",
+            Category::Undo => "undo history",
+            Category::Layout => "layout caches",
+            Category::Atlas => "glyph atlas",
+            Category::Fonts => "font data",
+        }
+    }
+
+    // Whether trimming can get it back
+    pub fn is_cache(self) -> bool {
+        matches!(self, Category::Layout | Category::Atlas)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Usage {
+    // What holds the memory, like a buffer's name, and how many bytes
+    pub items: Vec<(Category, String, usize)>,
+}
+
+impl Usage {
+    pub fn add(&mut self, category: Category, what: impl Into<String>, bytes: usize) {
+        self.items.push((category, what.into(), bytes));
+    }
+
+    pub fn category(&self, category: Category) -> usize {
+        self.items.iter().filter(|(c, _, _)| *c == category).map(|(_, _, bytes)| bytes).sum()
+    }
+
+    pub fn total(&self) -> usize {
+        self.items.iter().map(|(_, _, bytes)| bytes).sum()
+    }
+
+    pub fn report(&self, config: &MemoryConfig) -> String {
+        let mut text = format!("{} in use, budget {}\n", human_bytes(self.total()), human_bytes(config.budget()));
+        for category in Category::ALL {
+            text += &format!("\n{:<14} {:>10}\n", category.name(), human_bytes(self.category(category)));
+            let mut items: Vec<_> = self.items.iter().filter(|(c, _, _)| *c == category).collect();
+            items.sort_by_key(|(_, _, bytes)| std::cmp::Reverse(*bytes));
+            for (_, what, bytes) in items {
+                text += &format!("  {what:<12} {:>10}\n", human_bytes(*bytes));
+            }
+        }
+        text
+    }
+}
+
+pub fn human_bytes(bytes: usize) -> String {
+    match bytes {
+        0..=1023 => format!("{bytes}B"),
+        1024..=1048575 => format!("{:.1}KB", bytes as f64 / 1024.),
+        _ => format!("{:.1}MB", bytes as f64 / 1024. / 1024.),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryConfig {
+    pub budget_mb: usize,
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        MemoryConfig { budget_mb: 512 }
+    }
+}
+
+impl MemoryConfig {
+    pub fn budget(&self) -> usize {
+        self.budget_mb * 1024 * 1024
+    }
+}
+
+// What :memory needs from the editor
+pub trait MemoryHost {
+    fn memory_usage(&self) -> Usage;
+    // Drops the layout caches and the glyph atlas
+    fn trim_caches(&mut self);
+    fn memory_config(&self) -> &MemoryConfig;
+    // Shows `text` in a scratch buffer named `title`
+    fn show_report(&mut self, title: &str, text: String);
+}
+
+// Trims the caches if over budget, returning a warning if that wasn't enough. Called every now and then
+pub fn enforce_budget(host: &mut impl MemoryHost) -> Option<String> {
+    let budget = host.memory_config().budget();
+    let usage = host.memory_usage();
+    if usage.total() <= budget {
+        return None;
+    }
+    if Category::ALL.iter().any(|category| category.is_cache() && usage.category(*category) > 0) {
+        host.trim_caches();
+    }
+    let total = host.memory_usage().total();
+    (total > budget).then(|| format!("Using {} with the caches trimmed, over the budget of {}", human_bytes(total), human_bytes(budget)))
+}
+
+// memory [trim]
+fn memory_command<Ctx: MemoryHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    match args {
+        [] => {}
+        ["trim"] => ctx.trim_caches(),
+        _ => return Err("Usage: memory [trim]".to_string()),
+    }
+    let text = ctx.memory_usage().report(ctx.memory_config());
+    ctx.show_report("*memory*", text);
+    Ok(())
+}
+
+pub fn register<Ctx: MemoryHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("memory", memory_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Editor {
+        text: usize,
+        atlas: usize,
+        config: MemoryConfig,
+        report: String,
+    }
+
+    impl MemoryHost for Editor {
+        fn memory_usage(&self) -> Usage {
+            let mut usage = Usage::default();
+            usage.add(Category::Text, "main.rs", self.text);
+            usage.add(Category::Atlas, "coverage", self.atlas);
+            usage
+        }
+
+        fn trim_caches(&mut self) {
+            self.atlas = 0;
+        }
+
+        fn memory_config(&self) -> &MemoryConfig {
+            &self.config
+        }
+
+        fn show_report(&mut self, _title: &str, text: String) {
+            self.report = text;
+        }
+    }
+
+    #[test]
+    fn trims_over_budget() {
+        let mut editor = Editor { text: 300 * 1024, atlas: 2 * 1024 * 1024, config: MemoryConfig { budget_mb: 4 }, report: String::new() };
+        assert_eq!(enforce_budget(&mut editor), None);
+        assert_eq!(editor.atlas, 2 * 1024 * 1024);
+
+        let mut registry = Registry::default();
+        register(&mut registry);
+        registry.run(&mut editor, "memory").unwrap();
+        assert!(editor.report.starts_with("2.3MB in use, budget 4.0MB\n"));
+        assert!(editor.report.contains("\nglyph atlas         2.0MB\n  coverage          2.0MB\n"));
+
+        editor.config.budget_mb = 1;
+        assert_eq!(enforce_budget(&mut editor), None);
+        assert_eq!(editor.atlas, 0);
+        editor.text = 3 * 1024 * 1024;
+        assert_eq!(enforce_budget(&mut editor).unwrap(), "Using 3.0MB with the caches trimmed, over the budget of 1.0MB");
+    }
+}
@@ -0,0 +1,183 @@
+// The File, Edit and View menus of the native menu bar on macOS and Windows, for finding commands
+// without knowing their names. Every item runs a command line through the registry, toggles run
+// `toggle <option>` and get a checkmark when the option is on
+
+use crate::commands::Registry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Item {
+    // `accelerator` as muda parses it, like CmdOrCtrl+S, and only shown: the keys themselves are bound as usual
+    Command { label: &'static str, command: &'static str, accelerator: Option<&'static str> },
+    Toggle { label: &'static str, option: &'static str },
+    Separator,
+}
+
+impl Item {
+    // What the item runs, which is also its id in the native menu
+    pub fn command_line(&self) -> Option<String> {
+        match self {
+            Item::Command { command, .. } => Some(command.to_string()),
+            Item::Toggle { option, .. } => Some(format!("toggle {option}")),
+            Item::Separator => None,
+        }
+    }
+}
+
+const fn command(label: &'static str, command: &'static str) -> Item {
+    Item::Command { label, command, accelerator: None }
+}
+
+pub const MENUS: &[(&str, &[Item])] = &[
+    ("File", &[
+        Item::Command { label: "Save", command: "w", accelerator: Some("CmdOrCtrl+S") },
+        command("Open Recent…", "recent"),
+        command("Browse…", "browse"),
+        Item::Separator,
+        command("Save Session", "session-save"),
+        command("Load Session", "session-load"),
+    ]),
+    ("Edit", &[
+        command("Copy as HTML", "copy-as html"),
+        command("Copy as RTF", "copy-as rtf"),
+        Item::Separator,
+        command("Format", "format"),
+        command("Rename Symbol…", "rename"),
+        command("Code Actions…", "code-actions"),
+    ]),
+    ("View", &[
+        Item::Toggle { label: "Word Wrap", option: "wrap" },
+        Item::Toggle { label: "Line Numbers", option: "line-numbers" },
+        Item::Toggle { label: "Zen Mode", option: "zen" },
+        Item::Separator,
+        command("Outline", "outline"),
+        command("Symbols…", "symbols"),
+        command("Diagnostics", "diagnostics"),
+        command("File Tree", "tree"),
+    ]),
+];
+
+// What toggle needs from the editor
+pub trait OptionsHost {
+    // None for options that don't exist
+    fn option(&self, name: &str) -> Option<bool>;
+    fn set_option(&mut self, name: &str, on: bool);
+}
+
+// toggle <option>
+fn toggle_command<Ctx: OptionsHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let [name] = args else { return Err("Usage: toggle <option>".to_string()) };
+    let on = ctx.option(name).ok_or_else(|| format!("No option {name}"))?;
+    ctx.set_option(name, !on);
+    Ok(())
+}
+
+pub fn register<Ctx: OptionsHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("toggle", toggle_command::<Ctx>);
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+pub mod native {
+    use muda::accelerator::Accelerator;
+    use muda::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu};
+
+    use super::{Item, OptionsHost, MENUS};
+
+    pub struct MenuBar {
+        // Dropping the menu takes it off the window
+        _menu: Menu,
+        // With whether each is checked, to only touch the menu when an option changed
+        toggles: Vec<(&'static str, CheckMenuItem, bool)>,
+    }
+
+    impl MenuBar {
+        pub fn new(window: &winit::window::Window) -> muda::Result<MenuBar> {
+            let menu = Menu::new();
+            // macOS puts the first menu under the application's name
+            #[cfg(target_os = "macos")]
+            menu.append(&Submenu::with_items("rakoune", true, &[&PredefinedMenuItem::about(None, None), &PredefinedMenuItem::separator(), &PredefinedMenuItem::quit(None)])?)?;
+            let mut toggles = Vec::new();
+            for (title, items) in MENUS {
+                let submenu = Submenu::new(*title, true);
+                for item in *items {
+                    match (item, item.command_line()) {
+                        (Item::Command { label, accelerator, .. }, Some(id)) => {
+                            let accelerator = accelerator.and_then(|accelerator| accelerator.parse::<Accelerator>().ok());
+                            submenu.append(&MenuItem::with_id(id, *label, true, accelerator))?;
+                        }
+                        (Item::Toggle { label, option }, Some(id)) => {
+                            let check = CheckMenuItem::with_id(id, *label, true, false, None);
+                            submenu.append(&check)?;
+                            toggles.push((*option, check, false));
+                        }
+                        _ => submenu.append(&PredefinedMenuItem::separator())?,
+                    }
+                }
+                menu.append(&submenu)?;
+            }
+            #[cfg(target_os = "windows")]
+            {
+                use winit::platform::windows::WindowExtWindows;
+                menu.init_for_hwnd(window.hwnd())?;
+            }
+            #[cfg(target_os = "macos")]
+            {
+                let _ = window;
+                menu.init_for_nsapp();
+            }
+            Ok(MenuBar { _menu: menu, toggles })
+        }
+
+        // Command lines of the items picked since the last call
+        pub fn picked(&self) -> Vec<String> {
+            MenuEvent::receiver().try_iter().map(|event| event.id.0).collect()
+        }
+
+        // Puts the checkmarks where the options are on. Called after running commands
+        pub fn refresh(&mut self, host: &impl OptionsHost) {
+            for (option, check, checked) in &mut self.toggles {
+                let on = host.option(option).unwrap_or(false);
+                if on != *checked {
+                    check.set_checked(on);
+                    *checked = on;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct Options(HashMap<String, bool>);
+
+    impl OptionsHost for Options {
+        fn option(&self, name: &str) -> Option<bool> {
+            self.0.get(name).copied()
+        }
+
+        fn set_option(&mut self, name: &str, on: bool) {
+            self.0.insert(name.to_string(), on);
+        }
+    }
+
+    #[test]
+    fn items_run_commands() {
+        let mut registry = Registry::default();
+        register(&mut registry);
+        let mut options = Options(HashMap::from([("wrap".to_string(), false)]));
+        let view = MENUS.iter().find(|(title, _)| *title == "View").unwrap().1;
+        let wrap = view[0].command_line().unwrap();
+        registry.run(&mut options, &wrap).unwrap();
+        assert_eq!(options.option("wrap"), Some(true));
+        assert!(registry.run(&mut options, "toggle ligatures").is_err());
+
+        // Ids have to be unique for the native menu to tell items apart
+        let mut ids: Vec<String> = MENUS.iter().flat_map(|(_, items)| items.iter().filter_map(Item::command_line)).collect();
+        let count = ids.len();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), count);
+    }
+}
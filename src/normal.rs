@@ -0,0 +1,100 @@
+// Parsing of normal mode key sequences: count prefixes, keys after g, m and ' and repeating the last change
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub count: usize,
+    // 'g' for the keys after g, which are commands of their own like gj. 'm' and '\'' for the mark
+    // set or jumped to
+    pub prefix: Option<char>,
+    pub key: char,
+    // Text typed after the command, for commands that enter insert mode
+    pub inserted: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    // Waiting for more keys
+    Pending,
+    Run(Command),
+}
+
+#[derive(Debug, Default)]
+pub struct NormalMode {
+    count: Option<usize>,
+    // Set after g, m or ', until the key it prefixes
+    prefix: Option<char>,
+    // The last command that changed the buffer, replayed by '.'
+    last_change: Option<Command>,
+}
+
+impl NormalMode {
+    pub fn key(&mut self, key: char) -> Input {
+        if let Some(prefix) = self.prefix.take() {
+            return Input::Run(Command { count: self.count.take().unwrap_or(1), prefix: Some(prefix), key, inserted: String::new() });
+        }
+        if matches!(key, 'g' | 'm' | '\'') {
+            self.prefix = Some(key);
+            return Input::Pending;
+        }
+        if let Some(digit) = key.to_digit(10) {
+            // A leading 0 is a command of its own (go to start of line), not a count
+            if digit != 0 || self.count.is_some() {
+                let count = self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit as usize);
+                self.count = Some(count);
+                return Input::Pending;
+            }
+        }
+        let count = self.count.take();
+        if key == '.' {
+            return match &self.last_change {
+                Some(last) => Input::Run(Command {
+                    // A count given to '.' replaces the count of the repeated command
+                    count: count.unwrap_or(last.count),
+                    ..last.clone()
+                }),
+                None => Input::Pending,
+            };
+        }
+        Input::Run(Command { count: count.unwrap_or(1), prefix: None, key, inserted: String::new() })
+    }
+
+    // Called by whoever runs commands once a command has changed the buffer, so it can be repeated.
+    // For commands entering insert mode, this should be called when leaving insert mode, with the typed text
+    pub fn record_change(&mut self, command: Command) {
+        self.last_change = Some(command);
+    }
+
+    pub fn cancel(&mut self) {
+        self.count = None;
+        self.prefix = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(mode: &mut NormalMode, keys: &str) -> Vec<Input> {
+        keys.chars().map(|k| mode.key(k)).filter(|i| *i != Input::Pending).collect()
+    }
+
+    #[test]
+    fn counts_and_repeat() {
+        let mut mode = NormalMode::default();
+        assert_eq!(run(&mut mode, "5j0"), vec![
+            Input::Run(Command { count: 5, prefix: None, key: 'j', inserted: String::new() }),
+            Input::Run(Command { count: 1, prefix: None, key: '0', inserted: String::new() }),
+        ]);
+        assert_eq!(run(&mut mode, "."), vec![]);
+
+        let x = Command { count: 10, prefix: None, key: 'x', inserted: String::new() };
+        mode.record_change(x.clone());
+        assert_eq!(run(&mut mode, "."), vec![Input::Run(x.clone())]);
+        assert_eq!(run(&mut mode, "3."), vec![Input::Run(Command { count: 3, ..x })]);
+        assert_eq!(run(&mut mode, "2gj"), vec![Input::Run(Command { count: 2, prefix: Some('g'), key: 'j', inserted: String::new() })]);
+        assert_eq!(run(&mut mode, "ma'a"), vec![
+            Input::Run(Command { count: 1, prefix: Some('m'), key: 'a', inserted: String::new() }),
+            Input::Run(Command { count: 1, prefix: Some('\''), key: 'a', inserted: String::new() }),
+        ]);
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+use crate::commands::Registry;
+use crate::crash;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    // Errors stay longer, since they usually need doing something about
+    pub fn timeout(self) -> Duration {
+        match self {
+            Severity::Info => Duration::from_secs(3),
+            Severity::Warning => Duration::from_secs(5),
+            Severity::Error => Duration::from_secs(8),
+        }
+    }
+
+    // Linear RGBA of the toast's accent
+    pub fn color(self) -> [f32; 4] {
+        match self {
+            Severity::Info => [0.3, 0.5, 0.9, 1.],
+            Severity::Warning => [0.9, 0.7, 0.2, 1.],
+            Severity::Error => [0.9, 0.25, 0.2, 1.],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub severity: Severity,
+    pub text: String,
+    pub at: Instant,
+}
+
+// Messages for the user: shown as toasts in the top right corner and in the status line for a
+// while, and kept for :messages. Errors from commands, config, fonts and language servers all end
+// up here instead of on stderr
+#[derive(Debug, Default)]
+pub struct Notifications {
+    pub log: Vec<Message>,
+    // Indices into `log` of the toasts still showing
+    toasts: Vec<usize>,
+    // Also print to stderr, for when there is no window to show toasts in
+    pub echo: bool,
+}
+
+impl Notifications {
+    pub fn notify(&mut self, severity: Severity, text: impl Into<String>) {
+        let text = text.into();
+        if self.echo {
+            eprintln!("{text}");
+        }
+        crash::log(&text);
+        self.toasts.push(self.log.len());
+        self.log.push(Message { severity, text, at: Instant::now() });
+    }
+
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.notify(Severity::Info, text)
+    }
+
+    pub fn warn(&mut self, text: impl Into<String>) {
+        self.notify(Severity::Warning, text)
+    }
+
+    pub fn error(&mut self, text: impl Into<String>) {
+        self.notify(Severity::Error, text)
+    }
+
+    // The error from `result`, if any, returning the value otherwise
+    pub fn report<T, E: Display>(&mut self, result: Result<T, E>) -> Option<T> {
+        result.map_err(|e| self.error(e.to_string())).ok()
+    }
+
+    // Toasts to draw, newest first
+    pub fn toasts(&self, now: Instant) -> impl Iterator<Item = &Message> {
+        self.toasts
+            .iter()
+            .rev()
+            .map(|&idx| &self.log[idx])
+            .filter(move |m| now.duration_since(m.at) < m.severity.timeout())
+    }
+
+    // For the status line, which only has room for one: the newest of the most severe still showing
+    pub fn latest(&self, now: Instant) -> Option<&Message> {
+        self.toasts(now).reduce(|newest, message| if message.severity > newest.severity { message } else { newest })
+    }
+
+    // Drops toasts that timed out. Returns true if any did, so there is something to redraw
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let before = self.toasts.len();
+        let log = &self.log;
+        self.toasts.retain(|&idx| now.duration_since(log[idx].at) < log[idx].severity.timeout());
+        self.toasts.len() != before
+    }
+
+    // When the next toast times out, to wake up for
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.toasts.iter().map(|&idx| self.log[idx].at + self.log[idx].severity.timeout()).min()
+    }
+
+    pub fn dismiss_all(&mut self) {
+        self.toasts.clear();
+    }
+
+    // The :messages buffer
+    pub fn messages_text(&self) -> String {
+        self.log
+            .iter()
+            .map(|m| {
+                let prefix = match m.severity {
+                    Severity::Info => "",
+                    Severity::Warning => "Warning: ",
+                    Severity::Error => "Error: ",
+                };
+                format!("{prefix}{}\n", m.text)
+            })
+            .collect()
+    }
+}
+
+// What :messages needs from the editor
+pub trait MessagesHost {
+    fn notifications(&mut self) -> &mut Notifications;
+    // Shows `text` in a scratch buffer named `title`
+    fn show_report(&mut self, title: &str, text: String);
+}
+
+// Runs a command line typed or bound to a key, showing what went wrong if it fails
+pub fn run_reporting<Ctx: MessagesHost>(registry: &Registry<Ctx>, ctx: &mut Ctx, command_line: &str) {
+    let result = registry.run(ctx, command_line);
+    ctx.notifications().report(result);
+}
+
+// messages [clear]
+fn messages_command<Ctx: MessagesHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    match args {
+        [] => {}
+        ["clear"] => {
+            let notifications = ctx.notifications();
+            notifications.dismiss_all();
+            notifications.log.clear();
+        }
+        _ => return Err("Usage: messages [clear]".to_string()),
+    }
+    let text = ctx.notifications().messages_text();
+    ctx.show_report("*messages*", text);
+    Ok(())
+}
+
+pub fn register<Ctx: MessagesHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("messages", messages_command::<Ctx>);
+    registry.set_help(|ctx, text| ctx.show_report("*help*", text));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toasts_expire_but_stay_in_log() {
+        let mut notifications = Notifications::default();
+        notifications.info("saved");
+        notifications.error("couldn't write");
+        let at = notifications.log[0].at;
+        assert_eq!(notifications.toasts(at).map(|m| m.text.as_str()).collect::<Vec<_>>(), vec!["couldn't write", "saved"]);
+        assert_eq!(notifications.next_expiry(), Some(at + Severity::Info.timeout()));
+
+        assert!(notifications.expire(at + Duration::from_secs(4)));
+        assert_eq!(notifications.toasts(at + Duration::from_secs(4)).count(), 1);
+        assert!(!notifications.expire(at + Duration::from_secs(4)));
+        assert_eq!(notifications.messages_text(), "saved\nError: couldn't write\n");
+    }
+
+    struct Editor {
+        notifications: Notifications,
+        shown: Option<String>,
+    }
+
+    impl MessagesHost for Editor {
+        fn notifications(&mut self) -> &mut Notifications {
+            &mut self.notifications
+        }
+
+        fn show_report(&mut self, _title: &str, text: String) {
+            self.shown = Some(text);
+        }
+    }
+
+    #[test]
+    fn command_errors_become_messages() {
+        let mut registry = Registry::<Editor>::default();
+        register(&mut registry);
+        let mut editor = Editor { notifications: Notifications::default(), shown: None };
+        editor.notifications.warn("no fallback font");
+        run_reporting(&registry, &mut editor, "frobnicate");
+        let now = editor.notifications.log[0].at;
+        // The error outranks both the warning before it and the info after it
+        editor.notifications.info("saved");
+        assert_eq!(editor.notifications.latest(now).unwrap().text, "Unknown command: frobnicate");
+
+        run_reporting(&registry, &mut editor, "messages");
+        assert_eq!(editor.shown.as_deref(), Some("Warning: no fallback font\nError: Unknown command: frobnicate\nsaved\n"));
+        run_reporting(&registry, &mut editor, "messages clear");
+        assert_eq!(editor.shown.as_deref(), Some(""));
+        assert_eq!(editor.notifications.latest(now), None);
+    }
+}
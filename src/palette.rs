@@ -0,0 +1,112 @@
+// :check-theme, for theme authors. Looks at the syntax colors of the active theme as they are and
+// as someone with protanopia or deuteranopia sees them, and reports faces that can't be read
+// against the background or that can't be told apart from each other
+
+use std::fmt;
+
+use crate::color::{contrast_ratio, difference, simulate, Deficiency};
+use crate::commands::Registry;
+
+// WCAG AA for text
+const MIN_CONTRAST: f32 = 4.5;
+// ΔE below which two faces are taken to look the same
+const MIN_DIFFERENCE: f32 = 10.;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    LowContrast { face: String, deficiency: Option<Deficiency>, ratio: f32 },
+    // Faces that only look different with full color vision
+    Alike { faces: (String, String), deficiency: Deficiency, difference: f32 },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::LowContrast { face, deficiency: None, ratio } => write!(f, "{face} has a contrast of {ratio:.1}:1 against the background"),
+            Problem::LowContrast { face, deficiency: Some(deficiency), ratio } => write!(f, "{face} has a contrast of {ratio:.1}:1 against the background with {}", deficiency.name()),
+            Problem::Alike { faces: (a, b), deficiency, difference } => write!(f, "{a} and {b} look alike with {} (ΔE {difference:.1})", deficiency.name()),
+        }
+    }
+}
+
+// Every problem with `palette`, a color per face, on `background`. What's wrong with full color
+// vision isn't repeated for each deficiency
+pub fn check(palette: &[(String, [f32; 4])], background: [f32; 4]) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for (face, color) in palette {
+        let ratio = contrast_ratio(*color, background);
+        if ratio < MIN_CONTRAST {
+            problems.push(Problem::LowContrast { face: face.clone(), deficiency: None, ratio });
+            continue;
+        }
+        for deficiency in Deficiency::ALL {
+            let ratio = contrast_ratio(simulate(deficiency, *color), simulate(deficiency, background));
+            if ratio < MIN_CONTRAST {
+                problems.push(Problem::LowContrast { face: face.clone(), deficiency: Some(deficiency), ratio });
+            }
+        }
+    }
+    for deficiency in Deficiency::ALL {
+        for (i, (a, a_color)) in palette.iter().enumerate() {
+            for (b, b_color) in &palette[i + 1..] {
+                // Faces sharing a color on purpose are fine
+                if difference(*a_color, *b_color) < MIN_DIFFERENCE {
+                    continue;
+                }
+                let seen = difference(simulate(deficiency, *a_color), simulate(deficiency, *b_color));
+                if seen < MIN_DIFFERENCE {
+                    problems.push(Problem::Alike { faces: (a.clone(), b.clone()), deficiency, difference: seen });
+                }
+            }
+        }
+    }
+    problems
+}
+
+pub fn report(problems: &[Problem]) -> String {
+    if problems.is_empty() {
+        return "No problems found\n".to_string();
+    }
+    problems.iter().map(|problem| format!("{problem}\n")).collect()
+}
+
+// What :check-theme needs from the editor
+pub trait PaletteHost {
+    // The syntax faces of the active theme and their colors
+    fn syntax_palette(&self) -> Vec<(String, [f32; 4])>;
+    fn theme_background(&self) -> [f32; 4];
+    // Shows `text` in a scratch buffer named `title`
+    fn show_report(&mut self, title: &str, text: String);
+}
+
+fn check_theme_command<Ctx: PaletteHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: check-theme".to_string());
+    }
+    let problems = check(&ctx.syntax_palette(), ctx.theme_background());
+    ctx.show_report("*theme check*", report(&problems));
+    Ok(())
+}
+
+pub fn register<Ctx: PaletteHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("check-theme", check_theme_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_faces_that_merge() {
+        let background = [0.1, 0.1, 0.1, 1.];
+        let face = |name: &str, color: [f32; 4]| (name.to_string(), color);
+        let palette = [face("keyword", [0.95, 0.35, 0.3, 1.]), face("string", [0.2, 0.5, 0.3, 1.]), face("function", [0.4, 0.6, 1., 1.]), face("comment", [0.3, 0.3, 0.3, 1.]), face("keyword.control", [0.95, 0.35, 0.3, 1.])];
+        let text = report(&check(&palette, background));
+        assert!(text.contains("comment has a contrast of 2.1:1 against the background\n"));
+        // Red gets darker without red cones
+        assert!(text.contains("keyword has a contrast of 3.8:1 against the background with protanopia\n"));
+        assert!(text.contains("keyword and string look alike with protanopia (ΔE 1.9)\n"));
+        assert!(!text.contains("deuteranopia") && !text.contains("function") && !text.contains("keyword and keyword.control"));
+        assert_eq!(report(&[]), "No problems found\n");
+    }
+}
@@ -0,0 +1,87 @@
+// Font sizes of single panes, so one split can be zoomed in without the others. Panes without a
+// size of their own follow the global one. The glyph atlas keys glyphs by size, so panes at
+// different sizes share it, each laying out with its own LayoutSettings
+
+use std::collections::HashMap;
+
+use crate::layout::{LayoutSettings, Rect};
+use crate::session::Layout;
+use crate::viewport::{MAX_FONT_SIZE, MIN_FONT_SIZE};
+
+// Size change of one notch of the scroll wheel
+const ZOOM_PER_LINE: f32 = 1.1;
+// Pixels of touchpad scrolling that zoom as much as one notch
+const PIXELS_PER_LINE: f32 = 50.;
+
+#[derive(Debug, Default)]
+pub struct PaneZoom {
+    // By pane index, as in session::Layout::Pane
+    sizes: HashMap<usize, f32>,
+    // From the accessibility config, raising MIN_FONT_SIZE
+    pub min_font_size: f32,
+}
+
+impl PaneZoom {
+    pub fn font_size(&self, pane: usize, global: f32) -> f32 {
+        self.sizes.get(&pane).copied().unwrap_or(global)
+    }
+
+    // What to lay out the pane with
+    pub fn settings(&self, pane: usize, global: &LayoutSettings) -> LayoutSettings {
+        LayoutSettings { font_size: self.font_size(pane, global.font_size), ..global.clone() }
+    }
+
+    // Scales the pane's size by `factor`, starting from the global size if it had none
+    pub fn scale(&mut self, pane: usize, global: f32, factor: f32) {
+        let size = self.font_size(pane, global) * factor;
+        self.sizes.insert(pane, size.clamp(self.min_font_size.max(MIN_FONT_SIZE), MAX_FONT_SIZE));
+    }
+
+    // Ctrl and scrolling, by notches of the wheel. Scrolling up zooms in
+    pub fn scroll(&mut self, pane: usize, global: f32, lines: f32) {
+        self.scale(pane, global, ZOOM_PER_LINE.powf(lines));
+    }
+
+    pub fn scroll_pixels(&mut self, pane: usize, global: f32, dy: f32) {
+        self.scroll(pane, global, dy / PIXELS_PER_LINE);
+    }
+
+    // Back to following the global size
+    pub fn reset(&mut self, pane: usize) {
+        self.sizes.remove(&pane);
+    }
+
+    // Forgets panes that were closed
+    pub fn retain(&mut self, open: impl Fn(usize) -> bool) {
+        self.sizes.retain(|&pane, _| open(pane));
+    }
+}
+
+// The pane at (x, y) when `layout` fills `area`
+pub fn pane_at(layout: &Layout, area: Rect, x: f32, y: f32) -> Option<usize> {
+    let contains = |rect: &Rect| x >= rect.x && x < rect.x + rect.w && y >= rect.y && y < rect.y + rect.h;
+    layout.rects(area).into_iter().find(|(_, rect)| contains(rect)).map(|(pane, _)| pane)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zooms_one_pane() {
+        let layout = Layout::Split { vertical: true, children: vec![(0.5, Layout::Pane(0)), (0.5, Layout::Pane(1))] };
+        let area = Rect { x: 0., y: 0., w: 200., h: 100. };
+        assert_eq!(pane_at(&layout, area, 150., 10.), Some(1));
+        assert_eq!(pane_at(&layout, area, 250., 10.), None);
+
+        let mut zoom = PaneZoom::default();
+        zoom.scroll(1, 20., 2.);
+        assert!((zoom.font_size(1, 20.) - 24.2).abs() < 1e-3);
+        assert_eq!(zoom.font_size(0, 30.), 30.);
+        assert!((zoom.settings(1, &LayoutSettings::default()).font_size - 24.2).abs() < 1e-3);
+        zoom.scroll_pixels(1, 20., -1e6);
+        assert_eq!(zoom.font_size(1, 20.), MIN_FONT_SIZE);
+        zoom.retain(|pane| pane == 0);
+        assert_eq!(zoom.font_size(1, 20.), 20.);
+    }
+}
@@ -0,0 +1,166 @@
+// Pastes, from the clipboard or typed in as characters by something replaying input. A paste of
+// any size is one edit and one undo step instead of one per character, and skips what typing a
+// character does (like closing brackets). Big ones are parsed again on another thread, keeping
+// the old bracket tree for highlighting until the new one is ready. Layout only ever covers the
+// visible lines, so it doesn't grow with the paste
+
+use std::ops::Range;
+use std::sync::mpsc;
+use std::sync::Arc;
+
+use crate::bracket_tree::{BracketTree, Grammar};
+use crate::insert;
+use crate::selection::Selection;
+use crate::undo::{Delta, History};
+
+// Bigger pastes are parsed on another thread
+pub const LARGE_PASTE: usize = 64 * 1024;
+// More characters than this between two frames can't have been typed by hand
+const BURST: usize = 16;
+// Bracketed paste, as terminals send it
+const PASTE_START: &str = "\u{1b}[200~";
+const PASTE_END: &str = "\u{1b}[201~";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Typed {
+    Char(char),
+    Paste(String),
+}
+
+// Sorts characters received between frames into typing and pastes
+#[derive(Debug, Default)]
+pub struct PasteDetector {
+    received: String,
+    // Inside bracketed paste markers, which may span frames
+    bracketed: Option<String>,
+}
+
+impl PasteDetector {
+    // winit's ReceivedCharacter
+    pub fn character(&mut self, c: char) {
+        match &mut self.bracketed {
+            Some(pasted) => {
+                pasted.push(c);
+                if let Some(rest) = pasted.strip_suffix(PASTE_END) {
+                    let rest = rest.to_string();
+                    self.bracketed = None;
+                    // Kept apart from typing around it by the markers
+                    self.received.push_str(PASTE_START);
+                    self.received.push_str(&rest);
+                    self.received.push_str(PASTE_END);
+                }
+            }
+            None => {
+                self.received.push(c);
+                if let Some(before) = self.received.strip_suffix(PASTE_START) {
+                    self.received.truncate(before.len());
+                    self.bracketed = Some(String::new());
+                }
+            }
+        }
+    }
+
+    // What was received since the last frame. A bracketed paste still coming in waits for its end
+    pub fn take(&mut self) -> Vec<Typed> {
+        let received = std::mem::take(&mut self.received);
+        let mut typed = Vec::new();
+        let mut rest = received.as_str();
+        while !rest.is_empty() {
+            let (before, after) = rest.split_once(PASTE_START).unwrap_or((rest, ""));
+            typed.extend(unbracketed(before));
+            let (pasted, after) = after.split_once(PASTE_END).unwrap_or((after, ""));
+            if !pasted.is_empty() {
+                typed.push(Typed::Paste(pasted.to_string()));
+            }
+            rest = after;
+        }
+        typed
+    }
+}
+
+fn unbracketed(received: &str) -> Vec<Typed> {
+    match received.chars().count() > BURST {
+        true => vec![Typed::Paste(received.to_string())],
+        false => received.chars().map(Typed::Char).collect(),
+    }
+}
+
+// Pastes `clips` at every selection as one edit, recorded as one undo step. Returns the replaced
+// range of the old text and how long it is now, for BracketTree::edit
+pub fn paste(text: &mut String, selections: &mut Vec<Selection>, history: &mut History, clips: &[String]) -> (Range<usize>, usize) {
+    let before = selections.clone();
+    let old = text.clone();
+    insert::paste(text, selections, clips);
+    // Everything from the first cursor to the last one changed, and nothing outside of it
+    let start = before.iter().map(|selection| selection.head).min().unwrap_or(0);
+    let end = before.iter().map(|selection| selection.head).max().unwrap_or(0);
+    let new_len = end + text.len() - old.len() - start;
+    history.record(vec![Delta::replace(&old, start..end, &text[start..start + new_len])], before, selections.clone());
+    (start..end, new_len)
+}
+
+// A bracket tree being made on another thread after a large paste
+pub struct Reparse {
+    // Which version of the text it's for, as counted by the caller
+    version: u64,
+    done: mpsc::Receiver<BracketTree>,
+}
+
+impl Reparse {
+    pub fn start(grammar: Arc<Grammar>, text: String, version: u64) -> Reparse {
+        let (tx, done) = mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = tx.send(BracketTree::new(grammar, &text));
+        });
+        Reparse { version, done }
+    }
+
+    // The new tree once it's ready. None for good if the text changed since, and the tree is out
+    // of date; the caller should start another
+    pub fn poll(&self, version: u64) -> Option<BracketTree> {
+        self.done.try_recv().ok().filter(|_| version == self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn pastes_are_one_edit() {
+        let mut detector = PasteDetector::default();
+        "ab".chars().for_each(|c| detector.character(c));
+        assert_eq!(detector.take(), vec![Typed::Char('a'), Typed::Char('b')]);
+        "x\u{1b}[200~fn main".chars().for_each(|c| detector.character(c));
+        // Waits for the end marker
+        assert_eq!(detector.take(), vec![Typed::Char('x')]);
+        "() {}\u{1b}[201~y".chars().for_each(|c| detector.character(c));
+        assert_eq!(detector.take(), vec![Typed::Paste("fn main() {}".to_string()), Typed::Char('y')]);
+        "let replayed = input;".chars().for_each(|c| detector.character(c));
+        assert_eq!(detector.take(), vec![Typed::Paste("let replayed = input;".to_string())]);
+
+        let mut text = "ab\ncd\n".to_string();
+        let mut selections = vec![Selection::cursor(1), Selection::cursor(4)];
+        let mut history = History::default();
+        let big = "x".repeat(LARGE_PASTE);
+        assert_eq!(paste(&mut text, &mut selections, &mut history, std::slice::from_ref(&big)), (1..4, 3 + 2 * LARGE_PASTE));
+        assert_eq!(text, format!("a{big}b\nc{big}d\n"));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.undo(&mut text), Some(vec![Selection::cursor(1), Selection::cursor(4)]));
+        assert_eq!(text, "ab\ncd\n");
+
+        let grammar = Arc::new(Grammar::parse(Path::new("rust.toml"), "name = \"rust\"\nextensions = [\"rs\"]\nbrackets = [\"{}\"]\n").unwrap());
+        let reparse = Reparse::start(grammar.clone(), "{}".repeat(LARGE_PASTE), 1);
+        let tree = loop {
+            if let Some(tree) = reparse.poll(1) {
+                break tree;
+            }
+            std::thread::yield_now();
+        };
+        assert_eq!(tree.root.children.len(), LARGE_PASTE);
+        let stale = Reparse::start(grammar, "{}".to_string(), 1);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(stale.poll(2).is_none());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::completion::fuzzy_score;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PickerEvent<T> {
+    // The selection moved, so this item should be applied temporarily
+    Preview(T),
+    // Enter was pressed, keep the item for good
+    Commit(T),
+    // Escape was pressed, go back to what was there before the picker opened
+    Revert(T),
+}
+
+// A filterable list where moving the selection live-previews the selected item, like picking a theme or font
+#[derive(Debug)]
+pub struct Picker<T> {
+    items: Vec<(String, T)>,
+    // Added to the match score of each item, and the order before anything is typed
+    boost: Vec<i64>,
+    original: T,
+    pub filter: String,
+    // Indices into items matching the filter, best first
+    pub shown: Vec<usize>,
+    pub selected: usize,
+}
+
+impl<T: Clone> Picker<T> {
+    pub fn new(items: Vec<(String, T)>, original: T) -> Picker<T> {
+        let mut picker = Picker { items, boost: Vec::new(), original, filter: String::new(), shown: Vec::new(), selected: 0 };
+        picker.refilter();
+        picker
+    }
+
+    // Ranks items by `boost` on top of how well they match, like recently used files in the finder
+    pub fn with_boost(mut self, boost: impl Fn(&T) -> i64) -> Picker<T> {
+        self.boost = self.items.iter().map(|(_, item)| boost(item)).collect();
+        self.refilter();
+        self
+    }
+
+    pub fn label(&self, shown_idx: usize) -> &str {
+        &self.items[self.shown[shown_idx]].0
+    }
+
+    // Labels of the items matching the filter, best first
+    pub fn shown_labels(&self) -> Vec<&str> {
+        self.shown.iter().map(|&idx| self.items[idx].0.as_str()).collect()
+    }
+
+    fn refilter(&mut self) {
+        let mut scored: Vec<(i64, usize)> = self.items
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, (label, _))| Some((fuzzy_score(&self.filter, label)? + self.boost.get(idx).copied().unwrap_or(0), idx)))
+            .collect();
+        if self.filter.is_empty() {
+            // Keep the given order when not searching for anything, unless boosted
+            scored.sort_by_key(|&(_, idx)| (-self.boost.get(idx).copied().unwrap_or(0), idx));
+        } else {
+            scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.cmp(b)));
+        }
+        self.shown = scored.into_iter().map(|(_, idx)| idx).collect();
+        self.selected = 0;
+    }
+
+    // The selected item, previewed when the picker opens before any key moves the selection
+    pub fn current(&self) -> Option<&T> {
+        let &idx = self.shown.get(self.selected)?;
+        Some(&self.items[idx].1)
+    }
+
+    fn preview(&self) -> Option<PickerEvent<T>> {
+        self.current().cloned().map(PickerEvent::Preview)
+    }
+
+    pub fn set_filter(&mut self, filter: &str) -> Option<PickerEvent<T>> {
+        self.filter = filter.to_string();
+        self.refilter();
+        self.preview()
+    }
+
+    pub fn move_selection(&mut self, delta: isize) -> Option<PickerEvent<T>> {
+        if self.shown.is_empty() {
+            return None;
+        }
+        let n = self.shown.len() as isize;
+        self.selected = (self.selected as isize + delta).rem_euclid(n) as usize;
+        self.preview()
+    }
+
+    pub fn enter(self) -> PickerEvent<T> {
+        match self.shown.get(self.selected) {
+            Some(&idx) => PickerEvent::Commit(self.items[idx].1.clone()),
+            None => PickerEvent::Revert(self.original),
+        }
+    }
+
+    pub fn escape(self) -> PickerEvent<T> {
+        PickerEvent::Revert(self.original)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn previews_commits_and_reverts() {
+        let items = vec![("gruvbox".to_string(), 1), ("solarized dark".to_string(), 2), ("solarized light".to_string(), 3)];
+        let mut picker = Picker::new(items.clone(), 0);
+        assert_eq!(picker.move_selection(1), Some(PickerEvent::Preview(2)));
+        assert_eq!(picker.move_selection(-2), Some(PickerEvent::Preview(3)));
+        assert_eq!(picker.escape(), PickerEvent::Revert(0));
+
+        let mut picker = Picker::new(items, 0);
+        assert_eq!(picker.set_filter("lig"), Some(PickerEvent::Preview(3)));
+        assert_eq!(picker.enter(), PickerEvent::Commit(3));
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::commands::Registry;
+
+const SPINNER: [char; 8] = ['⣾', '⣽', '⣻', '⢿', '⡿', '⣟', '⣯', '⣷'];
+const SPINNER_FRAME: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+struct Shared {
+    title: String,
+    done: AtomicU64,
+    // 0 while the amount of work isn't known, which shows a spinner without percentage
+    total: AtomicU64,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+}
+
+// Handed to the thread doing the work. Finishes when the last clone is dropped
+#[derive(Debug, Clone)]
+pub struct Progress(Arc<Shared>);
+
+impl Progress {
+    pub fn set_total(&self, total: u64) {
+        self.0.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, by: u64) {
+        self.0.done.fetch_add(by, Ordering::Relaxed);
+    }
+
+    // For work that reports how far along it is rather than what it just did
+    pub fn set_done(&self, done: u64) {
+        self.0.done.store(done, Ordering::Relaxed);
+    }
+
+    // Long running work should check this now and then, and stop early if set
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn finish(&self) {
+        self.0.finished.store(true, Ordering::Relaxed);
+    }
+
+    // Done so far and total, with total 0 if unknown
+    pub fn amount(&self) -> (u64, u64) {
+        (self.0.done.load(Ordering::Relaxed), self.0.total.load(Ordering::Relaxed))
+    }
+}
+
+// Keeps track of the background work going on, for the status line
+#[derive(Debug, Default)]
+pub struct ProgressTracker {
+    tasks: Vec<Progress>,
+}
+
+impl ProgressTracker {
+    pub fn start(&mut self, title: impl Into<String>) -> Progress {
+        let progress = Progress(Arc::new(Shared {
+            title: title.into(),
+            done: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            cancelled: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+        }));
+        self.tasks.push(progress.clone());
+        progress
+    }
+
+    // Leaves out finished tasks, including ones whose worker went away without calling finish
+    fn running(&self) -> impl Iterator<Item = &Progress> {
+        self.tasks.iter().filter(|task| !task.0.finished.load(Ordering::Relaxed) && Arc::strong_count(&task.0) > 1)
+    }
+
+    fn prune(&mut self) {
+        self.tasks = self.running().cloned().collect();
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.running().next().is_some()
+    }
+
+    // Asks tasks to stop: those whose title starts with `title`, or all of them
+    pub fn cancel(&mut self, title: Option<&str>) -> usize {
+        self.prune();
+        let matching = self.tasks.iter().filter(|task| title.is_none_or(|title| task.0.title.starts_with(title)));
+        let mut count = 0;
+        for task in matching {
+            task.0.cancelled.store(true, Ordering::Relaxed);
+            count += 1;
+        }
+        count
+    }
+
+    // Status line segment, like `⣾ Searching 40%`, with `(+2)` for further tasks. None when idle
+    pub fn status(&self, started: Instant, now: Instant) -> Option<String> {
+        let first = self.running().next()?;
+        let frame = (now.duration_since(started).as_millis() / SPINNER_FRAME.as_millis()) as usize % SPINNER.len();
+        let mut status = format!("{} {}", SPINNER[frame], first.0.title);
+        if let (done, total @ 1..) = first.amount() {
+            status.push_str(&format!(" {}%", (done * 100 / total).min(100)));
+        }
+        let more = self.running().count() - 1;
+        if more > 0 {
+            status.push_str(&format!(" (+{more})"));
+        }
+        Some(status)
+    }
+}
+
+// What :cancel needs from the editor
+pub trait ProgressHost {
+    fn progress(&mut self) -> &mut ProgressTracker;
+}
+
+// cancel [title], stopping the background work whose title starts with `title`, or all of it
+fn cancel_command<Ctx: ProgressHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let title = match args {
+        [] => None,
+        [title] => Some(*title),
+        _ => return Err("Usage: cancel [title]".to_string()),
+    };
+    match ctx.progress().cancel(title) {
+        0 => Err("Nothing to cancel".to_string()),
+        _ => Ok(()),
+    }
+}
+
+pub fn register<Ctx: ProgressHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("cancel", cancel_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_and_cancels() {
+        let mut tracker = ProgressTracker::default();
+        let now = Instant::now();
+        assert_eq!(tracker.status(now, now), None);
+
+        let search = tracker.start("Searching");
+        search.set_total(4);
+        search.advance(1);
+        let scan = tracker.start("Scanning project");
+        assert_eq!(tracker.status(now, now).unwrap(), "⣾ Searching 25% (+1)");
+
+        assert_eq!(tracker.cancel(Some("Scan")), 1);
+        assert!(scan.is_cancelled() && !search.is_cancelled());
+        drop(scan);
+        search.finish();
+        assert!(!tracker.is_busy());
+    }
+}
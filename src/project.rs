@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::commands::Registry;
+use crate::jobs::JobSpec;
+use crate::progress::Progress;
+
+pub const FILE_NAME: &str = ".rakoune.toml";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("{0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+}
+
+// Settings for everything under the directory the .rakoune.toml is in, layered over the user config:
+//
+//   format_on_save = true
+//   session = true
+//   exclude = ["target", "*.lock"]
+//
+//   [bind]
+//   "<C-b>" = "make build"
+//
+//   [jobs]
+//   build = "cargo build"
+//
+//   [format.rs]
+//   command = "rustfmt --edition 2021"
+//   on_save = true
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    // Unset leaves the user's setting alone
+    pub format_on_save: Option<bool>,
+    // Whether open files and the layout are kept per project rather than in the global session.
+    // Unset leaves the user's setting alone
+    pub session: Option<bool>,
+    // Paths the finder skips. A pattern matches a whole path component, with * matching any
+    // characters, or a path relative to the project root
+    pub exclude: Vec<String>,
+    // Key to command line, like `bind` in the user config
+    pub bind: HashMap<String, String>,
+    // Build and test commands, by name
+    pub jobs: HashMap<String, String>,
+    // By file extension
+    pub format: HashMap<String, FormatConfig>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormatConfig {
+    // Reads the text on stdin and writes it formatted to stdout. Unset formats with the language
+    // server
+    pub command: Option<String>,
+    // Overrides format_on_save for this filetype
+    pub on_save: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Project {
+    pub root: PathBuf,
+    pub config: ProjectConfig,
+    // Of the config file, to notice when a trusted project's config changes
+    pub hash: u64,
+}
+
+// Closest .rakoune.toml in the directory of `file` or one of its parents
+pub fn discover(file: &Path) -> Option<PathBuf> {
+    let start = if file.is_dir() { file } else { file.parent()? };
+    start.ancestors().map(|dir| dir.join(FILE_NAME)).find(|candidate| candidate.is_file())
+}
+
+// FNV-1a, which unlike DefaultHasher stays the same between Rust versions
+pub(crate) fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+fn matches_glob(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(name) = name.strip_prefix(prefix) else { return false };
+            (0..=name.len()).filter(|&at| name.is_char_boundary(at)).any(|at| matches_glob(rest, &name[at..]))
+        }
+    }
+}
+
+impl Project {
+    pub fn load(config_path: &Path) -> Result<Project, Error> {
+        let text = std::fs::read_to_string(config_path).map_err(|err| Error::Read(config_path.to_path_buf(), err))?;
+        let config = toml::from_str(&text).map_err(|err| Error::Parse(config_path.to_path_buf(), err))?;
+        Ok(Project {
+            root: config_path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+            config,
+            hash: hash(text.as_bytes()),
+        })
+    }
+
+    // Adds the project's bindings, replacing the user's for the same keys
+    pub fn apply<Ctx>(&self, registry: &mut Registry<Ctx>) {
+        for (key, command_line) in &self.config.bind {
+            registry.bind(key, command_line);
+        }
+    }
+
+    pub fn format_on_save(&self, user_setting: bool) -> bool {
+        self.config.format_on_save.unwrap_or(user_setting)
+    }
+
+    pub fn auto_session(&self, user_setting: bool) -> bool {
+        self.config.session.unwrap_or(user_setting)
+    }
+
+    fn format_config(&self, path: &Path) -> Option<&FormatConfig> {
+        self.config.format.get(path.extension()?.to_str()?)
+    }
+
+    // Like format_on_save, but for a file, whose filetype may say otherwise
+    pub fn format_file_on_save(&self, path: &Path, user_setting: bool) -> bool {
+        self.format_config(path).and_then(|config| config.on_save).unwrap_or(self.format_on_save(user_setting))
+    }
+
+    // External formatter for a file, run from the project root
+    pub fn formatter(&self, path: &Path) -> Option<&str> {
+        self.format_config(path)?.command.as_deref()
+    }
+
+    // Jobs run from the project root
+    pub fn job(&self, name: &str) -> Option<JobSpec> {
+        let command = self.config.jobs.get(name)?;
+        Some(JobSpec { name: name.to_string(), command: command.clone(), cwd: self.root.clone() })
+    }
+
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        self.config.exclude.iter().any(|pattern| {
+            relative.starts_with(pattern)
+                || relative.components().any(|c| c.as_os_str().to_str().is_some_and(|name| matches_glob(pattern, name)))
+        })
+    }
+
+    // What's in `dir` and isn't excluded, directories first and each sorted by name, with whether
+    // it's a directory. Shared by the finder and the file tree, so they skip the same things
+    pub fn list_dir(&self, dir: &Path) -> Vec<(PathBuf, bool)> {
+        let Ok(entries) = std::fs::read_dir(dir) else { return Vec::new() };
+        let paths = entries.flatten().map(|entry| entry.path()).filter(|path| !self.is_excluded(path));
+        let mut listed: Vec<(PathBuf, bool)> = paths.map(|path| {
+            let is_dir = path.is_dir();
+            (path, is_dir)
+        }).collect();
+        listed.sort_by(|(a, a_dir), (b, b_dir)| b_dir.cmp(a_dir).then_with(|| a.file_name().cmp(&b.file_name())));
+        listed
+    }
+
+    // Every file in the project, for the finder
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.list_files(None).unwrap_or_default()
+    }
+
+    // None once cancelled
+    fn list_files(&self, progress: Option<&Progress>) -> Option<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            if progress.is_some_and(Progress::is_cancelled) {
+                return None;
+            }
+            for (path, is_dir) in self.list_dir(&dir) {
+                match is_dir {
+                    true => dirs.push(path),
+                    false => files.push(path),
+                }
+            }
+            // How many files are found, as there's no knowing how many there are
+            if let Some(progress) = progress {
+                progress.set_done(files.len() as u64);
+            }
+        }
+        files.sort();
+        Some(files)
+    }
+
+    // Lists the files on another thread. Nothing comes if it's cancelled
+    pub fn scan(&self, progress: Progress) -> mpsc::Receiver<Vec<PathBuf>> {
+        let (tx, rx) = mpsc::channel();
+        let project = self.clone();
+        std::thread::spawn(move || {
+            if let Some(files) = project.list_files(Some(&progress)) {
+                let _ = tx.send(files);
+            }
+            progress.finish();
+        });
+        rx
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    Trusted,
+    // Never seen, or the config changed since it was trusted. Ask before applying it, since
+    // bindings can run arbitrary commands
+    Unknown,
+}
+
+// Projects whose config the user agreed to use, stored as `hash root` lines
+#[derive(Debug, Default)]
+pub struct TrustStore {
+    pub path: Option<PathBuf>,
+    trusted: HashMap<PathBuf, u64>,
+}
+
+impl TrustStore {
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_dir.join("rakoune").join("trusted"))
+    }
+
+    pub fn load(path: PathBuf) -> TrustStore {
+        let text = std::fs::read_to_string(&path).unwrap_or_default();
+        let trusted = text
+            .lines()
+            .filter_map(|line| {
+                let (hash, root) = line.split_once(' ')?;
+                Some((PathBuf::from(root), u64::from_str_radix(hash, 16).ok()?))
+            })
+            .collect();
+        TrustStore { path: Some(path), trusted }
+    }
+
+    pub fn check(&self, project: &Project) -> Trust {
+        match self.trusted.get(&project.root) {
+            Some(&hash) if hash == project.hash => Trust::Trusted,
+            _ => Trust::Unknown,
+        }
+    }
+
+    pub fn trust(&mut self, project: &Project) -> std::io::Result<()> {
+        self.trusted.insert(project.root.clone(), project.hash);
+        let Some(path) = &self.path else { return Ok(()) };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut lines: Vec<String> = self.trusted.iter().map(|(root, hash)| format!("{hash:016x} {}", root.display())).collect();
+        lines.sort();
+        std::fs::write(path, lines.join("\n") + "\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discovers_and_applies() {
+        let dir = std::env::temp_dir().join(format!("rakoune-project-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src/deep")).unwrap();
+        std::fs::write(dir.join(FILE_NAME), "format_on_save = true\nexclude = [\"target\", \"*.lock\"]\n[bind]\n\"<C-b>\" = \"make\"\n[jobs]\ntest = \"cargo test\"\n[format.md]\non_save = false\n[format.rs]\ncommand = \"rustfmt\"\n").unwrap();
+
+        let config_path = discover(&dir.join("src/deep/main.rs")).unwrap();
+        assert_eq!(config_path, dir.join(FILE_NAME));
+        let project = Project::load(&config_path).unwrap();
+        assert!(project.format_on_save(false));
+        assert!(project.auto_session(true));
+        assert!(project.format_file_on_save(Path::new("src/main.rs"), false));
+        assert!(!project.format_file_on_save(Path::new("README.md"), false));
+        assert_eq!(project.formatter(Path::new("src/main.rs")), Some("rustfmt"));
+        assert_eq!(project.formatter(Path::new("README.md")), None);
+        assert_eq!(project.job("test").unwrap().cwd, dir);
+        assert!(project.job("build").is_none());
+        assert!(project.is_excluded(&dir.join("target/debug/rakoune")));
+        assert!(project.is_excluded(&dir.join("Cargo.lock")));
+        assert!(!project.is_excluded(&dir.join("src/lock.rs")));
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join("src/lock.rs"), "").unwrap();
+        std::fs::write(dir.join("Cargo.lock"), "").unwrap();
+        assert_eq!(project.list_dir(&dir), vec![(dir.join("src"), true), (dir.join(FILE_NAME), false)]);
+        assert_eq!(project.files(), vec![dir.join(FILE_NAME), dir.join("src/lock.rs")]);
+
+        let mut registry = Registry::<()>::default();
+        registry.bind("<C-b>", "build");
+        project.apply(&mut registry);
+        assert_eq!(registry.binding("<C-b>"), Some("make"));
+
+        let mut store = TrustStore::load(dir.join("trusted"));
+        assert_eq!(store.check(&project), Trust::Unknown);
+        store.trust(&project).unwrap();
+        assert_eq!(TrustStore::load(dir.join("trusted")).check(&project), Trust::Trusted);
+
+        std::fs::write(dir.join(FILE_NAME), "format_on_save = false\n").unwrap();
+        let changed = Project::load(&config_path).unwrap();
+        assert_eq!(store.check(&changed), Trust::Unknown);
+
+        std::fs::write(dir.join(FILE_NAME), "unknown = 1\n").unwrap();
+        assert!(matches!(Project::load(&config_path), Err(Error::Parse(..))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+use std::path::Path;
+
+use crate::completion::fuzzy_score;
+
+// Things the ':' prompt can complete besides paths
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Sources<'a> {
+    pub commands: &'a [&'a str],
+    pub buffers: &'a [String],
+    pub themes: &'a [String],
+}
+
+fn ranked<'s>(pattern: &str, candidates: impl Iterator<Item = &'s str>) -> Vec<String> {
+    let mut scored: Vec<(i64, &str)> = candidates.filter_map(|c| Some((fuzzy_score(pattern, c)?, c))).collect();
+    scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.cmp(b)));
+    scored.dedup_by(|(_, a), (_, b)| a == b);
+    scored.into_iter().map(|(_, c)| c.to_string()).collect()
+}
+
+// Completes the last component of `partial` against the entries of the directory it's in.
+// Directories get a trailing slash, so completing again goes into them
+pub fn complete_path(partial: &str) -> Vec<String> {
+    let (dir, file) = match partial.rfind('/') {
+        Some(at) => (&partial[..at + 1], &partial[at + 1..]),
+        None => ("", partial),
+    };
+    let expanded_dir = match dir.strip_prefix("~/") {
+        Some(rest) => std::env::var("HOME").map(|home| format!("{home}/{rest}")).unwrap_or(dir.to_string()),
+        None => dir.to_string(),
+    };
+    let Ok(entries) = std::fs::read_dir(if expanded_dir.is_empty() { Path::new(".") } else { Path::new(&expanded_dir) }) else {
+        return Vec::new();
+    };
+    let names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let mut name = entry.file_name().into_string().ok()?;
+            // Hidden files only show up when asked for
+            if name.starts_with('.') && !file.starts_with('.') {
+                return None;
+            }
+            if entry.file_type().ok()?.is_dir() {
+                name.push('/');
+            }
+            Some(name)
+        })
+        .collect();
+    ranked(file, names.iter().map(String::as_str))
+        .into_iter()
+        .map(|name| format!("{dir}{name}"))
+        .collect()
+}
+
+// Candidates for the last word of a command line
+pub fn candidates(line: &str, sources: Sources) -> Vec<String> {
+    let command = line.split(' ').next().unwrap_or("");
+    let Some((_, last)) = line.rsplit_once(' ') else {
+        return ranked(command, sources.commands.iter().copied());
+    };
+    match command {
+        "theme" => ranked(last, sources.themes.iter().map(String::as_str)),
+        "b" | "buffer" => ranked(last, sources.buffers.iter().map(String::as_str)),
+        _ => complete_path(last),
+    }
+}
+
+// The text of the ':' prompt, with Tab cycling through completions of the last word
+#[derive(Debug, Default, Clone)]
+pub struct Prompt {
+    pub line: String,
+    // Candidates being cycled through, and which one is in the line right now
+    pub completions: Vec<String>,
+    pub selected: Option<usize>,
+    // Where the word being completed starts in the line
+    word_start: usize,
+}
+
+impl Prompt {
+    // Opened with `line` already typed
+    pub fn new(line: &str) -> Prompt {
+        Prompt { line: line.to_string(), ..Default::default() }
+    }
+
+    pub fn type_char(&mut self, ch: char) {
+        self.line.push(ch);
+        self.stop_completing();
+    }
+
+    pub fn backspace(&mut self) {
+        self.line.pop();
+        self.stop_completing();
+    }
+
+    fn stop_completing(&mut self) {
+        self.completions.clear();
+        self.selected = None;
+    }
+
+    // Moves to the next completion, or back by one if `backwards` (Shift+Tab)
+    pub fn tab(&mut self, sources: Sources, backwards: bool) {
+        if self.selected.is_none() {
+            self.completions = candidates(&self.line, sources);
+            self.word_start = self.line.rfind(' ').map_or(0, |at| at + 1);
+        }
+        if self.completions.is_empty() {
+            return;
+        }
+        let n = self.completions.len();
+        let next = match self.selected {
+            None if backwards => n - 1,
+            None => 0,
+            Some(i) if backwards => (i + n - 1) % n,
+            Some(i) => (i + 1) % n,
+        };
+        self.selected = Some(next);
+        self.line.truncate(self.word_start);
+        self.line.push_str(&self.completions[next]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycles_through_commands() {
+        let sources = Sources { commands: &["write", "wq", "quit"], ..Default::default() };
+        let mut prompt = Prompt::default();
+        prompt.type_char('w');
+        prompt.tab(sources, false);
+        assert_eq!(prompt.line, "wq");
+        prompt.tab(sources, false);
+        assert_eq!(prompt.line, "write");
+        prompt.tab(sources, true);
+        assert_eq!(prompt.line, "wq");
+    }
+
+    #[test]
+    fn completes_paths_and_themes() {
+        let themes = vec!["gruvbox".to_string(), "solarized".to_string()];
+        let sources = Sources { themes: &themes, ..Default::default() };
+        assert_eq!(candidates("theme sol", sources), vec!["solarized"]);
+
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/");
+        assert_eq!(complete_path(&format!("{dir}sr"))[0], format!("{dir}src/"));
+        assert!(complete_path(&format!("{dir}resources/fira")).contains(&format!("{dir}resources/firacode-regular.ttf")));
+    }
+}
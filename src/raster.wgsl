@@ -0,0 +1,61 @@
+struct Params {
+    // Size of the glyph bitmap in pixels
+    size: vec2<u32>,
+    // Output row length in u32s, each holding four pixels
+    row_words: u32,
+    segment_count: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+// Line segments of the flattened outline, from xy to zw, in bitmap pixels with y pointing down
+@group(0) @binding(1) var<storage, read> segments: array<vec4<f32>>;
+@group(0) @binding(2) var<storage, read_write> coverage_out: array<u32>;
+
+const SAMPLES: u32 = 4u;
+
+// Nonzero winding number of the outline around `point`
+fn winding(point: vec2<f32>) -> i32 {
+    var winding = 0;
+    for (var i = 0u; i < params.segment_count; i++) {
+        let a = segments[i].xy;
+        let b = segments[i].zw;
+        if (a.y <= point.y) != (b.y <= point.y) {
+            let x = a.x + (point.y - a.y) * (b.x - a.x) / (b.y - a.y);
+            if x > point.x {
+                winding += select(-1, 1, b.y > a.y);
+            }
+        }
+    }
+    return winding;
+}
+
+// Fraction of the pixel with top left corner `pixel` inside the outline, from a grid of samples
+fn coverage(pixel: vec2<f32>) -> f32 {
+    var inside = 0u;
+    for (var sy = 0u; sy < SAMPLES; sy++) {
+        for (var sx = 0u; sx < SAMPLES; sx++) {
+            let offset = (vec2<f32>(f32(sx), f32(sy)) + 0.5) / f32(SAMPLES);
+            if winding(pixel + offset) != 0 {
+                inside += 1u;
+            }
+        }
+    }
+    return f32(inside) / f32(SAMPLES * SAMPLES);
+}
+
+// Each invocation fills four horizontally adjacent pixels, packed into one u32 like R8 texels
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.row_words || id.y >= params.size.y {
+        return;
+    }
+    var packed = 0u;
+    for (var i = 0u; i < 4u; i++) {
+        let x = id.x * 4u + i;
+        if x < params.size.x {
+            let value = u32(round(coverage(vec2<f32>(f32(x), f32(id.y))) * 255.));
+            packed |= value << (8u * i);
+        }
+    }
+    coverage_out[id.y * params.row_words + id.x] = packed;
+}
@@ -0,0 +1,201 @@
+// Rename and code actions. Both end in a workspace edit from the language server, applied with
+// workspace_edit::apply_workspace_edit so one undo puts every buffer back
+//
+// :rename without a name opens the prompt with the word under the cursor to edit. Code actions
+// are listed in a picker, quick fixes first
+
+use serde_json::Value;
+
+use crate::commands::Registry;
+use crate::picker::{Picker, PickerEvent};
+use crate::workspace_edit::{self, EditHost, UndoGroup, WorkspaceEdit};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeAction {
+    pub title: String,
+    // Like "quickfix" or "refactor.extract"
+    pub kind: Option<String>,
+    // The server's pick when there are several fixes for the same problem
+    pub is_preferred: bool,
+    pub edit: Option<WorkspaceEdit>,
+    // A command for the server to run instead of, or after, the edit
+    pub command: Option<Command>,
+}
+
+// What workspace/executeCommand asks the server to run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    pub name: String,
+    pub arguments: Vec<Value>,
+}
+
+impl CodeAction {
+    fn is_quick_fix(&self) -> bool {
+        self.kind.as_deref().is_some_and(|kind| kind == "quickfix" || kind.starts_with("quickfix."))
+    }
+}
+
+// The menu of code actions at the cursor
+#[derive(Debug)]
+pub struct CodeActionMenu {
+    pub picker: Picker<Option<usize>>,
+    actions: Vec<CodeAction>,
+}
+
+impl CodeActionMenu {
+    // None if there are no actions, so the host can say so instead of opening an empty menu
+    pub fn new(mut actions: Vec<CodeAction>) -> Option<CodeActionMenu> {
+        if actions.is_empty() {
+            return None;
+        }
+        // Stable, so the server's order is kept otherwise
+        actions.sort_by_key(|action| (!action.is_quick_fix(), !action.is_preferred));
+        let items = actions.iter().enumerate().map(|(idx, action)| (action.title.clone(), Some(idx))).collect();
+        Some(CodeActionMenu { picker: Picker::new(items, None), actions })
+    }
+
+    // The selected action, to apply with apply_code_action. Moving the selection previews nothing
+    pub fn enter(self) -> Option<CodeAction> {
+        match self.picker.enter() {
+            PickerEvent::Commit(Some(idx)) => self.actions.into_iter().nth(idx),
+            _ => None,
+        }
+    }
+}
+
+// What rename and code actions need from the editor
+pub trait RefactorHost: EditHost {
+    fn word_at_cursor(&self) -> Option<String>;
+    // Opens the ':' prompt with `line` already typed
+    fn open_prompt(&mut self, line: &str);
+    // Asks the server of the focused buffer to rename the symbol at the cursor. The response goes
+    // to workspace_edit::apply_workspace_edit
+    fn request_rename(&mut self, new_name: &str) -> Result<(), String>;
+    // Asks for the code actions at the cursor. The response goes to CodeActionMenu::new
+    fn request_code_actions(&mut self) -> Result<(), String>;
+    // Asks the server to run a code action's command
+    fn execute_command(&mut self, command: &Command) -> Result<(), String>;
+}
+
+// Applies the action's edit, then runs its command
+pub fn apply_code_action(host: &mut impl RefactorHost, action: &CodeAction) -> Result<UndoGroup, String> {
+    let undo = match &action.edit {
+        Some(edit) => workspace_edit::apply_workspace_edit(host, edit).map_err(|e| e.to_string())?,
+        None => UndoGroup::default(),
+    };
+    if let Some(command) = &action.command {
+        host.execute_command(command)?;
+    }
+    Ok(undo)
+}
+
+fn rename_command<Ctx: RefactorHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    match args {
+        [] => {
+            let word = ctx.word_at_cursor().ok_or("Nothing to rename under the cursor")?;
+            ctx.open_prompt(&format!("rename {word}"));
+            Ok(())
+        }
+        [new_name] => ctx.request_rename(new_name),
+        _ => Err("Usage: rename [new name]".to_string()),
+    }
+}
+
+fn code_actions_command<Ctx: RefactorHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    ctx.request_code_actions()
+}
+
+pub fn register<Ctx: RefactorHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("rename", rename_command::<Ctx>);
+    registry.add_builtin("code-actions", code_actions_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::workspace_edit::{Position, TextEdit};
+
+    #[derive(Default)]
+    struct Editor {
+        text: String,
+        prompt: Option<String>,
+        renames: Vec<String>,
+        commands: Vec<String>,
+    }
+
+    impl EditHost for Editor {
+        fn buffer_text(&mut self, _path: &Path) -> Result<String, String> {
+            Ok(self.text.clone())
+        }
+
+        fn set_buffer_text(&mut self, _path: &Path, text: String, _edits: &[(Range<usize>, usize)]) {
+            self.text = text;
+        }
+    }
+
+    impl RefactorHost for Editor {
+        fn word_at_cursor(&self) -> Option<String> {
+            self.text.split_whitespace().next().map(str::to_string)
+        }
+
+        fn open_prompt(&mut self, line: &str) {
+            self.prompt = Some(line.to_string());
+        }
+
+        fn request_rename(&mut self, new_name: &str) -> Result<(), String> {
+            self.renames.push(new_name.to_string());
+            Ok(())
+        }
+
+        fn request_code_actions(&mut self) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn execute_command(&mut self, command: &Command) -> Result<(), String> {
+            self.commands.push(command.name.clone());
+            Ok(())
+        }
+    }
+
+    fn action(title: &str, kind: &str, is_preferred: bool) -> CodeAction {
+        CodeAction { title: title.to_string(), kind: Some(kind.to_string()), is_preferred, edit: None, command: None }
+    }
+
+    #[test]
+    fn rename_prompts_for_a_name() {
+        let mut registry = Registry::<Editor>::default();
+        register(&mut registry);
+        let mut editor = Editor { text: "count + 1".to_string(), ..Default::default() };
+        registry.run(&mut editor, "rename").unwrap();
+        assert_eq!(editor.prompt.as_deref(), Some("rename count"));
+        registry.run(&mut editor, "rename total").unwrap();
+        assert_eq!(editor.renames, vec!["total"]);
+        assert!(registry.run(&mut editor, "rename a b").is_err());
+    }
+
+    #[test]
+    fn quick_fixes_first_and_applied() {
+        let mut fix = action("Add missing import", "quickfix", false);
+        fix.edit = Some(WorkspaceEdit {
+            changes: vec![(PathBuf::from("a.rs"), vec![TextEdit { start: Position { line: 0, character: 0 }, end: Position { line: 0, character: 0 }, new_text: "use x;\n".to_string() }])],
+        });
+        fix.command = Some(Command { name: "cleanup".to_string(), arguments: Vec::new() });
+        let actions = vec![action("Extract function", "refactor.extract", true), fix, action("Remove unused", "quickfix.unused", true)];
+        let mut menu = CodeActionMenu::new(actions).unwrap();
+        let titles: Vec<&str> = (0..3).map(|idx| menu.picker.label(idx)).collect();
+        assert_eq!(titles, vec!["Remove unused", "Add missing import", "Extract function"]);
+
+        menu.picker.move_selection(1);
+        let chosen = menu.enter().unwrap();
+        let mut editor = Editor { text: "x();\n".to_string(), ..Default::default() };
+        let undo = apply_code_action(&mut editor, &chosen).unwrap();
+        assert_eq!(editor.text, "use x;\nx();\n");
+        assert_eq!(editor.commands, vec!["cleanup"]);
+        undo.undo(&mut editor);
+        assert_eq!(editor.text, "x();\n");
+        assert!(CodeActionMenu::new(Vec::new()).is_none());
+    }
+}
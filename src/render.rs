@@ -0,0 +1,908 @@
+// Drawing the App into its window. Renderer owns everything on the GPU: the device, the surface
+// and the renderers for text, shapes and images. A frame is drawn back to front:
+//
+//   clear, background image, gutter marks, selections and cursors, images in the buffer, the
+//   visible lines of the buffer, the sticky header, the tab bar, the :preview pane, the sidebar
+//   with the :tree, :outline and debug panes, the open picker, the which-key popup, a tooltip,
+//   status line and scrollbar, status line text, splash
+//
+// While a terminal is open, its cursor and screen are drawn in place of the buffer's
+//
+// Each of those renderers draws everything queued in one pass, so text on the status line is
+// queued after the buffer's text has been drawn, or the status line would cover it
+
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::app::{App, SidebarPane};
+use crate::atlas::{AtlasConfig, AtlasOverrides};
+use crate::background::{self, Background};
+use crate::completion::Menu;
+use crate::crash;
+use crate::dap::{GutterMark, Session};
+use crate::editor::Picking;
+use crate::error::RenderError;
+use crate::file_preview::FilePreview;
+use crate::gpu::Gpu;
+use crate::gpu_timing::{GpuTimer, Pass, PassTimes};
+use crate::images::{self, ImageId, ImageRenderer, ImageStore};
+use crate::jobs::Job;
+use crate::layout::{layout, layout_decorated, Block, Decorations, LayoutSettings, Rect, VirtualText};
+use crate::links;
+use crate::markdown::{Piece, PreviewPane, SpanStyle};
+use crate::memory::{Category, Usage};
+use crate::search::lines_bytes;
+use crate::selection::line_starts;
+use crate::shapes::{self, Shape, ShapeRenderer};
+use crate::splash::Splash;
+use crate::sticky;
+use crate::substitute;
+use crate::tabs::{self, TabRect};
+use crate::terminal::Grid;
+use crate::text_renderer::{CulledDocument, TextRenderer, TextSpan, VIRTUAL_TEXT_ALPHA};
+use crate::welcome::StartScreen;
+
+pub const BACKGROUND: [f32; 4] = [0.012, 0.012, 0.018, 1.];
+pub const TEXT: [f32; 4] = [0.8, 0.8, 0.78, 1.];
+const SELECTION: [f32; 4] = [0.06, 0.1, 0.22, 1.];
+const CURSOR: [f32; 4] = [0.6, 0.45, 0.1, 1.];
+const STATUS_TEXT: [f32; 4] = [0.9, 0.9, 0.9, 1.];
+const PREVIEW_BACKGROUND: [f32; 4] = [0.03, 0.03, 0.045, 1.];
+const CODE_BACKGROUND: [f32; 4] = [0.07, 0.07, 0.09, 1.];
+const CODE: [f32; 4] = [0.85, 0.7, 0.5, 1.];
+const BOLD: [f32; 4] = [1., 1., 0.97, 1.];
+const ITALIC: [f32; 4] = [0.7, 0.75, 0.9, 1.];
+// Thickness of underlines, like those of diagnostics
+const UNDERLINE: f32 = 1.5;
+const PICKER_BACKGROUND: [f32; 4] = [0.05, 0.05, 0.07, 1.];
+// Items of a picker shown at once
+const PICKER_ROWS: usize = 12;
+// The most toasts shown at once, the newest ones
+const TOASTS: usize = 5;
+const BREAKPOINT: [f32; 4] = [0.8, 0.2, 0.2, 1.];
+const STOPPED: [f32; 4] = [0.95, 0.75, 0.2, 1.];
+const STOPPED_LINE: [f32; 4] = [0.1, 0.08, 0.02, 1.];
+const MARK: [f32; 4] = [0.45, 0.6, 0.85, 1.];
+const LINK: [f32; 4] = [0.35, 0.55, 0.95, 1.];
+const TAB_BAR: [f32; 4] = [0.03, 0.03, 0.045, 1.];
+const INACTIVE_TAB: [f32; 4] = [0.55, 0.55, 0.55, 1.];
+// After the first line of a closed fold, with how many lines it hides
+const FOLD_MARKER: &str = "⋯";
+
+// The virtual text, folds and ranges zen mode dims the buffer was laid out with
+type Decorated = (Vec<VirtualText>, Vec<Range<usize>>, Vec<Range<usize>>, Vec<Block>);
+// The buffer, its highlights and the theme they were colored with
+type Versions = (u64, Option<u64>, &'static str);
+
+// How frames wait for the display, from the config:
+//
+//   present_mode = "mailbox"
+//
+// Fifo waits for vblank, so a key pressed just after one shows a frame later. Mailbox replaces the
+// waiting frame with a newer one instead, for less latency at the cost of drawing frames that are
+// never shown. Not every surface has it, and those fall back to Fifo
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresentMode {
+    #[default]
+    Fifo,
+    Mailbox,
+}
+
+impl PresentMode {
+    // What the surface can do of it
+    fn supported(self, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+        match self {
+            PresentMode::Mailbox if supported.contains(&wgpu::PresentMode::Mailbox) => wgpu::PresentMode::Mailbox,
+            _ => wgpu::PresentMode::Fifo,
+        }
+    }
+}
+
+pub struct Renderer {
+    gpu: Gpu,
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    config: wgpu::SurfaceConfiguration,
+    text: TextRenderer,
+    shapes: ShapeRenderer,
+    images: ImageRenderer,
+    store: ImageStore,
+    logo: ImageId,
+    background: Option<(Background, ImageId)>,
+    document: CulledDocument,
+    // The buffer and highlights versions, settings, virtual text and folds the document was laid out
+    // with, to lay it out again when any of them changes. Versions are never reused, so switching
+    // buffers changes them too
+    laid_out: Option<(Versions, LayoutSettings, Decorated)>,
+    // Where the adapter has timestamp queries, and what it measured of the last frame it read back
+    timer: Option<GpuTimer>,
+    gpu_times: Option<PassTimes>,
+}
+
+impl Renderer {
+    pub fn new(window: &winit::window::Window, atlas: AtlasOverrides, present_mode: PresentMode, gpu_raster: bool) -> Result<Renderer, RenderError> {
+        let gpu = Gpu::new(window)?;
+        crash::set_adapter_info(&gpu.adapter.get_info());
+        let descriptor = wgpu::DeviceDescriptor { label: Some("rakoune"), features: GpuTimer::features(&gpu.adapter), limits: gpu.adapter.limits() };
+        let (device, queue) = futures::executor::block_on(gpu.adapter.request_device(&descriptor, None))?;
+        let surface = gpu.surface.as_ref().expect("a new Gpu has a surface");
+        let capabilities = surface.get_capabilities(&gpu.adapter);
+        // Colors are linear, so the surface does the conversion to sRGB
+        let format = capabilities.formats.iter().copied().find(wgpu::TextureFormat::is_srgb).unwrap_or(capabilities.formats[0]);
+        let size = window.inner_size();
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format,
+            width: size.width.max(1),
+            height: size.height.max(1),
+            present_mode: present_mode.supported(&capabilities.present_modes),
+            alpha_mode: capabilities.alpha_modes[0],
+            view_formats: Vec::new(),
+        };
+        surface.configure(&device, &config);
+        let mut text = TextRenderer::new(&device, format, AtlasConfig::from_limits(&device.limits(), atlas));
+        if gpu_raster {
+            text = text.with_gpu_raster(&device);
+        }
+        let shapes = ShapeRenderer::new(&device, format);
+        let images = ImageRenderer::new(&device, format);
+        let mut store = ImageStore::default();
+        let logo = images::decode_png(include_bytes!("../resources/rakoune_logo.png")).map_err(|e| RenderError::Io(std::io::Error::other(e)))?;
+        let logo = store.upload(&device, &queue, &logo);
+        let timer = GpuTimer::new(&device, &queue);
+        Ok(Renderer { gpu, device, queue, config, text, shapes, images, store, logo, background: None, document: CulledDocument::default(), laid_out: None, timer, gpu_times: None })
+    }
+
+    // For the notifications, when drawing will be slower than usual
+    pub fn warning(&self) -> Option<String> {
+        self.gpu.warning()
+    }
+
+    pub fn atlas_summary(&self) -> String {
+        self.text.atlas.config.summary()
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.config.width = width.max(1);
+        self.config.height = height.max(1);
+        self.reconfigure();
+    }
+
+    // Also after RenderError::SurfaceLost
+    pub fn reconfigure(&mut self) {
+        if let Some(surface) = &self.gpu.surface {
+            surface.configure(&self.device, &self.config);
+        }
+    }
+
+    // The surface goes away with the window's while suspended, and the glyphs are drawn again after
+    // resuming rather than kept in GPU memory meanwhile
+    pub fn suspend(&mut self) {
+        self.gpu.surface = None;
+        self.trim();
+    }
+
+    // At the window's size now, which may have changed while suspended
+    pub fn resume(&mut self, window: &winit::window::Window) -> Result<(), RenderError> {
+        if self.gpu.surface.is_none() {
+            let size = window.inner_size();
+            self.config.width = size.width.max(1);
+            self.config.height = size.height.max(1);
+            self.gpu.create_surface(window)?.configure(&self.device, &self.config);
+        }
+        Ok(())
+    }
+
+    // The image from BackgroundConfig::for_theme, loaded once
+    pub fn set_background(&mut self, background: Option<Background>) -> Result<(), images::Error> {
+        self.background = match background {
+            Some(background) => {
+                let id = self.store.load(&self.device, &self.queue, &background.image)?;
+                Some((background, id))
+            }
+            None => None,
+        };
+        Ok(())
+    }
+
+    // What the layout caches and the glyph atlas hold, for :memory
+    pub fn memory_usage(&self) -> Usage {
+        let mut usage = Usage::default();
+        usage.add(Category::Layout, "buffer", self.document.memory_usage());
+        usage.add(Category::Atlas, "glyphs", self.text.atlas.memory_usage() as usize);
+        usage
+    }
+
+    // Clears the atlas if enough of it hasn't been drawn for `idle`, see GlyphAtlas::evict_idle.
+    // The document is laid out again on the next frame, as its glyphs are gone
+    pub fn evict_idle(&mut self, now: Instant, idle: Duration) -> usize {
+        self.text.atlas.evict_idle(now, idle)
+    }
+
+    // Drops the layout caches and the glyph atlas, which fill up again as text is drawn
+    pub fn trim(&mut self) {
+        self.document.trim();
+        self.text.atlas.clear();
+        self.laid_out = None;
+    }
+
+    pub fn draw(&mut self, app: &App, now: Instant) -> Result<(), RenderError> {
+        // Nothing to draw to while suspended
+        let Some(surface) = &self.gpu.surface else { return Ok(()) };
+        let frame = surface.get_current_texture()?;
+        let view = frame.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let size = (self.config.width, self.config.height);
+        let window = (size.0 as f32, size.1 as f32);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("frame") });
+        self.time(&mut encoder, Pass::Clear, true);
+        let [r, g, b, a] = BACKGROUND.map(f64::from);
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("clear pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Clear(wgpu::Color { r, g, b, a }), store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        if let Some((background, id)) = &self.background {
+            let image = self.store.get(*id);
+            for rect in background::rects(background.fit, window, (image.width, image.height)) {
+                self.images.queue_faded(*id, rect, background.opacity);
+            }
+            self.images.render(&self.device, &self.queue, &mut encoder, &view, size, &self.store);
+        }
+        self.time(&mut encoder, Pass::Clear, false);
+
+        let settings = app.layout_settings();
+        self.text.focus = app.text_focus();
+        let popup_cursor = match &app.editor.terminal {
+            Some(terminal) => {
+                self.time(&mut encoder, Pass::Text, true);
+                self.draw_terminal(app, &terminal.grid, &mut encoder, &view, size);
+                self.time(&mut encoder, Pass::Text, false);
+                None
+            }
+            None => match &app.editor.start_screen {
+                Some(screen) => {
+                    self.time(&mut encoder, Pass::Text, true);
+                    self.draw_start_screen(app, screen, &mut encoder, &view, size);
+                    self.time(&mut encoder, Pass::Text, false);
+                    None
+                }
+                None => self.draw_buffer(app, &mut encoder, &view, size),
+            },
+        };
+        self.time(&mut encoder, Pass::Ui, true);
+        // Touch handles on the ends of the selection, under everything drawn over the buffer
+        let handles = app.touch.handle_shapes(CURSOR);
+        if !handles.is_empty() {
+            for handle in &handles {
+                self.shapes.queue(handle);
+            }
+            self.shapes.render(&self.device, &self.queue, &mut encoder, &view, size);
+        }
+        let tab_rects = app.tab_rects();
+        if !tab_rects.is_empty() {
+            self.draw_tabs(app, &tab_rects, &mut encoder, &view, size);
+        }
+        if let Some((pane, _)) = &app.editor.preview {
+            self.draw_preview(app, pane, &mut encoder, &view, size);
+        }
+        if app.sidebar_width() > 0. {
+            self.draw_sidebar(app, &mut encoder, &view, size);
+        }
+        if let (Some(job), Some(rect)) = (&app.editor.job, app.output_rect()) {
+            self.draw_output(app, job, rect, &mut encoder, &view, size);
+        }
+        if let Some(picking) = &app.editor.picking {
+            self.draw_picker(app, picking, &mut encoder, &view, size);
+        }
+        if let Some(lines) = app.editor.which_key_popup(&app.registry, now) {
+            self.draw_which_key(app, &lines, &mut encoder, &view, size);
+        }
+        if let Some((menu, rect)) = popup_cursor.and_then(|start| app.completion_menu(start)) {
+            self.draw_completion(app, menu, rect, &mut encoder, &view, size);
+        } else if let Some((pieces, rect)) = popup_cursor.and_then(|cursor| app.hover_popup(cursor)) {
+            self.draw_tooltip(app, &pieces, rect, &mut encoder, &view, size);
+        }
+        self.draw_toasts(app, now, &mut encoder, &view, size);
+        if let Some((pieces, rect)) = app.tooltip(now) {
+            self.draw_tooltip(app, &pieces, rect, &mut encoder, &view, size);
+        }
+
+        // The status line, or the prompt or question in its place
+        let status_top = app.status_top();
+        let status = app.status_line.render(&app.editor.status_context(now));
+        let status_rect = Rect { x: 0., y: status_top, w: window.0, h: window.1 - status_top };
+        self.shapes.queue(&Shape::RoundedRect { rect: status_rect, radius: 0., border: 0., color: status.background });
+        if let Some(thumb) = app.scrollbar.thumb_shape(&app.viewport, app.text_width(), now).filter(|_| app.editor.terminal.is_none()) {
+            self.shapes.queue(&thumb);
+        }
+        self.shapes.render(&self.device, &self.queue, &mut encoder, &view, size);
+        let line = app.editor.prompt_line().unwrap_or_else(|| status.line((window.0 / app.advance()) as usize));
+        let status_color = app.accessibility.color(STATUS_TEXT, status.background);
+        let mut spans = vec![TextSpan { text: &line, color: status_color }];
+        // The message in the color of its severity
+        if let Some((message, color)) = &status.message {
+            if let Some(at) = line.find(message.as_str()).filter(|_| !message.is_empty()) {
+                let (before, rest) = line.split_at(at);
+                let (message, after) = rest.split_at(message.len());
+                spans = vec![TextSpan { text: before, color: status_color }, TextSpan { text: message, color: *color }, TextSpan { text: after, color: status_color }];
+            }
+        }
+        self.text.queue(&self.device, &self.queue, &app.fontstack, &spans, (0., status_top), &LayoutSettings { wrap_width: None, ..settings });
+        self.text.render(&self.device, &self.queue, &mut encoder, &view, size);
+
+        if let Some(opacity) = app.splash.opacity(now) {
+            let logo = self.store.get(self.logo);
+            self.images.queue_faded(self.logo, Splash::rect(window, (logo.width, logo.height)), opacity);
+            self.images.render(&self.device, &self.queue, &mut encoder, &view, size, &self.store);
+        }
+        self.time(&mut encoder, Pass::Ui, false);
+
+        if let Some(timer) = &mut self.timer {
+            timer.resolve(&mut encoder);
+        }
+        self.queue.submit([encoder.finish()]);
+        frame.present();
+        if let Some(timer) = &mut self.timer {
+            timer.submitted();
+            self.device.poll(wgpu::Maintain::Poll);
+            self.gpu_times = timer.poll().or(self.gpu_times);
+        }
+        // The frame went out without the glyphs of a face fontdue can't read
+        match self.text.atlas.take_font_error() {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+
+    // The selections and cursors, then the visible lines of the buffer. Returns where on screen the
+    // completion menu or the hover or signature help popup is drawn from, if it's in view
+    fn draw_buffer(&mut self, app: &App, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) -> Option<Rect> {
+        let settings = app.layout_settings();
+        let line_height = app.line_height();
+        let scroll_y = app.render_scroll_y();
+        let buffer = app.editor.buffer();
+        // Rows, which are lines unless long lines wrap
+        let visible = app.viewport.visible_lines(line_height);
+        let lines = app.rows().lines(visible.clone());
+        let text_color = app.accessibility.color(TEXT, BACKGROUND);
+
+        // Replacements :s would make, in front of the text they replace, after the first line of
+        // each closed fold how many more it hides, and :blame at the end of lines
+        let substituted = app.editor.substitute_preview();
+        let mut virtual_text = substitute::preview(&substituted);
+        let (folds, starts) = (&buffer.folds.closed, line_starts(&buffer.text));
+        virtual_text.extend(folds.iter().map(|fold| VirtualText { at: starts.get(fold.start + 1).map_or(buffer.text.len(), |&next| next - 1), text: format!(" {FOLD_MARKER} {} lines", fold.len() - 1) }));
+        virtual_text.extend(app.editor.blame_annotations(lines.clone()));
+        virtual_text.sort_by_key(|vt| vt.at);
+        // Room below lines for the images of a markdown buffer
+        let blocks: Vec<Block> = app.images().iter().map(|(_, block)| *block).collect();
+        let decorations = Decorations { folds, virtual_text: &virtual_text, blocks: &blocks };
+
+        // Selections and cursors, on a layout of the visible lines only
+        let bytes = lines_bytes(&buffer.text, lines.clone());
+        let shown_virtual: Vec<VirtualText> = virtual_text.iter().filter(|vt| (bytes.start..=bytes.end).contains(&vt.at)).map(|vt| VirtualText { at: vt.at - bytes.start, text: vt.text.clone() }).collect();
+        let shown_folds: Vec<Range<usize>> = folds.iter().filter(|fold| fold.start >= lines.start).map(|fold| fold.start - lines.start..fold.end - lines.start).collect();
+        let shown_images: Vec<(&PathBuf, Block)> = app.images().iter().filter(|(_, block)| lines.contains(&block.after_line)).map(|(path, block)| (path, Block { after_line: block.after_line - lines.start, ..*block })).collect();
+        let shown_blocks: Vec<Block> = shown_images.iter().map(|(_, block)| *block).collect();
+        let shown = layout_decorated(&app.fontstack, &buffer.text[bytes.clone()], &settings, Decorations { folds: &shown_folds, virtual_text: &shown_virtual, blocks: &shown_blocks });
+        // Rows are laid out from the top of the view, under the tab bar
+        let view_top = app.viewport.top;
+        let top = view_top + app.rows().row_of_line(lines.start) as f32 * line_height - scroll_y;
+        let (gutter, left) = (app.gutter_width(), app.text_left());
+        let moved = |rect: Rect| Rect { x: rect.x + left, y: rect.y + top, ..rect };
+        // Diagnostic signs along the left of the gutter, in the color of the first on each line,
+        // which show their messages when hovered
+        let mut signs = Vec::new();
+        for (range, severity) in buffer.path().iter().filter(|_| gutter > 0.).flat_map(|path| app.editor.diagnostics.underlines(path)) {
+            let line = starts.partition_point(|&start| start <= range.start) - 1;
+            if lines.contains(&line) && !buffer.folds.is_hidden(line) && !signs.contains(&line) {
+                signs.push(line);
+                let y = view_top + app.rows().row_of_line(line) as f32 * line_height - scroll_y;
+                self.shapes.queue(&Shape::RoundedRect { rect: Rect { x: 0., y, w: (gutter / 8.).max(2.), h: line_height }, radius: 0., border: 0., color: severity.color() });
+            }
+        }
+        // Breakpoints as dots in the gutter, and an arrow and a highlight on the line the program
+        // stopped on
+        let gutter_marks = app.editor.gutter();
+        for (line, mark) in gutter_marks.range(lines.clone()).filter(|(line, _)| !buffer.folds.is_hidden(**line)) {
+            let y = view_top + app.rows().row_of_line(*line) as f32 * line_height - scroll_y;
+            let dot = Shape::Circle { center: (gutter / 2., y + line_height / 2.), radius: line_height / 4., border: 0., color: BREAKPOINT };
+            match mark {
+                GutterMark::Breakpoint if gutter > 0. => self.shapes.queue(&dot),
+                GutterMark::Breakpoint => {}
+                GutterMark::Stopped { on_breakpoint } => {
+                    self.shapes.queue(&Shape::RoundedRect { rect: Rect { x: 0., y, w: app.text_width(), h: line_height }, radius: 0., border: 0., color: STOPPED_LINE });
+                    if *on_breakpoint && gutter > 0. {
+                        self.shapes.queue(&dot);
+                    }
+                    let (back, tip) = (gutter / 4., gutter * 3. / 4.);
+                    for line in shapes::polyline(&[(back, y + line_height / 4.), (tip, y + line_height / 2.), (back, y + line_height * 3. / 4.)], 2., STOPPED).into_iter().filter(|_| gutter > 0.) {
+                        self.shapes.queue(&line);
+                    }
+                }
+            }
+        }
+        for selection in &buffer.selections {
+            let range = selection.range();
+            let clipped = range.start.max(bytes.start)..range.end.min(bytes.end);
+            if clipped.start < clipped.end {
+                for rect in shown.selection_rects(clipped.start - bytes.start..clipped.end - bytes.start) {
+                    self.shapes.queue(&Shape::RoundedRect { rect: moved(rect), radius: 0., border: 0., color: SELECTION });
+                }
+            }
+            let cell = selection.head_cell(&buffer.text);
+            if app.cursor_shown && bytes.start <= cell.start && cell.end <= bytes.end {
+                let rect = shown.head_rect(cell.start - bytes.start..cell.end - bytes.start, line_height / 2.);
+                self.shapes.queue(&app.cursor_style().shape(moved(rect), CURSOR));
+            }
+        }
+        // Diagnostics underlined in the color of their severity
+        let path = buffer.path();
+        let diagnostics = &app.editor.diagnostics;
+        for (range, severity) in path.iter().flat_map(|path| diagnostics.underlines(path)) {
+            let clipped = range.start.max(bytes.start)..range.end.min(bytes.end);
+            if clipped.start < clipped.end {
+                for rect in links::underline_rects(&shown, clipped.start - bytes.start..clipped.end - bytes.start, UNDERLINE) {
+                    self.shapes.queue(&Shape::RoundedRect { rect: moved(rect), radius: 0., border: 0., color: severity.color() });
+                }
+            }
+        }
+        // Links underlined, which Ctrl+click opens
+        let link_color = app.accessibility.color(LINK, BACKGROUND);
+        for url in links::find_urls(&buffer.text[bytes.clone()]) {
+            for rect in links::underline_rects(&shown, url, UNDERLINE) {
+                self.shapes.queue(&Shape::RoundedRect { rect: moved(rect), radius: 0., border: 0., color: link_color });
+            }
+        }
+        // What :s would replace struck out
+        for m in &substituted {
+            let clipped = m.range.start.max(bytes.start)..m.range.end.min(bytes.end);
+            if clipped.start < clipped.end {
+                for rect in shown.selection_rects(clipped.start - bytes.start..clipped.end - bytes.start) {
+                    let rect = Rect { y: rect.y + (rect.h - UNDERLINE) / 2., h: UNDERLINE, ..rect };
+                    self.shapes.queue(&Shape::RoundedRect { rect: moved(rect), radius: 0., border: 0., color: text_color });
+                }
+            }
+        }
+        // Images in the room the layout left for them
+        for (idx, rect) in &shown.blocks {
+            if let Some(id) = self.store.load_once(&self.device, &self.queue, shown_images[*idx].0) {
+                let image = self.store.get(id);
+                let (w, h) = images::fit_width(image.width, image.height, rect.w);
+                self.images.queue(id, moved(Rect { w, h, ..*rect }));
+            }
+        }
+        self.time(encoder, Pass::Decorations, true);
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+        self.images.render(&self.device, &self.queue, encoder, view, size, &self.store);
+        self.time(encoder, Pass::Decorations, false);
+
+        // Outside the paragraph with the cursor in zen mode
+        let dimmed = app.editor.zen.dimmed(&buffer.text, buffer.cursor());
+        let decorated = (virtual_text.clone(), folds.clone(), dimmed.clone(), blocks.clone());
+        let versions = (buffer.version, app.editor.highlights_version(), app.editor.theme.name);
+        if self.laid_out.as_ref().is_none_or(|(laid_out_versions, laid_out_with, laid_out_decorated)| *laid_out_versions != versions || *laid_out_with != settings || *laid_out_decorated != decorated) {
+            self.document.invalidate();
+            self.laid_out = Some((versions, settings.clone(), decorated));
+        }
+        // Highlights and brackets in the colors of the theme, as readable on the background as the text
+        let colors = |bytes: Range<usize>| {
+            let colors = app.editor.text_colors(bytes.clone()).into_iter().map(|(range, color)| (range, app.accessibility.color(color, BACKGROUND))).collect();
+            app.editor.zen.dim(colors, &dimmed, bytes, text_color)
+        };
+        self.text.queue_document(&self.device, &self.queue, &mut self.document, &app.fontstack, &buffer.text, decorations, app.rows(), text_color, &colors, visible, scroll_y, (left, view_top), &settings);
+        // The first diagnostic of each line dimmed after its end, running past the wrap width
+        let unwrapped = LayoutSettings { wrap_width: None, ..settings.clone() };
+        for (virtual_text, severity) in path.iter().flat_map(|path| diagnostics.virtual_text(path, &buffer.text)) {
+            let hidden = buffer.folds.is_hidden(starts.partition_point(|&start| start <= virtual_text.at) - 1);
+            if (bytes.start..=bytes.end).contains(&virtual_text.at) && !hidden {
+                let rect = moved(shown.cursor_rect(virtual_text.at - bytes.start, 0.));
+                let [r, g, b, _] = severity.color();
+                let spans = [TextSpan { text: &virtual_text.text, color: [r, g, b, VIRTUAL_TEXT_ALPHA] }];
+                self.text.queue(&self.device, &self.queue, &app.fontstack, &spans, (rect.x, rect.y), &unwrapped);
+            }
+        }
+        // Marks as their letter in the gutter, on lines without a breakpoint or arrow
+        for (line, names) in app.editor.marks_by_line().range(lines.clone()).filter(|(line, _)| gutter > 0. && !buffer.folds.is_hidden(**line) && !gutter_marks.contains_key(*line)) {
+            let y = view_top + app.rows().row_of_line(*line) as f32 * line_height - scroll_y;
+            let name = names[0].to_string();
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &[TextSpan { text: &name, color: MARK }], ((gutter - app.advance()) / 2., y), &unwrapped);
+        }
+        self.time(encoder, Pass::Text, true);
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+
+        // The sticky header over the top rows, once the text under it is drawn
+        let pinned = app.sticky_lines();
+        if let Some(backdrop) = sticky::backdrop(&pinned, view_top, app.text_width(), line_height) {
+            self.shapes.queue(&backdrop);
+            self.shapes.render(&self.device, &self.queue, encoder, view, size);
+            let header = sticky::header_text(&buffer.text, &pinned);
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &[TextSpan { text: &header, color: text_color }], (left, view_top), &unwrapped);
+            self.text.render(&self.device, &self.queue, encoder, view, size);
+        }
+        self.time(encoder, Pass::Text, false);
+
+        let anchor = app.editor.popup_anchor()?;
+        let hidden = buffer.folds.is_hidden(starts.partition_point(|&start| start <= anchor) - 1);
+        ((bytes.start..=bytes.end).contains(&anchor) && !hidden).then(|| moved(shown.cursor_rect(anchor - bytes.start, 0.)))
+    }
+
+    // Writes the timestamp at the start or end of `pass`, where the adapter has timestamp queries
+    fn time(&mut self, encoder: &mut wgpu::CommandEncoder, pass: Pass, start: bool) {
+        match (&mut self.timer, start) {
+            (Some(timer), true) => timer.begin(encoder, pass),
+            (Some(timer), false) => timer.end(encoder, pass),
+            (None, _) => {}
+        }
+    }
+
+    // How long the passes of the last frame the GPU finished took, where it can tell
+    pub fn gpu_times(&self) -> Option<PassTimes> {
+        self.gpu_times
+    }
+
+    // The bar of open buffers over the buffer, the active one in the buffer's background. Each has a
+    // cross to close it, or a dot while it has unsaved changes
+    fn draw_tabs(&mut self, app: &App, tab_rects: &[TabRect], encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let (advance, line_height) = (app.advance(), app.line_height());
+        self.shapes.queue(&Shape::RoundedRect { rect: Rect { x: 0., y: 0., w: app.text_width(), h: app.viewport.top }, radius: 0., border: 0., color: TAB_BAR });
+        for tab in tab_rects.iter().filter(|tab| tab.active) {
+            self.shapes.queue(&Shape::RoundedRect { rect: tab.rect, radius: 0., border: 0., color: BACKGROUND });
+        }
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+        let settings = LayoutSettings { wrap_width: None, ..app.layout_settings() };
+        for tab in tab_rects {
+            let color = match tab.active {
+                true => app.accessibility.color(TEXT, BACKGROUND),
+                false => app.accessibility.color(INACTIVE_TAB, TAB_BAR),
+            };
+            let y = tab.rect.y + (tab.rect.h - line_height) / 2.;
+            // Titles too long for a tab are cut short
+            let room = ((tab.close.x - tab.rect.x - tabs::PADDING * 2.) / advance).max(1.) as usize;
+            let title = match tab.title.chars().count() > room {
+                true => tab.title.chars().take(room.saturating_sub(1)).chain(['…']).collect(),
+                false => tab.title.clone(),
+            };
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &[TextSpan { text: &title, color }], (tab.rect.x + tabs::PADDING, y), &settings);
+            let close = if tab.modified { "●" } else { "×" };
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &[TextSpan { text: close, color }], (tab.close.x + (tab.close.w - advance) / 2., y), &settings);
+        }
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+    }
+
+    // The pane over the right of the window, covering buffer lines too long to end before it. Bold,
+    // italic and code are told apart by color, as the font stack has one weight and style
+    fn draw_preview(&mut self, app: &App, pane: &PreviewPane, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let (left, padding) = (app.text_width(), app.advance());
+        let right = size.0 as f32 - app.sidebar_width();
+        let width = right - left - padding * 2.;
+        let bottom = app.viewport.top + app.viewport.height;
+        self.shapes.queue(&Shape::RoundedRect { rect: Rect { x: left, y: 0., w: right - left, h: bottom }, radius: 0., border: 0., color: PREVIEW_BACKGROUND });
+        let text_color = app.accessibility.color(TEXT, PREVIEW_BACKGROUND);
+        let color = |style: SpanStyle| match style {
+            SpanStyle { code: true, .. } => app.accessibility.color(CODE, CODE_BACKGROUND),
+            SpanStyle { bold: true, .. } => app.accessibility.color(BOLD, PREVIEW_BACKGROUND),
+            SpanStyle { italic: true, .. } => app.accessibility.color(ITALIC, PREVIEW_BACKGROUND),
+            _ => text_color,
+        };
+        let mut y = padding;
+        let mut queued = Vec::new();
+        for piece in pane.pieces() {
+            if y > bottom {
+                break;
+            }
+            // Images that can't be loaded are shown by their alt text
+            if let Some(id) = piece.image.as_ref().and_then(|path| self.store.load_once(&self.device, &self.queue, path)) {
+                let image = self.store.get(id);
+                let (w, h) = images::fit_width(image.width, image.height, width);
+                self.images.queue(id, Rect { x: left + padding, y, w, h });
+                y += h + padding / 2.;
+                continue;
+            }
+            let indent = piece.indent as f32 * padding;
+            let settings = LayoutSettings { font_size: app.viewport.font_size * piece.scale, wrap_width: Some((width - indent).max(padding)), ..app.layout_settings() };
+            let laid_out = layout(&app.fontstack, &piece.text, &settings);
+            let height = laid_out.lines.len() as f32 * laid_out.line_height;
+            if piece.code_block {
+                self.shapes.queue(&Shape::RoundedRect { rect: Rect { x: left + padding / 2., y: y - padding / 4., w: width + padding, h: height + padding / 2. }, radius: 4., border: 0., color: CODE_BACKGROUND });
+            }
+            queued.push((piece, settings, (left + padding + indent, y)));
+            y += height + laid_out.line_height / 2.;
+        }
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+        self.images.render(&self.device, &self.queue, encoder, view, size, &self.store);
+        for (piece, settings, position) in &queued {
+            let spans: Vec<TextSpan> = piece.styles.iter().map(|(range, style)| TextSpan { text: &piece.text[range.clone()], color: color(*style) }).collect();
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &spans, *position, settings);
+        }
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+    }
+
+    // The panes sharing the sidebar at the right edge, each a list of lines scrolled to keep its
+    // highlighted one in view: the :tree with its selected file, the :outline with the symbol the
+    // cursor is in while that buffer is shown, and the stack, variables and latest output of the
+    // program being debugged
+    fn draw_sidebar(&mut self, app: &App, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let (width, line_height, advance) = (app.sidebar_width(), app.line_height(), app.advance());
+        let left = size.0 as f32 - width;
+        let buffer = app.editor.buffer();
+        let mut queued = Vec::new();
+        for (pane, top, height) in app.sidebar() {
+            let rows = (height / line_height) as usize;
+            let (lines, highlighted): (Vec<(String, [f32; 4])>, Option<usize>) = match pane {
+                SidebarPane::Tree => {
+                    let Some(tree) = &app.editor.file_tree else { continue };
+                    (tree.lines().into_iter().map(|line| (line, TEXT)).collect(), Some(tree.selected))
+                }
+                SidebarPane::Outline => {
+                    let Some((outline, _)) = &app.editor.outline else { continue };
+                    let current = (buffer.path().as_deref() == Some(outline.path.as_path())).then(|| outline.current(buffer.cursor_line())).flatten();
+                    (outline.lines().iter().map(|line| (line.clone(), TEXT)).collect(), current)
+                }
+                SidebarPane::Debug => {
+                    let Some(debugger) = &app.editor.debugger else { continue };
+                    (debug_lines(&debugger.session, rows), None)
+                }
+            };
+            self.shapes.queue(&Shape::RoundedRect { rect: Rect { x: left, y: top, w: width, h: height }, radius: 0., border: 0., color: PICKER_BACKGROUND });
+            let first = highlighted.map_or(0, |highlighted| (highlighted + 1).saturating_sub(rows));
+            if let Some(highlighted) = highlighted {
+                self.shapes.queue(&Shape::RoundedRect { rect: Rect { x: left, y: top + (highlighted - first) as f32 * line_height, w: width, h: line_height }, radius: 0., border: 0., color: SELECTION });
+            }
+            queued.extend(lines.into_iter().skip(first).take(rows).enumerate().map(|(row, (line, color))| (line, color, top + row as f32 * line_height)));
+        }
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+        let settings = LayoutSettings { wrap_width: None, ..app.layout_settings() };
+        for (line, color, y) in &queued {
+            let spans = [TextSpan { text: line, color: app.accessibility.color(*color, PICKER_BACKGROUND) }];
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &spans, (left + advance / 2., *y), &settings);
+        }
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+    }
+
+    // The open picker over the top of the buffer: what's been typed, then the items matching it,
+    // scrolled to keep the selected one in view
+    fn draw_picker(&mut self, app: &App, picking: &Picking, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let (filter, labels, selected) = picking.view();
+        let (advance, line_height) = (app.advance(), app.line_height());
+        let rows = labels.len().min(PICKER_ROWS);
+        let first = (selected + 1).saturating_sub(rows);
+        let width = (advance * 60.).min(app.text_width() - advance * 2.);
+        let left = ((app.text_width() - width) / 2.).round();
+        let rect = Rect { x: left, y: line_height, w: width, h: (rows + 1) as f32 * line_height + advance / 2. };
+        self.shapes.queue(&Shape::RoundedRect { rect, radius: 4., border: 0., color: PICKER_BACKGROUND });
+        if !labels.is_empty() {
+            let y = (selected - first + 2) as f32 * line_height;
+            self.shapes.queue(&Shape::RoundedRect { rect: Rect { x: left, y, w: width, h: line_height }, radius: 0., border: 0., color: SELECTION });
+        }
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+        let settings = LayoutSettings { wrap_width: None, ..app.layout_settings() };
+        let text_color = app.accessibility.color(TEXT, PICKER_BACKGROUND);
+        let typed = format!("> {filter}");
+        let lines = std::iter::once(typed.as_str()).chain(labels[first..first + rows].iter().copied());
+        for (row, line) in lines.enumerate() {
+            let spans = [TextSpan { text: line, color: text_color }];
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &spans, (left + advance / 2., (row + 1) as f32 * line_height), &settings);
+        }
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+        if let (Picking::File(_), Some(preview)) = (picking, app.editor.file_previews.current()) {
+            let top = rect.y + rect.h + advance / 2.;
+            self.draw_file_preview(app, preview, Rect { x: left, y: top, w: width, h: app.viewport.top + app.viewport.height - top - advance / 2. }, encoder, view, size);
+        }
+    }
+
+    // The start of the file selected in the finder, under it, as many lines as fit
+    fn draw_file_preview(&mut self, app: &App, preview: &FilePreview, rect: Rect, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let (advance, line_height) = (app.advance(), app.line_height());
+        let rows = (rect.h / line_height).floor() as usize;
+        if rows == 0 {
+            return;
+        }
+        let rect = Rect { h: rows as f32 * line_height, ..rect };
+        self.shapes.queue(&Shape::RoundedRect { rect, radius: 4., border: 0., color: PREVIEW_BACKGROUND });
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+
+        let text = &preview.text;
+        let end = text.match_indices('\n').nth(rows - 1).map_or(text.len(), |(at, _)| at);
+        let text_color = app.accessibility.color(TEXT, PREVIEW_BACKGROUND);
+        let mut spans = Vec::new();
+        let mut at = 0;
+        for (range, face) in &preview.highlights {
+            if range.start < at || range.end > end {
+                continue;
+            }
+            let Some(color) = app.editor.theme.face_color(face) else { continue };
+            spans.push(TextSpan { text: &text[at..range.start], color: text_color });
+            spans.push(TextSpan { text: &text[range.clone()], color: app.accessibility.color(color, PREVIEW_BACKGROUND) });
+            at = range.end;
+        }
+        spans.push(TextSpan { text: &text[at..end], color: text_color });
+        let settings = LayoutSettings { wrap_width: None, ..app.layout_settings() };
+        self.text.queue(&self.device, &self.queue, &app.fontstack, &spans, (rect.x + advance / 2., rect.y), &settings);
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+    }
+
+    // The keys that can follow those typed so far, in the bottom right corner above the status line
+    fn draw_which_key(&mut self, app: &App, lines: &[String], encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let (advance, line_height) = (app.advance(), app.line_height());
+        let rows = lines.len().min((app.viewport.height / line_height) as usize).max(1);
+        let columns = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+        let width = (columns as f32 + 1.) * advance;
+        let rect = Rect { x: app.text_width() - width - advance, y: app.viewport.top + app.viewport.height - rows as f32 * line_height - advance / 2., w: width, h: rows as f32 * line_height };
+        self.shapes.queue(&Shape::RoundedRect { rect, radius: 4., border: 0., color: PICKER_BACKGROUND });
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+        let settings = LayoutSettings { wrap_width: None, ..app.layout_settings() };
+        let text_color = app.accessibility.color(TEXT, PICKER_BACKGROUND);
+        for (row, line) in lines.iter().take(rows).enumerate() {
+            let spans = [TextSpan { text: line, color: text_color }];
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &spans, (rect.x + advance / 2., rect.y + row as f32 * line_height), &settings);
+        }
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+    }
+
+    // The output of :make or :run under the buffer in the colors it was printed in, after a line
+    // saying what runs and how it ended, with the error last stepped to highlighted
+    fn draw_output(&mut self, app: &App, job: &Job, rect: Rect, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let (advance, line_height) = (app.advance(), app.line_height());
+        self.shapes.queue(&Shape::RoundedRect { rect, radius: 0., border: 0., color: PREVIEW_BACKGROUND });
+        let shown = job.shown_lines(((rect.h / line_height) as usize).saturating_sub(1));
+        if let Some(error) = job.current_error().filter(|error| shown.contains(&error.output_line)) {
+            let y = rect.y + (error.output_line - shown.start + 1) as f32 * line_height;
+            self.shapes.queue(&Shape::RoundedRect { rect: Rect { y, h: line_height, ..rect }, radius: 0., border: 0., color: SELECTION });
+        }
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+        let settings = LayoutSettings { wrap_width: None, ..app.layout_settings() };
+        let text_color = app.accessibility.color(TEXT, PREVIEW_BACKGROUND);
+        let title = job.title();
+        self.text.queue(&self.device, &self.queue, &app.fontstack, &[TextSpan { text: &title, color: app.accessibility.color(BOLD, PREVIEW_BACKGROUND) }], (advance / 2., rect.y), &settings);
+        for (row, at) in shown.enumerate() {
+            let (line, mut spans, mut end) = (&job.output[at], Vec::new(), 0);
+            // Only the styled runs have spans
+            for styled in &job.styles[at] {
+                spans.push(TextSpan { text: &line[end..styled.range.start], color: text_color });
+                spans.push(TextSpan { text: &line[styled.range.clone()], color: styled.style.fg.rgba(text_color) });
+                end = styled.range.end;
+            }
+            spans.push(TextSpan { text: &line[end..], color: text_color });
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &spans, (advance / 2., rect.y + (row + 1) as f32 * line_height), &settings);
+        }
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+    }
+
+    // The logo in the top third of where the buffer goes, and the recent files and keys centered
+    // under it
+    fn draw_start_screen(&mut self, app: &App, screen: &StartScreen, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let (advance, line_height) = (app.advance(), app.line_height());
+        let logo = self.store.get(self.logo);
+        let area = (app.text_width(), app.viewport.height / 3.);
+        let rect = Splash::rect(area, (logo.width, logo.height));
+        self.images.queue_faded(self.logo, Rect { y: rect.y + app.viewport.top, ..rect }, 1.);
+        self.images.render(&self.device, &self.queue, encoder, view, size, &self.store);
+
+        let lines = screen.lines();
+        let columns = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+        let left = ((app.text_width() - columns as f32 * advance) / 2.).max(advance).round();
+        let settings = LayoutSettings { wrap_width: None, ..app.layout_settings() };
+        let text_color = app.accessibility.color(TEXT, BACKGROUND);
+        let (top, bottom) = (app.viewport.top + area.1 + line_height, app.viewport.top + app.viewport.height);
+        for (row, line) in lines.iter().enumerate() {
+            let y = top + row as f32 * line_height;
+            if y + line_height > bottom {
+                break;
+            }
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &[TextSpan { text: line, color: text_color }], (left, y), &settings);
+        }
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+    }
+
+    // Notifications in the top right corner until they time out, the newest on top, each with the
+    // color of its severity down its left edge
+    fn draw_toasts(&mut self, app: &App, now: Instant, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let (advance, line_height) = (app.advance(), app.line_height());
+        let width = (advance * 40.).min(app.text_width() - advance);
+        let settings = LayoutSettings { wrap_width: Some(width - advance * 1.5), ..app.layout_settings() };
+        let (mut y, bottom) = (app.viewport.top + advance / 2., app.viewport.top + app.viewport.height);
+        let mut queued = Vec::new();
+        for message in app.editor.notifications.toasts(now).take(TOASTS) {
+            let rows = layout(&app.fontstack, &message.text, &settings).lines.len().max(1);
+            let rect = Rect { x: app.text_width() - width - advance / 2., y, w: width, h: rows as f32 * line_height + advance / 2. };
+            if rect.y + rect.h > bottom {
+                break;
+            }
+            self.shapes.queue(&Shape::RoundedRect { rect, radius: 4., border: 0., color: PICKER_BACKGROUND });
+            self.shapes.queue(&Shape::RoundedRect { rect: Rect { w: advance / 4., ..rect }, radius: 0., border: 0., color: message.severity.color() });
+            queued.push((message, (rect.x + advance, rect.y + advance / 4.)));
+            y += rect.h + advance / 2.;
+        }
+        if queued.is_empty() {
+            return;
+        }
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+        let text_color = app.accessibility.color(TEXT, PICKER_BACKGROUND);
+        for (message, position) in queued {
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &[TextSpan { text: &message.text, color: text_color }], position, &settings);
+        }
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+    }
+
+    // The completions by the word they complete, the picked one highlighted
+    fn draw_completion(&mut self, app: &App, menu: &Menu, rect: Rect, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let (advance, line_height) = (app.advance(), app.line_height());
+        self.shapes.queue(&Shape::RoundedRect { rect, radius: 4., border: 0., color: PICKER_BACKGROUND });
+        let picked = Rect { y: rect.y + menu.selected as f32 * line_height, h: line_height, ..rect };
+        self.shapes.queue(&Shape::RoundedRect { rect: picked, radius: 0., border: 0., color: SELECTION });
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+        let settings = LayoutSettings { wrap_width: None, ..app.layout_settings() };
+        let text_color = app.accessibility.color(TEXT, PICKER_BACKGROUND);
+        for (row, item) in menu.items.iter().enumerate() {
+            let spans = [TextSpan { text: item, color: text_color }];
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &spans, (rect.x + advance / 2., rect.y + row as f32 * line_height), &settings);
+        }
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+    }
+
+    // One line of text per line of each piece, code in its own color, at the size of the buffer's
+    fn draw_tooltip(&mut self, app: &App, pieces: &[Piece], rect: Rect, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let (advance, line_height) = (app.advance(), app.line_height());
+        self.shapes.queue(&Shape::RoundedRect { rect, radius: 4., border: 0., color: PICKER_BACKGROUND });
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+        let settings = LayoutSettings { wrap_width: None, ..app.layout_settings() };
+        let (text_color, code_color) = (app.accessibility.color(TEXT, PICKER_BACKGROUND), app.accessibility.color(CODE, PICKER_BACKGROUND));
+        let mut y = rect.y + advance / 4.;
+        for piece in pieces {
+            let spans: Vec<TextSpan> = piece.styles.iter().map(|(range, style)| TextSpan { text: &piece.text[range.clone()], color: if style.code { code_color } else { text_color } }).collect();
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &spans, (rect.x + advance / 2. + piece.indent as f32 * advance, y), &settings);
+            y += piece.text.lines().count().max(1) as f32 * line_height;
+        }
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+    }
+
+    // The cursor, then every cell of the grid in its color, a row at a time
+    fn draw_terminal(&mut self, app: &App, grid: &Grid, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, size: (u32, u32)) {
+        let settings = LayoutSettings { wrap_width: None, ..app.layout_settings() };
+        let (advance, line_height) = (app.advance(), app.line_height());
+        if app.cursor_shown {
+            let (col, row) = grid.cursor;
+            let rect = Rect { x: col as f32 * advance, y: row as f32 * line_height, w: advance, h: line_height };
+            self.shapes.queue(&app.cursor_style().shape(rect, CURSOR));
+        }
+        self.shapes.render(&self.device, &self.queue, encoder, view, size);
+
+        let text_color = app.accessibility.color(TEXT, BACKGROUND);
+        for row in 0..grid.rows {
+            let mut runs: Vec<(String, [f32; 4])> = Vec::new();
+            for col in 0..grid.cols {
+                let cell = grid.cell(col, row);
+                let color = cell.style.fg.rgba(text_color);
+                match runs.last_mut() {
+                    Some((text, last)) if *last == color => text.push(cell.ch),
+                    _ => runs.push((cell.ch.to_string(), color)),
+                }
+            }
+            let spans: Vec<TextSpan> = runs.iter().map(|(text, color)| TextSpan { text, color: *color }).collect();
+            self.text.queue(&self.device, &self.queue, &app.fontstack, &spans, (0., row as f32 * line_height), &settings);
+        }
+        self.text.render(&self.device, &self.queue, encoder, view, size);
+    }
+}
+
+// Headings, then the stack and the top frame's variables, then as much of the end of the output
+// as fits in `rows`
+fn debug_lines(session: &Session, rows: usize) -> Vec<(String, [f32; 4])> {
+    let mut lines = vec![("Stack".to_string(), BOLD)];
+    lines.extend(session.stack_lines().into_iter().map(|line| (line, TEXT)));
+    lines.push(("Variables".to_string(), BOLD));
+    lines.extend(session.variable_lines().into_iter().map(|line| (line, TEXT)));
+    lines.push(("Output".to_string(), BOLD));
+    let fit = rows.saturating_sub(lines.len());
+    lines.extend(session.output[session.output.len().saturating_sub(fit)..].iter().map(|line| (line.clone(), TEXT)));
+    lines
+}
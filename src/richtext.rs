@@ -0,0 +1,183 @@
+// Copying text with its highlight colors, as HTML with inline styles or as RTF, so pasting into
+// documents and slides keeps the colors. Platforms whose clipboard can't take formatted text get
+// the markup as plain text instead
+
+use std::fmt::Write as _;
+use std::ops::Range;
+
+use crate::clipboard::{Clipboard, Selection};
+use crate::commands::Registry;
+use crate::export::Colors;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    Rtf,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "html" => Some(Format::Html),
+            "rtf" => Some(Format::Rtf),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Format::Html => "text/html",
+            Format::Rtf => "text/rtf",
+        }
+    }
+}
+
+// What to draw the copied text with, besides the colors of its highlights
+#[derive(Debug, Clone, PartialEq)]
+pub struct Style {
+    pub font_family: String,
+    pub foreground: [f32; 4],
+    pub background: [f32; 4],
+}
+
+fn rgb(color: [f32; 4]) -> [u8; 3] {
+    [0, 1, 2].map(|channel| (color[channel].clamp(0., 1.) * 255.).round() as u8)
+}
+
+// The text cut where its color changes, with the color of each piece
+fn pieces<'t>(text: &'t str, colors: &[(Range<usize>, [f32; 4])], default: [f32; 4]) -> Vec<(&'t str, [f32; 4])> {
+    let color_at = |byte: usize| colors.iter().find(|(range, _)| range.contains(&byte)).map_or(default, |(_, color)| *color);
+    let mut cuts: Vec<usize> = colors.iter().flat_map(|(range, _)| [range.start, range.end]).filter(|&at| at < text.len() && text.is_char_boundary(at)).collect();
+    cuts.extend([0, text.len()]);
+    cuts.sort();
+    cuts.dedup();
+    let mut pieces: Vec<(&str, [f32; 4])> = Vec::new();
+    for pair in cuts.windows(2) {
+        let color = color_at(pair[0]);
+        match pieces.last_mut() {
+            // Neighbours of the same color are joined, for shorter markup
+            Some((last, last_color)) if *last_color == color => *last = &text[pair[0] - last.len()..pair[1]],
+            _ => pieces.push((&text[pair[0]..pair[1]], color)),
+        }
+    }
+    pieces
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// A <pre> with a <span> per colored piece. `colors` are byte ranges of `text`
+pub fn to_html(text: &str, colors: &Colors, style: &Style) -> String {
+    let [br, bg, bb] = rgb(style.background);
+    let [fr, fg, fb] = rgb(style.foreground);
+    let mut html = format!("<pre style=\"font-family: '{}', monospace; background-color: #{br:02x}{bg:02x}{bb:02x}; color: #{fr:02x}{fg:02x}{fb:02x}\">", escape_html(&style.font_family));
+    for (piece, color) in pieces(text, colors, style.foreground) {
+        match color == style.foreground {
+            true => html.push_str(&escape_html(piece)),
+            false => {
+                let [r, g, b] = rgb(color);
+                let _ = write!(html, "<span style=\"color: #{r:02x}{g:02x}{b:02x}\">{}</span>", escape_html(piece));
+            }
+        }
+    }
+    html.push_str("</pre>");
+    html
+}
+
+fn escape_rtf(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\line\n"),
+            '\t' => escaped.push_str("\\tab "),
+            c if c.is_ascii() => escaped.push(c),
+            // RTF takes signed 16 bit units, followed by a fallback for readers without unicode
+            c => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    let _ = write!(escaped, "\\u{}?", *unit as i16);
+                }
+            }
+        }
+    }
+    escaped
+}
+
+// RTF with a color table of every color used. `colors` are byte ranges of `text`
+pub fn to_rtf(text: &str, colors: &Colors, style: &Style) -> String {
+    let pieces = pieces(text, colors, style.foreground);
+    // The background is color 1, and text colors come after it
+    let mut table = vec![rgb(style.background)];
+    for (_, color) in &pieces {
+        if !table[1..].contains(&rgb(*color)) {
+            table.push(rgb(*color));
+        }
+    }
+    let mut rtf = format!("{{\\rtf1\\ansi\\deff0{{\\fonttbl{{\\f0\\fmodern {};}}}}{{\\colortbl;", escape_rtf(&style.font_family));
+    for [r, g, b] in &table {
+        let _ = write!(rtf, "\\red{r}\\green{g}\\blue{b};");
+    }
+    rtf.push_str("}\n\\f0\\fs20\\cb1\\highlight1 ");
+    for (piece, color) in pieces {
+        let idx = table[1..].iter().position(|&c| c == rgb(color)).unwrap() + 2;
+        let _ = write!(rtf, "\\cf{idx} {}", escape_rtf(piece));
+    }
+    rtf.push('}');
+    rtf
+}
+
+// What :copy-as needs from the editor
+pub trait RichCopyHost {
+    // The selected text, with the colors of its highlights as ranges of it
+    fn selected_highlights(&self) -> Option<(String, Colors)>;
+    fn copy_style(&self) -> Style;
+    fn clipboard(&mut self) -> &mut Clipboard;
+}
+
+// copy-as <html|rtf>
+fn copy_as_command<Ctx: RichCopyHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let [name] = args else { return Err("Usage: copy-as <html|rtf>".to_string()) };
+    let format = Format::parse(name).ok_or_else(|| format!("Can't copy as {name}, only html or rtf"))?;
+    let (text, colors) = ctx.selected_highlights().ok_or("Nothing selected")?;
+    let style = ctx.copy_style();
+    let markup = match format {
+        Format::Html => to_html(&text, &colors, &style),
+        Format::Rtf => to_rtf(&text, &colors, &style),
+    };
+    let clipboard = ctx.clipboard();
+    let copied = clipboard.copy_formatted(Selection::Clipboard, &markup, format.mime_type()).map_err(|e| e.to_string())?;
+    if !copied {
+        clipboard.copy(Selection::Clipboard, &markup).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+pub fn register<Ctx: RichCopyHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("copy-as", copy_as_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_and_rtf_keep_colors() {
+        let style = Style { font_family: "Fira Code".to_string(), foreground: [1.; 4], background: [0., 0., 0., 1.] };
+        let red = [1., 0., 0., 1.];
+        let text = "if a<b {\n\tx = \"é\"\n}";
+        let colors = vec![(0..2, red), (4..5, red), (5..6, red)];
+        assert_eq!(pieces(text, &colors, style.foreground), vec![("if", red), (" a", style.foreground), ("<b", red), (" {\n\tx = \"é\"\n}", style.foreground)]);
+
+        let html = to_html(text, &colors, &style);
+        assert!(html.starts_with("<pre style=\"font-family: 'Fira Code', monospace; background-color: #000000; color: #ffffff\">"));
+        assert!(html.contains("<span style=\"color: #ff0000\">&lt;b</span> {\n\tx = &quot;é&quot;"));
+
+        let rtf = to_rtf(text, &colors, &style);
+        assert!(rtf.contains("{\\colortbl;\\red0\\green0\\blue0;\\red255\\green0\\blue0;\\red255\\green255\\blue255;}"));
+        assert!(rtf.contains("\\cf2 if\\cf3  a\\cf2 <b\\cf3  \\{\\line\n\\tab x = \"\\u233?\"\\line\n\\}}"));
+    }
+}
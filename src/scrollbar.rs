@@ -0,0 +1,139 @@
+use std::time::{Duration, Instant};
+
+use crate::layout::Rect;
+use crate::shapes::Shape;
+use crate::viewport::Viewport;
+
+pub const WIDTH: f32 = 10.;
+// The thumb never gets smaller than this, so it can still be grabbed in long buffers
+const MIN_THUMB_HEIGHT: f32 = 20.;
+// How long the scrollbar stays after the last scroll, and how long it then takes to fade out
+const SHOW_FOR: Duration = Duration::from_millis(1000);
+const FADE_FOR: Duration = Duration::from_millis(300);
+
+// Scrollbar along the right edge, shown while scrolling or hovering and hidden again after a while
+#[derive(Debug, Default)]
+pub struct Scrollbar {
+    last_activity: Option<Instant>,
+    // Offset from the top of the thumb to where it was grabbed
+    dragging: Option<f32>,
+    hovered: bool,
+    // Disappears at once instead of fading out, for reduced motion
+    pub no_fade: bool,
+    // Opaque and bright, for the high contrast theme
+    pub high_contrast: bool,
+}
+
+impl Scrollbar {
+    pub fn track(&self, viewport: &Viewport, window_width: f32) -> Rect {
+        Rect { x: window_width - WIDTH, y: viewport.top, w: WIDTH, h: viewport.height }
+    }
+
+    // None when everything fits and there is nothing to scroll
+    pub fn thumb(&self, viewport: &Viewport, window_width: f32) -> Option<Rect> {
+        let max_scroll = viewport.max_scroll();
+        if max_scroll <= 0. {
+            return None;
+        }
+        let h = (viewport.height * viewport.height / viewport.content_height).max(MIN_THUMB_HEIGHT).min(viewport.height);
+        let y = viewport.top + (viewport.height - h) * viewport.scroll_y / max_scroll;
+        Some(Rect { x: window_width - WIDTH, y, w: WIDTH, h })
+    }
+
+    // The thumb as a pill faded to the current opacity, inset from the track so it doesn't touch the edges
+    pub fn thumb_shape(&self, viewport: &Viewport, window_width: f32, now: Instant) -> Option<Shape> {
+        let thumb = self.thumb(viewport, window_width)?;
+        let rect = Rect { x: thumb.x + 2., y: thumb.y + 2., w: thumb.w - 4., h: thumb.h - 4. };
+        let color = if self.high_contrast { [1., 1., 1., self.opacity(now)] } else { [0.5, 0.5, 0.5, 0.8 * self.opacity(now)] };
+        Some(Shape::RoundedRect { rect, radius: rect.w / 2., border: 0., color })
+    }
+
+    // Should be called whenever the viewport scrolls
+    pub fn activity(&mut self, now: Instant) {
+        self.last_activity = Some(now);
+    }
+
+    pub fn opacity(&self, now: Instant) -> f32 {
+        if self.dragging.is_some() || self.hovered {
+            return 1.;
+        }
+        let Some(last) = self.last_activity else { return 0. };
+        let since = now.duration_since(last);
+        if since < SHOW_FOR {
+            1.
+        } else if self.no_fade {
+            0.
+        } else {
+            1. - ((since - SHOW_FOR).as_secs_f32() / FADE_FOR.as_secs_f32()).min(1.)
+        }
+    }
+
+    // Whether the scrollbar is visible but will change opacity without further input
+    pub fn is_fading(&self, now: Instant) -> bool {
+        let o = self.opacity(now);
+        o > 0. && self.dragging.is_none() && !self.hovered
+    }
+
+    // Returns true if the press landed on the scrollbar, so it shouldn't go to the buffer
+    pub fn mouse_down(&mut self, viewport: &mut Viewport, window_width: f32, x: f32, y: f32, now: Instant) -> bool {
+        if x < window_width - WIDTH {
+            return false;
+        }
+        let Some(thumb) = self.thumb(viewport, window_width) else { return false };
+        if y < thumb.y || y >= thumb.y + thumb.h {
+            // Clicking the track jumps so the thumb is centered on the mouse
+            self.dragging = Some(thumb.h / 2.);
+            self.mouse_moved(viewport, window_width, x, y, now);
+        } else {
+            self.dragging = Some(y - thumb.y);
+        }
+        self.activity(now);
+        true
+    }
+
+    pub fn mouse_moved(&mut self, viewport: &mut Viewport, window_width: f32, x: f32, y: f32, now: Instant) {
+        self.hovered = x >= window_width - WIDTH && self.thumb(viewport, window_width).is_some();
+        let (Some(grab), Some(thumb)) = (self.dragging, self.thumb(viewport, window_width)) else { return };
+        let free = viewport.height - thumb.h;
+        if free > 0. {
+            viewport.scroll_to((y - viewport.top - grab) / free * viewport.max_scroll());
+        }
+        self.activity(now);
+    }
+
+    pub fn mouse_up(&mut self, now: Instant) {
+        if self.dragging.take().is_some() {
+            self.activity(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drag_and_fade() {
+        let mut viewport = Viewport::new(16., 100.);
+        viewport.content_height = 1000.;
+        let mut scrollbar = Scrollbar::default();
+        let now = Instant::now();
+
+        let thumb = scrollbar.thumb(&viewport, 500.).unwrap();
+        assert_eq!((thumb.y, thumb.h), (0., 20.));
+
+        assert!(!scrollbar.mouse_down(&mut viewport, 500., 10., 10., now));
+        assert!(scrollbar.mouse_down(&mut viewport, 500., 495., 10., now));
+        scrollbar.mouse_moved(&mut viewport, 500., 495., 50., now);
+        assert_eq!(viewport.scroll_y, 450.);
+        scrollbar.mouse_moved(&mut viewport, 500., 300., 1000., now);
+        assert_eq!(viewport.scroll_y, 900.);
+        scrollbar.mouse_up(now);
+
+        assert_eq!(scrollbar.opacity(now + SHOW_FOR / 2), 1.);
+        assert!(scrollbar.is_fading(now + SHOW_FOR + FADE_FOR / 2));
+        assert_eq!(scrollbar.opacity(now + SHOW_FOR + FADE_FOR), 0.);
+        scrollbar.no_fade = true;
+        assert_eq!(scrollbar.opacity(now + SHOW_FOR + FADE_FOR / 2), 0.);
+    }
+}
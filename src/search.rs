@@ -0,0 +1,216 @@
+// Searching the buffer with the '/' prompt, moving to the next match as the pattern is typed.
+// Patterns are regexes, searched without case unless they have an uppercase letter in them
+// (smart-case), and optionally only as whole words. Searches are remembered across sessions, and
+// Up and Down in the prompt go through the ones starting with what's typed. '*' searches for what
+// the primary selection has in it, and matches can be turned into selections, in the viewport or
+// the whole buffer
+
+use std::ops::Range;
+use std::path::PathBuf;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::grammar::normalize;
+use crate::selection::{is_word_char, line_starts, word_around, Selection};
+
+// Remembered, oldest first
+const MAX_HISTORY: usize = 200;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Search {
+    pub pattern: String,
+    // Only matches with no word characters right before or after them. Toggled with Alt+W in the prompt
+    pub whole_word: bool,
+}
+
+// Escaped characters like \S and \W aren't letters the user typed, so they don't count
+fn has_uppercase(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            c if c.is_uppercase() => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+impl Search {
+    // '*': the text of `selection` as it is, or the word under it when it's a cursor. Whole words
+    // when it starts and ends on word boundaries, like kakoune adding \b
+    pub fn from_selection(text: &str, selection: &Selection) -> Option<Search> {
+        let range = match selection.range() {
+            range if range.is_empty() => word_around(text, &range),
+            range => range,
+        };
+        let selected = text.get(range.clone()).filter(|selected| !selected.is_empty())?;
+        let word_at = |c: Option<char>| c.is_some_and(is_word_char);
+        let starts_word = word_at(selected.chars().next()) && !word_at(text[..range.start].chars().next_back());
+        let ends_word = word_at(selected.chars().next_back()) && !word_at(text[range.end..].chars().next());
+        Some(Search { pattern: regex::escape(selected), whole_word: starts_word && ends_word })
+    }
+
+    pub fn regex(&self) -> Result<Regex, regex::Error> {
+        let pattern = if self.whole_word { format!(r"\b(?:{})\b", self.pattern) } else { self.pattern.clone() };
+        RegexBuilder::new(&pattern).multi_line(true).case_insensitive(!has_uppercase(&self.pattern)).build()
+    }
+
+    // Every match in `range` of `text`. Empty matches are skipped, as there is nothing to select
+    pub fn matches(&self, text: &str, range: Range<usize>) -> Result<Vec<Range<usize>>, regex::Error> {
+        let regex = self.regex()?;
+        Ok(regex.find_iter(&text[range.clone()]).filter(|found| !found.is_empty()).map(|found| range.start + found.start()..range.start + found.end()).collect())
+    }
+}
+
+// The match to go to from `cursor`, wrapping around the end of the buffer
+pub fn next_match(matches: &[Range<usize>], cursor: usize, backwards: bool) -> Option<Range<usize>> {
+    let found = match backwards {
+        true => matches.iter().rev().find(|found| found.start < cursor).or(matches.last()),
+        false => matches.iter().find(|found| found.start > cursor).or(matches.first()),
+    };
+    found.cloned()
+}
+
+// Every match in `range` as a selection, with the head at the end. None when there are none, to
+// keep the selections as they were
+pub fn select_matches(text: &str, search: &Search, range: Range<usize>) -> Result<Option<Vec<Selection>>, regex::Error> {
+    let mut selections: Vec<Selection> = search.matches(text, range)?.into_iter().map(|found| Selection { anchor: found.start, head: found.end, goal: None }).collect();
+    if selections.is_empty() {
+        return Ok(None);
+    }
+    normalize(text, &mut selections);
+    Ok(Some(selections))
+}
+
+// Bytes of `lines`, like Viewport::visible_lines, for select_matches in the viewport
+pub fn lines_bytes(text: &str, lines: Range<usize>) -> Range<usize> {
+    let starts = line_starts(text);
+    let at = |line: usize| starts.get(line).copied().unwrap_or(text.len());
+    at(lines.start)..at(lines.end)
+}
+
+// Which match the cursor is on or after, 1-based, and how many there are, for the status line
+pub fn match_count(matches: &[Range<usize>], cursor: usize) -> Option<(usize, usize)> {
+    let before = matches.iter().take_while(|found| found.start <= cursor).count();
+    (!matches.is_empty()).then_some((before.max(1), matches.len()))
+}
+
+// Searches typed in the prompt, stored one per line
+#[derive(Debug, Default)]
+pub struct SearchHistory {
+    pub path: Option<PathBuf>,
+    pub entries: Vec<String>,
+    // While going through the history: what was typed before, and the entry shown
+    browsing: Option<(String, usize)>,
+}
+
+impl SearchHistory {
+    pub fn default_path() -> Option<PathBuf> {
+        let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+        };
+        Some(state_dir.join("rakoune").join("search"))
+    }
+
+    pub fn load(path: PathBuf) -> SearchHistory {
+        let text = std::fs::read_to_string(&path).unwrap_or_default();
+        SearchHistory { path: Some(path), entries: text.lines().filter(|line| !line.is_empty()).map(str::to_string).collect(), browsing: None }
+    }
+
+    // When a search is made. The same search again moves to the end instead of being kept twice
+    pub fn add(&mut self, pattern: &str) -> std::io::Result<()> {
+        self.browsing = None;
+        if pattern.is_empty() || pattern.contains('\n') {
+            return Ok(());
+        }
+        self.entries.retain(|entry| entry != pattern);
+        self.entries.push(pattern.to_string());
+        let excess = self.entries.len().saturating_sub(MAX_HISTORY);
+        self.entries.drain(..excess);
+        let Some(path) = &self.path else { return Ok(()) };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.entries.join("\n") + "\n")
+    }
+
+    // Up in the prompt, with `typed` in it. Returns what the prompt should show
+    pub fn older(&mut self, typed: &str) -> Option<&str> {
+        let (prefix, from) = self.browsing.clone().unwrap_or((typed.to_string(), self.entries.len()));
+        let at = self.entries[..from].iter().rposition(|entry| entry.starts_with(&prefix))?;
+        self.browsing = Some((prefix, at));
+        Some(&self.entries[at])
+    }
+
+    // Down in the prompt. Past the newest entry it's back to what was typed
+    pub fn newer(&mut self) -> Option<String> {
+        let (prefix, from) = self.browsing.take()?;
+        match self.entries.iter().skip(from + 1).position(|entry| entry.starts_with(&prefix)) {
+            Some(offset) => {
+                self.browsing = Some((prefix, from + 1 + offset));
+                Some(self.entries[from + 1 + offset].clone())
+            }
+            None => Some(prefix),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smart_case_words_and_history() {
+        let text = "Foo foo food\nfoo_bar FOO";
+        let search = |pattern: &str, whole_word| Search { pattern: pattern.to_string(), whole_word }.matches(text, 0..text.len()).unwrap();
+        assert_eq!(search("foo", false).len(), 5);
+        assert_eq!(search("Foo", false), vec![0..3]);
+        // \W isn't an uppercase letter
+        assert_eq!(search(r"foo\W", false).len(), 2);
+        assert_eq!(search("foo", true), vec![0..3, 4..7, 21..24]);
+
+        let matches = search("foo", true);
+        assert_eq!(next_match(&matches, 4, false), Some(21..24));
+        assert_eq!(next_match(&matches, 21, false), Some(0..3));
+        assert_eq!(next_match(&matches, 0, true), Some(21..24));
+        assert_eq!(match_count(&matches, 5), Some((2, 3)));
+        assert_eq!(match_count(&[], 5), None);
+
+        let dir = std::env::temp_dir().join(format!("rakoune-search-{}", std::process::id()));
+        let mut history = SearchHistory::load(dir.join("search"));
+        for pattern in ["fn main", "foo", "fn new", "foo"] {
+            history.add(pattern).unwrap();
+        }
+        let mut history = SearchHistory::load(dir.join("search"));
+        assert_eq!(history.entries, vec!["fn main", "fn new", "foo"]);
+        assert_eq!(history.older("fn"), Some("fn new"));
+        assert_eq!(history.older("fn"), Some("fn main"));
+        assert_eq!(history.older("fn"), None);
+        assert_eq!(history.newer().as_deref(), Some("fn new"));
+        assert_eq!(history.newer().as_deref(), Some("fn"));
+        assert_eq!(history.newer(), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn selections_and_search_round_trip() {
+        let text = "let a = foo(x);\nfoo_bar(a.b);\nfoo(a.b)\n";
+        let star = Search::from_selection(text, &Selection::cursor(9)).unwrap();
+        assert_eq!(star, Search { pattern: "foo".to_string(), whole_word: true });
+        // Not starting on a word boundary, and escaped
+        let dotted = Search::from_selection(text, &Selection { anchor: 27, head: 25, goal: None }).unwrap();
+        assert_eq!(dotted, Search { pattern: r"\.b".to_string(), whole_word: false });
+        assert_eq!(Search::from_selection(text, &Selection::cursor(7)), None);
+
+        let selections = select_matches(text, &star, 0..text.len()).unwrap().unwrap();
+        assert_eq!(selections.iter().map(|selection| &text[selection.range()]).collect::<Vec<_>>(), ["foo", "foo"]);
+        assert_eq!(selections[1].head, 33);
+        let dotted = select_matches(text, &dotted, lines_bytes(text, 2..5)).unwrap().unwrap();
+        assert_eq!(dotted, vec![Selection { anchor: 35, head: 37, goal: None }]);
+        assert_eq!(select_matches(text, &Search { pattern: "bar".to_string(), whole_word: true }, 0..text.len()).unwrap(), None);
+    }
+}
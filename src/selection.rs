@@ -0,0 +1,233 @@
+use std::ops::Range;
+
+use crate::folding::indent_folds;
+
+pub(crate) fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Byte offsets where each line starts
+pub fn line_starts(text: &str) -> Vec<usize> {
+    std::iter::once(0).chain(text.match_indices('\n').map(|(at, _)| at + 1)).collect()
+}
+
+fn line_of(starts: &[usize], byte: usize) -> usize {
+    starts.partition_point(|&start| start <= byte).saturating_sub(1)
+}
+
+// Byte range of lines `lines`, including the newline of the last line if it has one
+fn lines_range(text: &str, starts: &[usize], lines: Range<usize>) -> Range<usize> {
+    let start = starts[lines.start];
+    let end = starts.get(lines.end).copied().unwrap_or(text.len());
+    start..end
+}
+
+pub(crate) fn word_around(text: &str, range: &Range<usize>) -> Range<usize> {
+    let start = text[..range.start]
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_word_char(c))
+        .last()
+        .map_or(range.start, |(at, _)| at);
+    let end = text[range.end..]
+        .char_indices()
+        .find(|&(_, c)| !is_word_char(c))
+        .map_or(text.len(), |(at, _)| range.end + at);
+    start..end
+}
+
+fn is_blank(text: &str, starts: &[usize], line: usize) -> bool {
+    text[lines_range(text, starts, line..line + 1)].trim().is_empty()
+}
+
+fn paragraph_around(text: &str, starts: &[usize], lines: Range<usize>) -> Range<usize> {
+    let mut first = lines.start;
+    while first > 0 && !is_blank(text, starts, first - 1) {
+        first -= 1;
+    }
+    let mut end = lines.end;
+    while end < starts.len() && !is_blank(text, starts, end) {
+        end += 1;
+    }
+    first..end
+}
+
+// Tabs go to the next multiple of this many columns, for keeping the column when moving up and down
+pub const TAB_WIDTH: usize = 4;
+
+// Columns `line` takes up on screen, with tabs going to the next tab stop
+pub fn visual_column(line: &str) -> usize {
+    line.chars().fold(0, |column, c| if c == '\t' { (column / TAB_WIDTH + 1) * TAB_WIDTH } else { column + 1 })
+}
+
+// Byte offset of the character at `column` of `line`, or of the end of the line if it's shorter. A
+// column inside a tab is on the tab
+pub fn byte_at_column(line: &str, column: usize) -> usize {
+    let mut at_column = 0;
+    for (at, c) in line.char_indices() {
+        at_column = if c == '\t' { (at_column / TAB_WIDTH + 1) * TAB_WIDTH } else { at_column + 1 };
+        if at_column > column {
+            return at;
+        }
+    }
+    line.len()
+}
+
+// Where vertical motions try to put the head. It's kept while moving through lines too short to
+// reach it, so coming out of them returns to where the motions started
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Goal {
+    // Screen column in the line, for j and k
+    Column(usize),
+    // x coordinate in the row, for gj and gk
+    X(f32),
+}
+
+// A selection keeps the end it was started from (anchor) apart from the end that moves (head), so
+// extending to the left of the anchor and back works. The head is also the cursor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+    // Set by vertical motions, and cleared by every other way of moving the head
+    pub goal: Option<Goal>,
+}
+
+impl Selection {
+    pub fn cursor(at: usize) -> Selection {
+        Selection { anchor: at, head: at, goal: None }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.anchor.min(self.head)..self.anchor.max(self.head)
+    }
+
+    pub fn is_backward(&self) -> bool {
+        self.head < self.anchor
+    }
+
+    // Swaps the ends, so the other end moves from now on
+    pub fn flip(&mut self) {
+        std::mem::swap(&mut self.anchor, &mut self.head);
+    }
+
+    // The character to draw as the head: the last selected one when the head is at the end, the
+    // first when it is at the start. Empty at the end of the text
+    pub fn head_cell(&self, text: &str) -> Range<usize> {
+        if self.head > self.anchor {
+            let before = text[..self.head].chars().next_back().map_or(0, char::len_utf8);
+            self.head - before..self.head
+        } else {
+            let after = text[self.head..].chars().next().map_or(0, char::len_utf8);
+            self.head..self.head + after
+        }
+    }
+}
+
+// The next bigger unit of text around `range`: word, line, paragraph, then enclosing indentation
+// blocks. Returns `range` itself if there is nothing bigger
+pub fn expand(text: &str, range: Range<usize>) -> Range<usize> {
+    let starts = line_starts(text);
+    let candidates = {
+        let first_line = line_of(&starts, range.start);
+        let last_line = line_of(&starts, range.end.saturating_sub(1).max(range.start)) + 1;
+        let lines = first_line..last_line;
+        let paragraph = paragraph_around(text, &starts, lines.clone());
+        let mut candidates = vec![word_around(text, &range), lines_range(text, &starts, lines.clone()), lines_range(text, &starts, paragraph)];
+
+        let mut blocks: Vec<Range<usize>> = indent_folds(text)
+            .into_iter()
+            .map(|fold| fold.lines)
+            .filter(|block| block.start <= lines.start && lines.end <= block.end)
+            .collect();
+        blocks.sort_by_key(|block| block.len());
+        candidates.extend(blocks.into_iter().map(|block| lines_range(text, &starts, block)));
+        candidates.push(0..text.len());
+        candidates
+    };
+    candidates
+        .into_iter()
+        .find(|candidate| candidate.start <= range.start && range.end <= candidate.end && candidate.len() > range.len())
+        .unwrap_or(range)
+}
+
+// Remembers what the selections were before each expansion, so they can be shrunk back
+#[derive(Debug, Default, Clone)]
+pub struct ExpansionHistory {
+    stack: Vec<Vec<Range<usize>>>,
+}
+
+impl ExpansionHistory {
+    // Grows each selection with `expand`, like the free function here or a syntax tree's
+    pub fn expand(&mut self, selections: &mut [Range<usize>], expand: impl Fn(Range<usize>) -> Range<usize>) {
+        self.stack.push(selections.to_vec());
+        for selection in selections.iter_mut() {
+            *selection = expand(selection.clone());
+        }
+    }
+
+    // Goes back to the selections before the last expansion. Does nothing if the selections were
+    // changed some other way since then
+    pub fn shrink(&mut self, selections: &mut Vec<Range<usize>>) {
+        let Some(previous) = self.stack.pop() else { return };
+        if previous.len() == selections.len() && previous.iter().zip(selections.iter()).all(|(p, s)| s.start <= p.start && p.end <= s.end) {
+            *selections = previous;
+        } else {
+            self.stack.clear();
+        }
+    }
+
+    // Should be called when the selections change for other reasons than expanding or shrinking
+    pub fn clear(&mut self) {
+        self.stack.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_word_line_paragraph_block() {
+        let text = "fn f() {\n    let abc = 1;\n    g(abc);\n\n    h();\n}\n";
+        let at = text.find("bc").unwrap();
+        let mut range = at..at;
+        let mut steps = Vec::new();
+        for _ in 0..5 {
+            range = expand(text, range);
+            steps.push(&text[range.clone()]);
+        }
+        assert_eq!(steps, vec![
+            "abc",
+            "    let abc = 1;\n",
+            "fn f() {\n    let abc = 1;\n    g(abc);\n",
+            text.trim_end_matches("}\n"),
+            text,
+        ]);
+    }
+
+    #[test]
+    fn head_cell_follows_orientation() {
+        let text = "héllo";
+        let mut selection = Selection { anchor: 0, head: 3, goal: None };
+        assert_eq!(selection.head_cell(text), 1..3);
+        selection.flip();
+        assert!(selection.is_backward());
+        assert_eq!(selection.range(), 0..3);
+        assert_eq!(selection.head_cell(text), 0..1);
+        assert_eq!(Selection::cursor(6).head_cell(text), 6..6);
+    }
+
+    #[test]
+    fn shrink_undoes_expand() {
+        let text = "one two\nthree";
+        let mut selections = vec![1..1, 9..9];
+        let mut history = ExpansionHistory::default();
+        history.expand(&mut selections, |range| expand(text, range));
+        assert_eq!(selections, vec![0..3, 8..13]);
+        history.expand(&mut selections, |range| expand(text, range));
+        history.shrink(&mut selections);
+        history.shrink(&mut selections);
+        assert_eq!(selections, vec![1..1, 9..9]);
+    }
+}
@@ -0,0 +1,213 @@
+// Semantic token highlighting, as sent by language servers in semanticTokens/full and
+// semanticTokens/full/delta responses. Tokens come as a flat array of five numbers per token, each
+// relative to the one before it:
+//
+//   [line delta, start delta, length, token type, modifier bits]
+//
+// Types and modifiers index the legend the server gave when initializing. They become faces like
+// "variable.readonly", which are merged with the bracket tree's highlights, semantic ones winning
+
+use std::ops::Range;
+
+use thiserror::Error;
+
+use crate::selection::line_starts;
+
+// Priorities of highlight sources when they overlap. Higher wins
+pub const SYNTAX: u8 = 0;
+pub const SEMANTIC: u8 = 1;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("Token data has {0} numbers, which isn't a multiple of 5")]
+    BadLength(usize),
+    #[error("Edit at {start} deleting {delete_count} is outside of the {len} numbers of token data")]
+    EditOutOfRange { start: usize, delete_count: usize, len: usize },
+}
+
+// From the server's semanticTokensProvider capability
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Legend {
+    pub token_types: Vec<String>,
+    pub token_modifiers: Vec<String>,
+}
+
+impl Legend {
+    // "type.modifier.modifier", with modifiers in legend order. None for types not in the legend
+    pub fn face(&self, token_type: u32, modifiers: u32) -> Option<String> {
+        let mut face = self.token_types.get(token_type as usize)?.clone();
+        for (bit, modifier) in self.token_modifiers.iter().enumerate().take(32) {
+            if modifiers & (1 << bit) != 0 {
+                face.push('.');
+                face.push_str(modifier);
+            }
+        }
+        Some(face)
+    }
+}
+
+// Faces to look for in the theme, most specific first: "a.b.c", "a.b", "a"
+pub fn fallbacks(face: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(face), |face| face.rfind('.').map(|dot| &face[..dot]))
+}
+
+// One edit of a delta response: replace `delete_count` numbers at `start` with `data`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokensEdit {
+    pub start: usize,
+    pub delete_count: usize,
+    pub data: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Highlight {
+    pub range: Range<usize>,
+    pub face: String,
+    pub priority: u8,
+}
+
+// The tokens of one buffer
+#[derive(Debug, Default, Clone)]
+pub struct SemanticTokens {
+    // To ask for a delta against next time
+    pub result_id: Option<String>,
+    data: Vec<u32>,
+}
+
+impl SemanticTokens {
+    pub fn full(result_id: Option<String>, data: Vec<u32>) -> Result<SemanticTokens, Error> {
+        match data.len() % 5 {
+            0 => Ok(SemanticTokens { result_id, data }),
+            _ => Err(Error::BadLength(data.len())),
+        }
+    }
+
+    // Applies a delta response. On errors the tokens are left as they were, and should be asked
+    // for in full again
+    pub fn apply_delta(&mut self, result_id: Option<String>, edits: &[TokensEdit]) -> Result<(), Error> {
+        // Edits all refer to the old data, so they are applied back to front
+        let mut sorted: Vec<&TokensEdit> = edits.iter().collect();
+        sorted.sort_by_key(|edit| std::cmp::Reverse(edit.start));
+        let mut data = self.data.clone();
+        for edit in sorted {
+            if edit.start + edit.delete_count > data.len() {
+                return Err(Error::EditOutOfRange { start: edit.start, delete_count: edit.delete_count, len: data.len() });
+            }
+            data.splice(edit.start..edit.start + edit.delete_count, edit.data.iter().copied());
+        }
+        *self = SemanticTokens::full(result_id, data)?;
+        Ok(())
+    }
+
+    // Highlights in bytes of `text`, which positions the server sent in UTF-16 code units are
+    // converted to. Tokens of types missing from the legend, or outside of the text, are skipped
+    pub fn highlights(&self, legend: &Legend, text: &str) -> Vec<Highlight> {
+        let starts = line_starts(text);
+        let (mut line, mut column) = (0, 0);
+        let mut highlights = Vec::new();
+        for token in self.data.chunks_exact(5) {
+            let [line_delta, start_delta, length, token_type, modifiers] = [token[0], token[1], token[2], token[3], token[4]];
+            if line_delta > 0 {
+                column = 0;
+            }
+            line += line_delta as usize;
+            column += start_delta as usize;
+            let Some(&line_start) = starts.get(line) else { break };
+            let line_text = text[line_start..].split('\n').next().unwrap();
+            let (Some(start), Some(face)) = (utf16_to_byte(line_text, column), legend.face(token_type, modifiers)) else { continue };
+            let end = utf16_to_byte(line_text, column + length as usize).unwrap_or(line_text.len());
+            highlights.push(Highlight { range: line_start + start..line_start + end, face, priority: SEMANTIC });
+        }
+        highlights
+    }
+}
+
+// Byte offset in `line` of a UTF-16 column. None past the end of the line
+pub(crate) fn utf16_to_byte(line: &str, column: usize) -> Option<usize> {
+    let mut units = 0;
+    for (at, c) in line.char_indices() {
+        if units >= column {
+            return Some(at);
+        }
+        units += c.len_utf16();
+    }
+    (units >= column).then_some(line.len())
+}
+
+// Flattens overlapping highlights from several sources into sorted, non-overlapping ranges. Where
+// they overlap, the higher priority wins, then the shorter, innermost one
+pub fn merge(mut highlights: Vec<Highlight>) -> Vec<(Range<usize>, String)> {
+    highlights.retain(|highlight| !highlight.range.is_empty());
+    highlights.sort_by_key(|highlight| highlight.range.start);
+    let mut bounds: Vec<usize> = highlights.iter().flat_map(|highlight| [highlight.range.start, highlight.range.end]).collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut merged: Vec<(Range<usize>, String)> = Vec::new();
+    let mut active: Vec<&Highlight> = Vec::new();
+    let mut next = highlights.iter().peekable();
+    for pair in bounds.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        active.retain(|highlight| highlight.range.end > from);
+        while let Some(highlight) = next.next_if(|highlight| highlight.range.start <= from) {
+            active.push(highlight);
+        }
+        let Some(winner) = active.iter().max_by_key(|highlight| (highlight.priority, std::cmp::Reverse(highlight.range.len()))) else { continue };
+        match merged.last_mut() {
+            Some((range, face)) if range.end == from && *face == winner.face => range.end = to,
+            _ => merged.push((from..to, winner.face.clone())),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legend() -> Legend {
+        Legend {
+            token_types: vec!["variable".to_string(), "function".to_string()],
+            token_modifiers: vec!["readonly".to_string(), "static".to_string()],
+        }
+    }
+
+    #[test]
+    fn decodes_relative_utf16_positions() {
+        let text = "let é = f(é);\n  🦀 x\n";
+        // `é` at 4, `f` at 8, `é` at 10 on the first line; `x` after the crab, which is two units
+        let data = vec![0, 4, 1, 0, 1, 0, 4, 1, 1, 0, 0, 2, 1, 0, 0, 1, 5, 1, 0, 3, 0, 0, 1, 9, 0];
+        let tokens = SemanticTokens::full(None, data).unwrap();
+        let highlights = tokens.highlights(&legend(), text);
+        let found: Vec<(&str, &str)> = highlights.iter().map(|highlight| (&text[highlight.range.clone()], highlight.face.as_str())).collect();
+        assert_eq!(found, vec![("é", "variable.readonly"), ("f", "function"), ("é", "variable"), ("x", "variable.readonly.static")]);
+    }
+
+    #[test]
+    fn deltas() {
+        let mut tokens = SemanticTokens::full(Some("1".to_string()), vec![0, 0, 1, 0, 0, 0, 2, 1, 1, 0]).unwrap();
+        let edits = [TokensEdit { start: 5, delete_count: 5, data: vec![] }, TokensEdit { start: 0, delete_count: 0, data: vec![0, 0, 1, 1, 0] }];
+        tokens.apply_delta(Some("2".to_string()), &edits).unwrap();
+        assert_eq!(tokens.data, vec![0, 0, 1, 1, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(tokens.result_id.as_deref(), Some("2"));
+
+        let bad = [TokensEdit { start: 8, delete_count: 5, data: vec![] }];
+        assert_eq!(tokens.apply_delta(None, &bad), Err(Error::EditOutOfRange { start: 8, delete_count: 5, len: 10 }));
+        assert_eq!(tokens.result_id.as_deref(), Some("2"), "unchanged on errors");
+        assert_eq!(SemanticTokens::full(None, vec![0; 4]).unwrap_err(), Error::BadLength(4));
+    }
+
+    #[test]
+    fn merges_by_priority() {
+        let highlight = |range: Range<usize>, face: &str, priority| Highlight { range, face: face.to_string(), priority };
+        let merged = merge(vec![
+            highlight(0..10, "string", SYNTAX),
+            highlight(2..4, "punctuation", SYNTAX),
+            highlight(3..6, "variable", SEMANTIC),
+            highlight(12..14, "comment", SYNTAX),
+        ]);
+        let expected = [(0..2, "string"), (2..3, "punctuation"), (3..6, "variable"), (6..10, "string"), (12..14, "comment")];
+        assert_eq!(merged, expected.map(|(range, face)| (range, face.to_string())));
+        assert_eq!(fallbacks("variable.readonly.static").collect::<Vec<_>>(), vec!["variable.readonly.static", "variable.readonly", "variable"]);
+    }
+}
@@ -0,0 +1,296 @@
+// One rakoune per user. The first one listens on a local socket, and starting another one with
+// files, like the OS does for "Open with", sends them to it instead of opening a second window.
+// Requests are lines of text, like `open /home/me/notes.txt`, with backslashes, newlines and bytes
+// that aren't UTF-8 escaped. Windows has no unix sockets in std, so there it's a TCP port on
+// localhost, written to a file where the socket would be. Anyone on the machine can connect to
+// that, so the file also has a token which has to be the first line sent
+//
+// The socket is in a directory only this user can get into, as whoever can connect to it can open
+// files in our window, and whoever could put their own socket there would be sent our paths
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Open(PathBuf),
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> io::Result<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(path.as_os_str().as_bytes().to_vec())
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> io::Result<Vec<u8>> {
+    let path = path.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't valid unicode", path.display())))?;
+    Ok(path.as_bytes().to_vec())
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+// The path as one line of UTF-8
+fn escape(path: &Path) -> io::Result<String> {
+    let bytes = path_bytes(path)?;
+    let mut escaped = String::new();
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                c => escaped.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{byte:02x}"));
+        }
+    }
+    Ok(escaped)
+}
+
+fn unescape(line: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                '\\' => bytes.push(b'\\'),
+                'n' => bytes.push(b'\n'),
+                'r' => bytes.push(b'\r'),
+                'x' => bytes.push(u8::from_str_radix(&chars.by_ref().take(2).collect::<String>(), 16).ok()?),
+                _ => return None,
+            },
+            c => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    path_from_bytes(bytes)
+}
+
+impl Request {
+    fn line(&self) -> io::Result<String> {
+        match self {
+            Request::Open(path) => Ok(format!("open {}\n", escape(path)?)),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Request> {
+        match line.split_once(' ')? {
+            ("open", path) if !path.is_empty() => Some(Request::Open(unescape(path)?)),
+            _ => None,
+        }
+    }
+}
+
+// In the runtime dir where there is one, which is already private. Elsewhere in a directory of
+// our own in the temporary directory, which everyone can write to
+#[cfg(unix)]
+pub fn default_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("rakoune.sock"),
+        // SAFETY: geteuid can't fail
+        None => std::env::temp_dir().join(format!("rakoune-{}", unsafe { libc::geteuid() })).join("rakoune.sock"),
+    }
+}
+
+#[cfg(not(unix))]
+pub fn default_path() -> PathBuf {
+    let user = std::env::var("USERNAME").unwrap_or_default();
+    std::env::temp_dir().join(format!("rakoune-{user}")).join("rakoune.sock")
+}
+
+// Makes the directory `path` is in if it isn't there, and checks nobody else can get into it
+#[cfg(unix)]
+fn private_dir(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+    let dir = path.parent().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The socket needs to be in a directory"))?;
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    // Not followed if it's a link, as someone else could point it anywhere
+    let metadata = std::fs::symlink_metadata(dir)?;
+    // SAFETY: geteuid can't fail
+    if !metadata.is_dir() || metadata.uid() != unsafe { libc::geteuid() } || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} isn't a directory only we can use", dir.display())));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn private_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) => std::fs::create_dir_all(dir),
+        None => Ok(()),
+    }
+}
+
+// Sends `requests` to the rakoune listening at `path`. Ok(false) if none is
+pub fn send(path: &Path, requests: &[Request]) -> io::Result<bool> {
+    private_dir(path)?;
+    let Some(mut stream) = connect(path)? else { return Ok(false) };
+    let lines: String = requests.iter().map(Request::line).collect::<io::Result<_>>()?;
+    stream.write_all(lines.as_bytes())?;
+    Ok(true)
+}
+
+// Starts listening at `path`, calling `received` with each request. Every connection is read on a
+// thread of its own, so one that never sends anything doesn't hold up the others
+pub fn listen(path: &Path, received: impl Fn(Request) + Clone + Send + 'static) -> io::Result<()> {
+    private_dir(path)?;
+    let (listener, token) = bind(path)?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let (received, token) = (received.clone(), token.clone());
+            std::thread::spawn(move || {
+                let mut lines = BufReader::new(stream).lines();
+                if token.is_some() && lines.next().and_then(Result::ok) != token {
+                    return;
+                }
+                for line in lines {
+                    let Ok(line) = line else { break };
+                    match Request::parse(&line) {
+                        Some(request) => received(request),
+                        None => eprintln!("Unknown request {line:?}"),
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(unix)]
+fn connect(path: &Path) -> io::Result<Option<std::os::unix::net::UnixStream>> {
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(stream) => Ok(Some(stream)),
+        Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Another rakoune listening at `path` already, started without files so it didn't send them there
+fn running(path: &Path) -> io::Result<()> {
+    match connect(path)? {
+        Some(_) => Err(io::Error::new(io::ErrorKind::AddrInUse, "Another rakoune is listening there")),
+        None => Ok(()),
+    }
+}
+
+// The listener, and the token connections have to start with if there is one
+#[cfg(unix)]
+fn bind(path: &Path) -> io::Result<(std::os::unix::net::UnixListener, Option<String>)> {
+    running(path)?;
+    // Nothing answered, so it was left behind by a rakoune that didn't exit cleanly
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    Ok((std::os::unix::net::UnixListener::bind(path)?, None))
+}
+
+// The file has the port and the token, like `49152 9f86d081884c7d65...`
+#[cfg(windows)]
+fn connect(path: &Path) -> io::Result<Option<std::net::TcpStream>> {
+    let Ok(contents) = std::fs::read_to_string(path) else { return Ok(None) };
+    let Some((port, token)) = contents.trim().split_once(' ') else { return Ok(None) };
+    let Ok(port) = port.parse::<u16>() else { return Ok(None) };
+    match std::net::TcpStream::connect(("127.0.0.1", port)) {
+        Ok(mut stream) => {
+            stream.write_all(format!("{token}\n").as_bytes())?;
+            Ok(Some(stream))
+        }
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Unguessable without the keys std seeds its hashers with from the OS
+#[cfg(windows)]
+fn token() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    (0..4).map(|_| format!("{:016x}", std::collections::hash_map::RandomState::new().build_hasher().finish())).collect()
+}
+
+// The temporary directory on Windows is in the user's profile, which other users can't read
+#[cfg(windows)]
+fn bind(path: &Path) -> io::Result<(std::net::TcpListener, Option<String>)> {
+    running(path)?;
+    let listener = std::net::TcpListener::bind(("127.0.0.1", 0))?;
+    let token = token();
+    std::fs::write(path, format!("{} {token}", listener.local_addr()?.port()))?;
+    Ok((listener, Some(token)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn files_go_to_the_running_instance() {
+        let dir = std::env::temp_dir().join(format!("rakoune-test-{}", std::process::id()));
+        let path = dir.join("rakoune.sock");
+        assert!(!send(&path, &[Request::Open(PathBuf::from("/tmp/a.txt"))]).unwrap());
+
+        let (tx, rx) = mpsc::channel();
+        listen(&path, move |request| tx.send(request).unwrap()).unwrap();
+        // Another one started without files leaves this one listening
+        assert_eq!(listen(&path, |_| {}).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        // A connection that sends nothing doesn't keep others waiting
+        let _idle = connect(&path).unwrap().unwrap();
+        let requests = [Request::Open(PathBuf::from("/tmp/a.txt")), Request::Open(PathBuf::from("/tmp/with space.rs")), Request::Open(PathBuf::from("/tmp/two\nlines\\n.rs"))];
+        assert!(send(&path, &requests).unwrap());
+        for request in requests {
+            assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), request);
+        }
+        assert_eq!(Request::parse("close x"), None);
+        assert_eq!(Request::parse("open bad\\q"), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replaces_stale_sockets() {
+        let dir = std::env::temp_dir().join(format!("rakoune-stale-{}", std::process::id()));
+        let path = dir.join("rakoune.sock");
+        private_dir(&path).unwrap();
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let (tx, rx) = mpsc::channel();
+        listen(&path, move |request| tx.send(request).unwrap()).unwrap();
+        assert!(send(&path, &[Request::Open(PathBuf::from("/tmp/a.txt"))]).unwrap());
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), Request::Open(PathBuf::from("/tmp/a.txt")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_in_a_private_directory() {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::PermissionsExt;
+        let not_utf8 = PathBuf::from(std::ffi::OsStr::from_bytes(b"/tmp/caf\xe9.txt"));
+        assert_eq!(Request::parse(Request::Open(not_utf8.clone()).line().unwrap().trim_end()), Some(Request::Open(not_utf8)));
+
+        let dir = std::env::temp_dir().join(format!("rakoune-shared-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        let path = dir.join("rakoune.sock");
+        assert_eq!(listen(&path, |_| {}).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(send(&path, &[]).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+// Open files and how the window is split, saved on exit and restored on the next start. Inside a
+// project with sessions enabled, each project root gets a session of its own, so working on one
+// project doesn't replace the files of another. Elsewhere the global session is used
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::commands::Registry;
+use crate::layout::Rect;
+use crate::project::{self, Project};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Couldn't read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Couldn't write {0}: {1}")]
+    Write(PathBuf, std::io::Error),
+    #[error("{0}: {1}")]
+    Parse(PathBuf, serde_json::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpenFile {
+    pub path: PathBuf,
+    // 0-based
+    pub cursor_line: usize,
+    pub cursor_column: usize,
+    pub top_line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    // Shows the file at this index of Session::files
+    Pane(usize),
+    // Children side by side, or stacked if not vertical, with the fraction of the space each takes
+    Split { vertical: bool, children: Vec<(f32, Layout)> },
+}
+
+impl Layout {
+    // Where each pane goes when the layout fills `area`
+    pub fn rects(&self, area: Rect) -> Vec<(usize, Rect)> {
+        match self {
+            Layout::Pane(idx) => vec![(*idx, area)],
+            Layout::Split { vertical, children } => {
+                let mut rects = Vec::new();
+                let mut offset = 0.;
+                for (size, child) in children {
+                    let rect = match vertical {
+                        true => Rect { x: area.x + offset * area.w, w: size * area.w, ..area },
+                        false => Rect { y: area.y + offset * area.h, h: size * area.h, ..area },
+                    };
+                    rects.extend(child.rects(rect));
+                    offset += size;
+                }
+                rects
+            }
+        }
+    }
+
+    // Drops panes of files that are gone and splits left with one child. None if nothing's left
+    fn retain(self, kept: &[Option<usize>]) -> Option<Layout> {
+        match self {
+            Layout::Pane(idx) => Some(Layout::Pane(kept.get(idx).copied().flatten()?)),
+            Layout::Split { vertical, children } => {
+                let mut children: Vec<(f32, Layout)> = children.into_iter().filter_map(|(size, child)| Some((size, child.retain(kept)?))).collect();
+                let total: f32 = children.iter().map(|(size, _)| size).sum();
+                match children.len() {
+                    0 => None,
+                    1 => children.pop().map(|(_, child)| child),
+                    _ => Some(Layout::Split { vertical, children: children.into_iter().map(|(size, child)| (size / total, child)).collect() }),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    // The directory a project session is for, so a hash collision can't restore another's files
+    pub root: Option<PathBuf>,
+    pub files: Vec<OpenFile>,
+    pub layout: Option<Layout>,
+    // Index into files
+    pub focused: usize,
+}
+
+impl Session {
+    // None if there is no session saved there yet
+    pub fn load(path: &Path) -> Result<Option<Session>, Error> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(Error::Read(path.to_owned(), err)),
+        };
+        serde_json::from_str(&text).map(Some).map_err(|err| Error::Parse(path.to_owned(), err))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| Error::Write(path.to_owned(), err))?;
+        }
+        let text = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(path, text + "\n").map_err(|err| Error::Write(path.to_owned(), err))
+    }
+
+    // Without the files that were deleted or moved since it was saved
+    pub fn restorable(self) -> Session {
+        let mut kept = Vec::new();
+        let mut files = Vec::new();
+        for file in self.files {
+            match file.path.exists() {
+                true => {
+                    kept.push(Some(files.len()));
+                    files.push(file);
+                }
+                false => kept.push(None),
+            }
+        }
+        let focused = kept.get(self.focused).copied().flatten().unwrap_or(0);
+        Session { root: self.root, layout: self.layout.and_then(|layout| layout.retain(&kept)), files, focused }
+    }
+}
+
+pub fn default_dir() -> Option<PathBuf> {
+    let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state_dir.join("rakoune").join("sessions"))
+}
+
+// Where the session for `project` is kept. Project sessions are named by a hash of their root
+pub fn session_path(sessions_dir: &Path, project: Option<&Project>, user_setting: bool) -> PathBuf {
+    match project.filter(|project| project.auto_session(user_setting)) {
+        Some(project) => sessions_dir.join(format!("{:016x}.json", project::hash(project.root.as_os_str().as_encoded_bytes()))),
+        None => sessions_dir.join("global.json"),
+    }
+}
+
+// What sessions need from the editor
+pub trait SessionHost {
+    // The project rakoune was started in
+    fn project(&self) -> Option<&Project>;
+    // The user's setting for per-project sessions
+    fn project_sessions(&self) -> bool;
+    // Where sessions are kept, default_dir unless changed
+    fn sessions_dir(&self) -> Option<PathBuf>;
+    fn current_session(&self) -> Session;
+    fn restore_session(&mut self, session: Session);
+}
+
+// Where the session is kept, and the project root it's for unless it's the global one
+fn path_for(host: &impl SessionHost) -> Result<(PathBuf, Option<PathBuf>), String> {
+    let dir = host.sessions_dir().ok_or("No state directory to keep sessions in")?;
+    let root = host.project().filter(|project| project.auto_session(host.project_sessions())).map(|project| project.root.clone());
+    Ok((session_path(&dir, host.project(), host.project_sessions()), root))
+}
+
+// To be called on start without files to open
+pub fn restore(host: &mut impl SessionHost) -> Result<(), String> {
+    let (path, root) = path_for(host)?;
+    let Some(session) = Session::load(&path).map_err(|e| e.to_string())? else { return Ok(()) };
+    if session.root != root {
+        return Ok(());
+    }
+    host.restore_session(session.restorable());
+    Ok(())
+}
+
+// To be called on exit
+pub fn store(host: &impl SessionHost) -> Result<(), String> {
+    let (path, root) = path_for(host)?;
+    let session = Session { root, ..host.current_session() };
+    session.save(&path).map_err(|e| e.to_string())
+}
+
+fn session_save_command<Ctx: SessionHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    store(ctx)
+}
+
+fn session_load_command<Ctx: SessionHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    restore(ctx)
+}
+
+pub fn register<Ctx: SessionHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("session-save", session_save_command::<Ctx>);
+    registry.add_builtin("session-load", session_load_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::ProjectConfig;
+
+    fn open(path: &Path) -> OpenFile {
+        OpenFile { path: path.to_owned(), cursor_line: 3, cursor_column: 1, top_line: 0 }
+    }
+
+    #[test]
+    fn per_project_sessions() {
+        let dir = std::env::temp_dir().join(format!("rakoune-session-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let project = |session| Project { root: dir.clone(), config: ProjectConfig { session, ..Default::default() }, hash: 0 };
+        let sessions = dir.join("sessions");
+        let own = session_path(&sessions, Some(&project(None)), true);
+        assert_ne!(own, sessions.join("global.json"));
+        assert_eq!(session_path(&sessions, Some(&project(Some(false))), true), sessions.join("global.json"));
+        assert_eq!(session_path(&sessions, Some(&project(Some(true))), false), own);
+        assert_eq!(session_path(&sessions, None, true), sessions.join("global.json"));
+
+        let (kept, gone) = (dir.join("kept.rs"), dir.join("gone.rs"));
+        std::fs::write(&kept, "").unwrap();
+        let session = Session {
+            root: Some(dir.clone()),
+            files: vec![open(&gone), open(&kept)],
+            layout: Some(Layout::Split { vertical: true, children: vec![(0.25, Layout::Pane(0)), (0.75, Layout::Pane(1))] }),
+            focused: 1,
+        };
+        assert_eq!(Session::load(&own).unwrap(), None);
+        session.save(&own).unwrap();
+        let loaded = Session::load(&own).unwrap().unwrap();
+        assert_eq!(loaded, session);
+
+        let restored = loaded.restorable();
+        assert_eq!(restored.files, vec![open(&kept)]);
+        assert_eq!(restored.layout, Some(Layout::Pane(0)));
+        assert_eq!(restored.focused, 0);
+
+        std::fs::write(&own, "{").unwrap();
+        assert!(matches!(Session::load(&own), Err(Error::Parse(..))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+// Antialiased UI shapes drawn with signed distance functions: rounded rectangles, circles, and
+// lines with round ends and joins. For popup borders, scrollbar thumbs, icons and color swatches,
+// which axis-aligned quads can't draw nicely
+
+use crate::layout::Rect;
+
+pub const SHADER: &str = include_str!("shapes.wgsl");
+
+// Pixels of antialiased edge drawn outside of each shape
+const EDGE: f32 = 1.;
+
+const ROUNDED_RECT: u32 = 0;
+const LINE: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shape {
+    // A `border` of 0 fills the rectangle, anything else draws only a border that wide inside it
+    RoundedRect { rect: Rect, radius: f32, border: f32, color: [f32; 4] },
+    Circle { center: (f32, f32), radius: f32, border: f32, color: [f32; 4] },
+    // Round ends, so lines that share end points join smoothly
+    Line { from: (f32, f32), to: (f32, f32), width: f32, color: [f32; 4] },
+}
+
+// Lines through `points`, joined at each of them
+pub fn polyline(points: &[(f32, f32)], width: f32, color: [f32; 4]) -> Vec<Shape> {
+    points.windows(2).map(|pair| Shape::Line { from: pair[0], to: pair[1], width, color }).collect()
+}
+
+impl Shape {
+    // Signed distance from (x, y) to the edge of the shape, negative inside. Matches the shader,
+    // so it can be used for hit testing
+    pub fn distance(&self, x: f32, y: f32) -> f32 {
+        let instance = self.instance();
+        let [gx, gy, gz, gw] = instance.geometry;
+        let d = match instance.kind {
+            LINE => {
+                let (pa, ba) = ((x - gx, y - gy), (gz - gx, gw - gy));
+                let h = ((pa.0 * ba.0 + pa.1 * ba.1) / (ba.0 * ba.0 + ba.1 * ba.1).max(1e-6)).clamp(0., 1.);
+                (pa.0 - ba.0 * h).hypot(pa.1 - ba.1 * h) - instance.radius
+            }
+            _ => {
+                let (half_w, half_h) = (gz / 2., gw / 2.);
+                let q = ((x - gx - half_w).abs() - half_w + instance.radius, (y - gy - half_h).abs() - half_h + instance.radius);
+                q.0.max(0.).hypot(q.1.max(0.)) + q.0.max(q.1).min(0.) - instance.radius
+            }
+        };
+        if instance.border > 0. {
+            (d + instance.border / 2.).abs() - instance.border / 2.
+        } else {
+            d
+        }
+    }
+
+    fn instance(&self) -> ShapeInstance {
+        match *self {
+            Shape::RoundedRect { rect, radius, border, color } => ShapeInstance {
+                bounds: [rect.x - EDGE, rect.y - EDGE, rect.w + 2. * EDGE, rect.h + 2. * EDGE],
+                geometry: [rect.x, rect.y, rect.w, rect.h],
+                color,
+                kind: ROUNDED_RECT,
+                // Bigger radii would make the corners overlap
+                radius: radius.min(rect.w / 2.).min(rect.h / 2.).max(0.),
+                border,
+            },
+            Shape::Circle { center, radius, border, color } => {
+                let rect = Rect { x: center.0 - radius, y: center.1 - radius, w: 2. * radius, h: 2. * radius };
+                Shape::RoundedRect { rect, radius, border, color }.instance()
+            }
+            Shape::Line { from, to, width, color } => {
+                let reach = width / 2. + EDGE;
+                let (x, y) = (from.0.min(to.0) - reach, from.1.min(to.1) - reach);
+                ShapeInstance {
+                    bounds: [x, y, (from.0 - to.0).abs() + 2. * reach, (from.1 - to.1).abs() + 2. * reach],
+                    geometry: [from.0, from.1, to.0, to.1],
+                    color,
+                    kind: LINE,
+                    radius: width / 2.,
+                    border: 0.,
+                }
+            }
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct Globals {
+    target_size: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShapeInstance {
+    bounds: [f32; 4],
+    geometry: [f32; 4],
+    color: [f32; 4],
+    kind: u32,
+    radius: f32,
+    border: f32,
+}
+
+impl ShapeInstance {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 6] =
+        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4, 3 => Uint32, 4 => Float32, 5 => Float32];
+}
+
+pub struct ShapeRenderer {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    globals: wgpu::Buffer,
+    queued: Vec<ShapeInstance>,
+    instance_buffer: Option<wgpu::Buffer>,
+}
+
+impl ShapeRenderer {
+    pub fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat) -> ShapeRenderer {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shape shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("shape bind group layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("shape pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("shape pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<ShapeInstance>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &ShapeInstance::ATTRIBUTES,
+                }],
+            },
+            primitive: wgpu::PrimitiveState { topology: wgpu::PrimitiveTopology::TriangleStrip, ..Default::default() },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let globals = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shape globals"),
+            size: std::mem::size_of::<Globals>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("shape bind group"),
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry { binding: 0, resource: globals.as_entire_binding() }],
+        });
+        ShapeRenderer { pipeline, bind_group, globals, queued: Vec::new(), instance_buffer: None }
+    }
+
+    // Shapes are drawn in the order they are queued
+    pub fn queue(&mut self, shape: &Shape) {
+        self.queued.push(shape.instance());
+    }
+
+    // Draws everything queued onto `view`, which is `target_size` pixels large, and clears the queue
+    pub fn render(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, target_size: (u32, u32)) {
+        if self.queued.is_empty() {
+            return;
+        }
+        let bytes: &[u8] = bytemuck::cast_slice(&self.queued);
+        let fits = self.instance_buffer.as_ref().is_some_and(|buffer| buffer.size() >= bytes.len() as u64);
+        if !fits {
+            self.instance_buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("shape instances"),
+                size: (bytes.len() as u64).next_power_of_two(),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        let instance_buffer = self.instance_buffer.as_ref().unwrap();
+        queue.write_buffer(instance_buffer, 0, bytes);
+        queue.write_buffer(&self.globals, 0, bytemuck::bytes_of(&Globals { target_size: [target_size.0 as f32, target_size.1 as f32] }));
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("shape pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..4, 0..self.queued.len() as u32);
+        drop(pass);
+        self.queued.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::validate_shader;
+
+    #[test]
+    fn shader_is_valid() {
+        validate_shader(SHADER);
+        assert_eq!(std::mem::size_of::<ShapeInstance>(), 60);
+    }
+
+    #[test]
+    fn distances() {
+        let color = [1.; 4];
+        let rect = Shape::RoundedRect { rect: Rect { x: 0., y: 0., w: 20., h: 10. }, radius: 4., border: 0., color };
+        assert!(rect.distance(10., 5.) < 0.);
+        assert!(rect.distance(0.5, 0.5) > 0., "rounded off corner");
+        assert!((rect.distance(25., 5.) - 5.).abs() < 1e-4);
+
+        let ring = Shape::Circle { center: (10., 10.), radius: 5., border: 1., color };
+        assert!(ring.distance(10., 10.) > 0., "hollow middle");
+        assert!(ring.distance(14.5, 10.) < 0.);
+
+        let lines = polyline(&[(0., 0.), (10., 0.), (10., 10.)], 2., color);
+        assert_eq!(lines.len(), 2);
+        // The joint is covered by both round ends
+        assert!(lines.iter().all(|line| line.distance(10.5, -0.5) < 0.));
+        assert!(lines[0].distance(5., 2.) > 0.);
+    }
+
+    #[test]
+    fn bounds_include_the_edge() {
+        let line = Shape::Line { from: (10., 10.), to: (0., 20.), width: 4., color: [1.; 4] };
+        assert_eq!(line.instance().bounds, [-3., 7., 16., 16.]);
+    }
+}
@@ -0,0 +1,79 @@
+struct Globals {
+    // Size of the render target in pixels
+    target_size: vec2<f32>,
+}
+
+@group(0) @binding(0) var<uniform> globals: Globals;
+
+const ROUNDED_RECT: u32 = 0u;
+const LINE: u32 = 1u;
+
+struct Instance {
+    // Quad covering the shape and its antialiased edge, in pixels
+    @location(0) bounds: vec4<f32>,
+    // The rectangle as x, y, w, h for rounded rectangles, the end points for lines
+    @location(1) geometry: vec4<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) kind: u32,
+    // Corner radius for rectangles, half the width for lines
+    @location(4) radius: f32,
+    // Width of the border to draw, or 0 to fill
+    @location(5) border: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) pixel: vec2<f32>,
+    @location(1) geometry: vec4<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) @interpolate(flat) kind: u32,
+    @location(4) radius: f32,
+    @location(5) border: f32,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex: u32, instance: Instance) -> VertexOutput {
+    // Triangle strip over the corners (0, 0), (1, 0), (0, 1), (1, 1)
+    let corner = vec2<f32>(f32(vertex & 1u), f32(vertex >> 1u));
+    let pixel = instance.bounds.xy + corner * instance.bounds.zw;
+    var out: VertexOutput;
+    out.position = vec4<f32>(pixel / globals.target_size * vec2<f32>(2., -2.) + vec2<f32>(-1., 1.), 0., 1.);
+    out.pixel = pixel;
+    out.geometry = instance.geometry;
+    out.color = instance.color;
+    out.kind = instance.kind;
+    out.radius = instance.radius;
+    out.border = instance.border;
+    return out;
+}
+
+// Signed distance from `p` to a rectangle with corners rounded by `radius`, negative inside
+fn rounded_rect(p: vec2<f32>, rect: vec4<f32>, radius: f32) -> f32 {
+    let half = rect.zw / 2.;
+    let q = abs(p - rect.xy - half) - half + radius;
+    return length(max(q, vec2<f32>(0.))) + min(max(q.x, q.y), 0.) - radius;
+}
+
+// Signed distance from `p` to a line from a to b with round ends, `radius` wide on each side
+fn line(p: vec2<f32>, a: vec2<f32>, b: vec2<f32>, radius: f32) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h = clamp(dot(pa, ba) / max(dot(ba, ba), 1e-6), 0., 1.);
+    return length(pa - ba * h) - radius;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Distances are measured from pixel centers
+    var d: f32;
+    if in.kind == LINE {
+        d = line(in.pixel, in.geometry.xy, in.geometry.zw, in.radius);
+    } else {
+        d = rounded_rect(in.pixel, in.geometry, in.radius);
+    }
+    if in.border > 0. {
+        d = abs(d + in.border / 2.) - in.border / 2.;
+    }
+    let coverage = clamp(0.5 - d, 0., 1.);
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
@@ -0,0 +1,64 @@
+use std::time::{Duration, Instant};
+
+use crate::layout::Rect;
+
+const FADE_FOR: Duration = Duration::from_millis(1000);
+// Never more than this much of the window's width or height
+const MAX_FRACTION: f32 = 0.5;
+
+// The logo over the text while rakoune starts, fading out over the first second. After that its
+// pass is skipped altogether. The welcome screen still draws the same image, without fading
+#[derive(Debug)]
+pub struct Splash {
+    shown_at: Instant,
+    // Gone at once instead of fading out, for reduced motion
+    pub no_fade: bool,
+}
+
+impl Splash {
+    pub fn new(now: Instant) -> Splash {
+        Splash { shown_at: now, no_fade: false }
+    }
+
+    // None once it has faded out, and there is nothing to draw
+    pub fn opacity(&self, now: Instant) -> Option<f32> {
+        let shown_for = now.saturating_duration_since(self.shown_at);
+        if self.no_fade || shown_for >= FADE_FOR {
+            return None;
+        }
+        let t = shown_for.as_secs_f32() / FADE_FOR.as_secs_f32();
+        // Eases out, so the logo is gone before it gets in the way of reading
+        Some((1. - t) * (1. - t))
+    }
+
+    pub fn is_fading(&self, now: Instant) -> bool {
+        self.opacity(now).is_some()
+    }
+
+    // Centered in the window, scaled down to fit
+    pub fn rect(window_size: (f32, f32), image_size: (u32, u32)) -> Rect {
+        let (w, h) = (image_size.0 as f32, image_size.1 as f32);
+        let scale = (window_size.0 * MAX_FRACTION / w).min(window_size.1 * MAX_FRACTION / h).min(1.);
+        let (w, h) = (w * scale, h * scale);
+        Rect { x: ((window_size.0 - w) / 2.).round(), y: ((window_size.1 - h) / 2.).round(), w, h }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fades_out_then_stops() {
+        let start = Instant::now();
+        let mut splash = Splash::new(start);
+        assert_eq!(splash.opacity(start), Some(1.));
+        assert_eq!(splash.opacity(start + FADE_FOR / 2), Some(0.25));
+        assert!(!splash.is_fading(start + FADE_FOR));
+        splash.no_fade = true;
+        assert_eq!(splash.opacity(start), None);
+
+        assert_eq!(Splash::rect((800., 600.), (500, 500)), Rect { x: 250., y: 150., w: 300., h: 300. });
+        assert_eq!(Splash::rect((2000., 2000.), (500, 500)), Rect { x: 750., y: 750., w: 500., h: 500. });
+    }
+}
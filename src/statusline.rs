@@ -0,0 +1,355 @@
+// The status line, made of segments on the left, in the center and on the right. Which segments go
+// where is a list in the config, and its background follows the mode:
+//
+//   [status_line]
+//   left = ["mode", "file"]
+//   center = ["progress"]
+//   right = ["search", "lsp", "branch", "encoding", "position", "clock"]
+//
+//   [status_line.mode_colors]
+//   insert = [0.2, 0.4, 0.2, 1.0]
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::notifications::{Message, Severity};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Unknown status line segment {0}")]
+    UnknownSegment(String),
+    #[error("Unknown mode {0} in status_line.mode_colors")]
+    UnknownMode(String),
+}
+
+// Put between segments on the same side
+const SEPARATOR: &str = "  ";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    #[default]
+    Normal,
+    Insert,
+    Select,
+    Command,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Normal => "normal",
+            Mode::Insert => "insert",
+            Mode::Select => "select",
+            Mode::Command => "command",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Mode> {
+        [Mode::Normal, Mode::Insert, Mode::Select, Mode::Command].into_iter().find(|mode| mode.name() == name)
+    }
+
+    fn default_color(self) -> [f32; 4] {
+        match self {
+            Mode::Normal => [0.2, 0.2, 0.25, 1.],
+            Mode::Insert => [0.15, 0.3, 0.2, 1.],
+            Mode::Select => [0.35, 0.25, 0.1, 1.],
+            Mode::Command => [0.25, 0.15, 0.3, 1.],
+        }
+    }
+}
+
+// What segments show, gathered from the editor whenever the status line is drawn
+#[derive(Debug, Default, Clone)]
+pub struct Context {
+    pub mode: Mode,
+    pub path: Option<PathBuf>,
+    pub modified: bool,
+    // 0-based
+    pub line: usize,
+    pub column: usize,
+    pub encoding: String,
+    pub crlf: bool,
+    pub branch: Option<String>,
+    // Language server name and state, like `rust-analyzer: indexing`
+    pub lsp: Option<String>,
+    // From ProgressTracker::status
+    pub progress: Option<String>,
+    // Local time as hours and minutes
+    pub time: Option<(u8, u8)>,
+    // From Notifications::latest
+    pub message: Option<(Severity, String)>,
+    // Match under or before the cursor and how many there are, from search::match_count
+    pub search: Option<(usize, usize)>,
+}
+
+impl Context {
+    pub fn with_message(mut self, message: Option<&Message>) -> Context {
+        self.message = message.map(|message| (message.severity, message.text.clone()));
+        self
+    }
+}
+
+pub trait Segment {
+    // None to leave the segment out, along with its separator
+    fn text(&self, context: &Context) -> Option<String>;
+}
+
+struct ModeSegment;
+struct FileSegment;
+struct PositionSegment;
+struct EncodingSegment;
+struct BranchSegment;
+struct LspSegment;
+struct ProgressSegment;
+struct ClockSegment;
+struct SearchSegment;
+
+impl Segment for ModeSegment {
+    fn text(&self, context: &Context) -> Option<String> {
+        Some(context.mode.name().to_uppercase())
+    }
+}
+
+impl Segment for FileSegment {
+    fn text(&self, context: &Context) -> Option<String> {
+        let name = context.path.as_ref().map_or("[scratch]".to_string(), |path| path.display().to_string());
+        Some(if context.modified { format!("{name} [+]") } else { name })
+    }
+}
+
+impl Segment for PositionSegment {
+    fn text(&self, context: &Context) -> Option<String> {
+        Some(format!("{}:{}", context.line + 1, context.column + 1))
+    }
+}
+
+impl Segment for EncodingSegment {
+    fn text(&self, context: &Context) -> Option<String> {
+        let encoding = if context.encoding.is_empty() { "utf-8" } else { &context.encoding };
+        Some(format!("{encoding} {}", if context.crlf { "crlf" } else { "lf" }))
+    }
+}
+
+impl Segment for BranchSegment {
+    fn text(&self, context: &Context) -> Option<String> {
+        context.branch.as_ref().map(|branch| format!("⎇ {branch}"))
+    }
+}
+
+impl Segment for LspSegment {
+    fn text(&self, context: &Context) -> Option<String> {
+        context.lsp.clone()
+    }
+}
+
+impl Segment for ProgressSegment {
+    fn text(&self, context: &Context) -> Option<String> {
+        context.progress.clone()
+    }
+}
+
+impl Segment for ClockSegment {
+    fn text(&self, context: &Context) -> Option<String> {
+        context.time.map(|(hours, minutes)| format!("{hours:02}:{minutes:02}"))
+    }
+}
+
+impl Segment for SearchSegment {
+    fn text(&self, context: &Context) -> Option<String> {
+        context.search.map(|(index, total)| format!("[{index}/{total}]"))
+    }
+}
+
+// Hours and minutes of `now` in the local time zone
+pub fn local_time(now: SystemTime) -> (u8, u8) {
+    let seconds = now.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() as i64) + utc_offset();
+    let minutes = seconds.div_euclid(60).rem_euclid(24 * 60);
+    ((minutes / 60) as u8, (minutes % 60) as u8)
+}
+
+// Until the clock shows the next minute
+pub fn until_next_minute(now: SystemTime) -> Duration {
+    let since = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Duration::from_secs(60 - since.as_secs() % 60) - Duration::from_nanos(since.subsec_nanos().into())
+}
+
+// Seconds east of UTC as `date` says, asked once. UTC where there's no `date`
+fn utc_offset() -> i64 {
+    static OFFSET: OnceLock<i64> = OnceLock::new();
+    *OFFSET.get_or_init(|| {
+        let output = Command::new("date").arg("+%z").output().ok().filter(|output| output.status.success());
+        output.and_then(|output| parse_offset(String::from_utf8_lossy(&output.stdout).trim())).unwrap_or(0)
+    })
+}
+
+// Like +0100 or -0930
+fn parse_offset(offset: &str) -> Option<i64> {
+    let (sign, digits) = match offset.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes): (i64, i64) = (digits[..2].parse().ok()?, digits[2..].parse().ok()?);
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+pub fn segment(name: &str) -> Result<Box<dyn Segment>, Error> {
+    Ok(match name {
+        "mode" => Box::new(ModeSegment),
+        "file" => Box::new(FileSegment),
+        "position" => Box::new(PositionSegment),
+        "encoding" => Box::new(EncodingSegment),
+        "branch" => Box::new(BranchSegment),
+        "lsp" => Box::new(LspSegment),
+        "progress" => Box::new(ProgressSegment),
+        "clock" => Box::new(ClockSegment),
+        "search" => Box::new(SearchSegment),
+        _ => return Err(Error::UnknownSegment(name.to_string())),
+    })
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusLineConfig {
+    pub left: Vec<String>,
+    pub center: Vec<String>,
+    pub right: Vec<String>,
+    // Background by mode name, over the built in ones
+    pub mode_colors: HashMap<String, [f32; 4]>,
+}
+
+impl Default for StatusLineConfig {
+    fn default() -> Self {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        StatusLineConfig {
+            left: names(&["mode", "file"]),
+            center: names(&["progress"]),
+            right: names(&["search", "lsp", "branch", "encoding", "position"]),
+            mode_colors: HashMap::new(),
+        }
+    }
+}
+
+// The text of each side, and the background to draw it on
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendered {
+    pub left: String,
+    pub center: String,
+    pub right: String,
+    pub background: [f32; 4],
+    // Shown instead of the center for as long as its toast, in the color of its severity
+    pub message: Option<(String, [f32; 4])>,
+}
+
+impl Rendered {
+    // All three in one line `width` characters wide. When they don't fit, the center goes first,
+    // then the left side is cut short
+    pub fn line(&self, width: usize) -> String {
+        let len = |s: &str| s.chars().count();
+        let right: String = self.right.chars().take(width).collect();
+        let room = width - len(&right);
+        let mut left: String = self.left.chars().take(room.saturating_sub(1)).collect();
+        if len(&left) < len(&self.left) && !left.is_empty() {
+            left.pop();
+            left.push('…');
+        }
+        let mut line = left;
+        let center = self.message.as_ref().map_or(self.center.as_str(), |(message, _)| message.as_str());
+        let center_start = (width.saturating_sub(len(center))) / 2;
+        if !center.is_empty() && center_start > len(&line) && center_start + len(center) < width - len(&right) {
+            line.extend(std::iter::repeat_n(' ', center_start - len(&line)));
+            line.push_str(center);
+        }
+        line.extend(std::iter::repeat_n(' ', width - len(&right) - len(&line)));
+        line + &right
+    }
+}
+
+pub struct StatusLine {
+    left: Vec<Box<dyn Segment>>,
+    center: Vec<Box<dyn Segment>>,
+    right: Vec<Box<dyn Segment>>,
+    mode_colors: HashMap<Mode, [f32; 4]>,
+    // Whether there's a clock to keep up to date
+    pub clock: bool,
+}
+
+impl StatusLine {
+    pub fn new(config: &StatusLineConfig) -> Result<StatusLine, Error> {
+        let segments = |names: &[String]| names.iter().map(|name| segment(name)).collect::<Result<Vec<_>, _>>();
+        let mut mode_colors = HashMap::new();
+        for (name, color) in &config.mode_colors {
+            mode_colors.insert(Mode::parse(name).ok_or_else(|| Error::UnknownMode(name.clone()))?, *color);
+        }
+        let clock = [&config.left, &config.center, &config.right].iter().any(|names| names.iter().any(|name| name == "clock"));
+        Ok(StatusLine { left: segments(&config.left)?, center: segments(&config.center)?, right: segments(&config.right)?, mode_colors, clock })
+    }
+
+    pub fn render(&self, context: &Context) -> Rendered {
+        let side = |segments: &[Box<dyn Segment>]| segments.iter().filter_map(|segment| segment.text(context)).collect::<Vec<_>>().join(SEPARATOR);
+        Rendered {
+            left: side(&self.left),
+            center: side(&self.center),
+            right: side(&self.right),
+            background: self.mode_colors.get(&context.mode).copied().unwrap_or(context.mode.default_color()),
+            // Only the first line of long errors
+            message: context.message.as_ref().map(|(severity, text)| (text.lines().next().unwrap_or_default().to_string(), severity.color())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_from_config() {
+        let config: StatusLineConfig = toml::from_str("left = [\"mode\", \"file\"]\ncenter = [\"progress\"]\nright = [\"branch\", \"position\"]\n[mode_colors]\ninsert = [1.0, 0.0, 0.0, 1.0]\n").unwrap();
+        let status_line = StatusLine::new(&config).unwrap();
+        let mut context = Context { path: Some(PathBuf::from("src/main.rs")), modified: true, line: 9, column: 0, ..Default::default() };
+        let rendered = status_line.render(&context);
+        assert_eq!(rendered.left, "NORMAL  src/main.rs [+]");
+        // Without a branch its separator goes too
+        assert_eq!(rendered.right, "10:1");
+        assert_eq!(rendered.line(30), "NORMAL  src/main.rs [+]   10:1");
+        assert_eq!(rendered.line(12), "NORMAL… 10:1");
+
+        context.mode = Mode::Insert;
+        context.progress = Some("Indexing".to_string());
+        let rendered = status_line.render(&context);
+        assert_eq!(rendered.background, [1., 0., 0., 1.]);
+        assert_eq!(rendered.line(60), format!("{:<26}{:<30}{}", "INSERT  src/main.rs [+]", "Indexing", "10:1"));
+
+        context.message = Some((Severity::Error, "Not saved\nPermission denied".to_string()));
+        let rendered = status_line.render(&context);
+        assert_eq!(rendered.message, Some(("Not saved".to_string(), Severity::Error.color())));
+        assert_eq!(rendered.line(60), format!("{:<25}{:<31}{}", "INSERT  src/main.rs [+]", "Not saved", "10:1"));
+
+        context.search = Some((2, 17));
+        assert_eq!(StatusLine::new(&StatusLineConfig::default()).unwrap().render(&context).right, "[2/17]  utf-8 lf  10:1");
+
+        assert!(!status_line.clock);
+        let unknown: StatusLineConfig = toml::from_str("left = [\"weather\"]").unwrap();
+        assert!(matches!(StatusLine::new(&unknown), Err(Error::UnknownSegment(_))));
+    }
+
+    #[test]
+    fn clock() {
+        assert_eq!(parse_offset("+0130"), Some(5400));
+        assert_eq!(parse_offset("-0900"), Some(-9 * 3600));
+        assert_eq!(parse_offset("UTC"), None);
+        let at = UNIX_EPOCH + Duration::from_millis((13 * 3600 + 5 * 60 + 20) * 1000 + 500);
+        assert_eq!(until_next_minute(at), Duration::from_millis(39500));
+        let context = Context { time: Some((9, 5)), ..Default::default() };
+        assert_eq!(ClockSegment.text(&context), Some("09:05".to_string()));
+    }
+}
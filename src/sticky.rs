@@ -0,0 +1,110 @@
+// Sticky context: the first lines of the scopes around the top of the viewport, like the function
+// being read, pinned over the top rows of the buffer while they're scrolled off. Scopes are curly
+// brackets from the bracket tree, or blocks of indentation for languages without them
+//
+// Drawn by queueing header_text with the text renderer at the top of the buffer area, after the
+// buffer and over backdrop
+
+use crate::bracket_tree::{BracketTree, Node, NodeKind};
+use crate::folding::{indent_folds, Fold};
+use crate::layout::Rect;
+use crate::selection::line_starts;
+use crate::shapes::Shape;
+
+// More than this and the header takes too much of the screen, so only the innermost are shown
+pub const MAX_ROWS: usize = 4;
+const BACKGROUND: [f32; 4] = [0.15, 0.15, 0.17, 0.95];
+
+fn bracket_scopes(node: &Node, starts: &[usize], depth: usize, scopes: &mut Vec<Fold>) {
+    let line_of = |byte: usize| starts.partition_point(|&start| start <= byte) - 1;
+    for child in &node.children {
+        let (first, last) = (line_of(child.range.start), line_of(child.range.end.saturating_sub(1)));
+        let is_scope = child.kind == NodeKind::Bracketed('{') && last > first;
+        if is_scope {
+            scopes.push(Fold { lines: first..last + 1, depth });
+        }
+        bracket_scopes(child, starts, depth + is_scope as usize, scopes);
+    }
+}
+
+// Scopes of the text, sorted by their first line
+pub fn scopes(text: &str, tree: Option<&BracketTree>) -> Vec<Fold> {
+    let mut scopes = Vec::new();
+    if let Some(tree) = tree {
+        bracket_scopes(&tree.root, &line_starts(text), 0, &mut scopes);
+    }
+    if scopes.is_empty() {
+        return indent_folds(text);
+    }
+    scopes.sort_by_key(|scope| scope.lines.start);
+    scopes
+}
+
+// First lines of the scopes to pin when `top` is the first line in view, outermost first. Each
+// pinned row hides a line of the buffer under it, so a scope is pinned only while some of it shows
+// below its row
+pub fn context_lines(scopes: &[Fold], top: usize, max_rows: usize) -> Vec<usize> {
+    let mut lines = Vec::new();
+    for scope in scopes {
+        let row = top + lines.len();
+        if scope.lines.start < row && row + 1 < scope.lines.end {
+            lines.push(scope.lines.start);
+        }
+    }
+    lines.drain(..lines.len().saturating_sub(max_rows));
+    lines
+}
+
+// The pinned lines, one per row, without the line breaks
+pub fn header_text(text: &str, lines: &[usize]) -> String {
+    let all: Vec<&str> = text.split('\n').collect();
+    lines.iter().filter_map(|&line| all.get(line)).copied().collect::<Vec<_>>().join("\n")
+}
+
+// Covers the buffer under the header, which starts `top` down the window, so the pinned lines
+// don't mix with the text there
+pub fn backdrop(lines: &[usize], top: f32, width: f32, line_height: f32) -> Option<Shape> {
+    if lines.is_empty() {
+        return None;
+    }
+    let rect = Rect { x: 0., y: top, w: width, h: lines.len() as f32 * line_height };
+    Some(Shape::RoundedRect { rect, radius: 0., border: 0., color: BACKGROUND })
+}
+
+// The line to jump to when clicking at `y` from the top of the buffer area, if it's in the header
+pub fn line_at(lines: &[usize], y: f32, line_height: f32) -> Option<usize> {
+    if y < 0. {
+        return None;
+    }
+    lines.get((y / line_height) as usize).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bracket_tree::Grammar;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn pins_enclosing_scopes() {
+        let text = "def outer():\n    x = 1\n    def inner():\n        y = 2\n        z = 3\n        w = 4\n    return x\n";
+        let scopes = scopes(text, None);
+        assert_eq!(context_lines(&scopes, 0, MAX_ROWS), Vec::<usize>::new());
+        assert_eq!(context_lines(&scopes, 1, MAX_ROWS), vec![0]);
+        assert_eq!(context_lines(&scopes, 3, MAX_ROWS), vec![0, 2]);
+        assert_eq!(context_lines(&scopes, 3, 1), vec![2]);
+        // Pinning inner would hide its last line, so there'd be nothing of it left to see
+        assert_eq!(context_lines(&scopes, 4, MAX_ROWS), vec![0]);
+        assert_eq!(context_lines(&scopes, 6, MAX_ROWS), Vec::<usize>::new());
+        // Brackets don't need the indentation to be right
+        let braces = "fn main() {\nif x {\na();\nb();\n}\n}\n";
+        let grammar = Grammar::parse(Path::new("rust.toml"), "name = \"rust\"\nextensions = [\"rs\"]\nbrackets = [\"{}\"]\n").unwrap();
+        let tree = BracketTree::new(Arc::new(grammar), braces);
+        assert_eq!(context_lines(&super::scopes(braces, Some(&tree)), 2, MAX_ROWS), vec![0, 1]);
+        assert_eq!(header_text(text, &[0, 2]), "def outer():\n    def inner():");
+        assert_eq!(line_at(&[0, 2], 25., 20.), Some(2));
+        assert_eq!(line_at(&[0, 2], 45., 20.), None);
+        assert!(backdrop(&[], 0., 100., 20.).is_none());
+    }
+}
@@ -0,0 +1,536 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use thiserror::Error;
+
+use crate::commands::Registry;
+use crate::layout::VirtualText;
+use crate::progress::{Progress, ProgressHost};
+use crate::selection::line_starts;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Expected :s/pattern/replacement/[flags]")]
+    Syntax,
+    #[error("Unknown flag {0:?}")]
+    UnknownFlag(char),
+    #[error("Invalid pattern: {0}")]
+    BadPattern(#[from] regex::Error),
+}
+
+#[derive(Debug)]
+pub struct Substitution {
+    pub regex: regex::Regex,
+    // In the regex crate's syntax, with $1 or ${name} for capture groups
+    pub replacement: String,
+    // Replace every match on a line, not just the first one
+    pub global: bool,
+    // Ask before replacing each match
+    pub confirm: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+// Splits on unescaped `/`, turning \/ into /
+fn split_slashes(s: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('/') => parts.last_mut().unwrap().push('/'),
+                Some(other) => {
+                    parts.last_mut().unwrap().push('\\');
+                    parts.last_mut().unwrap().push(other);
+                }
+                None => parts.last_mut().unwrap().push('\\'),
+            },
+            '/' => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+// Vim writes capture groups as \1 and the whole match as &, the regex crate as ${1} and ${0}
+fn convert_replacement(vim: &str) -> String {
+    let mut out = String::new();
+    let mut chars = vim.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => out.push_str(&format!("${{{d}}}")),
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(other) => out.push(other),
+                None => out.push('\\'),
+            },
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl Substitution {
+    // Parses the part after the ':', like `s/(\w+) (\w+)/\2 \1/gc`
+    pub fn parse(command: &str) -> Result<Substitution, Error> {
+        let rest = command.strip_prefix('s').ok_or(Error::Syntax)?;
+        let rest = rest.strip_prefix('/').ok_or(Error::Syntax)?;
+        let parts = split_slashes(rest);
+        let (pattern, replacement, flags) = match parts.as_slice() {
+            [pattern, replacement] => (pattern, replacement, ""),
+            [pattern, replacement, flags] => (pattern, replacement, flags.as_str()),
+            _ => return Err(Error::Syntax),
+        };
+
+        let mut builder = regex::RegexBuilder::new(pattern);
+        builder.multi_line(true);
+        let (mut global, mut confirm) = (false, false);
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'c' => confirm = true,
+                'i' => { builder.case_insensitive(true); }
+                other => return Err(Error::UnknownFlag(other)),
+            }
+        }
+        Ok(Substitution {
+            regex: builder.build()?,
+            replacement: convert_replacement(replacement),
+            global,
+            confirm,
+        })
+    }
+
+    // Matches within `ranges` (the selections, or the whole buffer), with what each would be replaced by
+    pub fn find(&self, text: &str, ranges: &[Range<usize>]) -> Vec<Match> {
+        let mut matches = Vec::new();
+        for range in ranges {
+            let mut last_line_start = None;
+            for caps in self.regex.captures_iter(&text[range.clone()]) {
+                let whole = caps.get(0).unwrap();
+                let start = range.start + whole.start();
+                let line_start = text[..start].rfind('\n').map_or(0, |at| at + 1);
+                if !self.global && last_line_start == Some(line_start) {
+                    continue;
+                }
+                last_line_start = Some(line_start);
+
+                let mut replacement = String::new();
+                caps.expand(&self.replacement, &mut replacement);
+                matches.push(Match { range: start..range.start + whole.end(), replacement });
+            }
+        }
+        matches
+    }
+}
+
+// Replaces the matches, which must be sorted and not overlap
+pub fn apply(text: &str, matches: &[Match]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut copied_to = 0;
+    for m in matches {
+        out.push_str(&text[copied_to..m.range.start]);
+        out.push_str(&m.replacement);
+        copied_to = m.range.end;
+    }
+    out.push_str(&text[copied_to..]);
+    out
+}
+
+// Shows each replacement in front of the text it replaces, which should be drawn struck out
+pub fn preview(matches: &[Match]) -> Vec<VirtualText> {
+    matches
+        .iter()
+        .map(|m| VirtualText { at: m.range.start, text: m.replacement.clone() })
+        .collect()
+}
+
+// A file from the project search results, with the matches in it
+#[derive(Debug, Clone)]
+pub struct FileMatches {
+    pub path: PathBuf,
+    pub matches: Vec<Match>,
+    // 1-based line and column of each match, and the line it's on
+    pub lines: Vec<(usize, usize, String)>,
+    // The text each match had when searched, and how long the file was, to tell if it changed since
+    found: Vec<String>,
+    len: usize,
+}
+
+impl FileMatches {
+    pub fn search(substitution: &Substitution, path: &Path) -> std::io::Result<FileMatches> {
+        let text = std::fs::read_to_string(path)?;
+        let whole = 0..text.len();
+        let matches = substitution.find(&text, std::slice::from_ref(&whole));
+        let found = matches.iter().map(|m| text[m.range.clone()].to_string()).collect();
+        let starts = line_starts(&text);
+        let lines = matches.iter().map(|m| {
+            let line = starts.partition_point(|&start| start <= m.range.start) - 1;
+            let end = starts.get(line + 1).map_or(text.len(), |&next| next - 1);
+            (line + 1, text[starts[line]..m.range.start].chars().count() + 1, text[starts[line]..end].to_string())
+        }).collect();
+        Ok(FileMatches { path: path.to_path_buf(), matches, lines, found, len: text.len() })
+    }
+
+    // Whether `text` still has the matches where they were found
+    fn lines_up(&self, text: &str) -> bool {
+        text.len() == self.len && self.matches.iter().zip(&self.found).all(|(m, found)| text.get(m.range.clone()) == Some(found.as_str()))
+    }
+}
+
+// Searches every file, skipping ones that can't be read. Stops early if cancelled
+pub fn search_files(substitution: &Substitution, paths: &[PathBuf], progress: &Progress) -> Vec<FileMatches> {
+    progress.set_total(paths.len() as u64);
+    let mut results = Vec::new();
+    for path in paths {
+        if progress.is_cancelled() {
+            break;
+        }
+        if let Ok(file) = FileMatches::search(substitution, path) {
+            if !file.matches.is_empty() {
+                results.push(file);
+            }
+        }
+        progress.advance(1);
+    }
+    progress.finish();
+    results
+}
+
+// :search-files running on another thread
+pub struct FileSearch {
+    results: mpsc::Receiver<Vec<FileMatches>>,
+}
+
+impl FileSearch {
+    pub fn start(substitution: Substitution, paths: Vec<PathBuf>, progress: Progress) -> FileSearch {
+        let (tx, results) = mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = tx.send(search_files(&substitution, &paths, &progress));
+        });
+        FileSearch { results }
+    }
+
+    // The matches once the search is done, what was found before it was cancelled if it was
+    pub fn poll(&self) -> Option<Vec<FileMatches>> {
+        match self.results.try_recv() {
+            Ok(files) => Some(files),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Vec::new()),
+        }
+    }
+}
+
+// What the file looked like before replacing, to put it back with
+#[derive(Debug, Clone)]
+pub struct FileUndo {
+    pub path: PathBuf,
+    pub original: String,
+    pub replaced: String,
+}
+
+impl FileUndo {
+    // Refuses to undo if the file was changed again since, rather than throwing those changes away
+    pub fn undo(&self) -> std::io::Result<bool> {
+        if std::fs::read_to_string(&self.path)? != self.replaced {
+            return Ok(false);
+        }
+        std::fs::write(&self.path, &self.original)?;
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ReplaceReport {
+    pub replacements: usize,
+    pub undo: Vec<FileUndo>,
+    pub failed: Vec<(PathBuf, std::io::Error)>,
+}
+
+impl ReplaceReport {
+    pub fn summary(&self) -> String {
+        let mut summary = format!("Replaced {} matches in {} files", self.replacements, self.undo.len());
+        if !self.failed.is_empty() {
+            summary.push_str(&format!(", {} files failed:", self.failed.len()));
+            for (path, err) in &self.failed {
+                summary.push_str(&format!("\n  {}: {err}", path.display()));
+            }
+        }
+        summary
+    }
+}
+
+// Applies the replacements across every file in the search results. Files changed on disk since
+// the search, so that the matches aren't where they were found anymore, are skipped and reported as
+// failed
+pub fn replace_in_files(results: &[FileMatches]) -> ReplaceReport {
+    let mut report = ReplaceReport::default();
+    for file in results.iter().filter(|file| !file.matches.is_empty()) {
+        let result = std::fs::read_to_string(&file.path).and_then(|original| {
+            if !file.lines_up(&original) {
+                return Err(std::io::Error::other("changed since the search"));
+            }
+            let replaced = apply(&original, &file.matches);
+            std::fs::write(&file.path, &replaced)?;
+            Ok(FileUndo { path: file.path.clone(), original, replaced })
+        });
+        match result {
+            Ok(undo) => {
+                report.replacements += file.matches.len();
+                report.undo.push(undo);
+            }
+            Err(err) => report.failed.push((file.path.clone(), err)),
+        }
+    }
+    report
+}
+
+// The project search results buffer, a match on each line, which replace-all replaces
+#[derive(Debug, Clone)]
+pub struct SearchResults {
+    pub files: Vec<FileMatches>,
+}
+
+impl SearchResults {
+    pub fn text(&self) -> String {
+        let lines = self.files.iter().flat_map(|file| file.lines.iter().map(move |(line, col, text)| (&file.path, line, col, text)));
+        lines.map(|(path, line, col, text)| format!("{}:{line}:{col}: {}\n", path.display(), text.trim())).collect()
+    }
+
+    // Where the match on `line` of the buffer is, to jump to with Enter
+    pub fn location(&self, line: usize) -> Option<(PathBuf, usize, usize)> {
+        let (path, (line, col, _)) = self.files.iter().flat_map(|file| file.lines.iter().map(move |at| (&file.path, at))).nth(line)?;
+        Some((path.clone(), *line, *col))
+    }
+}
+
+// What the project search and replace commands need from the editor
+pub trait ReplaceHost {
+    // The files of the project, or the open files without one
+    fn files_to_search(&mut self) -> Vec<PathBuf>;
+    // Keeps the search to show its results with show_results once it's done
+    fn start_search(&mut self, search: FileSearch);
+    // Shows the results in a buffer of their own
+    fn show_results(&mut self, results: SearchResults);
+    // Of the current buffer, if it's a search results buffer
+    fn search_results(&self) -> Option<&SearchResults>;
+    // Why a file can't be written behind the editor's back, like unsaved changes in a buffer of it
+    fn unsaved(&self, path: &Path) -> Option<String>;
+    // The files were changed on disk, so buffers of them should read them again
+    fn files_changed(&mut self, paths: &[PathBuf]);
+    // What undo-replace puts back
+    fn replace_undo(&mut self) -> &mut Vec<FileUndo>;
+    fn show_report(&mut self, title: &str, text: String);
+}
+
+// search-files s/pattern/replacement/[flags]
+fn search_files_command<Ctx: ReplaceHost + ProgressHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let [line] = args else { return Err("Usage: search-files s/pattern/replacement/[flags]".to_string()) };
+    let substitution = Substitution::parse(line).map_err(|e| e.to_string())?;
+    let paths = ctx.files_to_search();
+    let progress = ctx.progress().start("Searching");
+    ctx.start_search(FileSearch::start(substitution, paths, progress));
+    Ok(())
+}
+
+// replace-all, in a search results buffer
+fn replace_all_command<Ctx: ReplaceHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: replace-all".to_string());
+    }
+    let results = ctx.search_results().ok_or("Not a search results buffer")?;
+    if let Some(reason) = results.files.iter().find_map(|file| ctx.unsaved(&file.path)) {
+        return Err(reason);
+    }
+    let report = replace_in_files(&results.files);
+    let changed: Vec<PathBuf> = report.undo.iter().map(|undo| undo.path.clone()).collect();
+    ctx.files_changed(&changed);
+    ctx.show_report("*replaced*", report.summary() + "\n");
+    *ctx.replace_undo() = report.undo;
+    Ok(())
+}
+
+// undo-replace, putting back the files of the last replace-all that haven't changed since
+fn undo_replace_command<Ctx: ReplaceHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: undo-replace".to_string());
+    }
+    let undo = std::mem::take(ctx.replace_undo());
+    if undo.is_empty() {
+        return Err("Nothing replaced to undo".to_string());
+    }
+    if let Some(reason) = undo.iter().find_map(|file| ctx.unsaved(&file.path)) {
+        *ctx.replace_undo() = undo;
+        return Err(reason);
+    }
+    let mut restored = Vec::new();
+    let mut summary = String::new();
+    for file in &undo {
+        match file.undo() {
+            Ok(true) => restored.push(file.path.clone()),
+            Ok(false) => summary.push_str(&format!("\n  {}: changed since", file.path.display())),
+            Err(e) => summary.push_str(&format!("\n  {}: {e}", file.path.display())),
+        }
+    }
+    ctx.files_changed(&restored);
+    let failed = undo.len() - restored.len();
+    let heading = match failed {
+        0 => format!("Restored {} files", restored.len()),
+        _ => format!("Restored {} files, {failed} files failed:", restored.len()),
+    };
+    ctx.show_report("*replaced*", heading + &summary + "\n");
+    Ok(())
+}
+
+pub fn register<Ctx: ReplaceHost + ProgressHost>(registry: &mut Registry<Ctx>) {
+    registry.add_raw_builtin("search-files", search_files_command::<Ctx>);
+    registry.add_builtin("replace-all", replace_all_command::<Ctx>);
+    registry.add_builtin("undo-replace", undo_replace_command::<Ctx>);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    Yes,
+    No,
+    All,
+    Quit,
+}
+
+impl Answer {
+    pub fn from_key(key: char) -> Option<Answer> {
+        match key {
+            'y' => Some(Answer::Yes),
+            'n' => Some(Answer::No),
+            'a' => Some(Answer::All),
+            'q' | '\u{1b}' => Some(Answer::Quit),
+            _ => None,
+        }
+    }
+}
+
+// Goes through matches one at a time for the c flag
+#[derive(Debug)]
+pub struct Confirm {
+    pub matches: Vec<Match>,
+    accepted: Vec<Match>,
+    next: usize,
+}
+
+impl Confirm {
+    pub fn new(matches: Vec<Match>) -> Confirm {
+        Confirm { matches, accepted: Vec::new(), next: 0 }
+    }
+
+    // The match being asked about, None once done
+    pub fn current(&self) -> Option<&Match> {
+        self.matches.get(self.next)
+    }
+
+    pub fn answer(&mut self, answer: Answer) {
+        match answer {
+            Answer::Yes => {
+                if let Some(m) = self.current() {
+                    self.accepted.push(m.clone());
+                }
+                self.next += 1;
+            }
+            Answer::No => self.next += 1,
+            Answer::All => {
+                self.accepted.extend(self.matches[self.next.min(self.matches.len())..].iter().cloned());
+                self.next = self.matches.len();
+            }
+            Answer::Quit => self.next = self.matches.len(),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next >= self.matches.len()
+    }
+
+    // The matches to replace
+    pub fn finish(self) -> Vec<Match> {
+        self.accepted
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutes_with_groups() {
+        let text = "let a = b; let c = d;\nlet e = f;";
+        let s = Substitution::parse(r"s/let (\w) = (\w)/let \2 = \1/").unwrap();
+        let matches = s.find(text, &[0..text.len()]);
+        assert_eq!(apply(text, &matches), "let b = a; let c = d;\nlet f = e;");
+
+        let s = Substitution::parse(r"s/let (\w) = (\w)/let \2 = \1/g").unwrap();
+        let matches = s.find(text, &[0..text.len()]);
+        assert_eq!(apply(text, &matches), "let b = a; let d = c;\nlet f = e;");
+
+        // Only within a selection
+        let matches = s.find(text, &[11..21]);
+        assert_eq!(apply(text, &matches), "let a = b; let d = c;\nlet e = f;");
+    }
+
+    #[test]
+    fn parses_flags_and_escapes() {
+        let s = Substitution::parse(r"s/a\/b/[&] $x/gi").unwrap();
+        assert!(s.global && !s.confirm);
+        assert_eq!(apply("A/B", &s.find("A/B", &[0..3])), "[A/B] $x");
+        assert!(matches!(Substitution::parse("s/a/b/z"), Err(Error::UnknownFlag('z'))));
+        assert!(matches!(Substitution::parse("s/a"), Err(Error::Syntax)));
+        assert!(matches!(Substitution::parse("s/(/b/"), Err(Error::BadPattern(_))));
+    }
+
+    #[test]
+    fn confirm_each_match() {
+        let text = "x x x x";
+        let s = Substitution::parse("s/x/y/gc").unwrap();
+        let mut confirm = Confirm::new(s.find(text, &[0..text.len()]));
+        confirm.answer(Answer::Yes);
+        confirm.answer(Answer::No);
+        confirm.answer(Answer::All);
+        assert!(confirm.is_done());
+        assert_eq!(apply(text, &confirm.finish()), "y x y y");
+    }
+
+    #[test]
+    fn replaces_across_files() {
+        let dir = std::env::temp_dir().join(format!("rakoune-replace-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+        std::fs::write(&a, "foo bar foo").unwrap();
+        std::fs::write(&b, "bar").unwrap();
+
+        let s = Substitution::parse("s/foo/baz/g").unwrap();
+        let mut tracker = crate::progress::ProgressTracker::default();
+        let results = search_files(&s, &[a.clone(), b.clone(), dir.join("missing")], &tracker.start("Searching"));
+        assert_eq!(results.len(), 1);
+        assert!(!tracker.is_busy());
+        let report = replace_in_files(&results);
+        assert_eq!(report.summary(), "Replaced 2 matches in 1 files");
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "baz bar baz");
+
+        assert!(report.undo[0].undo().unwrap());
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "foo bar foo");
+        // Already undone, so the file doesn't look like the replaced version anymore
+        assert!(!report.undo[0].undo().unwrap());
+
+        // Edited between the search and replacing, with the matches moved but still in bounds
+        let results = search_files(&s, std::slice::from_ref(&a), &tracker.start("Searching"));
+        std::fs::write(&a, "xfoo bar fo").unwrap();
+        let report = replace_in_files(&results);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "xfoo bar fo");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
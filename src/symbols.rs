@@ -0,0 +1,273 @@
+// Symbols of a document or the whole workspace, for :symbols and :workspace-symbols pickers and
+// the outline sidebar. They come from the language server when there is one, and otherwise from
+// matching common definition syntax line by line
+//
+// Pickers jump to the selected symbol as it's moved over, and back on Escape
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::commands::Registry;
+use crate::markdown::is_markdown;
+use crate::picker::Picker;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Module,
+    Class,
+    Struct,
+    Enum,
+    Interface,
+    Function,
+    Method,
+    Constant,
+    Variable,
+    Field,
+    Heading,
+    Other,
+}
+
+impl SymbolKind {
+    // From the numbers of the LSP SymbolKind enum
+    pub fn from_lsp(kind: u32) -> SymbolKind {
+        match kind {
+            2..=4 => SymbolKind::Module,
+            5 => SymbolKind::Class,
+            6 | 9 => SymbolKind::Method,
+            7 | 8 => SymbolKind::Field,
+            10 => SymbolKind::Enum,
+            11 => SymbolKind::Interface,
+            12 => SymbolKind::Function,
+            13 => SymbolKind::Variable,
+            14 => SymbolKind::Constant,
+            23 => SymbolKind::Struct,
+            _ => SymbolKind::Other,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SymbolKind::Module => "mod",
+            SymbolKind::Class => "class",
+            SymbolKind::Struct => "struct",
+            SymbolKind::Enum => "enum",
+            SymbolKind::Interface => "trait",
+            SymbolKind::Function => "fn",
+            SymbolKind::Method => "method",
+            SymbolKind::Constant => "const",
+            SymbolKind::Variable => "let",
+            SymbolKind::Field => "field",
+            SymbolKind::Heading => "#",
+            SymbolKind::Other => "·",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub path: PathBuf,
+    // 0-based
+    pub line: usize,
+    // How many symbols it's nested in
+    pub depth: usize,
+}
+
+fn definition_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"^(\s*)(?:pub(?:\([^)]*\))?\s+|export\s+|default\s+|async\s+|unsafe\s+)*(?:const\s+)?\b(fn|struct|enum|trait|mod|const|static|type|def|class|function|interface)\s+([A-Za-z_]\w*)").unwrap()
+    })
+}
+
+fn heading_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"^(#{1,6})\s+(.+?)\s*#*$").unwrap())
+}
+
+// Symbols found without a language server: markdown headings, or definitions in Rust, Python,
+// JavaScript and the like. Nesting follows indentation
+pub fn outline(path: &Path, text: &str) -> Vec<Symbol> {
+    let symbol = |name: &str, kind, line, depth| Symbol { name: name.to_string(), kind, path: path.to_owned(), line, depth };
+    if is_markdown(path) {
+        return text
+            .lines()
+            .enumerate()
+            .filter_map(|(line, content)| heading_pattern().captures(content).map(|captures| symbol(&captures[2], SymbolKind::Heading, line, captures[1].len() - 1)))
+            .collect();
+    }
+    let mut symbols = Vec::new();
+    // Indentation of the symbols the next one may be nested in
+    let mut indents: Vec<usize> = Vec::new();
+    for (line, content) in text.lines().enumerate() {
+        if content.trim().is_empty() {
+            continue;
+        }
+        // Any line as far out as a symbol, like its closing brace, ends it
+        let indent = content.len() - content.trim_start().len();
+        while indents.last().is_some_and(|&outer| outer >= indent) {
+            indents.pop();
+        }
+        let Some(captures) = definition_pattern().captures(content) else { continue };
+        let kind = match &captures[2] {
+            "fn" | "def" | "function" if indent > 0 => SymbolKind::Method,
+            "fn" | "def" | "function" => SymbolKind::Function,
+            "struct" | "type" => SymbolKind::Struct,
+            "enum" => SymbolKind::Enum,
+            "trait" | "interface" => SymbolKind::Interface,
+            "mod" => SymbolKind::Module,
+            "const" | "static" => SymbolKind::Constant,
+            "class" => SymbolKind::Class,
+            _ => SymbolKind::Other,
+        };
+        symbols.push(symbol(&captures[3], kind, line, indents.len()));
+        indents.push(indent);
+    }
+    symbols
+}
+
+pub fn label(symbol: &Symbol, with_path: bool) -> String {
+    match with_path {
+        true => format!("{} {}  {}:{}", symbol.kind.label(), symbol.name, symbol.path.display(), symbol.line + 1),
+        false => format!("{}{} {}", "  ".repeat(symbol.depth), symbol.kind.label(), symbol.name),
+    }
+}
+
+// A picker over `symbols`, whose items are where each one is. `original` is where the cursor is,
+// to go back to on Escape. Workspace symbols are shown with their paths
+pub fn picker(symbols: &[Symbol], with_path: bool, original: (PathBuf, usize)) -> Picker<(PathBuf, usize)> {
+    let items = symbols.iter().map(|symbol| (label(symbol, with_path), (symbol.path.clone(), symbol.line))).collect();
+    Picker::new(items, original)
+}
+
+// The outline of one buffer, shown next to it
+#[derive(Debug, Clone)]
+pub struct OutlinePane {
+    pub path: PathBuf,
+    pub symbols: Vec<Symbol>,
+    // Symbols come from the language server, so edits don't recompute them
+    from_server: bool,
+}
+
+impl OutlinePane {
+    pub fn new(path: &Path, text: &str) -> OutlinePane {
+        OutlinePane { path: path.to_owned(), symbols: outline(path, text), from_server: false }
+    }
+
+    pub fn set_server_symbols(&mut self, symbols: Vec<Symbol>) {
+        self.symbols = symbols;
+        self.from_server = true;
+    }
+
+    // Should be called when the buffer changes
+    pub fn update(&mut self, text: &str) {
+        if !self.from_server {
+            self.symbols = outline(&self.path, text);
+        }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.symbols.iter().map(|symbol| label(symbol, false)).collect()
+    }
+
+    // The symbol the cursor on `line` is in, to highlight: the last one starting before it
+    pub fn current(&self, line: usize) -> Option<usize> {
+        self.symbols.iter().rposition(|symbol| symbol.line <= line)
+    }
+}
+
+// What the symbol commands need from the editor
+pub trait SymbolHost {
+    fn current_buffer(&self) -> Option<(PathBuf, String)>;
+    fn cursor_line(&self) -> usize;
+    fn open_buffers(&self) -> Vec<(PathBuf, String)>;
+    // Ask the language server, which answers by opening a picker with the symbols. Ok(false) when
+    // there is no server, so the outline is used instead
+    fn request_document_symbols(&mut self, path: &Path) -> Result<bool, String>;
+    fn request_workspace_symbols(&mut self, query: &str) -> Result<bool, String>;
+    fn open_picker(&mut self, picker: Picker<(PathBuf, usize)>);
+    fn toggle_outline(&mut self, pane: OutlinePane);
+}
+
+fn symbols_command<Ctx: SymbolHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    let (path, text) = ctx.current_buffer().ok_or("No buffer to list symbols of")?;
+    if !ctx.request_document_symbols(&path)? {
+        let original = (path.clone(), ctx.cursor_line());
+        ctx.open_picker(picker(&outline(&path, &text), false, original));
+    }
+    Ok(())
+}
+
+fn workspace_symbols_command<Ctx: SymbolHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    if ctx.request_workspace_symbols(&args.join(" "))? {
+        return Ok(());
+    }
+    let symbols: Vec<Symbol> = ctx.open_buffers().iter().flat_map(|(path, text)| outline(path, text)).collect();
+    let original = ctx.current_buffer().map_or_else(PathBuf::new, |(path, _)| path);
+    let mut picker = picker(&symbols, true, (original, ctx.cursor_line()));
+    if !args.is_empty() {
+        picker.set_filter(&args.join(" "));
+    }
+    ctx.open_picker(picker);
+    Ok(())
+}
+
+fn outline_command<Ctx: SymbolHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    let (path, text) = ctx.current_buffer().ok_or("No buffer to outline")?;
+    ctx.toggle_outline(OutlinePane::new(&path, &text));
+    Ok(())
+}
+
+pub fn register<Ctx: SymbolHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("symbols", symbols_command::<Ctx>);
+    registry.add_builtin("workspace-symbols", workspace_symbols_command::<Ctx>);
+    registry.add_builtin("outline", outline_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::picker::PickerEvent;
+
+    #[test]
+    fn outlines_by_indentation() {
+        let text = "pub struct Editor {\n    x: u8,\n}\n\nimpl Editor {\n    pub(crate) fn new() -> Editor {\n        fn helper() {}\n    }\n    const fn size() {}\n}\n\nasync fn main() {}\n";
+        let symbols = outline(Path::new("main.rs"), text);
+        let found: Vec<(&str, SymbolKind, usize, usize)> = symbols.iter().map(|s| (s.name.as_str(), s.kind, s.line, s.depth)).collect();
+        assert_eq!(found, vec![
+            ("Editor", SymbolKind::Struct, 0, 0),
+            ("new", SymbolKind::Method, 5, 0),
+            ("helper", SymbolKind::Method, 6, 1),
+            ("size", SymbolKind::Method, 8, 0),
+            ("main", SymbolKind::Function, 11, 0),
+        ]);
+
+        let headings = outline(Path::new("README.md"), "# Title\ntext\n## Usage ##\n");
+        assert_eq!(headings.iter().map(|s| (s.name.as_str(), s.depth)).collect::<Vec<_>>(), vec![("Title", 0), ("Usage", 1)]);
+        assert_eq!(label(&headings[1], false), "  # Usage");
+        assert_eq!(label(&headings[1], true), "# Usage  README.md:3");
+    }
+
+    #[test]
+    fn picker_jumps_and_reverts() {
+        let path = PathBuf::from("a.py");
+        let symbols = outline(&path, "class A:\n    def run(self):\n        pass\ndef main():\n    pass\n");
+        let mut picker = picker(&symbols, false, (path.clone(), 4));
+        assert_eq!(picker.set_filter("run"), Some(PickerEvent::Preview((path.clone(), 1))));
+        assert_eq!(picker.escape(), PickerEvent::Revert((path.clone(), 4)));
+
+        let mut pane = OutlinePane::new(&path, "def a():\n    pass\ndef b():\n    pass\n");
+        assert_eq!(pane.current(3), Some(1));
+        assert_eq!(pane.current(0), Some(0));
+        pane.update("x = 1\ndef a():\n    pass\n");
+        assert_eq!(pane.lines(), vec!["fn a"]);
+        assert_eq!(pane.current(0), None);
+        pane.set_server_symbols(Vec::new());
+        pane.update("def c(): pass\n");
+        assert!(pane.symbols.is_empty());
+        assert_eq!(SymbolKind::from_lsp(23), SymbolKind::Struct);
+    }
+}
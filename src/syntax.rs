@@ -0,0 +1,381 @@
+// Tree-sitter syntax trees of buffers whose grammar has a tree-sitter parser, kept up to date as
+// the buffer is edited, and the queries built on them: highlighting, expanding the selection to
+// the enclosing node, folding by node and text objects. Buffers of other languages get the same
+// from the bracket tree, which only knows brackets, strings and comments.
+//
+// The parser for a language comes with its grammar file, see bracket_tree.rs:
+//
+//   name = "toml"
+//   extensions = ["toml"]
+//   brackets = ["[]", "{}"]
+//
+//   [tree_sitter]
+//   library = "libtree-sitter-toml.so"
+//   highlights = "toml-highlights.scm"
+//
+// The library is loaded at runtime and has to export tree_sitter_<name>, as tree-sitter's grammars
+// do. Rust's parser and highlights are built in, so they can be left out for it. Every edit the
+// buffer records is passed on to the tree, and only the part of the text around it is parsed again
+
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use tree_sitter::{InputEdit, Language, Node, Parser, Point, Query, QueryCursor, Tree};
+
+use crate::bracket_tree::{BracketTree, TextObject};
+use crate::commands::Registry;
+use crate::folding::Fold;
+use crate::layout::LineEdit;
+use crate::selection::{self, line_starts, ExpansionHistory};
+
+// A parser and the query highlighting what it parses
+pub struct SyntaxLanguage {
+    pub name: String,
+    language: Language,
+    highlights: Query,
+}
+
+impl std::fmt::Debug for SyntaxLanguage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SyntaxLanguage").field("name", &self.name).finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for SyntaxLanguage {
+    fn eq(&self, other: &SyntaxLanguage) -> bool {
+        self.name == other.name
+    }
+}
+
+impl SyntaxLanguage {
+    pub fn new(name: &str, language: Language, highlights: &str) -> Result<SyntaxLanguage, String> {
+        let highlights = Query::new(language, highlights).map_err(|e| format!("Bad highlights query: {e}"))?;
+        Ok(SyntaxLanguage { name: name.to_string(), language, highlights })
+    }
+
+    // The parser rakoune has built in for `name`, with its own highlights unless given others
+    pub fn builtin(name: &str, highlights: Option<&str>) -> Option<Result<SyntaxLanguage, String>> {
+        let (language, builtin_highlights) = match name {
+            "rust" => (tree_sitter_rust::language(), tree_sitter_rust::HIGHLIGHT_QUERY),
+            _ => return None,
+        };
+        Some(SyntaxLanguage::new(name, language, highlights.unwrap_or(builtin_highlights)))
+    }
+
+    // The parser of `name` from the shared library at `library`, which is kept loaded for good
+    pub fn load(name: &str, library: &Path, highlights: &str) -> Result<SyntaxLanguage, String> {
+        let symbol = format!("tree_sitter_{}", name.replace('-', "_"));
+        // Safety: tree-sitter grammars export this function, taking nothing and returning their
+        // language, which stays valid as long as the library is loaded
+        let language = unsafe {
+            let library = libloading::Library::new(library).map_err(|e| e.to_string())?;
+            let language = library.get::<unsafe extern "C" fn() -> Language>(symbol.as_bytes()).map_err(|e| e.to_string())?();
+            std::mem::forget(library);
+            language
+        };
+        SyntaxLanguage::new(name, language, highlights)
+    }
+}
+
+fn point(text: &str, starts: &[usize], byte: usize) -> Point {
+    let row = starts.partition_point(|&start| start <= byte) - 1;
+    Point { row, column: byte.min(text.len()) - starts[row] }
+}
+
+// The syntax tree of one buffer
+#[derive(Clone)]
+pub struct SyntaxTree {
+    pub language: Arc<SyntaxLanguage>,
+    tree: Tree,
+    // Where the lines of the text it was last parsed from start, to turn line edits into byte edits
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl std::fmt::Debug for SyntaxTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SyntaxTree").field("language", &self.language.name).field("root", &self.tree.root_node()).finish()
+    }
+}
+
+impl SyntaxTree {
+    // None if the parser gives up, which it only does when cancelled
+    pub fn new(language: Arc<SyntaxLanguage>, text: &str) -> Option<SyntaxTree> {
+        let tree = parse(&language, text, None)?;
+        Some(SyntaxTree { language, tree, starts: line_starts(text), len: text.len() })
+    }
+
+    // Must be called for every edit to the buffer, with the text after it. The lines of `edit` are
+    // parsed again, and whatever their new text changed around them. None parses everything again
+    pub fn edit(&mut self, edit: Option<&LineEdit>, text: &str) {
+        let new_starts = line_starts(text);
+        let old_tree = match edit {
+            Some(edit) if edit.lines.end <= self.starts.len() => {
+                let start_byte = self.starts[edit.lines.start];
+                let old_end_byte = self.starts.get(edit.lines.end).copied().unwrap_or(self.len);
+                let new_end_byte = new_starts.get(edit.lines.start + edit.new_lines).copied().unwrap_or(text.len());
+                let old_end_position = match self.starts.get(edit.lines.end) {
+                    Some(_) => Point { row: edit.lines.end, column: 0 },
+                    None => Point { row: self.starts.len() - 1, column: self.len - self.starts[self.starts.len() - 1] },
+                };
+                self.tree.edit(&InputEdit {
+                    start_byte,
+                    old_end_byte,
+                    new_end_byte,
+                    start_position: Point { row: edit.lines.start, column: 0 },
+                    old_end_position,
+                    new_end_position: point(text, &new_starts, new_end_byte),
+                });
+                Some(&self.tree)
+            }
+            _ => None,
+        };
+        if let Some(tree) = parse(&self.language, text, old_tree) {
+            self.tree = tree;
+        }
+        self.starts = new_starts;
+        self.len = text.len();
+    }
+
+    // Ranges of `visible` to color, with the name of the theme face for each. Where several
+    // patterns of the query match the same node, the first one wins
+    pub fn highlights(&self, text: &str, visible: Range<usize>) -> Vec<(Range<usize>, &str)> {
+        let query = &self.language.highlights;
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(visible);
+        let mut found: Vec<(Range<usize>, usize, &str)> = cursor
+            .captures(query, self.tree.root_node(), text.as_bytes())
+            .map(|(found, idx)| {
+                let capture = found.captures[idx];
+                (capture.node.byte_range(), found.pattern_index, query.capture_names()[capture.index as usize].as_str())
+            })
+            .collect();
+        found.sort_by_key(|(range, pattern, _)| (range.start, std::cmp::Reverse(range.end), *pattern));
+        found.dedup_by(|later, first| later.0 == first.0);
+        found.into_iter().map(|(range, _, face)| (range, face)).collect()
+    }
+
+    // The smallest node bigger than `range`, or the inside of one with brackets. For expand-selection
+    pub fn expand(&self, range: Range<usize>) -> Range<usize> {
+        let mut node = self.tree.root_node().descendant_for_byte_range(range.start, range.end);
+        while let Some(found) = node {
+            if let Some(inside) = inside(found).filter(|inside| inside.start <= range.start && range.end <= inside.end && inside.len() > range.len()) {
+                return inside;
+            }
+            if found.byte_range().len() > range.len() {
+                return found.byte_range();
+            }
+            node = found.parent();
+        }
+        range
+    }
+
+    // Named nodes spanning several lines, by their lines. A node on the same lines as the one
+    // around it is the same fold
+    pub fn folds(&self) -> Vec<Fold> {
+        let mut folds = Vec::new();
+        let mut cursor = self.tree.walk();
+        // Lines of the folds around the cursor's node, innermost last
+        let mut open: Vec<(usize, Range<usize>)> = Vec::new();
+        let mut depth = 0;
+        'walk: loop {
+            let node = cursor.node();
+            while open.last().is_some_and(|(at, _)| *at >= depth) {
+                open.pop();
+            }
+            let lines = node.start_position().row..node.end_position().row + 1;
+            if node.is_named() && lines.len() > 1 && open.last().is_none_or(|(_, around)| *around != lines) && node.parent().is_some() {
+                folds.push(Fold { lines: lines.clone(), depth: open.len() });
+                open.push((depth, lines));
+            }
+            if cursor.goto_first_child() {
+                depth += 1;
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+                depth -= 1;
+            }
+        }
+        folds.sort_by_key(|fold| fold.lines.start);
+        folds
+    }
+
+    // The innermost node of `kind` around `pos`. With `inside`, nodes with brackets leave them
+    // out, and functions are only their body
+    pub fn textobject(&self, pos: usize, kind: TextObject, inside_only: bool) -> Option<Range<usize>> {
+        let mut node = self.tree.root_node().descendant_for_byte_range(pos, pos);
+        while let Some(found) = node {
+            let is_kind = match kind {
+                TextObject::Bracketed => inside(found).is_some(),
+                TextObject::String => found.kind().contains("string") || found.kind().contains("char"),
+                TextObject::Comment => found.kind().contains("comment"),
+                TextObject::Function => found.kind().contains("function") && found.child_by_field_name("body").is_some(),
+            };
+            if is_kind && found.is_named() {
+                return Some(match (inside_only, kind) {
+                    (false, _) => found.byte_range(),
+                    (true, TextObject::Function) => found.child_by_field_name("body").and_then(inside).unwrap_or(found.byte_range()),
+                    (true, TextObject::String) => {
+                        let range = found.byte_range();
+                        let quotes = |child: Option<Node>| child.filter(|child| !child.is_named() && child.byte_range().len() < range.len()).map(|child| child.byte_range());
+                        let start = quotes(found.child(0)).map_or(range.start, |quote| quote.end);
+                        let end = quotes(found.child(found.child_count().saturating_sub(1))).map_or(range.end, |quote| quote.start);
+                        start..end.max(start)
+                    }
+                    (true, _) => inside(found).unwrap_or(found.byte_range()),
+                });
+            }
+            node = found.parent();
+        }
+        None
+    }
+}
+
+fn parse(language: &SyntaxLanguage, text: &str, old_tree: Option<&Tree>) -> Option<Tree> {
+    let mut parser = Parser::new();
+    parser.set_language(language.language).ok()?;
+    parser.parse(text, old_tree)
+}
+
+// Nodes starting and ending with a pair of brackets, without them
+fn inside(node: Node) -> Option<Range<usize>> {
+    let (first, last) = (node.child(0)?, node.child(node.child_count().checked_sub(1)?)?);
+    let pair = (first.kind(), last.kind());
+    if first.id() == last.id() || !matches!(pair, ("(", ")") | ("[", "]") | ("{", "}")) {
+        return None;
+    }
+    Some(first.end_byte()..last.start_byte())
+}
+
+// What the selection commands need from the editor. Without a syntax tree they go by the bracket
+// tree, and expanding goes by words, lines and indentation without either
+pub trait SyntaxHost {
+    fn text(&self) -> &str;
+    // Of the current buffer
+    fn syntax(&self) -> Option<&SyntaxTree>;
+    // Made now from the grammar of the current buffer, if it has one
+    fn bracket_tree(&self) -> Option<BracketTree>;
+    fn selection_ranges(&self) -> Vec<Range<usize>>;
+    fn select_ranges(&mut self, ranges: Vec<Range<usize>>);
+    fn expansions(&mut self) -> &mut ExpansionHistory;
+}
+
+fn expand_selection_command<Ctx: SyntaxHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    let mut ranges = ctx.selection_ranges();
+    let mut expansions = std::mem::take(ctx.expansions());
+    match (ctx.syntax(), ctx.bracket_tree()) {
+        (Some(syntax), _) => expansions.expand(&mut ranges, |range| syntax.expand(range)),
+        (None, Some(tree)) => expansions.expand(&mut ranges, |range| tree.expand(range)),
+        (None, None) => expansions.expand(&mut ranges, |range| selection::expand(ctx.text(), range)),
+    }
+    *ctx.expansions() = expansions;
+    ctx.select_ranges(ranges);
+    Ok(())
+}
+
+fn shrink_selection_command<Ctx: SyntaxHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    let mut ranges = ctx.selection_ranges();
+    ctx.expansions().shrink(&mut ranges);
+    ctx.select_ranges(ranges);
+    Ok(())
+}
+
+// select-object <function|bracket|string|comment> [inside], selecting the innermost one around
+// each selection
+fn select_object_command<Ctx: SyntaxHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    const USAGE: &str = "Usage: select-object function|bracket|string|comment [inside]";
+    let (kind, inside) = match args {
+        [kind] => (*kind, false),
+        [kind, "inside"] => (*kind, true),
+        _ => return Err(USAGE.to_string()),
+    };
+    let kind = match kind {
+        "function" => TextObject::Function,
+        "bracket" => TextObject::Bracketed,
+        "string" => TextObject::String,
+        "comment" => TextObject::Comment,
+        _ => return Err(USAGE.to_string()),
+    };
+    let tree = ctx.bracket_tree();
+    let find = |pos: usize| match ctx.syntax() {
+        Some(syntax) => syntax.textobject(pos, kind, inside),
+        None => tree.as_ref()?.textobject(pos, kind, inside),
+    };
+    let ranges: Option<Vec<Range<usize>>> = ctx.selection_ranges().into_iter().map(|range| find(range.start)).collect();
+    let ranges = ranges.ok_or_else(|| format!("No {} around the cursor", args[0]))?;
+    ctx.select_ranges(ranges);
+    Ok(())
+}
+
+pub fn register<Ctx: SyntaxHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("expand-selection", expand_selection_command::<Ctx>);
+    registry.add_builtin("shrink-selection", shrink_selection_command::<Ctx>);
+    registry.add_builtin("select-object", select_object_command::<Ctx>);
+    registry.bind("<A-o>", "expand-selection");
+    registry.bind("<A-i>", "shrink-selection");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rust() -> Arc<SyntaxLanguage> {
+        Arc::new(SyntaxLanguage::builtin("rust", None).unwrap().unwrap())
+    }
+
+    #[test]
+    fn parses_and_queries() {
+        let text = "// entry\nfn f(a: u8) -> u8 {\n    g(\"x)\", [a])\n}\n";
+        let tree = SyntaxTree::new(rust(), text).unwrap();
+        let faces: Vec<(&str, &str)> = tree.highlights(text, 0..text.len()).into_iter().map(|(range, face)| (&text[range], face)).collect();
+        assert!(faces.contains(&("// entry", "comment")));
+        assert!(faces.contains(&("f", "function")));
+        assert!(faces.contains(&("u8", "type.builtin")));
+        assert!(faces.contains(&("\"x)\"", "string")));
+
+        let x = text.find('x').unwrap();
+        assert_eq!(&text[tree.expand(x..x + 1)], "\"x)\"");
+        assert_eq!(&text[tree.expand(tree.expand(x..x + 1))], "\"x)\", [a]");
+        assert_eq!(&text[tree.expand(tree.expand(tree.expand(x..x + 1)))], "(\"x)\", [a])");
+        assert_eq!(tree.folds(), vec![Fold { lines: 1..4, depth: 0 }]);
+        assert_eq!(tree.textobject(x, TextObject::String, true).map(|r| &text[r]), Some("x)"));
+        assert_eq!(tree.textobject(x, TextObject::Bracketed, true).map(|r| &text[r]), Some("\"x)\", [a]"));
+        assert_eq!(tree.textobject(x, TextObject::Function, true).map(|r| &text[r]), Some("\n    g(\"x)\", [a])\n"));
+        assert_eq!(tree.textobject(0, TextObject::Comment, false).map(|r| &text[r]), Some("// entry"));
+        assert_eq!(tree.textobject(0, TextObject::Function, false), None);
+    }
+
+    #[test]
+    fn edits_match_a_full_parse() {
+        let mut text = "fn a() {}\n\nfn b() {\n    1\n}\n".to_string();
+        let mut tree = SyntaxTree::new(rust(), &text).unwrap();
+        let sexp = |tree: &SyntaxTree| tree.tree.root_node().to_sexp();
+
+        // Typing within a line
+        let at = text.find('1').unwrap();
+        text.replace_range(at..at + 1, "call(2)");
+        tree.edit(Some(&LineEdit { lines: 3..4, new_lines: 1 }), &text);
+        assert_eq!(sexp(&tree), sexp(&SyntaxTree::new(rust(), &text).unwrap()));
+        assert!(tree.tree.root_node().descendant_for_byte_range(at, at + 4).is_some_and(|node| node.kind() == "identifier"));
+
+        // Lines joined at the end of the text, then an edit too big to say
+        text.truncate(text.find("\n\nfn b").unwrap());
+        tree.edit(Some(&LineEdit { lines: 0..6, new_lines: 1 }), &text);
+        assert_eq!(sexp(&tree), sexp(&SyntaxTree::new(rust(), &text).unwrap()));
+        text.push_str("\nstruct S;\n");
+        tree.edit(None, &text);
+        assert_eq!(sexp(&tree), sexp(&SyntaxTree::new(rust(), &text).unwrap()));
+    }
+
+    #[test]
+    fn libraries_that_arent_there() {
+        let missing = std::env::temp_dir().join("rakoune-no-such-grammar.so");
+        assert!(SyntaxLanguage::load("toml", &missing, "").is_err());
+        assert!(SyntaxLanguage::builtin("toml", None).is_none());
+        assert!(SyntaxLanguage::builtin("rust", Some("(no_such_node) @keyword")).unwrap().is_err());
+    }
+}
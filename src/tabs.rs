@@ -0,0 +1,227 @@
+use std::path::{Path, PathBuf};
+
+use crate::commands::Registry;
+use crate::layout::Rect;
+
+pub const HEIGHT: f32 = 24.;
+// Around the title and the close button
+pub const PADDING: f32 = 8.;
+const CLOSE_SIZE: f32 = 14.;
+const MAX_TAB_WIDTH: f32 = 200.;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tab {
+    pub path: Option<PathBuf>,
+    pub modified: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TabRect {
+    pub title: String,
+    pub rect: Rect,
+    pub close: Rect,
+    pub active: bool,
+    pub modified: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Click {
+    Select(usize),
+    Close(usize),
+}
+
+// Title for each tab: the file name, with as many parent directories as it takes to tell apart
+// files with the same name
+pub fn titles(tabs: &[Tab]) -> Vec<String> {
+    let components = |path: &Path| -> Vec<String> {
+        path.components().rev().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect()
+    };
+    let reversed: Vec<Vec<String>> = tabs.iter().map(|tab| tab.path.as_deref().map(components).unwrap_or_default()).collect();
+    let title = |parts: &[String], depth: usize| -> String {
+        let mut shown: Vec<&str> = parts.iter().take(depth).map(String::as_str).collect();
+        shown.reverse();
+        shown.join("/")
+    };
+    reversed
+        .iter()
+        .map(|parts| {
+            if parts.is_empty() {
+                return "[scratch]".to_string();
+            }
+            let mut depth = 1;
+            while depth < parts.len()
+                && reversed.iter().any(|other| !std::ptr::eq(other, parts) && title(other, depth) == title(parts, depth))
+            {
+                depth += 1;
+            }
+            title(parts, depth)
+        })
+        .collect()
+}
+
+// The bar of open buffers along the top of the window
+#[derive(Debug, Default)]
+pub struct TabBar {
+    pub visible: bool,
+    pub tabs: Vec<Tab>,
+    pub active: usize,
+}
+
+impl TabBar {
+    // Where each tab goes, given a function measuring the width of a title in pixels
+    pub fn layout(&self, window_width: f32, measure: impl Fn(&str) -> f32) -> Vec<TabRect> {
+        let mut x = 0.;
+        let mut rects = Vec::new();
+        for (idx, title) in titles(&self.tabs).into_iter().enumerate() {
+            let w = (measure(&title) + 3. * PADDING + CLOSE_SIZE).min(MAX_TAB_WIDTH);
+            if x >= window_width {
+                break;
+            }
+            let close = Rect { x: x + w - PADDING - CLOSE_SIZE, y: (HEIGHT - CLOSE_SIZE) / 2., w: CLOSE_SIZE, h: CLOSE_SIZE };
+            rects.push(TabRect {
+                title,
+                rect: Rect { x, y: 0., w, h: HEIGHT },
+                close,
+                active: idx == self.active,
+                modified: self.tabs[idx].modified,
+            });
+            x += w;
+        }
+        rects
+    }
+
+    // Space the buffer loses to the bar
+    pub fn height(&self) -> f32 {
+        if self.visible { HEIGHT } else { 0. }
+    }
+
+    pub fn click(&self, rects: &[TabRect], x: f32, y: f32) -> Option<Click> {
+        let inside = |r: &Rect| x >= r.x && x < r.x + r.w && y >= r.y && y < r.y + r.h;
+        let idx = rects.iter().position(|tab| inside(&tab.rect))?;
+        Some(if inside(&rects[idx].close) { Click::Close(idx) } else { Click::Select(idx) })
+    }
+
+    // gt: the next tab, or with a count, tab number `count` like in vim
+    pub fn goto_next(&mut self, count: Option<usize>) {
+        if self.tabs.is_empty() {
+            return;
+        }
+        self.active = match count {
+            Some(n) => (n.max(1) - 1).min(self.tabs.len() - 1),
+            None => (self.active + 1) % self.tabs.len(),
+        };
+    }
+
+    // gT: back `count` tabs, wrapping around
+    pub fn goto_prev(&mut self, count: usize) {
+        if self.tabs.is_empty() {
+            return;
+        }
+        let n = self.tabs.len();
+        self.active = (self.active + n - count % n) % n;
+    }
+
+    // Removes a tab, keeping the same tab active if it's still there
+    pub fn close(&mut self, idx: usize) -> Option<Tab> {
+        if idx >= self.tabs.len() {
+            return None;
+        }
+        let tab = self.tabs.remove(idx);
+        if idx < self.active || (idx == self.active && self.active == self.tabs.len() && self.active > 0) {
+            self.active -= 1;
+        }
+        Some(tab)
+    }
+}
+
+// What the tab commands need from the editor
+pub trait TabsHost {
+    // With a tab for each buffer, the current one active
+    fn tab_bar(&mut self) -> &mut TabBar;
+    // Shows the buffer of the active tab
+    fn select_tab(&mut self, idx: usize);
+    fn close_tab(&mut self, idx: usize) -> Result<(), String>;
+}
+
+fn count_arg(args: &[&str], usage: &str) -> Result<Option<usize>, String> {
+    match args {
+        [] => Ok(None),
+        [count] => count.parse().map(Some).map_err(|_| usage.to_string()),
+        _ => Err(usage.to_string()),
+    }
+}
+
+// tabnext [n], the next tab or tab number n
+fn tab_next_command<Ctx: TabsHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let count = count_arg(args, "Usage: tabnext [n]")?;
+    let bar = ctx.tab_bar();
+    bar.goto_next(count);
+    let active = bar.active;
+    ctx.select_tab(active);
+    Ok(())
+}
+
+// tabprev [n], back n tabs
+fn tab_prev_command<Ctx: TabsHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let count = count_arg(args, "Usage: tabprev [n]")?;
+    let bar = ctx.tab_bar();
+    bar.goto_prev(count.unwrap_or(1));
+    let active = bar.active;
+    ctx.select_tab(active);
+    Ok(())
+}
+
+// tabclose, closing the current buffer unless it has unsaved changes
+fn tab_close_command<Ctx: TabsHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    if !args.is_empty() {
+        return Err("Usage: tabclose".to_string());
+    }
+    let active = ctx.tab_bar().active;
+    ctx.close_tab(active)
+}
+
+pub fn register<Ctx: TabsHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("tabnext", tab_next_command::<Ctx>);
+    registry.add_builtin("tabprev", tab_prev_command::<Ctx>);
+    registry.add_builtin("tabclose", tab_close_command::<Ctx>);
+    registry.bind("gt", "tabnext");
+    registry.bind("gT", "tabprev");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tab(path: &str) -> Tab {
+        Tab { path: Some(PathBuf::from(path)), modified: false }
+    }
+
+    #[test]
+    fn disambiguates_titles() {
+        let tabs = [tab("src/main.rs"), tab("tests/main.rs"), tab("src/font.rs"), Tab { path: None, modified: true }];
+        assert_eq!(titles(&tabs), vec!["src/main.rs", "tests/main.rs", "font.rs", "[scratch]"]);
+    }
+
+    #[test]
+    fn clicks_and_switching() {
+        let mut bar = TabBar { visible: true, tabs: vec![tab("a"), tab("b"), tab("c")], active: 0 };
+        let rects = bar.layout(1000., |title| title.len() as f32 * 10.);
+        assert_eq!(rects.len(), 3);
+        assert_eq!(bar.click(&rects, rects[1].rect.x + 1., 5.), Some(Click::Select(1)));
+        assert_eq!(bar.click(&rects, rects[2].close.x + 1., HEIGHT / 2.), Some(Click::Close(2)));
+        assert_eq!(bar.click(&rects, 999., 5.), None);
+
+        bar.goto_next(None);
+        assert_eq!(bar.active, 1);
+        bar.goto_prev(2);
+        assert_eq!(bar.active, 2);
+        bar.goto_next(Some(1));
+        assert_eq!(bar.active, 0);
+
+        bar.active = 2;
+        bar.close(0);
+        assert_eq!(bar.active, 1);
+        bar.close(1);
+        assert_eq!(bar.active, 0);
+    }
+}
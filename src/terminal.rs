@@ -0,0 +1,501 @@
+use winit::event::{ModifiersState, VirtualKeyCode};
+
+use crate::ansi::Style;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell { ch: ' ', style: Style::default() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    Csi,
+    // Operating system command, like setting the title. Ignored up to BEL or ST
+    Osc,
+}
+
+// The screen of a terminal, updated from the bytes the program writes. Handles what shells and
+// most command line programs use: printing with wrapping and scrolling, cursor movement, erasing
+// and SGR colors. The alternate screen and scroll regions aren't supported
+#[derive(Debug, Clone)]
+pub struct Grid {
+    pub cols: usize,
+    pub rows: usize,
+    cells: Vec<Cell>,
+    // Lines scrolled off the top
+    pub scrollback: Vec<Vec<Cell>>,
+    pub cursor: (usize, usize),
+    pub style: Style,
+    pub title: String,
+    state: State,
+    params: String,
+    osc: String,
+    // For decoding UTF-8 split between writes
+    pending_utf8: Vec<u8>,
+    // The cursor is past the last column, so the next character wraps first
+    wrap_next: bool,
+}
+
+impl Grid {
+    // At least one column and one row, as the cursor has to be somewhere
+    pub fn new(cols: usize, rows: usize) -> Grid {
+        let (cols, rows) = (cols.max(1), rows.max(1));
+        Grid {
+            cols,
+            rows,
+            cells: vec![Cell::default(); cols * rows],
+            scrollback: Vec::new(),
+            cursor: (0, 0),
+            style: Style::default(),
+            title: String::new(),
+            state: State::Ground,
+            params: String::new(),
+            osc: String::new(),
+            pending_utf8: Vec::new(),
+            wrap_next: false,
+        }
+    }
+
+    pub fn cell(&self, col: usize, row: usize) -> Cell {
+        self.cells[row * self.cols + col]
+    }
+
+    pub fn row_text(&self, row: usize) -> String {
+        let text: String = self.cells[row * self.cols..(row + 1) * self.cols].iter().map(|cell| cell.ch).collect();
+        text.trim_end().to_string()
+    }
+
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let (cols, rows) = (cols.max(1), rows.max(1));
+        let mut cells = vec![Cell::default(); cols * rows];
+        for row in 0..rows.min(self.rows) {
+            for col in 0..cols.min(self.cols) {
+                cells[row * cols + col] = self.cell(col, row);
+            }
+        }
+        (self.cols, self.rows, self.cells) = (cols, rows, cells);
+        self.cursor = (self.cursor.0.min(cols - 1), self.cursor.1.min(rows - 1));
+        self.wrap_next = false;
+    }
+
+    fn scroll_up(&mut self) {
+        self.scrollback.push(self.cells.drain(..self.cols).collect());
+        self.cells.extend(std::iter::repeat_n(Cell::default(), self.cols));
+    }
+
+    fn newline(&mut self) {
+        if self.cursor.1 + 1 == self.rows {
+            self.scroll_up();
+        } else {
+            self.cursor.1 += 1;
+        }
+    }
+
+    fn print(&mut self, ch: char) {
+        if self.wrap_next {
+            self.wrap_next = false;
+            self.cursor.0 = 0;
+            self.newline();
+        }
+        let (col, row) = self.cursor;
+        self.cells[row * self.cols + col] = Cell { ch, style: self.style };
+        if col + 1 == self.cols {
+            self.wrap_next = true;
+        } else {
+            self.cursor.0 += 1;
+        }
+    }
+
+    fn erase(&mut self, range: std::ops::Range<usize>) {
+        let blank = Cell { ch: ' ', style: Style { bg: self.style.bg, ..Default::default() } };
+        self.cells[range].fill(blank);
+    }
+
+    fn csi(&mut self, final_byte: char) {
+        let private = self.params.starts_with('?');
+        let params: Vec<u16> = self.params
+            .trim_start_matches('?')
+            .split(';')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        // Most sequences count from 1, and treat 0 or a missing parameter as 1
+        let n = |i: usize| params.get(i).copied().filter(|&p| p != 0).unwrap_or(1) as usize;
+        let (col, row) = self.cursor;
+        let at = row * self.cols + col;
+        self.wrap_next = false;
+        match final_byte {
+            'A' => self.cursor.1 = row.saturating_sub(n(0)),
+            'B' => self.cursor.1 = (row + n(0)).min(self.rows - 1),
+            'C' => self.cursor.0 = (col + n(0)).min(self.cols - 1),
+            'D' => self.cursor.0 = col.saturating_sub(n(0)),
+            'G' => self.cursor.0 = (n(0) - 1).min(self.cols - 1),
+            'd' => self.cursor.1 = (n(0) - 1).min(self.rows - 1),
+            'H' | 'f' => self.cursor = ((n(1) - 1).min(self.cols - 1), (n(0) - 1).min(self.rows - 1)),
+            'J' => match params[0] {
+                0 => self.erase(at..self.cells.len()),
+                1 => self.erase(0..at + 1),
+                _ => self.erase(0..self.cells.len()),
+            },
+            'K' => match params[0] {
+                0 => self.erase(at..(row + 1) * self.cols),
+                1 => self.erase(row * self.cols..at + 1),
+                _ => self.erase(row * self.cols..(row + 1) * self.cols),
+            },
+            'm' if !private => self.style.apply_sgr(&params),
+            _ => {}
+        }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let mut data = std::mem::take(&mut self.pending_utf8);
+        data.extend_from_slice(bytes);
+        let (text, rest) = match std::str::from_utf8(&data) {
+            Ok(text) => (text, &[][..]),
+            Err(err) if err.error_len().is_none() => {
+                let (valid, rest) = data.split_at(err.valid_up_to());
+                (std::str::from_utf8(valid).unwrap(), rest)
+            }
+            Err(_) => {
+                let lossy = String::from_utf8_lossy(&data).into_owned();
+                self.feed_str(&lossy);
+                return;
+            }
+        };
+        self.feed_str(text);
+        self.pending_utf8 = rest.to_vec();
+    }
+
+    fn feed_str(&mut self, text: &str) {
+        for c in text.chars() {
+            match (self.state, c) {
+                (State::Ground, '\u{1b}') => self.state = State::Escape,
+                (State::Ground, '\r') => {
+                    self.cursor.0 = 0;
+                    self.wrap_next = false;
+                }
+                (State::Ground, '\n' | '\u{b}' | '\u{c}') => {
+                    self.wrap_next = false;
+                    self.newline();
+                }
+                (State::Ground, '\u{8}') => {
+                    self.cursor.0 = self.cursor.0.saturating_sub(1);
+                    self.wrap_next = false;
+                }
+                (State::Ground, '\t') => self.cursor.0 = ((self.cursor.0 / 8 + 1) * 8).min(self.cols - 1),
+                (State::Ground, c) if c.is_control() => {}
+                (State::Ground, c) => self.print(c),
+                (State::Escape, '[') => {
+                    self.params.clear();
+                    self.state = State::Csi;
+                }
+                (State::Escape, ']') => {
+                    self.osc.clear();
+                    self.state = State::Osc;
+                }
+                (State::Escape, _) => self.state = State::Ground,
+                (State::Csi, c) if ('@'..='~').contains(&c) => {
+                    self.csi(c);
+                    self.state = State::Ground;
+                }
+                (State::Csi, c) => self.params.push(c),
+                (State::Osc, '\u{7}' | '\u{1b}') => {
+                    // `0;title` and `2;title` set the window title
+                    if let Some(title) = self.osc.strip_prefix("0;").or(self.osc.strip_prefix("2;")) {
+                        self.title = title.to_string();
+                    }
+                    // ESC here starts the ST terminator, whose \ is then ignored
+                    self.state = if c == '\u{1b}' { State::Escape } else { State::Ground };
+                }
+                (State::Osc, c) => self.osc.push(c),
+            }
+        }
+    }
+}
+
+// What a key sends to the program in the terminal, like xterm does
+pub fn key_bytes(key: VirtualKeyCode, modifiers: ModifiersState) -> Option<Vec<u8>> {
+    use VirtualKeyCode as K;
+    let bytes: &[u8] = match key {
+        K::Return | K::NumpadEnter => b"\r",
+        K::Back => b"\x7f",
+        K::Tab if modifiers.shift() => b"\x1b[Z",
+        K::Tab => b"\t",
+        K::Escape => b"\x1b",
+        K::Up => b"\x1b[A",
+        K::Down => b"\x1b[B",
+        K::Right => b"\x1b[C",
+        K::Left => b"\x1b[D",
+        K::Home => b"\x1b[H",
+        K::End => b"\x1b[F",
+        K::Insert => b"\x1b[2~",
+        K::Delete => b"\x1b[3~",
+        K::PageUp => b"\x1b[5~",
+        K::PageDown => b"\x1b[6~",
+        _ => return None,
+    };
+    Some(bytes.to_vec())
+}
+
+// What a typed character sends, with Ctrl+letter as the matching control character
+pub fn char_bytes(ch: char, modifiers: ModifiersState) -> Vec<u8> {
+    if modifiers.ctrl() && ch.is_ascii_alphabetic() {
+        return vec![ch.to_ascii_lowercase() as u8 - b'a' + 1];
+    }
+    let mut bytes = ch.to_string().into_bytes();
+    if modifiers.alt() {
+        bytes.insert(0, 0x1b);
+    }
+    bytes
+}
+
+pub use pty::Pty;
+
+// A program running in a pty, and its screen. Shown in place of the buffers while it runs, with
+// every key going to it
+pub struct Terminal {
+    pub grid: Grid,
+    pty: Pty,
+}
+
+impl Terminal {
+    pub fn spawn(command: &mut std::process::Command, cols: usize, rows: usize) -> std::io::Result<Terminal> {
+        let grid = Grid::new(cols, rows);
+        let pty = Pty::spawn(command, grid.cols, grid.rows)?;
+        Ok(Terminal { grid, pty })
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.pty.write(bytes)
+    }
+
+    // Feeds what the program wrote since the last call to the grid. Returns whether it wrote anything
+    pub fn poll(&mut self) -> bool {
+        let output = self.pty.read();
+        self.grid.feed(&output);
+        !output.is_empty()
+    }
+
+    pub fn resize(&mut self, cols: usize, rows: usize) -> std::io::Result<()> {
+        if (cols.max(1), rows.max(1)) == (self.grid.cols, self.grid.rows) {
+            return Ok(());
+        }
+        self.grid.resize(cols, rows);
+        self.pty.resize(self.grid.cols, self.grid.rows)
+    }
+
+    pub fn has_exited(&mut self) -> bool {
+        self.pty.has_exited()
+    }
+}
+
+#[cfg(not(unix))]
+mod pty {
+    use std::process::Command;
+
+    // There are no ptys to run programs in outside unix
+    pub struct Pty;
+
+    impl Pty {
+        pub fn spawn(_command: &mut Command, _cols: usize, _rows: usize) -> std::io::Result<Pty> {
+            Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Terminals need a pty, which this platform doesn't have"))
+        }
+
+        pub fn write(&mut self, _bytes: &[u8]) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        pub fn resize(&self, _cols: usize, _rows: usize) -> std::io::Result<()> {
+            Ok(())
+        }
+
+        pub fn read(&self) -> Vec<u8> {
+            Vec::new()
+        }
+
+        pub fn has_exited(&mut self) -> bool {
+            true
+        }
+    }
+}
+
+#[cfg(unix)]
+mod pty {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::unix::process::CommandExt;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc;
+
+    // A shell running in a pseudo terminal. Output is read on a background thread
+    pub struct Pty {
+        master: File,
+        child: Child,
+        output: mpsc::Receiver<Vec<u8>>,
+    }
+
+    fn winsize(cols: usize, rows: usize) -> libc::winsize {
+        libc::winsize { ws_row: rows as u16, ws_col: cols as u16, ws_xpixel: 0, ws_ypixel: 0 }
+    }
+
+    impl Pty {
+        pub fn spawn(command: &mut Command, cols: usize, rows: usize) -> std::io::Result<Pty> {
+            let (mut master_fd, mut slave_fd) = (0, 0);
+            let size = winsize(cols, rows);
+            // SAFETY: openpty writes the two descriptors; the name and termios are optional
+            let result = unsafe { libc::openpty(&mut master_fd, &mut slave_fd, std::ptr::null_mut(), std::ptr::null(), &size) };
+            if result != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            // SAFETY: both were just opened and are owned by nothing else
+            let (master, slave) = unsafe { (File::from_raw_fd(master_fd), OwnedFd::from_raw_fd(slave_fd)) };
+            // openpty doesn't set close-on-exec, so every program started later would hold the pty
+            // open, and the shell would never see it close
+            for fd in [master.as_raw_fd(), slave.as_raw_fd()] {
+                // SAFETY: F_SETFD only changes the flags of a descriptor we own
+                if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+
+            command
+                .env("TERM", "xterm-256color")
+                .stdin(Stdio::from(slave.try_clone()?))
+                .stdout(Stdio::from(slave.try_clone()?))
+                .stderr(Stdio::from(slave));
+            // SAFETY: only async-signal-safe calls between fork and exec
+            unsafe {
+                command.pre_exec(|| {
+                    // New session with the pty as its controlling terminal, so job control and ^C work
+                    if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+            let child = command.spawn()?;
+
+            let mut reader = master.try_clone()?;
+            let (tx, rx) = mpsc::channel();
+            std::thread::spawn(move || {
+                let mut buf = [0; 4096];
+                // Fails with EIO once the shell exits
+                while let Ok(n @ 1..) = reader.read(&mut buf) {
+                    if tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+            });
+            Ok(Pty { master, child, output: rx })
+        }
+
+        pub fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+            self.master.write_all(bytes)
+        }
+
+        pub fn resize(&self, cols: usize, rows: usize) -> std::io::Result<()> {
+            let size = winsize(cols, rows);
+            // SAFETY: TIOCSWINSZ only reads the winsize
+            if unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) } < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        // Output since the last call, to feed to a Grid
+        pub fn read(&self) -> Vec<u8> {
+            self.output.try_iter().flatten().collect()
+        }
+
+        pub fn has_exited(&mut self) -> bool {
+            !matches!(self.child.try_wait(), Ok(None))
+        }
+    }
+
+    impl Drop for Pty {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_handles_sequences() {
+        let mut grid = Grid::new(10, 3);
+        grid.feed(b"hello\r\nworld\x1b[1;31m!\x1b[0m");
+        assert_eq!(grid.row_text(0), "hello");
+        assert_eq!(grid.row_text(1), "world!");
+        assert!(grid.cell(5, 1).style.bold);
+        assert_eq!(grid.cursor, (6, 1));
+
+        grid.feed(b"\x1b[1;3Hxy\x1b[K\x1b[2;1H\x1b[2K");
+        assert_eq!(grid.row_text(0), "hexy");
+        assert_eq!(grid.row_text(1), "");
+
+        // Wrapping onto the last line, then scrolling
+        grid.feed(b"\x1b[3;1H0123456789ab\x1b]0;title\x07");
+        assert_eq!(grid.row_text(1), "0123456789");
+        assert_eq!(grid.row_text(2), "ab");
+        assert_eq!(grid.scrollback.len(), 1);
+        assert_eq!(grid.title, "title");
+
+        // A character split over two writes
+        grid.feed(&"é".as_bytes()[..1]);
+        grid.feed(&"é".as_bytes()[1..]);
+        assert_eq!(grid.row_text(2), "abé");
+
+        // A window too small for even one cell still has one
+        grid.resize(0, 0);
+        grid.feed(b"\x1b[5;5Hx\x1b[3G\x1b[2d\x1b[9C\ty");
+        assert_eq!((grid.cols, grid.rows, grid.cursor), (1, 1, (0, 0)));
+        assert_eq!(Grid::new(0, 3).cols, 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pty_is_not_inherited() {
+        // Only stdin, stdout, stderr and the directory ls is listing, with no copies of the pty left open
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "ls /proc/self/fd | wc -l"]);
+        let mut terminal = Terminal::spawn(&mut command, 20, 5).unwrap();
+        let start = std::time::Instant::now();
+        while terminal.grid.row_text(0).is_empty() && start.elapsed() < std::time::Duration::from_secs(5) {
+            terminal.poll();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let _ = terminal.has_exited();
+        assert!(terminal.grid.row_text(0).parse::<usize>().unwrap() <= 4, "{}", terminal.grid.row_text(0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_in_pty() {
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "tty >/dev/null && printf 'in a tty'"]);
+        let mut pty = Pty::spawn(&mut command, 20, 5).unwrap();
+        let mut grid = Grid::new(20, 5);
+        let start = std::time::Instant::now();
+        while grid.row_text(0).is_empty() && start.elapsed() < std::time::Duration::from_secs(5) {
+            grid.feed(&pty.read());
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert_eq!(grid.row_text(0), "in a tty");
+        while !pty.has_exited() {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+    }
+}
@@ -0,0 +1,21 @@
+// What the tests of more than one module need
+
+// Parses and validates WGSL like wgpu does when making a shader module, without needing a GPU
+pub fn validate_shader(source: &str) {
+    let module = naga::front::wgsl::parse_str(source).unwrap();
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::empty())
+        .validate(&module)
+        .unwrap();
+}
+
+// xorshift, so randomized tests do the same on every run
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
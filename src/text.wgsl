@@ -0,0 +1,61 @@
+struct Globals {
+    // Size of the render target in pixels
+    target_size: vec2<f32>,
+    // Size of an atlas page (a layer of the array) in pixels
+    page_size: vec2<f32>,
+    // Multiplies the alpha of all text, below 1 to dim it while the window isn't focused
+    focus: f32,
+}
+
+@group(0) @binding(0) var<uniform> globals: Globals;
+@group(0) @binding(1) var pages: texture_2d_array<f32>;
+@group(0) @binding(2) var page_sampler: sampler;
+// Pages of color glyphs, like emoji
+@group(0) @binding(3) var color_pages: texture_2d_array<f32>;
+
+struct Instance {
+    // Top left corner and size on the target, in pixels
+    @location(0) pos: vec2<f32>,
+    @location(1) size: vec2<f32>,
+    // Top left corner of the glyph in the atlas page, in pixels
+    @location(2) uv: vec2<f32>,
+    @location(3) color: vec4<f32>,
+    // Atlas page the glyph is on
+    @location(4) layer: u32,
+    // 1 if that's a color page
+    @location(5) color_page: u32,
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) layer: u32,
+    @location(3) @interpolate(flat) color_page: u32,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex: u32, instance: Instance) -> VertexOutput {
+    // Triangle strip over the corners (0, 0), (1, 0), (0, 1), (1, 1)
+    let corner = vec2<f32>(f32(vertex & 1u), f32(vertex >> 1u));
+    let pixel = instance.pos + corner * instance.size;
+    var out: VertexOutput;
+    out.position = vec4<f32>(pixel / globals.target_size * vec2<f32>(2., -2.) + vec2<f32>(-1., 1.), 0., 1.);
+    out.uv = (instance.uv + corner * instance.size) / globals.page_size;
+    out.color = instance.color;
+    out.layer = instance.layer;
+    out.color_page = instance.color_page;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Both are sampled, as sampling has to happen in uniform control flow
+    let coverage = textureSample(pages, page_sampler, in.uv, in.layer).r;
+    let color = textureSample(color_pages, page_sampler, in.uv, in.layer);
+    if in.color_page == 1u {
+        // In their own colors, faded like the text around them
+        return vec4<f32>(color.rgb, color.a * in.color.a * globals.focus);
+    }
+    return vec4<f32>(in.color.rgb, in.color.a * coverage * globals.focus);
+}
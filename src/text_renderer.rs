@@ -0,0 +1,466 @@
+// Draws shaped text with wgpu, for use as a glyph brush in other applications:
+//
+//   let mut text = TextRenderer::new(&device, surface_format, AtlasConfig::from_limits(&device.limits(), Default::default()));
+//   text.queue(&device, &queue, &fontstack, &[TextSpan { text: "hello", color: [1.; 4] }], (10., 10.), &LayoutSettings::default());
+//   text.render(&device, &queue, &mut encoder, &view, (width, height));
+//
+// The device, queue and target are owned by the caller. Text is drawn over whatever is in the target
+
+use std::ops::Range;
+
+use crate::atlas::{AtlasConfig, AtlasEntry, GlyphAtlas, GlyphKey, PageFormat};
+use crate::font::{Face, FontStack};
+use crate::gpu_raster::GpuRasterizer;
+use crate::layout::{layout, layout_decorated, line_height, Block, Decorations, LayoutSettings, RowIndex, VirtualText, WRAP_MARKER};
+use crate::selection::line_starts;
+
+// A piece of text with one color, as linear RGBA
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextSpan<'a> {
+    pub text: &'a str,
+    pub color: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct Globals {
+    target_size: [f32; 2],
+    page_size: [f32; 2],
+    focus: f32,
+    // The shader's struct is rounded up to a multiple of 8 bytes
+    _padding: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GlyphInstance {
+    pub pos: [f32; 2],
+    pub size: [f32; 2],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+    // Layer of the atlas page the glyph is on
+    pub layer: u32,
+    // 1 for glyphs in color pages, which are drawn in their own colors
+    pub color_page: u32,
+}
+
+impl GlyphInstance {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Float32x2, 3 => Float32x4, 4 => Uint32, 5 => Uint32];
+}
+
+pub const SHADER: &str = include_str!("text.wgsl");
+
+// Lines laid out above and below the visible ones, so scrolling a little reuses the last layout
+pub const CULL_MARGIN_LINES: usize = 20;
+
+// The colors of a byte range of a document, sorted and not overlapping. The bytes they leave out
+// are drawn in the document's color
+pub type ColorsOf<'c> = &'c dyn Fn(Range<usize>) -> Vec<(Range<usize>, [f32; 4])>;
+
+// Instances for a document too long to lay out at once. Only the lines around the visible ones
+// are laid out, and their instances are kept until scrolling gets past the margin
+#[derive(Debug, Default)]
+pub struct CulledDocument {
+    built: Option<Range<usize>>,
+    // GlyphAtlas::generation the instances were made in
+    generation: u64,
+    // Positioned relative to the top left of the document
+    instances: Vec<GlyphInstance>,
+}
+
+impl CulledDocument {
+    // Must be called when the text or layout settings change
+    pub fn invalidate(&mut self) {
+        self.built = None;
+    }
+
+    // Bytes held by the kept instances
+    pub fn memory_usage(&self) -> usize {
+        self.instances.capacity() * std::mem::size_of::<GlyphInstance>()
+    }
+
+    // Frees the kept instances. They're laid out again on the next update
+    pub fn trim(&mut self) {
+        self.instances = Vec::new();
+        self.built = None;
+    }
+
+    // Lays out the lines around the `visible` rows again, unless they were laid out already with the
+    // atlas at `generation`. Returns whether it did. Changing `decorations` or `colors` needs an
+    // invalidate first
+    #[allow(clippy::too_many_arguments)]
+    fn update(&mut self, fontstack: &FontStack, text: &str, decorations: Decorations, rows: &RowIndex, color: [f32; 4], colors: ColorsOf, visible: Range<usize>, settings: &LayoutSettings, generation: u64, entry: impl FnMut(&Face, GlyphKey) -> Option<AtlasEntry>) -> bool {
+        let visible = rows.lines(visible);
+        if generation == self.generation && self.built.as_ref().is_some_and(|built| built.start <= visible.start && visible.end <= built.end) {
+            return false;
+        }
+        let starts = line_starts(text);
+        let mut lines = visible.start.saturating_sub(CULL_MARGIN_LINES).min(starts.len())..(visible.end + CULL_MARGIN_LINES).min(starts.len());
+        // From the first line of a fold, or its hidden lines would show
+        if let Some(header) = decorations.folds.iter().filter(|fold| fold.start < lines.start && lines.start < fold.end).map(|fold| fold.start).min() {
+            lines.start = header;
+        }
+        let bytes = starts.get(lines.start).map_or(text.len(), |&start| start)..starts.get(lines.end).map_or(text.len(), |&end| end);
+        let line_height = line_height(fontstack, settings);
+        let top = rows.row_of_line(lines.start) as f32 * line_height;
+        let virtual_text: Vec<VirtualText> = decorations.virtual_text.iter().filter(|vt| bytes.contains(&vt.at) || vt.at == bytes.end).map(|vt| VirtualText { at: vt.at - bytes.start, text: vt.text.clone() }).collect();
+        let folds: Vec<Range<usize>> = decorations.folds.iter().filter(|fold| fold.start >= lines.start).map(|fold| fold.start - lines.start..fold.end - lines.start).collect();
+        let blocks: Vec<Block> = decorations.blocks.iter().filter(|block| lines.contains(&block.after_line)).map(|block| Block { after_line: block.after_line - lines.start, ..*block }).collect();
+        // In `color` between the ranges `colors` has
+        let (mut filled, mut end) = (Vec::new(), 0);
+        for (range, range_color) in colors(bytes.clone()) {
+            let range = range.start - bytes.start..range.end - bytes.start;
+            if end < range.start {
+                filled.push((end..range.start, color));
+            }
+            end = range.end;
+            filled.push((range, range_color));
+        }
+        filled.push((end..bytes.len(), color));
+        let slice = &text[bytes];
+        let decorations = Decorations { folds: &folds, virtual_text: &virtual_text, blocks: &blocks };
+        self.instances = glyph_instances(fontstack, slice, decorations, &filled, (0., top), settings, entry);
+        self.built = Some(lines);
+        self.generation = generation;
+        true
+    }
+}
+
+pub struct TextRenderer {
+    pub atlas: GlyphAtlas,
+    // Experimental: rasterize glyphs in a compute shader instead of with fontdue
+    gpu_raster: Option<GpuRasterizer>,
+    // From App::text_focus
+    pub focus: f32,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    // Bound in place of the pages of a format the atlas has none of yet
+    placeholders: [wgpu::TextureView; 2],
+    globals: wgpu::Buffer,
+    // Instances queued since the last render
+    queued: Vec<GlyphInstance>,
+    instance_buffer: Option<wgpu::Buffer>,
+}
+
+impl TextRenderer {
+    pub fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat, atlas_config: AtlasConfig) -> TextRenderer {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("text shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("text bind group layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, has_dynamic_offset: false, min_binding_size: None },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("text pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("text pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<GlyphInstance>() as wgpu::BufferAddress,
+                    step_mode: wgpu::VertexStepMode::Instance,
+                    attributes: &GlyphInstance::ATTRIBUTES,
+                }],
+            },
+            primitive: wgpu::PrimitiveState { topology: wgpu::PrimitiveTopology::TriangleStrip, ..Default::default() },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("glyph sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let placeholders = [PageFormat::Coverage, PageFormat::Color].map(|format| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("glyph atlas placeholder"),
+                size: wgpu::Extent3d { width: 1, height: 1, depth_or_array_layers: 1 },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: format.texture_format(),
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            texture.create_view(&wgpu::TextureViewDescriptor { dimension: Some(wgpu::TextureViewDimension::D2Array), ..Default::default() })
+        });
+        let globals = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("text globals"),
+            size: std::mem::size_of::<Globals>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        TextRenderer {
+            atlas: GlyphAtlas::new(atlas_config),
+            gpu_raster: None,
+            focus: 1.,
+            pipeline,
+            bind_group_layout,
+            sampler,
+            placeholders,
+            globals,
+            queued: Vec::new(),
+            instance_buffer: None,
+        }
+    }
+
+    // Rasterizes glyphs with GpuRasterizer from now on
+    pub fn with_gpu_raster(mut self, device: &wgpu::Device) -> TextRenderer {
+        self.gpu_raster = Some(GpuRasterizer::new(device));
+        self
+    }
+
+    // Lays out the spans as one piece of text with its top left corner at `position`, and queues
+    // its glyphs for the next render. Glyphs missing from every face are skipped
+    pub fn queue(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, fontstack: &FontStack, spans: &[TextSpan], position: (f32, f32), settings: &LayoutSettings) {
+        let mut text = String::new();
+        let mut colors: Vec<(Range<usize>, [f32; 4])> = Vec::new();
+        for span in spans {
+            colors.push((text.len()..text.len() + span.text.len(), span.color));
+            text.push_str(span.text);
+        }
+        let (atlas, gpu_raster) = (&mut self.atlas, &self.gpu_raster);
+        let instances = glyph_instances(fontstack, &text, Decorations::default(), &colors, position, settings, |face, key| rasterize(device, queue, atlas, gpu_raster, face, key));
+        self.queued.extend(instances);
+    }
+
+    // Queues the lines of `text` shown on the `visible` rows of `rows`, drawn scrolled up by `scroll_y`
+    // from `position`. Only lines near the visible ones are ever laid out, so this stays fast for huge documents.
+    // `decorations` and `colors` are in lines and bytes of all of `text`, and `rows` must be folded
+    // like them
+    #[allow(clippy::too_many_arguments)]
+    pub fn queue_document(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, document: &mut CulledDocument, fontstack: &FontStack, text: &str, decorations: Decorations, rows: &RowIndex, color: [f32; 4], colors: ColorsOf, visible: Range<usize>, scroll_y: f32, position: (f32, f32), settings: &LayoutSettings) {
+        let generation = self.atlas.generation;
+        let (atlas, gpu_raster) = (&mut self.atlas, &self.gpu_raster);
+        document.update(fontstack, text, decorations, rows, color, colors, visible, settings, generation, |face, key| rasterize(device, queue, atlas, gpu_raster, face, key));
+        self.queued.extend(document.instances.iter().map(|&instance| {
+            let pos = [instance.pos[0] + position.0, (instance.pos[1] + position.1 - scroll_y).round()];
+            GlyphInstance { pos, ..instance }
+        }));
+    }
+
+    // Draws everything queued onto `view`, which is `target_size` pixels large, and clears the queue.
+    // The atlas pages of each format are bound as one texture array, so this is a single draw call
+    pub fn render(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, target_size: (u32, u32)) {
+        // Glyphs looked up while queueing the next frame count as used about now
+        self.atlas.begin_frame(std::time::Instant::now());
+        if self.queued.is_empty() {
+            return;
+        }
+        let [coverage, color] = [PageFormat::Coverage, PageFormat::Color].map(|format| self.atlas.array(format).map(|array| &array.view));
+        let (coverage, color) = (coverage.unwrap_or(&self.placeholders[0]), color.unwrap_or(&self.placeholders[1]));
+        let bytes: &[u8] = bytemuck::cast_slice(&self.queued);
+
+        let fits = self.instance_buffer.as_ref().is_some_and(|buffer| buffer.size() >= bytes.len() as u64);
+        if !fits {
+            self.instance_buffer = Some(device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("glyph instances"),
+                size: (bytes.len() as u64).next_power_of_two(),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }));
+        }
+        let instance_buffer = self.instance_buffer.as_ref().unwrap();
+        queue.write_buffer(instance_buffer, 0, bytes);
+        let page_size = self.atlas.config.page_size as f32;
+        queue.write_buffer(&self.globals, 0, bytemuck::bytes_of(&Globals {
+            target_size: [target_size.0 as f32, target_size.1 as f32],
+            page_size: [page_size, page_size],
+            focus: self.focus,
+            _padding: 0.,
+        }));
+
+        // Made every frame, as the array is replaced when the atlas grows
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("text bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: self.globals.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: wgpu::BindingResource::TextureView(coverage) },
+                wgpu::BindGroupEntry { binding: 2, resource: wgpu::BindingResource::Sampler(&self.sampler) },
+                wgpu::BindGroupEntry { binding: 3, resource: wgpu::BindingResource::TextureView(color) },
+            ],
+        });
+
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("text pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: true },
+            })],
+            depth_stencil_attachment: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_vertex_buffer(0, instance_buffer.slice(..));
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..4, 0..self.queued.len() as u32);
+        drop(pass);
+        self.queued.clear();
+    }
+}
+
+fn rasterize(device: &wgpu::Device, queue: &wgpu::Queue, atlas: &mut GlyphAtlas, gpu_raster: &Option<GpuRasterizer>, face: &Face, key: GlyphKey) -> Option<AtlasEntry> {
+    match gpu_raster {
+        Some(gpu_raster) => gpu_raster.get_or_insert(device, queue, atlas, face, key),
+        None => atlas.get_or_insert(device, queue, face, key),
+    }
+}
+
+const WRAP_MARKER_COLOR: [f32; 4] = [0.5, 0.5, 0.5, 1.];
+// Opacity of virtual text, relative to the text around it
+pub const VIRTUAL_TEXT_ALPHA: f32 = 0.6;
+
+// Lays out `text` with its top left corner at `position` and makes an instance for each glyph
+// `entry` finds in the atlas. `colors` are sorted byte ranges of the text with their color
+fn glyph_instances(fontstack: &FontStack, text: &str, decorations: Decorations, colors: &[(Range<usize>, [f32; 4])], position: (f32, f32), settings: &LayoutSettings, mut entry: impl FnMut(&Face, GlyphKey) -> Option<AtlasEntry>) -> Vec<GlyphInstance> {
+    let laid_out = layout_decorated(fontstack, text, settings, decorations);
+    let marker = layout(fontstack, WRAP_MARKER, &LayoutSettings { wrap_width: None, ..settings.clone() });
+    // Continuation rows of wrapped lines get the marker, dimmed, in front of their text
+    let markers = laid_out.lines.iter().filter(|line| line.continuation).flat_map(|line| marker.glyphs.iter().map(|glyph| (glyph, line.top, Some(WRAP_MARKER_COLOR))));
+    let mut instances = Vec::new();
+    for (glyph, top, color) in laid_out.glyphs.iter().map(|glyph| (glyph, 0., None)).chain(markers) {
+        let Some(shaped) = &glyph.shaped else { continue };
+        let Some(face_idx) = fontstack.faces.iter().position(|face| std::ptr::eq(face, shaped.face)) else { continue };
+        let key = GlyphKey::new(face_idx, shaped.glyph, settings.font_size * shaped.face.size_scale);
+        let Some(entry) = entry(shaped.face, key) else { continue };
+        if entry.w == 0 || entry.h == 0 {
+            continue;
+        }
+        let color = color.unwrap_or_else(|| {
+            let at = glyph.byte_range.start;
+            let [r, g, b, a] = colors
+                .get(colors.partition_point(|(range, _)| range.end <= at))
+                .filter(|(range, _)| range.contains(&at))
+                .map_or([1.; 4], |(_, color)| *color);
+            if glyph.is_virtual { [r, g, b, a * VIRTUAL_TEXT_ALPHA] } else { [r, g, b, a] }
+        });
+        let x = position.0 + glyph.x + glyph.offset.0 + entry.bearing.0;
+        let y = position.1 + top + glyph.y + glyph.offset.1 + entry.bearing.1;
+        instances.push(GlyphInstance {
+            // Snapped to whole pixels, so glyphs are sampled 1:1 from the atlas and stay sharp
+            pos: [x.round(), y.round()],
+            size: [entry.w as f32, entry.h as f32],
+            uv: [entry.x as f32, entry.y as f32],
+            color,
+            layer: entry.layer,
+            color_page: (entry.format == PageFormat::Color) as u32,
+        });
+    }
+    instances
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::validate_shader;
+    use crate::viewport::Viewport;
+
+    #[test]
+    fn shader_is_valid() {
+        validate_shader(SHADER);
+        assert_eq!(std::mem::size_of::<GlyphInstance>(), 48);
+    }
+
+    #[test]
+    fn huge_documents_only_build_visible_lines() {
+        let fontstack = FontStack::new(std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/firacode-regular.ttf"))).unwrap();
+        let settings = LayoutSettings::default();
+        let text: String = (0..1_000_000).map(|n| format!("line {n}\n")).collect();
+        let rows = RowIndex::new(&fontstack, &text, &settings);
+        let line_height = line_height(&fontstack, &settings);
+        let mut viewport = Viewport::new(settings.font_size, 600.);
+        viewport.content_height = 1_000_000. * line_height;
+        // Every glyph gets a 1x1 bitmap, so no atlas is needed
+        let fake_entry = |_: &Face, _: GlyphKey| Some(AtlasEntry { page: 0, layer: 0, x: 0, y: 0, w: 1, h: 1, bearing: (0., 0.), format: PageFormat::Coverage });
+
+        let mut document = CulledDocument::default();
+        viewport.scroll_to(500_000. * line_height);
+        let visible = viewport.visible_lines(line_height);
+        assert!(document.update(&fontstack, &text, Decorations::default(), &rows, [1.; 4], &|_| Vec::new(), visible.clone(), &settings, 0, fake_entry));
+        let max_chars_per_line = "line 999999".len();
+        assert!(document.instances.len() <= (visible.len() + 2 * CULL_MARGIN_LINES) * max_chars_per_line);
+        let first_y = document.instances.iter().map(|instance| instance.pos[1]).fold(f32::MAX, f32::min);
+        assert!((first_y - (visible.start - CULL_MARGIN_LINES) as f32 * line_height).abs() < line_height);
+
+        // Scrolling within the margin keeps the instances, scrolling past it builds them again
+        viewport.scroll_to(viewport.scroll_y + 5. * line_height);
+        assert!(!document.update(&fontstack, &text, Decorations::default(), &rows, [1.; 4], &|_| Vec::new(), viewport.visible_lines(line_height), &settings, 0, fake_entry));
+        viewport.scroll_to(viewport.scroll_y + 50. * line_height);
+        assert!(document.update(&fontstack, &text, Decorations::default(), &rows, [1.; 4], &|_| Vec::new(), viewport.visible_lines(line_height), &settings, 0, fake_entry));
+        // The atlas was cleared under it
+        assert!(document.update(&fontstack, &text, Decorations::default(), &rows, [1.; 4], &|_| Vec::new(), viewport.visible_lines(line_height), &settings, 1, fake_entry));
+    }
+
+    #[test]
+    fn wrapped_lines_take_their_rows() {
+        let fontstack = FontStack::new(std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/firacode-regular.ttf"))).unwrap();
+        let advance = layout(&fontstack, "a", &LayoutSettings::default()).lines[0].width;
+        let settings = LayoutSettings { wrap_width: Some(advance * 10.5), ..Default::default() };
+        let line = "aaaa bbbb cccccccccccc\n";
+        let text = line.repeat(CULL_MARGIN_LINES * 4);
+        let rows = RowIndex::new(&fontstack, &text, &settings);
+        assert_eq!(rows.rows(), CULL_MARGIN_LINES * 4 * 3 + 1);
+        let fake_entry = |_: &Face, _: GlyphKey| Some(AtlasEntry { page: 0, layer: 0, x: 0, y: 0, w: 1, h: 1, bearing: (0., 0.), format: PageFormat::Coverage });
+
+        // Row 150 is on line 50, so the margin starts at line 30, which is shown from row 90
+        let mut document = CulledDocument::default();
+        assert!(document.update(&fontstack, &text, Decorations::default(), &rows, [1.; 4], &|_| Vec::new(), 150..160, &settings, 0, fake_entry));
+        assert_eq!(document.built, Some(30..74));
+        let first_y = document.instances.iter().map(|instance| instance.pos[1]).fold(f32::MAX, f32::min);
+        let line_height = line_height(&fontstack, &settings);
+        assert!((first_y - 90. * line_height).abs() < line_height);
+    }
+}
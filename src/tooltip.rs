@@ -0,0 +1,167 @@
+// Tooltips for the window chrome: resting the mouse on a diagnostic sign in the gutter shows its
+// messages, on the git segment of the status line the branch and how far it is ahead or behind, on
+// a tab its full path. Shown in a hover popup after the mouse stays put for DELAY
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::diagnostics::Diagnostic;
+use crate::hover::{self, PopupKind};
+use crate::layout::Rect;
+use crate::markdown::{self, Element};
+use crate::selection::line_starts;
+use crate::tabs::{Tab, TabRect};
+
+pub const DELAY: Duration = Duration::from_millis(500);
+
+// Something with a tooltip, and what the tooltip says, as markdown
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tip {
+    pub rect: Rect,
+    pub text: String,
+}
+
+// The messages of the diagnostics on each line, for the sign at `sign_rect(line)`. Lines
+// scrolled out of view have no sign
+pub fn gutter_tips(diagnostics: &[Diagnostic], text: &str, sign_rect: impl Fn(usize) -> Option<Rect>) -> Vec<Tip> {
+    let starts = line_starts(text);
+    let mut tips: Vec<(usize, Tip)> = Vec::new();
+    for diagnostic in diagnostics {
+        let line = starts.partition_point(|&start| start <= diagnostic.range.start) - 1;
+        let message = match &diagnostic.source {
+            Some(source) => format!("{} ({source})", diagnostic.message),
+            None => diagnostic.message.clone(),
+        };
+        match tips.iter_mut().find(|(tip_line, _)| *tip_line == line) {
+            Some((_, tip)) => {
+                tip.text.push_str("\n\n");
+                tip.text.push_str(&message);
+            }
+            None => {
+                let Some(rect) = sign_rect(line) else { continue };
+                tips.push((line, Tip { rect, text: message }));
+            }
+        }
+    }
+    tips.into_iter().map(|(_, tip)| tip).collect()
+}
+
+// The full path of each tab, whose title may only be the file name
+pub fn tab_tips(tabs: &[Tab], rects: &[TabRect]) -> Vec<Tip> {
+    tabs.iter()
+        .zip(rects)
+        .filter_map(|(tab, rect)| Some(Tip { rect: rect.rect, text: format!("`{}`", tab.path.as_ref()?.display()) }))
+        .collect()
+}
+
+// Commits ahead of and behind the upstream branch, from `git rev-list --left-right --count HEAD...@{upstream}`
+pub fn parse_ahead_behind(output: &str) -> Option<(usize, usize)> {
+    let mut counts = output.split_whitespace().map(str::parse);
+    match (counts.next(), counts.next()) {
+        (Some(Ok(ahead)), Some(Ok(behind))) => Some((ahead, behind)),
+        _ => None,
+    }
+}
+
+// Runs git in `dir` for how far the branch is from its upstream. None without an upstream
+pub fn ahead_behind(dir: &Path) -> Option<(usize, usize)> {
+    let output = std::process::Command::new("git").args(["rev-list", "--left-right", "--count", "HEAD...@{upstream}"]).current_dir(dir).output().ok()?;
+    parse_ahead_behind(std::str::from_utf8(&output.stdout).ok()?)
+}
+
+// For the git segment of the status line
+pub fn branch_tip(rect: Rect, branch: &str, ahead_behind: Option<(usize, usize)>) -> Tip {
+    let text = match ahead_behind {
+        None => format!("On branch `{branch}`, without an upstream"),
+        Some((0, 0)) => format!("On branch `{branch}`, up to date with its upstream"),
+        Some((ahead, behind)) => format!("On branch `{branch}`, {ahead} ahead and {behind} behind its upstream"),
+    };
+    Tip { rect, text }
+}
+
+// Which tip the mouse is on, and whether it has been there long enough to show
+#[derive(Debug, Default)]
+pub struct Tooltips {
+    tips: Vec<Tip>,
+    // Index into tips and when the mouse got there
+    hovered: Option<(usize, Instant)>,
+}
+
+impl Tooltips {
+    // Called whenever the chrome is laid out again. Keeps the hovered tip if it's still there
+    pub fn set_tips(&mut self, tips: Vec<Tip>) {
+        let hovered = self.hovered.take().and_then(|(idx, since)| {
+            let tip = self.tips.get(idx)?;
+            Some((tips.iter().position(|new| new == tip)?, since))
+        });
+        self.tips = tips;
+        self.hovered = hovered;
+    }
+
+    pub fn mouse_moved(&mut self, x: f32, y: f32, now: Instant) {
+        let inside = |r: &Rect| x >= r.x && x < r.x + r.w && y >= r.y && y < r.y + r.h;
+        let idx = self.tips.iter().position(|tip| inside(&tip.rect));
+        if idx != self.hovered.map(|(hovered, _)| hovered) {
+            self.hovered = idx.map(|idx| (idx, now));
+        }
+    }
+
+    pub fn mouse_left(&mut self) {
+        self.hovered = None;
+    }
+
+    pub fn current(&self, now: Instant) -> Option<&Tip> {
+        let (idx, since) = self.hovered?;
+        (now.duration_since(since) >= DELAY).then(|| &self.tips[idx])
+    }
+
+    // When to wake up to show the tip being waited on
+    pub fn wake_at(&self, now: Instant) -> Option<Instant> {
+        let (_, since) = self.hovered?;
+        (now < since + DELAY).then_some(since + DELAY)
+    }
+
+    // What to draw, and where for a popup of `size` on a screen of `screen`
+    pub fn popup(&self, now: Instant, size: impl Fn(&[Element]) -> (f32, f32), screen: (f32, f32)) -> Option<(Vec<Element>, Rect)> {
+        let tip = self.current(now)?;
+        let elements = markdown::format(&tip.text, Path::new(""));
+        let rect = hover::place(PopupKind::Hover, tip.rect, size(&elements), screen);
+        Some((elements, rect))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::Severity;
+
+    #[test]
+    fn shows_after_resting() {
+        let text = "let x = 1;\nlet y = 2;\n";
+        let diagnostic = |at: usize, message: &str| Diagnostic { range: at..at + 1, severity: Severity::Warning, message: message.to_string(), source: Some("rustc".to_string()) };
+        let sign = |line: usize| Some(Rect { x: 0., y: line as f32 * 20., w: 10., h: 20. });
+        let gutter = gutter_tips(&[diagnostic(4, "unused x"), diagnostic(8, "literal"), diagnostic(15, "unused y")], text, sign);
+        assert_eq!(gutter.len(), 2);
+        assert_eq!(gutter[0].text, "unused x (rustc)\n\nliteral (rustc)");
+
+        assert_eq!(parse_ahead_behind("2\t0\n"), Some((2, 0)));
+        assert_eq!(parse_ahead_behind("fatal: no upstream"), None);
+        let status = Rect { x: 100., y: 300., w: 50., h: 20. };
+        assert_eq!(branch_tip(status, "main", Some((2, 1))).text, "On branch `main`, 2 ahead and 1 behind its upstream");
+
+        let mut tooltips = Tooltips::default();
+        tooltips.set_tips(gutter);
+        let start = Instant::now();
+        tooltips.mouse_moved(5., 25., start);
+        assert_eq!(tooltips.current(start + DELAY / 2), None);
+        assert_eq!(tooltips.wake_at(start), Some(start + DELAY));
+        assert_eq!(tooltips.current(start + DELAY).unwrap().text, "unused y (rustc)");
+        // Moving within the same sign doesn't start over
+        tooltips.mouse_moved(6., 30., start + DELAY);
+        assert!(tooltips.current(start + DELAY).is_some());
+        let (_, rect) = tooltips.popup(start + DELAY, |_| (80., 30.), (400., 400.)).unwrap();
+        assert_eq!((rect.x, rect.y), (0., 44.));
+        tooltips.mouse_moved(50., 25., start + DELAY);
+        assert_eq!(tooltips.current(start + 2 * DELAY), None);
+    }
+}
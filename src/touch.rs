@@ -0,0 +1,200 @@
+// Touch screens: a tap places the cursor, holding a finger still starts a selection, whose ends get
+// handles that can be dragged, and two fingers scroll. Turns winit's Touch events into gestures for
+// the editor to act on
+
+use std::time::{Duration, Instant};
+
+use winit::event::TouchPhase;
+
+use crate::shapes::Shape;
+
+// Holding a finger this long without moving starts a selection
+pub const LONG_PRESS: Duration = Duration::from_millis(500);
+// Fingers wobble, so moving less than this still counts as holding still
+const SLOP: f32 = 10.;
+pub const HANDLE_RADIUS: f32 = 8.;
+// Handles are small, but fingers aren't
+const GRAB_RADIUS: f32 = 24.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Handle {
+    Anchor,
+    Head,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    // Put the cursor here
+    Tap { x: f32, y: f32 },
+    // Select the word here, with handles on both ends
+    LongPress { x: f32, y: f32 },
+    // Move one end of the selection here
+    DragHandle { handle: Handle, x: f32, y: f32 },
+    // Fingers moved down by `dy`, with the phase to pass on to Viewport::scroll_pixels for momentum
+    Scroll { dy: f32, phase: TouchPhase },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Finger {
+    id: u64,
+    start: (f32, f32),
+    position: (f32, f32),
+    down_at: Instant,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum State {
+    // Nothing happening, or what's happening is ignored until every finger is lifted
+    #[default]
+    Idle,
+    // One finger down, to become a tap or a long press
+    Pending,
+    // After a long press, moving the finger moves the head
+    Selecting,
+    Dragging(Handle),
+    Scrolling,
+}
+
+#[derive(Debug, Default)]
+pub struct TouchInput {
+    fingers: Vec<Finger>,
+    state: State,
+    // Where the selection ends are drawn, at the bottom of their lines. Set by the app from a long
+    // press until the selection goes, None without one
+    pub handles: Option<[(f32, f32); 2]>,
+}
+
+impl TouchInput {
+    fn centroid_y(&self) -> f32 {
+        self.fingers.iter().map(|finger| finger.position.1).sum::<f32>() / self.fingers.len().max(1) as f32
+    }
+
+    fn grabbed_handle(&self, x: f32, y: f32) -> Option<Handle> {
+        let [anchor, head] = self.handles?;
+        // The circle hangs below the line
+        let near = |(hx, hy): (f32, f32)| (x - hx).hypot(y - hy - HANDLE_RADIUS) < GRAB_RADIUS;
+        if near(head) {
+            Some(Handle::Head)
+        } else {
+            near(anchor).then_some(Handle::Anchor)
+        }
+    }
+
+    // A winit Touch event, with its position in physical pixels
+    pub fn touch(&mut self, id: u64, phase: TouchPhase, x: f32, y: f32, now: Instant) -> Option<Gesture> {
+        match phase {
+            TouchPhase::Started => {
+                self.fingers.push(Finger { id, start: (x, y), position: (x, y), down_at: now });
+                match (self.fingers.len(), self.state) {
+                    (1, _) => {
+                        self.state = self.grabbed_handle(x, y).map_or(State::Pending, State::Dragging);
+                        None
+                    }
+                    // A second finger turns whatever the first one was doing into scrolling
+                    (2, State::Pending | State::Idle) => {
+                        self.state = State::Scrolling;
+                        Some(Gesture::Scroll { dy: 0., phase: TouchPhase::Started })
+                    }
+                    _ => None,
+                }
+            }
+            TouchPhase::Moved => {
+                let before = self.centroid_y();
+                let finger = self.fingers.iter_mut().find(|finger| finger.id == id)?;
+                finger.position = (x, y);
+                let start = finger.start;
+                match self.state {
+                    State::Pending if (x - start.0).hypot(y - start.1) > SLOP => {
+                        self.state = State::Idle;
+                        None
+                    }
+                    State::Selecting => Some(Gesture::DragHandle { handle: Handle::Head, x, y }),
+                    State::Dragging(handle) => Some(Gesture::DragHandle { handle, x, y }),
+                    State::Scrolling => Some(Gesture::Scroll { dy: self.centroid_y() - before, phase: TouchPhase::Moved }),
+                    _ => None,
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                let idx = self.fingers.iter().position(|finger| finger.id == id)?;
+                let finger = self.fingers.remove(idx);
+                let state = std::mem::take(&mut self.state);
+                match state {
+                    State::Pending if phase == TouchPhase::Ended && now.duration_since(finger.down_at) < LONG_PRESS => Some(Gesture::Tap { x, y }),
+                    State::Scrolling => Some(Gesture::Scroll { dy: 0., phase }),
+                    // The other finger of a scroll stays ignored
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    // Gestures that happen without the fingers doing anything, like a long press
+    pub fn update(&mut self, now: Instant) -> Option<Gesture> {
+        let [finger] = self.fingers[..] else { return None };
+        if self.state != State::Pending || now.duration_since(finger.down_at) < LONG_PRESS {
+            return None;
+        }
+        self.state = State::Selecting;
+        Some(Gesture::LongPress { x: finger.position.0, y: finger.position.1 })
+    }
+
+    // When a finger held down becomes a long press
+    pub fn wake_at(&self) -> Option<Instant> {
+        match (self.state, &self.fingers[..]) {
+            (State::Pending, [finger]) => Some(finger.down_at + LONG_PRESS),
+            _ => None,
+        }
+    }
+
+    // The handles as a stem down from each end of the selection and a circle to hold on to, for
+    // the shape pass
+    pub fn handle_shapes(&self, color: [f32; 4]) -> Vec<Shape> {
+        let Some(handles) = self.handles else { return Vec::new() };
+        handles
+            .into_iter()
+            .flat_map(|(x, y)| [Shape::Line { from: (x, y - HANDLE_RADIUS), to: (x, y), width: 2., color }, Shape::Circle { center: (x, y + HANDLE_RADIUS), radius: HANDLE_RADIUS, border: 0., color }])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taps_presses_and_scrolls() {
+        let mut touch = TouchInput::default();
+        let start = Instant::now();
+        assert_eq!(touch.touch(1, TouchPhase::Started, 100., 100., start), None);
+        assert_eq!(touch.touch(1, TouchPhase::Moved, 104., 103., start), None);
+        assert_eq!(touch.touch(1, TouchPhase::Ended, 104., 103., start + LONG_PRESS / 2), Some(Gesture::Tap { x: 104., y: 103. }));
+
+        touch.touch(1, TouchPhase::Started, 100., 100., start);
+        assert_eq!(touch.wake_at(), Some(start + LONG_PRESS));
+        assert_eq!(touch.update(start + LONG_PRESS / 2), None);
+        assert_eq!(touch.update(start + LONG_PRESS), Some(Gesture::LongPress { x: 100., y: 100. }));
+        assert_eq!(touch.touch(1, TouchPhase::Moved, 150., 100., start + LONG_PRESS), Some(Gesture::DragHandle { handle: Handle::Head, x: 150., y: 100. }));
+        assert_eq!(touch.touch(1, TouchPhase::Ended, 150., 100., start + LONG_PRESS), None);
+
+        // Grabbing the anchor's circle, just below its line
+        touch.handles = Some([(100., 120.), (150., 120.)]);
+        assert_eq!(touch.handle_shapes([1.; 4]).len(), 4);
+        touch.touch(1, TouchPhase::Started, 98., 130., start);
+        assert_eq!(touch.touch(1, TouchPhase::Moved, 80., 130., start), Some(Gesture::DragHandle { handle: Handle::Anchor, x: 80., y: 130. }));
+        touch.touch(1, TouchPhase::Ended, 80., 130., start);
+
+        // Moving a finger too far is neither a tap nor a long press
+        touch.touch(1, TouchPhase::Started, 300., 300., start);
+        touch.touch(1, TouchPhase::Moved, 300., 330., start);
+        assert_eq!(touch.update(start + LONG_PRESS), None);
+        assert_eq!(touch.touch(1, TouchPhase::Ended, 300., 330., start), None);
+
+        touch.touch(1, TouchPhase::Started, 300., 300., start);
+        assert_eq!(touch.touch(2, TouchPhase::Started, 340., 300., start), Some(Gesture::Scroll { dy: 0., phase: TouchPhase::Started }));
+        assert_eq!(touch.touch(2, TouchPhase::Moved, 340., 320., start), Some(Gesture::Scroll { dy: 10., phase: TouchPhase::Moved }));
+        assert_eq!(touch.touch(1, TouchPhase::Ended, 300., 300., start), Some(Gesture::Scroll { dy: 0., phase: TouchPhase::Ended }));
+        assert_eq!(touch.touch(2, TouchPhase::Moved, 340., 400., start), None);
+        assert_eq!(touch.touch(2, TouchPhase::Ended, 340., 400., start), None);
+        assert_eq!(touch.update(start + LONG_PRESS), None);
+    }
+}
@@ -0,0 +1,197 @@
+// Undo history of a buffer. Each step keeps the edits it made along with the selections from before
+// and after them, so undoing puts the cursors back where they were when the edit was made instead
+// of wherever the text change leaves them, and redoing puts them where the edit left them
+
+use std::ops::Range;
+
+use crate::selection::Selection;
+
+// One replacement, at a byte offset of the text as it was when it was made
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta {
+    pub at: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Delta {
+    // Replacing `range` of `text` with `inserted`, before it's done
+    pub fn replace(text: &str, range: Range<usize>, inserted: &str) -> Delta {
+        Delta { at: range.start, removed: text[range].to_string(), inserted: inserted.to_string() }
+    }
+
+    pub fn apply(&self, text: &mut String) {
+        text.replace_range(self.at..self.at + self.removed.len(), &self.inserted);
+    }
+
+    pub fn revert(&self, text: &mut String) {
+        text.replace_range(self.at..self.at + self.inserted.len(), &self.removed);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    // In the order they were applied
+    pub deltas: Vec<Delta>,
+    pub before: Vec<Selection>,
+    pub after: Vec<Selection>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    undo: Vec<Step>,
+    redo: Vec<Step>,
+    // How many steps could be undone when the text was last written, or None once that text can't
+    // be got back to by undoing and redoing
+    saved: Option<usize>,
+}
+
+impl Default for History {
+    fn default() -> History {
+        History { undo: Vec::new(), redo: Vec::new(), saved: Some(0) }
+    }
+}
+
+impl History {
+    // Called after making the edits. Forgets what could be redone
+    pub fn record(&mut self, deltas: Vec<Delta>, before: Vec<Selection>, after: Vec<Selection>) {
+        if deltas.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        if self.saved.is_some_and(|saved| saved > self.undo.len()) {
+            self.saved = None;
+        }
+        self.undo.push(Step { deltas, before, after });
+    }
+
+    // Reverts the last step, returning the selections from before it
+    pub fn undo(&mut self, text: &mut String) -> Option<Vec<Selection>> {
+        let step = self.undo.pop()?;
+        step.deltas.iter().rev().for_each(|delta| delta.revert(text));
+        let selections = step.before.clone();
+        self.redo.push(step);
+        Some(selections)
+    }
+
+    // Makes the last undone step again, returning the selections from after it
+    pub fn redo(&mut self, text: &mut String) -> Option<Vec<Selection>> {
+        let step = self.redo.pop()?;
+        step.deltas.iter().for_each(|delta| delta.apply(text));
+        let selections = step.after.clone();
+        self.undo.push(step);
+        Some(selections)
+    }
+
+    // The last step made or redone, which undo reverts next
+    pub fn done(&self) -> Option<&Step> {
+        self.undo.last()
+    }
+
+    // The last step undone, which redo makes again next
+    pub fn undone(&self) -> Option<&Step> {
+        self.redo.last()
+    }
+
+    // Called when the text is written, which undoing or redoing back to is unmodified
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+    }
+
+    // Whether the text is what was last written
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.undo.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.undo.is_empty()
+    }
+
+    // Forgets the oldest steps until at most `limit` are left, for maintenance, freeing the text
+    // they kept. They can't be undone anymore. Returns how many steps were dropped
+    pub fn compact(&mut self, limit: usize) -> usize {
+        let excess = self.undo.len().saturating_sub(limit);
+        self.undo.drain(..excess);
+        self.saved = self.saved.and_then(|saved| saved.checked_sub(excess));
+        excess
+    }
+
+    // Bytes of text kept, for memory accounting
+    pub fn memory_usage(&self) -> usize {
+        self.undo.iter().chain(&self.redo).flat_map(|step| &step.deltas).map(|delta| delta.removed.capacity() + delta.inserted.capacity() + std::mem::size_of::<Delta>()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selections_come_back_with_the_text() {
+        let mut text = "one two three".to_string();
+        let mut history = History::default();
+
+        // Two cursors deleting a word each
+        let before = vec![Selection { anchor: 4, head: 7, goal: None }, Selection { anchor: 8, head: 13, goal: None }];
+        let deltas = vec![Delta::replace(&text, 8..13, ""), Delta::replace(&text, 4..7, "")];
+        deltas.iter().for_each(|delta| delta.apply(&mut text));
+        let after = vec![Selection::cursor(4), Selection::cursor(5)];
+        history.record(deltas, before.clone(), after.clone());
+        assert_eq!(text, "one  ");
+
+        assert_eq!(history.undo(&mut text), Some(before));
+        assert_eq!(text, "one two three");
+        assert_eq!(history.redo(&mut text), Some(after));
+        assert_eq!(text, "one  ");
+        assert_eq!(history.redo(&mut text), None);
+
+        let delta = Delta::replace(&text, 0..3, "1");
+        delta.apply(&mut text);
+        history.record(vec![delta], vec![Selection::cursor(0)], vec![Selection::cursor(1)]);
+        history.undo(&mut text);
+        // A new edit after undoing forgets what could be redone
+        history.record(vec![Delta::replace(&text, 0..0, "x")], vec![Selection::cursor(0)], vec![Selection::cursor(1)]);
+        assert_eq!(history.len(), 2);
+
+        let kept = history.memory_usage();
+        assert_eq!(history.compact(1), 1);
+        assert!(history.memory_usage() < kept);
+        let mut text = "xone  ".to_string();
+        assert_eq!(history.undo(&mut text).unwrap()[0], Selection::cursor(0));
+        assert_eq!(text, "one  ");
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn undoing_back_to_the_saved_text() {
+        let mut text = "a".to_string();
+        let mut history = History::default();
+        let type_char = |text: &mut String, history: &mut History| {
+            let delta = Delta::replace(text, text.len()..text.len(), "b");
+            delta.apply(text);
+            history.record(vec![delta], vec![], vec![]);
+        };
+        assert!(history.is_saved());
+        type_char(&mut text, &mut history);
+        history.mark_saved();
+        type_char(&mut text, &mut history);
+        assert!(!history.is_saved());
+        history.undo(&mut text);
+        assert!(history.is_saved());
+        history.undo(&mut text);
+        assert!(!history.is_saved());
+        history.redo(&mut text);
+        assert!(history.is_saved());
+
+        // Undoing past the save and editing from there loses the saved text for good
+        history.undo(&mut text);
+        type_char(&mut text, &mut history);
+        history.undo(&mut text);
+        history.redo(&mut text);
+        assert!(!history.is_saved());
+    }
+}
@@ -0,0 +1,202 @@
+use std::time::{Duration, Instant};
+
+use winit::event::TouchPhase;
+
+// How quickly animated scrolling approaches its target, per second
+const SCROLL_APPROACH_RATE: f32 = 20.;
+// How quickly momentum scrolling slows down, per second
+const MOMENTUM_FRICTION: f32 = 4.;
+// Below this speed in pixels per second, momentum stops
+const MIN_MOMENTUM: f32 = 20.;
+// Pixel scroll events further apart than this don't count towards the velocity
+const VELOCITY_WINDOW: Duration = Duration::from_millis(100);
+
+pub const MIN_FONT_SIZE: f32 = 4.;
+pub const MAX_FONT_SIZE: f32 = 200.;
+
+// The visible part of a buffer: scroll position, with animated wheel scrolling and touchpad momentum, and zoom
+#[derive(Debug)]
+pub struct Viewport {
+    // Pixels scrolled from the top
+    pub scroll_y: f32,
+    // Where animated scrolling is heading
+    target_y: f32,
+    // Pixels per second, while scrolling with momentum
+    velocity: f32,
+    last_pixel_scroll: Option<Instant>,
+    pub font_size: f32,
+    // Where the visible area starts in the window, below the tab bar
+    pub top: f32,
+    // Height of the visible area and of everything that can be scrolled through
+    pub height: f32,
+    pub content_height: f32,
+    // Keep scrolling with decaying speed after letting go of the touchpad
+    pub kinetic: bool,
+    // Off to jump straight to where the wheel scrolls to, for reduced motion
+    pub animate: bool,
+    // Zooming out stops here, for people who can't read smaller text
+    pub min_font_size: f32,
+}
+
+impl Viewport {
+    pub fn new(font_size: f32, height: f32) -> Viewport {
+        Viewport {
+            scroll_y: 0.,
+            target_y: 0.,
+            velocity: 0.,
+            last_pixel_scroll: None,
+            font_size,
+            top: 0.,
+            height,
+            content_height: 0.,
+            kinetic: true,
+            animate: true,
+            min_font_size: MIN_FONT_SIZE,
+        }
+    }
+
+    pub fn max_scroll(&self) -> f32 {
+        (self.content_height - self.height).max(0.)
+    }
+
+    // Scroll wheel: animates towards the new position
+    pub fn scroll_lines(&mut self, lines: f32, line_height: f32) {
+        self.velocity = 0.;
+        self.target_y = (self.target_y + lines * line_height).clamp(0., self.max_scroll());
+        if !self.animate {
+            self.scroll_y = self.target_y;
+        }
+    }
+
+    // Touchpad: follows the fingers exactly, and keeps going with the fingers' speed once they let go
+    pub fn scroll_pixels(&mut self, dy: f32, phase: TouchPhase, now: Instant) {
+        match phase {
+            TouchPhase::Started => {
+                self.velocity = 0.;
+                self.last_pixel_scroll = Some(now);
+            }
+            TouchPhase::Moved => {
+                if let Some(last) = self.last_pixel_scroll.replace(now) {
+                    let dt = now.duration_since(last);
+                    if !dt.is_zero() && dt < VELOCITY_WINDOW {
+                        // Smooth a bit, touchpad events are noisy
+                        self.velocity = self.velocity * 0.5 + dy / dt.as_secs_f32() * 0.5;
+                    }
+                }
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                let stale = self.last_pixel_scroll.is_none_or(|last| now.duration_since(last) >= VELOCITY_WINDOW);
+                if stale || phase == TouchPhase::Cancelled || !self.kinetic {
+                    self.velocity = 0.;
+                }
+                self.last_pixel_scroll = None;
+            }
+        }
+        self.scroll_y = (self.scroll_y + dy).clamp(0., self.max_scroll());
+        self.target_y = self.scroll_y;
+    }
+
+    // Lines at least partly on screen, for lines `line_height` pixels high
+    pub fn visible_lines(&self, line_height: f32) -> std::ops::Range<usize> {
+        let first = (self.scroll_y / line_height).floor() as usize;
+        let end = ((self.scroll_y + self.height) / line_height).ceil() as usize;
+        first..end
+    }
+
+    // Jumps without animating, like when dragging the scrollbar
+    pub fn scroll_to(&mut self, y: f32) {
+        self.velocity = 0.;
+        self.scroll_y = y.clamp(0., self.max_scroll());
+        self.target_y = self.scroll_y;
+    }
+
+    // Pinch: `delta` is the relative change in size, as given by winit's TouchpadMagnify
+    pub fn magnify(&mut self, delta: f32) {
+        self.font_size = (self.font_size * (1. + delta)).clamp(self.min_font_size, MAX_FONT_SIZE);
+    }
+
+    // Whether tick should keep getting called
+    pub fn is_animating(&self) -> bool {
+        self.scroll_y != self.target_y || (self.velocity != 0. && self.last_pixel_scroll.is_none())
+    }
+
+    pub fn tick(&mut self, dt: Duration) {
+        let dt = dt.as_secs_f32();
+        if self.last_pixel_scroll.is_none() && self.velocity != 0. {
+            self.velocity *= (-MOMENTUM_FRICTION * dt).exp();
+            if self.velocity.abs() < MIN_MOMENTUM {
+                self.velocity = 0.;
+            }
+            self.scroll_y = (self.scroll_y + self.velocity * dt).clamp(0., self.max_scroll());
+            if self.scroll_y == 0. || self.scroll_y == self.max_scroll() {
+                self.velocity = 0.;
+            }
+            self.target_y = self.scroll_y;
+        } else if self.scroll_y != self.target_y {
+            let t = 1. - (-SCROLL_APPROACH_RATE * dt).exp();
+            self.scroll_y += (self.target_y - self.scroll_y) * t;
+            if (self.target_y - self.scroll_y).abs() < 0.5 {
+                self.scroll_y = self.target_y;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport() -> Viewport {
+        let mut viewport = Viewport::new(16., 100.);
+        viewport.content_height = 1000.;
+        viewport
+    }
+
+    #[test]
+    fn wheel_scroll_animates_to_target() {
+        let mut viewport = viewport();
+        viewport.scroll_lines(3., 20.);
+        assert_eq!(viewport.scroll_y, 0.);
+        let mut ticks = 0;
+        while viewport.is_animating() {
+            viewport.tick(Duration::from_millis(16));
+            ticks += 1;
+        }
+        assert_eq!(viewport.scroll_y, 60.);
+        assert!(ticks > 1 && ticks < 100);
+
+        viewport.scroll_lines(-100., 20.);
+        assert!(viewport.is_animating());
+
+        viewport.animate = false;
+        viewport.scroll_lines(2., 20.);
+        assert_eq!(viewport.scroll_y, 40.);
+        assert!(!viewport.is_animating());
+    }
+
+    #[test]
+    fn touchpad_momentum() {
+        let mut viewport = viewport();
+        let start = Instant::now();
+        viewport.scroll_pixels(0., TouchPhase::Started, start);
+        for i in 1..=5 {
+            viewport.scroll_pixels(10., TouchPhase::Moved, start + Duration::from_millis(10 * i));
+        }
+        assert_eq!(viewport.scroll_y, 50.);
+        viewport.scroll_pixels(0., TouchPhase::Ended, start + Duration::from_millis(60));
+        assert!(viewport.is_animating());
+        while viewport.is_animating() {
+            viewport.tick(Duration::from_millis(16));
+        }
+        assert!(viewport.scroll_y > 100.);
+    }
+
+    #[test]
+    fn zoom_is_clamped() {
+        let mut viewport = viewport();
+        viewport.magnify(0.5);
+        assert_eq!(viewport.font_size, 24.);
+        viewport.magnify(-0.99);
+        assert_eq!(viewport.font_size, MIN_FONT_SIZE);
+    }
+}
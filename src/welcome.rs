@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::commands::{self, Registry};
+use crate::picker::Picker;
+
+// Shown on the start screen
+const MAX_RECENT: usize = 10;
+// Remembered, for ranking in the finder
+const MAX_TRACKED: usize = 1000;
+// Frecency boosts in the finder stop here, about as much as ten more matching characters, so a
+// better match still wins over a file that's opened all the time
+const MAX_BOOST: i64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Visits {
+    pub count: u32,
+    // Seconds since the Unix epoch
+    pub last: u64,
+}
+
+// Recently opened files, newest first. Stored one per line as `count last path`, and plain paths
+// from before counts were kept still load
+#[derive(Debug, Default)]
+pub struct RecentFiles {
+    pub path: Option<PathBuf>,
+    pub files: Vec<PathBuf>,
+    pub visits: HashMap<PathBuf, Visits>,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+impl RecentFiles {
+    pub fn default_path() -> Option<PathBuf> {
+        let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(std::env::var_os("HOME")?).join(".local/state"),
+        };
+        Some(state_dir.join("rakoune").join("recent"))
+    }
+
+    pub fn load(path: PathBuf) -> RecentFiles {
+        let text = std::fs::read_to_string(&path).unwrap_or_default();
+        let mut recent = RecentFiles { path: Some(path), ..Default::default() };
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let mut parts = line.splitn(3, ' ');
+            let (file, visits) = match (parts.next().map(str::parse), parts.next().map(str::parse), parts.next()) {
+                (Some(Ok(count)), Some(Ok(last)), Some(file)) => (PathBuf::from(file), Visits { count, last }),
+                _ => (PathBuf::from(line), Visits { count: 1, last: 0 }),
+            };
+            recent.visits.insert(file.clone(), visits);
+            recent.files.push(file);
+        }
+        recent
+    }
+
+    // Moves the file to the front and counts the visit
+    pub fn opened(&mut self, file: &Path) -> std::io::Result<()> {
+        self.opened_at(file, now())
+    }
+
+    pub fn opened_at(&mut self, file: &Path, now: u64) -> std::io::Result<()> {
+        self.files.retain(|f| f != file);
+        self.files.insert(0, file.to_path_buf());
+        for forgotten in self.files.drain(self.files.len().min(MAX_TRACKED)..) {
+            self.visits.remove(&forgotten);
+        }
+        let visits = self.visits.entry(file.to_path_buf()).or_insert(Visits { count: 0, last: now });
+        visits.count += 1;
+        visits.last = now;
+        let Some(path) = &self.path else { return Ok(()) };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let lines: Vec<String> = self.files.iter().map(|f| {
+            let visits = self.visits[f];
+            format!("{} {} {}", visits.count, visits.last, f.display())
+        }).collect();
+        std::fs::write(path, lines.join("\n") + "\n")
+    }
+
+    // The newest ones, for the start screen
+    pub fn latest(&self) -> &[PathBuf] {
+        &self.files[..self.files.len().min(MAX_RECENT)]
+    }
+
+    // How often the file is opened, counting recent visits for more
+    pub fn frecency(&self, file: &Path, now: u64) -> i64 {
+        let Some(visits) = self.visits.get(file) else { return 0 };
+        let age = now.saturating_sub(visits.last);
+        let weight = match age {
+            age if age < 4 * 60 * 60 => 100,
+            age if age < 24 * 60 * 60 => 80,
+            age if age < 7 * 24 * 60 * 60 => 60,
+            age if age < 30 * 24 * 60 * 60 => 40,
+            _ => 20,
+        };
+        visits.count as i64 * weight
+    }
+
+    // Every remembered file that still exists, newest first
+    pub fn picker(&self, original: PathBuf) -> Picker<PathBuf> {
+        let items = self.files.iter().filter(|f| f.exists()).map(|f| (f.display().to_string(), f.clone())).collect();
+        Picker::new(items, original)
+    }
+
+    // The finder over `files`, labelled relative to `root`. Files opened often and lately rank
+    // higher, and come first before anything is typed
+    pub fn finder(&self, files: &[PathBuf], root: &Path, original: PathBuf) -> Picker<PathBuf> {
+        let now = now();
+        let items = files.iter().map(|f| (f.strip_prefix(root).unwrap_or(f).display().to_string(), f.clone())).collect();
+        Picker::new(items, original).with_boost(|f| self.frecency(f, now).min(MAX_BOOST))
+    }
+}
+
+// What :recent and :find need from the editor
+pub trait RecentHost {
+    fn recent_files(&self) -> &RecentFiles;
+    fn current_path(&self) -> Option<PathBuf>;
+    // Every file in the project and its root, if there is a project
+    fn project_files(&mut self) -> Option<(Vec<PathBuf>, PathBuf)>;
+    fn open_picker(&mut self, picker: Picker<PathBuf>);
+}
+
+fn recent_command<Ctx: RecentHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    let original = ctx.current_path().unwrap_or_default();
+    let picker = ctx.recent_files().picker(original);
+    ctx.open_picker(picker);
+    Ok(())
+}
+
+fn find_command<Ctx: RecentHost>(ctx: &mut Ctx, _args: &[&str]) -> Result<(), String> {
+    let (files, root) = ctx.project_files().ok_or("Not in a project")?;
+    let original = ctx.current_path().unwrap_or_default();
+    let picker = ctx.recent_files().finder(&files, &root, original);
+    ctx.open_picker(picker);
+    Ok(())
+}
+
+pub fn register<Ctx: RecentHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("recent", recent_command::<Ctx>);
+    registry.add_builtin("find", find_command::<Ctx>);
+}
+
+// What a key on the start screen does
+#[derive(Debug, PartialEq, Eq)]
+pub enum StartKey {
+    Moved,
+    // Opens a file or a new buffer, which dismisses the screen
+    Run(String),
+    // Not the start screen's, so it does what it does in the buffer
+    Other,
+}
+
+// What's shown when rakoune starts without a file. Goes away as soon as a file is opened
+#[derive(Debug)]
+pub struct StartScreen {
+    pub recent: Vec<PathBuf>,
+    // Key and command line
+    pub cheatsheet: Vec<(String, String)>,
+    pub selected: usize,
+}
+
+impl StartScreen {
+    pub fn new<Ctx>(recent: &RecentFiles, registry: &Registry<Ctx>) -> StartScreen {
+        StartScreen {
+            // Files that were deleted since aren't worth showing
+            recent: recent.latest().iter().filter(|f| f.exists()).cloned().collect(),
+            cheatsheet: registry.bindings().into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            selected: 0,
+        }
+    }
+
+    pub fn move_selection(&mut self, by: isize) {
+        if !self.recent.is_empty() {
+            self.selected = self.selected.saturating_add_signed(by).min(self.recent.len() - 1);
+        }
+    }
+
+    // The file to open when pressing enter, or for a digit key
+    pub fn pick(&self, digit: Option<usize>) -> Option<&Path> {
+        self.recent.get(digit.unwrap_or(self.selected)).map(PathBuf::as_path)
+    }
+
+    // j and k or the arrows move through the recent files, Enter or a digit opens one and n
+    // opens a new buffer, by the command line to run
+    pub fn key(&mut self, key: &str) -> StartKey {
+        let digit = key.parse::<usize>().ok().filter(|_| key.len() == 1);
+        let open = |file: &Path| StartKey::Run(format!("edit {}", commands::quote(&file.display().to_string())));
+        match key {
+            "j" | "<Down>" => self.move_selection(1),
+            "k" | "<Up>" => self.move_selection(-1),
+            "n" => return StartKey::Run("new".to_string()),
+            "<Enter>" => return self.pick(None).map_or(StartKey::Other, open),
+            _ if digit.is_some() => return self.pick(digit).map_or(StartKey::Other, open),
+            _ => return StartKey::Other,
+        }
+        StartKey::Moved
+    }
+
+    // Lines of text to draw under the logo
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec!["n  New file".to_string(), String::new()];
+        if !self.recent.is_empty() {
+            lines.push("Recent files".to_string());
+            for (idx, file) in self.recent.iter().enumerate() {
+                let marker = if idx == self.selected { '>' } else { ' ' };
+                lines.push(format!("{marker} {idx}  {}", file.display()));
+            }
+            lines.push(String::new());
+        }
+        if !self.cheatsheet.is_empty() {
+            lines.push("Keys".to_string());
+            let width = self.cheatsheet.iter().map(|(key, _)| key.chars().count()).max().unwrap_or(0);
+            for (key, command_line) in &self.cheatsheet {
+                lines.push(format!("  {key:width$}  {command_line}"));
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_files_and_lines() {
+        let dir = std::env::temp_dir().join(format!("rakoune-welcome-{}", std::process::id()));
+        let mut recent = RecentFiles::load(dir.join("recent"));
+        let cargo_toml = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let main_rs = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/main.rs");
+        recent.opened(&cargo_toml).unwrap();
+        recent.opened(&dir.join("deleted")).unwrap();
+        recent.opened(&main_rs).unwrap();
+        recent.opened(&cargo_toml).unwrap();
+        let recent = RecentFiles::load(dir.join("recent"));
+        assert_eq!(recent.files.len(), 3);
+        assert_eq!(recent.files[0], cargo_toml);
+
+        let mut registry = Registry::<()>::default();
+        registry.bind("<C-s>", "w");
+        registry.bind("<C-p>", "find");
+        let mut screen = StartScreen::new(&recent, &registry);
+        screen.move_selection(5);
+        assert_eq!(screen.pick(None), Some(main_rs.as_path()));
+        assert_eq!(screen.lines()[3], format!("  0  {}", cargo_toml.display()));
+        assert_eq!(screen.lines().last().unwrap(), "  <C-s>  w");
+        assert_eq!(screen.key("k"), StartKey::Moved);
+        assert_eq!(screen.key("<Enter>"), StartKey::Run(format!("edit {}", commands::quote(&cargo_toml.display().to_string()))));
+        assert_eq!(screen.key("1"), StartKey::Run(format!("edit {}", commands::quote(&main_rs.display().to_string()))));
+        assert_eq!((screen.key("7"), screen.key("n"), screen.key("x")), (StartKey::Other, StartKey::Run("new".to_string()), StartKey::Other));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn frecency_ranks_the_finder() {
+        let dir = std::env::temp_dir().join(format!("rakoune-frecency-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // From before visits were counted
+        std::fs::write(dir.join("recent"), "/p/src/old.rs\n").unwrap();
+        let mut recent = RecentFiles::load(dir.join("recent"));
+        let day = 24 * 60 * 60;
+        let (main, lib) = (PathBuf::from("/p/src/main.rs"), PathBuf::from("/p/src/lib.rs"));
+        for _ in 0..3 {
+            recent.opened_at(&lib, 100 * day).unwrap();
+        }
+        recent.opened_at(&main, 130 * day).unwrap();
+        let recent = RecentFiles::load(dir.join("recent"));
+        assert_eq!(recent.visits[&lib], Visits { count: 3, last: 100 * day });
+        assert_eq!(recent.visits[Path::new("/p/src/old.rs")], Visits { count: 1, last: 0 });
+        assert_eq!(recent.frecency(&main, 130 * day), 100);
+        assert_eq!(recent.frecency(&lib, 130 * day), 60);
+        assert_eq!(recent.frecency(&lib, 100 * day + 3600), 300);
+
+        let files = [PathBuf::from("/p/build.rs"), lib.clone(), main.clone()];
+        let items: Vec<(String, PathBuf)> = files.iter().map(|f| (f.display().to_string(), f.clone())).collect();
+        let mut picker = Picker::new(items, PathBuf::new()).with_boost(|f| recent.frecency(f, 130 * day));
+        assert_eq!(picker.label(0), "/p/src/main.rs");
+        // Matching as well, lib.rs being shorter loses to main.rs being opened more lately
+        picker.set_filter("src");
+        assert_eq!(picker.label(0), "/p/src/main.rs");
+        // A much better match still wins
+        picker.set_filter("b");
+        assert_eq!(picker.label(0), "/p/build.rs");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
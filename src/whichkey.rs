@@ -0,0 +1,86 @@
+use std::time::{Duration, Instant};
+
+use crate::commands::Registry;
+
+// How long after a prefix key the popup waits before showing, so fast typists never see it
+pub const DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyResult {
+    // Part of a longer binding, waiting for the next key
+    Pending,
+    Run(String),
+    // Not bound, with the keys typed so far
+    Unbound(String),
+}
+
+// Collects multi-key bindings, and after a pause shows which keys can come next
+#[derive(Debug, Default)]
+pub struct WhichKey {
+    pub prefix: String,
+    since: Option<Instant>,
+}
+
+impl WhichKey {
+    // `key` is a character or a key in angle brackets, like `<C-x>`
+    pub fn key<Ctx>(&mut self, registry: &Registry<Ctx>, key: &str, now: Instant) -> KeyResult {
+        self.prefix.push_str(key);
+        if let Some(command_line) = registry.binding(&self.prefix) {
+            let command_line = command_line.to_string();
+            self.cancel();
+            return KeyResult::Run(command_line);
+        }
+        if registry.continuations(&self.prefix).is_empty() {
+            let keys = std::mem::take(&mut self.prefix);
+            self.cancel();
+            return KeyResult::Unbound(keys);
+        }
+        self.since = Some(now);
+        KeyResult::Pending
+    }
+
+    pub fn cancel(&mut self) {
+        self.prefix.clear();
+        self.since = None;
+    }
+
+    // When the popup should appear, to wake up for
+    pub fn show_at(&self) -> Option<Instant> {
+        Some(self.since? + DELAY)
+    }
+
+    // Lines for the popup, once it's been shown long enough
+    pub fn popup<Ctx>(&self, registry: &Registry<Ctx>, now: Instant) -> Option<Vec<String>> {
+        if now < self.show_at()? {
+            return None;
+        }
+        Some(registry
+            .continuations(&self.prefix)
+            .into_iter()
+            .map(|(key, shown)| format!("{key}  {shown}"))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_then_shows_continuations() {
+        let mut registry = Registry::<()>::default();
+        registry.bind("gt", "tabnext");
+        registry.bind("gT", "tabprev");
+        let mut which = WhichKey::default();
+        let start = Instant::now();
+
+        assert_eq!(which.key(&registry, "g", start), KeyResult::Pending);
+        assert_eq!(which.popup(&registry, start), None);
+        assert_eq!(which.popup(&registry, start + DELAY).unwrap(), vec!["T  tabprev", "t  tabnext"]);
+        assert_eq!(which.key(&registry, "t", start), KeyResult::Run("tabnext".to_string()));
+        assert_eq!(which.popup(&registry, start + DELAY), None);
+
+        which.key(&registry, "g", start);
+        assert_eq!(which.key(&registry, "x", start), KeyResult::Unbound("gx".to_string()));
+    }
+}
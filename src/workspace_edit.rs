@@ -0,0 +1,210 @@
+// Edits across buffers sent by language servers, for rename, code actions and formatting. Positions
+// are lines and UTF-16 columns, which are converted to bytes of the buffer the edits are for
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::selection::line_starts;
+use crate::semantic::utf16_to_byte;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum Error {
+    #[error("{0}:{1}:{2} is outside of the buffer")]
+    OutOfRange(PathBuf, u32, u32),
+    #[error("Edits in {0} overlap")]
+    Overlapping(PathBuf),
+    #[error("Couldn't open {0}: {1}")]
+    Open(PathBuf, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: u32,
+    // In UTF-16 code units
+    pub character: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub start: Position,
+    pub end: Position,
+    pub new_text: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct WorkspaceEdit {
+    pub changes: Vec<(PathBuf, Vec<TextEdit>)>,
+}
+
+pub(crate) fn byte_offset(text: &str, starts: &[usize], position: Position) -> Option<usize> {
+    let &line_start = starts.get(position.line as usize)?;
+    let line = text[line_start..].split('\n').next().unwrap();
+    Some(line_start + utf16_to_byte(line, position.character as usize)?)
+}
+
+// The edits in bytes of `text`, sorted and checked not to overlap. `path` is for errors
+pub fn resolve(path: &Path, text: &str, edits: &[TextEdit]) -> Result<Vec<(Range<usize>, String)>, Error> {
+    let starts = line_starts(text);
+    let offset = |position: Position| byte_offset(text, &starts, position).ok_or(Error::OutOfRange(path.to_owned(), position.line, position.character));
+    let mut resolved = edits.iter().map(|edit| Ok((offset(edit.start)?..offset(edit.end)?, edit.new_text.clone()))).collect::<Result<Vec<_>, Error>>()?;
+    // Several inserts at the same place go in the order they were sent
+    resolved.sort_by_key(|(range, _)| range.start);
+    if resolved.windows(2).any(|pair| pair[0].0.end > pair[1].0.start) || resolved.iter().any(|(range, _)| range.start > range.end) {
+        return Err(Error::Overlapping(path.to_owned()));
+    }
+    Ok(resolved)
+}
+
+// Applies resolved edits to `text`. Returns each as the byte range it replaced and the length of
+// what replaced it, in the order they were applied, for Marks::apply_edit and the like
+pub fn apply(text: &mut String, resolved: &[(Range<usize>, String)]) -> Vec<(Range<usize>, usize)> {
+    // Back to front, so the ranges of the ones left stay right
+    resolved
+        .iter()
+        .rev()
+        .map(|(range, new_text)| {
+            text.replace_range(range.clone(), new_text);
+            (range.clone(), new_text.len())
+        })
+        .collect()
+}
+
+// What applying edits needs from the editor
+pub trait EditHost {
+    // Text of the buffer for `path`, opening it if it isn't open yet
+    fn buffer_text(&mut self, path: &Path) -> Result<String, String>;
+    // Replaces the text of the buffer for `path`. `edits` are what changed, as returned by apply,
+    // for moving cursors, marks and the bracket tree along
+    fn set_buffer_text(&mut self, path: &Path, text: String, edits: &[(Range<usize>, usize)]);
+}
+
+// What a buffer looked like before an edit, to put it back with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferUndo {
+    pub path: PathBuf,
+    pub original: String,
+    pub edited: String,
+}
+
+// Undoes every buffer a workspace edit changed at once
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UndoGroup {
+    pub buffers: Vec<BufferUndo>,
+}
+
+impl UndoGroup {
+    // Buffers changed again since are left alone rather than throwing those changes away. Returns
+    // the ones that were
+    pub fn undo(&self, host: &mut impl EditHost) -> Vec<PathBuf> {
+        let mut skipped = Vec::new();
+        for buffer in &self.buffers {
+            match host.buffer_text(&buffer.path) {
+                Ok(text) if text == buffer.edited => host.set_buffer_text(&buffer.path, buffer.original.clone(), &[(0..text.len(), buffer.original.len())]),
+                _ => skipped.push(buffer.path.clone()),
+            }
+        }
+        skipped
+    }
+}
+
+// Applies `edit` to every buffer it touches. Nothing is changed unless every buffer's edits are
+// valid
+pub fn apply_workspace_edit(host: &mut impl EditHost, edit: &WorkspaceEdit) -> Result<UndoGroup, Error> {
+    let mut planned = Vec::new();
+    for (path, edits) in &edit.changes {
+        let original = host.buffer_text(path).map_err(|e| Error::Open(path.clone(), e))?;
+        let resolved = resolve(path, &original, edits)?;
+        planned.push((path, original, resolved));
+    }
+    let mut group = UndoGroup::default();
+    for (path, original, resolved) in planned {
+        let mut edited = original.clone();
+        let applied = apply(&mut edited, &resolved);
+        host.set_buffer_text(path, edited.clone(), &applied);
+        group.buffers.push(BufferUndo { path: path.clone(), original, edited });
+    }
+    Ok(group)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::marks::Marks;
+
+    // Buffers by path, with the marks of each to check edits move them
+    #[derive(Default)]
+    struct Buffers {
+        texts: HashMap<PathBuf, String>,
+        marks: HashMap<PathBuf, Marks>,
+    }
+
+    impl EditHost for Buffers {
+        fn buffer_text(&mut self, path: &Path) -> Result<String, String> {
+            self.texts.get(path).cloned().ok_or_else(|| "No such file".to_string())
+        }
+
+        fn set_buffer_text(&mut self, path: &Path, text: String, edits: &[(Range<usize>, usize)]) {
+            let marks = self.marks.entry(path.to_owned()).or_default();
+            for (replaced, new_len) in edits {
+                marks.apply_edit(replaced.clone(), *new_len);
+            }
+            self.texts.insert(path.to_owned(), text);
+        }
+    }
+
+    fn edit(start: (u32, u32), end: (u32, u32), new_text: &str) -> TextEdit {
+        TextEdit {
+            start: Position { line: start.0, character: start.1 },
+            end: Position { line: end.0, character: end.1 },
+            new_text: new_text.to_string(),
+        }
+    }
+
+    #[test]
+    fn applies_across_buffers() {
+        let mut buffers = Buffers::default();
+        let (a, b) = (PathBuf::from("a.rs"), PathBuf::from("b.rs"));
+        buffers.texts.insert(a.clone(), "let ö = 1;\nf(ö);\n".to_string());
+        buffers.texts.insert(b.clone(), "use ö;\n".to_string());
+        buffers.marks.entry(a.clone()).or_default().set('x', 12);
+
+        let rename = WorkspaceEdit {
+            changes: vec![
+                (a.clone(), vec![edit((1, 2), (1, 3), "count"), edit((0, 4), (0, 5), "count")]),
+                (b.clone(), vec![edit((0, 4), (0, 5), "count")]),
+            ],
+        };
+        let undo = apply_workspace_edit(&mut buffers, &rename).unwrap();
+        assert_eq!(buffers.texts[&a], "let count = 1;\nf(count);\n");
+        assert_eq!(buffers.texts[&b], "use count;\n");
+        // The mark was on the 'f', after the first edit
+        assert_eq!(buffers.marks[&a].get('x'), Some(15));
+
+        buffers.texts.insert(b.clone(), "changed since".to_string());
+        assert_eq!(undo.undo(&mut buffers), vec![b.clone()]);
+        assert_eq!(buffers.texts[&a], "let ö = 1;\nf(ö);\n");
+    }
+
+    #[test]
+    fn rejects_bad_edits_before_changing_anything() {
+        let mut buffers = Buffers::default();
+        let (a, b) = (PathBuf::from("a.rs"), PathBuf::from("b.rs"));
+        buffers.texts.insert(a.clone(), "abc\n".to_string());
+        buffers.texts.insert(b.clone(), "abc\n".to_string());
+
+        let overlapping = WorkspaceEdit { changes: vec![(a.clone(), vec![edit((0, 0), (0, 1), "x")]), (b.clone(), vec![edit((0, 0), (0, 2), ""), edit((0, 1), (0, 3), "")])] };
+        assert_eq!(apply_workspace_edit(&mut buffers, &overlapping), Err(Error::Overlapping(b.clone())));
+        let outside = WorkspaceEdit { changes: vec![(a.clone(), vec![edit((3, 0), (3, 0), "x")])] };
+        assert_eq!(apply_workspace_edit(&mut buffers, &outside), Err(Error::OutOfRange(a.clone(), 3, 0)));
+        assert_eq!(buffers.texts[&a], "abc\n");
+
+        // Inserts at the same place keep their order
+        let inserts = WorkspaceEdit { changes: vec![(a.clone(), vec![edit((0, 1), (0, 1), "1"), edit((0, 1), (0, 1), "2")])] };
+        apply_workspace_edit(&mut buffers, &inserts).unwrap();
+        assert_eq!(buffers.texts[&a], "a12bc\n");
+    }
+}
@@ -0,0 +1,172 @@
+// Distraction-free mode: hides the gutters, status line, tabs and sidebars, and centers the text in
+// a column of its own width. Optionally dims everything but the paragraph being written
+
+use std::ops::Range;
+
+use crate::commands::Registry;
+use crate::selection::line_starts;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZenConfig {
+    // Width of the text column, in characters
+    pub width: usize,
+    // Dim the text outside the paragraph with the cursor
+    pub dim_others: bool,
+    // How much of its color dimmed text keeps
+    pub dim_opacity: f32,
+}
+
+impl Default for ZenConfig {
+    fn default() -> Self {
+        ZenConfig { width: 80, dim_others: false, dim_opacity: 0.35 }
+    }
+}
+
+// Which parts of the window besides the text are drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chrome {
+    pub gutter: bool,
+    pub status_line: bool,
+    pub tabs: bool,
+    pub sidebars: bool,
+}
+
+impl Default for Chrome {
+    fn default() -> Self {
+        Chrome { gutter: true, status_line: true, tabs: true, sidebars: true }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ZenMode {
+    pub config: ZenConfig,
+    pub enabled: bool,
+}
+
+impl ZenMode {
+    // What to draw, given what would be drawn outside zen mode
+    pub fn chrome(&self, normal: Chrome) -> Chrome {
+        match self.enabled {
+            true => Chrome { gutter: false, status_line: false, tabs: false, sidebars: false },
+            false => normal,
+        }
+    }
+
+    // Left edge and width of the text in a window `window_width` wide. The column is padded
+    // equally on both sides, and takes the whole window if that's narrower
+    pub fn text_column(&self, window_width: f32, char_width: f32) -> (f32, f32) {
+        if !self.enabled {
+            return (0., window_width);
+        }
+        let width = (self.config.width as f32 * char_width).min(window_width);
+        ((window_width - width) / 2., width)
+    }
+
+    // Byte ranges to draw with dim_color
+    pub fn dimmed(&self, text: &str, cursor: usize) -> Vec<Range<usize>> {
+        if !self.enabled || !self.config.dim_others {
+            return Vec::new();
+        }
+        let current = paragraph(text, cursor);
+        [0..current.start, current.end..text.len()].into_iter().filter(|range| !range.is_empty()).collect()
+    }
+
+    pub fn dim_color(&self, color: [f32; 4]) -> [f32; 4] {
+        [color[0], color[1], color[2], color[3] * self.config.dim_opacity]
+    }
+
+    // The sorted `colors` of `bytes`, filled in with `color` where they leave gaps, and split where
+    // the sorted `dimmed` ranges start and end so those parts are drawn with dim_color
+    pub fn dim(&self, colors: Vec<(Range<usize>, [f32; 4])>, dimmed: &[Range<usize>], bytes: Range<usize>, color: [f32; 4]) -> Vec<(Range<usize>, [f32; 4])> {
+        if dimmed.is_empty() {
+            return colors;
+        }
+        let (mut filled, mut end) = (Vec::new(), bytes.start);
+        for (range, range_color) in colors {
+            if end < range.start {
+                filled.push((end..range.start, color));
+            }
+            end = range.end;
+            filled.push((range, range_color));
+        }
+        if end < bytes.end {
+            filled.push((end..bytes.end, color));
+        }
+        let mut split = Vec::new();
+        for (range, range_color) in filled {
+            let cuts = dimmed.iter().flat_map(|dim| [dim.start, dim.end]).filter(|&cut| range.start < cut && cut < range.end);
+            let mut start = range.start;
+            for end in cuts.chain([range.end]) {
+                let dim = dimmed.iter().any(|dim| dim.contains(&start));
+                split.push((start..end, if dim { self.dim_color(range_color) } else { range_color }));
+                start = end;
+            }
+        }
+        split
+    }
+}
+
+// The lines around `pos` up to the blank lines before and after it, including its line break.
+// On a blank line, just that line
+pub fn paragraph(text: &str, pos: usize) -> Range<usize> {
+    let starts = line_starts(text);
+    let line_range = |line: usize| starts[line]..starts.get(line + 1).copied().unwrap_or(text.len());
+    let is_blank = |line: usize| text[line_range(line)].trim().is_empty();
+    let line = starts.partition_point(|&start| start <= pos) - 1;
+    if is_blank(line) {
+        return line_range(line);
+    }
+    let first = (0..line).rev().find(|&above| is_blank(above)).map_or(0, |blank| blank + 1);
+    let last = (line + 1..starts.len()).find(|&below| is_blank(below)).map_or(starts.len() - 1, |blank| blank - 1);
+    starts[first]..line_range(last).end
+}
+
+// What :zen needs from the editor
+pub trait ZenHost {
+    fn zen(&mut self) -> &mut ZenMode;
+}
+
+// Toggles, or turns on or off with `on` or `off`
+fn zen_command<Ctx: ZenHost>(ctx: &mut Ctx, args: &[&str]) -> Result<(), String> {
+    let zen = ctx.zen();
+    zen.enabled = match args {
+        [] => !zen.enabled,
+        ["on"] => true,
+        ["off"] => false,
+        _ => return Err("Usage: zen [on|off]".to_string()),
+    };
+    Ok(())
+}
+
+pub fn register<Ctx: ZenHost>(registry: &mut Registry<Ctx>) {
+    registry.add_builtin("zen", zen_command::<Ctx>);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn centers_and_dims() {
+        let mut zen = ZenMode { config: ZenConfig { width: 10, dim_others: true, ..Default::default() }, enabled: false };
+        assert_eq!(zen.text_column(300., 10.), (0., 300.));
+        assert_eq!(zen.chrome(Chrome::default()), Chrome::default());
+        let text = "one\ntwo\n\nthree\nfour\n\nfive";
+        assert!(zen.dimmed(text, 0).is_empty());
+
+        zen.enabled = true;
+        assert_eq!(zen.text_column(300., 10.), (100., 100.));
+        assert_eq!(zen.text_column(50., 10.), (0., 50.));
+        assert!(!zen.chrome(Chrome::default()).status_line);
+        assert_eq!(paragraph(text, 12), 9..20);
+        assert_eq!(paragraph(text, 8), 8..9);
+        assert_eq!(paragraph(text, text.len()), 21..25);
+        assert_eq!(zen.dimmed(text, 1), vec![8..text.len()]);
+        assert_eq!(zen.dimmed(text, 12), vec![0..9, 20..text.len()]);
+
+        let (red, text_color) = ([1., 0., 0., 1.], [1., 1., 1., 1.]);
+        let dim = |color: [f32; 4]| [color[0], color[1], color[2], 0.35];
+        assert_eq!(zen.dim(vec![(2..4, red)], &[], 0..10, text_color), vec![(2..4, red)]);
+        assert_eq!(zen.dim(vec![(2..6, red)], &[0..3, 8..10], 0..10, text_color), vec![(0..2, dim(text_color)), (2..3, dim(red)), (3..6, red), (6..8, text_color), (8..10, dim(text_color))]);
+    }
+}